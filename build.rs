@@ -0,0 +1,75 @@
+//  Glimbot - A Discord anti-spam and administration bot.
+//  Copyright (C) 2020-2021 Nick Samson
+
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Records details of the build environment for the `version` command. Anything which can't be
+//! determined, like the commit when building outside of a git checkout, is reported as unknown.
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Runs a command, returning its trimmed output if it succeeded.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let s = String::from_utf8(out.stdout).ok()?;
+    let s = s.trim();
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
+
+fn main() {
+    let git_hash = command_output("git", &["rev-parse", "--short=12", "HEAD"]).map(|h| {
+        let dirty = command_output("git", &["status", "--porcelain", "--untracked-files=no"]).is_some();
+        if dirty {
+            format!("{}-dirty", h)
+        } else {
+            h
+        }
+    });
+    println!(
+        "cargo:rustc-env=GLIMBOT_GIT_HASH={}",
+        git_hash.as_deref().unwrap_or("unknown")
+    );
+
+    // Honor SOURCE_DATE_EPOCH so reproducible builds stay reproducible.
+    let build_time = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=GLIMBOT_BUILD_TIMESTAMP={}", build_time);
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    println!(
+        "cargo:rustc-env=GLIMBOT_RUSTC_VERSION={}",
+        command_output(&rustc, &["--version"]).as_deref().unwrap_or("unknown")
+    );
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(k, _)| {
+            k.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=GLIMBOT_FEATURES={}", features.join(","));
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=sqlite_migrations");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
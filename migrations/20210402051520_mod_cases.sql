@@ -0,0 +1,21 @@
+CREATE TABLE mod_cases
+(
+    id          BIGSERIAL PRIMARY KEY,
+    guild       BIGINT      NOT NULL,
+    target_user BIGINT      NOT NULL,
+    moderator   BIGINT      NOT NULL,
+    action      JSONB       NOT NULL,
+    reason      TEXT,
+    created     TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX mod_cases_by_user ON mod_cases (guild, target_user, created);
+
+CREATE TRIGGER ensure_mod_case_guild
+    BEFORE INSERT OR UPDATE
+    ON mod_cases
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,33 @@
+CREATE TABLE filter_patterns
+(
+    guild   BIGINT NOT NULL,
+    pattern TEXT   NOT NULL,
+    PRIMARY KEY (guild, pattern),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_filter_pattern_guild
+    BEFORE INSERT OR UPDATE
+    ON filter_patterns
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
+
+CREATE TABLE filter_presets
+(
+    guild    BIGINT  NOT NULL,
+    preset   TEXT    NOT NULL,
+    version  INTEGER NOT NULL,
+    patterns JSONB   NOT NULL,
+    PRIMARY KEY (guild, preset),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_filter_preset_guild
+    BEFORE INSERT OR UPDATE
+    ON filter_presets
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,24 @@
+CREATE TABLE channel_archives
+(
+    id                 BIGSERIAL PRIMARY KEY,
+    guild              BIGINT      NOT NULL,
+    channel            BIGINT      NOT NULL,
+    archived_by        BIGINT      NOT NULL,
+    moved              BOOLEAN     NOT NULL,
+    previous_category  BIGINT,
+    previous_overwrite JSONB,
+    transcript         JSONB       NOT NULL,
+    created            TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    reversed           TIMESTAMPTZ,
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX channel_archives_active ON channel_archives (guild, channel) WHERE reversed IS NULL;
+
+CREATE TRIGGER ensure_channel_archive_guild
+    BEFORE INSERT OR UPDATE
+    ON channel_archives
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
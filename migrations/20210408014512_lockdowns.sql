@@ -0,0 +1,18 @@
+CREATE TABLE lockdown_channels
+(
+    guild              BIGINT      NOT NULL,
+    channel            BIGINT      NOT NULL,
+    started_by         BIGINT      NOT NULL,
+    previous_overwrite JSONB,
+    created            TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, channel),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_lockdown_channel_guild
+    BEFORE INSERT OR UPDATE
+    ON lockdown_channels
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
\ No newline at end of file
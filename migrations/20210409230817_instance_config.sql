@@ -0,0 +1,5 @@
+CREATE TABLE instance_config
+(
+    name  TEXT PRIMARY KEY,
+    value JSONB NOT NULL
+);
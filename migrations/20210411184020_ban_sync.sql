@@ -0,0 +1,29 @@
+CREATE TABLE ban_sync_groups
+(
+    id         BIGSERIAL PRIMARY KEY,
+    name       TEXT        NOT NULL,
+    join_code  TEXT        NOT NULL UNIQUE,
+    created_by BIGINT      NOT NULL,
+    created    TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE ban_sync_members
+(
+    guild    BIGINT PRIMARY KEY,
+    group_id BIGINT      NOT NULL,
+    joined   TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE,
+    FOREIGN KEY (group_id)
+        REFERENCES ban_sync_groups (id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX ban_sync_members_group ON ban_sync_members (group_id);
+
+CREATE TRIGGER ensure_ban_sync_member_guild
+    BEFORE INSERT OR UPDATE
+    ON ban_sync_members
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
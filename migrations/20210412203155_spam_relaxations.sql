@@ -0,0 +1,16 @@
+CREATE TABLE spam_relaxations
+(
+    guild      BIGINT           NOT NULL PRIMARY KEY,
+    factor     DOUBLE PRECISION NOT NULL,
+    started_by BIGINT           NOT NULL,
+    expires    TIMESTAMPTZ      NOT NULL,
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_spam_relaxation_guild
+    BEFORE INSERT OR UPDATE
+    ON spam_relaxations
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
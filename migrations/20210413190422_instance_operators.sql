@@ -0,0 +1,7 @@
+CREATE TABLE instance_operators
+(
+    user_id  BIGINT PRIMARY KEY,
+    tier     TEXT        NOT NULL CHECK (tier IN ('owner', 'operator')),
+    added_by BIGINT      NOT NULL,
+    added    TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,31 @@
+CREATE TABLE member_snapshots
+(
+    guild   BIGINT NOT NULL,
+    day     DATE   NOT NULL,
+    members BIGINT NOT NULL,
+    PRIMARY KEY (guild, day),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_member_snapshot_guild
+    BEFORE INSERT OR UPDATE
+    ON member_snapshots
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
+
+CREATE TABLE growth_milestones
+(
+    guild     BIGINT NOT NULL PRIMARY KEY,
+    milestone BIGINT NOT NULL,
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_growth_milestone_guild
+    BEFORE INSERT OR UPDATE
+    ON growth_milestones
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,3 @@
+-- How long members keep a joinable role after joining it, in seconds. NULL if the role never expires.
+ALTER TABLE joinable_roles
+    ADD COLUMN expires_after BIGINT CHECK (expires_after IS NULL OR expires_after > 0);
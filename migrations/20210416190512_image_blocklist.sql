@@ -0,0 +1,18 @@
+-- Perceptual hashes of images which may not be posted in a guild.
+CREATE TABLE image_blocklist
+(
+    guild    BIGINT      NOT NULL,
+    hash     BIGINT      NOT NULL,
+    added_by BIGINT      NOT NULL,
+    added    TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, hash),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_image_blocklist_guild
+    BEFORE INSERT OR UPDATE
+    ON image_blocklist
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
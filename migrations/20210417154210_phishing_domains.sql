@@ -0,0 +1,6 @@
+-- Known phishing domains, mirrored from the feed set by the bot operator.
+CREATE TABLE phishing_domains
+(
+    domain  TEXT PRIMARY KEY,
+    fetched TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,19 @@
+-- Each guild's raid mode. `auto` arms raid mode to turn on by itself when a raid alert is raised;
+-- the remaining columns are set while raid mode is on.
+CREATE TABLE raid_modes
+(
+    guild                 BIGINT  NOT NULL PRIMARY KEY,
+    auto                  BOOLEAN NOT NULL DEFAULT FALSE,
+    started_by            BIGINT,
+    expires               TIMESTAMPTZ,
+    previous_verification SMALLINT,
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_raid_mode_guild
+    BEFORE INSERT OR UPDATE
+    ON raid_modes
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,21 @@
+-- Channels, roles and users exempt from a guild's filters. `filter` is the name of the filter the
+-- exemption applies to, or 'all' for every filter.
+CREATE TABLE filter_exemptions
+(
+    guild    BIGINT      NOT NULL,
+    kind     TEXT        NOT NULL CHECK (kind IN ('channel', 'role', 'user')),
+    target   BIGINT      NOT NULL,
+    filter   TEXT        NOT NULL DEFAULT 'all',
+    added_by BIGINT      NOT NULL,
+    added    TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, kind, target, filter),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_filter_exemptions_guild
+    BEFORE INSERT OR UPDATE
+    ON filter_exemptions
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,21 @@
+-- Channels Glimbot has put in slowmode because of heavy traffic. `previous` is the channel's slowmode
+-- before Glimbot changed it and `applied` is the one Glimbot set, both in seconds; `last_busy` is
+-- when traffic last called for `applied`.
+CREATE TABLE auto_slowmodes
+(
+    guild     BIGINT      NOT NULL,
+    channel   BIGINT      NOT NULL,
+    previous  INT         NOT NULL,
+    applied   INT         NOT NULL,
+    last_busy TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, channel),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_auto_slowmode_guild
+    BEFORE INSERT OR UPDATE
+    ON auto_slowmodes
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
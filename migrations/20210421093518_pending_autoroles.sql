@@ -0,0 +1,17 @@
+-- Members waiting for their first message before they're given the guild's autoroles.
+CREATE TABLE pending_autoroles
+(
+    guild   BIGINT      NOT NULL,
+    user_id BIGINT      NOT NULL,
+    joined  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, user_id),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_pending_autorole_guild
+    BEFORE INSERT OR UPDATE
+    ON pending_autoroles
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,23 @@
+-- Named groups of joinable roles, of which members may hold at most `max_roles` at once.
+CREATE TABLE role_groups
+(
+    guild     BIGINT NOT NULL,
+    name      TEXT   NOT NULL CHECK (char_length(name) BETWEEN 1 AND 32),
+    max_roles INT    NOT NULL DEFAULT 1 CHECK (max_roles > 0),
+    PRIMARY KEY (guild, name),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_role_group_guild
+    BEFORE INSERT OR UPDATE
+    ON role_groups
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
+
+-- The group a joinable role belongs to, if any.
+ALTER TABLE joinable_roles
+    ADD COLUMN role_group TEXT,
+    ADD FOREIGN KEY (guild, role_group)
+        REFERENCES role_groups (guild, name);
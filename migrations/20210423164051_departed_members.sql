@@ -0,0 +1,18 @@
+-- The roles members held when they left, so they can be given back if they rejoin.
+CREATE TABLE departed_members
+(
+    guild   BIGINT      NOT NULL,
+    user_id BIGINT      NOT NULL,
+    roles   BIGINT[]    NOT NULL,
+    left_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, user_id),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_departed_member_guild
+    BEFORE INSERT OR UPDATE
+    ON departed_members
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,4 @@
+-- Descriptions shown alongside joinable roles, and the categories they're listed under.
+ALTER TABLE joinable_roles
+    ADD COLUMN description TEXT CHECK (char_length(description) BETWEEN 1 AND 100),
+    ADD COLUMN category    TEXT CHECK (char_length(category) BETWEEN 1 AND 32);
@@ -0,0 +1,22 @@
+-- Reminders members have asked for. Each is delivered by a timed event referencing its ID.
+CREATE TABLE reminders
+(
+    id         BIGSERIAL PRIMARY KEY,
+    guild      BIGINT      NOT NULL,
+    user_id    BIGINT      NOT NULL,
+    channel    BIGINT      NOT NULL,
+    content    TEXT        NOT NULL CHECK (char_length(content) BETWEEN 1 AND 1000),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    due        TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX reminders_by_user ON reminders (guild, user_id);
+
+CREATE TRIGGER ensure_reminder_guild
+    BEFORE INSERT OR UPDATE
+    ON reminders
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,24 @@
+-- Suggestions members have posted to a guild's suggestion channel, and how moderators responded.
+CREATE TABLE suggestions
+(
+    id         BIGSERIAL PRIMARY KEY,
+    guild      BIGINT      NOT NULL,
+    author     BIGINT      NOT NULL,
+    channel    BIGINT      NOT NULL,
+    message    BIGINT,
+    content    TEXT        NOT NULL CHECK (char_length(content) BETWEEN 1 AND 1400),
+    status     TEXT        NOT NULL DEFAULT 'open'
+        CHECK (status IN ('open', 'approved', 'denied', 'implemented')),
+    moderator  BIGINT,
+    response   TEXT CHECK (char_length(response) <= 500),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_suggestion_guild
+    BEFORE INSERT OR UPDATE
+    ON suggestions
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
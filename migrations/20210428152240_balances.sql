@@ -0,0 +1,18 @@
+-- Members' balances of a guild's currency.
+CREATE TABLE balances
+(
+    guild      BIGINT NOT NULL,
+    user_id    BIGINT NOT NULL,
+    balance    BIGINT NOT NULL DEFAULT 0 CHECK (balance >= 0),
+    last_daily TIMESTAMPTZ,
+    PRIMARY KEY (guild, user_id),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_balance_guild
+    BEFORE INSERT OR UPDATE
+    ON balances
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
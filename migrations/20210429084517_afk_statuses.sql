@@ -0,0 +1,18 @@
+-- Members who are away, and why, so those mentioning them can be told.
+CREATE TABLE afk_statuses
+(
+    guild   BIGINT      NOT NULL,
+    user_id BIGINT      NOT NULL,
+    reason  TEXT        NOT NULL CHECK (char_length(reason) BETWEEN 1 AND 200),
+    since   TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, user_id),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_afk_status_guild
+    BEFORE INSERT OR UPDATE
+    ON afk_statuses
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,18 @@
+-- Codes members who joined a guild using challenge verification must send to get in.
+CREATE TABLE verification_challenges
+(
+    guild     BIGINT      NOT NULL,
+    user_id   BIGINT      NOT NULL,
+    code      TEXT        NOT NULL,
+    issued_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, user_id),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_verification_challenge_guild
+    BEFORE INSERT OR UPDATE
+    ON verification_challenges
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,18 @@
+-- Temporary voice channels spawned from voice hubs, deleted once they're empty.
+CREATE TABLE temp_voice_channels
+(
+    guild      BIGINT      NOT NULL,
+    channel_id BIGINT      NOT NULL PRIMARY KEY,
+    owner      BIGINT      NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (guild, owner),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_temp_voice_channel_guild
+    BEFORE INSERT OR UPDATE
+    ON temp_voice_channels
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,21 @@
+-- Hourly rollups of how many messages each member sent in each channel.
+CREATE TABLE activity_hourly
+(
+    guild      BIGINT      NOT NULL,
+    hour       TIMESTAMPTZ NOT NULL,
+    channel_id BIGINT      NOT NULL,
+    user_id    BIGINT      NOT NULL,
+    messages   INT         NOT NULL DEFAULT 0,
+    PRIMARY KEY (guild, hour, channel_id, user_id),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX activity_hourly_hour ON activity_hourly (hour);
+
+CREATE TRIGGER ensure_activity_hourly_guild
+    BEFORE INSERT OR UPDATE
+    ON activity_hourly
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
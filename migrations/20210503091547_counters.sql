@@ -0,0 +1,21 @@
+-- Named counters members bump, like a running tally of bugs squashed. Counters with a channel_id of 0
+-- belong to the whole guild; others only exist in that channel.
+CREATE TABLE counters
+(
+    guild      BIGINT      NOT NULL,
+    name       TEXT        NOT NULL CHECK (char_length(name) BETWEEN 1 AND 32),
+    channel_id BIGINT      NOT NULL DEFAULT 0,
+    value      BIGINT      NOT NULL DEFAULT 0,
+    created_by BIGINT      NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, name, channel_id),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_counter_guild
+    BEFORE INSERT OR UPDATE
+    ON counters
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,23 @@
+-- Every change made to a guild's config, so moderators can see who changed what and when. A NULL
+-- old_value means the key wasn't set before; a NULL new_value means it was unset.
+CREATE TABLE config_audit
+(
+    id         BIGSERIAL PRIMARY KEY,
+    guild      BIGINT      NOT NULL,
+    name       TEXT        NOT NULL,
+    old_value  JSONB,
+    new_value  JSONB,
+    actor      BIGINT      NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX config_audit_guild_name_idx ON config_audit (guild, name, changed_at DESC);
+
+CREATE TRIGGER ensure_config_audit_guild
+    BEFORE INSERT OR UPDATE
+    ON config_audit
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,22 @@
+-- Tells every Glimbot process sharing this database when a guild's config changes, so each can drop
+-- its cached copy. The payload is "<guild>:<name>".
+CREATE OR REPLACE FUNCTION notify_config_change()
+    RETURNS TRIGGER
+    LANGUAGE plpgsql
+AS
+$$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PERFORM pg_notify('glimbot_config_changes', OLD.guild::TEXT || ':' || OLD.name);
+        RETURN OLD;
+    END IF;
+    PERFORM pg_notify('glimbot_config_changes', NEW.guild::TEXT || ':' || NEW.name);
+    RETURN NEW;
+END;
+$$;
+
+CREATE TRIGGER notify_config_values
+    AFTER INSERT OR UPDATE OR DELETE
+    ON config_values
+    FOR EACH ROW
+EXECUTE PROCEDURE notify_config_change();
@@ -0,0 +1,7 @@
+-- Settings for the whole Glimbot instance which aren't guild config values, like the bot's activity
+-- text. Set by the bot owner with `!global`.
+CREATE TABLE global_config
+(
+    name  TEXT PRIMARY KEY,
+    value JSONB NOT NULL
+);
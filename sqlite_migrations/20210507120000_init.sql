@@ -0,0 +1,62 @@
+-- The SQLite schema, which only covers what the storage trait stores: guild config and its audit
+-- history, joinable roles and their groups, and timed events. Everything else needs Postgres.
+CREATE TABLE config_values
+(
+    guild INTEGER NOT NULL,
+    name  TEXT    NOT NULL,
+    -- JSON text.
+    value TEXT    NOT NULL,
+    PRIMARY KEY (guild, name)
+);
+
+CREATE TABLE config_audit
+(
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild      INTEGER NOT NULL,
+    name       TEXT    NOT NULL,
+    -- JSON text. NULL if the key wasn't set before.
+    old_value  TEXT,
+    -- JSON text. NULL if the key was unset.
+    new_value  TEXT,
+    actor      INTEGER NOT NULL,
+    -- Microseconds since the Unix epoch, in UTC.
+    changed_at INTEGER NOT NULL
+);
+
+CREATE INDEX config_audit_guild_name_idx ON config_audit (guild, name, changed_at DESC);
+
+CREATE TABLE role_groups
+(
+    guild     INTEGER NOT NULL,
+    name      TEXT    NOT NULL CHECK (length(name) BETWEEN 1 AND 32),
+    max_roles INTEGER NOT NULL DEFAULT 1 CHECK (max_roles > 0),
+    PRIMARY KEY (guild, name)
+);
+
+CREATE TABLE joinable_roles
+(
+    guild         INTEGER NOT NULL,
+    role          INTEGER NOT NULL,
+    -- In seconds. NULL if the role never expires.
+    expires_after INTEGER CHECK (expires_after IS NULL OR expires_after > 0),
+    role_group    TEXT,
+    description   TEXT CHECK (length(description) BETWEEN 1 AND 100),
+    category      TEXT CHECK (length(category) BETWEEN 1 AND 32),
+    PRIMARY KEY (guild, role),
+    FOREIGN KEY (guild, role_group)
+        REFERENCES role_groups (guild, name)
+);
+
+CREATE TABLE timed_events
+(
+    target_user INTEGER NOT NULL,
+    guild       INTEGER NOT NULL,
+    -- Microseconds since the Unix epoch, in UTC.
+    expiry      INTEGER NOT NULL,
+    -- JSON text, compared as written.
+    action      TEXT    NOT NULL,
+    UNIQUE (target_user, guild, expiry, action)
+);
+
+CREATE INDEX timed_events_by_guild ON timed_events (guild);
+CREATE INDEX timed_events_by_time ON timed_events (expiry);
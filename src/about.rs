@@ -22,3 +22,30 @@ binary, You can obtain one at http://mozilla.org/MPL/2.0/."#;
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 /// The repository from which this source was compiled.
 pub const REPO_URL: &str = env!("CARGO_PKG_REPOSITORY");
+/// The git commit this binary was built from, suffixed with `-dirty` if there were uncommitted
+/// changes, or `unknown` if it wasn't built from a git checkout.
+pub const GIT_HASH: &str = env!("GLIMBOT_GIT_HASH");
+/// When this binary was built, as seconds since the Unix epoch.
+pub const BUILD_TIMESTAMP: &str = env!("GLIMBOT_BUILD_TIMESTAMP");
+/// The version of rustc used to build this binary.
+pub const RUSTC_VERSION: &str = env!("GLIMBOT_RUSTC_VERSION");
+/// Comma separated list of the Cargo features enabled when building this binary.
+pub const FEATURES: &str = env!("GLIMBOT_FEATURES");
+
+/// Formats the build time for display.
+pub fn build_date() -> String {
+    BUILD_TIMESTAMP
+        .parse::<i64>()
+        .ok()
+        .and_then(|t| chrono::NaiveDateTime::from_timestamp_opt(t, 0))
+        .map_or_else(|| "unknown".to_string(), |d| d.format("%F %T UTC").to_string())
+}
+
+/// Formats the enabled features for display.
+pub fn features() -> &'static str {
+    if FEATURES.is_empty() {
+        "none"
+    } else {
+        FEATURES
+    }
+}
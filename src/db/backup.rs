@@ -0,0 +1,335 @@
+//! Contains the CLI logic to back up and restore all of Glimbot's tables, for moving an instance
+//! to a new database without needing `pg_dump`, along with exports of a single guild's data.
+//!
+//! Backups are a JSON document holding every row of every table, along with the schema version
+//! they were taken at. They can only be restored into a database at that same schema version.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use serenity::model::id::GuildId;
+use sqlx::PgPool;
+
+use crate::db::{schema_version, MIGRATIONS};
+
+/// The version of the backup format written by this build.
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+
+impl_err!(
+    UnsupportedBackupFormat,
+    "That backup was written in a format this version of Glimbot doesn't understand.",
+    true
+);
+impl_err!(
+    SchemaMismatch,
+    "That backup was taken at a different schema version. Restore it with the Glimbot version that took it, then upgrade.",
+    true
+);
+impl_err!(
+    UnknownTable,
+    "That backup contains a table this database doesn't have.",
+    true
+);
+impl_err!(
+    RestoreNotConfirmed,
+    "Restoring replaces everything in the database. Pass --yes to confirm.",
+    true
+);
+
+/// A backup of every Glimbot table.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Backup {
+    /// The backup format, for rejecting backups written by a newer Glimbot.
+    pub format: u32,
+    /// The schema version the backup was taken at.
+    pub schema_version: Option<i64>,
+    /// When the backup was taken.
+    pub created_at: DateTime<Utc>,
+    /// The rows of each table, keyed by table name, as a JSON array of objects.
+    pub tables: BTreeMap<String, serde_json::Value>,
+}
+
+/// Everything stored about a single guild.
+#[derive(Serialize, Debug)]
+pub struct GuildExport {
+    /// The export format, which matches the backup format.
+    pub format: u32,
+    /// The schema version the export was taken at.
+    pub schema_version: Option<i64>,
+    /// The guild exported.
+    pub guild: GuildId,
+    /// When the export was taken.
+    pub created_at: DateTime<Utc>,
+    /// The guild's rows in each table with a guild column, keyed by table name.
+    pub tables: BTreeMap<String, serde_json::Value>,
+}
+
+/// Creates a subcommand for backing up the database.
+pub fn backup_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("backup")
+        .arg(
+            clap::Arg::with_name("out")
+                .long("out")
+                .value_name("FILE")
+                .default_value("./glimbot-backup.json")
+                .help("The file to write the backup to.")
+                .takes_value(true),
+        )
+        .about("Back up all of Glimbot's data to a file.")
+}
+
+/// Creates a subcommand for restoring the database from a backup.
+pub fn restore_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("restore")
+        .arg(
+            clap::Arg::with_name("input-file")
+                .value_name("FILE")
+                .help("The backup to restore.")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("yes")
+                .long("yes")
+                .help("Confirms that everything in the database should be replaced."),
+        )
+        .about("Replace all of Glimbot's data with a backup. Glimbot should not be running.")
+}
+
+/// Handles the case where someone invoked the output from the backup subcommand.
+pub async fn handle_backup(args: &ArgMatches<'_>) -> crate::error::Result<()> {
+    let out = args.value_of("out").unwrap();
+    // Backing up mustn't change the schema of the database being backed up.
+    let pool = crate::db::connect().await?;
+    let backup = take_backup(&pool).await?;
+    let rows: usize = backup.tables.values().map(|t| t.as_array().map_or(0, Vec::len)).sum();
+
+    tokio::fs::write(out, serde_json::to_vec(&backup)?).await?;
+    println!(
+        "Backed up {} rows from {} tables to {}.",
+        rows,
+        backup.tables.len(),
+        out
+    );
+    Ok(())
+}
+
+/// Handles the case where someone invoked the output from the restore subcommand.
+pub async fn handle_restore(args: &ArgMatches<'_>) -> crate::error::Result<()> {
+    if !args.is_present("yes") {
+        return Err(RestoreNotConfirmed.into());
+    }
+
+    let input = args.value_of("input-file").unwrap();
+    let backup: Backup = serde_json::from_slice(&tokio::fs::read(input).await?)?;
+    let pool = crate::db::connect().await?;
+    MIGRATIONS.run(&pool).await?;
+    restore_backup(&pool, &backup).await?;
+    println!("Restored {} tables from {}.", backup.tables.len(), input);
+    Ok(())
+}
+
+/// Quotes an identifier for use in SQL.
+fn quote_ident(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// Lists Glimbot's tables, ordered so that every table comes after the tables it references.
+async fn tables_in_dependency_order(pool: &PgPool) -> crate::error::Result<Vec<String>> {
+    let tables: Vec<String> = sqlx::query_scalar::<_, String>(
+        "SELECT tablename::TEXT FROM pg_tables WHERE schemaname = 'public' AND tablename <> '_sqlx_migrations';",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let references: Vec<(String, String)> = sqlx::query_as::<_, (String, String)>(
+        r#"
+        SELECT cl.relname::TEXT, ref.relname::TEXT
+        FROM pg_constraint c
+                 JOIN pg_class cl ON cl.oid = c.conrelid
+                 JOIN pg_class ref ON ref.oid = c.confrelid
+        WHERE c.contype = 'f'
+          AND c.connamespace = 'public'::REGNAMESPACE;
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut deps: BTreeMap<String, BTreeSet<String>> = tables.iter().map(|t| (t.clone(), BTreeSet::new())).collect();
+    for (from, to) in references {
+        if from != to {
+            if let Some(d) = deps.get_mut(&from) {
+                d.insert(to);
+            }
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(deps.len());
+    while !deps.is_empty() {
+        let ready: Vec<String> = deps
+            .iter()
+            .filter(|(_, d)| d.iter().all(|t| !deps.contains_key(t)))
+            .map(|(t, _)| t.clone())
+            .collect();
+        // Foreign keys can't form a cycle across Glimbot's tables, but don't spin if they somehow do.
+        let ready = if ready.is_empty() {
+            deps.keys().cloned().collect()
+        } else {
+            ready
+        };
+        for t in ready {
+            deps.remove(&t);
+            ordered.push(t);
+        }
+    }
+
+    Ok(ordered)
+}
+
+/// Reads every row of every table into a backup.
+pub async fn take_backup(pool: &PgPool) -> crate::error::Result<Backup> {
+    let mut tx = pool.begin().await?;
+    // Read every table from the same snapshot.
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY;")
+        .execute(&mut tx)
+        .await?;
+
+    let mut tables = BTreeMap::new();
+    for table in tables_in_dependency_order(pool).await? {
+        let sql = format!(
+            "SELECT COALESCE(json_agg(t), '[]'::JSON) FROM {} t;",
+            quote_ident(&table)
+        );
+        let rows: serde_json::Value = sqlx::query_scalar(&sql).fetch_one(&mut tx).await?;
+        tables.insert(table, rows);
+    }
+    let version = schema_version(&mut tx).await?;
+    tx.commit().await?;
+
+    Ok(Backup {
+        format: BACKUP_FORMAT_VERSION,
+        schema_version: version,
+        created_at: Utc::now(),
+        tables,
+    })
+}
+
+/// Reads every row belonging to a guild, from every table with a guild column.
+pub async fn export_guild(pool: &PgPool, guild: GuildId) -> crate::error::Result<GuildExport> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY;")
+        .execute(&mut tx)
+        .await?;
+
+    let guild_tables: Vec<String> = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT table_name::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = 'public'
+          AND column_name = 'guild';
+        "#,
+    )
+    .fetch_all(&mut tx)
+    .await?;
+
+    let mut tables = BTreeMap::new();
+    for table in guild_tables {
+        let sql = format!(
+            "SELECT COALESCE(json_agg(t), '[]'::JSON) FROM {} t WHERE guild = $1;",
+            quote_ident(&table)
+        );
+        let rows: serde_json::Value = sqlx::query_scalar(&sql).bind(guild.0 as i64).fetch_one(&mut tx).await?;
+        tables.insert(table, rows);
+    }
+    let version = schema_version(&mut tx).await?;
+    tx.commit().await?;
+
+    Ok(GuildExport {
+        format: BACKUP_FORMAT_VERSION,
+        schema_version: version,
+        guild,
+        created_at: Utc::now(),
+        tables,
+    })
+}
+
+/// Replaces everything in the database with the contents of a backup. The database must already
+/// be at the backup's schema version.
+pub async fn restore_backup(pool: &PgPool, backup: &Backup) -> crate::error::Result<()> {
+    if backup.format != BACKUP_FORMAT_VERSION {
+        return Err(UnsupportedBackupFormat.into());
+    }
+
+    if schema_version(pool).await? != backup.schema_version {
+        return Err(SchemaMismatch.into());
+    }
+
+    let order = tables_in_dependency_order(pool).await?;
+    if backup.tables.keys().any(|t| !order.contains(t)) {
+        return Err(UnknownTable.into());
+    }
+
+    let mut tx = pool.begin().await?;
+    let all = order.iter().map(|t| quote_ident(t)).collect::<Vec<_>>().join(", ");
+    sqlx::query(&format!("TRUNCATE {} CASCADE;", all))
+        .execute(&mut tx)
+        .await?;
+
+    for table in &order {
+        let rows = match backup.tables.get(table) {
+            Some(rows) => rows,
+            None => continue,
+        };
+
+        let sql = format!(
+            "INSERT INTO {0} SELECT * FROM jsonb_populate_recordset(NULL::{0}, $1);",
+            quote_ident(table)
+        );
+        sqlx::query(&sql).bind(rows).execute(&mut tx).await?;
+
+        if table == "known_guilds" {
+            // Inserting joinable roles counts them up again, so start from zero.
+            sqlx::query("UPDATE known_guilds SET joinable_role_cnt = 0;")
+                .execute(&mut tx)
+                .await?;
+        }
+
+        reset_sequences(&mut tx, table).await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Moves any sequences backing a table's serial columns past the restored rows, so new rows don't
+/// collide with them.
+async fn reset_sequences(tx: &mut sqlx::Transaction<'_, sqlx::Postgres>, table: &str) -> crate::error::Result<()> {
+    let columns: Vec<String> = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT column_name::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = 'public'
+          AND table_name = $1
+          AND column_default LIKE 'nextval(%';
+        "#,
+    )
+    .bind(table)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for column in columns {
+        let sql = format!(
+            "SELECT setval(pg_get_serial_sequence($1, $2), COALESCE(MAX({0}), 0) + 1, false) FROM {1};",
+            quote_ident(&column),
+            quote_ident(table)
+        );
+        sqlx::query(&sql)
+            .bind(quote_ident(table))
+            .bind(&column)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    Ok(())
+}
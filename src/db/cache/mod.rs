@@ -1,19 +1,24 @@
 //! Contains implementation of caching for per guild objects.
 
-use arc_swap::access::{Access, Map};
 use arc_swap::ArcSwap;
+use parking_lot::Mutex;
 
 use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::future::Future;
 use std::hash::Hash;
 use std::ops::Deref;
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
 
 pub type CacheValue<V, Tag> = Arc<arc_swap::ArcSwapOption<(Tag, V)>>;
 
+/// A looked up [`CacheValue`] slot, along with its value if that's present and unexpired.
+type Lookup<V, Tag> = (CacheValue<V, Tag>, Option<Arc<(Tag, V)>>);
+
 #[derive(Debug)]
 pub struct Cached<V, Tag>(Arc<(Tag, V)>);
 
@@ -56,6 +61,21 @@ where
     type Tag: fmt::Debug + Sized + Clone + Send + Sync;
     fn should_evict(&self, t: &Self::Tag) -> bool;
     fn create_tag(&self, k: &K) -> Self::Tag;
+
+    /// Called when a key already in the cache is used, so strategies can track recency.
+    fn on_access(&self, _k: &K) {}
+
+    /// Called when a key is added to the cache. Returns the keys which should be removed to make
+    /// room for it.
+    fn on_insert(&self, _k: &K) -> Vec<K> {
+        Vec::new()
+    }
+
+    /// Called when a key is removed from the cache.
+    fn on_remove(&self, _k: &K) {}
+
+    /// Called when every key is removed from the cache.
+    fn on_clear(&self) {}
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -93,6 +113,228 @@ impl<K: Send + Sync + Hash + Eq + Clone> EvictionStrategy<K> for NullEvictionStr
     fn create_tag(&self, _g: &K) -> Self::Tag {}
 }
 
+/// Recency bookkeeping for [`LruEvictionStrategy`].
+struct LruState<K> {
+    /// Incremented on every use, giving each use a unique, increasing stamp.
+    tick: u64,
+    /// The stamp of each key's last use.
+    last_used: HashMap<K, u64>,
+    /// Keys ordered by the stamp of their last use, least recent first.
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone> LruState<K> {
+    /// Marks a key as just used.
+    fn touch(&mut self, k: &K) {
+        self.tick += 1;
+        if let Some(old) = self.last_used.insert(k.clone(), self.tick) {
+            self.order.remove(&old);
+        }
+        self.order.insert(self.tick, k.clone());
+    }
+
+    /// Forgets a key.
+    fn forget(&mut self, k: &K) {
+        if let Some(t) = self.last_used.remove(k) {
+            self.order.remove(&t);
+        }
+    }
+
+    /// Removes and returns the least recently used key.
+    fn pop_oldest(&mut self) -> Option<K> {
+        let oldest = *self.order.keys().next()?;
+        let k = self.order.remove(&oldest)?;
+        self.last_used.remove(&k);
+        Some(k)
+    }
+}
+
+/// Bounds a cache to a number of keys, evicting the least recently used once it's full. Unlike
+/// the other strategies, this removes keys outright, so it also bounds the memory used by keys
+/// whose values have long since expired.
+pub struct LruEvictionStrategy<K> {
+    /// The most keys the cache may hold.
+    max_entries: usize,
+    /// Which keys were used when.
+    state: Mutex<LruState<K>>,
+}
+
+impl<K: Hash + Eq + Clone> LruEvictionStrategy<K> {
+    /// Creates a strategy which keeps at most `max_entries` keys, and always at least one.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries: max_entries.max(1),
+            state: Mutex::new(LruState {
+                tick: 0,
+                last_used: HashMap::new(),
+                order: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// The most keys the cache may hold.
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+}
+
+impl<K> fmt::Debug for LruEvictionStrategy<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LruEvictionStrategy")
+            .field("max_entries", &self.max_entries)
+            .field("entries", &self.state.lock().last_used.len())
+            .finish()
+    }
+}
+
+impl<K: Send + Sync + Hash + Eq + Clone> EvictionStrategy<K> for LruEvictionStrategy<K> {
+    type Tag = ();
+
+    fn should_evict(&self, _t: &Self::Tag) -> bool {
+        false
+    }
+
+    fn create_tag(&self, _k: &K) -> Self::Tag {}
+
+    fn on_access(&self, k: &K) {
+        self.state.lock().touch(k);
+    }
+
+    fn on_insert(&self, k: &K) -> Vec<K> {
+        let mut state = self.state.lock();
+        state.touch(k);
+        let mut evicted = Vec::new();
+        while state.last_used.len() > self.max_entries {
+            match state.pop_oldest() {
+                Some(old) => evicted.push(old),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    fn on_remove(&self, k: &K) {
+        self.state.lock().forget(k);
+    }
+
+    fn on_clear(&self) {
+        let mut state = self.state.lock();
+        state.last_used.clear();
+        state.order.clear();
+    }
+}
+
+/// Combines two strategies: values are evicted if either strategy says so, and keys removed by
+/// either are removed from the cache.
+#[derive(Debug, Default)]
+pub struct CompositeEvictionStrategy<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> CompositeEvictionStrategy<A, B> {
+    /// Combines two strategies.
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+/// Expires values after a time to live, and keeps at most a set number of keys.
+pub type BoundedTimedEvictionStrategy<K> = CompositeEvictionStrategy<TimedEvictionStrategy, LruEvictionStrategy<K>>;
+
+impl<K: Hash + Eq + Clone> BoundedTimedEvictionStrategy<K> {
+    /// Creates a strategy expiring values after `ttl`, and keeping at most `max_entries` keys.
+    pub fn bounded_timed(ttl: std::time::Duration, max_entries: usize) -> Self {
+        Self::new(TimedEvictionStrategy::new(ttl), LruEvictionStrategy::new(max_entries))
+    }
+}
+
+impl<K, A, B> EvictionStrategy<K> for CompositeEvictionStrategy<A, B>
+where
+    K: Send + Sync + Hash + Eq + Clone,
+    A: EvictionStrategy<K>,
+    B: EvictionStrategy<K>,
+{
+    type Tag = (A::Tag, B::Tag);
+
+    fn should_evict(&self, t: &Self::Tag) -> bool {
+        self.first.should_evict(&t.0) || self.second.should_evict(&t.1)
+    }
+
+    fn create_tag(&self, k: &K) -> Self::Tag {
+        (self.first.create_tag(k), self.second.create_tag(k))
+    }
+
+    fn on_access(&self, k: &K) {
+        self.first.on_access(k);
+        self.second.on_access(k);
+    }
+
+    fn on_insert(&self, k: &K) -> Vec<K> {
+        let mut evicted = self.first.on_insert(k);
+        evicted.extend(self.second.on_insert(k));
+        evicted
+    }
+
+    fn on_remove(&self, k: &K) {
+        self.first.on_remove(k);
+        self.second.on_remove(k);
+    }
+
+    fn on_clear(&self) {
+        self.first.on_clear();
+        self.second.on_clear();
+    }
+}
+
+/// A view of a cache's statistics. May or may not be accurate.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CacheStats {
+    /// Number of lookups which found a live value.
+    pub hits: u64,
+    /// Number of lookups which didn't, and so usually went to the source.
+    pub misses: u64,
+    /// Number of values dropped by the eviction strategy, whether expired or pushed out.
+    pub evictions: u64,
+    /// Number of keys held, including any whose values have expired.
+    pub entries: usize,
+}
+
+impl CacheStats {
+    /// The total number of lookups.
+    pub fn accesses(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    /// Adds together the statistics of two caches.
+    pub fn combine(self, other: CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            evictions: self.evictions + other.evictions,
+            entries: self.entries + other.entries,
+        }
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries, {} hits / {} misses, {} evicted",
+            self.entries, self.hits, self.misses, self.evictions
+        )
+    }
+}
+
+/// Counters backing [`CacheStats`].
+#[derive(Debug, Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
 #[derive(Debug)]
 pub struct Cache<
     K: Send + Sync + Hash + Eq + Clone,
@@ -101,6 +343,7 @@ pub struct Cache<
 > {
     cache: ArcSwap<im::HashMap<K, CacheValue<V, S::Tag>>>,
     strategy: S,
+    counters: CacheCounters,
 }
 
 impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K> + Send + Sync> Cache<K, V, S> {
@@ -108,27 +351,103 @@ impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K>
         Self {
             cache: Default::default(),
             strategy,
+            counters: Default::default(),
+        }
+    }
+
+    /// Gets a view of the cache's statistics.
+    pub fn statistics(&self) -> CacheStats {
+        CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+            entries: self.cache.load().len(),
+        }
+    }
+
+    /// Counts a lookup: a hit if it found a live value, and an eviction if it found an expired one.
+    fn record_lookup(&self, hit: bool, expired: bool) {
+        let counter = if hit {
+            &self.counters.hits
+        } else {
+            &self.counters.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if expired {
+            self.counters.evictions.fetch_add(1, Ordering::Relaxed);
         }
     }
 
-    pub fn ensure_entry<'a>(
-        &'a self,
-        k: &K,
-    ) -> impl Access<CacheValue<V, S::Tag>, Guard = impl Send + Deref<Target = CacheValue<V, S::Tag>>> + Send + 'a {
-        if self.cache.load().get(k).is_none() {
-            self.cache.rcu(|c| {
-                let mut c = im::HashMap::clone(c);
-                if !c.contains_key(k) {
-                    c.insert(k.clone(), CacheValue::default());
+    /// Retrieves the slot for a key, adding an empty one if there isn't one. The strategy is told
+    /// about the use, and any keys it evicts to make room are removed.
+    pub fn ensure_entry(&self, k: &K) -> CacheValue<V, S::Tag> {
+        if let Some(v) = self.cache.load().get(k) {
+            self.strategy.on_access(k);
+            return v.clone();
+        }
+
+        let mut inserted = false;
+        let mut out = CacheValue::default();
+        self.cache.rcu(|c| {
+            let mut c = im::HashMap::clone(c);
+            match c.get(k) {
+                Some(v) => {
+                    inserted = false;
+                    out = v.clone();
                 }
-                c
-            });
+                None => {
+                    inserted = true;
+                    out = CacheValue::default();
+                    c.insert(k.clone(), out.clone());
+                }
+            }
+            c
+        });
+
+        if inserted {
+            let evicted = self.strategy.on_insert(k);
+            self.counters
+                .evictions
+                .fetch_add(evicted.len() as u64, Ordering::Relaxed);
+            for e in evicted {
+                self.remove_entry(&e);
+            }
+        } else {
+            self.strategy.on_access(k);
         }
-        let k = k.clone();
+        out
+    }
+
+    /// Looks up a key's slot, returning it along with its value if that's present and unexpired.
+    fn lookup(&self, key: &K) -> Lookup<V, S::Tag> {
+        let c = self.ensure_entry(key);
+        let cloaded = c.load_full();
+
+        let needs_reset = cloaded
+            .as_ref()
+            .map(|a| self.strategy.should_evict(&a.0))
+            .unwrap_or(true);
+        self.record_lookup(!needs_reset, needs_reset && cloaded.is_some());
 
-        Map::new(&self.cache, move |c: &im::HashMap<K, CacheValue<V, S::Tag>>| {
-            c.get(&k).unwrap()
-        })
+        let live = if needs_reset { None } else { cloaded };
+        (c, live)
+    }
+
+    /// Stores a freshly made value in a slot, unless another unexpired one was stored first, in
+    /// which case that one is returned instead.
+    fn fill(&self, c: &CacheValue<V, S::Tag>, key: &K, v: V) -> Cached<V, S::Tag> {
+        let ins = Arc::new((self.strategy.create_tag(key), v));
+        let mut out = ins.clone();
+        c.rcu(|r| {
+            if let Some(r) = r.as_ref().filter(|r| !self.strategy.should_evict(&r.0)) {
+                out = r.clone();
+                Some(r.clone())
+            } else {
+                out = ins.clone();
+                Some(ins.clone())
+            }
+        });
+        Cached(out)
     }
 
     /// This is very subtly wrong
@@ -136,52 +455,53 @@ impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K>
     where
         Fut: Future<Output = crate::error::Result<V>>,
     {
-        let cache = self.ensure_entry(key).load();
-        let c: &CacheValue<V, S::Tag> = cache.deref();
-        let cloaded = c.load_full();
+        match self.lookup(key) {
+            (_, Some(v)) => Ok(Cached(v)),
+            (c, None) => Ok(self.fill(&c, key, f.await?)),
+        }
+    }
 
-        let needs_reset = cloaded
-            .as_ref()
-            .map(|a| self.strategy.should_evict(&(*a).0))
-            .unwrap_or(true);
+    /// Retrieves a value, making and inserting one if needed. For values which can be made without
+    /// waiting on anything; unlike [`Cache::get_or_insert_with`], this never blocks or yields.
+    pub fn get_or_insert(&self, key: &K, f: impl FnOnce() -> V) -> Cached<V, S::Tag> {
+        match self.lookup(key) {
+            (_, Some(v)) => Cached(v),
+            (c, None) => self.fill(&c, key, f()),
+        }
+    }
 
-        let out = if needs_reset {
-            let v = f.await?;
-            let ins = Arc::new((self.strategy.create_tag(key), v));
-            let mut out = ins.clone();
-            c.rcu(|r| {
-                if let Some(r) = r {
-                    out = r.clone();
-                    Some(r.clone())
-                } else {
-                    out = ins.clone();
-                    Some(ins.clone())
-                }
-            });
-            out
-        } else {
-            // The only way to get here is if `needs_reset` is false, which means
-            // the option was full.
-            cloaded.unwrap()
-        };
+    /// Retrieves a value if it's present and unexpired. Unlike [`Cache::get`], this isn't counted
+    /// as a lookup and doesn't add a slot for the key.
+    pub fn peek(&self, key: &K) -> Option<Cached<V, S::Tag>> {
+        self.cache
+            .load()
+            .get(key)
+            .and_then(|c| c.load_full())
+            .filter(|v| !self.strategy.should_evict(&v.0))
+            .map(Cached)
+    }
 
-        Ok(Cached(out))
+    /// Stores a value unless an unexpired one is already present, returning whichever is kept. Unlike
+    /// [`Cache::get_or_insert`], this isn't counted as a lookup, for callers which counted one
+    /// already.
+    pub fn insert_if_absent(&self, key: &K, v: V) -> Cached<V, S::Tag> {
+        let c = self.ensure_entry(key);
+        self.fill(&c, key, v)
     }
 
     pub fn insert(&self, key: &K, v: V) {
         self.ensure_entry(key)
-            .load()
-            .deref()
             .store(Some(Arc::new((self.strategy.create_tag(key), v))));
     }
 
     pub fn get(&self, key: &K) -> Option<Cached<V, S::Tag>> {
-        let cache = self.ensure_entry(&key).load();
-        let c: &CacheValue<V, S::Tag> = cache.deref();
+        let c = self.ensure_entry(key);
 
         let mut res = None;
+        let mut expired = false;
         c.rcu(|f| {
-            let needs_reset = f.as_ref().map(|a| self.strategy.should_evict(&(*a).0)).unwrap_or(true);
+            let needs_reset = f.as_ref().map(|a| self.strategy.should_evict(&a.0)).unwrap_or(true);
+            expired = needs_reset && f.is_some();
             if needs_reset {
                 res = None;
                 None
@@ -191,18 +511,15 @@ impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K>
             }
         });
 
+        self.record_lookup(res.is_some(), expired);
         res.map(Cached)
     }
 
-    pub fn get_or_insert_sync(&self, key: &K, val: impl FnOnce() -> V) -> Cached<V, S::Tag> {
-        futures::executor::block_on(self.get_or_insert_with(key, async { Ok(val()) })).unwrap()
-    }
-
     pub fn get_or_insert_default(&self, key: &K) -> Cached<V, S::Tag>
     where
         V: Default,
     {
-        self.get_or_insert_sync(key, V::default)
+        self.get_or_insert(key, V::default)
     }
 
     pub fn reset(&self, key: &K)
@@ -213,6 +530,15 @@ impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K>
     }
 
     pub fn remove(&self, key: &K) -> Option<Cached<V, S::Tag>> {
+        let out = self.remove_entry(key);
+        if out.is_some() {
+            self.strategy.on_remove(key);
+        }
+        out.and_then(|cv| cv.load_full()).map(Cached)
+    }
+
+    /// Removes a key's slot without telling the strategy, e.g. because the strategy evicted it.
+    fn remove_entry(&self, key: &K) -> Option<CacheValue<V, S::Tag>> {
         let mut out = None;
         self.cache.rcu(|r| {
             if r.contains_key(key) {
@@ -223,16 +549,40 @@ impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K>
                 r.clone()
             }
         });
-        out.and_then(|cv| cv.load_full()).map(Cached)
+        out
+    }
+
+    /// Lists the keys currently in the cache, including any whose values have expired.
+    pub fn keys(&self) -> Vec<K> {
+        self.cache.load().keys().cloned().collect()
+    }
+
+    /// Lists the values currently in the cache which haven't expired. Unlike [`Cache::get`], this
+    /// isn't counted as a lookup.
+    pub fn values(&self) -> Vec<Cached<V, S::Tag>> {
+        self.cache
+            .load()
+            .values()
+            .filter_map(|c| c.load_full())
+            .filter(|v| !self.strategy.should_evict(&v.0))
+            .map(Cached)
+            .collect()
+    }
+
+    /// Removes every entry from the cache.
+    pub fn clear(&self) {
+        self.cache.store(Default::default());
+        self.strategy.on_clear();
     }
 
     pub fn update(&self, key: &K, update_fn: impl Fn(Option<&V>) -> Option<V>) -> Update<V, S::Tag> {
-        let cache = self.ensure_entry(&key).load();
-        let c: &CacheValue<V, S::Tag> = cache.deref();
+        let c = self.ensure_entry(key);
 
         let mut out = None;
+        let mut expired = false;
         c.rcu(|o| {
-            let needs_reset = o.as_ref().map(|a| self.strategy.should_evict(&(*a).0)).unwrap_or(true);
+            let needs_reset = o.as_ref().map(|a| self.strategy.should_evict(&a.0)).unwrap_or(true);
+            expired = needs_reset && o.is_some();
             let pass_val = if needs_reset { None } else { o.clone() };
             let new = update_fn(pass_val.as_ref().map(|c| &c.1));
             let new = new.map(|v| Arc::new((self.strategy.create_tag(key), v)));
@@ -242,7 +592,9 @@ impl<K: Send + Sync + Hash + Eq + Clone, V: Send + Sync, S: EvictionStrategy<K>
             });
             new
         });
-        out.unwrap()
+        let out: Update<V, S::Tag> = out.unwrap();
+        self.record_lookup(out.old.is_some(), expired);
+        out
     }
 
     pub fn update_and_fetch(&self, key: &K, update_fn: impl Fn(Option<&V>) -> Option<V>) -> Option<Cached<V, S::Tag>> {
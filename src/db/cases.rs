@@ -0,0 +1,295 @@
+//! Contains the case store, a persistent record of moderation actions taken in each guild.
+
+use chrono::Utc;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+
+use crate::db::{DbContext, TxContext};
+use crate::module::moderation::ActionKind;
+
+/// The maximum number of characters of message content kept in a snapshot.
+pub const MAX_SNAPSHOT_CONTENT_LEN: usize = 512;
+
+/// A snapshot of a message relevant to a case, taken when the case was opened.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MessageSnapshot {
+    /// The id of the message.
+    pub id: MessageId,
+    /// The channel the message was sent in.
+    pub channel: ChannelId,
+    /// When the message was sent.
+    pub timestamp: chrono::DateTime<Utc>,
+    /// The content of the message, if it was still available, truncated to [`MAX_SNAPSHOT_CONTENT_LEN`] characters.
+    pub content: Option<String>,
+}
+
+impl MessageSnapshot {
+    /// Creates a snapshot of a message without its content.
+    pub fn new(id: MessageId, channel: ChannelId, timestamp: chrono::DateTime<Utc>) -> Self {
+        MessageSnapshot {
+            id,
+            channel,
+            timestamp,
+            content: None,
+        }
+    }
+
+    /// Attaches content to the snapshot, truncating it if necessary.
+    pub fn with_content(mut self, content: &str) -> Self {
+        self.content = Some(content.chars().take(MAX_SNAPSHOT_CONTENT_LEN).collect());
+        self
+    }
+}
+
+impl From<&Message> for MessageSnapshot {
+    fn from(m: &Message) -> Self {
+        Self::new(m.id, m.channel_id, m.timestamp).with_content(&m.content)
+    }
+}
+
+/// Spam pressure values at the time an automatic action was taken.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct PressureEvidence {
+    /// The user's pressure after the offending message.
+    pub pressure: f64,
+    /// The pressure generated by the offending message alone.
+    pub message_pressure: f64,
+    /// The guild's configured maximum pressure.
+    pub max_pressure: f64,
+}
+
+/// Where a ban mirrored from another guild in a ban-sync group originated.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BanProvenance {
+    /// The guild the user was originally banned from.
+    pub guild: GuildId,
+    /// The name of that guild when the ban was mirrored.
+    pub guild_name: String,
+    /// The moderator who issued the original ban, if known.
+    pub moderator: Option<UserId>,
+    /// The reason given for the original ban, if any.
+    pub reason: Option<String>,
+    /// When the original ban was observed.
+    pub banned_at: chrono::DateTime<Utc>,
+}
+
+/// Evidence attached to a case, so that anyone reviewing it later has full context.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Evidence {
+    /// Snapshots of relevant messages, oldest first.
+    pub messages: Vec<MessageSnapshot>,
+    /// Spam pressure values, if the spam engine took the action.
+    pub pressure: Option<PressureEvidence>,
+    /// The original ban, if this case mirrors a ban from another guild.
+    #[serde(default)]
+    pub provenance: Option<BanProvenance>,
+}
+
+impl Evidence {
+    /// Creates a short, single-line summary of the evidence.
+    pub fn summary(&self) -> String {
+        let mut out = format!("{} message snapshot(s)", self.messages.len());
+        if let Some(p) = &self.pressure {
+            out.push_str(&format!(
+                "; pressure {:.1} / {:.1} (last message {:.1})",
+                p.pressure, p.max_pressure, p.message_pressure
+            ));
+        }
+        if let Some(p) = &self.provenance {
+            out.push_str(&format!("; synced from {} ({})", p.guild_name, p.guild));
+        }
+        out
+    }
+}
+
+/// A moderation case as stored in the database.
+#[derive(Clone, Debug)]
+pub struct Case {
+    /// The id of the case.
+    pub id: i64,
+    /// The user the action was taken against.
+    pub user: UserId,
+    /// The moderator who took the action.
+    pub moderator: UserId,
+    /// The action taken.
+    pub action: ActionKind,
+    /// Why the action was taken.
+    pub reason: Option<String>,
+    /// Evidence recorded with the case, if any.
+    pub evidence: Option<Evidence>,
+    /// When the case was opened.
+    pub created: chrono::DateTime<Utc>,
+}
+
+#[doc(hidden)]
+struct CaseRow {
+    id: i64,
+    target_user: i64,
+    moderator: i64,
+    action: serde_json::Value,
+    reason: Option<String>,
+    evidence: Option<serde_json::Value>,
+    created: chrono::DateTime<Utc>,
+}
+
+impl CaseRow {
+    /// Converts a raw row into a [`Case`].
+    fn into_case(self) -> crate::error::Result<Case> {
+        Ok(Case {
+            id: self.id,
+            user: UserId::from(self.target_user as u64),
+            moderator: UserId::from(self.moderator as u64),
+            action: serde_json::from_value(self.action)?,
+            reason: self.reason,
+            evidence: self.evidence.map(serde_json::from_value).transpose()?,
+            created: self.created,
+        })
+    }
+}
+
+/// A wrapper for a database context for recording and querying moderation cases.
+#[derive(Clone)]
+pub struct Cases<'pool> {
+    /// The wrapped database context.
+    context: DbContext<'pool>,
+    /// How long warnings count against a user before they decay. Warnings never decay if unset.
+    warning_ttl: Option<chrono::Duration>,
+}
+
+impl<'pool> Cases<'pool> {
+    /// Wraps a database context to work with moderation cases.
+    pub fn new(context: DbContext<'pool>) -> Self {
+        Cases {
+            context,
+            warning_ttl: None,
+        }
+    }
+
+    /// Makes warnings older than the given time stop counting against users. Decayed warnings are
+    /// kept in the case history.
+    pub fn with_warning_ttl(mut self, ttl: Option<std::time::Duration>) -> Self {
+        self.warning_ttl = ttl.map(|t| chrono::Duration::from_std(t).unwrap_or_else(|_| chrono::Duration::max_value()));
+        self
+    }
+
+    /// Retrieves the time before which warnings have decayed, if they decay.
+    fn warnings_decayed_before(&self) -> Option<chrono::DateTime<Utc>> {
+        self.warning_ttl
+            .map(|ttl| Utc::now().checked_sub_signed(ttl).unwrap_or(chrono::MIN_DATETIME))
+    }
+
+    /// Returns true if the case is a warning which has decayed, and so no longer counts against
+    /// the user.
+    pub fn is_decayed(&self, case: &Case) -> bool {
+        case.action == ActionKind::Warn && self.warnings_decayed_before().map_or(false, |t| case.created < t)
+    }
+
+    /// Records a moderation action, returning the id of the new case.
+    pub async fn record(
+        &self,
+        user: UserId,
+        moderator: UserId,
+        action: ActionKind,
+        reason: Option<&str>,
+        evidence: Option<&Evidence>,
+    ) -> crate::error::Result<i64> {
+        Self::record_with(
+            self.context.conn()?,
+            self.context.guild(),
+            user,
+            moderator,
+            action,
+            reason,
+            evidence,
+        )
+        .await
+    }
+
+    /// Records a moderation action as part of a transaction, returning the id of the new case.
+    pub async fn record_in(
+        tx: &mut TxContext<'_>,
+        user: UserId,
+        moderator: UserId,
+        action: ActionKind,
+        reason: Option<&str>,
+        evidence: Option<&Evidence>,
+    ) -> crate::error::Result<i64> {
+        let guild = tx.guild();
+        Self::record_with(tx.conn(), guild, user, moderator, action, reason, evidence).await
+    }
+
+    /// Records a moderation action using the given executor.
+    async fn record_with<'e, E>(
+        ex: E,
+        guild: GuildId,
+        user: UserId,
+        moderator: UserId,
+        action: ActionKind,
+        reason: Option<&str>,
+        evidence: Option<&Evidence>,
+    ) -> crate::error::Result<i64>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+    {
+        let evidence = evidence.map(serde_json::to_value).transpose()?;
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO mod_cases (guild, target_user, moderator, action, reason, evidence)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id;
+            "#,
+            guild.0 as i64,
+            user.0 as i64,
+            moderator.0 as i64,
+            serde_json::to_value(action)?,
+            reason,
+            evidence
+        )
+        .fetch_one(ex)
+        .await?;
+        Ok(id)
+    }
+
+    /// Counts the number of cases of a given kind against a user since the specified time.
+    /// Decayed warnings aren't counted.
+    pub async fn count_since(
+        &self,
+        user: UserId,
+        action: ActionKind,
+        since: chrono::DateTime<Utc>,
+    ) -> crate::error::Result<i64> {
+        let since = match self.warnings_decayed_before() {
+            Some(t) if action == ActionKind::Warn => since.max(t),
+            _ => since,
+        };
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS matching FROM mod_cases
+            WHERE guild = $1 AND target_user = $2 AND action = $3 AND created >= $4;
+            "#,
+            self.context.guild_as_i64(),
+            user.0 as i64,
+            serde_json::to_value(action)?,
+            since
+        )
+        .fetch_one(self.context.conn()?)
+        .await?
+        .unwrap_or_default())
+    }
+
+    /// Retrieves a case by id.
+    pub async fn get(&self, id: i64) -> crate::error::Result<Option<Case>> {
+        let row = sqlx::query_as!(
+            CaseRow,
+            r#"
+            SELECT id, target_user, moderator, action, reason, evidence, created FROM mod_cases
+            WHERE guild = $1 AND id = $2;
+            "#,
+            self.context.guild_as_i64(),
+            id
+        )
+        .fetch_optional(self.context.conn()?)
+        .await?;
+        row.map(CaseRow::into_case).transpose()
+    }
+}
@@ -0,0 +1,83 @@
+//! Contains the CLI logic for managing the database schema outside of startup.
+
+use std::collections::HashSet;
+
+use clap::{AppSettings, ArgMatches};
+
+use crate::db::storage::Storage;
+
+/// Creates a subcommand for running and inspecting migrations.
+pub fn subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("db")
+        .about("Manage Glimbot's database schema.")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(clap::SubCommand::with_name("migrate").about("Applies any pending migrations."))
+        .subcommand(
+            clap::SubCommand::with_name("status").about("Shows the current schema version and any pending migrations."),
+        )
+}
+
+/// Handles the case where someone invoked the output from the subcommand function.
+pub async fn handle_matches(args: &ArgMatches<'_>) -> crate::error::Result<()> {
+    let storage = crate::db::storage::open().await?;
+    match args.subcommand_name() {
+        Some("migrate") => migrate(storage.as_ref()).await,
+        Some("status") => status(storage.as_ref()).await,
+        _ => unreachable!("Unrecognized db command; we should have errored out already."),
+    }
+}
+
+/// Applies pending migrations.
+async fn migrate(storage: &dyn Storage) -> crate::error::Result<()> {
+    let before = storage.applied_migrations().await?.len();
+    storage.migrate().await?;
+    let applied = storage.applied_migrations().await?.len().saturating_sub(before);
+
+    if applied == 0 {
+        println!("Already up to date.");
+    } else {
+        let after = storage.migration_state().await?.map(|(v, _)| v);
+        println!(
+            "Applied {} pending migrations. Schema version: {}.",
+            applied,
+            describe_version(after)
+        );
+    }
+    Ok(())
+}
+
+/// Prints the schema version and any pending migrations.
+async fn status(storage: &dyn Storage) -> crate::error::Result<()> {
+    let current = storage.migration_state().await?;
+    let version = current.map(|(v, _)| v);
+
+    println!("Schema version: {}", describe_version(version));
+    if let Some((_, true)) = current {
+        println!("The last migration failed partway through.");
+    }
+
+    // Module migrations can be older than the latest applied one, so anything not yet applied is
+    // pending, whatever its version.
+    let applied: HashSet<i64> = storage.applied_migrations().await?.into_iter().collect();
+    let pending: Vec<_> = storage
+        .migrations()
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .filter(|m| !applied.contains(&m.version))
+        .collect();
+
+    if pending.is_empty() {
+        println!("No pending migrations.");
+    } else {
+        println!("Pending migrations:");
+        for m in pending {
+            println!("  {} {}", m.version, m.description);
+        }
+    }
+    Ok(())
+}
+
+/// Describes a schema version for humans.
+fn describe_version(v: Option<i64>) -> String {
+    v.map_or_else(|| "none (no migrations applied)".to_string(), |v| v.to_string())
+}
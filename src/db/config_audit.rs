@@ -0,0 +1,66 @@
+//! Contains the config audit history: a record of every change made to a guild's config through
+//! `config set` and `config unset`, with who made it and when.
+
+use std::borrow::Borrow;
+
+use chrono::Utc;
+use serenity::model::id::UserId;
+
+use crate::db::DbContext;
+
+/// A single change to a config value.
+#[derive(Debug, Clone)]
+pub struct ConfigChange {
+    /// The config value's name.
+    pub name: String,
+    /// The value before the change, or `None` if it wasn't set.
+    pub old_value: Option<serde_json::Value>,
+    /// The value after the change, or `None` if it was unset.
+    pub new_value: Option<serde_json::Value>,
+    /// Who made the change.
+    pub actor: UserId,
+    /// When the change was made.
+    pub changed_at: chrono::DateTime<Utc>,
+}
+
+/// Wrapper around a guild's config audit history in the database.
+pub struct ConfigAudit<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> ConfigAudit<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        ConfigAudit {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Records a change to a config value.
+    pub async fn record(
+        &self,
+        name: &str,
+        old_value: Option<&serde_json::Value>,
+        new_value: Option<&serde_json::Value>,
+        actor: UserId,
+    ) -> crate::error::Result<()> {
+        self.ctx
+            .storage()
+            .record_config_change(self.ctx.guild(), name, old_value, new_value, actor)
+            .await
+    }
+
+    /// Retrieves the changes made to a config value, newest first, skipping `offset` of them.
+    pub async fn history(&self, name: &str, offset: i64, limit: i64) -> crate::error::Result<Vec<ConfigChange>> {
+        self.ctx
+            .storage()
+            .config_history(self.ctx.guild(), name, offset, limit)
+            .await
+    }
+
+    /// Counts the changes made to a config value.
+    pub async fn count(&self, name: &str) -> crate::error::Result<i64> {
+        self.ctx.storage().count_config_changes(self.ctx.guild(), name).await
+    }
+}
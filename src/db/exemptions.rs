@@ -0,0 +1,244 @@
+//! Contains filter exemptions: channels, roles and users which a guild's filters leave alone. Every
+//! filter consults the same exemptions through [`crate::module::filter::FilterModule::is_exempt`],
+//! so moderators manage them in one place with `filter exempt`. An exemption applies to every filter
+//! unless it's scoped to one by name.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use chrono::Utc;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, UserId};
+
+use crate::db::DbContext;
+use crate::error::DatabaseError;
+
+/// The scope of an exemption which applies to every filter.
+pub const ALL_FILTERS: &str = "all";
+/// The names of the filters an exemption may be scoped to.
+pub const FILTER_NAMES: &[&str] = &[
+    "filter",
+    "regex-filter",
+    "invite-filter",
+    "duplicate-filter",
+    "emoji-filter",
+    "wall-of-text-filter",
+    "attachment-filter",
+    "image-filter",
+    "phishing-filter",
+];
+/// The most exemptions a guild may have.
+pub const MAX_EXEMPTIONS: i64 = 200;
+
+impl_err!(
+    InvalidExemptionKind,
+    "Exemptions are for a `channel`, `role` or `user`.",
+    true
+);
+impl_err!(
+    NoSuchFilter,
+    "No filter by that name. Filters are filter, regex-filter, invite-filter, duplicate-filter, emoji-filter, wall-of-text-filter, attachment-filter, image-filter and phishing-filter.",
+    true
+);
+impl_err!(AlreadyExempt, "That's already exempt.", true);
+impl_err!(NoSuchExemption, "That isn't exempt.", true);
+impl_err!(
+    TooManyExemptions,
+    "This server has the maximum of 200 filter exemptions. Remove some before adding more.",
+    true
+);
+
+/// What an exemption applies to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExemptionKind {
+    /// Messages sent in a channel.
+    Channel,
+    /// Messages sent by members with a role.
+    Role,
+    /// Messages sent by a user.
+    User,
+}
+
+impl FromStr for ExemptionKind {
+    type Err = InvalidExemptionKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "channel" => Ok(ExemptionKind::Channel),
+            "role" => Ok(ExemptionKind::Role),
+            "user" => Ok(ExemptionKind::User),
+            _ => Err(InvalidExemptionKind),
+        }
+    }
+}
+
+impl fmt::Display for ExemptionKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ExemptionKind::Channel => "channel",
+            ExemptionKind::Role => "role",
+            ExemptionKind::User => "user",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Checks that a scope names a filter, normalizing it.
+pub fn validate_scope(scope: Option<&str>) -> crate::error::Result<&'static str> {
+    match scope {
+        None => Ok(ALL_FILTERS),
+        Some(s) => FILTER_NAMES
+            .iter()
+            .chain(std::iter::once(&ALL_FILTERS))
+            .find(|n| n.eq_ignore_ascii_case(s.trim()))
+            .copied()
+            .ok_or_else(|| NoSuchFilter.into()),
+    }
+}
+
+/// An exemption from a guild's filters.
+#[derive(Clone, Debug)]
+pub struct Exemption {
+    /// What the exemption applies to.
+    pub kind: ExemptionKind,
+    /// The ID of the channel, role or user.
+    pub target: u64,
+    /// The filter the exemption applies to, or [`ALL_FILTERS`].
+    pub filter: String,
+    /// The moderator who added it.
+    pub added_by: UserId,
+    /// When it was added.
+    pub added: chrono::DateTime<Utc>,
+}
+
+/// Wrapper around a guild's filter exemptions in the database.
+pub struct FilterExemptions<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> FilterExemptions<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        FilterExemptions {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Exempts a channel, role or user from a filter, or every filter.
+    pub async fn add(
+        &self,
+        kind: ExemptionKind,
+        target: u64,
+        filter: &str,
+        added_by: UserId,
+    ) -> crate::error::Result<()> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) AS exemptions FROM filter_exemptions WHERE guild = $1;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_one(self.ctx.conn()?)
+        .await?
+        .unwrap_or_default();
+
+        if count >= MAX_EXEMPTIONS {
+            return Err(TooManyExemptions.into());
+        }
+
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO filter_exemptions (guild, kind, target, filter, added_by) VALUES ($1, $2, $3, $4, $5);",
+            self.ctx.guild_as_i64(),
+            kind.to_string(),
+            target as i64,
+            filter,
+            added_by.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(AlreadyExempt.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Removes an exemption.
+    pub async fn remove(&self, kind: ExemptionKind, target: u64, filter: &str) -> crate::error::Result<()> {
+        let res = sqlx::query!(
+            "DELETE FROM filter_exemptions WHERE guild = $1 AND kind = $2 AND target = $3 AND filter = $4;",
+            self.ctx.guild_as_i64(),
+            kind.to_string(),
+            target as i64,
+            filter
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(NoSuchExemption.into());
+        }
+        Ok(())
+    }
+
+    /// Retrieves the guild's exemptions.
+    pub async fn exemptions(&self) -> crate::error::Result<Vec<Exemption>> {
+        let rows = sqlx::query!(
+            "SELECT kind, target, filter, added_by, added FROM filter_exemptions WHERE guild = $1 ORDER BY kind, filter, added;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_all(self.ctx.conn()?)
+        .await?;
+
+        let mut out = Vec::with_capacity(rows.len());
+        for r in rows {
+            match r.kind.parse() {
+                Ok(kind) => out.push(Exemption {
+                    kind,
+                    target: r.target as u64,
+                    filter: r.filter,
+                    added_by: UserId(r.added_by as u64),
+                    added: r.added,
+                }),
+                Err(e) => warn!("ignoring filter exemption for {}: {}", r.target, e),
+            }
+        }
+        Ok(out)
+    }
+
+    /// Checks whether a message is exempt from a filter, because of where it was sent, who sent it,
+    /// or the author's roles. Messages in a thread are also exempt if the thread's `parent` channel is.
+    pub async fn exempts(&self, orig: &Message, parent: Option<ChannelId>, filter: &str) -> crate::error::Result<bool> {
+        let roles: Vec<i64> = orig
+            .member
+            .as_ref()
+            .map(|m| m.roles.iter().map(|r| r.0 as i64).collect())
+            .unwrap_or_default();
+        let channels: Vec<i64> = std::iter::once(orig.channel_id)
+            .chain(parent)
+            .map(|c| c.0 as i64)
+            .collect();
+        let exempt = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS(
+                SELECT 1 FROM filter_exemptions
+                WHERE guild = $1
+                  AND filter IN ('all', $2)
+                  AND ((kind = 'channel' AND target = ANY($3))
+                    OR (kind = 'user' AND target = $4)
+                    OR (kind = 'role' AND target = ANY($5)))
+            ) AS "exempt!";
+            "#,
+            self.ctx.guild_as_i64(),
+            filter,
+            &channels,
+            orig.author.id.0 as i64,
+            &roles
+        )
+        .fetch_one(self.ctx.conn()?)
+        .await?;
+        Ok(exempt)
+    }
+}
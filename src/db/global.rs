@@ -0,0 +1,234 @@
+//! Contains global settings: configuration for the whole instance set by the bot owner, like the
+//! bot's activity text or a maintenance message. Unlike instance defaults, these aren't guild config
+//! values, and guilds can't override them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use parking_lot::RwLock;
+use serenity::model::user::OnlineStatus;
+use sqlx::PgPool;
+
+/// The longest activity text Discord shows.
+pub const MAX_ACTIVITY_LEN: usize = 128;
+/// The longest maintenance message, in characters.
+pub const MAX_MAINTENANCE_MESSAGE_LEN: usize = 1000;
+
+impl_err!(
+    NoSuchGlobalSetting,
+    "No such global setting. Settings are activity, status, maintenance_message and spam_pressure_ceiling.",
+    true
+);
+impl_err!(
+    InvalidActivity,
+    "The activity must be between 1 and 128 characters long.",
+    true
+);
+impl_err!(InvalidStatus, "Statuses are online, idle, dnd and invisible.", true);
+impl_err!(
+    InvalidMaintenanceMessage,
+    "The maintenance message must be between 1 and 1000 characters long.",
+    true
+);
+impl_err!(
+    InvalidSpamCeiling,
+    "The spam pressure ceiling must be a positive number.",
+    true
+);
+
+/// A setting which applies to the whole instance.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GlobalSetting {
+    /// The text shown as the bot's activity.
+    Activity,
+    /// The bot's online status, like idle or do not disturb.
+    Status,
+    /// When set, commands from anyone outside the operator team are answered with this message
+    /// instead of being run.
+    MaintenanceMessage,
+    /// The highest `max_pressure` any guild's spam filter uses, whatever it's configured to.
+    SpamPressureCeiling,
+}
+
+impl GlobalSetting {
+    /// Every global setting.
+    pub const ALL: [GlobalSetting; 4] = [
+        GlobalSetting::Activity,
+        GlobalSetting::Status,
+        GlobalSetting::MaintenanceMessage,
+        GlobalSetting::SpamPressureCeiling,
+    ];
+
+    /// The name the setting is stored and set by.
+    pub fn name(self) -> &'static str {
+        match self {
+            GlobalSetting::Activity => "activity",
+            GlobalSetting::Status => "status",
+            GlobalSetting::MaintenanceMessage => "maintenance_message",
+            GlobalSetting::SpamPressureCeiling => "spam_pressure_ceiling",
+        }
+    }
+
+    /// Describes the setting.
+    pub fn help(self) -> &'static str {
+        match self {
+            GlobalSetting::Activity => "The text shown as Glimbot's activity, e.g. \"Cultist Simulator\".",
+            GlobalSetting::Status => "Glimbot's online status: online, idle, dnd or invisible.",
+            GlobalSetting::MaintenanceMessage => {
+                "While set, commands from anyone outside the operator team are answered with this message instead of being run."
+            }
+            GlobalSetting::SpamPressureCeiling => {
+                "The highest max_pressure any server's spam filter uses, however leniently it's configured."
+            }
+        }
+    }
+
+    /// Whether the setting changes the bot's presence, and so needs to be sent to Discord.
+    pub fn affects_presence(self) -> bool {
+        matches!(self, GlobalSetting::Activity | GlobalSetting::Status)
+    }
+
+    /// Converts a string into the setting's stored JSON, checking it's valid.
+    pub fn validate(self, s: &str) -> crate::error::Result<serde_json::Value> {
+        match self {
+            GlobalSetting::Activity => {
+                let s = s.trim();
+                if s.is_empty() || s.chars().count() > MAX_ACTIVITY_LEN {
+                    return Err(InvalidActivity.into());
+                }
+                Ok(serde_json::Value::String(s.to_string()))
+            }
+            GlobalSetting::Status => {
+                let status = parse_status(s).ok_or(InvalidStatus)?;
+                Ok(serde_json::Value::String(status.name().to_string()))
+            }
+            GlobalSetting::MaintenanceMessage => {
+                let s = s.trim();
+                if s.is_empty() || s.chars().count() > MAX_MAINTENANCE_MESSAGE_LEN {
+                    return Err(InvalidMaintenanceMessage.into());
+                }
+                Ok(serde_json::Value::String(s.to_string()))
+            }
+            GlobalSetting::SpamPressureCeiling => {
+                let ceiling: f64 = s.trim().parse().map_err(|_| InvalidSpamCeiling)?;
+                if !ceiling.is_finite() || ceiling <= 0.0 {
+                    return Err(InvalidSpamCeiling.into());
+                }
+                Ok(serde_json::json!(ceiling))
+            }
+        }
+    }
+}
+
+/// Parses an online status a bot can set. Offline isn't one; invisible is how a bot appears offline.
+fn parse_status(s: &str) -> Option<OnlineStatus> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "online" => Some(OnlineStatus::Online),
+        "idle" => Some(OnlineStatus::Idle),
+        "dnd" => Some(OnlineStatus::DoNotDisturb),
+        "invisible" => Some(OnlineStatus::Invisible),
+        _ => None,
+    }
+}
+
+impl FromStr for GlobalSetting {
+    type Err = NoSuchGlobalSetting;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        GlobalSetting::ALL
+            .iter()
+            .copied()
+            .find(|g| g.name() == s)
+            .ok_or(NoSuchGlobalSetting)
+    }
+}
+
+impl fmt::Display for GlobalSetting {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The instance's global settings, mirrored in memory so lookups don't hit the DB.
+#[derive(Default, Debug)]
+pub struct GlobalConfig {
+    /// The current settings, keyed by name.
+    values: RwLock<HashMap<String, serde_json::Value>>,
+}
+
+impl GlobalConfig {
+    /// Loads all stored settings from the database, replacing any in memory.
+    pub async fn load(&self, pool: &PgPool) -> crate::error::Result<()> {
+        let rows = sqlx::query!("SELECT name, value FROM global_config;")
+            .fetch_all(pool)
+            .await?;
+
+        *self.values.write() = rows.into_iter().map(|r| (r.name, r.value)).collect();
+        Ok(())
+    }
+
+    /// Retrieves the raw JSON for a setting, if it's set.
+    pub fn get_json(&self, setting: GlobalSetting) -> Option<serde_json::Value> {
+        self.values.read().get(setting.name()).cloned()
+    }
+
+    /// Retrieves the activity text, if one is set.
+    pub fn activity(&self) -> Option<String> {
+        self.get_json(GlobalSetting::Activity)
+            .and_then(|v| v.as_str().map(str::to_string))
+    }
+
+    /// Retrieves the online status, if one is set.
+    pub fn status(&self) -> Option<OnlineStatus> {
+        self.get_json(GlobalSetting::Status)
+            .and_then(|v| v.as_str().and_then(parse_status))
+    }
+
+    /// Retrieves the maintenance message, if Glimbot is down for maintenance.
+    pub fn maintenance_message(&self) -> Option<String> {
+        self.get_json(GlobalSetting::MaintenanceMessage)
+            .and_then(|v| v.as_str().map(str::to_string))
+    }
+
+    /// Retrieves the spam pressure ceiling, if one is set.
+    pub fn spam_pressure_ceiling(&self) -> Option<f64> {
+        self.get_json(GlobalSetting::SpamPressureCeiling)
+            .and_then(|v| v.as_f64())
+    }
+
+    /// Sets a setting.
+    pub async fn set(
+        &self,
+        pool: &PgPool,
+        setting: GlobalSetting,
+        value: serde_json::Value,
+    ) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO global_config (name, value)
+            VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE
+                SET value = EXCLUDED.value;
+            "#,
+            setting.name(),
+            &value
+        )
+        .execute(pool)
+        .await?;
+
+        self.values.write().insert(setting.name().to_string(), value);
+        Ok(())
+    }
+
+    /// Removes a setting, returning whether it was set.
+    pub async fn unset(&self, pool: &PgPool, setting: GlobalSetting) -> crate::error::Result<bool> {
+        let res = sqlx::query!("DELETE FROM global_config WHERE name = $1;", setting.name())
+            .execute(pool)
+            .await?;
+
+        self.values.write().remove(setting.name());
+        Ok(res.rows_affected() > 0)
+    }
+}
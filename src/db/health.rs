@@ -0,0 +1,195 @@
+//! Contains logic for coping with an unreachable database. The background service checks the
+//! pool periodically; once enough checks fail in a row, Glimbot enters a degraded mode in which
+//! cached config still works, config changes are queued in the write buffer to be replayed once
+//! the database is back, and config reads which miss the cache fail fast instead of waiting on
+//! the pool.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::db::storage::Storage;
+
+/// The number of consecutive failed checks after which the database is considered down.
+pub const DEFAULT_FAILURE_THRESHOLD: u64 = 2;
+/// How long a health check may take before it counts as failed.
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// The most config writes which may be queued while the database is down. Past this, changes are
+/// refused rather than risking unbounded memory use.
+pub const DEGRADED_WRITE_LIMIT: usize = 10_000;
+
+impl_err!(
+    DatabaseUnavailable,
+    "Glimbot's database is unreachable right now, so that can't be done. Try again in a few minutes.",
+    true
+);
+impl_err!(
+    DegradedWriteQueueFull,
+    "Glimbot's database is unreachable, and too many changes are already waiting to be saved. Try again later.",
+    true
+);
+
+/// Whether the database is usable.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DbState {
+    /// Checks are passing.
+    Healthy,
+    /// Checks are failing; Glimbot is running from its caches.
+    Degraded,
+}
+
+impl fmt::Display for DbState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DbState::Healthy => "healthy",
+            DbState::Degraded => "degraded",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A view of the health check statistics. May or may not be accurate.
+#[derive(Debug, Copy, Clone)]
+pub struct HealthStats {
+    /// The current state.
+    pub state: DbState,
+    /// How long the last successful check took.
+    pub last_latency: Option<Duration>,
+    /// The number of checks run since startup.
+    pub checks: u64,
+    /// The number of those checks which failed.
+    pub failures: u64,
+    /// The number of times the database has gone down since startup.
+    pub outages: u64,
+}
+
+impl fmt::Display for HealthStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.state)?;
+        if let Some(l) = self.last_latency {
+            write!(f, ", {}ms", l.as_millis())?;
+        }
+        write!(
+            f,
+            " ({}/{} checks failed, {} outages)",
+            self.failures, self.checks, self.outages
+        )
+    }
+}
+
+/// Tracks the health of the database from periodic checks.
+#[derive(Debug)]
+pub struct DbHealth {
+    /// Set while the database is considered down.
+    degraded: AtomicBool,
+    /// The number of failed checks since the last success.
+    consecutive_failures: AtomicU64,
+    /// The number of checks run.
+    checks: AtomicU64,
+    /// The number of failed checks.
+    failures: AtomicU64,
+    /// The number of times the database has gone down.
+    outages: AtomicU64,
+    /// How long the last successful check took.
+    last_latency: Mutex<Option<Duration>>,
+    /// See [`DEFAULT_FAILURE_THRESHOLD`].
+    threshold: u64,
+}
+
+impl Default for DbHealth {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD)
+    }
+}
+
+impl DbHealth {
+    /// Creates a tracker which considers the database healthy until checks say otherwise.
+    pub fn new(threshold: u64) -> Self {
+        Self {
+            degraded: AtomicBool::new(false),
+            consecutive_failures: AtomicU64::new(0),
+            checks: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            outages: AtomicU64::new(0),
+            last_latency: Mutex::new(None),
+            threshold,
+        }
+    }
+
+    /// Retrieves the current state.
+    pub fn state(&self) -> DbState {
+        if self.is_degraded() {
+            DbState::Degraded
+        } else {
+            DbState::Healthy
+        }
+    }
+
+    /// Returns true if the database is considered down.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Acquire)
+    }
+
+    /// Fails with [`DatabaseUnavailable`] if the database is considered down, so callers can skip
+    /// waiting on the pool.
+    pub fn ensure_available(&self) -> crate::error::Result<()> {
+        if self.is_degraded() {
+            Err(DatabaseUnavailable.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Gets a view of the current statistics.
+    pub fn statistics(&self) -> HealthStats {
+        HealthStats {
+            state: self.state(),
+            last_latency: *self.last_latency.lock(),
+            checks: self.checks.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            outages: self.outages.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Runs a health check against the pool, updating the state. Returns the state afterwards.
+    #[instrument(level = "debug", skip(self, storage))]
+    pub async fn check(&self, storage: &dyn Storage) -> DbState {
+        self.checks.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
+        let res = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, storage.ping()).await;
+
+        match res {
+            Ok(Ok(_)) => self.record_success(start.elapsed()),
+            Ok(Err(e)) => self.record_failure(&e),
+            Err(_) => self.record_failure(&"timed out"),
+        }
+        self.state()
+    }
+
+    /// Records a passing check, leaving degraded mode.
+    fn record_success(&self, latency: Duration) {
+        trace!("database health check took {:?}", latency);
+        *self.last_latency.lock() = Some(latency);
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        if self.degraded.swap(false, Ordering::AcqRel) {
+            info!("database recovered; leaving degraded mode.");
+        }
+    }
+
+    /// Records a failed check, entering degraded mode once the threshold has been reached.
+    fn record_failure(&self, e: &dyn fmt::Display) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let fails = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!("database health check failed: {}", e);
+        if fails >= self.threshold && !self.degraded.swap(true, Ordering::AcqRel) {
+            self.outages.fetch_add(1, Ordering::Relaxed);
+            error!(
+                "database unreachable after {} failed checks; entering degraded mode.",
+                fails
+            );
+        }
+    }
+}
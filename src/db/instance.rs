@@ -0,0 +1,81 @@
+//! Contains instance defaults: config values set by the bot operator which apply to every guild
+//! that hasn't set its own value, taking precedence over the defaults compiled into Glimbot.
+
+use std::collections::HashMap;
+
+use parking_lot::RwLock;
+use serde::de::DeserializeOwned;
+use sqlx::PgPool;
+
+/// The instance-wide config defaults, mirrored in memory so lookups don't hit the DB.
+#[derive(Default, Debug)]
+pub struct InstanceDefaults {
+    /// The current defaults, keyed by config value name.
+    values: RwLock<HashMap<String, serde_json::Value>>,
+}
+
+impl InstanceDefaults {
+    /// Loads all stored instance defaults from the database, replacing any in memory.
+    pub async fn load(&self, pool: &PgPool) -> crate::error::Result<()> {
+        let rows = sqlx::query!("SELECT name, value FROM instance_config;")
+            .fetch_all(pool)
+            .await?;
+
+        *self.values.write() = rows.into_iter().map(|r| (r.name, r.value)).collect();
+        Ok(())
+    }
+
+    /// Retrieves the raw JSON for an instance default, if one is set.
+    pub fn get_json(&self, name: &str) -> Option<serde_json::Value> {
+        self.values.read().get(name).cloned()
+    }
+
+    /// Retrieves an instance default, if one is set. A stored value which no longer deserializes
+    /// (e.g. because the value's type changed) is logged and treated as unset.
+    pub fn get<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        let v = self.get_json(name)?;
+        match serde_json::from_value(v) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!("ignoring invalid instance default for {}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Lists the names of all values with an instance default.
+    pub fn names(&self) -> Vec<String> {
+        let mut out: Vec<String> = self.values.read().keys().cloned().collect();
+        out.sort_unstable();
+        out
+    }
+
+    /// Sets an instance default.
+    pub async fn set(&self, pool: &PgPool, name: &str, value: serde_json::Value) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO instance_config (name, value)
+            VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE
+                SET value = EXCLUDED.value;
+            "#,
+            name,
+            &value
+        )
+        .execute(pool)
+        .await?;
+
+        self.values.write().insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Removes an instance default, returning whether one was set.
+    pub async fn unset(&self, pool: &PgPool, name: &str) -> crate::error::Result<bool> {
+        let res = sqlx::query!("DELETE FROM instance_config WHERE name = $1;", name)
+            .execute(pool)
+            .await?;
+
+        self.values.write().remove(name);
+        Ok(res.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,301 @@
+//! Contains a thin instrumented layer over the connection pool. Every query run through an
+//! [`InstrumentedPool`] is timed and counted, and queries slower than a threshold are logged so hot
+//! paths can be found in production. Bound arguments are never logged, and literals in the SQL are
+//! masked, so logs can't leak message content or other user data.
+//!
+//! Functions which take a plain `&PgPool` still accept an `&InstrumentedPool` through deref, but
+//! queries they run aren't counted.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use parking_lot::Mutex;
+use sqlx::postgres::{PgQueryResult, PgRow, PgStatement, PgTypeInfo};
+use sqlx::{Describe, Either, Execute, Executor, PgPool, Postgres};
+
+/// How long a query may take before it's logged as slow, unless `GLIMBOT_SLOW_QUERY_MS` says
+/// otherwise.
+pub const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_millis(500);
+/// The most distinct queries tracked individually. Past this, queries are counted under
+/// [`OTHER_QUERIES`] so dynamically built SQL can't grow the table without bound.
+pub const MAX_TRACKED_QUERIES: usize = 512;
+/// The name queries are counted under once [`MAX_TRACKED_QUERIES`] has been reached.
+pub const OTHER_QUERIES: &str = "<other queries>";
+
+/// Timing for a single query, or for every query.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct QueryTiming {
+    /// How many times the query ran.
+    pub count: u64,
+    /// How many of those were slower than the threshold.
+    pub slow: u64,
+    /// The total time spent running it.
+    pub total: Duration,
+    /// The longest a single run took.
+    pub max: Duration,
+}
+
+impl QueryTiming {
+    /// Adds a run of the query.
+    fn record(&mut self, elapsed: Duration, slow: bool) {
+        self.count += 1;
+        self.slow += slow as u64;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+
+    /// The average time a run took.
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::default()
+        } else {
+            self.total / self.count as u32
+        }
+    }
+}
+
+impl fmt::Display for QueryTiming {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} runs ({} slow), {}ms total, {}ms avg, {}ms max",
+            self.count,
+            self.slow,
+            self.total.as_millis(),
+            self.mean().as_millis(),
+            self.max.as_millis()
+        )
+    }
+}
+
+/// Per-query timing and counts.
+#[derive(Debug)]
+pub struct QueryStats {
+    /// Timing for each query, keyed by its redacted SQL.
+    queries: Mutex<HashMap<String, QueryTiming>>,
+    /// The slow query threshold, in milliseconds.
+    slow_threshold_ms: AtomicU64,
+}
+
+impl Default for QueryStats {
+    fn default() -> Self {
+        Self {
+            queries: Mutex::new(HashMap::new()),
+            slow_threshold_ms: AtomicU64::new(DEFAULT_SLOW_QUERY_THRESHOLD.as_millis() as u64),
+        }
+    }
+}
+
+impl QueryStats {
+    /// Retrieves the slow query threshold.
+    pub fn slow_threshold(&self) -> Duration {
+        Duration::from_millis(self.slow_threshold_ms.load(Ordering::Relaxed))
+    }
+
+    /// Changes the slow query threshold.
+    pub fn set_slow_threshold(&self, threshold: Duration) {
+        self.slow_threshold_ms
+            .store(threshold.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Timing summed over every query.
+    pub fn totals(&self) -> QueryTiming {
+        self.queries.lock().values().fold(QueryTiming::default(), |mut acc, t| {
+            acc.count += t.count;
+            acc.slow += t.slow;
+            acc.total += t.total;
+            acc.max = acc.max.max(t.max);
+            acc
+        })
+    }
+
+    /// The queries which have taken the most time in total, most first.
+    pub fn hottest(&self, n: usize) -> Vec<(String, QueryTiming)> {
+        let mut queries: Vec<_> = self.queries.lock().iter().map(|(sql, t)| (sql.clone(), *t)).collect();
+        queries.sort_by_key(|(_, t)| std::cmp::Reverse(t.total));
+        queries.truncate(n);
+        queries
+    }
+
+    /// Forgets every recorded query.
+    pub fn reset(&self) {
+        self.queries.lock().clear();
+    }
+
+    /// Records a run of a query, logging it if it was slow.
+    fn record(&self, sql: &str, elapsed: Duration) {
+        let redacted = redact(sql);
+        let slow = elapsed >= self.slow_threshold();
+        if slow {
+            warn!("slow query took {}ms: {}", elapsed.as_millis(), redacted);
+        } else {
+            trace!("query took {}ms: {}", elapsed.as_millis(), redacted);
+        }
+
+        let mut queries = self.queries.lock();
+        let key = if queries.len() >= MAX_TRACKED_QUERIES && !queries.contains_key(&redacted) {
+            OTHER_QUERIES.to_string()
+        } else {
+            redacted
+        };
+        queries.entry(key).or_default().record(elapsed, slow);
+    }
+}
+
+/// Collapses whitespace in SQL and masks string and numeric literals. Bound arguments aren't part
+/// of the SQL, so this leaves nothing identifying.
+pub fn redact(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    // Whether the last character written was part of an identifier, so digits within names like
+    // `user_id2` or `$1` aren't taken for literals.
+    let mut in_word = false;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // Skip to the closing quote, treating doubled quotes as escapes.
+            while let Some(q) = chars.next() {
+                if q == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            out.push_str("'?'");
+            in_word = false;
+        } else if c.is_ascii_digit() && !in_word {
+            while chars.peek().map_or(false, |d| d.is_ascii_digit() || *d == '.') {
+                chars.next();
+            }
+            out.push('?');
+        } else if c.is_whitespace() {
+            if !out.is_empty() && !out.ends_with(' ') {
+                out.push(' ');
+            }
+            in_word = false;
+        } else {
+            out.push(c);
+            in_word = c.is_alphanumeric() || c == '_' || c == '$';
+        }
+    }
+    out.trim_end().to_string()
+}
+
+/// Times a query, recording it once dropped.
+pub(crate) struct QueryTimer<'s> {
+    /// Where to record the timing.
+    stats: &'s QueryStats,
+    /// The query being run.
+    sql: String,
+    /// When it started.
+    start: Instant,
+}
+
+impl<'s> QueryTimer<'s> {
+    /// Starts timing a query.
+    pub(crate) fn start(stats: &'s QueryStats, sql: &str) -> Self {
+        Self {
+            stats,
+            sql: sql.to_string(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for QueryTimer<'_> {
+    fn drop(&mut self) {
+        self.stats.record(&self.sql, self.start.elapsed());
+    }
+}
+
+/// A connection pool which records timing for every query run through it. Derefs to the
+/// underlying pool for anything other than running queries, like beginning transactions.
+#[derive(Debug, Clone)]
+pub struct InstrumentedPool {
+    /// The underlying pool.
+    pool: PgPool,
+    /// Where timing is recorded; shared between the primary and replica pools.
+    stats: Arc<QueryStats>,
+}
+
+impl InstrumentedPool {
+    /// Wraps a pool, recording timing into the given stats.
+    pub fn new(pool: PgPool, stats: Arc<QueryStats>) -> Self {
+        Self { pool, stats }
+    }
+
+    /// Retrieves the recorded timing.
+    pub fn stats(&self) -> &Arc<QueryStats> {
+        &self.stats
+    }
+}
+
+impl Deref for InstrumentedPool {
+    type Target = PgPool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+impl<'c> Executor<'c> for &'c InstrumentedPool {
+    type Database = Postgres;
+
+    fn fetch_many<'e, 'q: 'e, E>(self, query: E) -> BoxStream<'e, Result<Either<PgQueryResult, PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: Execute<'q, Self::Database> + 'q,
+    {
+        let timer = QueryTimer::start(&self.stats, query.sql());
+        // The timer lives as long as the stream, so the whole fetch is timed.
+        self.pool
+            .fetch_many(query)
+            .map(move |r| {
+                let _ = &timer;
+                r
+            })
+            .boxed()
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(self, query: E) -> BoxFuture<'e, Result<Option<PgRow>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: Execute<'q, Self::Database> + 'q,
+    {
+        let timer = QueryTimer::start(&self.stats, query.sql());
+        let fut = self.pool.fetch_optional(query);
+        Box::pin(async move {
+            let res = fut.await;
+            drop(timer);
+            res
+        })
+    }
+
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [PgTypeInfo],
+    ) -> BoxFuture<'e, Result<PgStatement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.pool.prepare_with(sql, parameters)
+    }
+
+    #[doc(hidden)]
+    fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, Result<Describe<Self::Database>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.pool.describe(sql)
+    }
+}
@@ -0,0 +1,96 @@
+//! Contains migrations shipped by modules rather than the central `migrations` directory, so a
+//! feature module can bring its own tables along with it.
+//!
+//! A module registers its migrations next to its code with [`inventory::submit!`]:
+//!
+//! ```ignore
+//! inventory::submit! {
+//!     ModuleMigration::new("tags", 20210425140832, "tags", include_str!("tags/migrations/20210425140832_tags.sql"))
+//! }
+//! ```
+//!
+//! At startup they're merged with the central migrations and ordered by version, so versions must
+//! be unique across both; use the timestamp the migration was written at, as `sqlx migrate add` does.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use sqlx::error::BoxDynError;
+use sqlx::migrate::{Migration, MigrationSource, MigrationType, Migrator};
+
+/// A migration belonging to a module.
+#[derive(Debug, Clone)]
+pub struct ModuleMigration {
+    /// The module the migration belongs to.
+    pub module: &'static str,
+    /// The migration's version, which orders it among every other migration.
+    pub version: i64,
+    /// A short description of the migration.
+    pub description: &'static str,
+    /// The SQL to run.
+    pub sql: &'static str,
+}
+
+impl ModuleMigration {
+    /// Creates a migration for the given module.
+    pub fn new(module: &'static str, version: i64, description: &'static str, sql: &'static str) -> Self {
+        Self {
+            module,
+            version,
+            description,
+            sql,
+        }
+    }
+
+    /// Converts this into a migration sqlx can run.
+    fn to_migration(&self) -> Migration {
+        Migration::new(
+            self.version,
+            Cow::Borrowed(self.description),
+            MigrationType::Simple,
+            Cow::Borrowed(self.sql),
+        )
+    }
+}
+
+inventory::collect!(ModuleMigration);
+
+/// Migrations which have already been gathered, for handing to [`Migrator::new`].
+#[derive(Debug)]
+struct Gathered(Vec<Migration>);
+
+impl MigrationSource<'static> for Gathered {
+    fn resolve(self) -> BoxFuture<'static, Result<Vec<Migration>, BoxDynError>> {
+        Box::pin(async move { Ok(self.0) })
+    }
+}
+
+/// Merges the central migrations with every module's, in version order.
+///
+/// # Panics
+/// Panics if two migrations share a version, since sqlx can't tell them apart.
+pub fn merged(central: Migrator) -> Migrator {
+    let mut all: BTreeMap<(i64, bool), (Cow<'static, str>, Migration)> = BTreeMap::new();
+    for m in central.iter() {
+        let key = (m.version, m.migration_type.is_down_migration());
+        all.insert(key, (Cow::Borrowed("migrations"), m.clone()));
+    }
+
+    for m in inventory::iter::<ModuleMigration> {
+        if let Some((owner, _)) = all.insert((m.version, false), (Cow::Borrowed(m.module), m.to_migration())) {
+            panic!(
+                "migration {} of module {} has the same version as one in {}",
+                m.version, m.module, owner
+            );
+        }
+    }
+
+    let gathered = Gathered(all.into_iter().map(|(_, (_, m))| m).collect());
+    // Resolving gathered migrations doesn't wait on anything, so this is always ready.
+    Migrator::new(gathered)
+        .now_or_never()
+        .expect("gathered migrations resolve immediately")
+        .expect("gathered migrations can't fail to resolve")
+}
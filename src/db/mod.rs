@@ -1,33 +1,56 @@
 //! Contains abstractions over the persistent store connections for glimbot.
-//! Currently, glimbot relies on a PostgreSQL server for its persistent store.
+//! Glimbot relies on a PostgreSQL server for its persistent store, though guild config, joinable
+//! roles and timed events can be kept in SQLite instead; see [`storage`].
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::io;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
+use std::time::Instant;
 
 use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use serenity::model::id::GuildId;
 use sqlx::migrate::Migrator;
-use sqlx::postgres::PgConnectOptions;
+use sqlx::postgres::{PgConnectOptions, PgListener};
 use sqlx::PgPool;
 
-use crate::db::cache::{Cache, NullEvictionStrategy};
+use crate::db::cache::{Cache, CacheStats, NullEvictionStrategy};
+use crate::db::health::{DegradedWriteQueueFull, DEGRADED_WRITE_LIMIT};
+use crate::db::instrumented::InstrumentedPool;
+use crate::db::storage::{postgres, NeedsPostgres, Storage, UnsupportedDatabase};
+use crate::db::write_buffer::{KeyClaim, MAX_PENDING_WRITES};
 
 use crate::dispatch::Dispatch;
 
 use downcast_rs::impl_downcast;
 use downcast_rs::DowncastSync;
-use futures::TryFutureExt;
+use futures::future::BoxFuture;
 use std::any::Any;
 
+pub mod backup;
+pub mod cases;
+pub mod cli;
+pub mod config_audit;
+pub mod exemptions;
+pub mod global;
+pub mod health;
+pub mod instance;
+pub mod instrumented;
+pub mod migrations;
+pub mod operators;
+pub mod phishing;
+pub mod purge;
+pub mod quota;
+pub mod storage;
 pub mod timed;
+pub mod write_buffer;
 #[macro_use]
 pub mod cache;
 
@@ -58,21 +81,128 @@ pub fn ensure_data_folder() -> io::Result<PathBuf> {
     Ok(dir)
 }
 
-/// The SQL migrations to be automatically applied on startup.
-static MIGRATIONS: Migrator = sqlx::migrate!();
+/// The SQL migrations to be automatically applied on startup, or by hand with `glimbot db migrate`:
+/// those in the `migrations` directory, along with any registered by modules. See [`migrations`].
+pub(crate) static MIGRATIONS: Lazy<Migrator> = Lazy::new(|| migrations::merged(sqlx::migrate!()));
 
-/// Create the database connection pool. This will eagerly spawn a single connection,
-/// and spawn more as contention occurs.
+/// Create the Postgres connection pool and run any pending migrations. This will eagerly spawn a
+/// single connection, and spawn more as contention occurs. Most callers want [`storage::create`],
+/// which also supports SQLite.
 pub async fn create_pool() -> crate::error::Result<PgPool> {
-    let db_url = std::env::var("DATABASE_URL")?;
-
-    let pool = sqlx::PgPool::connect_with(PgConnectOptions::from_str(&db_url)?.application_name("glimbot")).await?;
+    let pool = connect().await?;
 
     info!("Running DB migrations if necessary.");
     MIGRATIONS.run(&pool).await?;
     Ok(pool)
 }
 
+/// Create the Postgres connection pool without touching the schema.
+pub async fn connect() -> crate::error::Result<PgPool> {
+    let db_url = std::env::var("DATABASE_URL")?;
+    connect_to(&db_url).await
+}
+
+/// Create a connection pool for the read replica at `DATABASE_READ_URL`, if one is configured.
+pub async fn connect_replica() -> crate::error::Result<Option<PgPool>> {
+    match std::env::var("DATABASE_READ_URL") {
+        Ok(url) if !url.trim().is_empty() => Ok(Some(connect_to(url.trim()).await?)),
+        _ => Ok(None),
+    }
+}
+
+/// Create a Postgres connection pool for the given URL.
+pub(crate) async fn connect_to(db_url: &str) -> crate::error::Result<PgPool> {
+    // Say so up front rather than failing somewhere inside the driver on, e.g., a MySQL URL.
+    if db_url.starts_with("sqlite:") {
+        return Err(NeedsPostgres.into());
+    }
+    if !(db_url.starts_with("postgres://") || db_url.starts_with("postgresql://")) {
+        return Err(UnsupportedDatabase.into());
+    }
+
+    let pool = sqlx::PgPool::connect_with(PgConnectOptions::from_str(db_url)?.application_name("glimbot")).await?;
+    Ok(pool)
+}
+
+/// Retrieves the schema version the Postgres database has been migrated to, if any migrations have
+/// been applied.
+pub async fn schema_version<'e, E>(ex: E) -> crate::error::Result<Option<i64>>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let version = sqlx::query_scalar!("SELECT MAX(version) FROM _sqlx_migrations WHERE success;")
+        .fetch_one(ex)
+        .await?;
+    Ok(version)
+}
+
+/// How long every config value is read from the primary after changes may have been missed, to
+/// give read replicas time to catch up.
+pub const REPLICA_LAG_GRACE: std::time::Duration = std::time::Duration::from_secs(30);
+/// The channel Postgres notifies of config changes on. See the `config_notify` migration.
+pub const CONFIG_CHANGE_CHANNEL: &str = "glimbot_config_changes";
+/// How long to wait before listening again after the config change listener fails.
+const CONFIG_LISTENER_RETRY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Keeps the config cache in line with the database when other Glimbot processes share it. Every
+/// change to `config_values` is announced by Postgres, and the changed value is dropped from the
+/// cache so the next read fetches it. If the connection drops, changes may have been missed, so
+/// the whole cache is cleared, unless the database is down. Stops once Dispatch is dropped.
+pub async fn watch_config_changes(dispatch: Weak<Dispatch>) {
+    while let Some(d) = dispatch.upgrade() {
+        let listener = async {
+            let mut listener = PgListener::connect_with(d.pool()?).await?;
+            listener.listen(CONFIG_CHANGE_CHANNEL).await?;
+            crate::error::Result::Ok(listener)
+        }
+        .await;
+        std::mem::drop(d);
+        let mut listener = match listener {
+            Ok(l) => l,
+            Err(e) => {
+                error!("couldn't listen for config changes: {}", e);
+                tokio::time::sleep(CONFIG_LISTENER_RETRY).await;
+                continue;
+            }
+        };
+
+        loop {
+            let note = listener.try_recv().await;
+            let d = match dispatch.upgrade() {
+                Some(d) => d,
+                None => return,
+            };
+            match note {
+                Ok(Some(n)) => {
+                    let mut parts = n.payload().splitn(2, ':');
+                    let guild = parts.next().and_then(|g| g.parse::<u64>().ok());
+                    match (guild, parts.next()) {
+                        (Some(g), Some(name)) => d.config_cache().invalidate(GuildId(g), name),
+                        _ => warn!("unexpected config change payload: {}", n.payload()),
+                    }
+                }
+                // While the database is down, the cache is all there is; it's cleared once the
+                // database recovers instead.
+                Ok(None) if d.db_health().is_degraded() => {
+                    warn!("lost the config change listener's connection");
+                }
+                Ok(None) => {
+                    warn!("lost the config change listener's connection; clearing the config cache");
+                    d.config_cache().clear();
+                }
+                Err(e) => {
+                    error!("config change listener failed: {}", e);
+                    if !d.db_health().is_degraded() {
+                        d.config_cache().clear();
+                    }
+                    break;
+                }
+            }
+        }
+        tokio::time::sleep(CONFIG_LISTENER_RETRY).await;
+    }
+}
+
 /// A thin wrapper around a DB pool and the guild which queries should target.
 #[derive(Clone)]
 pub struct DbContext<'pool> {
@@ -96,12 +226,6 @@ impl DbContext<'_> {
     }
 }
 
-#[doc(hidden)]
-#[derive(Debug)]
-struct ConfigRow {
-    value: serde_json::Value,
-}
-
 /// The actual contents of a cache member
 pub type CVal = Arc<dyn Cacheable>;
 
@@ -115,33 +239,158 @@ impl<T> Cacheable for T where T: Any + Send + Sync + DowncastSync {}
 pub struct ConfigCache {
     /// The backing cache
     cache: HashMap<String, Cache<GuildId, CVal>>,
+    /// Tracks values which are known to be unset for a guild, so falling back to a default doesn't
+    /// hit the DB every time.
+    unset: HashMap<String, Cache<GuildId, ()>>,
+    /// Values which changed since they were last read. A read replica may not have the change
+    /// yet, so these are next read from the primary.
+    changed: Mutex<HashSet<(GuildId, String)>>,
+    /// After changes may have been missed, every value is read from the primary until this time.
+    primary_reads_until: Mutex<Option<Instant>>,
     /// The number of times we had to query the DB backend.
     cache_misses: AtomicU64,
     /// The number of times the cache was accessed.
     cache_accesses: AtomicU64,
+    /// Fetches of values which missed the cache, so concurrent misses for the same value wait on a
+    /// single fetch instead of each querying the DB.
+    fetches: Mutex<PendingFetches>,
 }
 
-/// Represents the values of the cache statistics.
-pub struct CacheStats {
-    /// Number of times the cache was accessed.
-    pub accesses: u64,
-    /// Number of times we had to access the DB
-    pub misses: u64,
+/// Config values being fetched after missing the cache, each with a lock held for the duration
+/// of its fetch.
+type PendingFetches = HashMap<(GuildId, String), Arc<tokio::sync::Mutex<()>>>;
+
+/// A claim on fetching a value which missed the cache; see [`ConfigCache::claim_fetch`]. Forgets
+/// the fetch once nobody else is waiting on it.
+struct FetchClaim<'a> {
+    /// Where the fetch is tracked.
+    fetches: &'a Mutex<PendingFetches>,
+    /// The value being fetched.
+    key: (GuildId, String),
+    /// Held by whichever task is fetching.
+    lock: Arc<tokio::sync::Mutex<()>>,
+    /// Set once this task's turn to fetch has come.
+    guard: Option<tokio::sync::OwnedMutexGuard<()>>,
+}
+
+impl Drop for FetchClaim<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut fetches = self.fetches.lock();
+        // One reference is in the map and one is ours; any more belong to tasks still waiting.
+        if Arc::strong_count(&self.lock) <= 2 {
+            fetches.remove(&self.key);
+        }
+    }
 }
 
 impl_err!(BadCast, "Cache contained a mismatched type.", false);
 
 impl ConfigCache {
-    /// Gets a view of the current cache statistics. May or may not be accurate.
+    /// Gets a view of the current cache statistics. May or may not be accurate. Misses count
+    /// trips to the database, and entries and evictions are summed over every config key.
     pub fn statistics(&self) -> CacheStats {
+        let accesses = self.cache_accesses.load(Ordering::Relaxed);
+        let misses = self.cache_misses.load(Ordering::Relaxed);
+        let inner = self
+            .cache
+            .values()
+            .map(Cache::statistics)
+            .chain(self.unset.values().map(Cache::statistics))
+            .fold(CacheStats::default(), CacheStats::combine);
         CacheStats {
-            accesses: self.cache_accesses.load(Ordering::Relaxed),
-            misses: self.cache_misses.load(Ordering::Relaxed),
+            hits: accesses.saturating_sub(misses),
+            misses,
+            evictions: inner.evictions,
+            entries: inner.entries,
         }
     }
 
     pub fn add_key(&mut self, s: impl Into<String>) {
-        self.cache.insert(s.into(), Cache::new(NullEvictionStrategy));
+        let s = s.into();
+        self.unset.insert(s.clone(), Cache::new(NullEvictionStrategy));
+        self.cache.insert(s, Cache::new(NullEvictionStrategy));
+    }
+
+    /// Forgets that a value was unset for a guild, since it's about to be set.
+    fn clear_unset<K: ConfigKey>(&self, gid: GuildId, key: &K) {
+        if let Some(c) = self.unset.get(key.to_key().as_ref()) {
+            c.remove(&gid);
+        }
+    }
+
+    /// Removes a guild's value from the cache, remembering that it's unset.
+    pub fn remove<K: ConfigKey>(&self, gid: GuildId, key: K) {
+        let key = key.to_key();
+        self.cache
+            .get(key.as_ref())
+            .expect("Unexpected config key")
+            .remove(&gid);
+        self.unset
+            .get(key.as_ref())
+            .expect("Unexpected config key")
+            .insert(&gid, ());
+    }
+
+    /// Forgets everything cached about a guild's value, set or unset, so the next read goes to the
+    /// DB. Keys this process doesn't know about are ignored.
+    pub fn invalidate<K: ConfigKey>(&self, gid: GuildId, key: K) {
+        let key = key.to_key();
+        if let Some(c) = self.cache.get(key.as_ref()) {
+            c.remove(&gid);
+        }
+        if let Some(c) = self.unset.get(key.as_ref()) {
+            c.remove(&gid);
+        }
+        if self.cache.contains_key(key.as_ref()) {
+            self.changed.lock().insert((gid, key.into_owned()));
+        }
+    }
+
+    /// Forgets every cached value for a guild, set or unset, so the next reads go to the primary.
+    pub fn invalidate_guild(&self, gid: GuildId) {
+        let mut changed = self.changed.lock();
+        for (key, c) in self.cache.iter() {
+            c.remove(&gid);
+            changed.insert((gid, key.clone()));
+        }
+        for c in self.unset.values() {
+            c.remove(&gid);
+        }
+    }
+
+    /// Forgets a value for every guild, set or unset, so the next reads go to the primary. Keys this
+    /// process doesn't know about are ignored.
+    pub fn invalidate_key(&self, key: &str) {
+        if let Some(c) = self.cache.get(key) {
+            let mut changed = self.changed.lock();
+            for gid in c.keys() {
+                changed.insert((gid, key.to_string()));
+            }
+            c.clear();
+        }
+        if let Some(c) = self.unset.get(key) {
+            c.clear();
+        }
+    }
+
+    /// Forgets every cached value, for when changes may have been missed.
+    pub fn clear(&self) {
+        for c in self.cache.values() {
+            c.clear();
+        }
+        for c in self.unset.values() {
+            c.clear();
+        }
+        self.changed.lock().clear();
+        *self.primary_reads_until.lock() = Some(Instant::now() + REPLICA_LAG_GRACE);
+    }
+
+    /// Returns true if a value should be read from the primary rather than a replica, because it
+    /// changed recently. Reading it clears the mark.
+    fn take_changed(&self, gid: GuildId, key: &str) -> bool {
+        let changed = self.changed.lock().remove(&(gid, key.to_string()));
+        changed || self.primary_reads_until.lock().map_or(false, |t| Instant::now() < t)
     }
 
     /// Track an access
@@ -154,7 +403,25 @@ impl ConfigCache {
         self.cache_misses.fetch_add(1, Ordering::Relaxed);
     }
 
-    /// Retrieves or inserts a value from the guild cache, using the given future.
+    /// Waits until no other task is fetching a value, then claims the fetch until the returned
+    /// claim is dropped.
+    async fn claim_fetch(&self, gid: GuildId, key: &str) -> FetchClaim<'_> {
+        let key = (gid, key.to_string());
+        let lock = self.fetches.lock().entry(key.clone()).or_default().clone();
+        // Made before waiting, so the fetch is still forgotten if this task is cancelled.
+        let mut claim = FetchClaim {
+            fetches: &self.fetches,
+            key,
+            lock,
+            guard: None,
+        };
+        claim.guard = Some(claim.lock.clone().lock_owned().await);
+        claim
+    }
+
+    /// Retrieves or inserts a value from the guild cache, using the given future. If the value
+    /// is already being fetched by another task, this waits for that fetch rather than running
+    /// its own, and only runs the future if that fetch failed.
     pub async fn get_or_insert_with<K, Fut, R>(&self, gid: GuildId, key: K, f: Fut) -> crate::error::Result<Arc<R>>
     where
         K: ConfigKey,
@@ -162,17 +429,26 @@ impl ConfigCache {
         R: Cacheable + Sized + Clone,
     {
         self.inc_access();
-        let f = f.and_then(|r: R| async {
-            self.inc_miss();
-            let cv: CVal = Arc::new(r);
-            Ok(cv)
-        });
-        let cv = self
-            .cache
-            .get(key.to_key().as_ref())
-            .expect("Unexpected config key")
-            .get_or_insert_with(&gid, f)
-            .await?;
+        self.clear_unset(gid, &key);
+        let key = key.to_key();
+        let cache = self.cache.get(key.as_ref()).expect("Unexpected config key");
+        let cv = match cache.get(&gid) {
+            Some(cv) => cv,
+            None => {
+                let _claim = self.claim_fetch(gid, key.as_ref()).await;
+                // The lookup was counted above, so these don't count another.
+                match cache.peek(&gid) {
+                    // Another task's fetch finished while this waited.
+                    Some(cv) => cv,
+                    None => {
+                        let r = f.await?;
+                        self.inc_miss();
+                        let cv: CVal = Arc::new(r);
+                        cache.insert_if_absent(&gid, cv)
+                    }
+                }
+            }
+        };
         Arc::clone(cv.as_ref()).downcast_arc::<R>().map_err(|_| BadCast.into())
     }
 
@@ -187,6 +463,7 @@ impl ConfigCache {
         self.inc_access();
         trace!("updating cache");
         let ins = f.await?;
+        self.clear_unset(gid, &key);
         self.cache
             .get(key.to_key().as_ref())
             .expect("Unexpected config key")
@@ -207,24 +484,51 @@ impl ConfigCache {
             .get(key.to_key().as_ref())
             .expect("Unexpected config key")
             .get(&gid);
+        let unset = self.unset.get(key.to_key().as_ref()).expect("Unexpected config key");
         if let Some(v) = val_cache {
             Arc::clone(v.as_ref())
                 .downcast_arc::<R>()
                 .map_err(|_| BadCast.into())
                 .map(Some)
+        } else if unset.get(&gid).is_some() {
+            Ok(None)
         } else if let Some(v) = f.await? {
             self.get_or_insert_with(gid, key, async { Ok(v) }).await.map(Some)
         } else {
+            self.inc_miss();
+            unset.insert(&gid, ());
             Ok(None)
         }
     }
 }
 
 impl DbContext<'_> {
-    /// Retrieves a reference to the underlying connection pool.
-    pub fn conn(&self) -> &PgPool {
+    /// Retrieves a reference to the underlying Postgres connection pool. Errors with
+    /// [`NeedsPostgres`] if the data is kept elsewhere.
+    pub fn conn(&self) -> crate::error::Result<&InstrumentedPool> {
         self.conn.pool()
     }
+
+    /// Retrieves the Postgres pool for read-only queries, which is the read replica if one is
+    /// configured. Replicas may lag behind, so don't use this for reads which must see a write
+    /// just made.
+    pub fn read_conn(&self) -> crate::error::Result<&InstrumentedPool> {
+        self.conn.read_pool()
+    }
+
+    /// Retrieves the storage backend holding config, joinable roles and timed events.
+    pub fn storage(&self) -> &dyn Storage {
+        self.conn.storage()
+    }
+
+    /// Retrieves the instance default for a config value, if the bot operator has set one.
+    pub fn instance_default<B, D>(&self, key: B) -> Option<D>
+    where
+        B: ConfigKey,
+        D: DeserializeOwned,
+    {
+        self.conn.instance_defaults().get(key.to_key().as_ref())
+    }
 }
 
 impl<'pool> DbContext<'pool> {
@@ -251,27 +555,24 @@ impl<'pool> DbContext<'pool> {
             .await
     }
 
-    /// Retrieves or inserts a value for the guild config. This version always hits the DB.
+    /// Retrieves a value for the guild config, falling back to the given default. This version
+    /// always misses the cache. A default is stored in the background, through the write buffer.
     async fn get_or_insert_uncached_with<B, S, F>(&self, key: B, def: F) -> crate::error::Result<S>
     where
         B: ConfigKey,
         S: Serialize + DeserializeOwned,
         F: (Fn() -> S) + Send + Sync,
     {
-        let v = serde_json::to_value(def())?;
         let key = key.to_key();
+        if let Some(v) = self.get_uncached(key.as_ref()).await? {
+            return Ok(v);
+        }
 
-        let out: Option<serde_json::Value> = sqlx::query_scalar!(
-            r#"
-                SELECT res AS value FROM get_or_insert_config($1, $2, $3);
-                "#,
-            self.guild_as_i64(),
-            key.as_ref(),
-            v
-        )
-        .fetch_one(self.conn())
-        .await?;
-        Ok(serde_json::from_value(out.expect("Failed to submit value to DB?"))?)
+        let v = def();
+        self.conn
+            .config_writes()
+            .set_default(self.guild, key.as_ref(), serde_json::to_value(&v)?);
+        Ok(v)
     }
 
     /// Inserts a value into the guild config. This version will hit the cache in addition to the database.
@@ -281,12 +582,45 @@ impl<'pool> DbContext<'pool> {
         B: ConfigKey,
         S: Cacheable + Clone + Sized + Serialize,
     {
+        // Rather than failing, hold the change until the database is back.
+        if self.conn.db_health().is_degraded() {
+            return self.insert_deferred(key, val).await;
+        }
+
         self.conn
             .config_cache()
             .insert_with(self.guild, key.to_key(), self.insert_uncached(key.to_key(), val))
             .await
     }
 
+    /// Inserts a value into the guild config without writing it to the database straight away.
+    /// Later writes to the same key replace it, and the latest is written when the buffer is next
+    /// flushed. Use this for values which change often and can stand to be lost in a crash.
+    #[instrument(level = "trace", skip(self, key, val), fields(g = % self.guild, k = % key.to_key()))]
+    pub async fn insert_deferred<B, S>(&self, key: B, val: S) -> crate::error::Result<()>
+    where
+        B: ConfigKey,
+        S: Cacheable + Clone + Sized + Serialize,
+    {
+        let writes = self.conn.config_writes();
+        let degraded = self.conn.db_health().is_degraded();
+        if degraded && writes.len() >= DEGRADED_WRITE_LIMIT {
+            return Err(DegradedWriteQueueFull.into());
+        }
+
+        writes.set(self.guild, key.to_key(), serde_json::to_value(&val)?);
+        self.conn
+            .config_cache()
+            .insert_with(self.guild, key.to_key(), async { Ok(val) })
+            .await?;
+
+        // While the database is down, writes are replayed once the background service sees it's back.
+        if !degraded && writes.len() >= MAX_PENDING_WRITES {
+            writes.flush(self.storage()).await?;
+        }
+        Ok(())
+    }
+
     /// Inserts a value into the guild config, and will bypass the cache. This should be avoided to avoid stale reads from the cache.
     async fn insert_uncached<B, S>(&self, key: B, val: S) -> crate::error::Result<S>
     where
@@ -295,24 +629,26 @@ impl<'pool> DbContext<'pool> {
     {
         let key = key.to_key();
         let v = serde_json::to_value(&val)?;
-
-        sqlx::query!(
-            r#"
-            INSERT INTO config_values (guild, name, value)
-            VALUES ($1, $2, $3)
-            ON CONFLICT (guild, name) DO UPDATE
-                SET value = EXCLUDED.value;
-            "#,
-            self.guild_as_i64(),
-            key.as_ref(),
-            &v
-        )
-        .execute(self.conn())
-        .await
-        .map(|_| ())?;
+        let _claim = self.conn.config_writes().claim(self.guild, key.as_ref()).await;
+        self.storage().upsert_config_value(self.guild, key.as_ref(), &v).await?;
         Ok(val)
     }
 
+    /// Removes a value from the guild config, so the default applies again. Returns whether the
+    /// guild had set the value.
+    #[instrument(level = "trace", skip(self, key), fields(g = % self.guild, k = % key.to_key()))]
+    pub async fn remove<B>(&self, key: B) -> crate::error::Result<bool>
+    where
+        B: ConfigKey,
+    {
+        let key = key.to_key();
+        self.conn.db_health().ensure_available()?;
+        let _claim = self.conn.config_writes().claim(self.guild, key.as_ref()).await;
+        let removed = self.storage().delete_config_value(self.guild, key.as_ref()).await?;
+        self.conn.config_cache().remove(self.guild, key.as_ref());
+        Ok(removed)
+    }
+
     /// Hits the cache to retrieve a config value, hitting the DB if necessary.
     #[instrument(level = "trace", skip(self, key), fields(g = % self.guild, k = % key.to_key()))]
     pub async fn get<B, D>(&self, key: B) -> crate::error::Result<Option<Arc<D>>>
@@ -326,35 +662,202 @@ impl<'pool> DbContext<'pool> {
             .await
     }
 
-    /// Grabs a value from the database.
+    /// Grabs a value from the write buffer, or failing that, the database.
     async fn get_uncached<B, D>(&self, key: B) -> crate::error::Result<Option<D>>
     where
         B: ConfigKey,
         D: DeserializeOwned,
     {
         let key = key.to_key();
-        let o: Option<ConfigRow> = sqlx::query_as!(
-            ConfigRow,
-            r#"
-            SELECT value FROM config_values WHERE guild = $1 AND name = $2;
-            "#,
-            self.guild_as_i64(),
-            key.as_ref(),
-        )
-        .fetch_optional(self.conn())
-        .await?;
-        Ok(o.map(|c| c.value).map(serde_json::from_value).transpose()?)
+        if let Some(v) = self.conn.config_writes().get(self.guild, key.as_ref()) {
+            return Ok(Some(serde_json::from_value(v)?));
+        }
+
+        self.conn.db_health().ensure_available()?;
+        let primary = self.conn.config_cache().take_changed(self.guild, key.as_ref());
+        let v = self
+            .storage()
+            .fetch_config_value(self.guild, key.as_ref(), primary)
+            .await?;
+        Ok(v.map(serde_json::from_value).transpose()?)
+    }
+
+    /// Begins a transaction targeting this context's guild. Nothing written through the returned
+    /// context is visible outside it until [`TxContext::commit`] is called; dropping it rolls back.
+    /// Transactions need Postgres.
+    pub async fn begin(&self) -> crate::error::Result<TxContext<'pool>> {
+        self.conn.db_health().ensure_available()?;
+        Ok(TxContext {
+            guild: self.guild,
+            conn: self.conn,
+            tx: self.conn()?.begin().await?,
+            written: Vec::new(),
+        })
+    }
+
+    /// Runs the given closure in a transaction, committing if it succeeds and rolling back if it
+    /// fails. Use this when several writes must land together or not at all, e.g. opening a case
+    /// and scheduling its reversal.
+    pub async fn transaction<F, R>(&self, f: F) -> crate::error::Result<R>
+    where
+        F: for<'t> FnOnce(&'t mut TxContext<'pool>) -> BoxFuture<'t, crate::error::Result<R>>,
+    {
+        let mut tx = self.begin().await?;
+        match f(&mut tx).await {
+            Ok(r) => {
+                tx.commit().await?;
+                Ok(r)
+            }
+            Err(e) => {
+                tx.rollback().await?;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A guild-focused context whose queries all run in a single transaction, created by
+/// [`DbContext::begin`] or [`DbContext::transaction`]. The config cache isn't consulted or
+/// updated until the transaction commits, so reads see the transaction's own writes.
+pub struct TxContext<'pool> {
+    /// The guild that queries will target.
+    guild: GuildId,
+    /// The dispatch holding the config cache and write buffer.
+    conn: &'pool Dispatch,
+    /// The open transaction.
+    tx: sqlx::Transaction<'static, sqlx::Postgres>,
+    /// The config keys written in this transaction, to be dropped from the cache on commit. Each
+    /// is claimed in the write buffer before it's first written and until the transaction ends,
+    /// so a flush can't write an older value over it.
+    written: Vec<KeyClaim<'pool>>,
+}
+
+impl<'pool> TxContext<'pool> {
+    /// Gets the guild this context refers to.
+    pub fn guild(&self) -> GuildId {
+        self.guild
+    }
+
+    /// Gets the guild this context refers to as an i64.
+    pub fn guild_as_i64(&self) -> i64 {
+        self.guild.0 as i64
+    }
+
+    /// Retrieves the underlying transaction, for running other queries as part of it.
+    pub fn conn(&mut self) -> &mut sqlx::Transaction<'static, sqlx::Postgres> {
+        &mut self.tx
+    }
+
+    /// Retrieves a config value as the transaction sees it.
+    #[instrument(level = "trace", skip(self, key), fields(g = % self.guild, k = % key.to_key()))]
+    pub async fn get<B, D>(&mut self, key: B) -> crate::error::Result<Option<Arc<D>>>
+    where
+        B: ConfigKey,
+        D: DeserializeOwned,
+    {
+        let key = key.to_key();
+        if !self.has_written(key.as_ref()) {
+            if let Some(v) = self.conn.config_writes().get(self.guild, key.as_ref()) {
+                return Ok(Some(Arc::new(serde_json::from_value(v)?)));
+            }
+        }
+
+        let v = postgres::fetch_config_value(&mut self.tx, self.guild, key.as_ref()).await?;
+        Ok(v.map(serde_json::from_value).transpose()?.map(Arc::new))
+    }
+
+    /// Retrieves a config value as the transaction sees it, falling back to the given default. As
+    /// outside a transaction, the default is stored in the background through the write buffer.
+    #[instrument(level = "trace", skip(self, key, def), fields(g = % self.guild, k = % key.to_key()))]
+    pub async fn get_or_insert_with<B, S, F>(&mut self, key: B, def: F) -> crate::error::Result<Arc<S>>
+    where
+        B: ConfigKey,
+        S: Serialize + DeserializeOwned,
+        F: (Fn() -> S) + Send + Sync,
+    {
+        let key = key.to_key();
+        if let Some(v) = self.get(key.as_ref()).await? {
+            return Ok(v);
+        }
+
+        let v = def();
+        self.conn
+            .config_writes()
+            .set_default(self.guild, key.as_ref(), serde_json::to_value(&v)?);
+        Ok(Arc::new(v))
+    }
+
+    /// Inserts a value into the guild config as part of the transaction.
+    #[instrument(level = "trace", skip(self, key, val), fields(g = % self.guild, k = % key.to_key()))]
+    pub async fn insert<B, S>(&mut self, key: B, val: S) -> crate::error::Result<()>
+    where
+        B: ConfigKey,
+        S: Serialize,
+    {
+        let key = key.to_key();
+        let v = serde_json::to_value(&val)?;
+        self.claim(key.as_ref()).await;
+        postgres::upsert_config_value(&mut self.tx, self.guild, key.as_ref(), &v).await?;
+        Ok(())
+    }
+
+    /// Removes a value from the guild config as part of the transaction. Returns whether the
+    /// guild had set the value.
+    #[instrument(level = "trace", skip(self, key), fields(g = % self.guild, k = % key.to_key()))]
+    pub async fn remove<B>(&mut self, key: B) -> crate::error::Result<bool>
+    where
+        B: ConfigKey,
+    {
+        let key = key.to_key();
+        self.claim(key.as_ref()).await;
+        let removed = postgres::delete_config_value(&mut self.tx, self.guild, key.as_ref()).await?;
+        Ok(removed)
+    }
+
+    /// Returns true if the transaction has written the given key.
+    fn has_written(&self, key: &str) -> bool {
+        self.written.iter().any(|c| c.key() == key)
+    }
+
+    /// Claims a key in the write buffer before the transaction first writes it. Writing a key
+    /// outside the transaction waits until the transaction ends, as the database would anyway.
+    async fn claim(&mut self, key: &str) {
+        if !self.has_written(key) {
+            let claim = self.conn.config_writes().claim(self.guild, key).await;
+            self.written.push(claim);
+        }
+    }
+
+    /// Commits the transaction, and drops the keys it wrote from the config cache. Buffered writes
+    /// queued for those keys since the transaction wrote them are newer, so they're flushed after.
+    pub async fn commit(self) -> crate::error::Result<()> {
+        self.tx.commit().await?;
+        for claim in &self.written {
+            self.conn.config_cache().invalidate(self.guild, claim.key());
+        }
+        Ok(())
+    }
+
+    /// Rolls back the transaction, discarding everything written through it. Buffered writes the
+    /// transaction discarded when it wrote their keys are queued again.
+    pub async fn rollback(self) -> crate::error::Result<()> {
+        let res = self.tx.rollback().await;
+        for claim in self.written {
+            claim.restore();
+        }
+        res?;
+        Ok(())
     }
 }
 
 /// Trait for configuration keys to implement.
 pub trait ConfigKey {
     /// Should return this key as a view on a string.
-    fn to_key(&self) -> Cow<str>;
+    fn to_key(&self) -> Cow<'_, str>;
 }
 
 impl<T: AsRef<str>> ConfigKey for T {
-    fn to_key(&self) -> Cow<str> {
+    fn to_key(&self) -> Cow<'_, str> {
         self.as_ref().into()
     }
 }
@@ -0,0 +1,173 @@
+//! Contains the operator team: the users allowed to run instance-wide commands. Owners set in
+//! Glimbot's environment are always present; further owners and operators are stored in the
+//! database, and members of an optional role in a home guild are treated as operators.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use parking_lot::RwLock;
+use serenity::client::Context;
+use serenity::model::id::{GuildId, RoleId, UserId};
+use sqlx::PgPool;
+
+/// How much of Glimbot a member of the operator team may control.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum OperatorTier {
+    /// May run operator commands, like `status`.
+    Operator,
+    /// May run every instance-wide command, including shutting down Glimbot and managing operators.
+    Owner,
+}
+
+impl_err!(
+    InvalidOperatorTier,
+    "Operator tier must be `owner` or `operator`.",
+    false
+);
+
+impl FromStr for OperatorTier {
+    type Err = InvalidOperatorTier;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner" => Ok(OperatorTier::Owner),
+            "operator" => Ok(OperatorTier::Operator),
+            _ => Err(InvalidOperatorTier),
+        }
+    }
+}
+
+impl fmt::Display for OperatorTier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            OperatorTier::Operator => "operator",
+            OperatorTier::Owner => "owner",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The operator team, with the stored members mirrored in memory so checks don't hit the DB.
+#[derive(Debug, Default)]
+pub struct Operators {
+    /// Owners set in the environment. These can't be removed at runtime.
+    configured: HashSet<UserId>,
+    /// A role in a home guild whose members are operators.
+    home_role: Option<(GuildId, RoleId)>,
+    /// Owners and operators added at runtime.
+    stored: RwLock<HashMap<UserId, OperatorTier>>,
+}
+
+impl Operators {
+    /// Creates an operator team with the given configured owners and optional home guild role.
+    pub fn new(owners: impl IntoIterator<Item = UserId>, home_role: Option<(GuildId, RoleId)>) -> Self {
+        Operators {
+            configured: owners.into_iter().collect(),
+            home_role,
+            stored: Default::default(),
+        }
+    }
+
+    /// Loads the stored owners and operators from the database, replacing any in memory.
+    pub async fn load(&self, pool: &PgPool) -> crate::error::Result<()> {
+        let rows = sqlx::query!("SELECT user_id, tier FROM instance_operators;")
+            .fetch_all(pool)
+            .await?;
+
+        let mut stored = HashMap::with_capacity(rows.len());
+        for r in rows {
+            match r.tier.parse() {
+                Ok(t) => {
+                    stored.insert(UserId::from(r.user_id as u64), t);
+                }
+                Err(e) => warn!("ignoring operator {}: {}", r.user_id, e),
+            }
+        }
+        *self.stored.write() = stored;
+        Ok(())
+    }
+
+    /// Whether a user is an owner set in the environment.
+    pub fn is_configured_owner(&self, user: UserId) -> bool {
+        self.configured.contains(&user)
+    }
+
+    /// The role in a home guild whose members are operators, if any.
+    pub fn home_role(&self) -> Option<(GuildId, RoleId)> {
+        self.home_role
+    }
+
+    /// Determines a user's tier, if they're on the operator team. The home guild role is checked
+    /// only if the user isn't listed explicitly.
+    pub async fn tier(&self, ctx: &Context, user: UserId) -> Option<OperatorTier> {
+        if self.configured.contains(&user) {
+            return Some(OperatorTier::Owner);
+        }
+
+        if let Some(t) = self.stored.read().get(&user).copied() {
+            return Some(t);
+        }
+
+        let (guild, role) = self.home_role?;
+        match guild.member(ctx, user).await {
+            Ok(m) if m.roles.contains(&role) => Some(OperatorTier::Operator),
+            _ => None,
+        }
+    }
+
+    /// Lists the explicitly listed members of the team, configured owners first.
+    pub fn list(&self) -> Vec<(UserId, OperatorTier)> {
+        let mut configured: Vec<_> = self.configured.iter().map(|u| (*u, OperatorTier::Owner)).collect();
+        configured.sort_unstable();
+
+        let mut stored: Vec<_> = self
+            .stored
+            .read()
+            .iter()
+            .filter(|(u, _)| !self.configured.contains(u))
+            .map(|(u, t)| (*u, *t))
+            .collect();
+        stored.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        configured.extend(stored);
+        configured
+    }
+
+    /// Adds a user to the team, or changes their tier.
+    pub async fn set(
+        &self,
+        pool: &PgPool,
+        user: UserId,
+        tier: OperatorTier,
+        added_by: UserId,
+    ) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO instance_operators (user_id, tier, added_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE
+                SET tier = EXCLUDED.tier, added_by = EXCLUDED.added_by;
+            "#,
+            user.0 as i64,
+            tier.to_string(),
+            added_by.0 as i64
+        )
+        .execute(pool)
+        .await?;
+
+        self.stored.write().insert(user, tier);
+        Ok(())
+    }
+
+    /// Removes a user from the team, returning whether they were on it.
+    pub async fn remove(&self, pool: &PgPool, user: UserId) -> crate::error::Result<bool> {
+        let res = sqlx::query!("DELETE FROM instance_operators WHERE user_id = $1;", user.0 as i64)
+            .execute(pool)
+            .await?;
+
+        self.stored.write().remove(&user);
+        Ok(res.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,157 @@
+//! Contains the phishing domain list: domains known to host phishing or scam pages, fetched
+//! periodically by the background service from a feed set by the bot operator. The list is stored in
+//! the database so it survives restarts and feed outages, and mirrored in memory for lookups.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Mutex, RwLock};
+use sqlx::PgPool;
+
+/// The environment variable holding the URL of the phishing domain feed. The feed may be a plain
+/// list with one domain per line (`#` starts a comment), or a JSON array of domains, or a JSON
+/// object with a `domains` array.
+pub const PHISHING_FEED_VAR: &str = "GLIMBOT_PHISHING_FEED";
+/// How often the feed is fetched.
+pub const PHISHING_REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+/// How long to wait before trying again after the feed couldn't be fetched.
+pub const PHISHING_RETRY_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// The most domains accepted from the feed.
+pub const MAX_PHISHING_DOMAINS: usize = 250_000;
+
+impl_err!(
+    EmptyPhishingFeed,
+    "The phishing feed didn't contain any domains; keeping the current list.",
+    false
+);
+
+/// Normalizes a domain from the feed, returning `None` if it doesn't look like one.
+fn normalize_domain(s: &str) -> Option<String> {
+    let d = s.trim().trim_end_matches('.').to_lowercase();
+    let d = d.strip_prefix("*.").unwrap_or(&d);
+    let valid =
+        d.contains('.') && d.len() <= 253 && d.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if valid {
+        Some(d.to_string())
+    } else {
+        None
+    }
+}
+
+/// Parses the domains out of a feed. See [`PHISHING_FEED_VAR`] for the formats understood.
+pub fn parse_feed(body: &str) -> Vec<String> {
+    let entries: Vec<String> = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Array(a)) => a.into_iter().filter_map(|v| v.as_str().map(String::from)).collect(),
+        Ok(serde_json::Value::Object(mut o)) => o
+            .remove("domains")
+            .and_then(|d| serde_json::from_value(d).ok())
+            .unwrap_or_default(),
+        _ => body
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or_default().to_string())
+            .collect(),
+    };
+
+    let mut domains: Vec<String> = entries
+        .iter()
+        .filter_map(|e| normalize_domain(e))
+        .take(MAX_PHISHING_DOMAINS)
+        .collect();
+    domains.sort_unstable();
+    domains.dedup();
+    domains
+}
+
+/// The phishing domain list, mirrored in memory so lookups don't hit the DB.
+#[derive(Default, Debug)]
+pub struct PhishingDomains {
+    /// The known phishing domains.
+    domains: RwLock<HashSet<String>>,
+    /// When the feed should next be fetched. Unset until the first attempt.
+    next_refresh: Mutex<Option<Instant>>,
+}
+
+impl PhishingDomains {
+    /// Loads the stored list from the database, replacing any in memory.
+    pub async fn load(&self, pool: &PgPool) -> crate::error::Result<()> {
+        let rows = sqlx::query_scalar!("SELECT domain FROM phishing_domains;")
+            .fetch_all(pool)
+            .await?;
+
+        *self.domains.write() = rows.into_iter().collect();
+        Ok(())
+    }
+
+    /// The number of known phishing domains.
+    pub fn len(&self) -> usize {
+        self.domains.read().len()
+    }
+
+    /// Returns true if no phishing domains are known.
+    pub fn is_empty(&self) -> bool {
+        self.domains.read().is_empty()
+    }
+
+    /// Checks whether a host is a known phishing domain, or a subdomain of one.
+    pub fn contains(&self, host: &str) -> bool {
+        let domains = self.domains.read();
+        let host = host.trim_end_matches('.').to_lowercase();
+        let mut rest = host.as_str();
+        loop {
+            if domains.contains(rest) {
+                return true;
+            }
+            match rest.find('.') {
+                Some(i) => rest = &rest[i + 1..],
+                None => return false,
+            }
+        }
+    }
+
+    /// Fetches the feed and replaces the stored list with it, if the feed is set and it's time to.
+    /// Returns the number of domains fetched, if the feed was fetched.
+    pub async fn refresh_if_due(&self, pool: &PgPool) -> crate::error::Result<Option<usize>> {
+        let feed = match std::env::var(PHISHING_FEED_VAR) {
+            Ok(f) if !f.trim().is_empty() => f,
+            _ => return Ok(None),
+        };
+
+        let now = Instant::now();
+        {
+            let mut next = self.next_refresh.lock();
+            if next.map_or(false, |n| n > now) {
+                return Ok(None);
+            }
+            // Assume this attempt fails until it's done, so a failing feed isn't hammered.
+            *next = Some(now + PHISHING_RETRY_INTERVAL);
+        }
+
+        let count = self.refresh(pool, feed.trim()).await?;
+        *self.next_refresh.lock() = Some(now + PHISHING_REFRESH_INTERVAL);
+        Ok(Some(count))
+    }
+
+    /// Fetches the feed and replaces the stored list with it. A feed with no domains is assumed to
+    /// be broken, and leaves the list alone.
+    pub async fn refresh(&self, pool: &PgPool, feed: &str) -> crate::error::Result<usize> {
+        let body = reqwest::get(feed).await?.error_for_status()?.text().await?;
+        let domains = parse_feed(&body);
+        if domains.is_empty() {
+            return Err(EmptyPhishingFeed.into());
+        }
+
+        let mut tx = pool.begin().await?;
+        sqlx::query!("DELETE FROM phishing_domains;").execute(&mut tx).await?;
+        sqlx::query!(
+            "INSERT INTO phishing_domains (domain) SELECT * FROM UNNEST($1::TEXT[]);",
+            &domains
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        let count = domains.len();
+        *self.domains.write() = domains.into_iter().collect();
+        Ok(count)
+    }
+}
@@ -0,0 +1,211 @@
+//! Contains erasure of everything Glimbot stores about a user, for data protection requests.
+//!
+//! Rows about the user, like cases against them, their reminders and their activity, are deleted.
+//! Rows recording something the user did to others, like cases they issued or tags they wrote,
+//! are kept, but attributed to [`ANONYMOUS_USER`] instead. The user's messages are taken out of
+//! archive transcripts.
+
+use clap::ArgMatches;
+use serenity::model::id::UserId;
+use sqlx::PgPool;
+
+/// The user ID anonymized rows are attributed to.
+pub const ANONYMOUS_USER: i64 = 0;
+
+impl_err!(InvalidUserId, "User IDs are numbers, like 123456789012345678.", true);
+
+/// How many rows a purge touched.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PurgeReport {
+    /// Rows deleted.
+    pub deleted: u64,
+    /// Rows kept, but no longer attributed to the user.
+    pub anonymized: u64,
+}
+
+/// Deletes or anonymizes everything stored about a user in every guild, in one transaction.
+/// Anything modules keep in memory is untouched; see [`crate::dispatch::Dispatch::forget_user`].
+pub async fn purge_user(pool: &PgPool, user: UserId) -> crate::error::Result<PurgeReport> {
+    let uid = user.0 as i64;
+    let mut report = PurgeReport::default();
+    let mut tx = pool.begin().await?;
+
+    let deleted = [
+        sqlx::query!("DELETE FROM mod_cases WHERE target_user = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM timed_events WHERE target_user = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM reminders WHERE user_id = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM suggestions WHERE author = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM pending_autoroles WHERE user_id = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM departed_members WHERE user_id = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM balances WHERE user_id = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM afk_statuses WHERE user_id = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM verification_challenges WHERE user_id = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM activity_hourly WHERE user_id = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("DELETE FROM temp_voice_channels WHERE owner = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!(
+            "DELETE FROM filter_exemptions WHERE kind = 'user' AND target = $1;",
+            uid
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!("DELETE FROM instance_operators WHERE user_id = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+    ];
+    report.deleted = deleted.iter().map(|r| r.rows_affected()).sum();
+
+    let anonymized = [
+        sqlx::query!(
+            "UPDATE mod_cases SET moderator = $2 WHERE moderator = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!("UPDATE suggestions SET moderator = NULL WHERE moderator = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!("UPDATE tags SET owner = $2 WHERE owner = $1;", uid, ANONYMOUS_USER)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!(
+            "UPDATE config_audit SET actor = $2 WHERE actor = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!(
+            "UPDATE filter_exemptions SET added_by = $2 WHERE added_by = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!(
+            "UPDATE image_blocklist SET added_by = $2 WHERE added_by = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!(
+            "UPDATE lockdown_channels SET started_by = $2 WHERE started_by = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!(
+            "UPDATE spam_relaxations SET started_by = $2 WHERE started_by = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!("UPDATE raid_modes SET started_by = NULL WHERE started_by = $1;", uid)
+            .execute(&mut tx)
+            .await?,
+        sqlx::query!(
+            "UPDATE ban_sync_groups SET created_by = $2 WHERE created_by = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!(
+            "UPDATE instance_operators SET added_by = $2 WHERE added_by = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!(
+            "UPDATE counters SET created_by = $2 WHERE created_by = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        sqlx::query!(
+            "UPDATE channel_archives SET archived_by = $2 WHERE archived_by = $1;",
+            uid,
+            ANONYMOUS_USER
+        )
+        .execute(&mut tx)
+        .await?,
+        // Transcripts keep everyone else's messages; only the user's are taken out.
+        sqlx::query!(
+            r#"
+            UPDATE channel_archives SET transcript = jsonb_set(
+                transcript,
+                '{messages}',
+                COALESCE(
+                    (SELECT jsonb_agg(m ORDER BY i) FROM jsonb_array_elements(transcript->'messages') WITH ORDINALITY AS e(m, i)
+                     WHERE (m->>'author')::BIGINT <> $1),
+                    '[]'::JSONB
+                )
+            )
+            WHERE EXISTS (SELECT 1 FROM jsonb_array_elements(transcript->'messages') m WHERE (m->>'author')::BIGINT = $1);
+            "#,
+            uid
+        )
+        .execute(&mut tx)
+        .await?,
+    ];
+    report.anonymized = anonymized.iter().map(|r| r.rows_affected()).sum();
+
+    tx.commit().await?;
+    info!(
+        "purged user {}: {} rows deleted, {} anonymized",
+        user, report.deleted, report.anonymized
+    );
+    Ok(report)
+}
+
+/// Creates a subcommand for purging a user's data.
+pub fn subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("purge-user")
+        .arg(
+            clap::Arg::with_name("user-id")
+                .value_name("ID")
+                .help("The ID of the user to purge.")
+                .required(true)
+                .index(1),
+        )
+        .about("Delete or anonymize everything stored about a user. Restart Glimbot afterwards to clear its memory.")
+}
+
+/// Handles the case where someone invoked the output from the subcommand function.
+pub async fn handle_matches(args: &ArgMatches<'_>) -> crate::error::Result<()> {
+    let user: u64 = args.value_of("user-id").unwrap().parse().map_err(|_| InvalidUserId)?;
+    let pool = crate::db::create_pool().await?;
+    let report = purge_user(&pool, UserId(user)).await?;
+    println!(
+        "Purged user {}: deleted {} rows and anonymized {}.",
+        user, report.deleted, report.anonymized
+    );
+    Ok(())
+}
@@ -0,0 +1,352 @@
+//! Contains quotas: limits on how many of something, like tags or reminders, a guild may have.
+//! Each resource has a built-in limit, which the bot owner can replace for every guild with an
+//! instance default, and for particular guilds with an override.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use serenity::model::id::UserId;
+use sqlx::PgPool;
+
+use crate::db::DbContext;
+
+impl_err!(
+    NoSuchResource,
+    "No such resource. Resources are tags, filter_patterns, blocked_images, role_groups, joinable_roles and reminders.",
+    true
+);
+impl_err!(
+    InvalidQuota,
+    "Quotas can't be negative, and the joinable_roles quota can't be more than 128.",
+    true
+);
+
+/// Something a guild may only have so many of.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Resource {
+    /// Tags; see [`crate::module::tags`].
+    Tags,
+    /// Custom filter patterns; see [`crate::module::filter`].
+    FilterPatterns,
+    /// Images on the image blocklist; see [`crate::module::image_filter`].
+    BlockedImages,
+    /// Role groups; see [`crate::module::roles`].
+    RoleGroups,
+    /// Joinable roles; see [`crate::module::roles`].
+    JoinableRoles,
+    /// Waiting reminders, counted per member; see [`crate::module::remind`].
+    Reminders,
+}
+
+impl Resource {
+    /// Every resource.
+    pub const ALL: [Resource; 6] = [
+        Resource::Tags,
+        Resource::FilterPatterns,
+        Resource::BlockedImages,
+        Resource::RoleGroups,
+        Resource::JoinableRoles,
+        Resource::Reminders,
+    ];
+
+    /// The name the resource is stored and set by.
+    pub fn name(self) -> &'static str {
+        match self {
+            Resource::Tags => "tags",
+            Resource::FilterPatterns => "filter_patterns",
+            Resource::BlockedImages => "blocked_images",
+            Resource::RoleGroups => "role_groups",
+            Resource::JoinableRoles => "joinable_roles",
+            Resource::Reminders => "reminders",
+        }
+    }
+
+    /// How the resource is described to users, in the plural.
+    pub fn noun(self) -> &'static str {
+        match self {
+            Resource::Tags => "tags",
+            Resource::FilterPatterns => "custom filter patterns",
+            Resource::BlockedImages => "blocked images",
+            Resource::RoleGroups => "role groups",
+            Resource::JoinableRoles => "joinable roles",
+            Resource::Reminders => "reminders",
+        }
+    }
+
+    /// The limit when neither an instance default nor an override is set.
+    pub fn built_in(self) -> i64 {
+        match self {
+            Resource::Tags => crate::module::tags::MAX_TAGS,
+            Resource::FilterPatterns => crate::module::filter::MAX_CUSTOM_PATTERNS,
+            Resource::BlockedImages => crate::module::image_filter::MAX_BLOCKED_IMAGES,
+            Resource::RoleGroups => crate::module::roles::MAX_ROLE_GROUPS,
+            Resource::JoinableRoles => crate::module::roles::MAX_JOINABLE_ROLES,
+            Resource::Reminders => crate::module::remind::MAX_REMINDERS,
+        }
+    }
+
+    /// The highest limit which may be set, if the database can't hold more.
+    pub fn cap(self) -> Option<i64> {
+        match self {
+            // Enforced by a check constraint on known_guilds.
+            Resource::JoinableRoles => Some(crate::module::roles::MAX_JOINABLE_ROLES),
+            _ => None,
+        }
+    }
+
+    /// Whether the limit applies to each member rather than the whole guild.
+    pub fn per_member(self) -> bool {
+        matches!(self, Resource::Reminders)
+    }
+
+    /// Checks that a limit can be set for this resource.
+    pub fn validate(self, quota: i64) -> crate::error::Result<i64> {
+        if quota < 0 || self.cap().map_or(false, |c| quota > c) {
+            return Err(InvalidQuota.into());
+        }
+        Ok(quota)
+    }
+}
+
+impl FromStr for Resource {
+    type Err = NoSuchResource;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Resource::ALL
+            .iter()
+            .copied()
+            .find(|r| r.name().eq_ignore_ascii_case(s))
+            .ok_or(NoSuchResource)
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Returned when adding something would put a guild, or a member, over its quota.
+#[derive(Debug)]
+pub struct QuotaReached {
+    /// The resource whose quota was reached.
+    pub resource: Resource,
+    /// The quota.
+    pub limit: i64,
+}
+
+impl fmt::Display for QuotaReached {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.resource.per_member() {
+            write!(f, "You can have at most {} {} here", self.limit, self.resource.noun())?;
+        } else {
+            write!(
+                f,
+                "This server can have at most {} {}",
+                self.limit,
+                self.resource.noun()
+            )?;
+        }
+        write!(f, ". Remove some before adding more.")
+    }
+}
+
+impl std::error::Error for QuotaReached {}
+impl_user_err_from!(QuotaReached);
+
+/// Where a guild's quota for a resource comes from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum QuotaSource {
+    /// The resource's built-in limit.
+    BuiltIn,
+    /// The bot owner's default for every guild.
+    InstanceDefault,
+    /// The bot owner's override for this guild.
+    Override,
+}
+
+impl fmt::Display for QuotaSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            QuotaSource::BuiltIn => "built-in",
+            QuotaSource::InstanceDefault => "instance default",
+            QuotaSource::Override => "override",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Wrapper around a guild's quotas in the database.
+pub struct Quota<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> Quota<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        Quota {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Retrieves the guild's quota for a resource, and where it comes from. Overrides and instance
+    /// defaults are kept in Postgres, so only the built-in quotas apply on other backends.
+    pub async fn limit_with_source(&self, resource: Resource) -> crate::error::Result<(i64, QuotaSource)> {
+        let pool = match self.ctx.storage().postgres() {
+            Some(pg) => pg.pool(),
+            None => return Ok((resource.built_in(), QuotaSource::BuiltIn)),
+        };
+        let over = sqlx::query_scalar!(
+            "SELECT quota FROM quota_overrides WHERE guild = $1 AND resource = $2;",
+            self.ctx.guild_as_i64(),
+            resource.name()
+        )
+        .fetch_optional(pool)
+        .await?;
+        let (limit, source) = match over {
+            Some(q) => (q, QuotaSource::Override),
+            None => match default_quota(pool, resource).await? {
+                Some(q) => (q, QuotaSource::InstanceDefault),
+                None => (resource.built_in(), QuotaSource::BuiltIn),
+            },
+        };
+        Ok((resource.cap().map_or(limit, |c| limit.min(c)), source))
+    }
+
+    /// Retrieves the guild's quota for a resource.
+    pub async fn limit(&self, resource: Resource) -> crate::error::Result<i64> {
+        Ok(self.limit_with_source(resource).await?.0)
+    }
+
+    /// Counts how much of a resource the guild has, or the member has for per-member resources.
+    pub async fn usage(&self, resource: Resource, member: Option<UserId>) -> crate::error::Result<i64> {
+        let guild = self.ctx.guild_as_i64();
+        let count = match resource {
+            Resource::Tags => {
+                sqlx::query_scalar!("SELECT COUNT(*) AS tags FROM tags WHERE guild = $1;", guild)
+                    .fetch_one(self.ctx.conn()?)
+                    .await?
+            }
+            Resource::FilterPatterns => {
+                sqlx::query_scalar!(
+                    "SELECT COUNT(*) AS matching FROM filter_patterns WHERE guild = $1;",
+                    guild
+                )
+                .fetch_one(self.ctx.conn()?)
+                .await?
+            }
+            Resource::BlockedImages => {
+                sqlx::query_scalar!(
+                    "SELECT COUNT(*) AS blocked FROM image_blocklist WHERE guild = $1;",
+                    guild
+                )
+                .fetch_one(self.ctx.conn()?)
+                .await?
+            }
+            // These are kept by the storage backend, which may not be Postgres.
+            Resource::RoleGroups => Some(self.ctx.storage().count_role_groups(self.ctx.guild()).await?),
+            Resource::JoinableRoles => Some(self.ctx.storage().count_joinable_roles(self.ctx.guild()).await?),
+            Resource::Reminders => {
+                let user = member.expect("reminders are counted per member");
+                sqlx::query_scalar!(
+                    "SELECT COUNT(*) AS reminders FROM reminders WHERE guild = $1 AND user_id = $2;",
+                    guild,
+                    user.0 as i64
+                )
+                .fetch_one(self.ctx.conn()?)
+                .await?
+            }
+        };
+        Ok(count.unwrap_or_default())
+    }
+
+    /// Errors with [`QuotaReached`] if the guild can't have any more of a resource.
+    pub async fn check(&self, resource: Resource) -> crate::error::Result<()> {
+        self.check_usage(resource, None).await
+    }
+
+    /// Errors with [`QuotaReached`] if a member can't have any more of a per-member resource.
+    pub async fn check_member(&self, resource: Resource, member: UserId) -> crate::error::Result<()> {
+        self.check_usage(resource, Some(member)).await
+    }
+
+    /// Errors with [`QuotaReached`] if the usage counted for the resource has reached the limit.
+    async fn check_usage(&self, resource: Resource, member: Option<UserId>) -> crate::error::Result<()> {
+        let limit = self.limit(resource).await?;
+        if self.usage(resource, member).await? >= limit {
+            return Err(QuotaReached { resource, limit }.into());
+        }
+        Ok(())
+    }
+
+    /// Overrides the guild's quota for a resource, or removes the override if `None`. Returns
+    /// whether anything changed.
+    pub async fn set_override(&self, resource: Resource, quota: Option<i64>) -> crate::error::Result<bool> {
+        let res = match quota {
+            Some(q) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO quota_overrides (guild, resource, quota)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (guild, resource) DO UPDATE
+                        SET quota = EXCLUDED.quota;
+                    "#,
+                    self.ctx.guild_as_i64(),
+                    resource.name(),
+                    resource.validate(q)?
+                )
+                .execute(self.ctx.conn()?)
+                .await?
+            }
+            None => {
+                sqlx::query!(
+                    "DELETE FROM quota_overrides WHERE guild = $1 AND resource = $2;",
+                    self.ctx.guild_as_i64(),
+                    resource.name()
+                )
+                .execute(self.ctx.conn()?)
+                .await?
+            }
+        };
+        Ok(res.rows_affected() > 0)
+    }
+}
+
+/// Retrieves the instance default for a resource's quota, if the bot owner has set one.
+pub async fn default_quota(pool: &PgPool, resource: Resource) -> crate::error::Result<Option<i64>> {
+    let q = sqlx::query_scalar!("SELECT quota FROM quota_defaults WHERE resource = $1;", resource.name())
+        .fetch_optional(pool)
+        .await?;
+    Ok(q)
+}
+
+/// Sets the instance default for a resource's quota, or removes it if `None`. Returns whether
+/// anything changed.
+pub async fn set_default_quota(pool: &PgPool, resource: Resource, quota: Option<i64>) -> crate::error::Result<bool> {
+    let res = match quota {
+        Some(q) => {
+            sqlx::query!(
+                r#"
+                INSERT INTO quota_defaults (resource, quota)
+                VALUES ($1, $2)
+                ON CONFLICT (resource) DO UPDATE
+                    SET quota = EXCLUDED.quota;
+                "#,
+                resource.name(),
+                resource.validate(q)?
+            )
+            .execute(pool)
+            .await?
+        }
+        None => {
+            sqlx::query!("DELETE FROM quota_defaults WHERE resource = $1;", resource.name())
+                .execute(pool)
+                .await?
+        }
+    };
+    Ok(res.rows_affected() > 0)
+}
@@ -0,0 +1,239 @@
+//! Contains the storage backends glimbot can keep its data in. Most of glimbot's data lives in
+//! PostgreSQL, and most features query it directly through [`Dispatch::pool`](crate::dispatch::Dispatch::pool).
+//! Guild config, joinable roles and timed events go through the [`Storage`] trait instead, so small
+//! deployments can keep those in an SQLite file rather than running a Postgres server.
+//!
+//! The backend is picked by the scheme of `DATABASE_URL`: `postgres://` or `postgresql://` for
+//! [`PostgresStorage`], and `sqlite:` for [`SqliteStorage`]. Features which need Postgres fail
+//! with [`NeedsPostgres`] on SQLite.
+
+use std::time::Duration;
+
+use serenity::model::id::{GuildId, RoleId, UserId};
+use sqlx::migrate::Migrator;
+
+use crate::db::config_audit::ConfigChange;
+use crate::db::instrumented::QueryStats;
+use crate::db::timed::{Action, ActionKind};
+use crate::module::roles::{JoinableRole, RoleGroup};
+
+pub mod postgres;
+pub mod sqlite;
+
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+impl_err!(
+    NeedsPostgres,
+    "That needs a PostgreSQL database, but this instance of Glimbot keeps its data in SQLite.",
+    true
+);
+impl_err!(
+    UnsupportedDatabase,
+    "DATABASE_URL must point at a PostgreSQL server (postgres:// or postgresql://) or an SQLite database (sqlite:), and DATABASE_READ_URL at a PostgreSQL server; other databases aren't supported.",
+    true
+);
+
+/// A config value waiting to be written by [`Storage::write_config_values`].
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigWrite<'a> {
+    /// The guild the value belongs to.
+    pub guild: GuildId,
+    /// The config key.
+    pub key: &'a str,
+    /// The value to store.
+    pub value: &'a serde_json::Value,
+}
+
+/// A backend for the data every deployment needs: the schema's migrations, guild config and its
+/// audit history, joinable roles and timed events.
+#[async_trait::async_trait]
+pub trait Storage: Send + Sync {
+    /// The name of the backend, for humans.
+    fn name(&self) -> &'static str;
+
+    /// Retrieves the Postgres backend, if this is one. Features which query Postgres directly
+    /// reach the pool through this.
+    fn postgres(&self) -> Option<&PostgresStorage> {
+        None
+    }
+
+    /// Accessor for the timing recorded for queries run against the backend.
+    fn query_stats(&self) -> &QueryStats;
+
+    /// The number of open connections and how many of them are idle.
+    fn connections(&self) -> (u32, usize);
+
+    /// The migrations which make up this backend's schema.
+    fn migrations(&self) -> &Migrator;
+
+    /// Applies pending migrations.
+    async fn migrate(&self) -> crate::error::Result<()>;
+
+    /// Retrieves the version the schema has been migrated to, and whether the migration to it
+    /// failed partway, if any migrations have been applied.
+    async fn migration_state(&self) -> crate::error::Result<Option<(i64, bool)>>;
+
+    /// Retrieves the versions of every applied migration.
+    async fn applied_migrations(&self) -> crate::error::Result<Vec<i64>>;
+
+    /// Runs a trivial query, for checking the backend is reachable.
+    async fn ping(&self) -> crate::error::Result<()>;
+
+    /// Fetches a guild's stored config value. Backends with a read replica read from the primary
+    /// only if `primary` is set.
+    async fn fetch_config_value(
+        &self,
+        guild: GuildId,
+        key: &str,
+        primary: bool,
+    ) -> crate::error::Result<Option<serde_json::Value>>;
+
+    /// Stores a guild's config value, replacing any already stored.
+    async fn upsert_config_value(
+        &self,
+        guild: GuildId,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> crate::error::Result<()>;
+
+    /// Deletes a guild's stored config value, returning whether there was one.
+    async fn delete_config_value(&self, guild: GuildId, key: &str) -> crate::error::Result<bool>;
+
+    /// Writes a batch of config values in one transaction: `sets` replace whatever is stored, and
+    /// `defaults` are only stored where nothing is.
+    async fn write_config_values(
+        &self,
+        sets: &[ConfigWrite<'_>],
+        defaults: &[ConfigWrite<'_>],
+    ) -> crate::error::Result<()>;
+
+    /// Records a change to a guild's config value in its audit history.
+    async fn record_config_change(
+        &self,
+        guild: GuildId,
+        name: &str,
+        old_value: Option<&serde_json::Value>,
+        new_value: Option<&serde_json::Value>,
+        actor: UserId,
+    ) -> crate::error::Result<()>;
+
+    /// Retrieves up to `limit` changes made to a guild's config value, newest first, skipping
+    /// `offset` of them.
+    async fn config_history(
+        &self,
+        guild: GuildId,
+        name: &str,
+        offset: i64,
+        limit: i64,
+    ) -> crate::error::Result<Vec<ConfigChange>>;
+
+    /// Counts the changes made to a guild's config value.
+    async fn count_config_changes(&self, guild: GuildId, name: &str) -> crate::error::Result<i64>;
+
+    /// Stores a timed action.
+    async fn store_action(&self, action: &Action) -> crate::error::Result<()>;
+
+    /// Deletes a timed action.
+    async fn drop_action(&self, action: &Action) -> crate::error::Result<()>;
+
+    /// Deletes all of a guild's pending actions of the given kind against a user, returning the
+    /// number removed.
+    async fn cancel_actions(&self, guild: GuildId, user: UserId, kind: &ActionKind) -> crate::error::Result<u64>;
+
+    /// Retrieves up to `limit` actions due at or before `epoch`, soonest first.
+    async fn actions_before(
+        &self,
+        epoch: chrono::DateTime<chrono::Utc>,
+        limit: usize,
+    ) -> crate::error::Result<Vec<Action>>;
+
+    /// Makes a role joinable. Errors if it already is, or the guild has too many.
+    async fn add_joinable_role(
+        &self,
+        guild: GuildId,
+        role: RoleId,
+        expires_after: Option<Duration>,
+    ) -> crate::error::Result<()>;
+
+    /// Makes a role no longer joinable.
+    async fn del_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()>;
+
+    /// Retrieves a joinable role, if the role is joinable.
+    async fn joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<Option<JoinableRole>>;
+
+    /// Retrieves every joinable role in a guild, ordered by ID.
+    async fn joinable_roles(&self, guild: GuildId) -> crate::error::Result<Vec<JoinableRole>>;
+
+    /// Counts a guild's joinable roles.
+    async fn count_joinable_roles(&self, guild: GuildId) -> crate::error::Result<i64>;
+
+    /// Sets or clears a joinable role's description, returning whether the role is joinable.
+    async fn set_role_description(
+        &self,
+        guild: GuildId,
+        role: RoleId,
+        description: Option<&str>,
+    ) -> crate::error::Result<bool>;
+
+    /// Sets or clears a joinable role's category, returning whether the role is joinable.
+    async fn set_role_category(
+        &self,
+        guild: GuildId,
+        role: RoleId,
+        category: Option<&str>,
+    ) -> crate::error::Result<bool>;
+
+    /// Puts a joinable role in a group or takes it out of one, returning whether the role is
+    /// joinable. Errors if the group doesn't exist.
+    async fn set_role_group(&self, guild: GuildId, role: RoleId, group: Option<&str>) -> crate::error::Result<bool>;
+
+    /// Creates a role group. Errors if one by that name already exists.
+    async fn create_role_group(&self, guild: GuildId, name: &str, max_roles: u64) -> crate::error::Result<()>;
+
+    /// Deletes a role group, taking its roles out of it. Returns whether the group existed.
+    async fn delete_role_group(&self, guild: GuildId, name: &str) -> crate::error::Result<bool>;
+
+    /// Retrieves a role group along with its roles, if it exists.
+    async fn role_group(&self, guild: GuildId, name: &str) -> crate::error::Result<Option<RoleGroup>>;
+
+    /// Retrieves a guild's role groups ordered by name, without their roles.
+    async fn role_groups(&self, guild: GuildId) -> crate::error::Result<Vec<RoleGroup>>;
+
+    /// Counts a guild's role groups.
+    async fn count_role_groups(&self, guild: GuildId) -> crate::error::Result<i64>;
+}
+
+/// Returns true if the URL points at an SQLite database.
+fn is_sqlite_url(db_url: &str) -> bool {
+    db_url.starts_with("sqlite:")
+}
+
+/// Opens the backend `DATABASE_URL` points at without touching the schema. For Postgres, read-only
+/// queries are routed to `DATABASE_READ_URL` if it's set.
+pub async fn open() -> crate::error::Result<Box<dyn Storage>> {
+    let db_url = std::env::var("DATABASE_URL")?;
+    let replica = crate::db::connect_replica().await?;
+    if is_sqlite_url(&db_url) {
+        // A replica of an SQLite file isn't something Glimbot could keep in step with.
+        if replica.is_some() {
+            return Err(UnsupportedDatabase.into());
+        }
+        return Ok(Box::new(SqliteStorage::connect(&db_url).await?));
+    }
+
+    let mut storage = PostgresStorage::new(crate::db::connect_to(&db_url).await?);
+    if let Some(replica) = replica {
+        info!("Routing read-only queries to DATABASE_READ_URL.");
+        storage.set_read_pool(replica);
+    }
+    Ok(Box::new(storage))
+}
+
+/// Opens the backend `DATABASE_URL` points at and runs any pending migrations.
+pub async fn create() -> crate::error::Result<Box<dyn Storage>> {
+    let storage = open().await?;
+    info!("Running DB migrations if necessary.");
+    storage.migrate().await?;
+    Ok(storage)
+}
@@ -0,0 +1,633 @@
+//! Contains the PostgreSQL storage backend, which every feature of glimbot supports.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serenity::model::id::{GuildId, RoleId, UserId};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::PgPool;
+
+use crate::db::config_audit::ConfigChange;
+use crate::db::instrumented::{InstrumentedPool, QueryStats};
+use crate::db::storage::{ConfigWrite, Storage};
+use crate::db::timed::{Action, ActionKind};
+use crate::db::MIGRATIONS;
+use crate::error::DatabaseError;
+use crate::module::roles::{AlreadyJoinable, JoinableRole, NoSuchRoleGroup, RoleGroup, RoleGroupExists, TooManyRoles};
+
+/// Stores glimbot's data in PostgreSQL, optionally reading from a replica.
+#[derive(Debug, Clone)]
+pub struct PostgresStorage {
+    /// Database connection pool.
+    pool: InstrumentedPool,
+    /// Connection pool for read-only queries, if a read replica is configured.
+    read_pool: Option<InstrumentedPool>,
+}
+
+impl PostgresStorage {
+    /// Wraps a connection pool to the primary.
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool: InstrumentedPool::new(pool, Arc::new(QueryStats::default())),
+            read_pool: None,
+        }
+    }
+
+    /// Gets a reference to the DB pool.
+    pub fn pool(&self) -> &InstrumentedPool {
+        &self.pool
+    }
+
+    /// Gets a reference to the pool for read-only queries. This is the read replica if one is
+    /// configured, and the primary otherwise.
+    pub fn read_pool(&self) -> &InstrumentedPool {
+        self.read_pool.as_ref().unwrap_or(&self.pool)
+    }
+
+    /// Routes read-only queries to a read replica.
+    pub fn set_read_pool(&mut self, pool: PgPool) {
+        self.read_pool = Some(InstrumentedPool::new(pool, self.pool.stats().clone()));
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+struct ConfigRow {
+    value: serde_json::Value,
+}
+
+/// Fetches a guild's stored config value.
+pub(crate) async fn fetch_config_value<'e, E>(
+    ex: E,
+    guild: GuildId,
+    key: &str,
+) -> crate::error::Result<Option<serde_json::Value>>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let o: Option<ConfigRow> = sqlx::query_as!(
+        ConfigRow,
+        r#"
+            SELECT value FROM config_values WHERE guild = $1 AND name = $2;
+            "#,
+        guild.0 as i64,
+        key,
+    )
+    .fetch_optional(ex)
+    .await?;
+    Ok(o.map(|c| c.value))
+}
+
+/// Stores a guild's config value, replacing any already stored.
+pub(crate) async fn upsert_config_value<'e, E>(
+    ex: E,
+    guild: GuildId,
+    key: &str,
+    v: &serde_json::Value,
+) -> crate::error::Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        r#"
+            INSERT INTO config_values (guild, name, value)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (guild, name) DO UPDATE
+                SET value = EXCLUDED.value;
+            "#,
+        guild.0 as i64,
+        key,
+        v
+    )
+    .execute(ex)
+    .await?;
+    Ok(())
+}
+
+/// Deletes a guild's stored config value, returning whether there was one.
+pub(crate) async fn delete_config_value<'e, E>(ex: E, guild: GuildId, key: &str) -> crate::error::Result<bool>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    let res = sqlx::query!(
+        "DELETE FROM config_values WHERE guild = $1 AND name = $2;",
+        guild.0 as i64,
+        key
+    )
+    .execute(ex)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Stores a timed action using the given executor.
+pub(crate) async fn store_action<'e, E>(ex: E, action: &Action) -> crate::error::Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Postgres>,
+{
+    sqlx::query!(
+        r#"
+            INSERT INTO timed_events (target_user, guild, action, expiry) VALUES ($1, $2, $3, $4);
+            "#,
+        action.target_user().0 as i64,
+        action.guild().0 as i64,
+        action.kind().to_json(),
+        action.expiry()
+    )
+    .execute(ex)
+    .await?;
+    Ok(())
+}
+
+#[doc(hidden)]
+struct ActionRow {
+    target_user: i64,
+    guild: i64,
+    expiry: chrono::DateTime<chrono::Utc>,
+    action: serde_json::Value,
+}
+
+#[doc(hidden)]
+struct JoinableRoleRow {
+    role: i64,
+    expires_after: Option<i64>,
+    role_group: Option<String>,
+    description: Option<String>,
+    category: Option<String>,
+}
+
+impl From<JoinableRoleRow> for JoinableRole {
+    fn from(r: JoinableRoleRow) -> Self {
+        JoinableRole {
+            role: RoleId::from(r.role as u64),
+            expires_after: r.expires_after.map(|s| Duration::from_secs(s as u64)),
+            group: r.role_group,
+            description: r.description,
+            category: r.category,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for PostgresStorage {
+    fn name(&self) -> &'static str {
+        "PostgreSQL"
+    }
+
+    fn postgres(&self) -> Option<&PostgresStorage> {
+        Some(self)
+    }
+
+    fn query_stats(&self) -> &QueryStats {
+        self.pool.stats()
+    }
+
+    fn connections(&self) -> (u32, usize) {
+        (self.pool.size(), self.pool.num_idle())
+    }
+
+    fn migrations(&self) -> &Migrator {
+        &MIGRATIONS
+    }
+
+    async fn migrate(&self) -> crate::error::Result<()> {
+        MIGRATIONS.run(&*self.pool).await?;
+        Ok(())
+    }
+
+    async fn migration_state(&self) -> crate::error::Result<Option<(i64, bool)>> {
+        let mut conn = self.pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        if let Some(v) = conn.dirty_version().await? {
+            return Ok(Some((v, true)));
+        }
+        let applied = conn.list_applied_migrations().await?;
+        Ok(applied.iter().map(|m| m.version).max().map(|v| (v, false)))
+    }
+
+    async fn applied_migrations(&self) -> crate::error::Result<Vec<i64>> {
+        let mut conn = self.pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        let applied = conn.list_applied_migrations().await?;
+        Ok(applied.iter().map(|m| m.version).collect())
+    }
+
+    async fn ping(&self) -> crate::error::Result<()> {
+        sqlx::query("SELECT 1;").execute(&*self.pool).await?;
+        Ok(())
+    }
+
+    async fn fetch_config_value(
+        &self,
+        guild: GuildId,
+        key: &str,
+        primary: bool,
+    ) -> crate::error::Result<Option<serde_json::Value>> {
+        let pool = if primary { self.pool() } else { self.read_pool() };
+        fetch_config_value(pool, guild, key).await
+    }
+
+    async fn upsert_config_value(
+        &self,
+        guild: GuildId,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> crate::error::Result<()> {
+        upsert_config_value(self.pool(), guild, key, value).await
+    }
+
+    async fn delete_config_value(&self, guild: GuildId, key: &str) -> crate::error::Result<bool> {
+        delete_config_value(self.pool(), guild, key).await
+    }
+
+    async fn write_config_values(
+        &self,
+        sets: &[ConfigWrite<'_>],
+        defaults: &[ConfigWrite<'_>],
+    ) -> crate::error::Result<()> {
+        /// Splits writes into the columns UNNEST takes.
+        fn columns(writes: &[ConfigWrite<'_>]) -> (Vec<i64>, Vec<String>, Vec<String>) {
+            let guilds = writes.iter().map(|w| w.guild.0 as i64).collect();
+            let keys = writes.iter().map(|w| w.key.to_string()).collect();
+            let values = writes.iter().map(|w| w.value.to_string()).collect();
+            (guilds, keys, values)
+        }
+
+        let mut tx = self.pool.begin().await?;
+        if !sets.is_empty() {
+            let sets = columns(sets);
+            sqlx::query!(
+                r#"
+                INSERT INTO config_values (guild, name, value)
+                SELECT g, n, v::JSONB FROM UNNEST($1::BIGINT[], $2::TEXT[], $3::TEXT[]) AS t (g, n, v)
+                ON CONFLICT (guild, name) DO UPDATE
+                    SET value = EXCLUDED.value;
+                "#,
+                &sets.0,
+                &sets.1,
+                &sets.2
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        if !defaults.is_empty() {
+            let defaults = columns(defaults);
+            sqlx::query!(
+                r#"
+                INSERT INTO config_values (guild, name, value)
+                SELECT g, n, v::JSONB FROM UNNEST($1::BIGINT[], $2::TEXT[], $3::TEXT[]) AS t (g, n, v)
+                ON CONFLICT (guild, name) DO NOTHING;
+                "#,
+                &defaults.0,
+                &defaults.1,
+                &defaults.2
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn record_config_change(
+        &self,
+        guild: GuildId,
+        name: &str,
+        old_value: Option<&serde_json::Value>,
+        new_value: Option<&serde_json::Value>,
+        actor: UserId,
+    ) -> crate::error::Result<()> {
+        sqlx::query!(
+            "INSERT INTO config_audit (guild, name, old_value, new_value, actor) VALUES ($1, $2, $3, $4, $5);",
+            guild.0 as i64,
+            name,
+            old_value,
+            new_value,
+            actor.0 as i64
+        )
+        .execute(self.pool())
+        .await?;
+        Ok(())
+    }
+
+    async fn config_history(
+        &self,
+        guild: GuildId,
+        name: &str,
+        offset: i64,
+        limit: i64,
+    ) -> crate::error::Result<Vec<ConfigChange>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT name, old_value, new_value, actor, changed_at FROM config_audit
+            WHERE guild = $1 AND name = $2
+            ORDER BY changed_at DESC, id DESC
+            OFFSET $3
+            LIMIT $4;
+            "#,
+            guild.0 as i64,
+            name,
+            offset,
+            limit
+        )
+        .fetch_all(self.read_pool())
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| ConfigChange {
+                name: r.name,
+                old_value: r.old_value,
+                new_value: r.new_value,
+                actor: UserId(r.actor as u64),
+                changed_at: r.changed_at,
+            })
+            .collect())
+    }
+
+    async fn count_config_changes(&self, guild: GuildId, name: &str) -> crate::error::Result<i64> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) AS changes FROM config_audit WHERE guild = $1 AND name = $2;",
+            guild.0 as i64,
+            name
+        )
+        .fetch_one(self.read_pool())
+        .await?
+        .unwrap_or_default();
+        Ok(count)
+    }
+
+    async fn store_action(&self, action: &Action) -> crate::error::Result<()> {
+        store_action(self.pool(), action).await
+    }
+
+    async fn drop_action(&self, action: &Action) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM timed_events WHERE target_user = $1
+                                       AND guild = $2
+                                       AND action = $3
+                                       AND expiry = $4;
+            "#,
+            action.target_user().0 as i64,
+            action.guild().0 as i64,
+            action.kind().to_json(),
+            action.expiry()
+        )
+        .execute(self.pool())
+        .await?;
+        Ok(())
+    }
+
+    async fn cancel_actions(&self, guild: GuildId, user: UserId, kind: &ActionKind) -> crate::error::Result<u64> {
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM timed_events WHERE target_user = $1 AND guild = $2 AND action = $3;
+            "#,
+            user.0 as i64,
+            guild.0 as i64,
+            kind.to_json()
+        )
+        .execute(self.pool())
+        .await?;
+        Ok(res.rows_affected())
+    }
+
+    async fn actions_before(
+        &self,
+        epoch: chrono::DateTime<chrono::Utc>,
+        limit: usize,
+    ) -> crate::error::Result<Vec<Action>> {
+        let q: sqlx::query::Map<_, _, _> = sqlx::query_as!(
+            ActionRow,
+            r#"
+            SELECT target_user, guild, expiry, action FROM timed_events WHERE expiry <= $1 ORDER BY expiry ASC LIMIT $2;
+            "#,
+            epoch,
+            limit as i64
+        );
+
+        q.try_map(|r: ActionRow| {
+            Ok(Action::new(
+                (r.target_user as u64).into(),
+                (r.guild as u64).into(),
+                serde_json::from_value(r.action).map_err(|e| sqlx::Error::Decode(e.into()))?,
+                r.expiry,
+            ))
+        })
+        .fetch_all(self.pool())
+        .await
+        .map_err(crate::error::Error::from)
+    }
+
+    async fn add_joinable_role(
+        &self,
+        guild: GuildId,
+        role: RoleId,
+        expires_after: Option<Duration>,
+    ) -> crate::error::Result<()> {
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO joinable_roles (guild, role, expires_after) VALUES ($1, $2, $3);",
+            guild.0 as i64,
+            role.0 as i64,
+            expires_after.map(|d| d.as_secs().max(1) as i64)
+        )
+        .execute(self.pool())
+        .await;
+
+        match res {
+            Err(e) if e.is_check() => Err(TooManyRoles.into()),
+            Err(e) if e.is_unique() => Err(AlreadyJoinable.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    async fn del_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM joinable_roles WHERE guild = $1 AND role = $2;",
+            guild.0 as i64,
+            role.0 as i64
+        )
+        .execute(self.pool())
+        .await?;
+        Ok(())
+    }
+
+    async fn joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<Option<JoinableRole>> {
+        let row = sqlx::query_as!(
+            JoinableRoleRow,
+            "SELECT role, expires_after, role_group, description, category FROM joinable_roles WHERE guild = $1 AND role = $2;",
+            guild.0 as i64,
+            role.0 as i64
+        )
+        .fetch_optional(self.pool())
+        .await?;
+        Ok(row.map(JoinableRole::from))
+    }
+
+    async fn joinable_roles(&self, guild: GuildId) -> crate::error::Result<Vec<JoinableRole>> {
+        let rows = sqlx::query_as!(
+            JoinableRoleRow,
+            "SELECT role, expires_after, role_group, description, category FROM joinable_roles WHERE guild = $1 ORDER BY role ASC;",
+            guild.0 as i64
+        )
+        .fetch_all(self.pool())
+        .await?;
+        Ok(rows.into_iter().map(JoinableRole::from).collect())
+    }
+
+    async fn count_joinable_roles(&self, guild: GuildId) -> crate::error::Result<i64> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) AS roles FROM joinable_roles WHERE guild = $1;",
+            guild.0 as i64
+        )
+        .fetch_one(self.pool())
+        .await?;
+        Ok(count.unwrap_or_default())
+    }
+
+    async fn set_role_description(
+        &self,
+        guild: GuildId,
+        role: RoleId,
+        description: Option<&str>,
+    ) -> crate::error::Result<bool> {
+        let res = sqlx::query!(
+            "UPDATE joinable_roles SET description = $3 WHERE guild = $1 AND role = $2;",
+            guild.0 as i64,
+            role.0 as i64,
+            description
+        )
+        .execute(self.pool())
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn set_role_category(
+        &self,
+        guild: GuildId,
+        role: RoleId,
+        category: Option<&str>,
+    ) -> crate::error::Result<bool> {
+        let res = sqlx::query!(
+            "UPDATE joinable_roles SET category = $3 WHERE guild = $1 AND role = $2;",
+            guild.0 as i64,
+            role.0 as i64,
+            category
+        )
+        .execute(self.pool())
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn set_role_group(&self, guild: GuildId, role: RoleId, group: Option<&str>) -> crate::error::Result<bool> {
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "UPDATE joinable_roles SET role_group = $3 WHERE guild = $1 AND role = $2;",
+            guild.0 as i64,
+            role.0 as i64,
+            group
+        )
+        .execute(self.pool())
+        .await;
+
+        match res {
+            Err(e) if e.is_foreign_key() => Err(NoSuchRoleGroup.into()),
+            Err(e) => Err(e.into()),
+            Ok(r) => Ok(r.rows_affected() > 0),
+        }
+    }
+
+    async fn create_role_group(&self, guild: GuildId, name: &str, max_roles: u64) -> crate::error::Result<()> {
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO role_groups (guild, name, max_roles) VALUES ($1, $2, $3);",
+            guild.0 as i64,
+            name,
+            max_roles as i32
+        )
+        .execute(self.pool())
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(RoleGroupExists.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    async fn delete_role_group(&self, guild: GuildId, name: &str) -> crate::error::Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "UPDATE joinable_roles SET role_group = NULL WHERE guild = $1 AND role_group = $2;",
+            guild.0 as i64,
+            name
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let res = sqlx::query!(
+            "DELETE FROM role_groups WHERE guild = $1 AND name = $2;",
+            guild.0 as i64,
+            name
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn role_group(&self, guild: GuildId, name: &str) -> crate::error::Result<Option<RoleGroup>> {
+        let max_roles = sqlx::query_scalar!(
+            "SELECT max_roles FROM role_groups WHERE guild = $1 AND name = $2;",
+            guild.0 as i64,
+            name
+        )
+        .fetch_optional(self.pool())
+        .await?;
+        let max_roles = match max_roles {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+
+        let roles = sqlx::query_scalar!(
+            "SELECT role FROM joinable_roles WHERE guild = $1 AND role_group = $2 ORDER BY role ASC;",
+            guild.0 as i64,
+            name
+        )
+        .fetch_all(self.pool())
+        .await?;
+
+        Ok(Some(RoleGroup {
+            name: name.to_string(),
+            max_roles: max_roles as u64,
+            roles: roles.into_iter().map(|r| RoleId::from(r as u64)).collect(),
+        }))
+    }
+
+    async fn role_groups(&self, guild: GuildId) -> crate::error::Result<Vec<RoleGroup>> {
+        let rows = sqlx::query!(
+            "SELECT name, max_roles FROM role_groups WHERE guild = $1 ORDER BY name ASC;",
+            guild.0 as i64
+        )
+        .fetch_all(self.pool())
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| RoleGroup {
+                name: r.name,
+                max_roles: r.max_roles as u64,
+                roles: Vec::new(),
+            })
+            .collect())
+    }
+
+    async fn count_role_groups(&self, guild: GuildId) -> crate::error::Result<i64> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) AS groups FROM role_groups WHERE guild = $1;",
+            guild.0 as i64
+        )
+        .fetch_one(self.pool())
+        .await?;
+        Ok(count.unwrap_or_default())
+    }
+}
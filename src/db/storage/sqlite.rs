@@ -0,0 +1,550 @@
+//! Contains the SQLite storage backend, for small deployments which don't want to run a Postgres
+//! server. It keeps guild config and its audit history, joinable roles and timed events in a
+//! single file.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::TimeZone;
+use once_cell::sync::Lazy;
+use serenity::model::id::{GuildId, RoleId, UserId};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::SqlitePool;
+
+use crate::db::config_audit::ConfigChange;
+use crate::db::instrumented::{QueryStats, QueryTimer};
+use crate::db::storage::{ConfigWrite, Storage};
+use crate::db::timed::{Action, ActionKind};
+use crate::error::DatabaseError;
+use crate::module::roles::{AlreadyJoinable, JoinableRole, NoSuchRoleGroup, RoleGroup, RoleGroupExists, TooManyRoles};
+
+/// The SQL migrations making up the SQLite schema, in the `sqlite_migrations` directory. Module
+/// migrations are written for Postgres, so they aren't included.
+pub(crate) static SQLITE_MIGRATIONS: Lazy<Migrator> = Lazy::new(|| sqlx::migrate!("./sqlite_migrations"));
+
+/// How long a query waits for another connection's write to finish before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stores glimbot's data in an SQLite database.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    /// Database connection pool.
+    pool: SqlitePool,
+    /// Timing recorded for queries run through the pool.
+    stats: QueryStats,
+}
+
+impl SqliteStorage {
+    /// Opens the database at the given `sqlite:` URL, creating it if it doesn't exist.
+    pub async fn connect(db_url: &str) -> crate::error::Result<Self> {
+        let options = SqliteConnectOptions::from_str(db_url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(BUSY_TIMEOUT);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+        Ok(Self {
+            pool,
+            stats: QueryStats::default(),
+        })
+    }
+
+    /// Starts timing a query, recording it once the returned timer is dropped.
+    fn timer(&self, sql: &str) -> QueryTimer<'_> {
+        QueryTimer::start(&self.stats, sql)
+    }
+}
+
+/// Converts a timestamp to the microseconds since the epoch it's stored as.
+fn to_micros(t: chrono::DateTime<chrono::Utc>) -> i64 {
+    t.timestamp() * 1_000_000 + i64::from(t.timestamp_subsec_micros())
+}
+
+/// Converts microseconds since the epoch back into a timestamp.
+fn from_micros(micros: i64) -> chrono::DateTime<chrono::Utc> {
+    chrono::Utc.timestamp(
+        micros.div_euclid(1_000_000),
+        (micros.rem_euclid(1_000_000) * 1_000) as u32,
+    )
+}
+
+/// The columns of a config change, as selected by [`SqliteStorage`].
+type ConfigChangeRow = (String, Option<String>, Option<String>, i64, i64);
+
+/// The columns of a joinable role, as selected by [`SqliteStorage`].
+type JoinableRoleRow = (i64, Option<i64>, Option<String>, Option<String>, Option<String>);
+
+/// Converts a selected row into a joinable role.
+fn joinable_role(r: JoinableRoleRow) -> JoinableRole {
+    JoinableRole {
+        role: RoleId::from(r.0 as u64),
+        expires_after: r.1.map(|s| Duration::from_secs(s as u64)),
+        group: r.2,
+        description: r.3,
+        category: r.4,
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for SqliteStorage {
+    fn name(&self) -> &'static str {
+        "SQLite"
+    }
+
+    fn query_stats(&self) -> &QueryStats {
+        &self.stats
+    }
+
+    fn connections(&self) -> (u32, usize) {
+        (self.pool.size(), self.pool.num_idle())
+    }
+
+    fn migrations(&self) -> &Migrator {
+        &SQLITE_MIGRATIONS
+    }
+
+    async fn migrate(&self) -> crate::error::Result<()> {
+        SQLITE_MIGRATIONS.run(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn migration_state(&self) -> crate::error::Result<Option<(i64, bool)>> {
+        let mut conn = self.pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        if let Some(v) = conn.dirty_version().await? {
+            return Ok(Some((v, true)));
+        }
+        let applied = conn.list_applied_migrations().await?;
+        Ok(applied.iter().map(|m| m.version).max().map(|v| (v, false)))
+    }
+
+    async fn applied_migrations(&self) -> crate::error::Result<Vec<i64>> {
+        let mut conn = self.pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        let applied = conn.list_applied_migrations().await?;
+        Ok(applied.iter().map(|m| m.version).collect())
+    }
+
+    async fn ping(&self) -> crate::error::Result<()> {
+        sqlx::query("SELECT 1;").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn fetch_config_value(
+        &self,
+        guild: GuildId,
+        key: &str,
+        _primary: bool,
+    ) -> crate::error::Result<Option<serde_json::Value>> {
+        let sql = "SELECT value FROM config_values WHERE guild = ? AND name = ?;";
+        let _timer = self.timer(sql);
+        let v: Option<String> = sqlx::query_scalar(sql)
+            .bind(guild.0 as i64)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(v.map(|v| serde_json::from_str(&v)).transpose()?)
+    }
+
+    async fn upsert_config_value(
+        &self,
+        guild: GuildId,
+        key: &str,
+        value: &serde_json::Value,
+    ) -> crate::error::Result<()> {
+        let sql = r#"
+            INSERT INTO config_values (guild, name, value)
+            VALUES (?, ?, ?)
+            ON CONFLICT (guild, name) DO UPDATE
+                SET value = excluded.value;
+            "#;
+        let _timer = self.timer(sql);
+        sqlx::query(sql)
+            .bind(guild.0 as i64)
+            .bind(key)
+            .bind(value.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_config_value(&self, guild: GuildId, key: &str) -> crate::error::Result<bool> {
+        let sql = "DELETE FROM config_values WHERE guild = ? AND name = ?;";
+        let _timer = self.timer(sql);
+        let res = sqlx::query(sql)
+            .bind(guild.0 as i64)
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn write_config_values(
+        &self,
+        sets: &[ConfigWrite<'_>],
+        defaults: &[ConfigWrite<'_>],
+    ) -> crate::error::Result<()> {
+        let set_sql = r#"
+            INSERT INTO config_values (guild, name, value)
+            VALUES (?, ?, ?)
+            ON CONFLICT (guild, name) DO UPDATE
+                SET value = excluded.value;
+            "#;
+        let default_sql = r#"
+            INSERT INTO config_values (guild, name, value)
+            VALUES (?, ?, ?)
+            ON CONFLICT (guild, name) DO NOTHING;
+            "#;
+
+        let _timer = self.timer("<config write batch>");
+        let mut tx = self.pool.begin().await?;
+        let writes = sets
+            .iter()
+            .map(|w| (set_sql, w))
+            .chain(defaults.iter().map(|w| (default_sql, w)));
+        for (sql, w) in writes {
+            sqlx::query(sql)
+                .bind(w.guild.0 as i64)
+                .bind(w.key)
+                .bind(w.value.to_string())
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn record_config_change(
+        &self,
+        guild: GuildId,
+        name: &str,
+        old_value: Option<&serde_json::Value>,
+        new_value: Option<&serde_json::Value>,
+        actor: UserId,
+    ) -> crate::error::Result<()> {
+        let sql = "INSERT INTO config_audit (guild, name, old_value, new_value, actor, changed_at) VALUES (?, ?, ?, ?, ?, ?);";
+        let _timer = self.timer(sql);
+        sqlx::query(sql)
+            .bind(guild.0 as i64)
+            .bind(name)
+            .bind(old_value.map(serde_json::Value::to_string))
+            .bind(new_value.map(serde_json::Value::to_string))
+            .bind(actor.0 as i64)
+            .bind(to_micros(chrono::Utc::now()))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn config_history(
+        &self,
+        guild: GuildId,
+        name: &str,
+        offset: i64,
+        limit: i64,
+    ) -> crate::error::Result<Vec<ConfigChange>> {
+        let sql = r#"
+            SELECT name, old_value, new_value, actor, changed_at FROM config_audit
+            WHERE guild = ? AND name = ?
+            ORDER BY changed_at DESC, id DESC
+            LIMIT ? OFFSET ?;
+            "#;
+        let _timer = self.timer(sql);
+        let rows: Vec<ConfigChangeRow> = sqlx::query_as(sql)
+            .bind(guild.0 as i64)
+            .bind(name)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let parse = |v: Option<String>| v.map(|v| serde_json::from_str(&v)).transpose();
+        rows.into_iter()
+            .map(|(name, old_value, new_value, actor, changed_at)| {
+                Ok(ConfigChange {
+                    name,
+                    old_value: parse(old_value)?,
+                    new_value: parse(new_value)?,
+                    actor: UserId(actor as u64),
+                    changed_at: from_micros(changed_at),
+                })
+            })
+            .collect()
+    }
+
+    async fn count_config_changes(&self, guild: GuildId, name: &str) -> crate::error::Result<i64> {
+        let sql = "SELECT COUNT(*) FROM config_audit WHERE guild = ? AND name = ?;";
+        let _timer = self.timer(sql);
+        let count = sqlx::query_scalar(sql)
+            .bind(guild.0 as i64)
+            .bind(name)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+
+    async fn store_action(&self, action: &Action) -> crate::error::Result<()> {
+        let sql = "INSERT INTO timed_events (target_user, guild, action, expiry) VALUES (?, ?, ?, ?);";
+        let _timer = self.timer(sql);
+        sqlx::query(sql)
+            .bind(action.target_user().0 as i64)
+            .bind(action.guild().0 as i64)
+            .bind(action.kind().to_json().to_string())
+            .bind(to_micros(action.expiry()))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn drop_action(&self, action: &Action) -> crate::error::Result<()> {
+        let sql = "DELETE FROM timed_events WHERE target_user = ? AND guild = ? AND action = ? AND expiry = ?;";
+        let _timer = self.timer(sql);
+        sqlx::query(sql)
+            .bind(action.target_user().0 as i64)
+            .bind(action.guild().0 as i64)
+            .bind(action.kind().to_json().to_string())
+            .bind(to_micros(action.expiry()))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn cancel_actions(&self, guild: GuildId, user: UserId, kind: &ActionKind) -> crate::error::Result<u64> {
+        let sql = "DELETE FROM timed_events WHERE target_user = ? AND guild = ? AND action = ?;";
+        let _timer = self.timer(sql);
+        let res = sqlx::query(sql)
+            .bind(user.0 as i64)
+            .bind(guild.0 as i64)
+            .bind(kind.to_json().to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    async fn actions_before(
+        &self,
+        epoch: chrono::DateTime<chrono::Utc>,
+        limit: usize,
+    ) -> crate::error::Result<Vec<Action>> {
+        let sql = "SELECT target_user, guild, expiry, action FROM timed_events WHERE expiry <= ? ORDER BY expiry ASC LIMIT ?;";
+        let _timer = self.timer(sql);
+        let rows: Vec<(i64, i64, i64, String)> = sqlx::query_as(sql)
+            .bind(to_micros(epoch))
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.into_iter()
+            .map(|(user, guild, expiry, action)| {
+                Ok(Action::new(
+                    (user as u64).into(),
+                    (guild as u64).into(),
+                    serde_json::from_str(&action)?,
+                    from_micros(expiry),
+                ))
+            })
+            .collect()
+    }
+
+    async fn add_joinable_role(
+        &self,
+        guild: GuildId,
+        role: RoleId,
+        expires_after: Option<Duration>,
+    ) -> crate::error::Result<()> {
+        let sql = "INSERT INTO joinable_roles (guild, role, expires_after) VALUES (?, ?, ?);";
+        let _timer = self.timer(sql);
+        let res = sqlx::query(sql)
+            .bind(guild.0 as i64)
+            .bind(role.0 as i64)
+            .bind(expires_after.map(|d| d.as_secs().max(1) as i64))
+            .execute(&self.pool)
+            .await;
+
+        match res {
+            Err(e) if e.is_check() => Err(TooManyRoles.into()),
+            Err(e) if e.is_unique() => Err(AlreadyJoinable.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    async fn del_joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<()> {
+        let sql = "DELETE FROM joinable_roles WHERE guild = ? AND role = ?;";
+        let _timer = self.timer(sql);
+        sqlx::query(sql)
+            .bind(guild.0 as i64)
+            .bind(role.0 as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn joinable_role(&self, guild: GuildId, role: RoleId) -> crate::error::Result<Option<JoinableRole>> {
+        let sql = "SELECT role, expires_after, role_group, description, category FROM joinable_roles WHERE guild = ? AND role = ?;";
+        let _timer = self.timer(sql);
+        let row: Option<JoinableRoleRow> = sqlx::query_as(sql)
+            .bind(guild.0 as i64)
+            .bind(role.0 as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(joinable_role))
+    }
+
+    async fn joinable_roles(&self, guild: GuildId) -> crate::error::Result<Vec<JoinableRole>> {
+        let sql = "SELECT role, expires_after, role_group, description, category FROM joinable_roles WHERE guild = ? ORDER BY role ASC;";
+        let _timer = self.timer(sql);
+        let rows: Vec<JoinableRoleRow> = sqlx::query_as(sql).bind(guild.0 as i64).fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().map(joinable_role).collect())
+    }
+
+    async fn count_joinable_roles(&self, guild: GuildId) -> crate::error::Result<i64> {
+        let sql = "SELECT COUNT(*) FROM joinable_roles WHERE guild = ?;";
+        let _timer = self.timer(sql);
+        let count = sqlx::query_scalar(sql)
+            .bind(guild.0 as i64)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+
+    async fn set_role_description(
+        &self,
+        guild: GuildId,
+        role: RoleId,
+        description: Option<&str>,
+    ) -> crate::error::Result<bool> {
+        let sql = "UPDATE joinable_roles SET description = ? WHERE guild = ? AND role = ?;";
+        let _timer = self.timer(sql);
+        let res = sqlx::query(sql)
+            .bind(description)
+            .bind(guild.0 as i64)
+            .bind(role.0 as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn set_role_category(
+        &self,
+        guild: GuildId,
+        role: RoleId,
+        category: Option<&str>,
+    ) -> crate::error::Result<bool> {
+        let sql = "UPDATE joinable_roles SET category = ? WHERE guild = ? AND role = ?;";
+        let _timer = self.timer(sql);
+        let res = sqlx::query(sql)
+            .bind(category)
+            .bind(guild.0 as i64)
+            .bind(role.0 as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn set_role_group(&self, guild: GuildId, role: RoleId, group: Option<&str>) -> crate::error::Result<bool> {
+        let sql = "UPDATE joinable_roles SET role_group = ? WHERE guild = ? AND role = ?;";
+        let _timer = self.timer(sql);
+        let res = sqlx::query(sql)
+            .bind(group)
+            .bind(guild.0 as i64)
+            .bind(role.0 as i64)
+            .execute(&self.pool)
+            .await;
+
+        match res {
+            Err(e) if e.is_foreign_key() => Err(NoSuchRoleGroup.into()),
+            Err(e) => Err(e.into()),
+            Ok(r) => Ok(r.rows_affected() > 0),
+        }
+    }
+
+    async fn create_role_group(&self, guild: GuildId, name: &str, max_roles: u64) -> crate::error::Result<()> {
+        let sql = "INSERT INTO role_groups (guild, name, max_roles) VALUES (?, ?, ?);";
+        let _timer = self.timer(sql);
+        let res = sqlx::query(sql)
+            .bind(guild.0 as i64)
+            .bind(name)
+            .bind(max_roles as i64)
+            .execute(&self.pool)
+            .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(RoleGroupExists.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    async fn delete_role_group(&self, guild: GuildId, name: &str) -> crate::error::Result<bool> {
+        let ungroup = "UPDATE joinable_roles SET role_group = NULL WHERE guild = ? AND role_group = ?;";
+        let delete = "DELETE FROM role_groups WHERE guild = ? AND name = ?;";
+        let _timer = self.timer(delete);
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(ungroup)
+            .bind(guild.0 as i64)
+            .bind(name)
+            .execute(&mut tx)
+            .await?;
+        let res = sqlx::query(delete)
+            .bind(guild.0 as i64)
+            .bind(name)
+            .execute(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn role_group(&self, guild: GuildId, name: &str) -> crate::error::Result<Option<RoleGroup>> {
+        let sql = "SELECT max_roles FROM role_groups WHERE guild = ? AND name = ?;";
+        let timer = self.timer(sql);
+        let max_roles: Option<i64> = sqlx::query_scalar(sql)
+            .bind(guild.0 as i64)
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        std::mem::drop(timer);
+        let max_roles = match max_roles {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+
+        let sql = "SELECT role FROM joinable_roles WHERE guild = ? AND role_group = ? ORDER BY role ASC;";
+        let _timer = self.timer(sql);
+        let roles: Vec<i64> = sqlx::query_scalar(sql)
+            .bind(guild.0 as i64)
+            .bind(name)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(Some(RoleGroup {
+            name: name.to_string(),
+            max_roles: max_roles as u64,
+            roles: roles.into_iter().map(|r| RoleId::from(r as u64)).collect(),
+        }))
+    }
+
+    async fn role_groups(&self, guild: GuildId) -> crate::error::Result<Vec<RoleGroup>> {
+        let sql = "SELECT name, max_roles FROM role_groups WHERE guild = ? ORDER BY name ASC;";
+        let _timer = self.timer(sql);
+        let rows: Vec<(String, i64)> = sqlx::query_as(sql).bind(guild.0 as i64).fetch_all(&self.pool).await?;
+        Ok(rows
+            .into_iter()
+            .map(|(name, max_roles)| RoleGroup {
+                name,
+                max_roles: max_roles as u64,
+                roles: Vec::new(),
+            })
+            .collect())
+    }
+
+    async fn count_role_groups(&self, guild: GuildId) -> crate::error::Result<i64> {
+        let sql = "SELECT COUNT(*) FROM role_groups WHERE guild = ?;";
+        let _timer = self.timer(sql);
+        let count = sqlx::query_scalar(sql)
+            .bind(guild.0 as i64)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+}
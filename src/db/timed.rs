@@ -7,14 +7,17 @@ use std::fmt::Formatter;
 use chrono::Duration;
 use chrono::Utc;
 use once_cell::sync::Lazy;
-use serenity::model::id::{GuildId, UserId};
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
 use serenity::prelude::Context;
-use sqlx::PgPool;
 
-use crate::db::DbContext;
+use crate::db::storage::{postgres, Storage};
+use crate::db::{DbContext, TxContext};
 use crate::dispatch::config::VerifiedRole;
+use crate::dispatch::rest::{is_transient, RetryOp};
 use crate::dispatch::Dispatch;
-use crate::module::moderation::NoMuteRoleSet;
+use crate::error::LogErrorExt;
+use crate::module::moderation::{set_timeout, NoMuteRoleSet};
+use crate::module::persistent_roles::DepartedMembers;
 
 /// The kind of action to be taken once a timed event is processed.
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
@@ -24,8 +27,40 @@ pub enum ActionKind {
     Ban,
     /// A user needs to be unmuted.
     Mute,
+    /// A user's native Discord timeout needs to be removed.
+    Timeout,
     /// Prints a debug message to the logger.
     Debug,
+    /// A lockdown needs to be ended.
+    EndLockdown,
+    /// A guild's relaxed spam thresholds need to be restored.
+    EndRelaxation,
+    /// A guild's raid mode needs to be turned off.
+    EndRaidMode,
+    /// A temporary role needs to be removed from a user.
+    RemoveRole {
+        /// The role to remove.
+        role: RoleId,
+    },
+    /// A joinable role a user joined has expired and needs to be removed from them.
+    ExpireJoinedRole {
+        /// The role to remove.
+        role: RoleId,
+    },
+    /// A member needs to be given the guild's autoroles.
+    GrantAutoroles,
+    /// A channel's automatic slowmode needs to be relaxed if traffic has calmed down.
+    RelaxSlowmode {
+        /// The channel in slowmode.
+        channel: ChannelId,
+    },
+    /// A member needs to be reminded of something they asked to be.
+    Remind {
+        /// The ID of the reminder.
+        reminder: i64,
+    },
+    /// A member who hasn't verified in time needs to be kicked.
+    KickUnverified,
 }
 
 impl ActionKind {
@@ -93,12 +128,22 @@ impl ActionFailure {
         match self.action.kind {
             ActionKind::Ban => "could not unban",
             ActionKind::Mute => "could not unmute",
+            ActionKind::Timeout => "could not remove timeout",
             ActionKind::Debug => "could not print debug statement",
+            ActionKind::EndLockdown => "could not end lockdown",
+            ActionKind::EndRelaxation => "could not restore spam thresholds",
+            ActionKind::EndRaidMode => "could not end raid mode",
+            ActionKind::RemoveRole { .. } => "could not remove temporary role",
+            ActionKind::ExpireJoinedRole { .. } => "could not remove expired joinable role",
+            ActionKind::GrantAutoroles => "could not give autoroles",
+            ActionKind::RelaxSlowmode { .. } => "could not relax automatic slowmode",
+            ActionKind::Remind { .. } => "could not send reminder",
+            ActionKind::KickUnverified => "could not kick unverified member",
         }
     }
 
     /// Returns a string describing the failure that occurred.
-    pub fn failure_info(&self) -> Cow<str> {
+    pub fn failure_info(&self) -> Cow<'_, str> {
         match &self.kind {
             FailureKind::UserNotInGuild => {
                 format!("user {} is not a member of this guild", self.action.target_user).into()
@@ -124,12 +169,43 @@ impl Action {
     pub async fn act(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
         let db = dis.db(self.guild);
         let res: Result<(), ActionFailure> = match self.kind {
-            ActionKind::Ban => self.do_unban(ctx).await,
+            ActionKind::Ban => self.do_unban(dis, ctx).await,
             ActionKind::Mute => self.do_unmute(dis, db.clone(), ctx).await,
+            ActionKind::Timeout => self.do_untimeout(ctx).await,
             ActionKind::Debug => {
                 debug!("Got debug action: {:?}", self);
                 Ok(())
             }
+            ActionKind::RemoveRole { role } | ActionKind::ExpireJoinedRole { role } => {
+                self.do_remove_role(dis, ctx, role).await
+            }
+            ActionKind::EndLockdown => crate::module::lockdown::expire_lockdown(dis, ctx, self.guild)
+                .await
+                .map_err(|e| ActionFailure::from_err(*self, e)),
+            ActionKind::EndRelaxation => crate::module::automod::expire_relaxation(dis, ctx, self.guild)
+                .await
+                .map_err(|e| ActionFailure::from_err(*self, e)),
+            ActionKind::EndRaidMode => crate::module::raidmode::expire_raid_mode(dis, ctx, self.guild)
+                .await
+                .map_err(|e| ActionFailure::from_err(*self, e)),
+            ActionKind::GrantAutoroles => {
+                crate::module::autorole::grant_autoroles(dis, ctx, self.guild, self.target_user)
+                    .await
+                    .map_err(|e| ActionFailure::from_err(*self, e))
+            }
+            ActionKind::RelaxSlowmode { channel } => {
+                crate::module::slowmode::relax_slowmode(dis, ctx, self.guild, channel)
+                    .await
+                    .map_err(|e| ActionFailure::from_err(*self, e))
+            }
+            ActionKind::Remind { reminder } => crate::module::remind::send_reminder(dis, ctx, self.guild, reminder)
+                .await
+                .map_err(|e| ActionFailure::from_err(*self, e)),
+            ActionKind::KickUnverified => {
+                crate::module::verification::kick_unverified(dis, ctx, self.guild, self.target_user)
+                    .await
+                    .map_err(|e| ActionFailure::from_err(*self, e))
+            }
         };
 
         if let Err(e) = res {
@@ -141,7 +217,7 @@ impl Action {
         Ok(())
     }
 
-    /// Unmutes a user in a guild.
+    /// Unmutes a user in a guild. If Discord is degraded, the role removal is queued for retry.
     #[instrument(level = "debug", skip(self, dis, db, ctx))]
     async fn do_unmute<'me, 'dis, 'a>(
         &'me self,
@@ -157,17 +233,36 @@ impl Action {
             .map_err(|e| ActionFailure::from_err(*self, e))?
             .ok_or_else(|| ActionFailure::from_err(*self, NoMuteRoleSet))?;
 
-        let mut mem = self
-            .guild
-            .member(ctx, self.target_user)
-            .await
-            .map_err(|_| ActionFailure::new(*self, FailureKind::UserNotInGuild))?;
+        let remove = RetryOp::RemoveRole {
+            guild: self.guild,
+            user: self.target_user,
+            role: mute_role.into_inner(),
+        };
 
-        if mem.roles.contains(&mute_role.into_inner()) {
+        let is_muted = match self.guild.member(ctx, self.target_user).await {
+            Ok(mem) => mem.roles.contains(&mute_role.into_inner()),
+            // Removing a role is idempotent, so we can skip the membership check.
+            Err(e) if is_transient(&e) => true,
+            Err(_) => {
+                // Don't mute them again if they rejoin after their mute would have run out.
+                DepartedMembers::new(&db)
+                    .forget_role(self.target_user, mute_role.into_inner())
+                    .await
+                    .log_error();
+                return Err(ActionFailure::new(*self, FailureKind::UserNotInGuild));
+            }
+        };
+
+        if is_muted {
             debug!("unmuting user");
-            mem.remove_role(ctx, mute_role.into_inner())
+            let done = dis
+                .rest()
+                .perform_or_queue(ctx, remove)
                 .await
                 .map_err(|e| ActionFailure::from_err(*self, e))?;
+            if !done {
+                debug!("queued unmute for retry");
+            }
         } else {
             debug!("user wasn't muted");
         }
@@ -175,13 +270,63 @@ impl Action {
         Ok(())
     }
 
-    /// Unbans a user in a guild.
+    /// Removes a user's timeout. Discord lifts timeouts on its own once they run out, so this only
+    /// makes sure the timeout is gone.
     #[instrument(level = "debug", skip(self, ctx))]
-    async fn do_unban(&self, ctx: &Context) -> Result<(), ActionFailure> {
-        self.guild
-            .unban(ctx, self.target_user)
+    async fn do_untimeout(&self, ctx: &Context) -> Result<(), ActionFailure> {
+        match set_timeout(ctx, self.guild, self.target_user, None).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_transient(&e) => Err(ActionFailure::from_err(*self, e)),
+            Err(_) => Err(ActionFailure::new(*self, FailureKind::UserNotInGuild)),
+        }
+    }
+
+    /// Removes a temporary or expired joinable role from a user. If Discord is degraded, the removal is
+    /// queued for retry.
+    #[instrument(level = "debug", skip(self, dis, ctx))]
+    async fn do_remove_role(&self, dis: &Dispatch, ctx: &Context, role: RoleId) -> Result<(), ActionFailure> {
+        match self.guild.member(ctx, self.target_user).await {
+            Ok(mem) if !mem.roles.contains(&role) => {
+                debug!("user no longer has the role");
+                return Ok(());
+            }
+            Ok(_) => {}
+            // Removing a role is idempotent, so we can skip the membership check.
+            Err(e) if is_transient(&e) => {}
+            Err(_) => return Err(ActionFailure::new(*self, FailureKind::UserNotInGuild)),
+        }
+
+        let remove = RetryOp::RemoveRole {
+            guild: self.guild,
+            user: self.target_user,
+            role,
+        };
+        let done = dis
+            .rest()
+            .perform_or_queue(ctx, remove)
             .await
             .map_err(|e| ActionFailure::from_err(*self, e))?;
+        if !done {
+            debug!("queued temporary role removal for retry");
+        }
+        Ok(())
+    }
+
+    /// Unbans a user in a guild. If Discord is degraded, the unban is queued for retry.
+    #[instrument(level = "debug", skip(self, dis, ctx))]
+    async fn do_unban(&self, dis: &Dispatch, ctx: &Context) -> Result<(), ActionFailure> {
+        let unban = RetryOp::Unban {
+            guild: self.guild,
+            user: self.target_user,
+        };
+        let done = dis
+            .rest()
+            .perform_or_queue(ctx, unban)
+            .await
+            .map_err(|e| ActionFailure::from_err(*self, e))?;
+        if !done {
+            debug!("queued unban for retry");
+        }
         Ok(())
     }
 }
@@ -191,6 +336,21 @@ impl Action {
     pub fn guild(&self) -> GuildId {
         self.guild
     }
+
+    /// Accessor for when the action should be taken.
+    pub fn expiry(&self) -> chrono::DateTime<Utc> {
+        self.expiry
+    }
+
+    /// Accessor for the user affected by the action.
+    pub fn target_user(&self) -> UserId {
+        self.target_user
+    }
+
+    /// Accessor for the kind of action to take.
+    pub fn kind(&self) -> &ActionKind {
+        &self.kind
+    }
 }
 
 /// A duration representing one minute.
@@ -198,14 +358,6 @@ pub static ONE_MINUTE: Lazy<Duration> = Lazy::new(|| Duration::minutes(1));
 /// A duration representing about one hundred years.
 pub static ONE_HUNDREDISH_YEARS: Lazy<Duration> = Lazy::new(|| Duration::days(365 * 100));
 
-#[doc(hidden)]
-struct Row {
-    target_user: i64,
-    guild: i64,
-    expiry: chrono::DateTime<Utc>,
-    action: serde_json::Value,
-}
-
 /// A wrapper for a database context for performing actions with timed actions.
 #[derive(Clone)]
 pub struct TimedEvents<'pool> {
@@ -224,61 +376,34 @@ impl<'pool> TimedEvents<'pool> {
 
     /// Stores an action in the database.
     pub async fn store_action(&self, action: &Action) -> crate::error::Result<()> {
-        sqlx::query!(
-            r#"
-            INSERT INTO timed_events (target_user, guild, action, expiry) VALUES ($1, $2, $3, $4);
-            "#,
-            action.target_user.0 as i64,
-            self.context.guild_as_i64(),
-            action.kind.to_json(),
-            action.expiry.clone()
-        )
-        .execute(self.context.conn())
-        .await?;
-        Ok(())
+        self.context.storage().store_action(action).await
+    }
+
+    /// Stores an action in the database as part of a transaction.
+    pub async fn store_action_in(tx: &mut TxContext<'_>, action: &Action) -> crate::error::Result<()> {
+        postgres::store_action(tx.conn(), action).await
     }
 
     /// Deletes an action from the database.
     pub async fn drop_action(&self, action: &Action) -> crate::error::Result<()> {
-        sqlx::query!(
-            r#"
-            DELETE FROM timed_events WHERE target_user = $1
-                                       AND guild = $2
-                                       AND action = $3
-                                       AND expiry = $4;
-            "#,
-            action.target_user.0 as i64,
-            self.context.guild_as_i64(),
-            action.kind.to_json(),
-            action.expiry.clone()
-        )
-        .execute(self.context.conn())
-        .await?;
-        Ok(())
+        self.context.storage().drop_action(action).await
+    }
+
+    /// Deletes all pending actions of the given kind against a user, returning the number of
+    /// actions removed.
+    pub async fn cancel_actions_for(&self, user: UserId, kind: ActionKind) -> crate::error::Result<u64> {
+        self.context
+            .storage()
+            .cancel_actions(self.context.guild(), user, &kind)
+            .await
     }
 
     /// Retrieves the actions before the specified epoch, limited by `BATCH_LIMIT`.
-    pub async fn get_actions_before(pool: &PgPool, epoch: chrono::DateTime<Utc>) -> crate::error::Result<Vec<Action>> {
-        let q: sqlx::query::Map<_, _, _> = sqlx::query_as!(
-            Row,
-            r#"
-            SELECT target_user, guild, expiry, action FROM timed_events WHERE expiry <= $1 ORDER BY expiry ASC LIMIT $2;
-            "#,
-            epoch,
-            Self::BATCH_LIMIT as i64
-        );
-
-        q.try_map(|r: Row| {
-            Ok(Action::new(
-                (r.target_user as u64).into(),
-                (r.guild as u64).into(),
-                serde_json::from_value(r.action).map_err(|e| sqlx::Error::Decode(e.into()))?,
-                r.expiry,
-            ))
-        })
-        .fetch_all(pool)
-        .await
-        .map_err(crate::error::Error::from)
+    pub async fn get_actions_before(
+        storage: &dyn Storage,
+        epoch: chrono::DateTime<Utc>,
+    ) -> crate::error::Result<Vec<Action>> {
+        storage.actions_before(epoch, Self::BATCH_LIMIT).await
     }
 }
 
@@ -316,6 +441,61 @@ impl Action {
         Self::with_duration(user, guild, ActionKind::Mute, duration)
     }
 
+    /// Creates an action to remove a timeout from a user.
+    pub fn untimeout(user: UserId, guild: GuildId, duration: impl Into<chrono::Duration>) -> Self {
+        Self::with_duration(user, guild, ActionKind::Timeout, duration)
+    }
+
+    /// Creates an action to remove a temporary role from a user.
+    pub fn remove_role(user: UserId, guild: GuildId, role: RoleId, duration: impl Into<chrono::Duration>) -> Self {
+        Self::with_duration(user, guild, ActionKind::RemoveRole { role }, duration)
+    }
+
+    /// Creates an action to remove a joinable role from a user once it expires.
+    pub fn expire_joined_role(
+        user: UserId,
+        guild: GuildId,
+        role: RoleId,
+        duration: impl Into<chrono::Duration>,
+    ) -> Self {
+        Self::with_duration(user, guild, ActionKind::ExpireJoinedRole { role }, duration)
+    }
+
+    /// Creates an action to end a lockdown in a guild.
+    pub fn end_lockdown(guild: GuildId, duration: impl Into<chrono::Duration>) -> Self {
+        Self::with_duration(Default::default(), guild, ActionKind::EndLockdown, duration)
+    }
+
+    /// Creates an action to restore a guild's relaxed spam thresholds.
+    pub fn end_relaxation(guild: GuildId, duration: impl Into<chrono::Duration>) -> Self {
+        Self::with_duration(Default::default(), guild, ActionKind::EndRelaxation, duration)
+    }
+
+    /// Creates an action to turn off raid mode in a guild.
+    pub fn end_raid_mode(guild: GuildId, duration: impl Into<chrono::Duration>) -> Self {
+        Self::with_duration(Default::default(), guild, ActionKind::EndRaidMode, duration)
+    }
+
+    /// Creates an action to give a member the guild's autoroles.
+    pub fn grant_autoroles(user: UserId, guild: GuildId, duration: impl Into<chrono::Duration>) -> Self {
+        Self::with_duration(user, guild, ActionKind::GrantAutoroles, duration)
+    }
+
+    /// Creates an action to relax a channel's automatic slowmode at the given time.
+    pub fn relax_slowmode(guild: GuildId, channel: ChannelId, at: chrono::DateTime<Utc>) -> Self {
+        Self::new(Default::default(), guild, ActionKind::RelaxSlowmode { channel }, at)
+    }
+
+    /// Creates an action to deliver a member's reminder at the given time.
+    pub fn remind(user: UserId, guild: GuildId, reminder: i64, at: chrono::DateTime<Utc>) -> Self {
+        Self::new(user, guild, ActionKind::Remind { reminder }, at)
+    }
+
+    /// Creates an action to kick a member if they haven't verified once the duration is up.
+    pub fn kick_unverified(user: UserId, guild: GuildId, duration: impl Into<chrono::Duration>) -> Self {
+        Self::with_duration(user, guild, ActionKind::KickUnverified, duration)
+    }
+
     /// Creates an action to print a debug message.
     pub fn debug(duration: impl Into<chrono::Duration>) -> Self {
         Self::with_duration(Default::default(), Default::default(), ActionKind::Debug, duration)
@@ -0,0 +1,228 @@
+//! Contains a write-behind buffer for guild config values. Writes which don't need to reach the
+//! database straight away, like frequently updated values and defaults stored on first read, are
+//! held here and coalesced per (guild, key), so only the latest write for each is flushed.
+//!
+//! Writing a key straight to the database claims it first; see [`WriteBuffer::claim`]. A flush
+//! leaves claimed keys pending, and waits for nobody, so a transaction holding claims until it ends
+//! only holds up writes to the keys it wrote.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serenity::model::id::GuildId;
+
+use crate::db::storage::{ConfigWrite, Storage};
+
+/// How many writes may be pending before a write flushes the buffer itself instead of waiting
+/// for the background service.
+pub const MAX_PENDING_WRITES: usize = 1000;
+
+/// A write waiting to be flushed.
+#[derive(Debug, Clone)]
+enum PendingWrite {
+    /// Stores the value, replacing whatever the guild had.
+    Set(serde_json::Value),
+    /// Stores the value only if the guild has nothing stored, as with defaults.
+    Default(serde_json::Value),
+}
+
+impl PendingWrite {
+    /// The value being written.
+    fn value(&self) -> &serde_json::Value {
+        match self {
+            PendingWrite::Set(v) | PendingWrite::Default(v) => v,
+        }
+    }
+}
+
+/// Locks on individual config keys, keyed by guild and config key.
+type KeyLocks = HashMap<(GuildId, String), Arc<tokio::sync::Mutex<()>>>;
+
+/// Buffers config writes until they're flushed.
+#[derive(Default, Debug)]
+pub struct WriteBuffer {
+    /// The pending writes, keyed by guild and config key.
+    pending: Mutex<HashMap<(GuildId, String), PendingWrite>>,
+    /// The writes being flushed, which reads still need to see until they've reached the database.
+    in_flight: Mutex<HashMap<(GuildId, String), PendingWrite>>,
+    /// Held while flushing, so only one flush runs at a time.
+    flush_lock: tokio::sync::Mutex<()>,
+    /// Held for each key being flushed or written straight to the database, so a flush can't land
+    /// on top of a newer direct write.
+    key_locks: Mutex<KeyLocks>,
+}
+
+/// A claim on writing a config key, made by [`WriteBuffer::claim`]. Flushes leave the key alone
+/// until the claim is dropped.
+pub struct KeyClaim<'a> {
+    /// Where the key's lock is tracked.
+    locks: &'a Mutex<KeyLocks>,
+    /// The buffer the claim was made on.
+    buffer: &'a WriteBuffer,
+    /// The claimed key.
+    key: (GuildId, String),
+    /// The key's lock.
+    lock: Arc<tokio::sync::Mutex<()>>,
+    /// Set once the lock has been taken.
+    guard: Option<tokio::sync::OwnedMutexGuard<()>>,
+    /// The pending write discarded when the claim was made, if there was one.
+    discarded: Option<PendingWrite>,
+}
+
+impl KeyClaim<'_> {
+    /// The claimed config key.
+    pub fn key(&self) -> &str {
+        &self.key.1
+    }
+
+    /// Queues the write discarded by the claim again, unless a newer one has been queued since.
+    /// Used when the claimant's own write didn't happen after all.
+    pub fn restore(mut self) {
+        if let Some(w) = self.discarded.take() {
+            self.buffer.pending.lock().entry(self.key.clone()).or_insert(w);
+        }
+    }
+}
+
+impl Drop for KeyClaim<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut locks = self.locks.lock();
+        // One reference is in the map and one is ours; any more belong to tasks still waiting.
+        if Arc::strong_count(&self.lock) <= 2 {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+impl WriteBuffer {
+    /// Queues a value to be stored, replacing any pending write for the key.
+    pub fn set(&self, gid: GuildId, key: impl Into<String>, value: serde_json::Value) {
+        self.pending.lock().insert((gid, key.into()), PendingWrite::Set(value));
+    }
+
+    /// Queues a value to be stored if the guild has nothing stored. Any pending write for the key
+    /// wins over this.
+    pub fn set_default(&self, gid: GuildId, key: impl Into<String>, value: serde_json::Value) {
+        self.pending
+            .lock()
+            .entry((gid, key.into()))
+            .or_insert(PendingWrite::Default(value));
+    }
+
+    /// Retrieves the value pending for a key, if there is one.
+    pub fn get(&self, gid: GuildId, key: &str) -> Option<serde_json::Value> {
+        let k = (gid, key.to_string());
+        if let Some(w) = self.pending.lock().get(&k) {
+            return Some(w.value().clone());
+        }
+        self.in_flight.lock().get(&k).map(|w| w.value().clone())
+    }
+
+    /// Claims a key the caller is about to write to directly, waiting for any flush of it or
+    /// other claim on it to finish first. Any pending write for the key is discarded, since it's
+    /// older. Writes queued while the claim is held are kept, and flushed once it's dropped.
+    pub async fn claim(&self, gid: GuildId, key: &str) -> KeyClaim<'_> {
+        let key = (gid, key.to_string());
+        let lock = self.key_locks.lock().entry(key.clone()).or_default().clone();
+        // Made before waiting, so the lock is still forgotten if this task is cancelled.
+        let mut claim = KeyClaim {
+            locks: &self.key_locks,
+            buffer: self,
+            key,
+            lock,
+            guard: None,
+            discarded: None,
+        };
+        claim.guard = Some(claim.lock.clone().lock_owned().await);
+        claim.discarded = self.pending.lock().remove(&claim.key);
+        claim
+    }
+
+    /// Claims a key for flushing if nobody else has claimed it.
+    fn try_claim(&self, key: &(GuildId, String)) -> Option<KeyClaim<'_>> {
+        let mut locks = self.key_locks.lock();
+        let lock = locks.entry(key.clone()).or_default().clone();
+        let guard = lock.clone().try_lock_owned().ok();
+        drop(locks);
+        // Dropped unclaimed, this forgets the lock again if nobody's using it.
+        let mut claim = KeyClaim {
+            locks: &self.key_locks,
+            buffer: self,
+            key: key.clone(),
+            lock,
+            guard: None,
+            discarded: None,
+        };
+        claim.guard = Some(guard?);
+        Some(claim)
+    }
+
+    /// The number of pending writes.
+    pub fn len(&self) -> usize {
+        self.pending.lock().len()
+    }
+
+    /// Whether there are no pending writes.
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().is_empty()
+    }
+
+    /// Writes every pending write to the database, returning how many there were. Writes to keys
+    /// claimed by someone writing to them directly are left for a later flush. If writing fails,
+    /// the writes are queued again unless newer ones have replaced them.
+    pub async fn flush(&self, storage: &dyn Storage) -> crate::error::Result<usize> {
+        let _guard = self.flush_lock.lock().await;
+        let keys: Vec<_> = self.pending.lock().keys().cloned().collect();
+        let claims: Vec<_> = keys.iter().filter_map(|k| self.try_claim(k)).collect();
+        let pending: HashMap<_, _> = {
+            let mut queued = self.pending.lock();
+            claims
+                .iter()
+                .filter_map(|c| queued.remove(&c.key).map(|w| (c.key.clone(), w)))
+                .collect()
+        };
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let count = pending.len();
+        *self.in_flight.lock() = pending.clone();
+        let res = Self::write(storage, &pending).await;
+        self.in_flight.lock().clear();
+
+        if let Err(e) = res {
+            let mut queued = self.pending.lock();
+            for (k, w) in pending {
+                queued.entry(k).or_insert(w);
+            }
+            return Err(e);
+        }
+
+        trace!("flushed {} config writes", count);
+        Ok(count)
+    }
+
+    /// Writes a batch of pending writes in one transaction.
+    async fn write(
+        storage: &dyn Storage,
+        pending: &HashMap<(GuildId, String), PendingWrite>,
+    ) -> crate::error::Result<()> {
+        let mut sets = Vec::new();
+        let mut defaults = Vec::new();
+        for ((gid, key), w) in pending {
+            let batch = match w {
+                PendingWrite::Set(_) => &mut sets,
+                PendingWrite::Default(_) => &mut defaults,
+            };
+            batch.push(ConfigWrite {
+                guild: *gid,
+                key,
+                value: w.value(),
+            });
+        }
+
+        storage.write_config_values(&sets, &defaults).await
+    }
+}
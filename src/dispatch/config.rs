@@ -1,16 +1,17 @@
 //! Contains logic related to managing guild config values.
 
-use std::any::Any;
+use std::any::{Any, TypeId};
 use std::fmt;
 use std::fmt::Formatter;
+use std::marker::PhantomData;
 use std::str::FromStr;
 
 use downcast_rs::impl_downcast;
 use downcast_rs::DowncastSync;
-use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::de::{DeserializeOwned, MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serenity::client::Context;
-use serenity::model::guild::Member;
 use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
 use serenity::model::misc::Mentionable;
 
@@ -35,6 +36,8 @@ where
     help: &'static str,
     /// A default value which can be used if `T: Clone` to set an unset config value.
     default: Option<Box<dyn Fn() -> T + Send + Sync>>,
+    /// Whether the value is sensitive, like a webhook URL, and shouldn't be shown once set.
+    secret: bool,
 }
 
 impl<T> fmt::Debug for Value<T>
@@ -49,6 +52,7 @@ where
                 "default",
                 &self.default.as_ref().map(|_| "present").unwrap_or("not present") as &dyn fmt::Debug,
             )
+            .field("secret", &self.secret as &dyn fmt::Debug)
             .finish()
     }
 }
@@ -70,9 +74,17 @@ where
             name,
             help,
             default: None,
+            secret: false,
         }
     }
 
+    /// Marks the value as sensitive, like a webhook URL or an API key. Secret values are validated
+    /// and stored as usual, but are masked wherever they'd be displayed.
+    pub fn secret(mut self) -> Self {
+        self.secret = true;
+        self
+    }
+
     /// Creates a value with the given name and help, and with the specified default.
     pub fn with_default<F>(name: &'static str, help: &'static str, default: F) -> Self
     where
@@ -83,8 +95,8 @@ where
         out
     }
 
-    /// Retrieves the value associated with this value's name, setting it atomically if it doesn't
-    /// exist.
+    /// Retrieves the value associated with this value's name, setting it if it doesn't exist. The
+    /// default is written to the database in the background.
     pub async fn get_or_insert_with<F>(&self, ctx: &DbContext<'_>, def: F) -> crate::error::Result<Arc<T>>
     where
         F: Fn() -> T + Send + Sync,
@@ -92,14 +104,22 @@ where
         ctx.get_or_insert_with(self.name, def).await
     }
 
-    /// Retrieves the value associated with this value's name, setting it atomically if it doesn't
-    /// exist using the default specified when this value was constructed.
+    /// Retrieves the value associated with this value's name. If the guild hasn't set it, the
+    /// instance default is used, falling back to the default specified when this value was
+    /// constructed.
     pub async fn get_or_default(&self, ctx: &DbContext<'_>) -> crate::error::Result<Arc<T>> {
-        if self.default.is_none() {
-            return Err(NoDefaultSpecified.into());
+        if let Some(v) = self.get(ctx).await? {
+            return Ok(v);
+        }
+
+        if let Some(v) = ctx.instance_default::<_, T>(self.name) {
+            return Ok(Arc::new(v));
         }
 
-        ctx.get_or_insert_with(self.name, self.default.as_ref().unwrap()).await
+        self.default
+            .as_ref()
+            .map(|d| Arc::new(d()))
+            .ok_or_else(|| NoDefaultSpecified.into())
     }
 
     /// Retrieves the value associated with this value's name, returning `None` if hasn't been set.
@@ -111,6 +131,12 @@ where
     pub async fn set(&self, ctx: &DbContext<'_>, value: T) -> crate::error::Result<()> {
         ctx.insert(self.name, value).await
     }
+
+    /// Sets the value associated with this value's name, writing it to the database in the
+    /// background. See [`DbContext::insert_deferred`].
+    pub async fn set_deferred(&self, ctx: &DbContext<'_>, value: T) -> crate::error::Result<()> {
+        ctx.insert_deferred(self.name, value).await
+    }
 }
 
 /// Trait for converting arbitrary types from strings into values
@@ -163,10 +189,26 @@ pub trait Validator: Send + Sync + Any + DowncastSync + 'static {
     async fn validate(&self, ctx: &Context, gid: GuildId, s: &str) -> crate::error::Result<serde_json::Value>;
     /// Gets value from DB.
     async fn get_json(&self, db: &DbContext<'_>) -> crate::error::Result<Option<serde_json::Value>>;
+    /// Gets the value used when the guild hasn't set one: the instance default, falling back to
+    /// the default specified when the value was constructed. `None` if there's neither.
+    async fn default_json(&self, db: &DbContext<'_>) -> crate::error::Result<Option<serde_json::Value>>;
     /// Inserts value into DB.
     async fn insert_json(&self, v: serde_json::Value, db: &DbContext<'_>) -> crate::error::Result<()>;
     /// Converts a JSON representation of the associated type into a string.
     fn display_value(&self, v: serde_json::Value) -> crate::error::Result<String>;
+    /// Whether values of the associated type only make sense within a single guild, like roles
+    /// and channels. Such values can't have instance defaults.
+    fn is_guild_specific(&self) -> bool;
+    /// Whether the value is sensitive and should be masked when displayed.
+    fn is_secret(&self) -> bool;
+    /// Like [`Validator::display_value`], but shows [`SECRET_PLACEHOLDER`] for secret values.
+    fn display_masked(&self, v: serde_json::Value) -> crate::error::Result<String> {
+        if self.is_secret() {
+            Ok(SECRET_PLACEHOLDER.to_string())
+        } else {
+            self.display_value(v)
+        }
+    }
 }
 impl_downcast!(sync Validator);
 
@@ -193,6 +235,13 @@ where
         Ok(v.map(serde_json::to_value).transpose()?)
     }
 
+    async fn default_json(&self, db: &DbContext<'_>) -> crate::error::Result<Option<serde_json::Value>> {
+        if let Some(v) = db.instance_default::<_, T>(self.name) {
+            return Ok(Some(serde_json::to_value(v)?));
+        }
+        Ok(self.default.as_ref().map(|d| serde_json::to_value(d())).transpose()?)
+    }
+
     async fn insert_json(&self, v: serde_json::Value, db: &DbContext<'_>) -> crate::error::Result<()> {
         let v = serde_json::from_value::<T>(v)?;
         db.insert(self.name, v).await
@@ -202,8 +251,20 @@ where
         let v: T = serde_json::from_value(v)?;
         Ok(v.to_string())
     }
+
+    fn is_guild_specific(&self) -> bool {
+        let t = TypeId::of::<T>();
+        t == TypeId::of::<VerifiedRole>() || t == TypeId::of::<VerifiedChannel>() || t == TypeId::of::<VerifiedUser>()
+    }
+
+    fn is_secret(&self) -> bool {
+        self.secret
+    }
 }
 
+/// What's shown in place of a secret value.
+pub const SECRET_PLACEHOLDER: &str = "<hidden>";
+
 /// A role which has been verified to exist in a guild.
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, Hash, Eq, PartialEq, Shrinkwrap)]
 pub struct VerifiedRole(RoleId);
@@ -249,20 +310,124 @@ impl RoleExt for RoleId {
 
 impl_err!(NoSuchRole, "There is no such role in this guild.", true);
 
+/// The most matches listed when a name could refer to several entities.
+pub const MAX_AMBIGUOUS_LISTED: usize = 5;
+
+/// Error returned when a name could refer to several entities.
+#[derive(Debug)]
+pub struct Ambiguous {
+    /// The kind of entity, like "role".
+    kind: &'static str,
+    /// Descriptions of the matching entities.
+    matches: Vec<String>,
+}
+
+impl fmt::Display for Ambiguous {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Several {}s match that name: ", self.kind)?;
+        let listed = self
+            .matches
+            .iter()
+            .take(MAX_AMBIGUOUS_LISTED)
+            .cloned()
+            .collect::<Vec<_>>();
+        write!(f, "{}", listed.join(", "))?;
+        if self.matches.len() > MAX_AMBIGUOUS_LISTED {
+            write!(f, " and {} more", self.matches.len() - MAX_AMBIGUOUS_LISTED)?;
+        }
+        write!(f, ". Use an ID, a mention, or a more specific name.")
+    }
+}
+
+impl std::error::Error for Ambiguous {}
+impl_user_err_from!(Ambiguous);
+
+/// Parses a raw ID, or a mention starting with one of the given sigils (like `@!` for users).
+fn parse_snowflake(s: &str, sigils: &[&str]) -> Option<u64> {
+    let s = s.trim();
+    if let Ok(id) = s.parse() {
+        return Some(id);
+    }
+    let inner = s.strip_prefix('<')?.strip_suffix('>')?;
+    sigils
+        .iter()
+        .find_map(|sig| inner.strip_prefix(sig))
+        .and_then(|id| id.parse().ok())
+}
+
+/// Parses a raw user ID or a user mention, like `<@id>` or `<@!id>`.
+pub fn parse_user_id(s: &str) -> Option<UserId> {
+    parse_snowflake(s, &["@!", "@"]).map(UserId)
+}
+
+/// Parses a raw role ID or a role mention, like `<@&id>`.
+pub fn parse_role_id(s: &str) -> Option<RoleId> {
+    parse_snowflake(s, &["@&"]).map(RoleId)
+}
+
+/// Parses a raw channel ID or a channel mention, like `<#id>`.
+pub fn parse_channel_id(s: &str) -> Option<ChannelId> {
+    parse_snowflake(s, &["#"]).map(ChannelId)
+}
+
+/// Finds the entity a name refers to among candidates, each of which has one or more names; the
+/// first name is used to describe the candidate. Exact matches are preferred, then
+/// case-insensitive matches, then case-insensitive prefixes. Returns an error listing the matches
+/// if the best kind of match is shared by several candidates.
+pub fn resolve_by_name<T: Copy + fmt::Display>(
+    kind: &'static str,
+    query: &str,
+    candidates: &[(T, Vec<String>)],
+) -> Result<Option<T>, Ambiguous> {
+    if query.is_empty() {
+        return Ok(None);
+    }
+
+    let lower = query.to_lowercase();
+    let exact = |n: &str| n == query;
+    let insensitive = |n: &str| n.to_lowercase() == lower;
+    let prefix = |n: &str| n.to_lowercase().starts_with(&lower);
+    let tiers: [&dyn Fn(&str) -> bool; 3] = [&exact, &insensitive, &prefix];
+
+    for matches_name in &tiers {
+        let matches: Vec<&(T, Vec<String>)> = candidates
+            .iter()
+            .filter(|(_, names)| names.iter().any(|n| matches_name(n)))
+            .collect();
+        match matches.as_slice() {
+            [] => continue,
+            [(id, _)] => return Ok(Some(*id)),
+            _ => {
+                return Err(Ambiguous {
+                    kind,
+                    matches: matches
+                        .iter()
+                        .map(|(id, names)| format!("{} ({})", names.first().map_or("", String::as_str), id))
+                        .collect(),
+                })
+            }
+        }
+    }
+    Ok(None)
+}
+
 #[async_trait::async_trait]
 impl FromStrWithCtx for VerifiedRole {
     type Err = crate::error::Error;
 
     async fn from_str_with_ctx(s: &str, ctx: &Context, gid: GuildId) -> Result<Self, Self::Err> {
         let guild_info = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
-        let role_id = if let Ok(id) = RoleId::from_str(s) {
-            guild_info.roles.get(&id)
-        } else {
-            guild_info.role_by_name(s)
+        if let Some(id) = parse_role_id(s).filter(|id| guild_info.roles.contains_key(id)) {
+            return Ok(Self(id));
         }
-        .ok_or(NoSuchRole)?;
 
-        Ok(Self(role_id.id))
+        let candidates: Vec<_> = guild_info
+            .roles
+            .values()
+            .map(|r| (r.id, vec![r.name.clone()]))
+            .collect();
+        let role_id = resolve_by_name("role", s.trim(), &candidates)?.ok_or(NoSuchRole)?;
+        Ok(Self(role_id))
     }
 }
 
@@ -288,13 +453,17 @@ impl FromStrWithCtx for VerifiedChannel {
 
     async fn from_str_with_ctx(s: &str, ctx: &Context, gid: GuildId) -> Result<Self, Self::Err> {
         let guild_info = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
-        let chan_id = if let Ok(id) = ChannelId::from_str(s) {
-            guild_info.channels.get(&id).map(|c| c.id)
-        } else {
-            guild_info.channel_id_from_name(ctx, s).await
+        if let Some(id) = parse_channel_id(s).filter(|id| guild_info.channels.contains_key(id)) {
+            return Ok(Self(id));
         }
-        .ok_or(NoSuchChannel)?;
 
+        let candidates: Vec<_> = guild_info
+            .channels
+            .values()
+            .map(|c| (c.id, vec![c.name.clone()]))
+            .collect();
+        let name = s.trim().trim_start_matches('#');
+        let chan_id = resolve_by_name("channel", name, &candidates)?.ok_or(NoSuchChannel)?;
         Ok(Self(chan_id))
     }
 }
@@ -359,11 +528,7 @@ impl VerifiedUser {
     }
 }
 
-impl_err!(
-    NoSuchUser,
-    "No such user in guild, or two members have the same nickname.",
-    true
-);
+impl_err!(NoSuchUser, "No such user in this guild.", true);
 
 #[async_trait::async_trait]
 impl FromStrWithCtx for VerifiedUser {
@@ -371,14 +536,24 @@ impl FromStrWithCtx for VerifiedUser {
 
     async fn from_str_with_ctx(s: &str, ctx: &Context, gid: GuildId) -> Result<Self, Self::Err> {
         let guild = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
-        let uid: Member = if let Ok(id) = UserId::from_str(s) {
-            guild.member(ctx, id).await.ok()
-        } else {
-            guild.member_named(s).cloned()
+        if let Some(id) = parse_user_id(s) {
+            if let Ok(member) = guild.member(ctx, id).await {
+                return Ok(VerifiedUser(member.user.id));
+            }
         }
-        .ok_or(NoSuchUser)?;
 
-        Ok(VerifiedUser(uid.user.id))
+        let candidates: Vec<_> = guild
+            .members
+            .values()
+            .map(|m| {
+                let mut names = vec![m.user.name.clone(), m.user.tag()];
+                names.extend(m.nick.clone());
+                (m.user.id, names)
+            })
+            .collect();
+        let name = s.trim().trim_start_matches('@');
+        let uid = resolve_by_name("user", name, &candidates)?.ok_or(NoSuchUser)?;
+        Ok(VerifiedUser(uid))
     }
 }
 
@@ -387,3 +562,141 @@ impl fmt::Display for VerifiedUser {
         write!(f, "{}", self.0.mention())
     }
 }
+
+/// The most entries a [`MapValue`] may have.
+pub const MAX_MAP_ENTRIES: usize = 100;
+
+impl_err!(
+    InvalidMap,
+    "Expected a JSON object of up to 100 entries, like {\"key\": \"value\"}, with no repeated keys.",
+    true
+);
+
+/// A config value mapping keys to values, like levels to reward roles or filter names to actions.
+/// Both keys and values are validated as if they were set on their own, so a map of roles accepts
+/// role names and mentions just as a single role does. Entries keep the order they were given in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapValue<K, V>(Vec<(K, V)>);
+
+impl<K, V> MapValue<K, V> {
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        MapValue(Vec::new())
+    }
+
+    /// Iterates over the entries of the map, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.0.iter().map(|(k, v)| (k, v))
+    }
+
+    /// The number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<K: PartialEq, V> MapValue<K, V> {
+    /// Retrieves the value for a key, if the map has one.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
+impl<K, V> Default for MapValue<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Serialize, V: Serialize> Serialize for MapValue<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (k, v) in &self.0 {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for MapValue<K, V>
+where
+    K: Deserialize<'de> + PartialEq,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[doc(hidden)]
+        struct MapVisitor<K, V>(PhantomData<(K, V)>);
+
+        impl<'de, K, V> Visitor<'de> for MapVisitor<K, V>
+        where
+            K: Deserialize<'de> + PartialEq,
+            V: Deserialize<'de>,
+        {
+            type Value = MapValue<K, V>;
+
+            fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+                f.write_str("a map without repeated keys")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+                let mut entries: Vec<(K, V)> = Vec::with_capacity(access.size_hint().unwrap_or(0));
+                while let Some((k, v)) = access.next_entry()? {
+                    if entries.iter().any(|(e, _)| *e == k) {
+                        return Err(serde::de::Error::custom("repeated key"));
+                    }
+                    entries.push((k, v));
+                }
+                Ok(MapValue(entries))
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor(PhantomData))
+    }
+}
+
+#[async_trait::async_trait]
+impl<K, V> FromStrWithCtx for MapValue<K, V>
+where
+    K: FromStrWithCtx + PartialEq + Send,
+    V: FromStrWithCtx + Send,
+{
+    type Err = crate::error::Error;
+
+    async fn from_str_with_ctx(s: &str, ctx: &Context, gid: GuildId) -> Result<Self, Self::Err> {
+        let raw: serde_json::Map<String, serde_json::Value> = serde_json::from_str(s).map_err(|_| InvalidMap)?;
+        if raw.len() > MAX_MAP_ENTRIES {
+            return Err(InvalidMap.into());
+        }
+
+        let mut entries = Vec::with_capacity(raw.len());
+        for (k, v) in raw {
+            let key = K::from_str_with_ctx(&k, ctx, gid).await.into_user_err()?;
+            // Strings are parsed by their contents, anything else by its JSON.
+            let v = match v {
+                serde_json::Value::String(v) => v,
+                v => v.to_string(),
+            };
+            let value = V::from_str_with_ctx(&v, ctx, gid).await.into_user_err()?;
+            if entries.iter().any(|(e, _)| *e == key) {
+                return Err(InvalidMap.into());
+            }
+            entries.push((key, value));
+        }
+        Ok(MapValue(entries))
+    }
+}
+
+impl<K: fmt::Display, V: fmt::Display> fmt::Display for MapValue<K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "<empty>");
+        }
+        let lines: Vec<String> = self.0.iter().map(|(k, v)| format!("{} → {}", k, v)).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
@@ -0,0 +1,336 @@
+//! Contains a bounded background job queue, which lets hooks move heavy work (scanning, REST calls)
+//! off the event path. Jobs are prioritized, and within a priority, guilds take turns so one busy
+//! guild can't starve the rest. When the queue is full, each job's [`OverloadPolicy`] decides
+//! whether it's rejected or displaces older, less important work.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fmt::Formatter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::id::GuildId;
+use tokio::sync::Notify;
+
+use crate::dispatch::Dispatch;
+
+/// The maximum number of jobs which may be queued across all guilds.
+pub const JOB_QUEUE_CAPACITY: usize = 4096;
+/// The maximum number of jobs a single guild may have queued.
+pub const MAX_JOBS_PER_GUILD: usize = 256;
+/// The number of workers processing jobs concurrently.
+pub const JOB_WORKERS: usize = 4;
+/// How long a job may run before it's abandoned.
+pub const JOB_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often an idle worker checks whether Glimbot is shutting down.
+pub const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How urgently a job should be run. Higher priorities are always run first.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum JobPriority {
+    /// Work which can wait, like housekeeping.
+    Low = 0,
+    /// Most work.
+    Normal = 1,
+    /// Work which affects users directly, like removing a bad message.
+    High = 2,
+}
+
+impl JobPriority {
+    /// All priorities, most urgent first.
+    const DESCENDING: [JobPriority; 3] = [JobPriority::High, JobPriority::Normal, JobPriority::Low];
+}
+
+/// What to do with a job when the queue (or its guild's share of it) is full.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OverloadPolicy {
+    /// Drop the new job.
+    Reject,
+    /// Drop the oldest queued job of the same or lower priority to make room, rejecting the new
+    /// job only if there isn't one.
+    DropOldest,
+}
+
+/// A unit of work to be run off the event path.
+#[async_trait::async_trait]
+pub trait Job: Send + Sync + 'static {
+    /// A short name for the job, for logging.
+    fn name(&self) -> &'static str;
+
+    /// How urgently the job should be run.
+    fn priority(&self) -> JobPriority {
+        JobPriority::Normal
+    }
+
+    /// What to do with the job if the queue is full.
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::Reject
+    }
+
+    /// Runs the job.
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()>;
+}
+
+/// A job waiting in the queue.
+struct QueuedJob {
+    /// The guild the job is for.
+    guild: GuildId,
+    /// When the job was queued.
+    queued_at: Instant,
+    /// The job itself.
+    job: Box<dyn Job>,
+}
+
+/// The jobs of a single priority, grouped by guild.
+#[derive(Default)]
+struct Lane {
+    /// Each guild's queued jobs, oldest first.
+    guilds: HashMap<GuildId, VecDeque<QueuedJob>>,
+    /// The order in which guilds get their next turn.
+    turns: VecDeque<GuildId>,
+}
+
+impl Lane {
+    /// Adds a job to the back of its guild's queue.
+    fn push(&mut self, job: QueuedJob) {
+        let guild = job.guild;
+        let q = self.guilds.entry(guild).or_default();
+        if q.is_empty() {
+            self.turns.push_back(guild);
+        }
+        q.push_back(job);
+    }
+
+    /// Takes the next job from the guild whose turn it is.
+    fn pop(&mut self) -> Option<QueuedJob> {
+        let guild = self.turns.pop_front()?;
+        let q = self.guilds.get_mut(&guild)?;
+        let job = q.pop_front();
+        if q.is_empty() {
+            self.guilds.remove(&guild);
+        } else {
+            self.turns.push_back(guild);
+        }
+        job
+    }
+
+    /// Removes the oldest job for a guild.
+    fn pop_guild(&mut self, guild: GuildId) -> Option<QueuedJob> {
+        let q = self.guilds.get_mut(&guild)?;
+        let job = q.pop_front();
+        if q.is_empty() {
+            self.guilds.remove(&guild);
+            self.turns.retain(|g| *g != guild);
+        }
+        job
+    }
+
+    /// Removes the oldest job of any guild.
+    fn pop_oldest(&mut self) -> Option<QueuedJob> {
+        let guild = self
+            .guilds
+            .iter()
+            .filter_map(|(g, q)| q.front().map(|j| (j.queued_at, *g)))
+            .min()?
+            .1;
+        self.pop_guild(guild)
+    }
+}
+
+/// The queued jobs along with their bookkeeping.
+#[derive(Default)]
+struct Queues {
+    /// One lane per priority, indexed by the priority's value.
+    lanes: [Lane; 3],
+    /// The number of jobs queued per guild, across all lanes.
+    per_guild: HashMap<GuildId, usize>,
+    /// The total number of jobs queued.
+    len: usize,
+}
+
+impl Queues {
+    /// Adds a job, assuming there is room.
+    fn push(&mut self, job: QueuedJob) {
+        *self.per_guild.entry(job.guild).or_default() += 1;
+        self.len += 1;
+        self.lanes[job.job.priority() as usize].push(job);
+    }
+
+    /// Updates bookkeeping after a job was removed.
+    fn removed(&mut self, job: &QueuedJob) {
+        self.len -= 1;
+        if let Some(n) = self.per_guild.get_mut(&job.guild) {
+            *n -= 1;
+            if *n == 0 {
+                self.per_guild.remove(&job.guild);
+            }
+        }
+    }
+
+    /// Takes the most urgent job, letting guilds take turns within a priority.
+    fn pop(&mut self) -> Option<QueuedJob> {
+        let job = JobPriority::DESCENDING
+            .iter()
+            .find_map(|p| self.lanes[*p as usize].pop())?;
+        self.removed(&job);
+        Some(job)
+    }
+
+    /// Evicts the oldest job of at most the given priority, least urgent first, optionally
+    /// restricted to one guild.
+    fn evict(&mut self, max: JobPriority, guild: Option<GuildId>) -> Option<QueuedJob> {
+        let job = JobPriority::DESCENDING
+            .iter()
+            .rev()
+            .filter(|p| **p <= max)
+            .find_map(|p| {
+                let lane = &mut self.lanes[*p as usize];
+                match guild {
+                    Some(g) => lane.pop_guild(g),
+                    None => lane.pop_oldest(),
+                }
+            })?;
+        self.removed(&job);
+        Some(job)
+    }
+}
+
+/// Counters describing the queue's activity since startup.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct JobStats {
+    /// The number of jobs currently queued.
+    pub queued: usize,
+    /// The number of jobs which ran to completion.
+    pub completed: u64,
+    /// The number of jobs which failed or timed out.
+    pub failed: u64,
+    /// The number of jobs which were rejected or evicted because the queue was full.
+    pub dropped: u64,
+}
+
+impl fmt::Display for JobStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} queued, {} done, {} failed, {} dropped",
+            self.queued, self.completed, self.failed, self.dropped
+        )
+    }
+}
+
+/// A bounded, in-memory queue of background jobs. Jobs in this queue are lost if Glimbot restarts.
+#[derive(Default)]
+pub struct JobQueue {
+    #[doc(hidden)]
+    queues: Mutex<Queues>,
+    /// Wakes idle workers when a job is queued.
+    notify: Arc<Notify>,
+    #[doc(hidden)]
+    completed: AtomicU64,
+    #[doc(hidden)]
+    failed: AtomicU64,
+    #[doc(hidden)]
+    dropped: AtomicU64,
+}
+
+impl JobQueue {
+    /// Queues a job for a guild. Returns false if the job was rejected because the queue is full.
+    pub fn enqueue(&self, guild: GuildId, job: impl Job) -> bool {
+        let job = QueuedJob {
+            guild,
+            queued_at: Instant::now(),
+            job: Box::new(job),
+        };
+        let priority = job.job.priority();
+        let policy = job.job.overload_policy();
+
+        let mut q = self.queues.lock();
+        let guild_full = q.per_guild.get(&guild).copied().unwrap_or_default() >= MAX_JOBS_PER_GUILD;
+        let queue_full = q.len >= JOB_QUEUE_CAPACITY;
+
+        if guild_full || queue_full {
+            // A guild which has used up its share may only displace its own work.
+            let evicted = match policy {
+                OverloadPolicy::Reject => None,
+                OverloadPolicy::DropOldest => q.evict(priority, if guild_full { Some(guild) } else { None }),
+            };
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            match evicted {
+                Some(old) => warn!(
+                    "job queue full; dropped {} for {} to make room for {}",
+                    old.job.name(),
+                    old.guild,
+                    job.job.name()
+                ),
+                None => {
+                    warn!("job queue full; rejected {} for {}", job.job.name(), guild);
+                    return false;
+                }
+            }
+        }
+
+        trace!("queued {} for {}", job.job.name(), guild);
+        q.push(job);
+        std::mem::drop(q);
+        self.notify.notify_one();
+        true
+    }
+
+    /// Takes the next job to run.
+    fn pop(&self) -> Option<QueuedJob> {
+        self.queues.lock().pop()
+    }
+
+    /// Retrieves statistics about the queue.
+    pub fn statistics(&self) -> JobStats {
+        JobStats {
+            queued: self.queues.lock().len,
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Runs a single job, recording its outcome.
+    async fn run(&self, dis: &Dispatch, ctx: &Context, job: QueuedJob) {
+        let name = job.job.name();
+        let guild = job.guild;
+        trace!(
+            "running {} for {} after {:?} in queue",
+            name,
+            guild,
+            job.queued_at.elapsed()
+        );
+
+        match tokio::time::timeout(JOB_TIMEOUT, job.job.run(dis, ctx)).await {
+            Ok(Ok(())) => {
+                self.completed.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(Err(e)) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                warn!("job {} for {} failed: {}", name, guild, e);
+            }
+            Err(_) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                warn!("job {} for {} timed out", name, guild);
+            }
+        }
+    }
+}
+
+/// Runs jobs from the queue until Dispatch is dropped.
+pub async fn run_worker(dispatch: Weak<Dispatch>, ctx: Context) {
+    while let Some(d) = dispatch.upgrade() {
+        match d.jobs().pop() {
+            Some(job) => d.jobs().run(&d, &ctx, job).await,
+            None => {
+                let notify = d.jobs().notify.clone();
+                std::mem::drop(d); // Avoid holding Dispatch while idle.
+                let _ = tokio::time::timeout(IDLE_CHECK_INTERVAL, notify.notified()).await;
+            }
+        }
+    }
+}
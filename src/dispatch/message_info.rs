@@ -2,6 +2,8 @@ use chrono::Utc;
 use serenity::model::id::{ChannelId, MessageId, UserId};
 use serenity::model::prelude::Message;
 use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 #[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub struct MsgInfo {
@@ -9,6 +11,31 @@ pub struct MsgInfo {
     pub user: UserId,
     pub channel: ChannelId,
     pub msg: MessageId,
+    /// A hash of the message's normalized content, used to spot repeated messages. Only
+    /// meaningful within a single run of Glimbot.
+    pub content_hash: u64,
+}
+
+/// Hashes a message's content, ignoring case, how words are spaced out, and invisible characters
+/// like zero-width spaces, so trivially altered copies hash the same.
+pub fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content
+        .split_whitespace()
+        .map(|w| {
+            w.chars()
+                .filter(|c| !c.is_control() && !is_invisible(*c))
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|w| !w.is_empty())
+        .for_each(|w| w.hash(&mut hasher));
+    hasher.finish()
+}
+
+/// Checks for characters which take up no space, which are often used to dodge duplicate checks.
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200b}'..='\u{200f}' | '\u{2060}'..='\u{2064}' | '\u{feff}' | '\u{00ad}')
 }
 
 impl<BM: Borrow<Message>> From<BM> for MsgInfo {
@@ -19,6 +46,7 @@ impl<BM: Borrow<Message>> From<BM> for MsgInfo {
             user: m.author.id,
             channel: m.channel_id,
             msg: m.id,
+            content_hash: content_hash(&m.content),
         }
     }
 }
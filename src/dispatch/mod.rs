@@ -10,42 +10,63 @@ use std::time::Instant;
 
 use futures::stream;
 use futures::stream::StreamExt;
-use futures::TryStreamExt;
+use futures::{FutureExt, TryStreamExt};
 
 use once_cell::sync::{OnceCell};
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use serenity::client::bridge::gateway::ShardManager;
 use serenity::client::{Context, EventHandler};
-use serenity::model::channel::Message;
+use serenity::model::channel::{GuildChannel, Message, Reaction};
 use serenity::model::gateway::{Activity, Ready};
+use serenity::model::guild::Member;
 use serenity::model::id::{GuildId, UserId};
+use serenity::model::interactions::Interaction;
+use serenity::model::user::{OnlineStatus, User};
+use serenity::model::voice::VoiceState;
 use serenity::prelude::TypeMapKey;
 use serenity::utils::MessageBuilder;
-use sqlx::PgPool;
 use tokio::sync::{watch, Mutex};
 use tracing::Instrument;
 
 use crate::db::cache::TimedCache;
+use crate::db::global::GlobalConfig;
+use crate::db::health::{DbHealth, DbState};
+use crate::db::instance::InstanceDefaults;
+use crate::db::instrumented::{InstrumentedPool, QueryStats};
+use crate::db::operators::Operators;
+use crate::db::phishing::PhishingDomains;
+use crate::db::storage::{NeedsPostgres, PostgresStorage, Storage};
 use crate::db::timed::TimedEvents;
+use crate::db::write_buffer::WriteBuffer;
 use crate::db::{ConfigCache, DbContext};
 use crate::dispatch::config::ValueType;
+use crate::dispatch::jobs::{JobQueue, JOB_WORKERS};
 use crate::dispatch::message_info::MsgInfo;
+use crate::dispatch::rest::RestHealth;
+use crate::dispatch::results::{process_cached, ResultCache};
 use crate::error::{LogErrorExt, SysError, UserError};
+use crate::module::persona::report_error;
+use crate::module::scoring::ScoreBoard;
 use crate::module::Module;
 use crate::util::ordset::OrdSet;
 use std::num::NonZeroUsize;
 
 pub mod config;
+pub mod jobs;
 pub mod message_info;
+pub mod rest;
+pub mod results;
 
 pub const PER_GUILD_MESSAGE_CACHE_SIZE: usize = 4096;
+/// The activity shown when the bot owner hasn't set one.
+pub const DEFAULT_ACTIVITY: &str = "Cultist Simulator";
 
 /// The primary dispatch state holder. Contains information on the various modules
 /// and filters installed in Glimbot.
 pub struct Dispatch {
-    /// The bot owner.
-    owner: UserId,
+    /// The owners and operators of this instance.
+    operators: Operators,
     /// Filters which are applied to each message.
     filters: Vec<Arc<dyn Module>>,
     /// Modules containing some combination of commands and filters.
@@ -54,16 +75,59 @@ pub struct Dispatch {
     message_hooks: Vec<Arc<dyn Module>>,
     /// Modules containing tick-based hooks
     tick_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing member join hooks.
+    member_join_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing reaction hooks.
+    reaction_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing component interaction hooks.
+    component_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing ban hooks.
+    ban_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing member removal hooks.
+    member_remove_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing member update hooks.
+    member_update_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing voice state hooks.
+    voice_state_hooks: Vec<Arc<dyn Module>>,
+    /// Modules containing thread creation hooks.
+    thread_create_hooks: Vec<Arc<dyn Module>>,
+    /// The command unknown commands are passed to, if any.
+    command_fallback: Option<&'static str>,
     /// Config value validators for the configuration values set in each guild.
     config_values: BTreeMap<&'static str, Arc<dyn config::Validator>>,
-    /// Database connection pool.
-    pool: PgPool,
+    /// Where glimbot's data is kept.
+    storage: Box<dyn Storage>,
     /// The background service, initialized on first start.
     background_service: OnceCell<Arc<BackgroundService>>,
     config_cache: ConfigCache,
+    /// Config writes waiting to be flushed to the database.
+    config_writes: WriteBuffer,
+    /// Tracks whether the database is reachable.
+    db_health: DbHealth,
+    /// Config defaults set by the bot operator for every guild.
+    instance_defaults: InstanceDefaults,
+    /// Settings for the whole instance, like the bot's activity.
+    global_config: GlobalConfig,
+    /// Known phishing domains, refreshed by the background service.
+    phishing_domains: PhishingDomains,
+    /// Tallies of messages being scored by the filters.
+    score_board: ScoreBoard,
     message_cache: TimedCache<GuildId, OrdSet<MsgInfo>>,
     bot_id_channels: (watch::Sender<Option<UserId>>, watch::Receiver<Option<UserId>>),
     bot_id_local: thread_local::ThreadLocal<Mutex<watch::Receiver<Option<UserId>>>>,
+    /// Tracks the health of the Discord REST API and operations waiting to be retried.
+    rest: RestHealth,
+    /// Work queued by hooks to be run off the event path.
+    jobs: JobQueue,
+    /// Rendered replies to cacheable commands.
+    results: ResultCache,
+}
+
+impl Dispatch {
+    /// Accessor for the Discord REST API health tracker.
+    pub fn rest(&self) -> &RestHealth {
+        &self.rest
+    }
 }
 
 impl Dispatch {
@@ -93,12 +157,89 @@ impl Dispatch {
     pub fn config_cache(&self) -> &ConfigCache {
         &self.config_cache
     }
+
+    /// Accessor for the config writes waiting to be flushed to the database.
+    pub fn config_writes(&self) -> &WriteBuffer {
+        &self.config_writes
+    }
+
+    /// Accessor for the database health tracker.
+    pub fn db_health(&self) -> &DbHealth {
+        &self.db_health
+    }
+}
+
+impl Dispatch {
+    /// Accessor for the background job queue.
+    pub fn jobs(&self) -> &JobQueue {
+        &self.jobs
+    }
+
+    /// Accessor for the cache of rendered command results.
+    pub fn results(&self) -> &ResultCache {
+        &self.results
+    }
+}
+
+impl Dispatch {
+    /// Accessor for the config defaults set by the bot operator.
+    pub fn instance_defaults(&self) -> &InstanceDefaults {
+        &self.instance_defaults
+    }
+
+    /// Accessor for the settings for the whole instance.
+    pub fn global_config(&self) -> &GlobalConfig {
+        &self.global_config
+    }
+
+    /// The activity and status the bot should show, per the global settings.
+    pub fn presence(&self) -> (Activity, OnlineStatus) {
+        let activity = self
+            .global_config
+            .activity()
+            .unwrap_or_else(|| DEFAULT_ACTIVITY.to_string());
+        let status = self.global_config.status().unwrap_or(OnlineStatus::Online);
+        (Activity::playing(&activity), status)
+    }
+
+    /// Accessor for the known phishing domains.
+    pub fn phishing_domains(&self) -> &PhishingDomains {
+        &self.phishing_domains
+    }
+
+    /// Accessor for the tallies of messages being scored by the filters.
+    pub fn score_board(&self) -> &ScoreBoard {
+        &self.score_board
+    }
 }
 
 impl Dispatch {
-    /// Gets a reference to the DB pool.
-    pub fn pool(&self) -> &PgPool {
-        &self.pool
+    /// Gets a reference to the storage backend.
+    pub fn storage(&self) -> &dyn Storage {
+        self.storage.as_ref()
+    }
+
+    /// Gets a reference to the Postgres pool. Errors with [`NeedsPostgres`] if the data is kept
+    /// elsewhere.
+    pub fn pool(&self) -> crate::error::Result<&InstrumentedPool> {
+        self.storage
+            .postgres()
+            .map(PostgresStorage::pool)
+            .ok_or_else(|| NeedsPostgres.into())
+    }
+
+    /// Gets a reference to the Postgres pool for read-only queries. This is the read replica if one
+    /// is configured, and the primary otherwise.
+    pub fn read_pool(&self) -> crate::error::Result<&InstrumentedPool> {
+        self.storage
+            .postgres()
+            .map(PostgresStorage::read_pool)
+            .ok_or_else(|| NeedsPostgres.into())
+    }
+
+    /// Accessor for the timing recorded for queries run against the storage backend.
+    pub fn query_stats(&self) -> &QueryStats {
+        self.storage.query_stats()
     }
 }
 
@@ -126,12 +267,12 @@ impl TypeMapKey for ShardManKey {
 }
 
 impl Dispatch {
-    /// Get the owner of this instance of Glimbot.
-    pub fn owner(&self) -> UserId {
-        self.owner
+    /// Accessor for the owners and operators of this instance of Glimbot.
+    pub fn operators(&self) -> &Operators {
+        &self.operators
     }
     /// Convenience function for constructing a DbContext with the pool in this Dispatch.
-    pub fn db(&self, gid: GuildId) -> DbContext {
+    pub fn db(&self, gid: GuildId) -> DbContext<'_> {
         DbContext::new(self, gid)
     }
 }
@@ -166,21 +307,39 @@ impl_err!(
 );
 
 impl Dispatch {
-    /// Creates an empty dispatch with the given pool and owner.
-    pub fn new(owner: UserId, pool: PgPool) -> Self {
+    /// Creates an empty dispatch with the given storage backend and operator team.
+    pub fn new(operators: Operators, storage: Box<dyn Storage>) -> Self {
         Self {
-            owner,
+            operators,
             filters: Vec::new(),
             modules: Default::default(),
             message_hooks: vec![],
             tick_hooks: vec![],
+            member_join_hooks: vec![],
+            reaction_hooks: vec![],
+            component_hooks: vec![],
+            ban_hooks: vec![],
+            member_remove_hooks: vec![],
+            member_update_hooks: vec![],
+            voice_state_hooks: vec![],
+            thread_create_hooks: vec![],
+            command_fallback: None,
             config_values: Default::default(),
             background_service: Default::default(),
-            pool,
+            storage,
             config_cache: ConfigCache::default(),
+            config_writes: WriteBuffer::default(),
+            db_health: DbHealth::default(),
+            instance_defaults: InstanceDefaults::default(),
+            global_config: GlobalConfig::default(),
+            phishing_domains: PhishingDomains::default(),
+            score_board: ScoreBoard::default(),
             message_cache: TimedCache::new(chrono::Duration::days(7).to_std().unwrap()),
             bot_id_channels: watch::channel(None),
             bot_id_local: Default::default(),
+            rest: Default::default(),
+            jobs: Default::default(),
+            results: Default::default(),
         }
     }
 
@@ -207,6 +366,51 @@ impl Dispatch {
             self.tick_hooks.push(a.clone());
         }
 
+        if inf.on_member_join {
+            info!("has member join hook");
+            self.member_join_hooks.push(a.clone());
+        }
+
+        if inf.on_reaction {
+            info!("has reaction hook");
+            self.reaction_hooks.push(a.clone());
+        }
+
+        if inf.on_component {
+            info!("has component hook");
+            self.component_hooks.push(a.clone());
+        }
+
+        if inf.on_ban {
+            info!("has ban hook");
+            self.ban_hooks.push(a.clone());
+        }
+
+        if inf.on_member_remove {
+            info!("has member removal hook");
+            self.member_remove_hooks.push(a.clone());
+        }
+
+        if inf.on_member_update {
+            info!("has member update hook");
+            self.member_update_hooks.push(a.clone());
+        }
+
+        if inf.on_voice_state {
+            info!("has voice state hook");
+            self.voice_state_hooks.push(a.clone());
+        }
+
+        if inf.on_thread_create {
+            info!("has thread create hook");
+            self.thread_create_hooks.push(a.clone());
+        }
+
+        if inf.command_fallback && self.command_fallback.is_none() {
+            info!("handles unknown commands");
+            self.command_fallback = Some(inf.name);
+        }
+
         for v in &inf.config_values {
             info!("adds config value {}", v.name());
             self.config_values.insert(v.name(), v.clone());
@@ -216,6 +420,37 @@ impl Dispatch {
         self.modules.insert(inf.name, a);
     }
 
+    /// Iterates over every installed module, in order of name.
+    pub fn modules(&self) -> impl Iterator<Item = &dyn Module> + '_ {
+        self.modules.values().map(|m| m.as_ref())
+    }
+
+    /// Has every module forget what it holds in memory about a user, after their stored data has
+    /// been purged.
+    pub fn forget_user(&self, user: UserId) {
+        for m in self.modules.values() {
+            m.forget_user(user);
+        }
+    }
+
+    /// Forgets cached config, recent messages and whatever modules cache, for one guild or for
+    /// every guild if `None`, so stale state can be dropped without a restart.
+    pub fn flush_caches(&self, guild: Option<GuildId>) {
+        match guild {
+            Some(g) => {
+                self.config_cache.invalidate_guild(g);
+                self.message_cache.remove(&g);
+            }
+            None => {
+                self.config_cache.clear();
+                self.message_cache.clear();
+            }
+        }
+        for m in self.modules.values() {
+            m.flush_caches(guild);
+        }
+    }
+
     /// Retrieves a module by name.
     pub fn module(&self, name: &str) -> Option<&dyn Module> {
         self.modules.get(name).map(|r| r.as_ref())
@@ -266,16 +501,20 @@ impl Dispatch {
         }
 
         self.message_cache
-            .get_or_insert_sync(&guild, || OrdSet::new(NonZeroUsize::new(PER_GUILD_MESSAGE_CACHE_SIZE)))
+            .get_or_insert(&guild, || OrdSet::new(NonZeroUsize::new(PER_GUILD_MESSAGE_CACHE_SIZE)))
             .insert(new_message.into());
 
-        stream::iter(self.message_hooks.iter())
+        let hooks = stream::iter(self.message_hooks.iter())
             .map(Ok)
             .try_for_each(|m| {
                 m.on_message(self, ctx, new_message)
                     .instrument(debug_span!("applying msg hook", h=%m.info().name))
             })
-            .await?;
+            .await;
+        crate::module::scoring::finish_hooks(self, new_message)
+            .await
+            .log_error();
+        hooks?;
 
         let first_bit = if let Some(c) = contents.chars().next() {
             c
@@ -305,6 +544,21 @@ impl Dispatch {
             return Ok(()); // The message was just the command prefix, and not actually a command.
         };
 
+        // While down for maintenance, only the operator team can run commands.
+        if let Some(notice) = self.global_config.maintenance_message() {
+            if self.operators().tier(ctx, new_message.author.id).await.is_none() {
+                new_message.reply(ctx, notice).await?;
+                return Ok(());
+            }
+        }
+
+        // Unknown commands are passed on to the fallback command, if there is one, so `!rules`
+        // runs as `!tag rules`.
+        let (cmd_name, fallback) = match self.command_fallback {
+            Some(f) if self.command_module(cmd_name).is_err() => (f, true),
+            _ => (cmd_name, false),
+        };
+
         let cmd = stream::iter(self.filters.iter())
             .map(Result::Ok)
             .try_fold(cmd_name.to_string(), |acc, f: &Arc<dyn Module>| {
@@ -319,16 +573,41 @@ impl Dispatch {
             #[allow(deprecated)]
             return Err(UserError::new(format!("Invalid command string: {}", &contents)).into());
         };
+        if fallback {
+            command.insert(0, String::new());
+        }
         command[0] = cmd;
         let name = cmd_name;
         let cmd_mod = self.command_module(name)?;
-        cmd_mod
-            .process(self, ctx, &new_message, command)
-            .instrument(info_span!("running command", c=%cmd_mod.info().name))
-            .await?;
+        match cmd_mod.info().result_cache {
+            Some(ttl) => {
+                process_cached(self, ctx, new_message, cmd_mod, command, ttl)
+                    .instrument(info_span!("running command", c=%cmd_mod.info().name))
+                    .await?
+            }
+            None => {
+                cmd_mod
+                    .process(self, ctx, new_message, command)
+                    .instrument(info_span!("running command", c=%cmd_mod.info().name))
+                    .await?
+            }
+        }
 
+        // Commands reply through the REST API, so one going through means Discord is answering.
+        self.rest.breaker().record_success();
         Ok(())
     }
+
+    /// Runs the tick hooks of every module which has one. Called by the background service.
+    pub async fn tick(&self, ctx: &Context) {
+        stream::iter(self.tick_hooks.iter())
+            .for_each(|m| {
+                m.on_tick(self, ctx)
+                    .instrument(debug_span!("applying tick hook", h=%m.info().name))
+                    .map(|r| r.log_error())
+            })
+            .await;
+    }
 }
 
 #[async_trait::async_trait]
@@ -340,21 +619,35 @@ impl EventHandler for Dispatch {
 
         res.log_error();
         if let Err(e) = res {
-            let mb = if e.is_user_error() {
-                MessageBuilder::new()
-                    .push_codeblock_safe(format!("{}", e), None)
-                    .build()
+            if e.is_transient() {
+                self.rest.breaker().record_failure();
+            }
+
+            if self.rest.breaker().allows_non_essential() {
+                let mb = if e.is_user_error() {
+                    MessageBuilder::new()
+                        .push_codeblock_safe(format!("{}", e), None)
+                        .build()
+                } else {
+                    MessageBuilder::new()
+                        .push_codeblock_safe(
+                            "An internal error occurred. If this continues, please contact the bot owner.",
+                            None,
+                        )
+                        .build()
+                };
+
+                match report_error(self, &ctx, &new_message, mb).await {
+                    Ok(()) => self.rest.breaker().record_success(),
+                    Err(e) => {
+                        if e.is_transient() {
+                            self.rest.breaker().record_failure();
+                        }
+                        error!("Failed while sending error message: {}", e);
+                    }
+                }
             } else {
-                MessageBuilder::new()
-                    .push_codeblock_safe(
-                        "An internal error occurred. If this continues, please contact the bot owner.",
-                        None,
-                    )
-                    .build()
-            };
-
-            if let Err(e) = new_message.reply(&ctx, mb).await {
-                error!("Failed while sending error message: {}", e);
+                debug!("Discord API degraded; not replying with error.");
             }
         }
 
@@ -362,13 +655,119 @@ impl EventHandler for Dispatch {
         debug!("Processing took {:?}", elapsed);
     }
 
+    #[instrument(level = "info", skip(self, ctx, new_member), fields(g = % guild_id, u = % new_member.user.id))]
+    async fn guild_member_addition(&self, ctx: Context, guild_id: GuildId, new_member: Member) {
+        stream::iter(self.member_join_hooks.iter())
+            .for_each(|m| {
+                m.on_member_join(self, &ctx, &new_member)
+                    .instrument(debug_span!("applying member join hook", h=%m.info().name))
+                    .map(|r| r.log_error())
+            })
+            .await;
+    }
+
+    #[instrument(level = "debug", skip(self, ctx, add_reaction), fields(m = % add_reaction.message_id))]
+    async fn reaction_add(&self, ctx: Context, add_reaction: Reaction) {
+        if add_reaction.guild_id.is_none() || add_reaction.user_id == Some(ctx.cache.current_user_id().await) {
+            return;
+        }
+
+        stream::iter(self.reaction_hooks.iter())
+            .for_each(|m| {
+                m.on_reaction(self, &ctx, &add_reaction)
+                    .instrument(debug_span!("applying reaction hook", h=%m.info().name))
+                    .map(|r| r.log_error())
+            })
+            .await;
+    }
+
+    #[instrument(level = "debug", skip(self, ctx, interaction), fields(id = % interaction.id()))]
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let component = match interaction {
+            Interaction::MessageComponent(c) if c.guild_id.is_some() => c,
+            _ => return,
+        };
+
+        stream::iter(self.component_hooks.iter())
+            .for_each(|m| {
+                m.on_component(self, &ctx, &component)
+                    .instrument(debug_span!("applying component hook", h=%m.info().name))
+                    .map(|r| r.log_error())
+            })
+            .await;
+    }
+
+    #[instrument(level = "info", skip(self, ctx, banned_user), fields(g = % guild_id, u = % banned_user.id))]
+    async fn guild_ban_addition(&self, ctx: Context, guild_id: GuildId, banned_user: User) {
+        stream::iter(self.ban_hooks.iter())
+            .for_each(|m| {
+                m.on_ban(self, &ctx, guild_id, &banned_user)
+                    .instrument(debug_span!("applying ban hook", h=%m.info().name))
+                    .map(|r| r.log_error())
+            })
+            .await;
+    }
+
+    #[instrument(level = "info", skip(self, ctx, user, member), fields(g = % guild_id, u = % user.id))]
+    async fn guild_member_removal(&self, ctx: Context, guild_id: GuildId, user: User, member: Option<Member>) {
+        stream::iter(self.member_remove_hooks.iter())
+            .for_each(|m| {
+                m.on_member_remove(self, &ctx, guild_id, &user, member.as_ref())
+                    .instrument(debug_span!("applying member removal hook", h=%m.info().name))
+                    .map(|r| r.log_error())
+            })
+            .await;
+    }
+
+    #[instrument(level = "debug", skip(self, ctx, old, new), fields(g = % new.guild_id, u = % new.user.id))]
+    async fn guild_member_update(&self, ctx: Context, old: Option<Member>, new: Member) {
+        stream::iter(self.member_update_hooks.iter())
+            .for_each(|m| {
+                m.on_member_update(self, &ctx, old.as_ref(), &new)
+                    .instrument(debug_span!("applying member update hook", h=%m.info().name))
+                    .map(|r| r.log_error())
+            })
+            .await;
+    }
+
+    async fn voice_state_update(
+        &self,
+        ctx: Context,
+        guild_id: Option<GuildId>,
+        old: Option<VoiceState>,
+        new: VoiceState,
+    ) {
+        let guild_id = match guild_id.or(new.guild_id) {
+            Some(g) => g,
+            None => return,
+        };
+        stream::iter(self.voice_state_hooks.iter())
+            .for_each(|m| {
+                m.on_voice_state(self, &ctx, guild_id, old.as_ref(), &new)
+                    .instrument(debug_span!("applying voice state hook", h=%m.info().name))
+                    .map(|r| r.log_error())
+            })
+            .await;
+    }
+
+    async fn thread_create(&self, ctx: Context, thread: GuildChannel) {
+        stream::iter(self.thread_create_hooks.iter())
+            .for_each(|m| {
+                m.on_thread_create(self, &ctx, &thread)
+                    .instrument(debug_span!("applying thread create hook", h=%m.info().name))
+                    .map(|r| r.log_error())
+            })
+            .await;
+    }
+
     async fn ready(&self, ctx: Context, rdy: Ready) {
         self.bot_id_channels
             .0
             .send(Some(rdy.user.id))
             .expect("All receivers dropped?");
         info!("up and running in {} guilds.", rdy.guilds.len());
-        ctx.set_activity(Activity::playing("Cultist Simulator")).await;
+        let (activity, status) = self.presence();
+        ctx.set_presence(Some(activity), status).await;
     }
 }
 
@@ -392,6 +791,8 @@ struct BackgroundService {
     ctx: Context,
     /// Set on first start.
     started: AtomicBool,
+    /// The day member snapshots were last taken.
+    last_snapshot: parking_lot::Mutex<Option<chrono::NaiveDate>>,
 }
 
 impl BackgroundService {
@@ -403,21 +804,63 @@ impl BackgroundService {
             return;
         }
 
+        for _ in 0..JOB_WORKERS {
+            tokio::task::spawn(crate::dispatch::jobs::run_worker(
+                self.dispatch.clone(),
+                self.ctx.clone(),
+            ));
+        }
+
+        // Only Postgres announces config changes; nothing else shares an SQLite database.
+        if self
+            .dispatch
+            .upgrade()
+            .map_or(false, |d| d.storage().postgres().is_some())
+        {
+            tokio::task::spawn(crate::db::watch_config_changes(self.dispatch.clone()));
+        }
+
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(15));
         interval.tick().await; // Avoid waiting while we're holding the pointer to Dispatch.
 
         while let Some(d) = self.dispatch.upgrade() {
-            self.process_events(&d).await.log_error();
+            if self.check_db(&d).await == DbState::Healthy {
+                self.process_events(&d).await.log_error();
+                // Snapshots and the phishing domain list are kept in Postgres.
+                if d.storage().postgres().is_some() {
+                    self.snapshot_if_due(&d).await.log_error();
+                    self.refresh_phishing_if_due(&d).await.log_error();
+                }
+                d.config_writes().flush(d.storage()).await.log_error();
+            }
+            d.tick(&self.ctx).await;
+            d.rest().process_retries(&d, &self.ctx).await;
             std::mem::drop(d); // Manually drop to avoid holding while we wait.
             interval.tick().await;
         }
     }
 
+    /// Checks the database's health. When it comes back after an outage, the config writes queued
+    /// meanwhile are replayed, and the config cache is cleared, since changes made by other
+    /// processes may have been missed.
+    pub async fn check_db(&self, dis: &Dispatch) -> DbState {
+        let was_degraded = dis.db_health().is_degraded();
+        let state = dis.db_health().check(dis.storage()).await;
+        if was_degraded && state == DbState::Healthy {
+            match dis.config_writes().flush(dis.storage()).await {
+                Ok(n) => info!("replayed {} config writes queued during the outage", n),
+                Err(e) => error!("couldn't replay config writes queued during the outage: {}", e),
+            }
+            dis.config_cache().clear();
+        }
+        state
+    }
+
     /// Processes timed events from the database.
     #[instrument(level = "info", skip(self, dis))]
     pub async fn process_events(&self, dis: &Dispatch) -> crate::error::Result<()> {
         let mut batch =
-            TimedEvents::get_actions_before(dis.pool(), chrono::DateTime::from(chrono::Local::now())).await?;
+            TimedEvents::get_actions_before(dis.storage(), chrono::DateTime::from(chrono::Local::now())).await?;
 
         // Avoid a long sequence of the same guild from bulk actions
         batch.shuffle(&mut thread_rng());
@@ -433,6 +876,26 @@ impl BackgroundService {
 
         Ok(())
     }
+
+    /// Takes member snapshots for growth tracking if none have been taken today.
+    pub async fn snapshot_if_due(&self, dis: &Dispatch) -> crate::error::Result<()> {
+        let today = chrono::Utc::today().naive_utc();
+        if *self.last_snapshot.lock() == Some(today) {
+            return Ok(());
+        }
+
+        crate::module::growth::take_snapshots(dis, &self.ctx).await?;
+        *self.last_snapshot.lock() = Some(today);
+        Ok(())
+    }
+
+    /// Refreshes the phishing domain list from its feed if it's due.
+    pub async fn refresh_phishing_if_due(&self, dis: &Dispatch) -> crate::error::Result<()> {
+        if let Some(count) = dis.phishing_domains().refresh_if_due(dis.pool()?).await? {
+            info!("refreshed phishing domain list with {} domains", count);
+        }
+        Ok(())
+    }
 }
 
 #[async_trait::async_trait]
@@ -444,6 +907,7 @@ impl EventHandler for ArcDispatch {
                 dispatch: Arc::downgrade(self.as_ref()),
                 ctx,
                 started: Default::default(),
+                last_snapshot: Default::default(),
             }
             .into()
         });
@@ -456,6 +920,44 @@ impl EventHandler for ArcDispatch {
         self.0.message(ctx, new_message).await
     }
 
+    async fn guild_member_addition(&self, ctx: Context, guild_id: GuildId, new_member: Member) {
+        self.0.guild_member_addition(ctx, guild_id, new_member).await
+    }
+
+    async fn reaction_add(&self, ctx: Context, add_reaction: Reaction) {
+        self.0.reaction_add(ctx, add_reaction).await
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        self.0.interaction_create(ctx, interaction).await
+    }
+
+    async fn guild_ban_addition(&self, ctx: Context, guild_id: GuildId, banned_user: User) {
+        self.0.guild_ban_addition(ctx, guild_id, banned_user).await
+    }
+
+    async fn guild_member_removal(&self, ctx: Context, guild_id: GuildId, user: User, member: Option<Member>) {
+        self.0.guild_member_removal(ctx, guild_id, user, member).await
+    }
+
+    async fn guild_member_update(&self, ctx: Context, old: Option<Member>, new: Member) {
+        self.0.guild_member_update(ctx, old, new).await
+    }
+
+    async fn voice_state_update(
+        &self,
+        ctx: Context,
+        guild_id: Option<GuildId>,
+        old: Option<VoiceState>,
+        new: VoiceState,
+    ) {
+        self.0.voice_state_update(ctx, guild_id, old, new).await
+    }
+
+    async fn thread_create(&self, ctx: Context, thread: GuildChannel) {
+        self.0.thread_create(ctx, thread).await
+    }
+
     async fn ready(&self, ctx: Context, rdy: Ready) {
         self.0.ready(ctx, rdy).await
     }
@@ -0,0 +1,361 @@
+//! Contains logic for coping with a degraded Discord API, namely a circuit breaker which pauses
+//! non-essential outbound traffic and a retry queue for idempotent REST operations.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fmt::Formatter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::http::HttpError;
+use serenity::model::id::{GuildId, RoleId, UserId};
+
+use crate::dispatch::Dispatch;
+
+/// The number of transient failures within [`FAILURE_WINDOW`] after which the circuit breaker
+/// opens.
+pub const DEFAULT_FAILURE_THRESHOLD: u64 = 5;
+/// How long a transient failure counts towards opening the circuit breaker, so failures spread
+/// out over days can't add up to an outage.
+pub const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+/// How long the circuit breaker stays open before allowing a probe request through.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+/// The delay before the first retry of a failed operation. Doubled for each subsequent attempt.
+pub const BASE_RETRY_DELAY: Duration = Duration::from_secs(15);
+/// The maximum delay between retries.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+/// The number of times an operation will be retried before it is dropped.
+pub const MAX_RETRY_ATTEMPTS: u32 = 8;
+/// The maximum number of operations which may be waiting to be retried.
+pub const RETRY_QUEUE_CAPACITY: usize = 4096;
+
+/// Returns true if a serenity error indicates that Discord is unreachable or degraded, i.e.
+/// the request may succeed if retried later.
+pub fn is_transient(e: &serenity::Error) -> bool {
+    match e {
+        serenity::Error::Http(h) => match h.as_ref() {
+            HttpError::UnsuccessfulRequest(r) => r.status_code.is_server_error() || r.status_code.as_u16() == 429,
+            HttpError::Request(_) => true,
+            _ => false,
+        },
+        serenity::Error::Io(_) => true,
+        _ => false,
+    }
+}
+
+/// The state of the circuit breaker.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CircuitState {
+    /// Discord is healthy; all traffic is allowed.
+    Closed,
+    /// Discord is failing; non-essential traffic is paused and retries are held.
+    Open,
+    /// The cooldown has passed; the next request acts as a probe.
+    HalfOpen,
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            CircuitState::Closed => "healthy",
+            CircuitState::Open => "degraded",
+            CircuitState::HalfOpen => "recovering",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Tracks recent transient failures of REST calls, opening once a threshold is reached.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// When transient failures happened since the last success, oldest first. Only those within
+    /// [`FAILURE_WINDOW`] are kept.
+    recent_failures: Mutex<VecDeque<Instant>>,
+    /// The number of times the breaker has opened.
+    trips: AtomicU64,
+    /// When the breaker last opened, if it's currently open.
+    opened_at: Mutex<Option<Instant>>,
+    /// When non-essential traffic was last let through as a probe while half-open.
+    last_probe: Mutex<Option<Instant>>,
+    /// See [`DEFAULT_FAILURE_THRESHOLD`].
+    threshold: u64,
+    /// See [`DEFAULT_COOLDOWN`].
+    cooldown: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN)
+    }
+}
+
+impl CircuitBreaker {
+    /// Creates a closed circuit breaker.
+    pub fn new(threshold: u64, cooldown: Duration) -> Self {
+        Self {
+            recent_failures: Mutex::new(VecDeque::new()),
+            trips: AtomicU64::new(0),
+            opened_at: Mutex::new(None),
+            last_probe: Mutex::new(None),
+            threshold,
+            cooldown,
+        }
+    }
+
+    /// Retrieves the current state of the breaker.
+    pub fn state(&self) -> CircuitState {
+        match *self.opened_at.lock() {
+            None => CircuitState::Closed,
+            Some(t) if t.elapsed() >= self.cooldown => CircuitState::HalfOpen,
+            Some(_) => CircuitState::Open,
+        }
+    }
+
+    /// Returns true if traffic which can safely be skipped (error replies, reactions) should be sent.
+    /// While half-open, one request per cooldown is let through as a probe, so its outcome can
+    /// close or reopen the breaker.
+    pub fn allows_non_essential(&self) -> bool {
+        match self.state() {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                let mut last = self.last_probe.lock();
+                if last.map_or(true, |t| t.elapsed() >= self.cooldown) {
+                    *last = Some(Instant::now());
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Returns true if essential traffic (e.g. retries) should be attempted.
+    pub fn allows_essential(&self) -> bool {
+        self.state() != CircuitState::Open
+    }
+
+    /// The number of times the breaker has opened since startup.
+    pub fn trips(&self) -> u64 {
+        self.trips.load(Ordering::Relaxed)
+    }
+
+    /// Records a successful request, closing the breaker.
+    pub fn record_success(&self) {
+        self.recent_failures.lock().clear();
+        *self.last_probe.lock() = None;
+        let mut g = self.opened_at.lock();
+        if g.take().is_some() {
+            info!("Discord API recovered; closing circuit breaker.");
+        }
+    }
+
+    /// Records a transient failure, opening the breaker if the threshold has been reached.
+    /// A failure while half-open reopens the breaker for another cooldown.
+    pub fn record_failure(&self) {
+        let now = Instant::now();
+        let fails = {
+            let mut recent = self.recent_failures.lock();
+            recent.push_back(now);
+            while recent
+                .front()
+                .map_or(false, |t| now.duration_since(*t) > FAILURE_WINDOW)
+            {
+                recent.pop_front();
+            }
+            recent.len() as u64
+        };
+        let mut g = self.opened_at.lock();
+        let reopen = matches!(*g, Some(t) if t.elapsed() >= self.cooldown);
+        if (g.is_none() && fails >= self.threshold) || reopen {
+            if g.is_none() {
+                self.trips.fetch_add(1, Ordering::Relaxed);
+            }
+            warn!(
+                "Discord API appears degraded after {} failures; opening circuit breaker.",
+                fails
+            );
+            *g = Some(Instant::now());
+        }
+    }
+
+    /// Inspects the result of a REST call, updating the breaker accordingly.
+    pub fn observe<T>(&self, r: serenity::Result<T>) -> serenity::Result<T> {
+        match &r {
+            Ok(_) => self.record_success(),
+            Err(e) if is_transient(e) => self.record_failure(),
+            Err(_) => {}
+        }
+        r
+    }
+}
+
+/// An idempotent REST operation which can be safely retried.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RetryOp {
+    /// Unbans a user.
+    Unban {
+        /// The guild the user is banned from.
+        guild: GuildId,
+        /// The banned user.
+        user: UserId,
+    },
+    /// Removes a role from a user.
+    RemoveRole {
+        /// The guild in which the user is a member.
+        guild: GuildId,
+        /// The user to remove the role from.
+        user: UserId,
+        /// The role to remove.
+        role: RoleId,
+    },
+}
+
+impl RetryOp {
+    /// Performs the operation.
+    pub async fn perform(&self, ctx: &Context) -> serenity::Result<()> {
+        match *self {
+            RetryOp::Unban { guild, user } => guild.unban(ctx, user).await,
+            RetryOp::RemoveRole { guild, user, role } => ctx.http.remove_member_role(guild.0, user.0, role.0).await,
+        }
+    }
+}
+
+/// An operation waiting to be retried.
+#[derive(Debug, Clone)]
+struct PendingRetry {
+    /// The operation to retry.
+    op: RetryOp,
+    /// The number of attempts made so far.
+    attempts: u32,
+    /// The earliest time at which the next attempt may be made.
+    next_attempt: Instant,
+}
+
+impl PendingRetry {
+    /// Calculates the backoff for the given number of failed attempts.
+    fn backoff(attempts: u32) -> Duration {
+        BASE_RETRY_DELAY
+            .checked_mul(1u32.checked_shl(attempts.saturating_sub(1)).unwrap_or(u32::MAX))
+            .unwrap_or(MAX_RETRY_DELAY)
+            .min(MAX_RETRY_DELAY)
+    }
+}
+
+impl_err!(
+    RetryQueueFull,
+    "Discord is having trouble, and too many actions are already waiting to be retried.",
+    true
+);
+
+/// A bounded, in-memory queue of operations to retry with exponential backoff. Operations in this
+/// queue are lost if Glimbot restarts.
+#[derive(Debug, Default)]
+pub struct RetryQueue {
+    #[doc(hidden)]
+    queue: Mutex<VecDeque<PendingRetry>>,
+}
+
+impl RetryQueue {
+    /// Schedules an operation which has failed once to be retried.
+    pub fn push(&self, op: RetryOp) -> crate::error::Result<()> {
+        self.push_attempted(op, 1)
+    }
+
+    /// Schedules an operation which has failed `attempts` times to be retried.
+    fn push_attempted(&self, op: RetryOp, attempts: u32) -> crate::error::Result<()> {
+        let mut q = self.queue.lock();
+        if q.len() >= RETRY_QUEUE_CAPACITY {
+            return Err(RetryQueueFull.into());
+        }
+        debug!("queueing {:?} for retry, attempt {}", op, attempts + 1);
+        q.push_back(PendingRetry {
+            op,
+            attempts,
+            next_attempt: Instant::now() + PendingRetry::backoff(attempts),
+        });
+        Ok(())
+    }
+
+    /// The number of operations waiting to be retried.
+    pub fn len(&self) -> usize {
+        self.queue.lock().len()
+    }
+
+    /// Returns true if nothing is waiting to be retried.
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().is_empty()
+    }
+
+    /// Removes all operations whose backoff has elapsed.
+    fn take_due(&self) -> Vec<PendingRetry> {
+        let now = Instant::now();
+        let mut q = self.queue.lock();
+        let (due, waiting): (VecDeque<_>, VecDeque<_>) = q.drain(..).partition(|p| p.next_attempt <= now);
+        *q = waiting;
+        due.into()
+    }
+}
+
+/// Aggregates the circuit breaker and retry queue for the Discord REST API.
+#[derive(Debug, Default)]
+pub struct RestHealth {
+    /// See [`CircuitBreaker`].
+    breaker: CircuitBreaker,
+    /// See [`RetryQueue`].
+    retries: RetryQueue,
+}
+
+impl RestHealth {
+    /// Accessor for the circuit breaker.
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// Accessor for the retry queue.
+    pub fn retries(&self) -> &RetryQueue {
+        &self.retries
+    }
+
+    /// Attempts an idempotent operation, queueing it for retry if Discord appears to be degraded.
+    /// Returns `Ok(false)` if the operation was queued rather than performed.
+    pub async fn perform_or_queue(&self, ctx: &Context, op: RetryOp) -> crate::error::Result<bool> {
+        if !self.breaker.allows_essential() {
+            self.retries.push(op)?;
+            return Ok(false);
+        }
+
+        match self.breaker.observe(op.perform(ctx).await) {
+            Ok(()) => Ok(true),
+            Err(e) if is_transient(&e) => {
+                warn!("{:?} failed transiently: {}", op, e);
+                self.retries.push(op)?;
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Retries every operation whose backoff has elapsed. Held while the breaker is open.
+    #[instrument(level = "debug", skip(self, _dis, ctx))]
+    pub async fn process_retries(&self, _dis: &Dispatch, ctx: &Context) {
+        if !self.breaker.allows_essential() {
+            trace!("circuit breaker open; holding retries");
+            return;
+        }
+
+        for p in self.retries.take_due() {
+            match self.breaker.observe(p.op.perform(ctx).await) {
+                Ok(()) => debug!("retry of {:?} succeeded", p.op),
+                Err(e) if is_transient(&e) && p.attempts + 1 < MAX_RETRY_ATTEMPTS => {
+                    if let Err(e) = self.retries.push_attempted(p.op, p.attempts + 1) {
+                        error!("dropping {:?}: {}", p.op, e);
+                    }
+                }
+                Err(e) => error!("giving up on {:?} after {} attempts: {}", p.op, p.attempts + 1, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Contains caching for the results of expensive read-only commands. Modules opt in with
+//! [`ModInfo::with_result_cache`](crate::module::ModInfo::with_result_cache) and render their reply
+//! with [`Module::render`]; repeated invocations in the same guild with the same arguments reuse the
+//! rendered reply until it expires. Staff can pass [`BYPASS_FLAG`] to render a fresh one.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serenity::builder::CreateEmbed;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::GuildId;
+
+use crate::dispatch::Dispatch;
+use crate::module::raid::is_staff;
+use crate::module::Module;
+
+/// The flag staff can pass to a cached command to skip the cache.
+pub const BYPASS_FLAG: &str = "--fresh";
+
+impl_err!(
+    BypassIsStaffOnly,
+    "Only staff can skip cached results with --fresh.",
+    true
+);
+
+/// A rendered reply to a command, which can be sent any number of times.
+#[derive(Clone, Default)]
+pub struct CommandReply {
+    /// The text content of the reply.
+    content: Option<String>,
+    /// The embed in the reply.
+    embed: Option<CreateEmbed>,
+}
+
+impl CommandReply {
+    /// Creates a plain text reply.
+    pub fn text(content: impl Into<String>) -> Self {
+        CommandReply {
+            content: Some(content.into()),
+            embed: None,
+        }
+    }
+
+    /// Creates a reply containing an embed.
+    pub fn embed(f: impl FnOnce(&mut CreateEmbed) -> &mut CreateEmbed) -> Self {
+        let mut embed = CreateEmbed::default();
+        f(&mut embed);
+        CommandReply {
+            content: None,
+            embed: Some(embed),
+        }
+    }
+
+    /// Sends the reply in response to a message.
+    pub async fn send(&self, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        orig.channel_id
+            .send_message(ctx, |m| {
+                if let Some(c) = &self.content {
+                    m.content(c);
+                }
+                if let Some(e) = &self.embed {
+                    m.set_embed(e.clone());
+                }
+                m.reference_message(orig)
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+/// The key cached replies are stored under: the command, the guild, and the command's arguments.
+type ResultKey = (&'static str, GuildId, Vec<String>);
+
+/// Stores rendered replies to cacheable commands until they expire.
+#[derive(Default)]
+pub struct ResultCache {
+    #[doc(hidden)]
+    entries: Mutex<HashMap<ResultKey, (Instant, CommandReply)>>,
+}
+
+impl ResultCache {
+    /// Retrieves an unexpired reply to a command, if one is cached.
+    pub fn get(&self, command: &'static str, guild: GuildId, args: &[String]) -> Option<CommandReply> {
+        let entries = self.entries.lock();
+        entries
+            .get(&(command, guild, args.to_vec()))
+            .filter(|(expiry, _)| *expiry > Instant::now())
+            .map(|(_, reply)| reply.clone())
+    }
+
+    /// Caches a reply to a command for the given time, dropping any expired replies.
+    pub fn insert(&self, command: &'static str, guild: GuildId, args: Vec<String>, reply: CommandReply, ttl: Duration) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock();
+        entries.retain(|_, (expiry, _)| *expiry > now);
+        entries.insert((command, guild, args), (now + ttl, reply));
+    }
+}
+
+/// Runs a command whose results may be cached, reusing a cached reply unless staff asked for a
+/// fresh one.
+pub async fn process_cached(
+    dis: &Dispatch,
+    ctx: &Context,
+    orig: &Message,
+    module: &dyn Module,
+    mut command: Vec<String>,
+    ttl: Duration,
+) -> crate::error::Result<()> {
+    let guild = orig.guild_id.unwrap();
+    let name = module.info().name;
+    let fresh = match command.iter().position(|a| a == BYPASS_FLAG) {
+        Some(i) => {
+            command.remove(i);
+            true
+        }
+        None => false,
+    };
+
+    if fresh {
+        if !is_staff(dis, ctx, guild, orig.author.id).await? {
+            return Err(BypassIsStaffOnly.into());
+        }
+    } else if let Some(reply) = dis.results().get(name, guild, &command[1..]) {
+        debug!("reusing cached result");
+        return reply.send(ctx, orig).await;
+    }
+
+    let args = command[1..].to_vec();
+    let reply = module.render(dis, ctx, orig, command).await?;
+    reply.send(ctx, orig).await?;
+    dis.results().insert(name, guild, args, reply, ttl);
+    Ok(())
+}
@@ -43,6 +43,14 @@ impl Error {
     pub const fn is_user_error(&self) -> bool {
         self.user_error
     }
+
+    /// Returns true if this error was caused by Discord being unreachable or degraded, i.e. the
+    /// failed request may succeed if retried later.
+    pub fn is_transient(&self) -> bool {
+        self.err
+            .downcast_ref::<serenity::Error>()
+            .map_or(false, crate::dispatch::rest::is_transient)
+    }
 }
 
 impl fmt::Display for Error {
@@ -176,7 +184,8 @@ impl_std_from! {
     dotenv::Error,
     tracing::subscriber::SetGlobalDefaultError,
     std::env::VarError,
-    sqlx::migrate::MigrateError
+    sqlx::migrate::MigrateError,
+    reqwest::Error
 }
 
 /// Implements [`From<Error>`] for a type, with `user_error` set to true
@@ -232,6 +241,9 @@ impl_err!(
 );
 impl_err!(DeputyConfused, "Performing that action would confuse the deputy. See https://en.wikipedia.org/wiki/Confused_deputy_problem for an explanation.", true);
 
+/// SQLite's primary result code for constraint violations.
+const SQLITE_CONSTRAINT: u32 = 19;
+
 /// Extension trait for [`sqlx::Error`]
 pub trait DatabaseError {
     /// Returns the name of the constraint violated if this was a constraint issue.
@@ -242,6 +254,8 @@ pub trait DatabaseError {
     fn is_unique(&self) -> bool;
     /// Returns whether or not this error is a `CHECK` constraint violation.
     fn is_check(&self) -> bool;
+    /// Returns whether or not this error is a `FOREIGN KEY` constraint violation.
+    fn is_foreign_key(&self) -> bool;
     /// Returns the several digit string representing what error occurred
     fn sqlstate(&self) -> Option<Cow<'_, str>>;
 }
@@ -254,16 +268,26 @@ impl DatabaseError for sqlx::Error {
         }
     }
 
+    // Postgres reports SQLSTATEs, which are five characters long; SQLite reports its extended
+    // result codes, which are shorter numbers.
     fn is_constraint(&self) -> bool {
-        self.sqlstate().map_or(false, |c| c.starts_with("23"))
+        self.sqlstate().map_or(false, |c| match c.parse::<u32>() {
+            Ok(n) if c.len() < 5 => n & 0xff == SQLITE_CONSTRAINT,
+            _ => c.starts_with("23"),
+        })
     }
 
     fn is_unique(&self) -> bool {
-        self.sqlstate().map_or(false, |c| c.starts_with("23505"))
+        self.sqlstate()
+            .map_or(false, |c| c.starts_with("23505") || c == "1555" || c == "2067")
     }
 
     fn is_check(&self) -> bool {
-        self.sqlstate().map_or(false, |c| c.starts_with("23515"))
+        self.sqlstate().map_or(false, |c| c.starts_with("23515") || c == "275")
+    }
+
+    fn is_foreign_key(&self) -> bool {
+        self.sqlstate().map_or(false, |c| c.starts_with("23503") || c == "787")
     }
 
     fn sqlstate(&self) -> Option<Cow<'_, str>> {
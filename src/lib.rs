@@ -11,6 +11,8 @@
 // #![deny(clippy::missing_docs_in_private_items, missing_docs, missing_crate_level_docs)]
 #![deny(unused_must_use)]
 #![allow(dead_code)]
+// impl_downcast! expands to impls which bound their type parameters in two places.
+#![allow(clippy::multiple_bound_locations)]
 
 #[macro_use]
 extern crate serde;
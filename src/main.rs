@@ -18,6 +18,7 @@ extern crate tracing;
 use clap::{AppSettings, SubCommand};
 #[cfg(target_env = "gnu")]
 use jemallocator::Jemalloc;
+#[allow(deprecated)]
 use std::panic::PanicInfo;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
@@ -32,6 +33,7 @@ fn main() -> glimbot::error::Result<()> {
     better_panic::install();
 
     let pre_hook = std::panic::take_hook();
+    #[allow(deprecated)]
     let hook = move |p: &PanicInfo<'_>| {
         if let Err(e) = glimbot::run::PANIC_ALERT_CHANNEL.0.send(()) {
             error!("Unable to alert panic watchdog of failure because {}. Aborting...", e);
@@ -68,6 +70,10 @@ async fn async_main() -> glimbot::error::Result<()> {
         .author(about::AUTHOR_NAME)
         .subcommand(SubCommand::with_name("run").about("Starts Glimbot."))
         .subcommand(glimbot::example::subcommand())
+        .subcommand(glimbot::db::cli::subcommand())
+        .subcommand(glimbot::db::backup::backup_subcommand())
+        .subcommand(glimbot::db::backup::restore_subcommand())
+        .subcommand(glimbot::db::purge::subcommand())
         .setting(AppSettings::SubcommandRequired)
         .get_matches();
 
@@ -79,6 +85,18 @@ async fn async_main() -> glimbot::error::Result<()> {
         ("make-config", Some(m)) => {
             glimbot::example::handle_matches(m).await?;
         }
+        ("db", Some(m)) => {
+            glimbot::db::cli::handle_matches(m).await?;
+        }
+        ("backup", Some(m)) => {
+            glimbot::db::backup::handle_backup(m).await?;
+        }
+        ("restore", Some(m)) => {
+            glimbot::db::backup::handle_restore(m).await?;
+        }
+        ("purge-user", Some(m)) => {
+            glimbot::db::purge::handle_matches(m).await?;
+        }
         _ => unreachable!("Unrecognized command; we should have errored out already."),
     }
     Ok(())
@@ -0,0 +1,254 @@
+//! Contains the account age gate, which turns away accounts created too recently to have joined in
+//! good faith, since raiders and ban evaders usually make fresh accounts. Account age comes from the
+//! creation time encoded in the user's ID, so no extra requests are needed. The gate can also turn
+//! away accounts without an avatar. Members caught by the gate are sent a polite DM explaining why,
+//! then kicked or quarantined.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::guild::Member;
+use serenity::model::id::ChannelId;
+
+use crate::dispatch::config;
+use crate::dispatch::jobs::{Job, JobPriority, OverloadPolicy};
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::moderation::{ActionKind, ModAction};
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing an [`AccountAgeConfig`].
+pub const ACCOUNT_AGE_GATE_KEY: &str = "account_age_gate";
+/// The DM sent to kicked members if the guild hasn't set its own.
+pub const DEFAULT_KICK_MESSAGE: &str = concat!(
+    "Hi! To keep out spam accounts, {guild} only accepts accounts older than {min_age}{avatar}. ",
+    "You're welcome to join again once your account meets that. Sorry for the trouble!"
+);
+/// The DM sent to quarantined members if the guild hasn't set its own.
+pub const DEFAULT_QUARANTINE_MESSAGE: &str = concat!(
+    "Hi! To keep out spam accounts, {guild} limits accounts younger than {min_age}{avatar} ",
+    "until a moderator has a look. Sorry for the wait!"
+);
+/// The longest DM which will be sent.
+pub const MAX_GATE_MESSAGE_CHARS: usize = 1500;
+
+/// What happens to members caught by the gate.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GateAction {
+    /// They're kicked.
+    Kick,
+    /// They're given the mute role.
+    Quarantine,
+}
+
+impl GateAction {
+    /// The moderation action taken against caught members.
+    fn kind(&self) -> ActionKind {
+        match self {
+            GateAction::Kick => ActionKind::Kick,
+            GateAction::Quarantine => ActionKind::Mute,
+        }
+    }
+}
+
+/// Configuration for the account age gate.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AccountAgeConfig {
+    /// Whether the gate is on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// How old an account must be to get in.
+    #[serde(with = "humantime_serde", default = "default_min_age")]
+    pub min_age: Duration,
+    /// Whether accounts must also have an avatar to get in.
+    #[serde(default)]
+    pub require_avatar: bool,
+    /// What happens to members caught by the gate.
+    #[serde(default = "default_action")]
+    pub action: GateAction,
+    /// The DM sent to members caught by the gate. `{guild}`, `{min_age}` and `{avatar}` are filled
+    /// in. A default matching the action is used if unset.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[doc(hidden)]
+fn default_min_age() -> Duration {
+    Duration::from_secs(7 * 24 * 60 * 60)
+}
+
+#[doc(hidden)]
+fn default_action() -> GateAction {
+    GateAction::Kick
+}
+
+impl Default for AccountAgeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_age: default_min_age(),
+            require_avatar: false,
+            action: default_action(),
+            message: None,
+        }
+    }
+}
+
+impl FromStr for AccountAgeConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for AccountAgeConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+impl AccountAgeConfig {
+    /// Checks whether a member gets past the gate, describing why not if they don't.
+    fn check(&self, member: &Member) -> Option<String> {
+        let age = Utc::now() - member.user.id.created_at();
+        let too_young = age.to_std().map_or(true, |a| a < self.min_age);
+        if too_young {
+            return Some(format!(
+                "Account is younger than {}.",
+                humantime::format_duration(self.min_age)
+            ));
+        }
+        if self.require_avatar && member.user.avatar.is_none() {
+            return Some("Account has no avatar.".to_string());
+        }
+        None
+    }
+
+    /// Fills in the DM sent to a caught member.
+    fn render_message(&self, guild_name: &str) -> String {
+        let template = self.message.as_deref().unwrap_or(match self.action {
+            GateAction::Kick => DEFAULT_KICK_MESSAGE,
+            GateAction::Quarantine => DEFAULT_QUARANTINE_MESSAGE,
+        });
+        let avatar = if self.require_avatar { " with an avatar" } else { "" };
+        template
+            .replace("{guild}", guild_name)
+            .replace("{min_age}", &humantime::format_duration(self.min_age).to_string())
+            .replace("{avatar}", avatar)
+            .chars()
+            .take(MAX_GATE_MESSAGE_CHARS)
+            .collect()
+    }
+}
+
+/// A member caught by the gate, dealt with as a background job.
+struct GatedMember {
+    /// The member who joined.
+    member: Member,
+    /// Why they were caught.
+    why: String,
+    /// The guild's gate configuration.
+    config: AccountAgeConfig,
+}
+
+#[async_trait::async_trait]
+impl Job for GatedMember {
+    fn name(&self) -> &'static str {
+        "account-age-gate"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::High
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let guild_name = ctx
+            .cache
+            .guild_field(self.member.guild_id, |g| g.name.clone())
+            .await
+            .unwrap_or_else(|| "this server".to_string());
+        let message = self.config.render_message(&guild_name);
+
+        // Sent before acting, since kicked members can't be reached afterwards.
+        if let Err(e) = self.member.user.direct_message(ctx, |m| m.content(&message)).await {
+            debug!("couldn't DM {} about the account age gate: {}", self.member.user.id, e);
+        }
+
+        let me = dis.bot().await;
+        let kind = self.config.action.kind();
+        let action = ModAction::new(&self.member, ChannelId::default(), me, kind).with_reason(self.why);
+        let case = action.act(dis, ctx).await?;
+        action.report_action(dis, ctx, case).await.log_error();
+        debug!(
+            "{} {} at the account age gate as case {}",
+            kind.name(),
+            self.member.user.id,
+            case
+        );
+        Ok(())
+    }
+}
+
+/// Module which kicks or quarantines accounts too new to join.
+pub struct AccountAgeGateModule;
+
+#[async_trait::async_trait]
+impl Module for AccountAgeGateModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "account-age-gate",
+                "kicks or quarantines newly created accounts when they join.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_member_join_hook(true)
+            .with_config_value(config::Value::<AccountAgeConfig>::with_default(
+                ACCOUNT_AGE_GATE_KEY,
+                "A JSON object configuring the account age gate, e.g. {\"enabled\": true, \"min_age\": \"7d\", \"require_avatar\": false, \"action\": \"Kick\"}. action may be Kick or Quarantine; set message to customize the DM, using {guild}, {min_age} and {avatar}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_member_join(&self, dis: &Dispatch, _ctx: &Context, member: &Member) -> crate::error::Result<()> {
+        if member.user.bot {
+            return Ok(());
+        }
+        let gid = member.guild_id;
+        let config = dis
+            .config_value_t::<AccountAgeConfig>(ACCOUNT_AGE_GATE_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !config.enabled {
+            return Ok(());
+        }
+
+        let why = match config.check(member) {
+            Some(w) => w,
+            None => return Ok(()),
+        };
+        debug!("{} caught by the account age gate: {}", member.user.id, why);
+        dis.jobs().enqueue(
+            gid,
+            GatedMember {
+                member: member.clone(),
+                why,
+                config: (*config).clone(),
+            },
+        );
+        Ok(())
+    }
+}
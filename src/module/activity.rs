@@ -0,0 +1,392 @@
+//! Contains activity statistics. Message counts are tallied per member, channel and hour in memory,
+//! flushed into hourly rollups from the background service's tick, and summarized by the `activity`
+//! command as the busiest channels, the most active members and weekly trends.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, TimeZone, Utc};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::model::misc::Mentionable;
+use sqlx::PgPool;
+use structopt::StructOpt;
+
+use crate::dispatch::results::CommandReply;
+use crate::dispatch::{config, Dispatch};
+use crate::module::status::GLIM_COLOR;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::ConstrainedU64;
+use crate::util::{sparkline, ClapExt};
+
+/// How often tallied message counts are written to the database.
+pub const ACTIVITY_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+/// How often rollups older than each guild's [`ActivityRetention`] are deleted.
+pub const ACTIVITY_PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// The config key for how many days of rollups a guild keeps.
+pub const ACTIVITY_RETENTION_KEY: &str = "activity_retention";
+/// How many days of rollups are kept by default.
+pub const DEFAULT_ACTIVITY_RETENTION_DAYS: u64 = 90;
+/// The number of rollups upserted per query.
+pub const ACTIVITY_BATCH_SIZE: usize = 1000;
+/// The number of channels or members listed by the `activity` command.
+pub const ACTIVITY_TOP_LISTED: i64 = 10;
+/// How long a rendered `activity` reply is reused for.
+pub const ACTIVITY_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How many days of rollups a guild keeps, from a week to a year.
+pub type ActivityRetention = ConstrainedU64<7, 365>;
+
+/// Identifies a rollup: a member's messages in a channel during an hour.
+type RollupKey = (GuildId, DateTime<Utc>, ChannelId, UserId);
+
+/// Truncates a time to the start of its hour.
+fn start_of_hour(t: DateTime<Utc>) -> DateTime<Utc> {
+    let secs = t.timestamp();
+    Utc.timestamp(secs - secs.rem_euclid(60 * 60), 0)
+}
+
+/// Adds message counts to their rollups, creating any which don't exist yet.
+async fn store_rollups(pool: &PgPool, counts: &[(RollupKey, i32)]) -> crate::error::Result<()> {
+    for batch in counts.chunks(ACTIVITY_BATCH_SIZE) {
+        let guilds: Vec<i64> = batch.iter().map(|((g, ..), _)| g.0 as i64).collect();
+        let hours: Vec<DateTime<Utc>> = batch.iter().map(|((_, h, ..), _)| *h).collect();
+        let channels: Vec<i64> = batch.iter().map(|((_, _, c, _), _)| c.0 as i64).collect();
+        let users: Vec<i64> = batch.iter().map(|((.., u), _)| u.0 as i64).collect();
+        let messages: Vec<i32> = batch.iter().map(|(_, m)| *m).collect();
+        sqlx::query!(
+            r#"
+            INSERT INTO activity_hourly (guild, hour, channel_id, user_id, messages)
+            SELECT * FROM UNNEST($1::BIGINT[], $2::TIMESTAMPTZ[], $3::BIGINT[], $4::BIGINT[], $5::INT[])
+            ON CONFLICT (guild, hour, channel_id, user_id) DO UPDATE
+                SET messages = activity_hourly.messages + EXCLUDED.messages;
+            "#,
+            &guilds,
+            &hours,
+            &channels,
+            &users,
+            &messages
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Deletes rollups older than each guild's [`ActivityRetention`].
+async fn prune_rollups(dis: &Dispatch) -> crate::error::Result<()> {
+    let guilds = sqlx::query_scalar!("SELECT DISTINCT guild FROM activity_hourly;")
+        .fetch_all(dis.pool()?)
+        .await?;
+
+    let mut pruned = 0;
+    for guild in guilds {
+        let days: u64 = (*dis
+            .config_value_t::<ActivityRetention>(ACTIVITY_RETENTION_KEY)?
+            .get_or_default(&dis.db(GuildId(guild as u64)))
+            .await?)
+            .into();
+        let res = sqlx::query!(
+            "DELETE FROM activity_hourly WHERE guild = $1 AND hour < NOW() - $2::INT * INTERVAL '1 day';",
+            guild,
+            days as i32
+        )
+        .execute(dis.pool()?)
+        .await?;
+        pruned += res.rows_affected();
+    }
+    debug!("pruned {} activity rollups", pruned);
+    Ok(())
+}
+
+/// Retrieves the channels with the most messages in a guild over the last number of days, busiest
+/// first.
+async fn top_channels(dis: &Dispatch, guild: GuildId, days: u64) -> crate::error::Result<Vec<(ChannelId, i64)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT channel_id, SUM(messages)::BIGINT AS "messages!" FROM activity_hourly
+        WHERE guild = $1 AND hour > NOW() - $2::INT * INTERVAL '1 day'
+        GROUP BY channel_id
+        ORDER BY 2 DESC
+        LIMIT $3;
+        "#,
+        guild.0 as i64,
+        days as i32,
+        ACTIVITY_TOP_LISTED
+    )
+    .fetch_all(dis.read_pool()?)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (ChannelId::from(r.channel_id as u64), r.messages))
+        .collect())
+}
+
+/// Retrieves the members who sent the most messages in a guild over the last number of days, most
+/// active first.
+async fn top_members(dis: &Dispatch, guild: GuildId, days: u64) -> crate::error::Result<Vec<(UserId, i64)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT user_id, SUM(messages)::BIGINT AS "messages!" FROM activity_hourly
+        WHERE guild = $1 AND hour > NOW() - $2::INT * INTERVAL '1 day'
+        GROUP BY user_id
+        ORDER BY 2 DESC
+        LIMIT $3;
+        "#,
+        guild.0 as i64,
+        days as i32,
+        ACTIVITY_TOP_LISTED
+    )
+    .fetch_all(dis.read_pool()?)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| (UserId::from(r.user_id as u64), r.messages))
+        .collect())
+}
+
+/// Retrieves a guild's total messages in each of the last number of weeks, oldest first. Weeks
+/// start on Monday, and the current week is included even though it isn't over yet.
+async fn weekly_totals(dis: &Dispatch, guild: GuildId, weeks: u64) -> crate::error::Result<Vec<(DateTime<Utc>, i64)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT w.week AS "week!", COALESCE(SUM(a.messages), 0)::BIGINT AS "messages!"
+        FROM generate_series(
+            date_trunc('week', NOW()) - ($2::INT - 1) * INTERVAL '1 week',
+            date_trunc('week', NOW()),
+            INTERVAL '1 week'
+        ) AS w(week)
+        LEFT JOIN activity_hourly a
+            ON a.guild = $1 AND a.hour >= w.week AND a.hour < w.week + INTERVAL '1 week'
+        GROUP BY w.week
+        ORDER BY w.week ASC;
+        "#,
+        guild.0 as i64,
+        weeks as i32
+    )
+    .fetch_all(dis.read_pool()?)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.week, r.messages)).collect())
+}
+
+/// Retrieves a guild's total messages in each hour of the day (in UTC) over the last number of
+/// weeks, indexed by hour.
+async fn hourly_totals(dis: &Dispatch, guild: GuildId, weeks: u64) -> crate::error::Result<[i64; 24]> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT EXTRACT(HOUR FROM hour AT TIME ZONE 'UTC')::INT AS "hour!", SUM(messages)::BIGINT AS "messages!"
+        FROM activity_hourly
+        WHERE guild = $1 AND hour >= NOW() - $2::INT * INTERVAL '1 week'
+        GROUP BY 1;
+        "#,
+        guild.0 as i64,
+        weeks as i32
+    )
+    .fetch_all(dis.read_pool()?)
+    .await?;
+    let mut totals = [0; 24];
+    for r in rows {
+        if let Some(t) = totals.get_mut(r.hour as usize) {
+            *t = r.messages;
+        }
+    }
+    Ok(totals)
+}
+
+/// Renders a ranked list of mentionable things and their message counts.
+fn render_ranking<T: Mentionable>(ranking: &[(T, i64)]) -> String {
+    ranking
+        .iter()
+        .enumerate()
+        .map(|(i, (who, messages))| format!("{}. {}: {} messages", i + 1, who.mention(), messages))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Shows which channels and members have been most active, and how activity has changed from week to
+/// week. Counts are updated every minute; results are reused for a few minutes, and staff can pass
+/// --fresh to skip them.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "activity", no_version)]
+enum ActivityOpt {
+    /// Shows the busiest channels.
+    Channels {
+        /// The number of days to look back over.
+        #[structopt(short, long, default_value = "7")]
+        days: ConstrainedU64<1, 90>,
+    },
+    /// Shows the most active members.
+    Members {
+        /// The number of days to look back over.
+        #[structopt(short, long, default_value = "7")]
+        days: ConstrainedU64<1, 90>,
+    },
+    /// Shows total messages per week, and the busiest hours of the day.
+    Trend {
+        /// The number of weeks to show.
+        #[structopt(short, long, default_value = "8")]
+        weeks: ConstrainedU64<1, 12>,
+    },
+}
+
+/// Module which tallies messages and adds the `activity` command.
+#[derive(Default)]
+pub struct ActivityModule {
+    /// Message counts which haven't been written to the database yet.
+    pending: Mutex<HashMap<RollupKey, i32>>,
+    /// When pending counts were last written.
+    last_flush: Mutex<Option<Instant>>,
+    /// When old rollups were last deleted.
+    last_prune: Mutex<Option<Instant>>,
+}
+
+impl ActivityModule {
+    /// Checks whether an interval has passed since `last`, marking it as passed now if so.
+    fn due(last: &Mutex<Option<Instant>>, interval: Duration) -> bool {
+        let mut last = last.lock();
+        match *last {
+            Some(t) if t.elapsed() < interval => false,
+            _ => {
+                *last = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Writes pending counts to the database. If that fails, the counts are put back to be retried
+    /// on the next flush.
+    async fn flush(&self, pool: &PgPool) -> crate::error::Result<()> {
+        let counts: Vec<(RollupKey, i32)> = std::mem::take(&mut *self.pending.lock()).into_iter().collect();
+        if counts.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = store_rollups(pool, &counts).await {
+            let mut pending = self.pending.lock();
+            for (key, messages) in counts {
+                *pending.entry(key).or_default() += messages;
+            }
+            return Err(e);
+        }
+        trace!("flushed {} activity rollups", counts.len());
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for ActivityModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "activity",
+                "shows the busiest channels, the most active members and weekly activity trends.",
+            )
+            .with_sensitivity(Sensitivity::Medium)
+            .with_command(true)
+            .with_message_hook(true)
+            .with_tick_hook(true)
+            .with_result_cache(ACTIVITY_CACHE_TTL)
+            .with_config_value(config::Value::<ActivityRetention>::with_default(
+                ACTIVITY_RETENTION_KEY,
+                "How many days of message counts are kept for activity statistics, from 7 to 365.",
+                || ActivityRetention::new(DEFAULT_ACTIVITY_RETENTION_DAYS).unwrap(),
+            ))
+        });
+        &INFO
+    }
+
+    fn forget_user(&self, user: UserId) {
+        self.pending.lock().retain(|(.., u), _| *u != user);
+    }
+
+    async fn render(
+        &self,
+        dis: &Dispatch,
+        _ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<CommandReply> {
+        let gid = orig.guild_id.unwrap();
+        let opts = ActivityOpt::from_iter_with_help(command)?;
+
+        let reply = match opts {
+            ActivityOpt::Channels { days } => {
+                let days: u64 = days.into();
+                let ranking = top_channels(dis, gid, days).await?;
+                CommandReply::embed(|e| {
+                    e.color(GLIM_COLOR)
+                        .title(format!("Busiest channels, last {} days", days))
+                        .description(if ranking.is_empty() {
+                            "No messages yet.".to_string()
+                        } else {
+                            render_ranking(&ranking)
+                        })
+                })
+            }
+            ActivityOpt::Members { days } => {
+                let days: u64 = days.into();
+                let ranking = top_members(dis, gid, days).await?;
+                CommandReply::embed(|e| {
+                    e.color(GLIM_COLOR)
+                        .title(format!("Most active members, last {} days", days))
+                        .description(if ranking.is_empty() {
+                            "No messages yet.".to_string()
+                        } else {
+                            render_ranking(&ranking)
+                        })
+                })
+            }
+            ActivityOpt::Trend { weeks } => {
+                let weeks: u64 = weeks.into();
+                let totals = weekly_totals(dis, gid, weeks).await?;
+                let hours = hourly_totals(dis, gid, weeks).await?;
+
+                let values: Vec<i64> = totals.iter().map(|(_, m)| *m).collect();
+                let mut by_week = format!("`{}`\n", sparkline(&values));
+                for (i, (week, messages)) in totals.iter().enumerate() {
+                    let delta = if i == 0 { 0 } else { messages - totals[i - 1].1 };
+                    by_week.push_str(&format!(
+                        "\nWeek of {}: {} ({:+})",
+                        week.format("%Y-%m-%d"),
+                        messages,
+                        delta
+                    ));
+                }
+                let busiest = hours.iter().enumerate().max_by_key(|(_, m)| **m).map_or(0, |(h, _)| h);
+                let by_hour = format!("`{}`\nBusiest hour: {:02}:00 UTC", sparkline(&hours), busiest);
+
+                CommandReply::embed(|e| {
+                    e.color(GLIM_COLOR)
+                        .title(format!("Activity, last {} weeks", weeks))
+                        .field("Messages per week", by_week, false)
+                        .field("Messages by hour of day (UTC)", by_hour, false)
+                })
+            }
+        };
+        Ok(reply)
+    }
+
+    async fn on_message(&self, _dis: &Dispatch, _ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            Some(g) if !orig.author.bot => g,
+            _ => return Ok(()),
+        };
+        let key = (gid, start_of_hour(Utc::now()), orig.channel_id, orig.author.id);
+        *self.pending.lock().entry(key).or_default() += 1;
+        Ok(())
+    }
+
+    async fn on_tick(&self, dis: &Dispatch, _ctx: &Context) -> crate::error::Result<()> {
+        if Self::due(&self.last_flush, ACTIVITY_FLUSH_INTERVAL) {
+            self.flush(dis.pool()?).await?;
+        }
+        if Self::due(&self.last_prune, ACTIVITY_PRUNE_INTERVAL) {
+            prune_rollups(dis).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+//! Contains AFK statuses. Members set one with `!afk <reason>`, and until they next speak, anyone who
+//! mentions them is told they're away and why.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, UserId};
+
+use crate::db::DbContext;
+use crate::dispatch::Dispatch;
+use crate::module::persona::react_success;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::text_after_words;
+
+/// The longest AFK reason, in characters.
+pub const MAX_AFK_REASON_LEN: usize = 200;
+/// The most mentioned members checked for AFK statuses in one message.
+pub const MAX_MENTIONS_CHECKED: usize = 10;
+/// How long before someone mentioning an AFK member in the same channel is told again.
+pub const NOTICE_COOLDOWN: Duration = Duration::from_secs(60);
+
+impl_err!(
+    InvalidAfkReason,
+    "AFK reasons can be at most 200 characters long.",
+    true
+);
+
+/// A member's AFK status.
+#[derive(Debug, Clone)]
+pub struct AfkStatus {
+    /// The member who's away.
+    pub user: UserId,
+    /// Why they're away.
+    pub reason: String,
+    /// When they went away.
+    pub since: DateTime<Utc>,
+}
+
+/// Wrapper around a guild's AFK statuses in the database.
+pub struct AfkStatuses<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> AfkStatuses<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        AfkStatuses {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Marks a member as away, replacing any status they already had.
+    pub async fn set(&self, user: UserId, reason: &str) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO afk_statuses (guild, user_id, reason) VALUES ($1, $2, $3)
+            ON CONFLICT (guild, user_id) DO UPDATE SET reason = EXCLUDED.reason, since = NOW();
+            "#,
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            reason
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Clears a member's AFK status, returning whether they had one.
+    pub async fn clear(&self, user: UserId) -> crate::error::Result<bool> {
+        let res = sqlx::query!(
+            "DELETE FROM afk_statuses WHERE guild = $1 AND user_id = $2;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Retrieves the AFK statuses of whichever of the given members are away.
+    pub async fn of(&self, users: &[UserId]) -> crate::error::Result<Vec<AfkStatus>> {
+        let users: Vec<i64> = users.iter().map(|u| u.0 as i64).collect();
+        let rows = sqlx::query!(
+            "SELECT user_id, reason, since FROM afk_statuses WHERE guild = $1 AND user_id = ANY($2);",
+            self.ctx.guild_as_i64(),
+            &users
+        )
+        .fetch_all(self.ctx.conn()?)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| AfkStatus {
+                user: UserId(r.user_id as u64),
+                reason: r.reason,
+                since: r.since,
+            })
+            .collect())
+    }
+}
+
+/// Module which lets members mark themselves as away.
+#[derive(Default)]
+pub struct AfkModule {
+    /// When each away member was last announced in each channel, so a busy channel isn't told
+    /// over and over.
+    last_noticed: Arc<Mutex<HashMap<(ChannelId, UserId), Instant>>>,
+}
+
+impl AfkModule {
+    /// Checks whether an away member is due to be announced in a channel, marking them as announced
+    /// if so.
+    fn notice_due(&self, channel: ChannelId, user: UserId) -> bool {
+        let now = Instant::now();
+        let mut last_noticed = self.last_noticed.lock();
+        if let Some(last) = last_noticed.get(&(channel, user)) {
+            if now.duration_since(*last) < NOTICE_COOLDOWN {
+                return false;
+            }
+        }
+        last_noticed.retain(|_, last| now.duration_since(*last) < NOTICE_COOLDOWN);
+        last_noticed.insert((channel, user), now);
+        true
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for AfkModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "afk",
+                "marks you as away, telling anyone who mentions you why until you next speak.",
+            )
+            .with_sensitivity(Sensitivity::Low)
+            .with_command(true)
+            .with_message_hook(true)
+        });
+        &INFO
+    }
+
+    fn forget_user(&self, user: UserId) {
+        self.last_noticed.lock().retain(|(_, u), _| *u != user);
+    }
+
+    async fn process(&self, dis: &Dispatch, ctx: &Context, orig: &Message, _: Vec<String>) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let reason = text_after_words(&orig.content, 1).trim();
+        let reason = if reason.is_empty() { "AFK" } else { reason };
+        if reason.chars().count() > MAX_AFK_REASON_LEN {
+            return Err(InvalidAfkReason.into());
+        }
+
+        AfkStatuses::new(dis.db(gid)).set(orig.author.id, reason).await?;
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if orig.author.bot {
+            return Ok(());
+        }
+        let db = dis.db(gid);
+        let statuses = AfkStatuses::new(&db);
+
+        // Setting a new status while away shouldn't welcome the member back first.
+        let prefix = dis
+            .config_value_t::<char>("command_prefix")?
+            .get_or_default(&db)
+            .await?;
+        let is_afk_command = orig
+            .content
+            .strip_prefix(*prefix)
+            .and_then(|c| c.split_whitespace().next())
+            .map_or(false, |c| c == self.info().name);
+        if !is_afk_command && statuses.clear(orig.author.id).await? {
+            orig.reply(ctx, "Welcome back! I've removed your AFK status.").await?;
+        }
+
+        let mentioned: Vec<UserId> = orig
+            .mentions
+            .iter()
+            .filter(|u| u.id != orig.author.id && !u.bot)
+            .map(|u| u.id)
+            .take(MAX_MENTIONS_CHECKED)
+            .collect();
+        if mentioned.is_empty() {
+            return Ok(());
+        }
+
+        let notices: Vec<String> = statuses
+            .of(&mentioned)
+            .await?
+            .into_iter()
+            .filter(|s| self.notice_due(orig.channel_id, s.user))
+            .map(|s| {
+                let name = orig
+                    .mentions
+                    .iter()
+                    .find(|u| u.id == s.user)
+                    .map_or_else(|| s.user.to_string(), |u| u.name.clone());
+                format!(
+                    "{} is AFK: {} (since {})",
+                    name,
+                    s.reason,
+                    s.since.format("%Y-%m-%d %H:%M UTC")
+                )
+            })
+            .collect();
+        if notices.is_empty() {
+            return Ok(());
+        }
+
+        orig.channel_id
+            .send_message(ctx, |m| {
+                m.content(notices.join("\n"))
+                    .reference_message(orig)
+                    .allowed_mentions(|am| am.empty_parse())
+            })
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+//! Contains the `archive` and `unarchive` commands, which freeze a channel so it can no longer be
+//! posted in, optionally move it into an archive category, and store a transcript of its recent
+//! history. Archiving is recorded in the database so it can be reversed exactly.
+//!
+//! Copies of members' messages, in transcripts and case evidence, are cleared once they're older
+//! than the guild's [`MessageRetention`].
+
+use std::borrow::Borrow;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::model::permissions::Permissions;
+use shrinkwraprs::Shrinkwrap;
+use structopt::StructOpt;
+
+use crate::db::DbContext;
+use crate::dispatch::config::{self, FromStrWithCtx, NoSuchChannel, VerifiedChannel};
+use crate::dispatch::Dispatch;
+use crate::error::{DatabaseError, LogErrorExt};
+use crate::module::moderation::mod_log_channel;
+use crate::module::persona::react_success;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::ConstrainedU64;
+use crate::util::overwrite::{denying_overwrite, restore_overwrite, StoredOverwrite};
+use crate::util::transcript::{Transcript, TranscriptRange};
+use crate::util::ClapExt;
+
+/// Config key for the category archived channels are moved into.
+pub const ARCHIVE_CATEGORY: &str = "archive_category";
+/// The number of recent messages saved in an archive's transcript.
+pub const ARCHIVE_TRANSCRIPT_LIMIT: u64 = 500;
+/// The config key for how many days copies of members' messages are kept.
+pub const MESSAGE_RETENTION_KEY: &str = "message_retention";
+/// How many days copies of members' messages are kept by default.
+pub const DEFAULT_MESSAGE_RETENTION_DAYS: u64 = 365;
+/// How often copies of messages older than each guild's [`MessageRetention`] are cleared.
+pub const MESSAGE_PRUNE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many days copies of members' messages are kept, from a week to ten years.
+pub type MessageRetention = ConstrainedU64<7, 3650>;
+
+/// Permissions removed from `@everyone` in an archived channel.
+fn frozen_permissions() -> Permissions {
+    Permissions::SEND_MESSAGES | Permissions::ADD_REACTIONS
+}
+
+/// An archive which hasn't been reversed yet.
+#[derive(Clone, Debug)]
+pub struct ActiveArchive {
+    /// The id of the archive record.
+    pub id: i64,
+    /// Whether the channel was moved into the archive category.
+    pub moved: bool,
+    /// The category the channel was in before it was archived.
+    pub previous_category: Option<ChannelId>,
+    /// The `@everyone` overwrite on the channel before it was archived.
+    pub previous_overwrite: Option<StoredOverwrite>,
+}
+
+impl_err!(AlreadyArchived, "That channel is already archived.", true);
+impl_err!(NotArchived, "That channel isn't archived.", true);
+
+/// Wrapper around DbContext to record and reverse channel archives.
+#[derive(Shrinkwrap)]
+pub struct ChannelArchives<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> ChannelArchives<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        ChannelArchives {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Records that a channel has been archived, returning the id of the record.
+    /// Errors if the channel is already archived.
+    pub async fn record(
+        &self,
+        channel: ChannelId,
+        archived_by: UserId,
+        moved: bool,
+        previous_category: Option<ChannelId>,
+        previous_overwrite: Option<StoredOverwrite>,
+        transcript: &Transcript,
+    ) -> crate::error::Result<i64> {
+        let res: Result<i64, sqlx::Error> = sqlx::query_scalar!(
+            r#"
+            INSERT INTO channel_archives (guild, channel, archived_by, moved, previous_category, previous_overwrite, transcript)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id;
+            "#,
+            self.ctx.guild_as_i64(),
+            channel.0 as i64,
+            archived_by.0 as i64,
+            moved,
+            previous_category.map(|c| c.0 as i64),
+            previous_overwrite.map(serde_json::to_value).transpose()?,
+            serde_json::to_value(transcript)?
+        )
+        .fetch_one(self.ctx.conn()?)
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(AlreadyArchived.into()),
+            Err(e) => Err(e.into()),
+            Ok(id) => Ok(id),
+        }
+    }
+
+    /// Deletes an archive record, e.g. because the channel couldn't actually be archived.
+    pub async fn forget(&self, id: i64) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM channel_archives WHERE guild = $1 AND id = $2;",
+            self.ctx.guild_as_i64(),
+            id
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Retrieves the active archive for a channel, if there is one.
+    pub async fn active(&self, channel: ChannelId) -> crate::error::Result<Option<ActiveArchive>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT id, moved, previous_category, previous_overwrite FROM channel_archives
+            WHERE guild = $1 AND channel = $2 AND reversed IS NULL;
+            "#,
+            self.ctx.guild_as_i64(),
+            channel.0 as i64
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+
+        let row = match row {
+            None => return Ok(None),
+            Some(r) => r,
+        };
+
+        Ok(Some(ActiveArchive {
+            id: row.id,
+            moved: row.moved,
+            previous_category: row.previous_category.map(|c| ChannelId::from(c as u64)),
+            previous_overwrite: row.previous_overwrite.map(serde_json::from_value).transpose()?,
+        }))
+    }
+
+    /// Marks an archive as reversed.
+    pub async fn mark_reversed(&self, id: i64) -> crate::error::Result<()> {
+        sqlx::query!(
+            "UPDATE channel_archives SET reversed = NOW() WHERE guild = $1 AND id = $2;",
+            self.ctx.guild_as_i64(),
+            id
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Archives a channel.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "archive", no_version)]
+pub struct ArchiveOpt {
+    /// The channel to archive.
+    channel: String,
+    /// Leave the channel where it is, even if an archive category has been configured.
+    #[structopt(long)]
+    no_move: bool,
+}
+
+/// Reverses archiving a channel.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "unarchive", no_version)]
+pub struct UnarchiveOpt {
+    /// The channel to unarchive.
+    channel: String,
+}
+
+/// Adds the `archive` command, and clears old copies of messages.
+#[derive(Default)]
+pub struct ArchiveModule {
+    /// When old copies of messages were last cleared.
+    last_prune: Mutex<Option<Instant>>,
+}
+
+/// Adds the `unarchive` command.
+pub struct UnarchiveModule;
+
+/// Freezes a channel and moves it into the archive category, if one is given.
+async fn freeze(
+    ctx: &Context,
+    channel: ChannelId,
+    everyone: RoleId,
+    previous: Option<StoredOverwrite>,
+    category: Option<ChannelId>,
+) -> crate::error::Result<()> {
+    channel
+        .create_permission(ctx, &denying_overwrite(everyone, previous, frozen_permissions()))
+        .await?;
+    if let Some(c) = category {
+        channel.edit(ctx, |e| e.category(c)).await?;
+    }
+    Ok(())
+}
+
+/// Clears the messages from archive transcripts and case evidence older than each guild's
+/// [`MessageRetention`]. The archives and cases themselves are kept.
+async fn prune_messages(dis: &Dispatch) -> crate::error::Result<()> {
+    let guilds = sqlx::query_scalar!("SELECT guild FROM known_guilds;")
+        .fetch_all(dis.pool()?)
+        .await?;
+
+    let mut pruned = 0;
+    for guild in guilds {
+        let days: u64 = (*dis
+            .config_value_t::<MessageRetention>(MESSAGE_RETENTION_KEY)?
+            .get_or_default(&dis.db(GuildId(guild as u64)))
+            .await?)
+            .into();
+        let transcripts = sqlx::query!(
+            r#"
+            UPDATE channel_archives SET transcript = jsonb_set(transcript, '{messages}', '[]'::JSONB)
+            WHERE guild = $1 AND created < NOW() - $2::INT * INTERVAL '1 day' AND transcript->'messages' <> '[]'::JSONB;
+            "#,
+            guild,
+            days as i32
+        )
+        .execute(dis.pool()?)
+        .await?;
+        let evidence = sqlx::query!(
+            r#"
+            UPDATE mod_cases SET evidence = jsonb_set(evidence, '{messages}', '[]'::JSONB)
+            WHERE guild = $1 AND created < NOW() - $2::INT * INTERVAL '1 day' AND evidence->'messages' <> '[]'::JSONB;
+            "#,
+            guild,
+            days as i32
+        )
+        .execute(dis.pool()?)
+        .await?;
+        pruned += transcripts.rows_affected() + evidence.rows_affected();
+    }
+    debug!("cleared old messages from {} transcripts and cases", pruned);
+    Ok(())
+}
+
+/// Posts the transcript for an archive as an HTML attachment, preferring the mod log.
+async fn post_transcript(
+    dis: &Dispatch,
+    ctx: &Context,
+    orig: &Message,
+    guild: GuildId,
+    id: i64,
+    transcript: &Transcript,
+) -> crate::error::Result<()> {
+    let dest = mod_log_channel(dis, guild).await.unwrap_or(orig.channel_id);
+    let html = transcript.to_html();
+    let name = format!("archive-{}-{}.html", transcript.channel_name, id);
+    dest.send_files(ctx, vec![(html.as_bytes(), name.as_str())], |m| {
+        m.content(format!(
+            "Archived <#{}> ({} messages saved, archive #{}). Reverse with `unarchive`.",
+            transcript.channel,
+            transcript.messages.len(),
+            id
+        ))
+    })
+    .await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl Module for ArchiveModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "archive",
+                "locks a channel, saves a transcript of its recent history, and optionally moves it into an archive category.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+            .with_tick_hook(true)
+            .with_config_value(config::Value::<VerifiedChannel>::new(
+                ARCHIVE_CATEGORY,
+                "Category archived channels should be moved into.",
+            ))
+            .with_config_value(config::Value::<MessageRetention>::with_default(
+                MESSAGE_RETENTION_KEY,
+                "How many days copies of members' messages are kept in archive transcripts and case evidence, from 7 to 3650.",
+                || MessageRetention::new(DEFAULT_MESSAGE_RETENTION_DAYS).unwrap(),
+            ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let opts = ArchiveOpt::from_iter_with_help(command)?;
+        let channel = VerifiedChannel::from_str_with_ctx(&opts.channel, ctx, gid)
+            .await?
+            .into_inner();
+        let guild_channel = ctx.cache.guild_channel(channel).await.ok_or(NoSuchChannel)?;
+        let everyone = RoleId(gid.0);
+
+        let previous_overwrite = StoredOverwrite::of_role(&guild_channel, everyone);
+
+        let db = dis.db(gid);
+        let category = if opts.no_move {
+            None
+        } else {
+            dis.config_value_t::<VerifiedChannel>(ARCHIVE_CATEGORY)?
+                .get(&db)
+                .await?
+                .map(|c| c.into_inner())
+                .filter(|c| guild_channel.category_id != Some(*c))
+        };
+
+        let transcript = Transcript::collect(
+            dis,
+            ctx,
+            gid,
+            channel,
+            &guild_channel.name,
+            TranscriptRange::latest(ARCHIVE_TRANSCRIPT_LIMIT),
+        )
+        .await?;
+        let archives = ChannelArchives::new(db);
+        let id = archives
+            .record(
+                channel,
+                orig.author.id,
+                category.is_some(),
+                guild_channel.category_id,
+                previous_overwrite,
+                &transcript,
+            )
+            .await?;
+
+        if let Err(e) = freeze(ctx, channel, everyone, previous_overwrite, category).await {
+            // Put the channel back the way it was, and forget the record so it can be retried.
+            restore_overwrite(ctx, channel, everyone, previous_overwrite)
+                .await
+                .log_error();
+            archives.forget(id).await?;
+            return Err(e);
+        }
+
+        post_transcript(dis, ctx, orig, gid, id, &transcript).await.log_error();
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+
+    async fn on_tick(&self, dis: &Dispatch, _ctx: &Context) -> crate::error::Result<()> {
+        {
+            let mut last = self.last_prune.lock();
+            match *last {
+                Some(t) if t.elapsed() < MESSAGE_PRUNE_INTERVAL => return Ok(()),
+                _ => *last = Some(Instant::now()),
+            }
+        }
+        prune_messages(dis).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for UnarchiveModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "unarchive",
+                "restores an archived channel to how it was before it was archived.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let opts = UnarchiveOpt::from_iter_with_help(command)?;
+        let channel = VerifiedChannel::from_str_with_ctx(&opts.channel, ctx, gid)
+            .await?
+            .into_inner();
+        let archives = ChannelArchives::new(dis.db(gid));
+        let archive = archives.active(channel).await?.ok_or(NotArchived)?;
+
+        restore_overwrite(ctx, channel, RoleId(gid.0), archive.previous_overwrite).await?;
+        if archive.moved {
+            channel.edit(ctx, |e| e.category(archive.previous_category)).await?;
+        }
+        archives.mark_reversed(archive.id).await?;
+
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//! Contains the attachment filter, which removes messages with attachments of blocked file types,
+//! like executables, or attachments over a size cap. File types are matched by extension, and by
+//! the MIME types the extension is known to stand for, since Discord doesn't tell us what a file
+//! actually contains.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::Path;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::{Attachment, Message};
+use serenity::model::id::{ChannelId, RoleId};
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::module::filter::{handle_filtered, FilterAction, FilterModule};
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing an [`AttachmentFilterConfig`].
+pub const ATTACHMENT_FILTER_KEY: &str = "attachment_filter";
+/// Extensions blocked by default: executables and scripts Windows will run when opened.
+pub const DEFAULT_BLOCKED_EXTENSIONS: &[&str] = &[
+    "bat", "cmd", "com", "cpl", "exe", "hta", "jar", "js", "lnk", "msi", "pif", "ps1", "scr", "vbe", "vbs", "wsf",
+];
+
+/// Configuration for the attachment filter.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AttachmentFilterConfig {
+    /// Whether the attachment filter is on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// File extensions which may not be attached, without the leading dot.
+    #[serde(default = "default_blocked_extensions")]
+    pub blocked_extensions: Vec<String>,
+    /// MIME types which may not be attached, like `application/x-msdownload`. A type ending in `/*`,
+    /// like `video/*`, blocks every subtype.
+    #[serde(default)]
+    pub blocked_types: Vec<String>,
+    /// The largest attachment allowed, in bytes. Unlimited if unset.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Roles whose members may attach anything.
+    #[serde(default)]
+    pub exempt_roles: Vec<RoleId>,
+    /// Channels anything may be attached in.
+    #[serde(default)]
+    pub exempt_channels: Vec<ChannelId>,
+    /// What happens to the author of a removed message.
+    #[serde(default = "default_action")]
+    pub action: FilterAction,
+}
+
+#[doc(hidden)]
+fn default_blocked_extensions() -> Vec<String> {
+    DEFAULT_BLOCKED_EXTENSIONS.iter().map(|e| e.to_string()).collect()
+}
+
+#[doc(hidden)]
+fn default_action() -> FilterAction {
+    FilterAction::Delete
+}
+
+impl Default for AttachmentFilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            blocked_extensions: default_blocked_extensions(),
+            blocked_types: Vec::new(),
+            max_size: None,
+            exempt_roles: Vec::new(),
+            exempt_channels: Vec::new(),
+            action: default_action(),
+        }
+    }
+}
+
+impl FromStr for AttachmentFilterConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for AttachmentFilterConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Checks whether a MIME type matches a blocked type, which may end in a `/*` wildcard.
+fn type_matches(mime: &str, blocked: &str) -> bool {
+    let blocked = blocked.trim().to_lowercase();
+    match blocked.strip_suffix("/*") {
+        Some(top) => mime.split('/').next() == Some(top),
+        None => mime == blocked,
+    }
+}
+
+impl AttachmentFilterConfig {
+    /// Checks a single attachment, describing what's wrong with it if it isn't allowed.
+    fn check(&self, attachment: &Attachment) -> Option<String> {
+        let name = &attachment.filename;
+        let ext = Path::new(name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let blocked_ext = !ext.is_empty()
+            && self
+                .blocked_extensions
+                .iter()
+                .any(|b| b.trim().trim_start_matches('.').to_lowercase() == ext);
+        let blocked_type = || {
+            mime_guess::from_ext(&ext)
+                .iter()
+                .any(|m| self.blocked_types.iter().any(|b| type_matches(m.essence_str(), b)))
+        };
+
+        if blocked_ext || (!ext.is_empty() && blocked_type()) {
+            return Some(format!("had an attachment of a blocked file type ({})", name));
+        }
+        match self.max_size {
+            Some(max) if attachment.size > max => Some(format!(
+                "had an attachment over the size limit ({}, {} bytes)",
+                name, attachment.size
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Module which removes messages with disallowed attachments.
+pub struct AttachmentFilterModule;
+
+#[async_trait::async_trait]
+impl Module for AttachmentFilterModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "attachment-filter",
+                "removes attachments of blocked file types or over a size limit.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_message_hook(true)
+            .with_config_value(config::Value::<AttachmentFilterConfig>::with_default(
+                ATTACHMENT_FILTER_KEY,
+                "A JSON object configuring the attachment filter, e.g. {\"enabled\": true, \"blocked_extensions\": [\"exe\"], \"blocked_types\": [\"video/*\"], \"max_size\": 8000000, \"exempt_roles\": [], \"exempt_channels\": [], \"action\": \"Delete\"}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if orig.attachments.is_empty() {
+            return Ok(());
+        }
+
+        let config = dis
+            .config_value_t::<AttachmentFilterConfig>(ATTACHMENT_FILTER_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !config.enabled || config.exempt_channels.contains(&orig.channel_id) {
+            return Ok(());
+        }
+
+        let why = match orig.attachments.iter().find_map(|a| config.check(a)) {
+            Some(w) => w,
+            None => return Ok(()),
+        };
+
+        let exempt_role = orig
+            .member
+            .as_ref()
+            .map_or(false, |m| m.roles.iter().any(|r| config.exempt_roles.contains(r)));
+        if exempt_role || FilterModule::is_exempt(dis, ctx, orig, gid, self.info().name).await? {
+            trace!("exempt user posted a blocked attachment");
+            return Ok(());
+        }
+
+        debug!("message {}", why);
+        handle_filtered(dis, orig, self.info().name, why, config.action).await
+    }
+}
@@ -0,0 +1,234 @@
+//! Contains attribution of moderation actions taken directly in Discord. When a member is banned,
+//! kicked, or given the mute role by someone other than Glimbot, the guild's audit log is checked
+//! for who did it and why, and a case is opened and reported in the mod log as if the action had
+//! been taken through Glimbot. This keeps each user's case history complete.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::guild::{AuditLogEntry, Member};
+use serenity::model::id::{ChannelId, GuildId, RoleId};
+use serenity::model::user::User;
+
+use crate::dispatch::config::VerifiedRole;
+use crate::dispatch::jobs::Job;
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::moderation::{ActionKind, ModAction, MUTE_ROLE};
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// How long to wait after an event before reading the audit log, since entries can show up a little
+/// after the event they describe.
+pub const AUDIT_LOG_DELAY: Duration = Duration::from_secs(2);
+/// The number of recent entries of the relevant type searched in the audit log.
+pub const AUDIT_LOG_SEARCH_LIMIT: u8 = 10;
+/// The maximum age, in seconds, of an audit log entry which may be attributed to an event. Kicks
+/// and role changes can't otherwise be told apart from older ones against the same user.
+pub const MAX_ENTRY_AGE_SECS: i64 = 30;
+/// The audit log action type for kicks.
+const AUDIT_LOG_MEMBER_KICK: u8 = 20;
+/// The audit log action type for bans.
+const AUDIT_LOG_MEMBER_BAN_ADD: u8 = 22;
+/// The audit log action type for role changes.
+const AUDIT_LOG_MEMBER_ROLE_UPDATE: u8 = 25;
+
+/// Checks whether a role update entry added the given role.
+fn adds_role(entry: &AuditLogEntry, role: RoleId) -> bool {
+    let role = role.to_string();
+    entry.changes.iter().flatten().any(|c| {
+        c.name == "$add"
+            && c.new
+                .as_ref()
+                .and_then(serde_json::Value::as_array)
+                .map_or(false, |roles| {
+                    roles
+                        .iter()
+                        .any(|r| r.get("id").and_then(serde_json::Value::as_str) == Some(role.as_str()))
+                })
+    })
+}
+
+/// A job which looks up who took an action in the audit log and opens a case for it.
+struct AttributeAction {
+    /// The guild the action was taken in.
+    guild: GuildId,
+    /// The user the action was taken against.
+    user: User,
+    /// The action which was taken.
+    action: ActionKind,
+}
+
+impl AttributeAction {
+    /// The audit log action type to search for.
+    fn audit_log_type(&self) -> u8 {
+        match self.action {
+            ActionKind::Kick => AUDIT_LOG_MEMBER_KICK,
+            ActionKind::Ban => AUDIT_LOG_MEMBER_BAN_ADD,
+            _ => AUDIT_LOG_MEMBER_ROLE_UPDATE,
+        }
+    }
+
+    /// Finds the audit log entry for the action, if there is one.
+    async fn find_entry(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<Option<AuditLogEntry>> {
+        let mute_role = match self.action {
+            ActionKind::Mute => match dis
+                .config_value_t::<VerifiedRole>(MUTE_ROLE)?
+                .get(&dis.db(self.guild))
+                .await?
+            {
+                Some(r) => Some(r.into_inner()),
+                None => return Ok(None),
+            },
+            _ => None,
+        };
+
+        let logs = self
+            .guild
+            .audit_logs(
+                ctx,
+                Some(self.audit_log_type()),
+                None,
+                None,
+                Some(AUDIT_LOG_SEARCH_LIMIT),
+            )
+            .await?;
+
+        let now = Utc::now();
+        Ok(logs.entries.into_iter().map(|(_, e)| e).find(|e| {
+            e.target_id == Some(self.user.id.0)
+                && (self.action == ActionKind::Ban || (now - e.id.created_at()).num_seconds() <= MAX_ENTRY_AGE_SECS)
+                && mute_role.map_or(true, |r| adds_role(e, r))
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for AttributeAction {
+    fn name(&self) -> &'static str {
+        "attribute-action"
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        tokio::time::sleep(AUDIT_LOG_DELAY).await;
+
+        let entry = match self.find_entry(dis, ctx).await? {
+            Some(e) => e,
+            None => {
+                trace!("no audit log entry for {} of {}", self.action.name(), self.user.id);
+                return Ok(());
+            }
+        };
+        if entry.user_id == dis.bot().await {
+            trace!("ignoring {} taken by Glimbot", self.action.name());
+            return Ok(());
+        }
+
+        let mut action = ModAction::for_user(
+            self.user.clone(),
+            self.guild,
+            ChannelId::default(),
+            entry.user_id,
+            self.action,
+        )
+        .taken_externally();
+        if let Some(r) = entry.reason {
+            action = action.with_reason(r);
+        }
+
+        let case = action.record(dis).await?;
+        info!(
+            "recorded {} of {} by {} as case {}",
+            self.action.name(),
+            self.user.id,
+            entry.user_id,
+            case
+        );
+        action.report_action(dis, ctx, case).await.log_error();
+        Ok(())
+    }
+}
+
+/// Module which opens cases for moderation actions taken directly in Discord.
+pub struct AuditModule;
+
+impl AuditModule {
+    /// Queues a job to attribute an action against a user.
+    fn attribute(dis: &Dispatch, guild: GuildId, user: &User, action: ActionKind) {
+        let queued = dis.jobs().enqueue(
+            guild,
+            AttributeAction {
+                guild,
+                user: user.clone(),
+                action,
+            },
+        );
+        if !queued {
+            debug!("dropped attribution of {} of {}", action.name(), user.id);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for AuditModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "audit",
+                "records kicks, bans and mutes taken directly in Discord as cases.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_ban_hook(true)
+            .with_member_remove_hook(true)
+            .with_member_update_hook(true)
+        });
+        &INFO
+    }
+
+    async fn on_ban(&self, dis: &Dispatch, _ctx: &Context, guild: GuildId, user: &User) -> crate::error::Result<()> {
+        Self::attribute(dis, guild, user, ActionKind::Ban);
+        Ok(())
+    }
+
+    async fn on_member_remove(
+        &self,
+        dis: &Dispatch,
+        _ctx: &Context,
+        guild: GuildId,
+        user: &User,
+        _member: Option<&Member>,
+    ) -> crate::error::Result<()> {
+        // Members who left on their own have no kick entry, so this is filtered out by the job.
+        Self::attribute(dis, guild, user, ActionKind::Kick);
+        Ok(())
+    }
+
+    async fn on_member_update(
+        &self,
+        dis: &Dispatch,
+        _ctx: &Context,
+        old: Option<&Member>,
+        new: &Member,
+    ) -> crate::error::Result<()> {
+        // Without the previous state, there's no telling whether the mute role is new.
+        let old = match old {
+            Some(o) => o,
+            None => return Ok(()),
+        };
+        let mute_role = match dis
+            .config_value_t::<VerifiedRole>(MUTE_ROLE)?
+            .get(&dis.db(new.guild_id))
+            .await?
+        {
+            Some(r) => r.into_inner(),
+            None => return Ok(()),
+        };
+
+        if new.roles.contains(&mute_role) && !old.roles.contains(&mute_role) {
+            Self::attribute(dis, new.guild_id, &new.user, ActionKind::Mute);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+//! Contains auto-publishing, which publishes messages posted in opted-in announcement channels so
+//! they reach servers following the channel without anyone having to press "Publish". Discord only
+//! allows ten publishes per channel an hour, so Glimbot keeps count itself and tells moderators
+//! when a channel runs out, rather than having publishes stall behind Discord's rate limiter.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::http::HttpError;
+use serenity::model::channel::{ChannelType, Message, MessageType};
+use serenity::model::id::{ChannelId, MessageId};
+use serenity::model::misc::Mentionable;
+
+use crate::dispatch::config;
+use crate::dispatch::jobs::{Job, JobPriority};
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::moderation::mod_log_channel;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing an [`AutoPublishConfig`].
+pub const AUTO_PUBLISH_KEY: &str = "auto_publish";
+/// The number of messages Discord allows to be published per channel in [`PUBLISH_WINDOW`].
+pub const PUBLISH_LIMIT: usize = 10;
+/// The window [`PUBLISH_LIMIT`] applies to.
+pub const PUBLISH_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Configuration for auto-publishing.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AutoPublishConfig {
+    /// Announcement channels whose messages are published automatically.
+    #[serde(default)]
+    pub channels: Vec<ChannelId>,
+}
+
+impl FromStr for AutoPublishConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for AutoPublishConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// How many publishes a channel has left.
+#[derive(Debug, Default)]
+struct PublishBudget {
+    /// When messages in the channel were published within the last [`PUBLISH_WINDOW`].
+    published: VecDeque<Instant>,
+    /// Whether moderators have been told the channel ran out, so they're only told once.
+    warned: bool,
+}
+
+/// Tracks the publish budgets of every auto-published channel.
+#[derive(Debug, Default)]
+struct PublishBudgets(Mutex<HashMap<ChannelId, PublishBudget>>);
+
+impl PublishBudgets {
+    /// Takes a publish from a channel's budget. Returns `Ok(())` if there was one left, or
+    /// `Err(first)` if there wasn't, where `first` is whether this is the first time since the
+    /// channel ran out.
+    fn take(&self, channel: ChannelId) -> Result<(), bool> {
+        let now = Instant::now();
+        let mut budgets = self.0.lock();
+        budgets.retain(|_, b| {
+            b.published
+                .back()
+                .map_or(false, |t| now.duration_since(*t) < PUBLISH_WINDOW)
+        });
+
+        let budget = budgets.entry(channel).or_default();
+        while budget
+            .published
+            .front()
+            .map_or(false, |t| now.duration_since(*t) >= PUBLISH_WINDOW)
+        {
+            budget.published.pop_front();
+        }
+        if budget.published.len() >= PUBLISH_LIMIT {
+            let first = !budget.warned;
+            budget.warned = true;
+            return Err(first);
+        }
+        budget.published.push_back(now);
+        budget.warned = false;
+        Ok(())
+    }
+
+    /// Marks a channel's budget as used up, such as when Discord says it's rate limited because
+    /// someone published messages by hand.
+    fn exhaust(&self, channel: ChannelId) {
+        let now = Instant::now();
+        let mut budgets = self.0.lock();
+        let budget = budgets.entry(channel).or_default();
+        budget.published.extend(std::iter::repeat(now).take(PUBLISH_LIMIT));
+    }
+}
+
+/// Checks whether an error is Discord refusing a request for being rate limited.
+fn is_rate_limited(e: &serenity::Error) -> bool {
+    match e {
+        serenity::Error::Http(h) => {
+            matches!(h.as_ref(), HttpError::UnsuccessfulRequest(r) if r.status_code.as_u16() == 429)
+        }
+        _ => false,
+    }
+}
+
+/// A message to be published.
+struct PublishMessage {
+    /// The channel the message is in.
+    channel: ChannelId,
+    /// The message.
+    message: MessageId,
+    /// The channels' budgets, to mark the channel's as used up if Discord says it's rate limited.
+    budgets: Arc<PublishBudgets>,
+}
+
+#[async_trait::async_trait]
+impl Job for PublishMessage {
+    fn name(&self) -> &'static str {
+        "auto-publish"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::Low
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let res = dis
+            .rest()
+            .breaker()
+            .observe(self.channel.crosspost(ctx, self.message).await);
+        if let Err(e) = &res {
+            if is_rate_limited(e) {
+                warn!("channel {} was rate limited while publishing", self.channel);
+                self.budgets.exhaust(self.channel);
+            }
+        }
+        res?;
+        Ok(())
+    }
+}
+
+/// Module which publishes messages in announcement channels.
+#[derive(Default)]
+pub struct AutoPublishModule {
+    /// How many publishes each channel has left.
+    budgets: Arc<PublishBudgets>,
+}
+
+#[async_trait::async_trait]
+impl Module for AutoPublishModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "auto-publish",
+                "publishes messages posted in announcement channels to the servers following them.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_message_hook(true)
+            .with_config_value(config::Value::<AutoPublishConfig>::with_default(
+                AUTO_PUBLISH_KEY,
+                "A JSON object listing announcement channels whose messages are published automatically, e.g. {\"channels\": [\"1234\"]}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if !matches!(orig.kind, MessageType::Regular | MessageType::InlineReply) {
+            return Ok(());
+        }
+
+        let conf = dis
+            .config_value_t::<AutoPublishConfig>(AUTO_PUBLISH_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !conf.channels.contains(&orig.channel_id) {
+            return Ok(());
+        }
+        let is_news = ctx
+            .cache
+            .guild_channel(orig.channel_id)
+            .await
+            .map_or(false, |c| c.kind == ChannelType::News);
+        if !is_news {
+            trace!("auto-publish channel isn't an announcement channel");
+            return Ok(());
+        }
+
+        match self.budgets.take(orig.channel_id) {
+            Ok(()) => {
+                dis.jobs().enqueue(
+                    gid,
+                    PublishMessage {
+                        channel: orig.channel_id,
+                        message: orig.id,
+                        budgets: self.budgets.clone(),
+                    },
+                );
+            }
+            Err(true) => {
+                if let Ok(chan) = mod_log_channel(dis, gid).await {
+                    let note = format!(
+                        "{} has reached Discord's limit of {} published messages an hour; new messages there won't be published until it frees up.",
+                        orig.channel_id.mention(),
+                        PUBLISH_LIMIT
+                    );
+                    chan.say(ctx, note).await.map_err(crate::error::Error::from).log_error();
+                }
+            }
+            Err(false) => {}
+        }
+        Ok(())
+    }
+}
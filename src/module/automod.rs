@@ -0,0 +1,260 @@
+//! Contains the `automod` command, which lets moderators temporarily relax the anti-spam for events
+//! where message rates legitimately spike, like watch parties. While relaxed, spam thresholds are
+//! scaled by [`SPAM_RELAX_FACTOR`]; they're restored automatically once the relaxation expires.
+
+use std::borrow::Borrow;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+use serenity::utils::MessageBuilder;
+use shrinkwraprs::Shrinkwrap;
+use structopt::StructOpt;
+
+use crate::db::timed::{Action, ActionKind, TimedEvents, ONE_HUNDREDISH_YEARS};
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::moderation::mod_log_channel;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// The config key for the factor spam thresholds are scaled by while relaxed.
+pub const SPAM_RELAX_FACTOR: &str = "spam_relax_factor";
+/// The default factor spam thresholds are scaled by while relaxed.
+pub const DEFAULT_RELAX_FACTOR: f64 = 2.0;
+
+impl_err!(
+    InvalidRelaxFactor,
+    "spam_relax_factor must be a number no smaller than 1.",
+    true
+);
+impl_err!(NotRelaxed, "The anti-spam isn't relaxed.", true);
+
+/// A period during which a guild's spam thresholds are relaxed.
+#[derive(Debug, Copy, Clone)]
+pub struct Relaxation {
+    /// The factor thresholds are scaled by.
+    pub factor: f64,
+    /// Who relaxed the thresholds.
+    pub started_by: UserId,
+    /// When the thresholds go back to normal.
+    pub expires: DateTime<Utc>,
+}
+
+impl Relaxation {
+    /// Describes the relaxation for display.
+    pub fn describe(&self) -> String {
+        format!(
+            "Relaxed (thresholds x{}) until {}",
+            self.factor,
+            self.expires.format("%Y-%m-%d %H:%M UTC")
+        )
+    }
+}
+
+/// Wrapper around DbContext to manage a guild's spam relaxation.
+#[derive(Shrinkwrap)]
+pub struct SpamRelaxations<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> SpamRelaxations<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        SpamRelaxations {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Relaxes the guild's spam thresholds, replacing any existing relaxation.
+    pub async fn relax(&self, factor: f64, started_by: UserId, expires: DateTime<Utc>) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO spam_relaxations (guild, factor, started_by, expires)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (guild) DO UPDATE
+                SET factor = EXCLUDED.factor, started_by = EXCLUDED.started_by, expires = EXCLUDED.expires;
+            "#,
+            self.ctx.guild_as_i64(),
+            factor,
+            started_by.0 as i64,
+            expires
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Restores the guild's spam thresholds, returning whether they were relaxed.
+    pub async fn restore(&self) -> crate::error::Result<bool> {
+        let res = sqlx::query!(
+            "DELETE FROM spam_relaxations WHERE guild = $1;",
+            self.ctx.guild_as_i64()
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Retrieves the guild's current relaxation, if any. Expired relaxations are ignored even if
+    /// they haven't been cleaned up yet.
+    pub async fn active(&self) -> crate::error::Result<Option<Relaxation>> {
+        let row = sqlx::query!(
+            "SELECT factor, started_by, expires FROM spam_relaxations WHERE guild = $1 AND expires > NOW();",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+
+        Ok(row.map(|r| Relaxation {
+            factor: r.factor,
+            started_by: UserId::from(r.started_by as u64),
+            expires: r.expires,
+        }))
+    }
+}
+
+/// Adjusts automatic moderation.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "automod", no_version)]
+pub enum AutomodOpt {
+    /// Relaxes the anti-spam for a while, e.g. during an event. Thresholds are scaled by
+    /// spam_relax_factor and restored automatically afterwards.
+    Relax {
+        /// How long the anti-spam should stay relaxed. Specified in human format, i.e. "2h 30m".
+        duration: humantime::Duration,
+    },
+    /// Restores the normal anti-spam thresholds early.
+    Restore,
+    /// Shows whether the anti-spam is currently relaxed.
+    Status,
+}
+
+/// Adds the `automod` command.
+pub struct AutomodModule;
+
+/// Posts a note about the anti-spam in the guild's mod log, if one is set.
+async fn log_relaxation(dis: &Dispatch, ctx: &Context, guild: GuildId, note: &str) {
+    if let Ok(chan) = mod_log_channel(dis, guild).await {
+        chan.say(ctx, note).await.map_err(crate::error::Error::from).log_error();
+    }
+}
+
+/// Restores a guild's spam thresholds once its relaxation has run out, noting it in the mod log.
+pub async fn expire_relaxation(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<()> {
+    if SpamRelaxations::new(dis.db(guild)).restore().await? {
+        log_relaxation(
+            dis,
+            ctx,
+            guild,
+            "Relaxed mode expired; spam thresholds are back to normal.",
+        )
+        .await;
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl Module for AutomodModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "automod",
+                "temporarily relaxes the anti-spam, e.g. during events where lots of messages are expected.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+            .with_config_value(config::Value::<f64>::with_default(
+                SPAM_RELAX_FACTOR,
+                "The factor spam thresholds are multiplied by while the anti-spam is relaxed with `automod relax`. Must be at least 1.",
+                || DEFAULT_RELAX_FACTOR,
+            ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let opts = AutomodOpt::from_iter_with_help(command)?;
+        let db = dis.db(gid);
+        let relaxations = SpamRelaxations::new(&db);
+        let timed = TimedEvents::new(db.clone());
+
+        let message = match opts {
+            AutomodOpt::Relax { duration } => {
+                let factor = *dis
+                    .config_value_t::<f64>(SPAM_RELAX_FACTOR)?
+                    .get_or_default(&db)
+                    .await?;
+                if !factor.is_finite() || factor < 1.0 {
+                    return Err(InvalidRelaxFactor.into());
+                }
+
+                let chrono_dur = chrono::Duration::from_std(*duration).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+                let action = Action::end_relaxation(gid, chrono_dur);
+                timed
+                    .cancel_actions_for(UserId::default(), ActionKind::EndRelaxation)
+                    .await?;
+                relaxations.relax(factor, orig.author.id, action.expiry()).await?;
+                action.store_action(dis).await?;
+
+                log_relaxation(
+                    dis,
+                    ctx,
+                    gid,
+                    &format!(
+                        "{} relaxed spam thresholds by a factor of {} for {}.",
+                        orig.author.tag(),
+                        factor,
+                        duration
+                    ),
+                )
+                .await;
+                format!(
+                    "Spam thresholds are relaxed by a factor of {} for {}.",
+                    factor, duration
+                )
+            }
+            AutomodOpt::Restore => {
+                if !relaxations.restore().await? {
+                    return Err(NotRelaxed.into());
+                }
+                timed
+                    .cancel_actions_for(UserId::default(), ActionKind::EndRelaxation)
+                    .await?;
+
+                log_relaxation(
+                    dis,
+                    ctx,
+                    gid,
+                    &format!(
+                        "{} ended relaxed mode; spam thresholds are back to normal.",
+                        orig.author.tag()
+                    ),
+                )
+                .await;
+                "Spam thresholds are back to normal.".to_string()
+            }
+            AutomodOpt::Status => match relaxations.active().await? {
+                Some(r) => r.describe(),
+                None => "Normal".to_string(),
+            },
+        };
+
+        let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,621 @@
+//! Contains the `automod-sync` command, which copies Glimbot's word, regex and invite filters into
+//! Discord's own AutoMod rules, so basic filtering keeps working while Glimbot is offline, and
+//! imports keyword rules set up in Discord into the `filter` patterns.
+//!
+//! Rules Glimbot pushes are named with [`MANAGED_RULE_PREFIX`]. Pushing again updates them in
+//! place and deletes the ones no longer needed; rules made by hand in Discord are never touched.
+//! serenity doesn't know about AutoMod yet, so the endpoints are called directly with the bot's
+//! token. Those requests bypass serenity's rate limiter, so they wait out 429s themselves, and
+//! they feed and respect the circuit breaker like other REST calls.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use reqwest::{Method, StatusCode};
+use serde::Serialize;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::db::exemptions::{ExemptionKind, FilterExemptions, ALL_FILTERS};
+use crate::dispatch::config::VerifiedRole;
+use crate::dispatch::Dispatch;
+use crate::error::UserError;
+use crate::module::filter::{validate_pattern, words_to_regex, GuildFilters, WORD_END, WORD_START};
+use crate::module::invites::{InviteFilterConfig, INVITE_FILTER_KEY, INVITE_FILTER_NAME, INVITE_PATTERN};
+use crate::module::moderation::mod_log_channel;
+use crate::module::privilege::PRIV_ROLE;
+use crate::module::regex_filter::{RegexRules, REGEX_FILTER_KEY};
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// The prefix of the names of AutoMod rules Glimbot manages.
+pub const MANAGED_RULE_PREFIX: &str = "Glimbot: ";
+/// Discord's API, which AutoMod rules are managed through.
+const DISCORD_API: &str = "https://discord.com/api/v9";
+/// How long to wait for Discord before giving up.
+const AUTOMOD_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many times a rate limited request is retried before giving up.
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+/// The longest Glimbot waits out a rate limit before giving up.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(30);
+/// The most keyword rules Discord allows in a guild.
+const MAX_KEYWORD_RULES: usize = 6;
+/// The most regex patterns Discord allows in one rule.
+const MAX_RULE_REGEXES: usize = 10;
+/// The most keywords Discord allows in one rule.
+const MAX_RULE_KEYWORDS: usize = 1000;
+/// The longest keyword Discord allows, in characters.
+const MAX_KEYWORD_LEN: usize = 60;
+/// The longest regex pattern Discord allows, in characters.
+const MAX_REGEX_LEN: usize = 260;
+/// The most entries Discord allows in a rule's allow list.
+const MAX_ALLOW_LIST: usize = 100;
+/// The most roles Discord allows a rule to exempt.
+const MAX_EXEMPT_ROLES: usize = 20;
+/// The most channels Discord allows a rule to exempt.
+const MAX_EXEMPT_CHANNELS: usize = 50;
+/// AutoMod's trigger type for keyword and regex rules.
+const KEYWORD_TRIGGER: u8 = 1;
+/// AutoMod's event type for messages being sent.
+const MESSAGE_SEND_EVENT: u8 = 1;
+/// AutoMod's action type for blocking a message.
+const BLOCK_MESSAGE_ACTION: u8 = 1;
+/// AutoMod's action type for posting an alert in a channel.
+const SEND_ALERT_ACTION: u8 = 2;
+
+/// Client for Discord's AutoMod endpoints, so requests can't hang forever.
+static AUTOMOD_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(AUTOMOD_TIMEOUT)
+        .build()
+        .expect("Couldn't build AutoMod client")
+});
+
+impl_err!(
+    AutomodPermissions,
+    "I need the Manage Server permission to manage AutoMod rules.",
+    true
+);
+impl_err!(
+    AutomodUnavailable,
+    "Discord is having trouble right now, so I can't manage AutoMod rules. Try again later.",
+    true
+);
+impl_err!(
+    TooManyAutomodRules,
+    "The filters need more AutoMod rules than Discord allows in a server (6). Remove some regex patterns or rules, and try again.",
+    true
+);
+
+/// Copies filters to and from Discord's AutoMod.
+pub struct AutomodSyncModule;
+
+/// Command to copy filters to and from Discord's AutoMod.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "automod-sync", no_version)]
+enum AutomodSyncOpt {
+    /// Creates or updates AutoMod rules matching the word and regex patterns, the regex rules and
+    /// the invite filter, and deletes Glimbot's rules which are no longer needed.
+    Push,
+    /// Adds the keywords and regex patterns of AutoMod rules made in Discord to the filter patterns.
+    Import,
+}
+
+/// The conditions an AutoMod rule matches on.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct TriggerMetadata {
+    /// Words and phrases, with `*` at either end matching part of a word.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    keyword_filter: Vec<String>,
+    /// Case-insensitive regular expressions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    regex_patterns: Vec<String>,
+    /// Keywords which never trigger the rule.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allow_list: Vec<String>,
+}
+
+/// What AutoMod does when a rule matches.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct RuleAction {
+    /// The kind of action.
+    #[serde(rename = "type")]
+    kind: u8,
+    /// Extra details, depending on the kind.
+    #[serde(default)]
+    metadata: serde_json::Value,
+}
+
+/// An AutoMod rule, as Discord sends and receives it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct AutomodRule {
+    /// The rule's ID, which is only known for rules which exist.
+    #[serde(default, skip_serializing)]
+    id: Option<String>,
+    /// The rule's name.
+    name: String,
+    /// When the rule is checked.
+    event_type: u8,
+    /// What kind of content the rule checks. It can't be changed once a rule exists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trigger_type: Option<u8>,
+    /// The conditions the rule matches on.
+    #[serde(default)]
+    trigger_metadata: TriggerMetadata,
+    /// What happens when the rule matches.
+    #[serde(default)]
+    actions: Vec<RuleAction>,
+    /// Whether the rule is on.
+    enabled: bool,
+    /// Roles whose members the rule ignores.
+    #[serde(default)]
+    exempt_roles: Vec<String>,
+    /// Channels the rule ignores.
+    #[serde(default)]
+    exempt_channels: Vec<String>,
+}
+
+impl AutomodRule {
+    /// Creates a keyword rule, which blocks matching messages and optionally alerts a channel.
+    fn keyword(name: String, trigger_metadata: TriggerMetadata, alert: Option<ChannelId>) -> Self {
+        let mut actions = vec![RuleAction {
+            kind: BLOCK_MESSAGE_ACTION,
+            metadata: serde_json::json!({}),
+        }];
+        if let Some(c) = alert {
+            actions.push(RuleAction {
+                kind: SEND_ALERT_ACTION,
+                metadata: serde_json::json!({ "channel_id": c.to_string() }),
+            });
+        }
+        AutomodRule {
+            id: None,
+            name,
+            event_type: MESSAGE_SEND_EVENT,
+            trigger_type: Some(KEYWORD_TRIGGER),
+            trigger_metadata,
+            actions,
+            enabled: true,
+            exempt_roles: Vec::new(),
+            exempt_channels: Vec::new(),
+        }
+    }
+
+    /// Returns true if Glimbot manages the rule.
+    fn is_managed(&self) -> bool {
+        self.name.starts_with(MANAGED_RULE_PREFIX)
+    }
+}
+
+/// Sends a request to one of a guild's AutoMod endpoints as Glimbot, returning the response body.
+/// Errors Discord reports about the rules themselves are passed on to the user. Rate limits are
+/// waited out a few times, and nothing is sent while the circuit breaker is open.
+async fn automod_request<T: Serialize + ?Sized>(
+    dis: &Dispatch,
+    ctx: &Context,
+    method: Method,
+    guild: GuildId,
+    path: &str,
+    body: Option<&T>,
+) -> crate::error::Result<Vec<u8>> {
+    let url = format!("{}/guilds/{}/auto-moderation/rules{}", DISCORD_API, guild, path);
+    let body = body.map(serde_json::to_vec).transpose()?;
+    let breaker = dis.rest().breaker();
+
+    let mut attempts = 0;
+    loop {
+        if !breaker.allows_essential() {
+            return Err(AutomodUnavailable.into());
+        }
+
+        let mut req = AUTOMOD_CLIENT
+            .request(method.clone(), &url)
+            .header(reqwest::header::AUTHORIZATION, &ctx.http.token);
+        if let Some(body) = &body {
+            req = req
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+        }
+
+        let resp = match req.send().await {
+            Ok(r) => r,
+            Err(e) => {
+                breaker.record_failure();
+                return Err(e.into());
+            }
+        };
+        match resp.status() {
+            StatusCode::TOO_MANY_REQUESTS => {
+                breaker.record_failure();
+                attempts += 1;
+                let header = resp
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(|h| h.parse::<f64>().ok());
+                let body: serde_json::Value = serde_json::from_slice(&resp.bytes().await?).unwrap_or_default();
+                let wait = header
+                    .or_else(|| body["retry_after"].as_f64())
+                    .filter(|w| w.is_finite() && *w >= 0.0)
+                    .map_or(Duration::from_secs(1), Duration::from_secs_f64);
+                if attempts > MAX_RATE_LIMIT_RETRIES || wait > MAX_RATE_LIMIT_WAIT {
+                    return Err(AutomodUnavailable.into());
+                }
+                debug!("AutoMod request rate limited; retrying in {:?}", wait);
+                tokio::time::sleep(wait).await;
+            }
+            StatusCode::FORBIDDEN => return Err(AutomodPermissions.into()),
+            StatusCode::BAD_REQUEST => {
+                let body: serde_json::Value = serde_json::from_slice(&resp.bytes().await?).unwrap_or_default();
+                let message = body["message"].as_str().unwrap_or("invalid rule").to_string();
+                #[allow(deprecated)]
+                return Err(UserError::new(format!("Discord rejected the AutoMod rule: {}", message)).into());
+            }
+            s if s.is_server_error() => {
+                breaker.record_failure();
+                return Err(resp.error_for_status().unwrap_err().into());
+            }
+            _ => {
+                breaker.record_success();
+                return Ok(resp.error_for_status()?.bytes().await?.to_vec());
+            }
+        }
+    }
+}
+
+/// Retrieves a guild's AutoMod rules.
+async fn automod_rules(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<Vec<AutomodRule>> {
+    let body = automod_request::<()>(dis, ctx, Method::GET, guild, "", None).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Converts a `filter` pattern back into an AutoMod keyword, if it was added as a plain word or
+/// phrase. See [`words_to_regex`].
+fn pattern_to_keyword(pattern: &str) -> Option<String> {
+    let body = pattern.strip_prefix(WORD_START)?.strip_suffix(WORD_END)?;
+    let mut words = Vec::new();
+    for escaped in body.split(r"\s+") {
+        let mut word = String::new();
+        let mut chars = escaped.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => word.push(chars.next()?),
+                '.' | '^' | '$' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' => return None,
+                c => word.push(c),
+            }
+        }
+        words.push(word);
+    }
+
+    let keyword = words.join(" ");
+    // `*` is a wildcard to AutoMod, so it can't stand for itself.
+    if keyword.is_empty() || keyword.contains('*') || keyword.chars().count() > MAX_KEYWORD_LEN {
+        return None;
+    }
+    Some(keyword)
+}
+
+/// Converts an AutoMod keyword into a `filter` pattern. A `*` at either end of the keyword lets it
+/// match part of a word, as it does in AutoMod.
+fn keyword_to_pattern(keyword: &str) -> String {
+    let pattern = words_to_regex(keyword.trim_matches('*'), false);
+    let mut pattern = pattern.as_str();
+    if keyword.starts_with('*') {
+        pattern = pattern.strip_prefix(WORD_START).unwrap_or(pattern);
+    }
+    if keyword.ends_with('*') {
+        pattern = pattern.strip_suffix(WORD_END).unwrap_or(pattern);
+    }
+    pattern.to_string()
+}
+
+/// Builds the AutoMod rules matching a guild's filters, along with notes on anything which
+/// couldn't be carried over.
+async fn desired_rules(
+    dis: &Dispatch,
+    ctx: &Context,
+    guild: GuildId,
+) -> crate::error::Result<(Vec<AutomodRule>, Vec<String>)> {
+    let db = dis.db(guild);
+    let alert = mod_log_channel(dis, guild).await.ok();
+    let mut notes = Vec::new();
+
+    let filters = GuildFilters::new(&db);
+    let mut keywords = Vec::new();
+    let mut regexes = Vec::new();
+    let presets = filters.presets().await?.into_iter().flat_map(|p| p.patterns);
+    for p in filters.patterns().await?.into_iter().chain(presets) {
+        match pattern_to_keyword(&p) {
+            Some(k) => keywords.push(k),
+            None => regexes.push(p),
+        }
+    }
+    keywords.truncate(MAX_RULE_KEYWORDS);
+
+    let regex_rules = dis
+        .config_value_t::<RegexRules>(REGEX_FILTER_KEY)?
+        .get_or_default(&db)
+        .await?;
+    let named: Vec<String> = regex_rules.rules().iter().map(|r| r.pattern.clone()).collect();
+
+    let too_long = regexes
+        .iter()
+        .chain(named.iter())
+        .filter(|p| p.chars().count() > MAX_REGEX_LEN)
+        .count();
+    if too_long > 0 {
+        notes.push(format!(
+            "{} patterns are longer than AutoMod allows and were left out.",
+            too_long
+        ));
+    }
+    regexes.retain(|p| p.chars().count() <= MAX_REGEX_LEN);
+
+    let mut rules = Vec::new();
+    let mut chunks = regexes.chunks(MAX_RULE_REGEXES);
+    if !keywords.is_empty() || !regexes.is_empty() {
+        let meta = TriggerMetadata {
+            keyword_filter: keywords,
+            regex_patterns: chunks.next().map(<[String]>::to_vec).unwrap_or_default(),
+            allow_list: Vec::new(),
+        };
+        rules.push((
+            "filter",
+            AutomodRule::keyword(format!("{}filter", MANAGED_RULE_PREFIX), meta, alert),
+        ));
+    }
+    for (i, chunk) in chunks.enumerate() {
+        let meta = TriggerMetadata {
+            regex_patterns: chunk.to_vec(),
+            ..Default::default()
+        };
+        let name = format!("{}filter {}", MANAGED_RULE_PREFIX, i + 2);
+        rules.push(("filter", AutomodRule::keyword(name, meta, alert)));
+    }
+
+    let named: Vec<String> = named
+        .into_iter()
+        .filter(|p| p.chars().count() <= MAX_REGEX_LEN)
+        .collect();
+    for (i, chunk) in named.chunks(MAX_RULE_REGEXES).enumerate() {
+        let meta = TriggerMetadata {
+            regex_patterns: chunk.to_vec(),
+            ..Default::default()
+        };
+        let name = match i {
+            0 => format!("{}regex rules", MANAGED_RULE_PREFIX),
+            i => format!("{}regex rules {}", MANAGED_RULE_PREFIX, i + 1),
+        };
+        rules.push(("regex-filter", AutomodRule::keyword(name, meta, alert)));
+    }
+
+    let invites = dis
+        .config_value_t::<InviteFilterConfig>(INVITE_FILTER_KEY)?
+        .get_or_default(&db)
+        .await?;
+    if invites.enabled {
+        // Invites to this server are let through; those to the servers on its allow list can't be
+        // told apart without resolving them, so AutoMod blocks them.
+        let mut allow_list = Vec::new();
+        let vanity = ctx
+            .cache
+            .guild_field(guild, |g| g.vanity_url_code.clone())
+            .await
+            .flatten();
+        let codes = guild.invites(ctx).await?.into_iter().map(|i| i.code).chain(vanity);
+        for code in codes {
+            allow_list.push(format!("*discord.gg/{}*", code));
+            allow_list.push(format!("*discord.com/invite/{}*", code));
+        }
+        allow_list.truncate(MAX_ALLOW_LIST);
+        if !invites.allowed_guilds.is_empty() {
+            notes.push("AutoMod blocks invites to the servers on the invite filter's allow list.".to_string());
+        }
+
+        let meta = TriggerMetadata {
+            regex_patterns: vec![INVITE_PATTERN.to_string()],
+            allow_list,
+            ..Default::default()
+        };
+        let mut rule = AutomodRule::keyword(format!("{}invites", MANAGED_RULE_PREFIX), meta, alert);
+        rule.exempt_roles
+            .extend(invites.exempt_roles.iter().map(ToString::to_string));
+        rule.exempt_channels
+            .extend(invites.exempt_channels.iter().map(ToString::to_string));
+        rules.push((INVITE_FILTER_NAME, rule));
+    }
+
+    if rules.is_empty() {
+        return Ok((Vec::new(), notes));
+    }
+
+    let mod_role = dis.config_value_t::<VerifiedRole>(PRIV_ROLE)?.get(&db).await?;
+    let exemptions = FilterExemptions::new(&db).exemptions().await?;
+    if exemptions.iter().any(|e| e.kind == ExemptionKind::User) {
+        notes.push("AutoMod can't exempt single users, so user exemptions were left out.".to_string());
+    }
+
+    let mut truncated = false;
+    let rules = rules
+        .into_iter()
+        .map(|(filter, mut rule)| {
+            let applies = |e: &&crate::db::exemptions::Exemption| e.filter == ALL_FILTERS || e.filter == filter;
+            rule.exempt_roles
+                .extend(mod_role.iter().map(|r| r.into_inner().to_string()));
+            for e in exemptions.iter().filter(applies) {
+                match e.kind {
+                    ExemptionKind::Role => rule.exempt_roles.push(e.target.to_string()),
+                    ExemptionKind::Channel => rule.exempt_channels.push(e.target.to_string()),
+                    ExemptionKind::User => {}
+                }
+            }
+            rule.exempt_roles.sort();
+            rule.exempt_roles.dedup();
+            rule.exempt_channels.sort();
+            rule.exempt_channels.dedup();
+            truncated |= rule.exempt_roles.len() > MAX_EXEMPT_ROLES || rule.exempt_channels.len() > MAX_EXEMPT_CHANNELS;
+            rule.exempt_roles.truncate(MAX_EXEMPT_ROLES);
+            rule.exempt_channels.truncate(MAX_EXEMPT_CHANNELS);
+            rule
+        })
+        .collect();
+    if truncated {
+        notes.push(
+            "Some exempt roles or channels were left out, as AutoMod allows at most 20 roles and 50 channels per rule."
+                .to_string(),
+        );
+    }
+    Ok((rules, notes))
+}
+
+/// Brings a guild's Glimbot-managed AutoMod rules in line with its filters, describing what changed.
+async fn push(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<String> {
+    let (desired, notes) = desired_rules(dis, ctx, guild).await?;
+    let existing = automod_rules(dis, ctx, guild).await?;
+
+    let unmanaged = existing
+        .iter()
+        .filter(|r| !r.is_managed() && r.trigger_type == Some(KEYWORD_TRIGGER))
+        .count();
+    if unmanaged + desired.len() > MAX_KEYWORD_RULES {
+        return Err(TooManyAutomodRules.into());
+    }
+
+    let (mut created, mut updated, mut deleted) = (0, 0, 0);
+    let synced: crate::error::Result<()> = async {
+        let names: HashSet<&str> = desired.iter().map(|r| r.name.as_str()).collect();
+        for old in existing
+            .iter()
+            .filter(|r| r.is_managed() && !names.contains(r.name.as_str()))
+        {
+            let id = old.id.as_deref().unwrap_or_default();
+            automod_request::<()>(dis, ctx, Method::DELETE, guild, &format!("/{}", id), None).await?;
+            deleted += 1;
+        }
+
+        for mut rule in desired {
+            let current = existing
+                .iter()
+                .find(|r| r.name == rule.name && r.trigger_type == Some(KEYWORD_TRIGGER));
+            match current.and_then(|r| r.id.as_deref()) {
+                Some(id) => {
+                    rule.trigger_type = None;
+                    automod_request(dis, ctx, Method::PATCH, guild, &format!("/{}", id), Some(&rule)).await?;
+                    updated += 1;
+                }
+                None => {
+                    automod_request(dis, ctx, Method::POST, guild, "", Some(&rule)).await?;
+                    created += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    // A failure part way leaves the rules half synced, so say how far it got rather than hiding it
+    // behind a generic error.
+    if let Err(e) = synced {
+        let reason = if e.is_user_error() {
+            e.to_string()
+        } else {
+            "Discord couldn't be reached.".to_string()
+        };
+        #[allow(deprecated)]
+        return Err(UserError::new(format!(
+            "Stopped after creating {} AutoMod rules, updating {} and deleting {}: {} Run `automod-sync push` again to finish.",
+            created, updated, deleted, reason
+        ))
+        .into());
+    }
+
+    let mut message = format!(
+        "Created {} AutoMod rules, updated {} and deleted {}.",
+        created, updated, deleted
+    );
+    for n in notes {
+        message.push('\n');
+        message.push_str(&n);
+    }
+    Ok(message)
+}
+
+/// Adds the keywords and regex patterns of a guild's hand-made AutoMod keyword rules to its filter
+/// patterns, describing what was imported.
+async fn import(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<String> {
+    let rules = automod_rules(dis, ctx, guild).await?;
+    let filters = GuildFilters::new(dis.db(guild));
+    let mut known: HashSet<String> = filters.patterns().await?.into_iter().collect();
+
+    let (mut imported, mut skipped) = (0, 0);
+    let candidates = rules
+        .into_iter()
+        .filter(|r| !r.is_managed() && r.trigger_type == Some(KEYWORD_TRIGGER))
+        .flat_map(|r| {
+            let keywords = r
+                .trigger_metadata
+                .keyword_filter
+                .into_iter()
+                .map(|k| (keyword_to_pattern(&k), k));
+            let regexes = r.trigger_metadata.regex_patterns.into_iter().map(|p| (p.clone(), p));
+            keywords.chain(regexes)
+        });
+    for (pattern, typed) in candidates {
+        if known.contains(&pattern) || validate_pattern(&typed, &pattern).is_err() {
+            skipped += 1;
+            continue;
+        }
+        filters.add_pattern(&pattern).await?;
+        known.insert(pattern);
+        imported += 1;
+    }
+
+    Ok(format!(
+        "Imported {} patterns from AutoMod; skipped {} which were already filtered or aren't valid patterns.",
+        imported, skipped
+    ))
+}
+
+#[async_trait::async_trait]
+impl Module for AutomodSyncModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "automod-sync",
+                "copies the word, regex and invite filters to and from Discord's AutoMod.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = AutomodSyncOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+
+        let message = match opts {
+            AutomodSyncOpt::Push => push(dis, ctx, gid).await?,
+            AutomodSyncOpt::Import => {
+                let message = import(dis, ctx, gid).await?;
+                dis.flush_caches(Some(gid));
+                message
+            }
+        };
+
+        let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+//! Contains autoroles, which are given to members when they join. They can be given straight away,
+//! after a delay, so members have to stick around before they're let in, or once members send their
+//! first message, so lurkers and raid accounts which never speak don't get them. Delayed autoroles
+//! are handed out through timed events, so they survive restarts. Members who are muted when their
+//! autoroles come due don't get them, so autoroles can't be used to slip out of quarantine.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::guild::Member;
+use serenity::model::id::{GuildId, RoleId, UserId};
+
+use crate::db::timed::{Action, ONE_HUNDREDISH_YEARS};
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::config::VerifiedRole;
+use crate::dispatch::Dispatch;
+use crate::module::moderation::MUTE_ROLE;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing an [`AutoroleConfig`].
+pub const AUTOROLE_KEY: &str = "autorole";
+/// The most autoroles a guild may have.
+pub const MAX_AUTOROLES: usize = 10;
+/// How long members are waited on to send their first message before they're forgotten.
+pub const PENDING_TTL_DAYS: i32 = 30;
+
+impl_err!(
+    InvalidAutoroleConfig,
+    "autorole may have at most 10 roles, and can't both set a delay and wait for a first message.",
+    true
+);
+
+/// Configuration for autoroles.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AutoroleConfig {
+    /// The roles given to members who join. Autoroles are off while this is empty.
+    #[serde(default)]
+    pub roles: Vec<RoleId>,
+    /// How long after joining members are given the roles.
+    #[serde(with = "humantime_serde", default)]
+    pub delay: Option<Duration>,
+    /// Whether members are only given the roles once they send their first message.
+    #[serde(default)]
+    pub wait_for_message: bool,
+}
+
+impl FromStr for AutoroleConfig {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conf: AutoroleConfig = serde_json::from_str(s)?;
+        if conf.roles.len() > MAX_AUTOROLES || (conf.delay.is_some() && conf.wait_for_message) {
+            return Err(InvalidAutoroleConfig.into());
+        }
+        Ok(conf)
+    }
+}
+
+impl fmt::Display for AutoroleConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Wrapper around the members of a guild waiting on their first message to get autoroles.
+pub struct PendingAutoroles<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> PendingAutoroles<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        PendingAutoroles {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Records that a member is waiting on their first message, forgetting members who have waited
+    /// too long.
+    pub async fn add(&self, user: UserId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM pending_autoroles WHERE guild = $1 AND joined < NOW() - make_interval(days => $2);",
+            self.ctx.guild_as_i64(),
+            PENDING_TTL_DAYS
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO pending_autoroles (guild, user_id) VALUES ($1, $2)
+            ON CONFLICT (guild, user_id) DO UPDATE SET joined = NOW();
+            "#,
+            self.ctx.guild_as_i64(),
+            user.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Stops waiting on a member, returning whether they were being waited on.
+    pub async fn take(&self, user: UserId) -> crate::error::Result<bool> {
+        let res = sqlx::query!(
+            "DELETE FROM pending_autoroles WHERE guild = $1 AND user_id = $2;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+}
+
+/// Gives a member the guild's autoroles they don't already have, unless they've left or are muted.
+pub async fn grant_autoroles(dis: &Dispatch, ctx: &Context, guild: GuildId, user: UserId) -> crate::error::Result<()> {
+    let db = dis.db(guild);
+    let conf = dis
+        .config_value_t::<AutoroleConfig>(AUTOROLE_KEY)?
+        .get_or_default(&db)
+        .await?;
+    if conf.roles.is_empty() {
+        return Ok(());
+    }
+
+    let mut member = match guild.member(ctx, user).await {
+        Ok(m) => m,
+        Err(e) => {
+            debug!("couldn't find member to give autoroles: {}", e);
+            return Ok(());
+        }
+    };
+    let mute_role = dis.config_value_t::<VerifiedRole>(MUTE_ROLE)?.get(&db).await?;
+    if mute_role.map_or(false, |r| member.roles.contains(&r.into_inner())) {
+        debug!("not giving autoroles to a muted member");
+        return Ok(());
+    }
+
+    let missing: Vec<RoleId> = conf
+        .roles
+        .iter()
+        .filter(|r| !member.roles.contains(r))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        member.add_roles(ctx, &missing).await?;
+    }
+    Ok(())
+}
+
+/// Module which gives members autoroles.
+pub struct AutoroleModule;
+
+#[async_trait::async_trait]
+impl Module for AutoroleModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "autorole",
+                "gives members roles when they join, after a delay, or once they first speak.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_message_hook(true)
+            .with_member_join_hook(true)
+            .with_config_value(config::Value::<AutoroleConfig>::with_default(
+                AUTOROLE_KEY,
+                "A JSON object configuring autoroles, e.g. {\"roles\": [\"123456789012345678\"], \"delay\": \"10m\"}. Set wait_for_message to true instead of a delay to give the roles once members first speak.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if orig.author.bot {
+            return Ok(());
+        }
+
+        let conf = dis
+            .config_value_t::<AutoroleConfig>(AUTOROLE_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !conf.wait_for_message || conf.roles.is_empty() {
+            return Ok(());
+        }
+        // Members who already have the roles can't be waiting for them, so save the query.
+        let has_roles = orig
+            .member
+            .as_ref()
+            .map_or(false, |m| conf.roles.iter().all(|r| m.roles.contains(r)));
+        if has_roles || !PendingAutoroles::new(dis.db(gid)).take(orig.author.id).await? {
+            return Ok(());
+        }
+
+        trace!("giving autoroles after first message");
+        grant_autoroles(dis, ctx, gid, orig.author.id).await
+    }
+
+    async fn on_member_join(&self, dis: &Dispatch, ctx: &Context, member: &Member) -> crate::error::Result<()> {
+        if member.user.bot {
+            return Ok(());
+        }
+        let gid = member.guild_id;
+        let db = dis.db(gid);
+        let conf = dis
+            .config_value_t::<AutoroleConfig>(AUTOROLE_KEY)?
+            .get_or_default(&db)
+            .await?;
+        if conf.roles.is_empty() {
+            return Ok(());
+        }
+
+        if conf.wait_for_message {
+            return PendingAutoroles::new(&db).add(member.user.id).await;
+        }
+        match conf.delay {
+            Some(delay) => {
+                let delay = chrono::Duration::from_std(delay).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+                Action::grant_autoroles(member.user.id, gid, delay)
+                    .store_action(dis)
+                    .await
+            }
+            None => grant_autoroles(dis, ctx, gid, member.user.id).await,
+        }
+    }
+}
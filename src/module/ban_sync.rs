@@ -0,0 +1,518 @@
+//! Contains ban synchronization for networks of affiliated guilds. Guilds opt in by joining a
+//! ban-sync group; when a user is banned in one member of the group, the ban is mirrored to the
+//! others along with where it came from, or staff there are notified, depending on each guild's
+//! [`BanSyncMode`].
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::model::misc::Mentionable;
+use serenity::model::user::User;
+use serenity::utils::{Color, MessageBuilder};
+use shrinkwraprs::Shrinkwrap;
+use structopt::StructOpt;
+
+use crate::db::cases::{BanProvenance, Evidence};
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::jobs::{Job, OverloadPolicy};
+use crate::dispatch::Dispatch;
+use crate::error::{DatabaseError, LogErrorExt};
+use crate::module::moderation::{mod_log_channel, ActionKind, ModAction};
+use crate::module::persona::react_success;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// Config key for a guild's [`BanSyncMode`].
+pub const BAN_SYNC_MODE: &str = "ban_sync_mode";
+/// The length of the codes used to join a ban-sync group.
+pub const JOIN_CODE_LEN: usize = 16;
+/// How long a ban Glimbot mirrored is remembered, so the ban event it causes isn't mirrored back.
+pub const MIRROR_MEMORY: Duration = Duration::from_secs(10 * 60);
+/// The number of recent bans searched in the audit log for the original ban's details.
+pub const AUDIT_LOG_SEARCH_LIMIT: u8 = 10;
+/// The audit log action type for bans.
+const AUDIT_LOG_MEMBER_BAN_ADD: u8 = 22;
+
+/// How a guild responds to bans from the rest of its ban-sync group.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BanSyncMode {
+    /// Apply the ban immediately.
+    Auto,
+    /// Only post a notice in the mod log, leaving the decision to staff.
+    Notify,
+}
+
+impl Default for BanSyncMode {
+    fn default() -> Self {
+        BanSyncMode::Notify
+    }
+}
+
+impl_err!(
+    InvalidBanSyncMode,
+    "Ban sync mode must be either `auto` or `notify`.",
+    true
+);
+
+impl FromStr for BanSyncMode {
+    type Err = InvalidBanSyncMode;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(BanSyncMode::Auto),
+            "notify" => Ok(BanSyncMode::Notify),
+            _ => Err(InvalidBanSyncMode),
+        }
+    }
+}
+
+impl fmt::Display for BanSyncMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BanSyncMode::Auto => "auto",
+            BanSyncMode::Notify => "notify",
+        };
+        f.write_str(s)
+    }
+}
+
+impl_err!(
+    AlreadyInGroup,
+    "This server is already in a ban-sync group; leave it first.",
+    true
+);
+impl_err!(NotInGroup, "This server isn't in a ban-sync group.", true);
+impl_err!(NoSuchGroup, "No ban-sync group has that join code.", true);
+
+/// A ban-sync group, as seen from one of its members.
+#[derive(Clone, Debug)]
+pub struct BanSyncGroup {
+    /// The id of the group.
+    pub id: i64,
+    /// The name of the group.
+    pub name: String,
+    /// The code other guilds use to join the group.
+    pub join_code: String,
+    /// The number of guilds in the group.
+    pub members: i64,
+}
+
+/// Wrapper around DbContext to manage a guild's ban-sync group membership.
+#[derive(Shrinkwrap)]
+pub struct BanSyncGroups<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> BanSyncGroups<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        BanSyncGroups {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Creates a group and adds this guild to it, returning the group's join code.
+    pub async fn create(&self, name: &str) -> crate::error::Result<String> {
+        if self.group().await?.is_some() {
+            return Err(AlreadyInGroup.into());
+        }
+
+        let code: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(JOIN_CODE_LEN)
+            .map(char::from)
+            .collect();
+
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO ban_sync_groups (name, join_code, created_by)
+            VALUES ($1, $2, $3)
+            RETURNING id;
+            "#,
+            name,
+            &code,
+            self.ctx.guild_as_i64()
+        )
+        .fetch_one(self.ctx.conn()?)
+        .await?;
+
+        self.add_member(id).await?;
+        Ok(code)
+    }
+
+    /// Adds this guild to the group with the given join code, returning the group's name.
+    pub async fn join(&self, code: &str) -> crate::error::Result<String> {
+        let group = sqlx::query!("SELECT id, name FROM ban_sync_groups WHERE join_code = $1;", code)
+            .fetch_optional(self.ctx.conn()?)
+            .await?
+            .ok_or(NoSuchGroup)?;
+
+        self.add_member(group.id).await?;
+        Ok(group.name)
+    }
+
+    /// Adds this guild to a group.
+    async fn add_member(&self, group: i64) -> crate::error::Result<()> {
+        let res = sqlx::query!(
+            "INSERT INTO ban_sync_members (guild, group_id) VALUES ($1, $2);",
+            self.ctx.guild_as_i64(),
+            group
+        )
+        .execute(self.ctx.conn()?)
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(AlreadyInGroup.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Removes this guild from its group. Groups are deleted once their last member leaves.
+    pub async fn leave(&self) -> crate::error::Result<()> {
+        let group = sqlx::query_scalar!(
+            "DELETE FROM ban_sync_members WHERE guild = $1 RETURNING group_id;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?
+        .ok_or(NotInGroup)?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM ban_sync_groups g
+            WHERE id = $1 AND NOT EXISTS (SELECT 1 FROM ban_sync_members m WHERE m.group_id = g.id);
+            "#,
+            group
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Retrieves the group this guild belongs to, if any.
+    pub async fn group(&self) -> crate::error::Result<Option<BanSyncGroup>> {
+        let row = sqlx::query!(
+            r#"
+            SELECT g.id, g.name, g.join_code,
+                   (SELECT COUNT(*) FROM ban_sync_members c WHERE c.group_id = g.id) AS "members!"
+            FROM ban_sync_groups g
+            JOIN ban_sync_members m ON m.group_id = g.id
+            WHERE m.guild = $1;
+            "#,
+            self.ctx.guild_as_i64()
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+
+        Ok(row.map(|r| BanSyncGroup {
+            id: r.id,
+            name: r.name,
+            join_code: r.join_code,
+            members: r.members,
+        }))
+    }
+
+    /// Retrieves the other guilds in this guild's group.
+    pub async fn peers(&self) -> crate::error::Result<Vec<GuildId>> {
+        let rows = sqlx::query_scalar!(
+            r#"
+            SELECT p.guild FROM ban_sync_members p
+            JOIN ban_sync_members m ON m.group_id = p.group_id
+            WHERE m.guild = $1 AND p.guild <> $1;
+            "#,
+            self.ctx.guild_as_i64()
+        )
+        .fetch_all(self.ctx.conn()?)
+        .await?;
+
+        Ok(rows.into_iter().map(|g| GuildId::from(g as u64)).collect())
+    }
+}
+
+/// Manages this server's membership in a ban-sync group.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "ban-sync", no_version)]
+pub enum BanSyncOpt {
+    /// Creates a new group containing this server, and shows the code other servers can use to join it.
+    Create {
+        /// The name of the group.
+        name: String,
+    },
+    /// Joins an existing group.
+    Join {
+        /// The group's join code.
+        code: String,
+    },
+    /// Leaves the current group.
+    Leave,
+    /// Shows the current group.
+    Status,
+}
+
+/// Bans Glimbot mirrored recently, keyed by guild and user.
+type MirroredBans = Arc<Mutex<HashMap<(GuildId, UserId), Instant>>>;
+
+/// Adds the `ban-sync` command and mirrors bans across ban-sync groups.
+#[derive(Default)]
+pub struct BanSyncModule {
+    #[doc(hidden)]
+    mirrored: MirroredBans,
+}
+
+/// Remembers that Glimbot is about to ban a user in a guild on behalf of its group.
+fn remember_mirror(mirrored: &MirroredBans, guild: GuildId, user: UserId) {
+    let mut m = mirrored.lock();
+    m.retain(|_, at| at.elapsed() < MIRROR_MEMORY);
+    m.insert((guild, user), Instant::now());
+}
+
+/// A ban to be mirrored to the rest of a guild's ban-sync group.
+struct MirrorBan {
+    /// The guild the user was banned from.
+    origin: GuildId,
+    /// The banned user.
+    user: User,
+    /// The guilds to mirror the ban to.
+    peers: Vec<GuildId>,
+    /// See [`MirroredBans`].
+    mirrored: MirroredBans,
+}
+
+impl MirrorBan {
+    /// Looks up who banned the user and why in the origin guild's audit log.
+    async fn provenance(&self, ctx: &Context) -> BanProvenance {
+        let guild_name = ctx
+            .cache
+            .guild_field(self.origin, |g| g.name.clone())
+            .await
+            .unwrap_or_else(|| self.origin.to_string());
+
+        let entry = self
+            .origin
+            .audit_logs(
+                ctx,
+                Some(AUDIT_LOG_MEMBER_BAN_ADD),
+                None,
+                None,
+                Some(AUDIT_LOG_SEARCH_LIMIT),
+            )
+            .await
+            .map_err(|e| debug!("couldn't read audit log for {}: {}", self.origin, e))
+            .ok()
+            .and_then(|logs| {
+                logs.entries
+                    .values()
+                    .find(|e| e.target_id == Some(self.user.id.0))
+                    .map(|e| (e.user_id, e.reason.clone()))
+            });
+
+        BanProvenance {
+            guild: self.origin,
+            guild_name,
+            moderator: entry.as_ref().map(|e| e.0),
+            reason: entry.and_then(|e| e.1),
+            banned_at: Utc::now(),
+        }
+    }
+
+    /// Bans the user in a peer guild, opening a case there which records the original ban.
+    async fn apply(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        peer: GuildId,
+        provenance: &BanProvenance,
+    ) -> crate::error::Result<()> {
+        let channel = mod_log_channel(dis, peer)
+            .await
+            .unwrap_or_else(|_| ChannelId::default());
+        let reason = format!(
+            "Ban synced from {}: {}",
+            provenance.guild_name,
+            provenance.reason.as_deref().unwrap_or("No reason specified.")
+        );
+        let action = ModAction::for_user(self.user.clone(), peer, channel, dis.bot().await, ActionKind::Ban)
+            .with_reason(reason)
+            .with_evidence(Evidence {
+                provenance: Some(provenance.clone()),
+                ..Default::default()
+            });
+
+        remember_mirror(&self.mirrored, peer, self.user.id);
+        let case = action.act(dis, ctx).await?;
+        action.report_action(dis, ctx, case).await.log_error();
+        Ok(())
+    }
+
+    /// Tells staff in a peer guild about the ban, leaving the decision to them.
+    async fn notify(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        peer: GuildId,
+        provenance: &BanProvenance,
+    ) -> crate::error::Result<()> {
+        let moderator = provenance
+            .moderator
+            .map(|m| format!("{} ({})", m.mention(), m))
+            .unwrap_or_else(|| "unknown".to_string());
+        mod_log_channel(dis, peer)
+            .await?
+            .send_message(ctx, |m| {
+                m.embed(|e| {
+                    e.color(Color::ORANGE)
+                        .title("Ban in an affiliated server")
+                        .field("User", format!("{} ({})", self.user.tag(), self.user.id), false)
+                        .field(
+                            "Server",
+                            format!("{} ({})", provenance.guild_name, provenance.guild),
+                            false,
+                        )
+                        .field("Moderator", moderator, false)
+                        .field(
+                            "Reason",
+                            provenance.reason.as_deref().unwrap_or("No reason specified."),
+                            false,
+                        )
+                        .footer(|f| f.text("This server's ban sync mode is notify, so no action was taken."))
+                })
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for MirrorBan {
+    fn name(&self) -> &'static str {
+        "mirror-ban"
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let provenance = self.provenance(ctx).await;
+        let mode = dis.config_value_t::<BanSyncMode>(BAN_SYNC_MODE)?;
+
+        for peer in &self.peers {
+            let res = match *mode.get_or_default(&dis.db(*peer)).await? {
+                BanSyncMode::Auto => self.apply(dis, ctx, *peer, &provenance).await,
+                BanSyncMode::Notify => self.notify(dis, ctx, *peer, &provenance).await,
+            };
+            if let Err(e) = res {
+                warn!("failed to sync ban of {} to {}: {}", self.user.id, peer, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for BanSyncModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "ban-sync",
+                "mirrors bans between affiliated servers which have opted into a ban-sync group.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+            .with_ban_hook(true)
+            .with_config_value(config::Value::<BanSyncMode>::with_default(
+                BAN_SYNC_MODE,
+                "Whether bans from the rest of the ban-sync group are applied automatically (`auto`) or only posted in the mod log (`notify`).",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let opts = BanSyncOpt::from_iter_with_help(command)?;
+        let groups = BanSyncGroups::new(dis.db(gid));
+
+        let message = match opts {
+            BanSyncOpt::Create { name } => {
+                let code = groups.create(&name).await?;
+                format!(
+                    "Created ban-sync group {}. Other servers can join it with `ban-sync join {}`.",
+                    name, code
+                )
+            }
+            BanSyncOpt::Join { code } => {
+                let name = groups.join(&code).await?;
+                format!("Joined ban-sync group {}.", name)
+            }
+            BanSyncOpt::Leave => {
+                groups.leave().await?;
+                react_success(dis, ctx, orig).await?;
+                return Ok(());
+            }
+            BanSyncOpt::Status => {
+                let group = groups.group().await?.ok_or(NotInGroup)?;
+                let mode = dis
+                    .config_value_t::<BanSyncMode>(BAN_SYNC_MODE)?
+                    .get_or_default(&dis.db(gid))
+                    .await?;
+                format!(
+                    "Group: {}\nServers: {}\nJoin code: {}\nThis server's mode: {}",
+                    group.name, group.members, group.join_code, mode
+                )
+            }
+        };
+
+        let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+
+    async fn on_ban(&self, dis: &Dispatch, _ctx: &Context, guild: GuildId, user: &User) -> crate::error::Result<()> {
+        if self.mirrored.lock().remove(&(guild, user.id)).is_some() {
+            trace!("ignoring ban mirrored by Glimbot");
+            return Ok(());
+        }
+
+        let peers = BanSyncGroups::new(dis.db(guild)).peers().await?;
+        if peers.is_empty() {
+            return Ok(());
+        }
+
+        debug!("mirroring ban to {} guilds", peers.len());
+        dis.jobs().enqueue(
+            guild,
+            MirrorBan {
+                origin: guild,
+                user: user.clone(),
+                peers,
+                mirrored: self.mirrored.clone(),
+            },
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+//! Contains bookmarks. Reacting to a message with the bookmark emoji (🔖 by default) DMs the
+//! reacting member a copy of the message with a link back to it, which is handy for keeping answers
+//! from busy help channels.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::channel::Reaction;
+use serenity::model::id::{MessageId, UserId};
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::module::status::GLIM_COLOR;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for the emoji which bookmarks a message.
+pub const BOOKMARK_EMOJI: &str = "bookmark_emoji";
+/// The emoji which bookmarks a message if the guild hasn't set its own.
+pub const DEFAULT_BOOKMARK_EMOJI: &str = "🔖";
+/// The value of [`BOOKMARK_EMOJI`] which turns bookmarks off.
+pub const BOOKMARKS_OFF: &str = "off";
+/// How long before the same message can be bookmarked by the same member again, so removing and
+/// re-adding the reaction doesn't send duplicate DMs.
+pub const BOOKMARK_COOLDOWN: Duration = Duration::from_secs(60 * 60);
+/// The most of a message's text copied into a bookmark, in characters.
+const MAX_BOOKMARK_LEN: usize = 2000;
+/// The most attachments listed in a bookmark, to stay within an embed field's length.
+const MAX_ATTACHMENTS_LISTED: usize = 5;
+
+/// Module which DMs members copies of messages they bookmark.
+#[derive(Default)]
+pub struct BookmarkModule {
+    /// When each member last bookmarked each message.
+    recent: Arc<Mutex<HashMap<(UserId, MessageId), Instant>>>,
+}
+
+impl BookmarkModule {
+    /// Checks whether a member may bookmark a message, marking it as bookmarked if so.
+    fn bookmark_due(&self, user: UserId, message: MessageId) -> bool {
+        let now = Instant::now();
+        let mut recent = self.recent.lock();
+        if let Some(last) = recent.get(&(user, message)) {
+            if now.duration_since(*last) < BOOKMARK_COOLDOWN {
+                return false;
+            }
+        }
+        recent.retain(|_, last| now.duration_since(*last) < BOOKMARK_COOLDOWN);
+        recent.insert((user, message), now);
+        true
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for BookmarkModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "bookmark",
+                "DMs you a copy of messages you react to with the bookmark emoji.",
+            )
+            .with_sensitivity(Sensitivity::Low)
+            .with_reaction_hook(true)
+            .with_config_value(config::Value::<String>::with_default(
+                BOOKMARK_EMOJI,
+                "The emoji members react with to be DMed a copy of a message. Set to off to turn bookmarks off.",
+                || DEFAULT_BOOKMARK_EMOJI.to_string(),
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_reaction(&self, dis: &Dispatch, ctx: &Context, reaction: &Reaction) -> crate::error::Result<()> {
+        let (gid, user) = match (reaction.guild_id, reaction.user_id) {
+            (Some(g), Some(u)) => (g, u),
+            _ => return Ok(()),
+        };
+        let emoji = dis
+            .config_value_t::<String>(BOOKMARK_EMOJI)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        let emoji = emoji.trim();
+        if emoji.eq_ignore_ascii_case(BOOKMARKS_OFF) || reaction.emoji.to_string() != emoji {
+            return Ok(());
+        }
+        if !self.bookmark_due(user, reaction.message_id) {
+            return Ok(());
+        }
+
+        let msg = reaction.message(ctx).await?;
+        let guild_name = ctx
+            .cache
+            .guild_field(gid, |g| g.name.clone())
+            .await
+            .unwrap_or_else(|| "a server".to_string());
+        let channel_name = ctx
+            .cache
+            .guild_channel_field(msg.channel_id, |c| c.name.clone())
+            .await
+            .unwrap_or_else(|| msg.channel_id.to_string());
+        let mut content: String = msg.content.chars().take(MAX_BOOKMARK_LEN).collect();
+        if content.len() < msg.content.len() {
+            content.push('…');
+        }
+        let image = msg
+            .attachments
+            .iter()
+            .find(|a| a.width.is_some())
+            .map(|a| a.url.clone());
+        let files: Vec<String> = msg
+            .attachments
+            .iter()
+            .take(MAX_ATTACHMENTS_LISTED)
+            .map(|a| format!("[{}]({})", a.filename, a.url))
+            .collect();
+        // Messages fetched over HTTP don't say which guild they're in, so the link is built by hand.
+        let link = format!("https://discord.com/channels/{}/{}/{}", gid, msg.channel_id, msg.id);
+
+        let dm = user.create_dm_channel(ctx).await?;
+        dm.send_message(ctx, |m| {
+            m.embed(|e| {
+                e.color(GLIM_COLOR)
+                    .author(|a| a.name(msg.author.tag()).icon_url(msg.author.face()))
+                    .description(content)
+                    .field("Original", format!("[Jump to message]({})", link), false)
+                    .footer(|f| f.text(format!("#{} in {}", channel_name, guild_name)))
+                    .timestamp(msg.timestamp);
+                if !files.is_empty() {
+                    e.field("Attachments", files.join("\n"), false);
+                }
+                if let Some(url) = image {
+                    e.image(url);
+                }
+                e
+            })
+        })
+        .await?;
+        Ok(())
+    }
+}
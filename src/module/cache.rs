@@ -0,0 +1,75 @@
+//! Contains the `cache` command, which lets the bot owner drop cached state, so stale config or
+//! messages can be recovered from without restarting Glimbot.
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::GuildId;
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// Owner command to flush caches.
+pub struct CacheModule;
+
+/// Command to manage Glimbot's caches.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "cache", no_version)]
+enum CacheOpt {
+    /// Forgets cached state, so it's read afresh from the database.
+    Flush {
+        /// A server ID to forget everything cached for, or a config key to forget for every
+        /// server; forgets everything if left out
+        target: Option<String>,
+    },
+}
+
+#[async_trait::async_trait]
+impl Module for CacheModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("cache", "flushes Glimbot's caches.")
+                .with_command(true)
+                .with_sensitivity(Sensitivity::Owner)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = CacheOpt::from_iter_with_help(command)?;
+        let message = match opts {
+            CacheOpt::Flush { target: None } => {
+                dis.flush_caches(None);
+                "Flushed every cache.".to_string()
+            }
+            CacheOpt::Flush { target: Some(t) } => match t.parse::<u64>() {
+                Ok(g) => {
+                    let g = GuildId(g);
+                    dis.flush_caches(Some(g));
+                    format!("Flushed everything cached for {}.", g)
+                }
+                Err(_) => {
+                    // Fails for keys which don't exist.
+                    dis.config_value(&t)?;
+                    dis.config_cache().invalidate_key(&t);
+                    format!("Flushed the cached {} for every server.", t)
+                }
+            },
+        };
+
+        info!("cache flush by {}: {}", orig.author.id, message);
+        let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+}
@@ -1,16 +1,98 @@
 //! Contains the `config` command module for updating per-guild config values.
 
-use itertools::Itertools;
 use once_cell::sync::Lazy;
 use serenity::client::Context;
 use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::misc::Mentionable;
 use serenity::utils::{content_safe, ContentSafeOptions, MessageBuilder};
 use structopt::StructOpt;
 
+use crate::db::config_audit::ConfigAudit;
 use crate::db::DbContext;
+use crate::dispatch::config::Validator;
 use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::moderation::mod_log_channel;
+use crate::module::status::GLIM_COLOR;
+use crate::module::time::GuildClock;
 use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::AtLeastU64;
 use crate::util::ClapExt;
+
+/// The most config values listed on each page of `config list`.
+pub const KEYS_PER_PAGE: usize = 6;
+/// The longest a value is shown in `config list`, in characters; `config show` gives it in full.
+const MAX_LISTED_VALUE_LEN: usize = 200;
+/// The most changes shown on each page of `config history`.
+pub const CHANGES_PER_PAGE: usize = 5;
+/// The longest a value is shown in a config change, in characters.
+const MAX_CHANGE_VALUE_LEN: usize = 400;
+/// The longest embed field value Discord allows.
+const EMBED_FIELD_LEN: usize = 1024;
+
+impl_err!(NoSuchPage, "There aren't that many pages of config values.", true);
+impl_err!(
+    NoSuchHistoryPage,
+    "There aren't that many pages of changes to that value.",
+    true
+);
+
+/// Truncates a string to at most `max` characters, marking it if anything was cut.
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    let mut out: String = s.chars().take(max - 1).collect();
+    out.push('…');
+    out
+}
+
+/// Describes a stored config value for display, truncated to at most `max` characters.
+pub fn describe_value(val: &dyn Validator, v: Option<serde_json::Value>, max: usize) -> String {
+    match v {
+        None => "<unset>".to_string(),
+        Some(v) => val
+            .display_masked(v.clone())
+            .map(|s| truncate(&s, max))
+            .unwrap_or_else(|_| truncate(&v.to_string(), max)),
+    }
+}
+
+/// Records a change to a config value in the audit history and posts it in the mod log, if one is
+/// set.
+pub async fn record_change(
+    dis: &Dispatch,
+    ctx: &Context,
+    gid: GuildId,
+    actor: UserId,
+    val: &dyn Validator,
+    old: Option<serde_json::Value>,
+    new: Option<serde_json::Value>,
+) -> crate::error::Result<()> {
+    ConfigAudit::new(dis.db(gid))
+        .record(val.name(), old.as_ref(), new.as_ref(), actor)
+        .await?;
+
+    if let Ok(chan) = mod_log_channel(dis, gid).await {
+        let before = describe_value(val, old, MAX_CHANGE_VALUE_LEN);
+        let after = describe_value(val, new, MAX_CHANGE_VALUE_LEN);
+        chan.send_message(ctx, |m| {
+            m.embed(|e| {
+                e.color(GLIM_COLOR)
+                    .title(format!("Config changed: {}", val.name()))
+                    .field("By", actor.mention(), false)
+                    .field("Before", before, false)
+                    .field("After", after, false)
+            })
+        })
+        .await
+        .map_err(crate::error::Error::from)
+        .log_error();
+    }
+    Ok(())
+}
+
 /// Module to allow setting configuration values for a guild.
 pub struct ConfigModule;
 
@@ -25,18 +107,35 @@ enum ConfigOpt {
         /// The value to set it to
         value: String,
     },
+    /// Unsets a bot config value, so its default applies again
+    Unset {
+        /// The name of the config value to unset
+        key: String,
+    },
     /// Shows a bot config value
     Show {
         /// The name of the config value to show
         key: String,
     },
-    /// Lists the available config values to be set.
-    List,
+    /// Lists the available config values, with their help and current values.
+    List {
+        /// The page of config values to show.
+        #[structopt(short, long, default_value = "1")]
+        page: AtLeastU64<1>,
+    },
     /// Shows info for config key
     Info {
         /// The name of the config value to show
         key: String,
     },
+    /// Shows who changed a config value and when, newest first.
+    History {
+        /// The name of the config value to show changes to
+        key: String,
+        /// The page of changes to show.
+        #[structopt(short, long, default_value = "1")]
+        page: AtLeastU64<1>,
+    },
 }
 
 #[async_trait::async_trait]
@@ -64,10 +163,32 @@ impl Module for ConfigModule {
             ConfigOpt::Set { key, value } => {
                 let config_val = dis.config_value(&key)?;
                 let new_val = config_val.validate(ctx, orig.guild_id.unwrap(), &value).await?;
-                let ctx = dis.db(gid);
-                config_val.insert_json(new_val, &ctx).await?;
+                let db = dis.db(gid);
+                let old_val = config_val.get_json(&db).await?;
+                config_val.insert_json(new_val.clone(), &db).await?;
+                record_change(dis, ctx, gid, orig.author.id, config_val, old_val, Some(new_val)).await?;
+                if config_val.is_secret() {
+                    // Don't leave the secret sitting in the channel.
+                    orig.delete(ctx).await.map_err(crate::error::Error::from).log_error();
+                    let message = MessageBuilder::new()
+                        .push_codeblock_safe(format!("Set {} to specified value.", &key), None)
+                        .build();
+                    orig.channel_id.say(ctx, message).await?;
+                    return Ok(());
+                }
                 format!("Set {} to specified value.", &key)
             }
+            ConfigOpt::Unset { key } => {
+                let config_val = dis.config_value(&key)?;
+                let db = dis.db(gid);
+                let old_val = config_val.get_json(&db).await?;
+                if db.remove(&key).await? {
+                    record_change(dis, ctx, gid, orig.author.id, config_val, old_val, None).await?;
+                    format!("Unset {}; the default applies again.", &key)
+                } else {
+                    format!("{} wasn't set.", &key)
+                }
+            }
             ConfigOpt::Show { key } => {
                 let config_val = dis.config_value(&key)?;
                 let db = DbContext::new(dis, gid);
@@ -75,14 +196,21 @@ impl Module for ConfigModule {
 
                 match val {
                     None => "<unset>".to_string(),
-                    Some(v) => config_val.display_value(v)?,
+                    Some(v) => config_val.display_masked(v)?,
                 }
             }
-            ConfigOpt::List => dis.config_values().keys().join(", "),
+            ConfigOpt::List { page } => {
+                let page: u64 = page.into();
+                return list(dis, ctx, orig, page as usize).await;
+            }
             ConfigOpt::Info { key } => {
                 let config_val = dis.config_value(&key)?;
                 format!("{}: {}", key, config_val.help())
             }
+            ConfigOpt::History { key, page } => {
+                let page: u64 = page.into();
+                return history(dis, ctx, orig, &key, page as usize).await;
+            }
         };
 
         let message = content_safe(ctx, message, &ContentSafeOptions::default().display_as_member_from(gid)).await;
@@ -91,3 +219,104 @@ impl Module for ConfigModule {
         Ok(())
     }
 }
+
+/// Replies with a page of every config value, giving its help and the value this guild uses.
+async fn list(dis: &Dispatch, ctx: &Context, orig: &Message, page: usize) -> crate::error::Result<()> {
+    let gid = orig.guild_id.unwrap();
+    let db = dis.db(gid);
+    let values = dis.config_values();
+    let pages = (values.len() + KEYS_PER_PAGE - 1) / KEYS_PER_PAGE;
+    if page > pages {
+        return Err(NoSuchPage.into());
+    }
+
+    let mut fields = Vec::with_capacity(KEYS_PER_PAGE);
+    for (name, val) in values.iter().skip((page - 1) * KEYS_PER_PAGE).take(KEYS_PER_PAGE) {
+        let current = match val.get_json(&db).await? {
+            Some(v) => format!("`{}`", truncate(&val.display_masked(v)?, MAX_LISTED_VALUE_LEN)),
+            None => match val.default_json(&db).await? {
+                Some(v) => format!(
+                    "`{}` (default)",
+                    truncate(&val.display_masked(v)?, MAX_LISTED_VALUE_LEN)
+                ),
+                None => "<unset>".to_string(),
+            },
+        };
+        let current = content_safe(ctx, current, &ContentSafeOptions::default().display_as_member_from(gid)).await;
+        let help_len = EMBED_FIELD_LEN - current.chars().count() - "\nValue: ".len();
+        let body = format!("{}\nValue: {}", truncate(val.help(), help_len), current);
+        fields.push((name.to_string(), body, false));
+    }
+
+    let footer = if page < pages {
+        format!("Page {}/{}. Add --page {} for more.", page, pages, page + 1)
+    } else {
+        format!("Page {}/{}.", page, pages)
+    };
+    orig.channel_id
+        .send_message(ctx, |m| {
+            m.reference_message(orig).embed(|e| {
+                e.color(GLIM_COLOR)
+                    .title("Config values")
+                    .fields(fields)
+                    .footer(|f| f.text(footer))
+            })
+        })
+        .await?;
+    Ok(())
+}
+
+/// Replies with a page of the changes made to a config value, newest first.
+async fn history(dis: &Dispatch, ctx: &Context, orig: &Message, key: &str, page: usize) -> crate::error::Result<()> {
+    let gid = orig.guild_id.unwrap();
+    let config_val = dis.config_value(key)?;
+    let audit = ConfigAudit::new(dis.db(gid));
+    let total = audit.count(config_val.name()).await? as usize;
+    if total == 0 {
+        orig.reply(ctx, format!("{} has never been changed.", config_val.name()))
+            .await?;
+        return Ok(());
+    }
+    let pages = (total + CHANGES_PER_PAGE - 1) / CHANGES_PER_PAGE;
+    if page > pages {
+        return Err(NoSuchHistoryPage.into());
+    }
+
+    let changes = audit
+        .history(
+            config_val.name(),
+            ((page - 1) * CHANGES_PER_PAGE) as i64,
+            CHANGES_PER_PAGE as i64,
+        )
+        .await?;
+    let clock = GuildClock::for_guild(dis, gid).await?;
+    let fields: Vec<(String, String, bool)> = changes
+        .into_iter()
+        .map(|c| {
+            let body = format!(
+                "By {}\nBefore: {}\nAfter: {}",
+                c.actor.mention(),
+                describe_value(config_val, c.old_value, MAX_CHANGE_VALUE_LEN),
+                describe_value(config_val, c.new_value, MAX_CHANGE_VALUE_LEN)
+            );
+            (clock.datetime(c.changed_at), truncate(&body, EMBED_FIELD_LEN), false)
+        })
+        .collect();
+
+    let footer = if page < pages {
+        format!("Page {}/{}. Add --page {} for more.", page, pages, page + 1)
+    } else {
+        format!("Page {}/{}.", page, pages)
+    };
+    orig.channel_id
+        .send_message(ctx, |m| {
+            m.reference_message(orig).embed(|e| {
+                e.color(GLIM_COLOR)
+                    .title(format!("Changes to {}", config_val.name()))
+                    .fields(fields)
+                    .footer(|f| f.text(footer))
+            })
+        })
+        .await?;
+    Ok(())
+}
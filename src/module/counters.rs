@@ -0,0 +1,376 @@
+//! Contains counters, named tallies members bump with `!count bump <name>`, like the number of times
+//! the build has broken. Counters belong to the whole server unless they're made for a single channel,
+//! in which case they take precedence over a server counter of the same name in that channel.
+
+use std::borrow::Borrow;
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, UserId};
+use serenity::model::misc::Mentionable;
+use structopt::StructOpt;
+
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::error::DatabaseError;
+use crate::module::persona::react_success;
+use crate::module::privilege::InsufficientUserPrivilege;
+use crate::module::raid::is_staff;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::{AtMostU64, ConstrainedI64};
+use crate::util::ClapExt;
+
+/// The config key for the most counters a guild may have.
+pub const COUNTER_LIMIT: &str = "counter_limit";
+/// The most counters a guild may have.
+pub const MAX_COUNTERS: u64 = 100;
+/// The most counters a guild allows itself, as set with [`COUNTER_LIMIT`].
+pub type CounterLimit = AtMostU64<MAX_COUNTERS>;
+/// The longest counter name, in characters.
+pub const MAX_COUNTER_NAME_LEN: usize = 32;
+/// The most a moderator can add to or take from a counter at once.
+pub const MAX_BUMP: i64 = 1_000_000;
+/// The most characters in each message of a counter listing.
+const MAX_LIST_CHUNK: usize = 1900;
+/// The channel ID counters which belong to the whole guild are stored with.
+const GUILD_WIDE: i64 = 0;
+
+impl_err!(
+    InvalidCounterName,
+    "Counter names must be between 1 and 32 characters long, and may only contain letters, numbers, - and _.",
+    true
+);
+impl_err!(CounterExists, "There's already a counter by that name.", true);
+impl_err!(NoSuchCounter, "There's no counter by that name.", true);
+impl_err!(
+    TooManyCounters,
+    "This server has as many counters as it's allowed. Delete some before adding more.",
+    true
+);
+
+/// A named tally.
+#[derive(Debug, Clone)]
+pub struct Counter {
+    /// The counter's name.
+    pub name: String,
+    /// The channel the counter belongs to, or `None` if it belongs to the whole guild.
+    pub channel: Option<ChannelId>,
+    /// The counter's value.
+    pub value: i64,
+}
+
+/// Converts a channel to the ID it's stored with, where `None` means the whole guild.
+fn stored_channel(channel: Option<ChannelId>) -> i64 {
+    channel.map_or(GUILD_WIDE, |c| c.0 as i64)
+}
+
+/// Converts a stored channel ID back to a channel.
+fn loaded_channel(channel: i64) -> Option<ChannelId> {
+    if channel == GUILD_WIDE {
+        None
+    } else {
+        Some(ChannelId(channel as u64))
+    }
+}
+
+/// Normalizes a counter name, checking it's a valid one.
+fn counter_name(name: &str) -> crate::error::Result<String> {
+    let name = name.trim().to_lowercase();
+    let valid = !name.is_empty()
+        && name.chars().count() <= MAX_COUNTER_NAME_LEN
+        && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(InvalidCounterName.into());
+    }
+    Ok(name)
+}
+
+/// Wrapper around a guild's counters in the database.
+pub struct Counters<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> Counters<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        Counters {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Creates a counter for the whole guild, or for a single channel, erroring if there's already one
+    /// by that name there or the guild has `limit` counters already.
+    pub async fn create(
+        &self,
+        name: &str,
+        channel: Option<ChannelId>,
+        start: i64,
+        creator: UserId,
+        limit: u64,
+    ) -> crate::error::Result<()> {
+        let name = counter_name(name)?;
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) AS counters FROM counters WHERE guild = $1;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_one(self.ctx.conn()?)
+        .await?
+        .unwrap_or_default();
+        if count as u64 >= limit {
+            return Err(TooManyCounters.into());
+        }
+
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO counters (guild, name, channel_id, value, created_by) VALUES ($1, $2, $3, $4, $5);",
+            self.ctx.guild_as_i64(),
+            name,
+            stored_channel(channel),
+            start,
+            creator.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(CounterExists.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Adds to the counter of the given name which applies in a channel, returning it with its new
+    /// value. The channel's own counter is used if it has one, otherwise the guild's.
+    pub async fn bump(&self, name: &str, channel: ChannelId, by: i64) -> crate::error::Result<Counter> {
+        let name = counter_name(name).map_err(|_| NoSuchCounter)?;
+        let row = sqlx::query!(
+            r#"
+            UPDATE counters SET value = value + $4
+            WHERE guild = $1 AND name = $2 AND channel_id = (
+                SELECT channel_id FROM counters
+                WHERE guild = $1 AND name = $2 AND channel_id IN ($3, 0)
+                ORDER BY channel_id DESC
+                LIMIT 1
+            )
+            RETURNING channel_id, value;
+            "#,
+            self.ctx.guild_as_i64(),
+            name,
+            channel.0 as i64,
+            by
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?
+        .ok_or(NoSuchCounter)?;
+        Ok(Counter {
+            name,
+            channel: loaded_channel(row.channel_id),
+            value: row.value,
+        })
+    }
+
+    /// Retrieves the counter of the given name which applies in a channel.
+    pub async fn get(&self, name: &str, channel: ChannelId) -> crate::error::Result<Counter> {
+        let name = counter_name(name).map_err(|_| NoSuchCounter)?;
+        let row = sqlx::query!(
+            r#"
+            SELECT channel_id, value FROM counters
+            WHERE guild = $1 AND name = $2 AND channel_id IN ($3, 0)
+            ORDER BY channel_id DESC
+            LIMIT 1;
+            "#,
+            self.ctx.guild_as_i64(),
+            name,
+            channel.0 as i64
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?
+        .ok_or(NoSuchCounter)?;
+        Ok(Counter {
+            name,
+            channel: loaded_channel(row.channel_id),
+            value: row.value,
+        })
+    }
+
+    /// Deletes a guild's counter, or a channel's.
+    pub async fn delete(&self, name: &str, channel: Option<ChannelId>) -> crate::error::Result<()> {
+        let name = counter_name(name).map_err(|_| NoSuchCounter)?;
+        let res = sqlx::query!(
+            "DELETE FROM counters WHERE guild = $1 AND name = $2 AND channel_id = $3;",
+            self.ctx.guild_as_i64(),
+            name,
+            stored_channel(channel)
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(NoSuchCounter.into());
+        }
+        Ok(())
+    }
+
+    /// Retrieves every counter in the guild, in order of name.
+    pub async fn list(&self) -> crate::error::Result<Vec<Counter>> {
+        let rows = sqlx::query!(
+            "SELECT name, channel_id, value FROM counters WHERE guild = $1 ORDER BY name ASC, channel_id ASC;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_all(self.ctx.conn()?)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| Counter {
+                name: r.name,
+                channel: loaded_channel(r.channel_id),
+                value: r.value,
+            })
+            .collect())
+    }
+}
+
+/// Describes a counter and its value, like `bugs in #dev: 12`.
+fn describe(counter: &Counter) -> String {
+    match counter.channel {
+        Some(c) => format!("{} in {}: {}", counter.name, c.mention(), counter.value),
+        None => format!("{}: {}", counter.name, counter.value),
+    }
+}
+
+#[derive(StructOpt)]
+#[structopt(name = "count", no_version)]
+/// Keeps named tallies. Counters made for a channel take precedence over server counters there.
+enum CountOpt {
+    /// Creates a counter. Moderators only.
+    Create {
+        /// The counter's name.
+        name: String,
+        /// Makes the counter for this channel only.
+        #[structopt(short, long)]
+        channel: bool,
+        /// The value to start at.
+        #[structopt(short, long, default_value = "0", allow_hyphen_values = true)]
+        start: i64,
+    },
+    /// Adds one to a counter.
+    Bump {
+        /// The counter's name.
+        name: String,
+        /// Adds this much instead, which may be negative. Moderators only.
+        #[structopt(short, long, default_value = "1", allow_hyphen_values = true)]
+        by: i64,
+    },
+    /// Shows a counter's value.
+    Show {
+        /// The counter's name.
+        name: String,
+    },
+    /// Lists the server's counters.
+    List,
+    /// Deletes a counter. Moderators only.
+    Delete {
+        /// The counter's name.
+        name: String,
+        /// Deletes this channel's counter rather than the server's.
+        #[structopt(short, long)]
+        channel: bool,
+    },
+}
+
+/// Module which keeps counters.
+pub struct CountersModule;
+
+#[async_trait::async_trait]
+impl Module for CountersModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("count", "keeps named tallies members can bump.")
+                .with_sensitivity(Sensitivity::Medium)
+                .with_command(true)
+                .with_config_value(config::Value::<CounterLimit>::with_default(
+                    COUNTER_LIMIT,
+                    "The most counters this server may have, from 0 to 100.",
+                    || CounterLimit::new(25).unwrap(),
+                ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = CountOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let db = dis.db(gid);
+        let counters = Counters::new(&db);
+
+        let staff_only = match &opts {
+            CountOpt::Create { .. } | CountOpt::Delete { .. } => true,
+            CountOpt::Bump { by, .. } => *by != 1,
+            _ => false,
+        };
+        if staff_only && !is_staff(dis, ctx, gid, orig.author.id).await? {
+            return Err(InsufficientUserPrivilege.into());
+        }
+
+        let this_channel = |c: bool| if c { Some(orig.channel_id) } else { None };
+        match opts {
+            CountOpt::Create { name, channel, start } => {
+                let limit = dis
+                    .config_value_t::<CounterLimit>(COUNTER_LIMIT)?
+                    .get_or_default(&db)
+                    .await?;
+                let limit: u64 = (*limit).into();
+                counters
+                    .create(&name, this_channel(channel), start, orig.author.id, limit)
+                    .await?;
+            }
+            CountOpt::Bump { name, by } => {
+                let by: i64 = ConstrainedI64::<{ -MAX_BUMP }, MAX_BUMP>::new(by)?.into();
+                let counter = counters.bump(&name, orig.channel_id, by).await?;
+                orig.reply(ctx, describe(&counter)).await?;
+                return Ok(());
+            }
+            CountOpt::Show { name } => {
+                let counter = counters.get(&name, orig.channel_id).await?;
+                orig.reply(ctx, describe(&counter)).await?;
+                return Ok(());
+            }
+            CountOpt::List => {
+                let list = counters.list().await?;
+                if list.is_empty() {
+                    orig.reply(ctx, "No counters.").await?;
+                    return Ok(());
+                }
+                // Names can't contain mentions, so only channels are ever mentioned here.
+                let mut chunks = vec![String::new()];
+                for line in list.iter().map(describe) {
+                    let last = chunks.last_mut().unwrap();
+                    if !last.is_empty() && last.len() + line.len() >= MAX_LIST_CHUNK {
+                        chunks.push(line);
+                    } else {
+                        last.push_str(&line);
+                        last.push('\n');
+                    }
+                }
+                for chunk in chunks {
+                    orig.channel_id
+                        .send_message(ctx, |m| m.content(chunk).allowed_mentions(|am| am.empty_parse()))
+                        .await?;
+                }
+                return Ok(());
+            }
+            CountOpt::Delete { name, channel } => counters.delete(&name, this_channel(channel)).await?,
+        }
+
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+//! Contains the `defaults` command module, which lets the bot owner manage instance defaults:
+//! config values that apply to every guild which hasn't set its own.
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// Module to allow the bot owner to set config defaults for every guild.
+pub struct DefaultsModule;
+
+impl_err!(
+    GuildSpecificValue,
+    "That config value refers to something in a single server, so it can't have an instance default.",
+    true
+);
+
+/// Command to manage instance-wide config defaults.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "defaults", no_version)]
+enum DefaultsOpt {
+    /// Sets the default for a config value in every server that hasn't set its own.
+    Set {
+        /// The name of the config value to set
+        key: String,
+        /// The value to set it to
+        value: String,
+    },
+    /// Shows the instance default for a config value
+    Show {
+        /// The name of the config value to show
+        key: String,
+    },
+    /// Removes an instance default, so servers fall back to the built-in default.
+    Unset {
+        /// The name of the config value to unset
+        key: String,
+    },
+    /// Lists the config values which have instance defaults.
+    List,
+}
+
+#[async_trait::async_trait]
+impl Module for DefaultsModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("defaults", "sets configuration defaults for every guild.")
+                .with_command(true)
+                .with_sensitivity(Sensitivity::Owner)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = DefaultsOpt::from_iter_with_help(command)?;
+        let defaults = dis.instance_defaults();
+        let message = match opts {
+            DefaultsOpt::Set { key, value } => {
+                let config_val = dis.config_value(&key)?;
+                if config_val.is_guild_specific() {
+                    return Err(GuildSpecificValue.into());
+                }
+                let new_val = config_val.validate(ctx, orig.guild_id.unwrap(), &value).await?;
+                defaults.set(dis.pool()?, &key, new_val).await?;
+                format!("Set the instance default for {}.", &key)
+            }
+            DefaultsOpt::Show { key } => {
+                let config_val = dis.config_value(&key)?;
+                match defaults.get_json(&key) {
+                    None => "<unset>".to_string(),
+                    Some(v) => config_val.display_masked(v)?,
+                }
+            }
+            DefaultsOpt::Unset { key } => {
+                dis.config_value(&key)?;
+                if defaults.unset(dis.pool()?, &key).await? {
+                    format!("Removed the instance default for {}.", &key)
+                } else {
+                    format!("{} has no instance default.", &key)
+                }
+            }
+            DefaultsOpt::List => {
+                let names = defaults.names();
+                if names.is_empty() {
+                    "No instance defaults are set.".to_string()
+                } else {
+                    names.iter().join(", ")
+                }
+            }
+        };
+
+        let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+//! Contains the `define` command, which looks up English words in the free dictionary at
+//! [dictionaryapi.dev](https://dictionaryapi.dev). Definitions are split into pages of a size each
+//! guild can set, and rendered replies are cached so popular lookups don't hit the API repeatedly.
+
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use reqwest::{StatusCode, Url};
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use structopt::StructOpt;
+
+use crate::dispatch::config;
+use crate::dispatch::results::CommandReply;
+use crate::dispatch::Dispatch;
+use crate::module::status::GLIM_COLOR;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::{AtLeastU64, ConstrainedU64};
+use crate::util::ClapExt;
+
+/// The config key for the number of definitions shown per page.
+pub const DEFINITIONS_PER_PAGE: &str = "definitions_per_page";
+/// The most definitions a guild may show per page.
+pub const MAX_DEFINITIONS_PER_PAGE: u64 = 10;
+/// The number of definitions shown per page, as set with [`DEFINITIONS_PER_PAGE`].
+pub type DefinitionsPerPage = ConstrainedU64<1, MAX_DEFINITIONS_PER_PAGE>;
+/// The longest word or phrase which may be looked up, in characters.
+pub const MAX_WORD_LEN: usize = 64;
+/// How long a rendered `define` reply is reused for. Dictionaries rarely change.
+pub const DEFINE_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// How long to wait for the dictionary before giving up.
+const DICTIONARY_TIMEOUT: Duration = Duration::from_secs(10);
+/// The dictionary's endpoint for English words; the word is appended as a path segment.
+const DICTIONARY_URL: &str = "https://api.dictionaryapi.dev/api/v2/entries/en";
+/// The longest embed field value Discord allows.
+const EMBED_FIELD_LEN: usize = 1024;
+
+/// Client for the dictionary, so lookups can't hang forever.
+static DICTIONARY_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(DICTIONARY_TIMEOUT)
+        .build()
+        .expect("Couldn't build dictionary client")
+});
+
+impl_err!(
+    InvalidWord,
+    "Words to define must be at most 64 characters, and only contain letters, spaces, hyphens and apostrophes.",
+    true
+);
+impl_err!(NoDefinitions, "I couldn't find any definitions for that.", true);
+impl_err!(NoSuchPage, "There aren't that many pages of definitions.", true);
+
+/// An entry returned by the dictionary.
+#[derive(Deserialize, Debug)]
+struct Entry {
+    /// The word defined.
+    word: String,
+    /// How the word is pronounced, if known.
+    #[serde(default)]
+    phonetic: Option<String>,
+    /// The word's meanings, one per part of speech.
+    #[serde(default)]
+    meanings: Vec<Meaning>,
+}
+
+/// A word's meanings as one part of speech.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Meaning {
+    /// The part of speech, like noun.
+    #[serde(default)]
+    part_of_speech: String,
+    /// The definitions.
+    #[serde(default)]
+    definitions: Vec<Sense>,
+}
+
+/// A single definition.
+#[derive(Deserialize, Debug)]
+struct Sense {
+    /// The definition's text.
+    definition: String,
+    /// An example of the word used this way.
+    #[serde(default)]
+    example: Option<String>,
+}
+
+/// A definition ready to be shown, along with its part of speech.
+#[derive(Debug)]
+struct Definition {
+    /// The part of speech, like noun.
+    part_of_speech: String,
+    /// The definition's text.
+    text: String,
+    /// An example of the word used this way.
+    example: Option<String>,
+}
+
+/// Checks a word is worth looking up, normalizing its spacing.
+fn normalize_word(words: &[String]) -> crate::error::Result<String> {
+    let word = words.join(" ").trim().to_lowercase();
+    let valid = !word.is_empty()
+        && word.chars().count() <= MAX_WORD_LEN
+        && word
+            .chars()
+            .all(|c| c.is_alphabetic() || c == ' ' || c == '-' || c == '\'');
+    if !valid {
+        return Err(InvalidWord.into());
+    }
+    Ok(word)
+}
+
+/// Looks up a word, returning it as the dictionary spells it, its pronunciation, and its
+/// definitions.
+async fn lookup(word: &str) -> crate::error::Result<(String, Option<String>, Vec<Definition>)> {
+    let mut url = Url::parse(DICTIONARY_URL).expect("Invalid dictionary URL");
+    url.path_segments_mut()
+        .expect("Dictionary URL can't have a path")
+        .push(word);
+
+    let resp = DICTIONARY_CLIENT.get(url).send().await?;
+    if resp.status() == StatusCode::NOT_FOUND {
+        return Err(NoDefinitions.into());
+    }
+    let body = resp.error_for_status()?.text().await?;
+    let entries: Vec<Entry> = serde_json::from_str(&body)?;
+
+    let spelling = entries.first().map_or_else(|| word.to_string(), |e| e.word.clone());
+    let phonetic = entries.iter().find_map(|e| e.phonetic.clone());
+    let definitions: Vec<Definition> = entries
+        .into_iter()
+        .flat_map(|e| e.meanings)
+        .flat_map(|m| {
+            let pos = m.part_of_speech;
+            m.definitions.into_iter().map(move |d| Definition {
+                part_of_speech: pos.clone(),
+                text: d.definition,
+                example: d.example,
+            })
+        })
+        .collect();
+    if definitions.is_empty() {
+        return Err(NoDefinitions.into());
+    }
+    Ok((spelling, phonetic, definitions))
+}
+
+/// Renders a definition as an embed field value, truncated to fit.
+fn definition_field(def: &Definition) -> String {
+    let mut value = def.text.clone();
+    if let Some(ex) = &def.example {
+        value.push_str(&format!("\n*{}*", ex));
+    }
+    if value.chars().count() > EMBED_FIELD_LEN {
+        value = value.chars().take(EMBED_FIELD_LEN - 1).collect();
+        value.push('…');
+    }
+    value
+}
+
+/// Looks up the definitions of an English word or phrase.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "define", no_version)]
+pub struct DefineOpt {
+    /// The word or phrase to define.
+    #[structopt(required = true)]
+    word: Vec<String>,
+    /// The page of definitions to show.
+    #[structopt(short, long, default_value = "1")]
+    page: AtLeastU64<1>,
+}
+
+/// Adds the `define` command.
+pub struct DefineModule;
+
+#[async_trait::async_trait]
+impl Module for DefineModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("define", "looks up the definitions of English words.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_command(true)
+                .with_result_cache(DEFINE_CACHE_TTL)
+                .with_config_value(config::Value::<DefinitionsPerPage>::with_default(
+                    DEFINITIONS_PER_PAGE,
+                    "The number of definitions `define` shows per page, from 1 to 10.",
+                    || DefinitionsPerPage::new(3).unwrap(),
+                ))
+        });
+        &INFO
+    }
+
+    async fn render(
+        &self,
+        dis: &Dispatch,
+        _ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<CommandReply> {
+        let gid = orig.guild_id.unwrap();
+        let opts = DefineOpt::from_iter_with_help(command)?;
+        let word = normalize_word(&opts.word)?;
+        let per_page = dis
+            .config_value_t::<DefinitionsPerPage>(DEFINITIONS_PER_PAGE)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        let per_page: u64 = (*per_page).into();
+        let per_page = per_page as usize;
+
+        let (spelling, phonetic, definitions) = lookup(&word).await?;
+        let pages = (definitions.len() + per_page - 1) / per_page;
+        let page: u64 = opts.page.into();
+        let page = page as usize;
+        if page > pages {
+            return Err(NoSuchPage.into());
+        }
+
+        let start = (page - 1) * per_page;
+        let fields: Vec<(String, String, bool)> = definitions
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(per_page)
+            .map(|(i, d)| (format!("{}. {}", i + 1, d.part_of_speech), definition_field(d), false))
+            .collect();
+        let footer = if page < pages {
+            format!("Page {}/{}. Add --page {} for more.", page, pages, page + 1)
+        } else {
+            format!("Page {}/{}. Definitions from dictionaryapi.dev.", page, pages)
+        };
+
+        Ok(CommandReply::embed(|e| {
+            e.color(GLIM_COLOR)
+                .title(spelling)
+                .fields(fields)
+                .footer(|f| f.text(footer));
+            if let Some(p) = phonetic {
+                e.description(p);
+            }
+            e
+        }))
+    }
+}
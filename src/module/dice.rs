@@ -0,0 +1,393 @@
+//! Contains the dice roller. Rolls are written in the usual notation, like `2d6+3` or `4d6kh3`, parsed
+//! into a [`Roll`], checked against the guild's [`DiceConfig`] limits and rolled, with each die shown
+//! in the reply.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use rand::Rng;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use structopt::StructOpt;
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::module::status::GLIM_COLOR;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// The config key for grabbing a [`DiceConfig`].
+pub const DICE_KEY: &str = "dice";
+/// The highest [`DiceConfig::max_dice`] a guild may set.
+pub const MAX_DICE_LIMIT: u32 = 1000;
+/// The highest [`DiceConfig::max_sides`] a guild may set.
+pub const MAX_SIDES_LIMIT: u32 = 1_000_000;
+/// The most terms a roll may have, like the three in `1d20+1d4+2`.
+pub const MAX_TERMS: usize = 20;
+/// The longest list of individual dice shown in a reply, in characters.
+const MAX_ROLLS_SHOWN: usize = 1500;
+
+impl_err!(
+    InvalidDiceConfig,
+    "Dice limits must be a JSON object like {\"max_dice\": 100, \"max_sides\": 1000}, with max_dice between 1 and 1000 and max_sides between 2 and 1000000.",
+    true
+);
+impl_err!(
+    InvalidRoll,
+    "Rolls look like 2d6+3: dice (NdM, or dM for one die), optionally keeping the highest or lowest few (4d6kh3, 2d20kl1), and whole numbers, joined by + or -.",
+    true
+);
+impl_err!(
+    RollTooLarge,
+    "That roll is over this server's limits on the number of dice or sides.",
+    true
+);
+
+/// Per-guild limits on the size of rolls.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DiceConfig {
+    /// The most dice a single roll may use, across all of its terms.
+    #[serde(default = "default_max_dice")]
+    pub max_dice: u32,
+    /// The most sides a die may have.
+    #[serde(default = "default_max_sides")]
+    pub max_sides: u32,
+}
+
+#[doc(hidden)]
+fn default_max_dice() -> u32 {
+    100
+}
+
+#[doc(hidden)]
+fn default_max_sides() -> u32 {
+    1000
+}
+
+impl Default for DiceConfig {
+    fn default() -> Self {
+        DiceConfig {
+            max_dice: default_max_dice(),
+            max_sides: default_max_sides(),
+        }
+    }
+}
+
+impl FromStr for DiceConfig {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conf: DiceConfig = serde_json::from_str(s).map_err(|_| InvalidDiceConfig)?;
+        if !(1..=MAX_DICE_LIMIT).contains(&conf.max_dice) || !(2..=MAX_SIDES_LIMIT).contains(&conf.max_sides) {
+            return Err(InvalidDiceConfig.into());
+        }
+        Ok(conf)
+    }
+}
+
+impl fmt::Display for DiceConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Which dice of a group count towards the total.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Keep {
+    /// Keeps the highest few dice.
+    Highest(u32),
+    /// Keeps the lowest few dice.
+    Lowest(u32),
+}
+
+/// A single term of a roll.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Term {
+    /// A group of identical dice, like `4d6kh3`.
+    Dice {
+        /// The number of dice.
+        count: u32,
+        /// The number of sides on each die.
+        sides: u32,
+        /// Which dice count, if not all of them.
+        keep: Option<Keep>,
+    },
+    /// A fixed number.
+    Constant(u32),
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Dice { count, sides, keep } => {
+                write!(f, "{}d{}", count, sides)?;
+                match keep {
+                    Some(Keep::Highest(n)) => write!(f, "kh{}", n),
+                    Some(Keep::Lowest(n)) => write!(f, "kl{}", n),
+                    None => Ok(()),
+                }
+            }
+            Term::Constant(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// A parsed roll: terms which are added or, if negated, subtracted.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Roll {
+    /// Each term along with whether it's subtracted.
+    terms: Vec<(bool, Term)>,
+}
+
+/// Parses a non-empty run of digits at the start of `s`, returning the number and the rest of `s`.
+fn take_number(s: &str) -> Option<(u32, &str)> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let n = s[..end].parse().ok()?;
+    Some((n, &s[end..]))
+}
+
+impl FromStr for Term {
+    type Err = InvalidRoll;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, rest) = match take_number(s) {
+            Some((n, rest)) => (Some(n), rest),
+            None => (None, s),
+        };
+        let rest = match rest.strip_prefix('d') {
+            Some(r) => r,
+            None if rest.is_empty() => return count.map(Term::Constant).ok_or(InvalidRoll),
+            None => return Err(InvalidRoll),
+        };
+
+        let (sides, rest) = take_number(rest).ok_or(InvalidRoll)?;
+        let count = count.unwrap_or(1);
+        let keep = if rest.is_empty() {
+            None
+        } else if let Some(r) = rest.strip_prefix("kh") {
+            Some(Keep::Highest(r.parse().map_err(|_| InvalidRoll)?))
+        } else if let Some(r) = rest.strip_prefix("kl") {
+            Some(Keep::Lowest(r.parse().map_err(|_| InvalidRoll)?))
+        } else {
+            return Err(InvalidRoll);
+        };
+
+        let kept = match keep {
+            Some(Keep::Highest(n)) | Some(Keep::Lowest(n)) => n,
+            None => count,
+        };
+        if count == 0 || sides == 0 || kept == 0 || kept > count {
+            return Err(InvalidRoll);
+        }
+        Ok(Term::Dice { count, sides, keep })
+    }
+}
+
+impl FromStr for Roll {
+    type Err = InvalidRoll;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s: String = s
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        let mut terms = Vec::new();
+        let mut negated = false;
+        let mut start = 0;
+        for (i, c) in s.char_indices().chain(std::iter::once((s.len(), '+'))) {
+            if c != '+' && c != '-' {
+                continue;
+            }
+            // A leading sign applies to the first term rather than ending an empty one.
+            if i == 0 && terms.is_empty() {
+                negated = c == '-';
+                start = 1;
+                continue;
+            }
+            terms.push((negated, s[start..i].parse()?));
+            negated = c == '-';
+            start = i + 1;
+        }
+
+        if terms.is_empty() || terms.len() > MAX_TERMS {
+            return Err(InvalidRoll);
+        }
+        Ok(Roll { terms })
+    }
+}
+
+impl fmt::Display for Roll {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, (negated, term)) in self.terms.iter().enumerate() {
+            match (i, negated) {
+                (0, true) => write!(f, "-")?,
+                (0, false) => {}
+                (_, true) => write!(f, " - ")?,
+                (_, false) => write!(f, " + ")?,
+            }
+            write!(f, "{}", term)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of rolling a single term.
+#[derive(Debug, Clone)]
+struct TermResult {
+    /// Each die rolled, along with whether it counts towards the total.
+    rolls: Vec<(u32, bool)>,
+    /// The term's contribution to the total, before negation.
+    value: i64,
+}
+
+impl Roll {
+    /// Checks that the roll is within a guild's limits.
+    pub fn check(&self, conf: &DiceConfig) -> crate::error::Result<()> {
+        let mut dice = 0u32;
+        for (_, term) in &self.terms {
+            if let Term::Dice { count, sides, .. } = term {
+                dice = dice.saturating_add(*count);
+                if *sides > conf.max_sides {
+                    return Err(RollTooLarge.into());
+                }
+            }
+        }
+        if dice > conf.max_dice {
+            return Err(RollTooLarge.into());
+        }
+        Ok(())
+    }
+
+    /// Rolls every term, returning each term's result along with the total.
+    fn roll(&self, rng: &mut impl Rng) -> (Vec<TermResult>, i64) {
+        let mut total = 0;
+        let results: Vec<TermResult> = self
+            .terms
+            .iter()
+            .map(|(negated, term)| {
+                let res = match *term {
+                    Term::Constant(n) => TermResult {
+                        rolls: Vec::new(),
+                        value: n as i64,
+                    },
+                    Term::Dice { count, sides, keep } => {
+                        let rolled: Vec<u32> = (0..count).map(|_| rng.gen_range(1..=sides)).collect();
+                        let mut order: Vec<usize> = (0..rolled.len()).collect();
+                        order.sort_by_key(|i| rolled[*i]);
+                        let kept: Vec<usize> = match keep {
+                            Some(Keep::Highest(n)) => order[order.len() - n as usize..].to_vec(),
+                            Some(Keep::Lowest(n)) => order[..n as usize].to_vec(),
+                            None => order,
+                        };
+                        let rolls: Vec<(u32, bool)> = rolled
+                            .iter()
+                            .enumerate()
+                            .map(|(i, r)| (*r, kept.contains(&i)))
+                            .collect();
+                        let value = rolls.iter().filter(|(_, k)| *k).map(|(r, _)| *r as i64).sum();
+                        TermResult { rolls, value }
+                    }
+                };
+                if *negated {
+                    total -= res.value;
+                } else {
+                    total += res.value;
+                }
+                res
+            })
+            .collect();
+        (results, total)
+    }
+}
+
+/// Renders the individual dice of a roll, striking through dice which were dropped.
+fn render_rolls(roll: &Roll, results: &[TermResult]) -> String {
+    let mut out = String::new();
+    for ((_, term), res) in roll.terms.iter().zip(results) {
+        if res.rolls.is_empty() {
+            continue;
+        }
+        let dice: Vec<String> = res
+            .rolls
+            .iter()
+            .map(|(r, kept)| if *kept { r.to_string() } else { format!("~~{}~~", r) })
+            .collect();
+        let line = format!("{}: [{}] = {}\n", term, dice.join(", "), res.value);
+        if out.len() + line.len() > MAX_ROLLS_SHOWN {
+            out.push('…');
+            break;
+        }
+        out.push_str(&line);
+    }
+    out
+}
+
+/// Rolls dice, like `2d6+3`. `4d6kh3` keeps the highest three of four dice, and `2d20kl1` the lowest of two.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "roll", no_version)]
+pub struct RollOpt {
+    /// The dice to roll. Spaces are ignored.
+    #[structopt(required = true)]
+    dice: Vec<String>,
+}
+
+/// Adds the `roll` command.
+pub struct DiceModule;
+
+#[async_trait::async_trait]
+impl Module for DiceModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("roll", "rolls dice, like 2d6+3.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_command(true)
+                .with_config_value(config::Value::<DiceConfig>::with_default(
+                    DICE_KEY,
+                    "A JSON object limiting the size of rolls, e.g. {\"max_dice\": 100, \"max_sides\": 1000}.",
+                    Default::default,
+                ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = RollOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let roll: Roll = opts.dice.join("").parse()?;
+
+        let conf = dis
+            .config_value_t::<DiceConfig>(DICE_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        roll.check(&conf)?;
+
+        let (results, total) = roll.roll(&mut rand::thread_rng());
+        let rolls = render_rolls(&roll, &results);
+        orig.channel_id
+            .send_message(ctx, |m| {
+                m.reference_message(orig).embed(|e| {
+                    e.color(GLIM_COLOR)
+                        .title(format!("🎲 {}", roll))
+                        .field("Total", total, false);
+                    if !rolls.is_empty() {
+                        e.description(rolls);
+                    }
+                    e
+                })
+            })
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Contains the duplicate filter, which catches copypasta: the same text posted over and over,
+//! either by one user or by many users at once, as often happens in raids. Messages are compared
+//! by the content hash kept in the message cache, so copies which only differ in case, spacing or
+//! invisible characters are caught too.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::ChannelId;
+
+use crate::dispatch::config;
+use crate::dispatch::message_info::content_hash;
+use crate::dispatch::Dispatch;
+use crate::module::filter::{handle_filtered, FilterAction, FilterModule};
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing a [`DuplicateFilterConfig`].
+pub const DUPLICATE_FILTER_KEY: &str = "duplicate_filter";
+/// Default number of copies one user may post within the window.
+pub const DEFAULT_USER_THRESHOLD: usize = 3;
+/// Default number of copies everyone together may post within the window.
+pub const DEFAULT_GUILD_THRESHOLD: usize = 5;
+/// Default window copies are counted within.
+pub const DEFAULT_WINDOW: time::Duration = time::Duration::from_secs(60);
+/// Default length, in UTF-8 code points, below which messages are never treated as copypasta.
+pub const DEFAULT_MIN_LENGTH: usize = 10;
+
+/// Configuration for the duplicate filter.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DuplicateFilterConfig {
+    /// Whether the duplicate filter is on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// The most copies of a message one user may post within `window`. 0 turns the check off.
+    #[serde(default = "default_user_threshold")]
+    pub user_threshold: usize,
+    /// The most copies of a message everyone together may post within `window`. 0 turns the
+    /// check off.
+    #[serde(default = "default_guild_threshold")]
+    pub guild_threshold: usize,
+    /// How far back copies are counted.
+    #[serde(with = "humantime_serde", default = "default_window")]
+    pub window: time::Duration,
+    /// Messages shorter than this are never treated as copypasta, so "lol" and "gg" are left alone.
+    #[serde(default = "default_min_length")]
+    pub min_length: usize,
+    /// Channels duplicates may be posted in.
+    #[serde(default)]
+    pub exempt_channels: Vec<ChannelId>,
+    /// What happens to the author of a removed duplicate.
+    #[serde(default = "default_action")]
+    pub action: FilterAction,
+}
+
+#[doc(hidden)]
+fn default_user_threshold() -> usize {
+    DEFAULT_USER_THRESHOLD
+}
+
+#[doc(hidden)]
+fn default_guild_threshold() -> usize {
+    DEFAULT_GUILD_THRESHOLD
+}
+
+#[doc(hidden)]
+fn default_window() -> time::Duration {
+    DEFAULT_WINDOW
+}
+
+#[doc(hidden)]
+fn default_min_length() -> usize {
+    DEFAULT_MIN_LENGTH
+}
+
+#[doc(hidden)]
+fn default_action() -> FilterAction {
+    FilterAction::Delete
+}
+
+impl Default for DuplicateFilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            user_threshold: DEFAULT_USER_THRESHOLD,
+            guild_threshold: DEFAULT_GUILD_THRESHOLD,
+            window: DEFAULT_WINDOW,
+            min_length: DEFAULT_MIN_LENGTH,
+            exempt_channels: Vec::new(),
+            action: default_action(),
+        }
+    }
+}
+
+impl FromStr for DuplicateFilterConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for DuplicateFilterConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// How many copies of a message were seen within the window, including the message itself.
+#[derive(Copy, Clone, Debug, Default)]
+struct Copies {
+    /// Copies posted by the message's author.
+    by_author: usize,
+    /// Copies posted by anyone.
+    by_anyone: usize,
+}
+
+/// Counts the copies of a message in the guild's message cache.
+fn count_copies(dis: &Dispatch, msg: &Message, window: time::Duration) -> Copies {
+    let gid = match msg.guild_id {
+        Some(g) => g,
+        None => return Copies::default(),
+    };
+    let window = chrono::Duration::from_std(window).unwrap_or_else(|_| chrono::Duration::max_value());
+    let since = Utc::now().checked_sub_signed(window).unwrap_or(chrono::MIN_DATETIME);
+    let hash = content_hash(&msg.content);
+
+    dis.message_cache()
+        .get(&gid)
+        .map(|cv| {
+            cv.snapshot()
+                .iter()
+                .filter(|m| m.timestamp >= since && m.content_hash == hash)
+                .fold(Copies::default(), |c, m| Copies {
+                    by_author: c.by_author + (m.user == msg.author.id) as usize,
+                    by_anyone: c.by_anyone + 1,
+                })
+        })
+        .unwrap_or_default()
+}
+
+/// Module which removes messages repeated too often.
+pub struct DuplicateFilterModule;
+
+#[async_trait::async_trait]
+impl Module for DuplicateFilterModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "duplicate-filter",
+                "removes copypasta posted over and over by one or many users.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_message_hook(true)
+            .with_config_value(config::Value::<DuplicateFilterConfig>::with_default(
+                DUPLICATE_FILTER_KEY,
+                "A JSON object configuring the duplicate filter, e.g. {\"enabled\": true, \"user_threshold\": 3, \"guild_threshold\": 5, \"window\": \"1m\", \"min_length\": 10, \"exempt_channels\": [], \"action\": \"Delete\"}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+
+        let config = dis
+            .config_value_t::<DuplicateFilterConfig>(DUPLICATE_FILTER_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !config.enabled
+            || orig.content.trim().chars().count() < config.min_length.max(1)
+            || config.exempt_channels.contains(&orig.channel_id)
+        {
+            return Ok(());
+        }
+
+        let copies = count_copies(dis, orig, config.window);
+        let over = |threshold: usize, count: usize| threshold > 0 && count > threshold;
+        let why = if over(config.user_threshold, copies.by_author) {
+            format!("was posted {} times by its author", copies.by_author)
+        } else if over(config.guild_threshold, copies.by_anyone) {
+            format!("was posted {} times by members of the server", copies.by_anyone)
+        } else {
+            return Ok(());
+        };
+
+        if FilterModule::is_exempt(dis, ctx, orig, gid, self.info().name).await? {
+            trace!("exempt user posted a duplicate");
+            return Ok(());
+        }
+
+        debug!("message {}", why);
+        handle_filtered(dis, orig, self.info().name, why, config.action).await
+    }
+}
@@ -0,0 +1,424 @@
+//! Contains a simple per-guild currency. Members collect an allowance with `!daily`, check what they
+//! have with `!balance`, and pay each other with `!give`. Other modules can charge members through
+//! [`charge`] and [`refund`], e.g. members who aren't moderators can pay to create tags. Balances are
+//! only ever changed by single statements or transactions, so they can't go negative or be spent
+//! twice.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, UserId};
+use structopt::StructOpt;
+
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::config::{FromStrWithCtx, VerifiedUser};
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::ConstrainedU64;
+use crate::util::ClapExt;
+
+/// The config key for grabbing an [`EconomyConfig`].
+pub const ECONOMY_KEY: &str = "economy";
+/// How long members wait between collecting their daily allowance, in hours.
+pub const DAILY_INTERVAL_HOURS: i32 = 24;
+/// The largest amount any one payment or allowance may be.
+pub const MAX_AMOUNT: u64 = 1_000_000_000;
+/// The longest currency name, in characters.
+pub const MAX_CURRENCY_NAME_LEN: usize = 32;
+
+impl_err!(
+    EconomyDisabled,
+    "This server doesn't have the economy turned on; see the economy config value.",
+    true
+);
+impl_err!(InsufficientFunds, "You don't have enough to pay for that.", true);
+impl_err!(
+    InvalidEconomyConfig,
+    "The currency name must be between 1 and 32 characters long, and amounts at most 1000000000.",
+    true
+);
+impl_err!(InvalidPayee, "You can't give to yourself or to bots.", true);
+
+/// Configuration for a guild's currency.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EconomyConfig {
+    /// Whether the economy is on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// What the currency is called.
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    /// How much members collect with `!daily`.
+    #[serde(default = "default_daily")]
+    pub daily: u64,
+    /// How much members who aren't moderators pay to create a tag. Only moderators can create tags
+    /// if this is 0.
+    #[serde(default)]
+    pub tag_cost: u64,
+}
+
+#[doc(hidden)]
+fn default_currency() -> String {
+    "coins".to_string()
+}
+
+#[doc(hidden)]
+fn default_daily() -> u64 {
+    100
+}
+
+impl Default for EconomyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            currency: default_currency(),
+            daily: default_daily(),
+            tag_cost: 0,
+        }
+    }
+}
+
+impl FromStr for EconomyConfig {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conf: EconomyConfig = serde_json::from_str(s)?;
+        let name_len = conf.currency.trim().chars().count();
+        if name_len == 0 || name_len > MAX_CURRENCY_NAME_LEN || conf.daily > MAX_AMOUNT || conf.tag_cost > MAX_AMOUNT {
+            return Err(InvalidEconomyConfig.into());
+        }
+        Ok(conf)
+    }
+}
+
+impl fmt::Display for EconomyConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+impl EconomyConfig {
+    /// Formats an amount of the currency.
+    pub fn amount(&self, amount: u64) -> String {
+        format!("{} {}", amount, self.currency.trim())
+    }
+}
+
+/// Wrapper around members' balances in the database.
+pub struct Wallets<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> Wallets<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        Wallets {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Retrieves a member's balance.
+    pub async fn balance(&self, user: UserId) -> crate::error::Result<u64> {
+        let balance = sqlx::query_scalar!(
+            "SELECT balance FROM balances WHERE guild = $1 AND user_id = $2;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+        Ok(balance.unwrap_or_default() as u64)
+    }
+
+    /// Adds to a member's balance, returning the new balance.
+    pub async fn deposit(&self, user: UserId, amount: u64) -> crate::error::Result<u64> {
+        let balance = sqlx::query_scalar!(
+            r#"
+            INSERT INTO balances (guild, user_id, balance) VALUES ($1, $2, $3)
+            ON CONFLICT (guild, user_id) DO UPDATE SET balance = balances.balance + EXCLUDED.balance
+            RETURNING balance;
+            "#,
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            amount as i64
+        )
+        .fetch_one(self.ctx.conn()?)
+        .await?;
+        Ok(balance as u64)
+    }
+
+    /// Takes from a member's balance, returning the new balance. Errors without taking anything if
+    /// the member can't afford it.
+    pub async fn withdraw(&self, user: UserId, amount: u64) -> crate::error::Result<u64> {
+        let balance = sqlx::query_scalar!(
+            r#"
+            UPDATE balances SET balance = balance - $3
+            WHERE guild = $1 AND user_id = $2 AND balance >= $3
+            RETURNING balance;
+            "#,
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            amount as i64
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?
+        .ok_or(InsufficientFunds)?;
+        Ok(balance as u64)
+    }
+
+    /// Moves an amount from one member's balance to another's, returning the payer's new balance.
+    /// Either both balances change or neither does.
+    pub async fn transfer(&self, from: UserId, to: UserId, amount: u64) -> crate::error::Result<u64> {
+        let mut tx = self.ctx.conn()?.begin().await?;
+        let balance = sqlx::query_scalar!(
+            r#"
+            UPDATE balances SET balance = balance - $3
+            WHERE guild = $1 AND user_id = $2 AND balance >= $3
+            RETURNING balance;
+            "#,
+            self.ctx.guild_as_i64(),
+            from.0 as i64,
+            amount as i64
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(InsufficientFunds)?;
+        sqlx::query!(
+            r#"
+            INSERT INTO balances (guild, user_id, balance) VALUES ($1, $2, $3)
+            ON CONFLICT (guild, user_id) DO UPDATE SET balance = balances.balance + EXCLUDED.balance;
+            "#,
+            self.ctx.guild_as_i64(),
+            to.0 as i64,
+            amount as i64
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(balance as u64)
+    }
+
+    /// Pays a member their daily allowance, returning their new balance, or `None` if they've
+    /// already collected it within the last [`DAILY_INTERVAL_HOURS`].
+    pub async fn claim_daily(&self, user: UserId, amount: u64) -> crate::error::Result<Option<u64>> {
+        let balance = sqlx::query_scalar!(
+            r#"
+            INSERT INTO balances (guild, user_id, balance, last_daily) VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (guild, user_id) DO UPDATE
+                SET balance = balances.balance + EXCLUDED.balance,
+                    last_daily = NOW()
+                WHERE balances.last_daily IS NULL
+                   OR balances.last_daily <= NOW() - make_interval(hours => $4)
+            RETURNING balance;
+            "#,
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            amount as i64,
+            DAILY_INTERVAL_HOURS
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+        Ok(balance.map(|b| b as u64))
+    }
+
+    /// Retrieves when a member last collected their daily allowance, if ever.
+    pub async fn last_daily(&self, user: UserId) -> crate::error::Result<Option<DateTime<Utc>>> {
+        let last = sqlx::query_scalar!(
+            "SELECT last_daily FROM balances WHERE guild = $1 AND user_id = $2;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+        Ok(last.flatten())
+    }
+}
+
+/// Retrieves a guild's economy config, erroring if the economy is off.
+pub async fn economy_config(dis: &Dispatch, guild: GuildId) -> crate::error::Result<EconomyConfig> {
+    let conf = dis
+        .config_value_t::<EconomyConfig>(ECONOMY_KEY)?
+        .get_or_default(&dis.db(guild))
+        .await?;
+    if !conf.enabled {
+        return Err(EconomyDisabled.into());
+    }
+    Ok(EconomyConfig::clone(&conf))
+}
+
+/// Charges a member for something, erroring without charging them if they can't afford it or the
+/// economy is off. Charging nothing always succeeds.
+pub async fn charge(dis: &Dispatch, guild: GuildId, user: UserId, amount: u64) -> crate::error::Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    economy_config(dis, guild).await?;
+    Wallets::new(dis.db(guild)).withdraw(user, amount).await?;
+    Ok(())
+}
+
+/// Gives back what a member was charged, such as when what they paid for couldn't be done.
+pub async fn refund(dis: &Dispatch, guild: GuildId, user: UserId, amount: u64) -> crate::error::Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    Wallets::new(dis.db(guild)).deposit(user, amount).await?;
+    Ok(())
+}
+
+/// Command to check a balance.
+#[derive(StructOpt)]
+#[structopt(name = "balance", no_version)]
+struct BalanceOpt {
+    /// Whose balance to check. Your own if omitted.
+    user: Option<String>,
+}
+
+/// Shows members' balances.
+pub struct BalanceModule;
+
+#[async_trait::async_trait]
+impl Module for BalanceModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("balance", "shows how much of the server's currency a member has.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_command(true)
+                .with_config_value(config::Value::<EconomyConfig>::with_default(
+                    ECONOMY_KEY,
+                    "A JSON object configuring the server's currency, e.g. {\"enabled\": true, \"currency\": \"coins\", \"daily\": 100, \"tag_cost\": 500}. Set tag_cost to let members who aren't moderators pay to create tags.",
+                    Default::default,
+                ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = BalanceOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let conf = economy_config(dis, gid).await?;
+        let user = match opts.user {
+            Some(u) => VerifiedUser::from_str_with_ctx(&u, ctx, gid).await?.into_inner(),
+            None => orig.author.id,
+        };
+
+        let balance = Wallets::new(dis.db(gid)).balance(user).await?;
+        let reply = if user == orig.author.id {
+            format!("You have {}.", conf.amount(balance))
+        } else {
+            format!("They have {}.", conf.amount(balance))
+        };
+        orig.reply(ctx, reply).await?;
+        Ok(())
+    }
+}
+
+/// Pays members their daily allowance.
+pub struct DailyModule;
+
+#[async_trait::async_trait]
+impl Module for DailyModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("daily", "collects your daily allowance of the server's currency.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(&self, dis: &Dispatch, ctx: &Context, orig: &Message, _: Vec<String>) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let conf = economy_config(dis, gid).await?;
+        let wallets = Wallets::new(dis.db(gid));
+
+        let reply = match wallets.claim_daily(orig.author.id, conf.daily).await? {
+            Some(balance) => format!(
+                "You collected {} and now have {}.",
+                conf.amount(conf.daily),
+                conf.amount(balance)
+            ),
+            None => {
+                let next = wallets
+                    .last_daily(orig.author.id)
+                    .await?
+                    .map(|l| l + chrono::Duration::hours(DAILY_INTERVAL_HOURS as i64))
+                    .unwrap_or_else(Utc::now);
+                format!(
+                    "You've already collected today's allowance. Come back at {}.",
+                    next.format("%Y-%m-%d %H:%M UTC")
+                )
+            }
+        };
+        orig.reply(ctx, reply).await?;
+        Ok(())
+    }
+}
+
+/// Command to pay another member.
+#[derive(StructOpt)]
+#[structopt(name = "give", no_version)]
+struct GiveOpt {
+    /// Who to pay.
+    user: String,
+    /// How much to pay them.
+    amount: ConstrainedU64<1, { MAX_AMOUNT }>,
+}
+
+/// Lets members pay each other.
+pub struct GiveModule;
+
+#[async_trait::async_trait]
+impl Module for GiveModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("give", "gives some of your currency to another member.")
+                .with_sensitivity(Sensitivity::Medium)
+                .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = GiveOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let conf = economy_config(dis, gid).await?;
+        let payee = VerifiedUser::from_str_with_ctx(&opts.user, ctx, gid)
+            .await?
+            .into_inner();
+        if payee == orig.author.id || payee.to_user(ctx).await?.bot {
+            return Err(InvalidPayee.into());
+        }
+
+        let amount = opts.amount.into();
+        let balance = Wallets::new(dis.db(gid))
+            .transfer(orig.author.id, payee, amount)
+            .await?;
+        let reply = format!("Gave {}. You now have {}.", conf.amount(amount), conf.amount(balance));
+        orig.reply(ctx, reply).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! Contains emoji management, letting moderators add emoji from a link or attachment, copy ("steal")
+//! custom emoji used in messages, and remove emoji, without leaving Discord's chat. Glimbot checks
+//! for a free emoji slot before uploading anything, since static and animated emoji have separate
+//! limits depending on the server's boost level.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::guild::PremiumTier;
+use serenity::model::id::GuildId;
+use serenity::model::permissions::Permissions;
+use structopt::StructOpt;
+
+use crate::dispatch::Dispatch;
+use crate::error::GuildNotInCache;
+use crate::module::persona::react_success;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// The largest image Discord accepts for an emoji, in bytes.
+pub const MAX_EMOJI_BYTES: u64 = 256 * 1024;
+
+/// Matches a custom emoji, capturing whether it's animated, its name and its ID.
+static CUSTOM_EMOJI_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"<(a?):(\w+):(\d+)>").expect("Invalid emoji RE"));
+
+impl_err!(
+    InvalidEmojiName,
+    "Emoji names must be between 2 and 32 characters long, and only contain letters, numbers and underscores.",
+    true
+);
+impl_err!(
+    NoEmojiImage,
+    "Give a link to the image, or attach it to your message.",
+    true
+);
+impl_err!(EmojiTooLarge, "Emoji images can be at most 256 KiB.", true);
+impl_err!(
+    NotAnEmojiImage,
+    "Emoji images must be PNG, JPEG, GIF or WebP files.",
+    true
+);
+impl_err!(
+    NoEmojiToSteal,
+    "Give a custom emoji, or reply to a message which uses one.",
+    true
+);
+impl_err!(NoSuchEmoji, "This server has no emoji by that name.", true);
+impl_err!(
+    NoEmojiSlots,
+    "This server has no free emoji slots of that kind. Remove some emoji, or boost the server for more.",
+    true
+);
+impl_err!(
+    CantManageEmojis,
+    "I need the Manage Emojis permission to do that.",
+    true
+);
+
+/// The number of static emoji, and separately animated emoji, a server with the given boost level
+/// may have.
+fn emoji_slots(tier: PremiumTier) -> usize {
+    match tier {
+        PremiumTier::Tier1 => 100,
+        PremiumTier::Tier2 => 150,
+        PremiumTier::Tier3 => 250,
+        _ => 50,
+    }
+}
+
+/// Checks that a name is valid for an emoji.
+fn valid_name(name: &str) -> bool {
+    let len = name.chars().count();
+    (2..=32).contains(&len) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Ensures Glimbot may manage emoji, and that the guild has a free slot for an emoji of the given
+/// kind.
+async fn check_slot(dis: &Dispatch, ctx: &Context, gid: GuildId, animated: bool) -> crate::error::Result<()> {
+    let guild = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
+    let me = guild.members.get(&dis.bot().await).ok_or(GuildNotInCache)?;
+    let perms = me.permissions(ctx).await?;
+    if !perms.contains(Permissions::MANAGE_EMOJIS) && !perms.administrator() {
+        return Err(CantManageEmojis.into());
+    }
+
+    let used = guild.emojis.values().filter(|e| e.animated == animated).count();
+    if used >= emoji_slots(guild.premium_tier) {
+        return Err(NoEmojiSlots.into());
+    }
+    Ok(())
+}
+
+/// Downloads an image from a link, refusing anything too large to be an emoji.
+async fn download(url: &str) -> crate::error::Result<Vec<u8>> {
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(NoEmojiImage.into());
+    }
+    let resp = reqwest::get(url).await?.error_for_status()?;
+    if resp.content_length().map_or(false, |l| l > MAX_EMOJI_BYTES) {
+        return Err(EmojiTooLarge.into());
+    }
+    let bytes = resp.bytes().await?;
+    if bytes.len() as u64 > MAX_EMOJI_BYTES {
+        return Err(EmojiTooLarge.into());
+    }
+    Ok(bytes.to_vec())
+}
+
+/// Works out the MIME type of an emoji image, returning it along with whether it may be animated.
+fn image_type(bytes: &[u8]) -> crate::error::Result<(&'static str, bool)> {
+    match image::guess_format(bytes) {
+        Ok(image::ImageFormat::Png) => Ok(("image/png", false)),
+        Ok(image::ImageFormat::Jpeg) => Ok(("image/jpeg", false)),
+        Ok(image::ImageFormat::Gif) => Ok(("image/gif", true)),
+        Ok(image::ImageFormat::WebP) => Ok(("image/webp", false)),
+        _ => Err(NotAnEmojiImage.into()),
+    }
+}
+
+/// Uploads an image as a new emoji, replying with it.
+async fn create(dis: &Dispatch, ctx: &Context, orig: &Message, name: &str, bytes: &[u8]) -> crate::error::Result<()> {
+    let gid = orig.guild_id.unwrap();
+    let (mime, animated) = image_type(bytes)?;
+    check_slot(dis, ctx, gid, animated).await?;
+
+    let data = format!("data:{};base64,{}", mime, base64::encode(bytes));
+    let emoji = gid.create_emoji(ctx, name, &data).await?;
+    orig.reply(ctx, format!("Added {}.", emoji)).await?;
+    Ok(())
+}
+
+/// Manages this server's emoji.
+#[derive(StructOpt)]
+#[structopt(name = "emoji", no_version)]
+enum EmojiOpt {
+    /// Adds an emoji from a link to an image, or an image attached to your message.
+    Add {
+        /// The emoji's name.
+        name: String,
+        /// A link to the image. Can be left out if the image is attached.
+        url: Option<String>,
+    },
+    /// Copies a custom emoji from another server. Give the emoji, or reply to a message using it.
+    Steal {
+        /// The emoji to copy. Defaults to the first custom emoji in the message you're replying to.
+        emoji: Option<String>,
+        /// A name to give the copy, instead of the original's.
+        #[structopt(short, long)]
+        name: Option<String>,
+    },
+    /// Removes an emoji from this server.
+    Remove {
+        /// The emoji, its name or its ID.
+        emoji: String,
+    },
+}
+
+/// Module which lets moderators manage the server's emoji.
+pub struct EmojiModule;
+
+#[async_trait::async_trait]
+impl Module for EmojiModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("emoji", "adds, copies and removes this server's emoji.")
+                .with_sensitivity(Sensitivity::High)
+                .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = EmojiOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+
+        match opts {
+            EmojiOpt::Add { name, url } => {
+                if !valid_name(&name) {
+                    return Err(InvalidEmojiName.into());
+                }
+                let bytes = match (url, orig.attachments.first()) {
+                    (Some(url), _) => download(&url).await?,
+                    (None, Some(a)) => {
+                        if a.size > MAX_EMOJI_BYTES {
+                            return Err(EmojiTooLarge.into());
+                        }
+                        a.download().await?
+                    }
+                    (None, None) => return Err(NoEmojiImage.into()),
+                };
+                create(dis, ctx, orig, &name, &bytes).await?;
+            }
+            EmojiOpt::Steal { emoji, name } => {
+                let source = match emoji {
+                    Some(e) => e,
+                    None => orig
+                        .referenced_message
+                        .as_ref()
+                        .map(|m| m.content.clone())
+                        .ok_or(NoEmojiToSteal)?,
+                };
+                let caps = CUSTOM_EMOJI_RE.captures(&source).ok_or(NoEmojiToSteal)?;
+                let animated = !caps[1].is_empty();
+                let name = name.unwrap_or_else(|| caps[2].to_string());
+                if !valid_name(&name) {
+                    return Err(InvalidEmojiName.into());
+                }
+
+                // Check before downloading, so a full server doesn't cost a download.
+                check_slot(dis, ctx, gid, animated).await?;
+                let url = format!(
+                    "https://cdn.discordapp.com/emojis/{}.{}",
+                    &caps[3],
+                    if animated { "gif" } else { "png" }
+                );
+                let bytes = download(&url).await?;
+                create(dis, ctx, orig, &name, &bytes).await?;
+            }
+            EmojiOpt::Remove { emoji } => {
+                let wanted = CUSTOM_EMOJI_RE
+                    .captures(&emoji)
+                    .and_then(|c| c[3].parse::<u64>().ok())
+                    .or_else(|| emoji.parse::<u64>().ok());
+                let id = ctx
+                    .cache
+                    .guild_field(gid, |g| {
+                        g.emojis
+                            .values()
+                            .find(|e| Some(e.id.0) == wanted || e.name == emoji)
+                            .map(|e| e.id)
+                    })
+                    .await
+                    .ok_or(GuildNotInCache)?
+                    .ok_or(NoSuchEmoji)?;
+                gid.delete_emoji(ctx, id).await?;
+                react_success(dis, ctx, orig).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+//! Contains the emoji filter, which removes walls of emoji, a common raid tactic the other filters
+//! don't catch. Both custom emoji and unicode emoji are counted; sequences which display as one
+//! emoji, like flags, skin tones and families joined with zero-width joiners, count once.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::ChannelId;
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::module::filter::{handle_filtered, FilterAction, FilterModule};
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing an [`EmojiFilterConfig`].
+pub const EMOJI_FILTER_KEY: &str = "emoji_filter";
+/// Default number of emoji a message may contain.
+pub const DEFAULT_MAX_EMOJI: usize = 15;
+/// Default share of a message which may be emoji.
+pub const DEFAULT_MAX_RATIO: f64 = 0.8;
+/// Default number of emoji a message needs before its ratio is checked.
+pub const DEFAULT_MIN_EMOJI: usize = 6;
+
+/// Matches a single emoji: a custom emoji, a flag, or a unicode emoji along with any skin tone,
+/// variation selector and zero-width joined emoji which display with it.
+static EMOJI_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(concat!(
+        r"<a?:\w+:\d+>",
+        r"|\p{Regional_Indicator}{2}",
+        r"|\p{Extended_Pictographic}[\p{Emoji_Modifier}\x{FE0F}]*",
+        r"(?:\x{200D}\p{Extended_Pictographic}[\p{Emoji_Modifier}\x{FE0F}]*)*",
+    ))
+    .expect("Invalid emoji RE")
+});
+
+impl_err!(
+    InvalidEmojiRatio,
+    "emoji_filter's max_ratio must be a number greater than 0 and no greater than 1.",
+    true
+);
+
+/// Configuration for the emoji filter.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EmojiFilterConfig {
+    /// Whether the emoji filter is on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// The most emoji a message may contain. 0 turns the check off.
+    #[serde(default = "default_max_emoji")]
+    pub max_emoji: usize,
+    /// The largest share of a message's visible characters which may be emoji, where each emoji
+    /// counts as one character. 1 turns the check off.
+    #[serde(default = "default_max_ratio")]
+    pub max_ratio: f64,
+    /// Messages with fewer emoji than this are never caught by `max_ratio`, so a lone reaction
+    /// emoji is left alone.
+    #[serde(default = "default_min_emoji")]
+    pub min_emoji: usize,
+    /// Channels emoji may be posted freely in.
+    #[serde(default)]
+    pub exempt_channels: Vec<ChannelId>,
+    /// What happens to the author of a removed message.
+    #[serde(default = "default_action")]
+    pub action: FilterAction,
+}
+
+#[doc(hidden)]
+fn default_max_emoji() -> usize {
+    DEFAULT_MAX_EMOJI
+}
+
+#[doc(hidden)]
+fn default_max_ratio() -> f64 {
+    DEFAULT_MAX_RATIO
+}
+
+#[doc(hidden)]
+fn default_min_emoji() -> usize {
+    DEFAULT_MIN_EMOJI
+}
+
+#[doc(hidden)]
+fn default_action() -> FilterAction {
+    FilterAction::Delete
+}
+
+impl Default for EmojiFilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_emoji: DEFAULT_MAX_EMOJI,
+            max_ratio: DEFAULT_MAX_RATIO,
+            min_emoji: DEFAULT_MIN_EMOJI,
+            exempt_channels: Vec::new(),
+            action: default_action(),
+        }
+    }
+}
+
+impl FromStr for EmojiFilterConfig {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conf: EmojiFilterConfig = serde_json::from_str(s)?;
+        if !conf.max_ratio.is_finite() || conf.max_ratio <= 0.0 || conf.max_ratio > 1.0 {
+            return Err(InvalidEmojiRatio.into());
+        }
+        Ok(conf)
+    }
+}
+
+impl fmt::Display for EmojiFilterConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// How much of a message is emoji.
+#[derive(Copy, Clone, Debug, Default)]
+struct EmojiCount {
+    /// The number of emoji.
+    emoji: usize,
+    /// The number of visible characters which aren't part of an emoji.
+    other: usize,
+}
+
+impl EmojiCount {
+    /// Counts the emoji in some text.
+    fn of(text: &str) -> Self {
+        let emoji = EMOJI_RE.find_iter(text).count();
+        let other = EMOJI_RE
+            .split(text)
+            .flat_map(str::chars)
+            .filter(|c| !c.is_whitespace() && !matches!(c, '\u{200D}' | '\u{FE0F}'))
+            .count();
+        EmojiCount { emoji, other }
+    }
+
+    /// The share of visible characters which are emoji.
+    fn ratio(&self) -> f64 {
+        let total = self.emoji + self.other;
+        if total == 0 {
+            0.0
+        } else {
+            self.emoji as f64 / total as f64
+        }
+    }
+}
+
+/// Module which removes walls of emoji.
+pub struct EmojiFilterModule;
+
+#[async_trait::async_trait]
+impl Module for EmojiFilterModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("emoji-filter", "removes messages which are walls of emoji.")
+                .with_sensitivity(Sensitivity::High)
+                .with_message_hook(true)
+                .with_config_value(config::Value::<EmojiFilterConfig>::with_default(
+                    EMOJI_FILTER_KEY,
+                    "A JSON object configuring the emoji filter, e.g. {\"enabled\": true, \"max_emoji\": 15, \"max_ratio\": 0.8, \"min_emoji\": 6, \"exempt_channels\": [], \"action\": \"Delete\"}.",
+                    Default::default,
+                ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if orig.content.is_empty() {
+            return Ok(());
+        }
+
+        let config = dis
+            .config_value_t::<EmojiFilterConfig>(EMOJI_FILTER_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !config.enabled || config.exempt_channels.contains(&orig.channel_id) {
+            return Ok(());
+        }
+
+        let count = EmojiCount::of(&orig.content);
+        let why = if config.max_emoji > 0 && count.emoji > config.max_emoji {
+            format!("contained {} emoji", count.emoji)
+        } else if count.emoji >= config.min_emoji.max(1) && count.ratio() > config.max_ratio {
+            format!("was {:.0}% emoji", count.ratio() * 100.0)
+        } else {
+            return Ok(());
+        };
+
+        if FilterModule::is_exempt(dis, ctx, orig, gid, self.info().name).await? {
+            trace!("exempt user posted an emoji wall");
+            return Ok(());
+        }
+
+        debug!("message {}", why);
+        handle_filtered(dis, orig, self.info().name, why, config.action).await
+    }
+}
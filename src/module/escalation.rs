@@ -0,0 +1,148 @@
+//! Contains warning escalation rules, which let guilds automatically punish users who have
+//! accumulated too many warnings, e.g. "3 warnings in 30 days => mute for 1 day".
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time;
+
+use serenity::client::Context;
+use serenity::model::id::{GuildId, UserId};
+
+use crate::db::cases::Cases;
+use crate::dispatch::Dispatch;
+use crate::module::moderation::{ActionKind, ModAction};
+use crate::util::constraints::AtLeastDuration;
+
+/// The config key for grabbing the [`EscalationRules`] for a guild.
+pub const WARN_ESCALATION_KEY: &str = "warn_escalation";
+/// The config key for how long warnings count toward escalation.
+pub const WARNING_TTL_KEY: &str = "warning_ttl";
+
+/// How long warnings count toward escalation before they decay; at least a minute.
+pub type WarningTtl = AtLeastDuration<60>;
+
+/// Retrieves the case store for a guild, with warnings decaying according to its [`WarningTtl`].
+pub async fn cases_with_decay(dis: &Dispatch, guild: GuildId) -> crate::error::Result<Cases<'_>> {
+    let db = dis.db(guild);
+    let ttl = dis.config_value_t::<WarningTtl>(WARNING_TTL_KEY)?.get(&db).await?;
+    Ok(Cases::new(db).with_warning_ttl(ttl.map(|t| (*t).into())))
+}
+
+/// A single escalation rule.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EscalationRule {
+    /// The number of warnings which will trigger this rule.
+    pub warnings: u32,
+    /// The window in which warnings are counted.
+    #[serde(with = "humantime_serde")]
+    pub window: time::Duration,
+    /// The action to take once the rule is triggered.
+    pub action: ActionKind,
+    /// How long the action should last, if it can be reversed. Indefinite if unspecified.
+    #[serde(default, with = "humantime_serde")]
+    pub duration: Option<time::Duration>,
+}
+
+/// The set of escalation rules for a guild.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(transparent)]
+pub struct EscalationRules(Vec<EscalationRule>);
+
+impl_err!(
+    InvalidEscalationRule,
+    "Escalation rules must require at least one warning and must escalate to a mute, kick, soft ban, or ban.",
+    true
+);
+
+impl FromStr for EscalationRules {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules: EscalationRules = serde_json::from_str(s).map_err(|e| crate::error::Error::from_err(e, true))?;
+        if rules
+            .0
+            .iter()
+            .any(|r| r.warnings == 0 || r.action == ActionKind::Warn || r.action.is_reversal())
+        {
+            return Err(InvalidEscalationRule.into());
+        }
+        Ok(rules)
+    }
+}
+
+impl fmt::Display for EscalationRules {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "[]".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+impl EscalationRules {
+    /// Determines which rule, if any, applies to a user after they've been warned. A rule only
+    /// applies when this warning is the one which brings the user up to its threshold, so later
+    /// warnings in the same window don't apply it again. If several rules apply, the one with the
+    /// highest warning threshold wins.
+    pub async fn triggered(
+        &self,
+        cases: &Cases<'_>,
+        user: UserId,
+    ) -> crate::error::Result<Option<(EscalationRule, i64)>> {
+        let mut out: Option<(EscalationRule, i64)> = None;
+        for rule in &self.0 {
+            if out.as_ref().map_or(false, |(o, _)| o.warnings >= rule.warnings) {
+                continue;
+            }
+
+            let window = chrono::Duration::from_std(rule.window).unwrap_or_else(|_| chrono::Duration::max_value());
+            let since = chrono::Utc::now()
+                .checked_sub_signed(window)
+                .unwrap_or(chrono::MIN_DATETIME);
+            let count = cases.count_since(user, ActionKind::Warn, since).await?;
+
+            // The count includes this warning, so it crossed the threshold only if it's exactly on it.
+            if count == rule.warnings as i64 {
+                out = Some((rule.clone(), count));
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Checks the escalation rules for a guild after a user has been warned, applying and
+/// reporting the escalated action if a rule was triggered. Reversal of the escalated action
+/// is scheduled through the timed events system.
+pub async fn escalate_after_warning(
+    dis: &Dispatch,
+    ctx: &Context,
+    warning: &ModAction,
+) -> crate::error::Result<Option<ModAction>> {
+    let db = dis.db(warning.guild());
+    let rules = dis
+        .config_value_t::<EscalationRules>(WARN_ESCALATION_KEY)?
+        .get_or_default(&db)
+        .await?;
+
+    let cases = cases_with_decay(dis, warning.guild()).await?;
+    let (rule, count) = match rules.triggered(&cases, warning.user().id).await? {
+        None => return Ok(None),
+        Some(r) => r,
+    };
+
+    debug!("escalating to {} after {} warnings", rule.action.name(), count);
+    let reason = format!(
+        "Automatic escalation: {} warnings within {}.",
+        count,
+        humantime::format_duration(rule.window)
+    );
+    let duration = rule.duration.filter(|_| rule.action.has_duration()).map(Into::into);
+    let me = dis.bot().await;
+    let mut action = warning
+        .follow_up(me, rule.action)
+        .with_duration(duration)
+        .with_reason(reason);
+    let case = action.act_and_notify(dis, ctx).await?;
+    action.report_action(dis, ctx, case).await?;
+    Ok(Some(action))
+}
@@ -0,0 +1,88 @@
+//! Contains the `export` command, which packages everything Glimbot stores about a server into a
+//! file, for transparency or for moving to a self-hosted instance.
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+
+use crate::db::backup::export_guild;
+use crate::dispatch::Dispatch;
+use crate::error::GuildNotInCache;
+use crate::module::privilege::InsufficientUserPrivilege;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The largest export Glimbot will upload; Discord's attachment limit for bots.
+pub const MAX_EXPORT_BYTES: usize = 8 * 1024 * 1024;
+
+impl_err!(
+    ExportTooLarge,
+    "This server's data is too large to send through Discord. Ask the bot owner for a copy.",
+    true
+);
+impl_err!(
+    CantSendExport,
+    "I couldn't DM you the export. Allow direct messages from server members and try again.",
+    true
+);
+
+/// Module which adds the `export` command.
+pub struct ExportModule;
+
+#[async_trait::async_trait]
+impl Module for ExportModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "export",
+                "sends the server's owner or an administrator everything Glimbot stores about the server.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        _command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+
+        // Exports include every case and config value, so moderators alone aren't enough.
+        let guild = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
+        if orig.author.id != guild.owner_id {
+            let member = guild.members.get(&orig.author.id).ok_or(GuildNotInCache)?;
+            if !member.permissions(ctx).await?.administrator() {
+                return Err(InsufficientUserPrivilege.into());
+            }
+        }
+
+        let export = export_guild(dis.pool()?, gid).await?;
+        let body = serde_json::to_vec_pretty(&export)?;
+        if body.len() > MAX_EXPORT_BYTES {
+            return Err(ExportTooLarge.into());
+        }
+
+        info!("{} exported the data for {}", orig.author.id, gid);
+        let file_name = format!("glimbot-export-{}.json", gid);
+        let rows: usize = export.tables.values().map(|t| t.as_array().map_or(0, Vec::len)).sum();
+        let dm = orig.author.create_dm_channel(ctx).await?;
+        dm.send_files(ctx, vec![(body.as_slice(), file_name.as_str())], |m| {
+            m.content(format!(
+                "Everything Glimbot stores about {}: {} rows from {} tables.",
+                guild.name,
+                rows,
+                export.tables.len()
+            ))
+        })
+        .await
+        .map_err(|_| CantSendExport)?;
+
+        orig.reply(ctx, "Sent you the export in a DM.").await?;
+        Ok(())
+    }
+}
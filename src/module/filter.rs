@@ -0,0 +1,1058 @@
+//! Contains the message filter, which deletes messages matching guild-defined patterns or any of
+//! the curated presets shipped with Glimbot (e.g. scam phrases or invite links).
+//!
+//! Presets are versioned. When a guild enables a preset, the patterns from that version are
+//! stored alongside the guild's own patterns, and are only replaced when a moderator pulls in a
+//! newer version with `filter preset update`. Guild-custom patterns are never touched by presets.
+//!
+//! If a guild sets [`QUARANTINE_CHANNEL`], filtered messages are copied there before they're
+//! deleted, so moderators can review false positives. What happens to the author afterwards is
+//! decided by the guild's [`FilterAction`].
+
+use std::borrow::{Borrow, Cow};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::Duration;
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::{RegexSet, RegexSetBuilder};
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::GuildId;
+use serenity::utils::MessageBuilder;
+use shrinkwraprs::Shrinkwrap;
+use structopt::StructOpt;
+
+use crate::db::cache::TimedCache;
+use crate::db::cases::{Evidence, MessageSnapshot};
+use crate::db::exemptions::{validate_scope, ExemptionKind, FilterExemptions};
+use crate::db::quota::{Quota, Resource};
+use crate::db::DbContext;
+use crate::dispatch::config::{
+    parse_channel_id, parse_role_id, parse_user_id, FromStrWithCtx, Value, VerifiedChannel, VerifiedRole, VerifiedUser,
+};
+use crate::dispatch::jobs::{Job, JobPriority, OverloadPolicy};
+use crate::dispatch::Dispatch;
+use crate::error::{DatabaseError, LogErrorExt};
+use crate::module::escalation::escalate_after_warning;
+use crate::module::image_filter::{block_replied_images, ImageBlocklist, ImageHash};
+use crate::module::moderation::{mod_log_channel, report_simulated, simulating, ActionKind, ModAction};
+use crate::module::persona::react_success;
+use crate::module::privilege::PRIV_ROLE;
+use crate::module::scoring::{score_hit, FilterHit};
+use crate::module::status::GLIM_COLOR;
+use crate::module::threads::thread_parent;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// The built-in quota for how many custom patterns a guild may define; see [`crate::db::quota`].
+pub const MAX_CUSTOM_PATTERNS: i64 = 100;
+/// The maximum length of a single custom pattern, in UTF-8 code points.
+pub const MAX_PATTERN_LEN: usize = 256;
+/// The maximum compiled size of a guild's filter, in bytes.
+pub const MAX_FILTER_SIZE: usize = 1 << 20;
+/// How long a compiled filter is cached before being reloaded from the database.
+pub const FILTER_CACHE_TTL: Duration = Duration::from_secs(60);
+/// Config key for the private channel filtered messages are copied to before being deleted.
+pub const QUARANTINE_CHANNEL: &str = "quarantine_channel";
+/// The most characters of a quarantined message's content shown, keeping the embed under
+/// Discord's size limit.
+pub const MAX_QUARANTINE_CONTENT_CHARS: usize = 2000;
+/// Config key for the guild's [`FilterAction`].
+pub const FILTER_ACTION: &str = "filter_action";
+
+/// What happens to the author of a message which matched the filter, once it's been removed.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FilterAction {
+    /// Nothing; the deletion is noted in the mod log.
+    Delete,
+    /// The author is warned, which may escalate according to `warn_escalation`.
+    Warn,
+    /// The author is muted indefinitely.
+    Mute,
+    /// The author is kicked.
+    Kick,
+    /// The author is banned.
+    Ban,
+}
+
+impl Default for FilterAction {
+    fn default() -> Self {
+        FilterAction::Warn
+    }
+}
+
+impl_err!(
+    InvalidFilterAction,
+    "Filter action must be one of `delete`, `warn`, `mute`, `kick` or `ban`.",
+    true
+);
+
+impl FromStr for FilterAction {
+    type Err = InvalidFilterAction;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "delete" => Ok(FilterAction::Delete),
+            "warn" => Ok(FilterAction::Warn),
+            "mute" => Ok(FilterAction::Mute),
+            "kick" => Ok(FilterAction::Kick),
+            "ban" => Ok(FilterAction::Ban),
+            _ => Err(InvalidFilterAction),
+        }
+    }
+}
+
+impl fmt::Display for FilterAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            FilterAction::Delete => "delete",
+            FilterAction::Warn => "warn",
+            FilterAction::Mute => "mute",
+            FilterAction::Kick => "kick",
+            FilterAction::Ban => "ban",
+        };
+        f.write_str(s)
+    }
+}
+
+impl FilterAction {
+    /// The moderation action taken against the author, if any.
+    pub fn kind(&self) -> Option<ActionKind> {
+        match self {
+            FilterAction::Delete => None,
+            FilterAction::Warn => Some(ActionKind::Warn),
+            FilterAction::Mute => Some(ActionKind::Mute),
+            FilterAction::Kick => Some(ActionKind::Kick),
+            FilterAction::Ban => Some(ActionKind::Ban),
+        }
+    }
+}
+
+#[derive(rust_embed::RustEmbed)]
+#[folder = "presets/filters/"]
+struct PresetFiles;
+
+/// A curated list of patterns which guilds can opt into.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Preset {
+    /// The name used to refer to the preset in commands.
+    pub name: String,
+    /// A short description of what the preset matches.
+    pub description: String,
+    /// The version of the preset; incremented whenever its patterns change.
+    pub version: i32,
+    /// The patterns in the preset, as case-insensitive regular expressions.
+    pub patterns: Vec<String>,
+}
+
+/// The presets compiled into this binary, keyed by name.
+pub static PRESETS: Lazy<BTreeMap<String, Preset>> = Lazy::new(|| {
+    PresetFiles::iter()
+        .filter(|f| f.ends_with(".json"))
+        .map(|f| {
+            let data = PresetFiles::get(&f).unwrap();
+            let preset: Preset =
+                serde_json::from_slice(&data).unwrap_or_else(|e| panic!("Invalid filter preset {}: {}", f, e));
+            (preset.name.clone(), preset)
+        })
+        .collect()
+});
+
+/// A preset as enabled in a guild, pinned at the version which was current when it was enabled
+/// or last updated.
+#[derive(Clone, Debug)]
+pub struct EnabledPreset {
+    /// The name of the preset.
+    pub name: String,
+    /// The version of the preset the guild is using.
+    pub version: i32,
+    /// The patterns from that version.
+    pub patterns: Vec<String>,
+}
+
+impl EnabledPreset {
+    /// Returns the newer version of this preset shipped with Glimbot, if there is one.
+    pub fn update_available(&self) -> Option<&'static Preset> {
+        PRESETS.get(&self.name).filter(|p| p.version > self.version)
+    }
+}
+
+impl_err!(AlreadyFiltered, "That pattern is already in the filter.", true);
+impl_err!(NoSuchPattern, "That pattern isn't in the filter.", true);
+impl_err!(
+    InvalidPattern,
+    "Invalid pattern: patterns must be no more than 256 characters, and valid regular expressions unless --word or --wildcard is given.",
+    true
+);
+impl_err!(
+    NoSuchPreset,
+    "No preset with that name exists. Try `filter preset list`.",
+    true
+);
+impl_err!(
+    PresetAlreadyEnabled,
+    "That preset is already enabled. Use `filter preset update` to pull in a newer version.",
+    true
+);
+impl_err!(PresetNotEnabled, "That preset isn't enabled in this server.", true);
+impl_err!(PresetUpToDate, "That preset is already at the latest version.", true);
+
+/// Wrapper around DbContext to retrieve/set filter patterns and presets.
+#[derive(Shrinkwrap)]
+pub struct GuildFilters<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> GuildFilters<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        GuildFilters {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Adds a custom pattern to the guild's filter.
+    pub async fn add_pattern(&self, pattern: &str) -> crate::error::Result<()> {
+        Quota::new(&self.ctx).check(Resource::FilterPatterns).await?;
+
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO filter_patterns (guild, pattern) VALUES ($1, $2);",
+            self.ctx.guild_as_i64(),
+            pattern
+        )
+        .execute(self.ctx.conn()?)
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(AlreadyFiltered.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Removes a custom pattern from the guild's filter.
+    pub async fn remove_pattern(&self, pattern: &str) -> crate::error::Result<()> {
+        let res = sqlx::query!(
+            "DELETE FROM filter_patterns WHERE guild = $1 AND pattern = $2;",
+            self.ctx.guild_as_i64(),
+            pattern
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(NoSuchPattern.into());
+        }
+        Ok(())
+    }
+
+    /// Retrieves the guild's custom patterns.
+    pub async fn patterns(&self) -> crate::error::Result<Vec<String>> {
+        Ok(sqlx::query_scalar!(
+            "SELECT pattern FROM filter_patterns WHERE guild = $1 ORDER BY pattern ASC;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_all(self.ctx.conn()?)
+        .await?)
+    }
+
+    /// Enables the current version of a preset in the guild.
+    pub async fn enable_preset(&self, preset: &Preset) -> crate::error::Result<()> {
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO filter_presets (guild, preset, version, patterns) VALUES ($1, $2, $3, $4);",
+            self.ctx.guild_as_i64(),
+            &preset.name,
+            preset.version,
+            serde_json::to_value(&preset.patterns)?
+        )
+        .execute(self.ctx.conn()?)
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(PresetAlreadyEnabled.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Replaces the guild's copy of a preset with the current version.
+    pub async fn update_preset(&self, preset: &Preset) -> crate::error::Result<()> {
+        let res = sqlx::query!(
+            "UPDATE filter_presets SET version = $3, patterns = $4 WHERE guild = $1 AND preset = $2;",
+            self.ctx.guild_as_i64(),
+            &preset.name,
+            preset.version,
+            serde_json::to_value(&preset.patterns)?
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(PresetNotEnabled.into());
+        }
+        Ok(())
+    }
+
+    /// Disables a preset in the guild.
+    pub async fn disable_preset(&self, name: &str) -> crate::error::Result<()> {
+        let res = sqlx::query!(
+            "DELETE FROM filter_presets WHERE guild = $1 AND preset = $2;",
+            self.ctx.guild_as_i64(),
+            name
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(PresetNotEnabled.into());
+        }
+        Ok(())
+    }
+
+    /// Retrieves the presets enabled in the guild.
+    pub async fn presets(&self) -> crate::error::Result<Vec<EnabledPreset>> {
+        let rows = sqlx::query!(
+            "SELECT preset, version, patterns FROM filter_presets WHERE guild = $1 ORDER BY preset ASC;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_all(self.ctx.conn()?)
+        .await?;
+
+        let mut out = Vec::with_capacity(rows.len());
+        for r in rows {
+            out.push(EnabledPreset {
+                name: r.preset,
+                version: r.version,
+                patterns: serde_json::from_value(r.patterns)?,
+            });
+        }
+        Ok(out)
+    }
+}
+
+/// A guild's filter, compiled into a single regex set.
+pub struct CompiledFilter {
+    /// The compiled patterns.
+    set: RegexSet,
+    /// Where each pattern in the set came from, i.e. `custom` or the name of a preset.
+    sources: Vec<Cow<'static, str>>,
+}
+
+impl CompiledFilter {
+    /// Compiles a filter from a guild's custom patterns and enabled presets.
+    pub fn compile(custom: Vec<String>, presets: Vec<EnabledPreset>) -> crate::error::Result<Self> {
+        let mut patterns = Vec::new();
+        let mut sources = Vec::new();
+        for p in custom {
+            patterns.push(p);
+            sources.push(Cow::Borrowed("custom"));
+        }
+        for preset in presets {
+            let source: Cow<'static, str> = Cow::Owned(format!("preset {} v{}", preset.name, preset.version));
+            for p in preset.patterns {
+                patterns.push(p);
+                sources.push(source.clone());
+            }
+        }
+
+        let set = build_set(&patterns)?;
+        Ok(Self { set, sources })
+    }
+
+    /// Returns the source of the first pattern which matches the text, if any.
+    pub fn first_match(&self, text: &str) -> Option<&str> {
+        self.set
+            .matches(text)
+            .into_iter()
+            .next()
+            .map(|i| self.sources[i].as_ref())
+    }
+}
+
+/// Builds a case-insensitive regex set, enforcing the filter size limit.
+fn build_set(patterns: &[String]) -> crate::error::Result<RegexSet> {
+    RegexSetBuilder::new(patterns)
+        .case_insensitive(true)
+        .size_limit(MAX_FILTER_SIZE)
+        .build()
+        .map_err(|_| InvalidPattern.into())
+}
+
+/// Matches the start of a message or a non-word character, so word patterns only match whole words.
+pub const WORD_START: &str = r"(?:^|\W)";
+/// Matches the end of a message or a non-word character.
+pub const WORD_END: &str = r"(?:\W|$)";
+
+/// Converts a word, phrase or wildcard pattern into a regular expression which only matches it as
+/// whole words. Whitespace in a phrase matches any run of whitespace; in a wildcard pattern, `*`
+/// matches any run of non-space characters and `?` matches a single one.
+pub fn words_to_regex(pattern: &str, wildcard: bool) -> String {
+    let escape_word = |w: &str| {
+        if !wildcard {
+            return regex::escape(w);
+        }
+        w.chars()
+            .map(|c| match c {
+                '*' => r"\S*".to_string(),
+                '?' => r"\S".to_string(),
+                c => regex::escape(&c.to_string()),
+            })
+            .collect()
+    };
+    let body = pattern
+        .split_whitespace()
+        .map(escape_word)
+        .collect::<Vec<_>>()
+        .join(r"\s+");
+    format!("{}{}{}", WORD_START, body, WORD_END)
+}
+
+/// Checks that a custom pattern is short enough as it was typed, and that the regular expression
+/// it's stored as compiles. Word and wildcard patterns are typed as words, so they're measured as
+/// words rather than by their longer regular expression.
+pub fn validate_pattern(typed: &str, regex: &str) -> crate::error::Result<()> {
+    if typed.chars().count() > MAX_PATTERN_LEN {
+        return Err(InvalidPattern.into());
+    }
+    build_set(&[regex.to_string()]).map(|_| ())
+}
+
+/// Manages the message filter.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "filter", no_version)]
+pub enum FilterOpt {
+    /// Adds a pattern to the filter. Patterns are case-insensitive regular expressions, unless
+    /// --word or --wildcard is given.
+    Add(PatternArgs),
+    /// Removes a pattern from the filter. Pass the same flags as when it was added.
+    Remove(PatternArgs),
+    /// Lists the patterns and presets in use in this server.
+    List,
+    /// Manages curated presets.
+    Preset {
+        #[structopt(subcommand)]
+        op: PresetOp,
+    },
+    /// Manages the image blocklist.
+    Image {
+        #[structopt(subcommand)]
+        op: ImageOp,
+    },
+    /// Manages the channels, roles and users the filters leave alone.
+    Exempt {
+        #[structopt(subcommand)]
+        op: ExemptOp,
+    },
+}
+
+/// A pattern given to `filter add` or `filter remove`.
+#[derive(Debug, StructOpt)]
+pub struct PatternArgs {
+    /// The pattern.
+    pattern: String,
+    /// Matches the pattern as an exact word or phrase, rather than a regular expression.
+    #[structopt(short, long, conflicts_with = "wildcard")]
+    word: bool,
+    /// Matches the pattern as whole words, where `*` stands for any characters and `?` for any
+    /// single character, rather than a regular expression.
+    #[structopt(short = "g", long)]
+    wildcard: bool,
+}
+
+impl PatternArgs {
+    /// Retrieves the regular expression the pattern is stored as.
+    fn to_regex(&self) -> String {
+        if self.word || self.wildcard {
+            words_to_regex(&self.pattern, self.wildcard)
+        } else {
+            self.pattern.clone()
+        }
+    }
+}
+
+/// Operations on filter presets.
+#[derive(Debug, StructOpt)]
+pub enum PresetOp {
+    /// Lists the available presets.
+    List,
+    /// Enables a preset in this server.
+    Enable {
+        /// The name of the preset.
+        name: String,
+    },
+    /// Disables a preset in this server.
+    Disable {
+        /// The name of the preset.
+        name: String,
+    },
+    /// Pulls the latest version of an enabled preset into this server.
+    Update {
+        /// The name of the preset.
+        name: String,
+    },
+}
+
+/// Operations on the image blocklist.
+#[derive(Debug, StructOpt)]
+pub enum ImageOp {
+    /// Blocks the images attached to the message this command replies to.
+    Add,
+    /// Unblocks an image.
+    Remove {
+        /// The image's hash, as shown by `filter image list`.
+        hash: String,
+    },
+    /// Lists the hashes of blocked images.
+    List,
+}
+
+/// Operations on filter exemptions.
+#[derive(Debug, StructOpt)]
+pub enum ExemptOp {
+    /// Exempts a channel, role or user from the filters.
+    Add(ExemptArgs),
+    /// Removes an exemption. Pass the same --only as when it was added.
+    Remove(ExemptArgs),
+    /// Lists the exemptions.
+    List,
+}
+
+/// An exemption given to `filter exempt add` or `filter exempt remove`.
+#[derive(Debug, StructOpt)]
+pub struct ExemptArgs {
+    /// What to exempt: `channel`, `role` or `user`.
+    kind: ExemptionKind,
+    /// The channel, role or user, as a mention, ID or name.
+    target: String,
+    /// Exempts it from only this filter, e.g. invite-filter, rather than all of them.
+    #[structopt(long)]
+    only: Option<String>,
+}
+
+impl ExemptArgs {
+    /// Resolves the target to an ID. When removing, a raw ID or mention is accepted even if it no
+    /// longer exists, so exemptions for deleted channels and roles can be cleaned up.
+    async fn resolve(&self, ctx: &Context, gid: GuildId, removing: bool) -> crate::error::Result<u64> {
+        let raw = match self.kind {
+            ExemptionKind::Channel => parse_channel_id(&self.target).map(|c| c.0),
+            ExemptionKind::Role => parse_role_id(&self.target).map(|r| r.0),
+            ExemptionKind::User => parse_user_id(&self.target).map(|u| u.0),
+        };
+        if let (true, Some(id)) = (removing, raw) {
+            return Ok(id);
+        }
+
+        Ok(match self.kind {
+            ExemptionKind::Channel => {
+                VerifiedChannel::from_str_with_ctx(&self.target, ctx, gid)
+                    .await?
+                    .into_inner()
+                    .0
+            }
+            ExemptionKind::Role => {
+                VerifiedRole::from_str_with_ctx(&self.target, ctx, gid)
+                    .await?
+                    .into_inner()
+                    .0
+            }
+            ExemptionKind::User => {
+                VerifiedUser::from_str_with_ctx(&self.target, ctx, gid)
+                    .await?
+                    .into_inner()
+                    .0
+            }
+        })
+    }
+}
+
+/// Module which deletes messages matching the filter.
+pub struct FilterModule {
+    #[doc(hidden)]
+    cache: TimedCache<GuildId, CompiledFilter>,
+}
+
+impl Default for FilterModule {
+    fn default() -> Self {
+        Self {
+            cache: TimedCache::new(FILTER_CACHE_TTL),
+        }
+    }
+}
+
+impl FilterModule {
+    /// Loads and compiles the filter for a guild.
+    async fn load(dis: &Dispatch, gid: GuildId) -> crate::error::Result<CompiledFilter> {
+        let filters = GuildFilters::new(dis.db(gid));
+        CompiledFilter::compile(filters.patterns().await?, filters.presets().await?)
+    }
+
+    /// Returns true if the message shouldn't be caught by the named filter: its author is a bot,
+    /// the owner or a moderator, or it's covered by one of the guild's [`FilterExemptions`]. Exemptions
+    /// for a channel cover its threads too.
+    pub async fn is_exempt(
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        gid: GuildId,
+        filter: &str,
+    ) -> crate::error::Result<bool> {
+        if orig.author.bot {
+            return Ok(true);
+        }
+
+        let owner = ctx.cache.guild_field(gid, |g| g.owner_id).await;
+        if owner == Some(orig.author.id) {
+            return Ok(true);
+        }
+
+        let mod_role = dis.config_value_t::<VerifiedRole>(PRIV_ROLE)?.get(&dis.db(gid)).await?;
+        let is_mod = match (mod_role, &orig.member) {
+            (Some(r), Some(m)) => m.roles.contains(&r.into_inner()),
+            _ => false,
+        };
+        if is_mod {
+            return Ok(true);
+        }
+        let parent = thread_parent(ctx, gid, orig.channel_id).await;
+        FilterExemptions::new(dis.db(gid)).exempts(orig, parent, filter).await
+    }
+}
+
+/// A message which matched the filter. It's dealt with as a background job so the REST calls
+/// involved don't hold up the message handler.
+struct FilterMatch {
+    /// The offending message.
+    message: Message,
+    /// The pattern or preset the message matched.
+    source: String,
+}
+
+#[async_trait::async_trait]
+impl Job for FilterMatch {
+    fn name(&self) -> &'static str {
+        "filter-match"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::High
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        act_on_match(dis, ctx, &self.message, &self.source).await
+    }
+}
+
+/// A message caught by one of the other filters, like the duplicate filter, which has already
+/// decided why the message is removed and what happens to its author.
+pub struct FilteredMessage {
+    /// The offending message.
+    pub message: Message,
+    /// Why the message was caught. See [`act_on_filtered`].
+    pub why: String,
+    /// What happens to the author.
+    pub action: FilterAction,
+}
+
+#[async_trait::async_trait]
+impl Job for FilteredMessage {
+    fn name(&self) -> &'static str {
+        "filtered-message"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::High
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        act_on_filtered(dis, ctx, &self.message, &self.why, self.action).await
+    }
+}
+
+/// Deals with a message caught by one of the filters. If the guild uses spam scoring, the catch is
+/// added to the message's score; otherwise the message is queued to be removed and its author dealt
+/// with according to `action`.
+pub async fn handle_filtered(
+    dis: &Dispatch,
+    orig: &Message,
+    filter: &'static str,
+    why: String,
+    action: FilterAction,
+) -> crate::error::Result<()> {
+    let hit = FilterHit { filter, why };
+    if score_hit(dis, orig, hit.clone()).await? {
+        return Ok(());
+    }
+    dis.jobs().enqueue(
+        orig.guild_id.unwrap(),
+        FilteredMessage {
+            message: orig.clone(),
+            why: hit.why,
+            action,
+        },
+    );
+    Ok(())
+}
+
+/// Removes a filtered message from its channel. If the guild has set a [`QUARANTINE_CHANNEL`],
+/// the message's content, author, and a link to where it was sent are posted there first, so
+/// moderators can review it. Quarantine failures are logged, but don't stop the deletion.
+pub async fn quarantine_message(dis: &Dispatch, ctx: &Context, orig: &Message, why: &str) -> crate::error::Result<()> {
+    let gid = orig.guild_id.unwrap();
+    let quarantine = dis
+        .config_value_t::<VerifiedChannel>(QUARANTINE_CHANNEL)?
+        .get(&dis.db(gid))
+        .await?;
+
+    if let Some(chan) = quarantine {
+        let mut content: String = orig.content.chars().take(MAX_QUARANTINE_CONTENT_CHARS).collect();
+        if content.is_empty() {
+            content.push_str("*No text content.*");
+        }
+        let attachments = orig
+            .attachments
+            .iter()
+            .map(|a| a.url.clone())
+            .collect::<Vec<_>>()
+            .join("\n");
+        chan.into_inner()
+            .send_message(ctx, |m| {
+                m.embed(|e| {
+                    e.color(GLIM_COLOR)
+                        .title("Quarantined message")
+                        .description(content)
+                        .field("Author", format!("{} ({})", orig.author.tag(), orig.author.id), false)
+                        .field("Channel", format!("<#{}>", orig.channel_id), true)
+                        .field("Jump", orig.link(), true)
+                        .field("Why", why, false)
+                        .timestamp(orig.timestamp);
+                    if !attachments.is_empty() {
+                        e.field("Attachments", attachments, false);
+                    }
+                    e
+                })
+            })
+            .await
+            .map_err(crate::error::Error::from)
+            .log_error();
+    }
+
+    orig.delete(ctx).await?;
+    Ok(())
+}
+
+/// Removes a filtered message, then acts against its author according to the guild's
+/// [`FilterAction`].
+async fn act_on_match(dis: &Dispatch, ctx: &Context, orig: &Message, source: &str) -> crate::error::Result<()> {
+    let gid = orig.guild_id.unwrap();
+    let filter_action = *dis
+        .config_value_t::<FilterAction>(FILTER_ACTION)?
+        .get_or_default(&dis.db(gid))
+        .await?;
+    let why = format!("matched the filter ({})", source);
+    act_on_filtered(dis, ctx, orig, &why, filter_action).await
+}
+
+/// Removes a message caught by a filter, then takes the given action against its author, opening a
+/// case for anything other than a plain deletion. `why` completes the sentence "The message ...".
+/// If the guild is only simulating actions, what would have been done is reported instead.
+pub async fn act_on_filtered(
+    dis: &Dispatch,
+    ctx: &Context,
+    orig: &Message,
+    why: &str,
+    filter_action: FilterAction,
+) -> crate::error::Result<()> {
+    let gid = orig.guild_id.unwrap();
+    if simulating(dis, gid).await? {
+        let mut what = format!(
+            "deleted a message from {} ({}) in <#{}>",
+            orig.author.tag(),
+            orig.author.id,
+            orig.channel_id
+        );
+        if let Some(k) = filter_action.kind() {
+            what.push_str(&format!(" and {} them", k.past_tense()));
+        }
+        what.push_str(&format!(": it {}.", why));
+        return report_simulated(dis, ctx, gid, &what).await;
+    }
+    quarantine_message(dis, ctx, orig, &format!("The message {}.", why)).await?;
+
+    let kind = match filter_action.kind() {
+        Some(k) => k,
+        None => {
+            if let Ok(chan) = mod_log_channel(dis, gid).await {
+                let note = format!(
+                    "Deleted a message from {} ({}) in <#{}>: it {}.",
+                    orig.author.tag(),
+                    orig.author.id,
+                    orig.channel_id,
+                    why
+                );
+                chan.say(ctx, note).await.map_err(crate::error::Error::from).log_error();
+            }
+            return Ok(());
+        }
+    };
+
+    let me = dis.bot().await;
+    let member = orig.member(ctx).await?;
+    let evidence = Evidence {
+        messages: vec![MessageSnapshot::from(orig)],
+        ..Default::default()
+    };
+    let mut action = ModAction::new(member, orig.channel_id, me, kind)
+        .with_reason(format!("The message {}.", why))
+        .with_evidence(evidence);
+    let case = action.act_and_notify(dis, ctx).await?;
+    action.report_action(dis, ctx, case).await.log_error();
+    if kind == ActionKind::Warn {
+        escalate_after_warning(dis, ctx, &action).await?;
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl Module for FilterModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "filter",
+                "deletes messages matching custom patterns or curated presets, and acts against their authors.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_message_hook(true)
+            .with_command(true)
+            .with_config_value(Value::<VerifiedChannel>::new(
+                QUARANTINE_CHANNEL,
+                "A private channel filtered messages are copied to before they're deleted, so moderators can review false positives.",
+            ))
+            .with_config_value(Value::<FilterAction>::with_default(
+                FILTER_ACTION,
+                "What happens to the author of a filtered message: `delete` (nothing beyond the deletion), `warn`, `mute`, `kick` or `ban`.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    fn flush_caches(&self, guild: Option<GuildId>) {
+        match guild {
+            Some(g) => {
+                self.cache.remove(&g);
+            }
+            None => self.cache.clear(),
+        }
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let opts = FilterOpt::from_iter_with_help(command)?;
+        let filters = GuildFilters::new(dis.db(gid));
+
+        match opts {
+            FilterOpt::Add(args) => {
+                let pattern = args.to_regex();
+                validate_pattern(&args.pattern, &pattern)?;
+                filters.add_pattern(&pattern).await?;
+            }
+            FilterOpt::Remove(args) => {
+                filters.remove_pattern(&args.to_regex()).await?;
+            }
+            FilterOpt::List => {
+                let mut message = String::new();
+                for p in filters.presets().await? {
+                    message.push_str(&format!("preset {} v{}\n", p.name, p.version));
+                }
+                for p in filters.patterns().await? {
+                    message.push_str(&p);
+                    message.push('\n');
+                }
+                if message.is_empty() {
+                    message.push_str("No patterns or presets.");
+                }
+                let msg = MessageBuilder::new().push_codeblock_safe(message, None).build();
+                orig.reply(ctx, msg).await?;
+                return Ok(());
+            }
+            FilterOpt::Preset { op: PresetOp::List } => {
+                let enabled: BTreeMap<_, _> = filters
+                    .presets()
+                    .await?
+                    .into_iter()
+                    .map(|p| (p.name.clone(), p))
+                    .collect();
+                let mut message = String::new();
+                for p in PRESETS.values() {
+                    let status = match enabled.get(&p.name) {
+                        None => Cow::Borrowed("disabled"),
+                        Some(e) if e.update_available().is_some() => {
+                            Cow::Owned(format!("enabled at v{}, update available", e.version))
+                        }
+                        Some(_) => Cow::Borrowed("enabled"),
+                    };
+                    message.push_str(&format!("{} v{} ({}): {}\n", p.name, p.version, status, p.description));
+                }
+                let msg = MessageBuilder::new().push_codeblock_safe(message, None).build();
+                orig.reply(ctx, msg).await?;
+                return Ok(());
+            }
+            FilterOpt::Preset {
+                op: PresetOp::Enable { name },
+            } => {
+                let preset = PRESETS.get(&name).ok_or(NoSuchPreset)?;
+                filters.enable_preset(preset).await?;
+            }
+            FilterOpt::Preset {
+                op: PresetOp::Disable { name },
+            } => {
+                filters.disable_preset(&name).await?;
+            }
+            FilterOpt::Preset {
+                op: PresetOp::Update { name },
+            } => {
+                let preset = PRESETS.get(&name).ok_or(NoSuchPreset)?;
+                let current = filters
+                    .presets()
+                    .await?
+                    .into_iter()
+                    .find(|p| p.name == name)
+                    .ok_or(PresetNotEnabled)?;
+                if current.update_available().is_none() {
+                    return Err(PresetUpToDate.into());
+                }
+                filters.update_preset(preset).await?;
+            }
+            FilterOpt::Image { op: ImageOp::Add } => {
+                let hashes = block_replied_images(dis, orig).await?;
+                let msg = format!("Blocked {}.", hashes.iter().map(ImageHash::to_string).join(", "));
+                orig.reply(ctx, msg).await?;
+                return Ok(());
+            }
+            FilterOpt::Image {
+                op: ImageOp::Remove { hash },
+            } => {
+                ImageBlocklist::new(dis.db(gid))
+                    .remove(ImageHash::parse(&hash)?)
+                    .await?;
+            }
+            FilterOpt::Image { op: ImageOp::List } => {
+                let mut message = String::new();
+                for i in ImageBlocklist::new(dis.db(gid)).images().await? {
+                    message.push_str(&format!(
+                        "{} added by {} on {}\n",
+                        i.hash,
+                        i.added_by,
+                        i.added.format("%Y-%m-%d")
+                    ));
+                }
+                if message.is_empty() {
+                    message.push_str("No blocked images.");
+                }
+                let msg = MessageBuilder::new().push_codeblock_safe(message, None).build();
+                orig.reply(ctx, msg).await?;
+                return Ok(());
+            }
+            FilterOpt::Exempt {
+                op: ExemptOp::Add(args),
+            } => {
+                let scope = validate_scope(args.only.as_deref())?;
+                let target = args.resolve(ctx, gid, false).await?;
+                FilterExemptions::new(dis.db(gid))
+                    .add(args.kind, target, scope, orig.author.id)
+                    .await?;
+            }
+            FilterOpt::Exempt {
+                op: ExemptOp::Remove(args),
+            } => {
+                let scope = validate_scope(args.only.as_deref())?;
+                let target = args.resolve(ctx, gid, true).await?;
+                FilterExemptions::new(dis.db(gid))
+                    .remove(args.kind, target, scope)
+                    .await?;
+            }
+            FilterOpt::Exempt { op: ExemptOp::List } => {
+                let mut message = String::new();
+                for e in FilterExemptions::new(dis.db(gid)).exemptions().await? {
+                    message.push_str(&format!(
+                        "{} {} from {}, added by {} on {}\n",
+                        e.kind,
+                        e.target,
+                        e.filter,
+                        e.added_by,
+                        e.added.format("%Y-%m-%d")
+                    ));
+                }
+                if message.is_empty() {
+                    message.push_str("No exemptions.");
+                }
+                let msg = MessageBuilder::new().push_codeblock_safe(message, None).build();
+                orig.reply(ctx, msg).await?;
+                return Ok(());
+            }
+        }
+
+        self.cache.remove(&gid);
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+
+        if orig.content.is_empty() {
+            return Ok(());
+        }
+
+        let filter = self.cache.get_or_insert_with(&gid, Self::load(dis, gid)).await?;
+        let source = match filter.first_match(&orig.content) {
+            None => return Ok(()),
+            Some(s) => s,
+        };
+
+        if Self::is_exempt(dis, ctx, orig, gid, self.info().name).await? {
+            trace!("exempt user matched filter ({})", source);
+            return Ok(());
+        }
+
+        debug!("message matched filter ({})", source);
+        let hit = FilterHit {
+            filter: self.info().name,
+            why: format!("matched the filter ({})", source),
+        };
+        if score_hit(dis, orig, hit).await? {
+            return Ok(());
+        }
+        dis.jobs().enqueue(
+            gid,
+            FilterMatch {
+                message: orig.clone(),
+                source: source.to_string(),
+            },
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+//! Contains the `global` command module, which lets the bot owner manage settings for the whole
+//! instance, like the bot's activity, status and maintenance mode.
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::db::global::GlobalSetting;
+use crate::dispatch::{Dispatch, ShardManKey};
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// Module to allow the bot owner to manage settings for the whole instance.
+pub struct GlobalModule;
+
+/// Command to manage settings for the whole instance.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "global", no_version)]
+enum GlobalOpt {
+    /// Sets a global setting.
+    Set {
+        /// The name of the setting
+        setting: GlobalSetting,
+        /// The value to set it to
+        value: String,
+    },
+    /// Shows a global setting.
+    Show {
+        /// The name of the setting
+        setting: GlobalSetting,
+    },
+    /// Removes a global setting.
+    Unset {
+        /// The name of the setting
+        setting: GlobalSetting,
+    },
+    /// Lists the global settings and what they do.
+    List,
+}
+
+/// Shows the configured activity and status on every shard.
+async fn set_presence_everywhere(dis: &Dispatch, ctx: &Context) {
+    let man = {
+        ctx.data
+            .read()
+            .await
+            .get::<ShardManKey>()
+            .expect("missing shard manager somehow")
+            .clone()
+    };
+    let (activity, status) = dis.presence();
+    let man = man.lock().await;
+    for runner in man.runners.lock().await.values() {
+        runner.runner_tx.set_presence(Some(activity.clone()), status);
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for GlobalModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("global", "manages settings for the whole instance.")
+                .with_command(true)
+                .with_sensitivity(Sensitivity::Owner)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = GlobalOpt::from_iter_with_help(command)?;
+        let global = dis.global_config();
+        let message = match opts {
+            GlobalOpt::Set { setting, value } => {
+                let new_val = setting.validate(&value)?;
+                global.set(dis.pool()?, setting, new_val).await?;
+                if setting.affects_presence() {
+                    set_presence_everywhere(dis, ctx).await;
+                }
+                format!("Set {}.", setting)
+            }
+            GlobalOpt::Show { setting } => match global.get_json(setting) {
+                None => "<unset>".to_string(),
+                Some(serde_json::Value::String(s)) => s,
+                Some(v) => v.to_string(),
+            },
+            GlobalOpt::Unset { setting } => {
+                if global.unset(dis.pool()?, setting).await? {
+                    if setting.affects_presence() {
+                        set_presence_everywhere(dis, ctx).await;
+                    }
+                    format!("Unset {}.", setting)
+                } else {
+                    format!("{} isn't set.", setting)
+                }
+            }
+            GlobalOpt::List => GlobalSetting::ALL
+                .iter()
+                .map(|s| format!("{}: {}", s, s.help()))
+                .join("\n"),
+        };
+
+        let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+}
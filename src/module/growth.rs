@@ -0,0 +1,256 @@
+//! Contains guild growth tracking. Once a day, the background service snapshots every guild's
+//! member count; the `growth` command shows recent trends from those snapshots, and guilds can
+//! have milestones (like reaching 10,000 members) announced in [`MILESTONE_CHANNEL`].
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::GuildId;
+use serenity::utils::MessageBuilder;
+use sqlx::PgPool;
+use structopt::StructOpt;
+
+use crate::dispatch::config::{Value, VerifiedChannel};
+use crate::dispatch::results::CommandReply;
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::ConstrainedU64;
+use crate::util::{sparkline, ClapExt};
+
+/// Config key for the channel milestones are announced in. Milestones aren't announced if unset.
+pub const MILESTONE_CHANNEL: &str = "milestone_channel";
+/// The number of guilds whose snapshots are inserted per query.
+pub const SNAPSHOT_BATCH_SIZE: usize = 500;
+/// The smallest member count which counts as a milestone.
+pub const FIRST_MILESTONE: u64 = 100;
+/// The number of most recent days listed individually by the `growth` command.
+pub const GROWTH_DAYS_LISTED: usize = 10;
+/// How long a rendered `growth` reply is reused for. Snapshots are only taken daily, so this can be
+/// fairly long.
+pub const GROWTH_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Finds the largest milestone a guild with the given member count has reached. Milestones go
+/// 100, 250, 500, 1000, 2500, 5000, 10000 and so on.
+pub fn milestone_for(members: u64) -> Option<u64> {
+    let mut best = None;
+    let mut base = FIRST_MILESTONE;
+    while base <= members {
+        for m in &[base, base * 5 / 2, base * 5] {
+            if *m <= members {
+                best = Some(*m);
+            }
+        }
+        base = match base.checked_mul(10) {
+            Some(b) => b,
+            None => break,
+        };
+    }
+    best
+}
+
+/// Stores one member count snapshot per guild for today, replacing any taken earlier today.
+async fn store_snapshots(pool: &PgPool, counts: &[(GuildId, u64)]) -> crate::error::Result<()> {
+    for batch in counts.chunks(SNAPSHOT_BATCH_SIZE) {
+        let guilds: Vec<i64> = batch.iter().map(|(g, _)| g.0 as i64).collect();
+        let members: Vec<i64> = batch.iter().map(|(_, m)| *m as i64).collect();
+        sqlx::query!(
+            r#"
+            INSERT INTO member_snapshots (guild, day, members)
+            SELECT g, CURRENT_DATE, m FROM UNNEST($1::BIGINT[], $2::BIGINT[]) AS t(g, m)
+            ON CONFLICT (guild, day) DO UPDATE
+                SET members = EXCLUDED.members;
+            "#,
+            &guilds,
+            &members
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Records newly reached milestones, returning the guilds which reached one along with the
+/// milestone. Guilds seen for the first time have their current milestone recorded silently, so
+/// milestones passed before Glimbot joined aren't announced.
+async fn update_milestones(pool: &PgPool, counts: &[(GuildId, u64)]) -> crate::error::Result<Vec<(GuildId, u64)>> {
+    let mut reached = Vec::new();
+    for batch in counts.chunks(SNAPSHOT_BATCH_SIZE) {
+        let guilds: Vec<i64> = batch.iter().map(|(g, _)| g.0 as i64).collect();
+        let known: HashMap<i64, i64> = sqlx::query!(
+            "SELECT guild, milestone FROM growth_milestones WHERE guild = ANY($1);",
+            &guilds
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| (r.guild, r.milestone))
+        .collect();
+
+        let mut changed_guilds = Vec::new();
+        let mut changed_milestones = Vec::new();
+        for (guild, members) in batch {
+            let milestone = match milestone_for(*members) {
+                Some(m) => m as i64,
+                None => continue,
+            };
+            match known.get(&(guild.0 as i64)) {
+                Some(prev) if *prev >= milestone => continue,
+                Some(_) => reached.push((*guild, milestone as u64)),
+                None => {}
+            }
+            changed_guilds.push(guild.0 as i64);
+            changed_milestones.push(milestone);
+        }
+
+        if changed_guilds.is_empty() {
+            continue;
+        }
+
+        sqlx::query!(
+            r#"
+            INSERT INTO growth_milestones (guild, milestone)
+            SELECT * FROM UNNEST($1::BIGINT[], $2::BIGINT[])
+            ON CONFLICT (guild) DO UPDATE
+                SET milestone = EXCLUDED.milestone;
+            "#,
+            &changed_guilds,
+            &changed_milestones
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(reached)
+}
+
+/// Snapshots the member count of every guild in the cache, then announces any milestones reached
+/// in guilds which have set [`MILESTONE_CHANNEL`].
+pub async fn take_snapshots(dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+    let mut counts = Vec::new();
+    for guild in ctx.cache.guilds().await {
+        if let Some(members) = ctx.cache.guild_field(guild, |g| g.member_count).await {
+            counts.push((guild, members));
+        }
+    }
+
+    store_snapshots(dis.pool()?, &counts).await?;
+    info!("took member snapshots of {} guilds", counts.len());
+
+    let channel_v = dis.config_value_t::<VerifiedChannel>(MILESTONE_CHANNEL)?;
+    for (guild, milestone) in update_milestones(dis.pool()?, &counts).await? {
+        let channel = match channel_v.get(&dis.db(guild)).await? {
+            Some(c) => c.into_inner(),
+            None => continue,
+        };
+        let name = ctx
+            .cache
+            .guild_field(guild, |g| g.name.clone())
+            .await
+            .unwrap_or_else(|| "This server".to_string());
+        channel
+            .say(ctx, format!("🎉 {} just reached {} members!", name, milestone))
+            .await
+            .map_err(crate::error::Error::from)
+            .log_error();
+    }
+    Ok(())
+}
+
+/// Retrieves a guild's snapshots from the last number of days, oldest first.
+async fn recent_snapshots(dis: &Dispatch, guild: GuildId, days: u64) -> crate::error::Result<Vec<(NaiveDate, i64)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT day, members FROM member_snapshots
+        WHERE guild = $1 AND day > CURRENT_DATE - $2::INT
+        ORDER BY day ASC;
+        "#,
+        guild.0 as i64,
+        days as i32
+    )
+    .fetch_all(dis.read_pool()?)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.day, r.members)).collect())
+}
+
+/// Renders a guild's growth over the given snapshots.
+fn render_growth(snapshots: &[(NaiveDate, i64)]) -> String {
+    let (first, last) = match (snapshots.first(), snapshots.last()) {
+        (Some(f), Some(l)) => (f, l),
+        _ => return "No member snapshots yet; they're taken once a day.".to_string(),
+    };
+
+    let values: Vec<i64> = snapshots.iter().map(|(_, m)| *m).collect();
+    let change = last.1 - first.1;
+    let days = (last.0 - first.0).num_days().max(1);
+
+    let mut out = format!(
+        "{}\n{} to {}: {} -> {} members ({:+}, {:+.1}/day)\n",
+        sparkline(&values),
+        first.0,
+        last.0,
+        first.1,
+        last.1,
+        change,
+        change as f64 / days as f64
+    );
+
+    let listed = snapshots.len().min(GROWTH_DAYS_LISTED);
+    let start = snapshots.len() - listed;
+    for (i, (day, members)) in snapshots.iter().enumerate().skip(start) {
+        let delta = if i == 0 { 0 } else { members - snapshots[i - 1].1 };
+        out.push_str(&format!("\n{} {:>8} {:>+6}", day, members, delta));
+    }
+    out
+}
+
+/// Shows how the server's member count has changed recently. Results are reused for a few
+/// minutes; staff can pass --fresh to skip them.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "growth", no_version)]
+pub struct GrowthOpt {
+    /// The number of days of history to show.
+    #[structopt(short, long, default_value = "30")]
+    days: ConstrainedU64<1, 90>,
+}
+
+/// Adds the `growth` command.
+pub struct GrowthModule;
+
+#[async_trait::async_trait]
+impl Module for GrowthModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("growth", "shows how the server's member count has changed recently.")
+                .with_sensitivity(Sensitivity::Medium)
+                .with_command(true)
+                .with_result_cache(GROWTH_CACHE_TTL)
+                .with_config_value(Value::<VerifiedChannel>::new(
+                    MILESTONE_CHANNEL,
+                    "A channel to announce member count milestones in, like reaching 10000 members.",
+                ))
+        });
+        &INFO
+    }
+
+    async fn render(
+        &self,
+        dis: &Dispatch,
+        _ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<CommandReply> {
+        let gid = orig.guild_id.unwrap();
+        let opts = GrowthOpt::from_iter_with_help(command)?;
+        let snapshots = recent_snapshots(dis, gid, opts.days.into()).await?;
+
+        let message = MessageBuilder::new()
+            .push_codeblock_safe(render_growth(&snapshots), None)
+            .build();
+        Ok(CommandReply::text(message))
+    }
+}
@@ -0,0 +1,355 @@
+//! Contains the image blocklist. Moderators add images to it with `filter image add`, and Glimbot
+//! stores a perceptual hash of each one rather than the image itself. Images posted later are
+//! hashed the same way, and removed if their hash is within [`IMAGE_MATCH_DISTANCE`] bits of one
+//! on the blocklist, which catches copies that were resized, recompressed or slightly edited.
+//!
+//! The hash is a difference hash: the image is shrunk to 9x8 grayscale pixels, and each bit records
+//! whether a pixel is brighter than its right-hand neighbour.
+
+use std::borrow::Borrow;
+
+use chrono::Utc;
+use image::imageops::FilterType;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::{Attachment, Message};
+use serenity::model::id::UserId;
+
+use crate::db::quota::{Quota, Resource};
+use crate::db::DbContext;
+use crate::dispatch::config::Value;
+use crate::dispatch::jobs::{Job, JobPriority, OverloadPolicy};
+use crate::dispatch::Dispatch;
+use crate::error::{DatabaseError, IntoBotErr};
+use crate::module::filter::{act_on_filtered, FilterAction, FilterModule, FILTER_ACTION};
+use crate::module::scoring::{defer_check, resolve_check, FilterHit};
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::AtMostU64;
+
+/// The image filter's name, as used by filter exemptions and spam scoring.
+pub const IMAGE_FILTER_NAME: &str = "image-filter";
+/// Config key for how many bits an image's hash may differ from a blocked one and still match.
+pub const IMAGE_MATCH_DISTANCE: &str = "image_match_distance";
+/// The default for [`IMAGE_MATCH_DISTANCE`].
+pub const DEFAULT_MATCH_DISTANCE: u64 = 6;
+/// The built-in quota for how many images a guild may block; see [`crate::db::quota`].
+pub const MAX_BLOCKED_IMAGES: i64 = 500;
+/// The largest image which will be downloaded and hashed, in bytes.
+pub const MAX_IMAGE_BYTES: u64 = 8 * 1024 * 1024;
+/// The largest width or height of an image which will be hashed, in pixels.
+pub const MAX_IMAGE_DIMENSION: u64 = 8192;
+/// The most attachments checked per message.
+pub const MAX_IMAGES_CHECKED: usize = 4;
+
+impl_err!(
+    NoImageToBlock,
+    "Reply to a message with image attachments to add them to the blocklist.",
+    true
+);
+impl_err!(
+    UnreadableImage,
+    "That image couldn't be read. It may be too large, or in a format Glimbot doesn't understand.",
+    true
+);
+impl_err!(ImageAlreadyBlocked, "That image is already on the blocklist.", true);
+impl_err!(NoSuchImage, "That hash isn't on the blocklist.", true);
+impl_err!(
+    InvalidImageHash,
+    "Image hashes are 16 hexadecimal digits, as shown by `filter image list`.",
+    true
+);
+
+/// A perceptual hash of an image.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ImageHash(pub u64);
+
+impl ImageHash {
+    /// Hashes an encoded image.
+    pub fn of(bytes: &[u8]) -> crate::error::Result<Self> {
+        let img = image::load_from_memory(bytes).map_err(|_| UnreadableImage)?;
+        let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+        let mut hash = 0u64;
+        for y in 0..8 {
+            for x in 0..8 {
+                hash <<= 1;
+                if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                    hash |= 1;
+                }
+            }
+        }
+        Ok(ImageHash(hash))
+    }
+
+    /// The number of bits which differ between two hashes.
+    pub fn distance(&self, other: ImageHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+
+    /// Parses a hash as shown by [`std::fmt::Display`].
+    pub fn parse(s: &str) -> crate::error::Result<Self> {
+        let s = s.trim();
+        if s.len() != 16 {
+            return Err(InvalidImageHash.into());
+        }
+        u64::from_str_radix(s, 16)
+            .map(ImageHash)
+            .map_err(|_| InvalidImageHash.into())
+    }
+}
+
+impl std::fmt::Display for ImageHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Checks whether an attachment is an image small enough to hash.
+fn is_hashable(a: &Attachment) -> bool {
+    match (a.width, a.height) {
+        (Some(w), Some(h)) => w <= MAX_IMAGE_DIMENSION && h <= MAX_IMAGE_DIMENSION && a.size <= MAX_IMAGE_BYTES,
+        _ => false,
+    }
+}
+
+/// Downloads and hashes an attachment. Decoding is done on the blocking pool, since large images
+/// take a while.
+pub async fn hash_attachment(a: &Attachment) -> crate::error::Result<ImageHash> {
+    if !is_hashable(a) {
+        return Err(UnreadableImage.into());
+    }
+    let bytes = a.download().await?;
+    tokio::task::spawn_blocking(move || ImageHash::of(&bytes))
+        .await
+        .into_sys_err()?
+}
+
+/// An image on a guild's blocklist.
+#[derive(Clone, Debug)]
+pub struct BlockedImage {
+    /// The image's hash.
+    pub hash: ImageHash,
+    /// The moderator who blocked it.
+    pub added_by: UserId,
+    /// When it was blocked.
+    pub added: chrono::DateTime<Utc>,
+}
+
+/// Wrapper around a guild's image blocklist in the database.
+pub struct ImageBlocklist<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> ImageBlocklist<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        ImageBlocklist {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Adds an image's hash to the blocklist.
+    pub async fn add(&self, hash: ImageHash, added_by: UserId) -> crate::error::Result<()> {
+        Quota::new(&self.ctx).check(Resource::BlockedImages).await?;
+
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO image_blocklist (guild, hash, added_by) VALUES ($1, $2, $3);",
+            self.ctx.guild_as_i64(),
+            hash.0 as i64,
+            added_by.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(ImageAlreadyBlocked.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Removes an image's hash from the blocklist.
+    pub async fn remove(&self, hash: ImageHash) -> crate::error::Result<()> {
+        let res = sqlx::query!(
+            "DELETE FROM image_blocklist WHERE guild = $1 AND hash = $2;",
+            self.ctx.guild_as_i64(),
+            hash.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(NoSuchImage.into());
+        }
+        Ok(())
+    }
+
+    /// Retrieves the blocklist, oldest first.
+    pub async fn images(&self) -> crate::error::Result<Vec<BlockedImage>> {
+        let rows = sqlx::query!(
+            "SELECT hash, added_by, added FROM image_blocklist WHERE guild = $1 ORDER BY added ASC;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_all(self.ctx.conn()?)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|r| BlockedImage {
+                hash: ImageHash(r.hash as u64),
+                added_by: UserId(r.added_by as u64),
+                added: r.added,
+            })
+            .collect())
+    }
+}
+
+/// Blocks the images attached to the message `orig` replies to, returning their hashes.
+pub async fn block_replied_images(dis: &Dispatch, orig: &Message) -> crate::error::Result<Vec<ImageHash>> {
+    let gid = orig.guild_id.unwrap();
+    let target = orig.referenced_message.as_ref().ok_or(NoImageToBlock)?;
+    let images: Vec<_> = target.attachments.iter().filter(|a| a.width.is_some()).collect();
+    if images.is_empty() {
+        return Err(NoImageToBlock.into());
+    }
+
+    let blocklist = ImageBlocklist::new(dis.db(gid));
+    let mut hashes = Vec::with_capacity(images.len());
+    for a in images {
+        let hash = hash_attachment(a).await?;
+        blocklist.add(hash, orig.author.id).await?;
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
+
+/// A message with images, checked against the blocklist as a background job since downloading and
+/// hashing them takes a while.
+struct ImageCheck {
+    /// The message containing the images.
+    message: Message,
+    /// Whether the message is being scored rather than acted on directly. See
+    /// [`crate::module::scoring`].
+    scored: bool,
+}
+
+impl ImageCheck {
+    /// Finds the first image in the message close enough to a blocked one, returning its file name.
+    async fn first_blocked(&self, dis: &Dispatch) -> crate::error::Result<Option<String>> {
+        let gid = self.message.guild_id.unwrap();
+        let db = dis.db(gid);
+        let blocked = ImageBlocklist::new(&db).images().await?;
+        if blocked.is_empty() {
+            return Ok(None);
+        }
+        let max_distance: u64 = (*dis
+            .config_value_t::<AtMostU64<32>>(IMAGE_MATCH_DISTANCE)?
+            .get_or_default(&db)
+            .await?)
+            .into();
+
+        for a in self
+            .message
+            .attachments
+            .iter()
+            .filter(|a| is_hashable(a))
+            .take(MAX_IMAGES_CHECKED)
+        {
+            let hash = match hash_attachment(a).await {
+                Ok(h) => h,
+                Err(e) => {
+                    debug!("couldn't hash {}: {}", a.filename, e);
+                    continue;
+                }
+            };
+            if blocked.iter().any(|b| u64::from(b.hash.distance(hash)) <= max_distance) {
+                return Ok(Some(a.filename.clone()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for ImageCheck {
+    fn name(&self) -> &'static str {
+        "image-check"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::High
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let blocked = self.first_blocked(dis).await;
+        if self.scored {
+            // The tally is resolved even if the check failed, so other filters' scores still count.
+            let hit = blocked.as_ref().ok().cloned().flatten().map(|name| FilterHit {
+                filter: IMAGE_FILTER_NAME,
+                why: format!("contained a blocked image ({})", name),
+            });
+            resolve_check(dis, &self.message, hit).await?;
+            return blocked.map(|_| ());
+        }
+        let name = match blocked? {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+        let gid = self.message.guild_id.unwrap();
+        let filter_action = *dis
+            .config_value_t::<FilterAction>(FILTER_ACTION)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        debug!("message contained blocked image {}", name);
+        let why = format!("contained a blocked image ({})", name);
+        act_on_filtered(dis, ctx, &self.message, &why, filter_action).await
+    }
+}
+
+/// Module which removes images on the guild's blocklist.
+pub struct ImageFilterModule;
+
+#[async_trait::async_trait]
+impl Module for ImageFilterModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(IMAGE_FILTER_NAME, "removes images on the server's blocklist.")
+                .with_sensitivity(Sensitivity::High)
+                .with_message_hook(true)
+                .with_config_value(Value::<AtMostU64<32>>::with_default(
+                    IMAGE_MATCH_DISTANCE,
+                    "How many bits of an image's hash may differ from a blocked image's and still match. Higher catches more edited copies, but risks false positives. At most 32.",
+                    || AtMostU64::new(DEFAULT_MATCH_DISTANCE).unwrap(),
+                ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if !orig.attachments.iter().any(is_hashable) {
+            return Ok(());
+        }
+        if FilterModule::is_exempt(dis, ctx, orig, gid, self.info().name).await? {
+            return Ok(());
+        }
+
+        let scored = defer_check(dis, orig).await?;
+        dis.jobs().enqueue(
+            gid,
+            ImageCheck {
+                message: orig.clone(),
+                scored,
+            },
+        );
+        Ok(())
+    }
+}
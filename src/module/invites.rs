@@ -0,0 +1,288 @@
+//! Contains the invite filter, which removes Discord invites to other servers. Invites are resolved
+//! with Discord, so invites to the current server or to servers on the guild's allow list are left
+//! alone. Links through third-party invite redirectors, and invites which can't be resolved, can't
+//! be checked and are treated like invites to other servers.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, RoleId};
+use serenity::model::invite::Invite;
+
+use crate::db::cache::{BoundedTimedEvictionStrategy, Cache, CacheStats};
+use crate::dispatch::config;
+use crate::dispatch::jobs::{Job, JobPriority, OverloadPolicy};
+use crate::dispatch::Dispatch;
+use crate::module::filter::{act_on_filtered, FilterAction, FilterModule};
+use crate::module::scoring::{defer_check, resolve_check, FilterHit};
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The invite filter's name, as used by filter exemptions and spam scoring.
+pub const INVITE_FILTER_NAME: &str = "invite-filter";
+/// The config key for grabbing an [`InviteFilterConfig`].
+pub const INVITE_FILTER_KEY: &str = "invite_filter";
+/// How long the guild an invite points to is remembered.
+pub const INVITE_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+/// The most invite codes remembered at once; the least recently used are forgotten first.
+pub const MAX_CACHED_INVITES: usize = 10_000;
+/// The most invites resolved per message; messages with more are treated as invites elsewhere.
+pub const MAX_INVITES_CHECKED: usize = 5;
+
+/// Matches invite links. The first group is the host, the second the invite code.
+pub const INVITE_PATTERN: &str =
+    r"(?i)(discord(?:app)?\.com/invite|discord\.gg|discord\.io|discord\.me|discord\.li|dsc\.gg)/([a-z0-9-]+)";
+
+#[doc(hidden)]
+static INVITE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(INVITE_PATTERN).expect("Invalid invite RE"));
+
+/// Configuration for the invite filter.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct InviteFilterConfig {
+    /// Whether the invite filter is on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Servers invites may point to, besides this one.
+    #[serde(default)]
+    pub allowed_guilds: Vec<GuildId>,
+    /// Roles whose members may post any invite.
+    #[serde(default)]
+    pub exempt_roles: Vec<RoleId>,
+    /// Channels any invite may be posted in.
+    #[serde(default)]
+    pub exempt_channels: Vec<ChannelId>,
+    /// What happens to the author of a removed invite.
+    #[serde(default = "default_action")]
+    pub action: FilterAction,
+}
+
+#[doc(hidden)]
+fn default_action() -> FilterAction {
+    FilterAction::Delete
+}
+
+impl FromStr for InviteFilterConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for InviteFilterConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// An invite link found in a message.
+#[derive(Clone, Debug)]
+struct InviteLink {
+    /// The invite code.
+    code: String,
+    /// Whether the link goes straight to Discord, so its code can be resolved.
+    resolvable: bool,
+}
+
+/// Finds the invite links in some text.
+fn find_invites(text: &str) -> Vec<InviteLink> {
+    INVITE_RE
+        .captures_iter(text)
+        .map(|c| {
+            let host = c[1].to_lowercase();
+            InviteLink {
+                code: c[2].to_string(),
+                resolvable: host == "discord.gg" || host.ends_with("/invite"),
+            }
+        })
+        .collect()
+}
+
+/// Remembers which guild invite codes point to, so popular invites aren't resolved over and over.
+/// Codes which couldn't be resolved are remembered as pointing nowhere.
+type InviteCache = Arc<Cache<String, Option<GuildId>, BoundedTimedEvictionStrategy<String>>>;
+
+/// Resolves the guild an invite points to, if it can be.
+async fn resolve(ctx: &Context, cache: &InviteCache, code: &str) -> Option<GuildId> {
+    let code = code.to_string();
+    if let Some(guild) = cache.get(&code) {
+        return *guild;
+    }
+
+    let guild = match Invite::get(ctx, &code, false).await {
+        Ok(i) => i.guild.map(|g| g.id),
+        Err(e) => {
+            debug!("couldn't resolve invite {}: {}", code, e);
+            None
+        }
+    };
+
+    cache.insert(&code, guild);
+    guild
+}
+
+/// A message containing invites, checked as a background job since resolving invites takes REST
+/// calls.
+struct InviteCheck {
+    /// The message containing the invites.
+    message: Message,
+    /// The invites in the message.
+    invites: Vec<InviteLink>,
+    /// The guild's invite filter config.
+    config: InviteFilterConfig,
+    /// See [`InviteCache`].
+    cache: InviteCache,
+    /// Whether the message is being scored rather than acted on directly. See
+    /// [`crate::module::scoring`].
+    scored: bool,
+}
+
+impl InviteCheck {
+    /// Finds the first invite in the message which doesn't point to an allowed guild.
+    async fn first_disallowed(&self, ctx: &Context) -> Option<&str> {
+        let gid = self.message.guild_id?;
+        if self.invites.len() > MAX_INVITES_CHECKED {
+            return self.invites.first().map(|i| i.code.as_str());
+        }
+        for invite in &self.invites {
+            let target = if invite.resolvable {
+                resolve(ctx, &self.cache, &invite.code).await
+            } else {
+                None
+            };
+            let allowed = target.map_or(false, |t| t == gid || self.config.allowed_guilds.contains(&t));
+            if !allowed {
+                return Some(&invite.code);
+            }
+        }
+        None
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for InviteCheck {
+    fn name(&self) -> &'static str {
+        "invite-check"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::High
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let code = self.first_disallowed(ctx).await.map(str::to_string);
+        let why = code.map(|c| {
+            debug!("message contained invite {} to another server", c);
+            format!("contained an invite to another server ({})", c)
+        });
+        if self.scored {
+            let hit = why.map(|why| FilterHit {
+                filter: INVITE_FILTER_NAME,
+                why,
+            });
+            return resolve_check(dis, &self.message, hit).await;
+        }
+        match why {
+            Some(why) => act_on_filtered(dis, ctx, &self.message, &why, self.config.action).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Module which removes invites to other servers.
+pub struct InviteFilterModule {
+    /// See [`InviteCache`].
+    cache: InviteCache,
+}
+
+impl Default for InviteFilterModule {
+    fn default() -> Self {
+        Self {
+            cache: Arc::new(Cache::new(BoundedTimedEvictionStrategy::bounded_timed(
+                INVITE_CACHE_TTL,
+                MAX_CACHED_INVITES,
+            ))),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for InviteFilterModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(INVITE_FILTER_NAME, "removes invites to other servers.")
+                .with_sensitivity(Sensitivity::High)
+                .with_message_hook(true)
+                .with_config_value(config::Value::<InviteFilterConfig>::with_default(
+                    INVITE_FILTER_KEY,
+                    "A JSON object configuring the invite filter, e.g. {\"enabled\": true, \"allowed_guilds\": [], \"exempt_roles\": [], \"exempt_channels\": [], \"action\": \"Delete\"}.",
+                    Default::default,
+                ))
+        });
+        &INFO
+    }
+
+    fn cache_statistics(&self) -> Vec<(&'static str, CacheStats)> {
+        vec![("Invites", self.cache.statistics())]
+    }
+
+    fn flush_caches(&self, guild: Option<GuildId>) {
+        // Invites aren't cached per guild, so they're only forgotten by a full flush.
+        if guild.is_none() {
+            self.cache.clear();
+        }
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+
+        let invites = find_invites(&orig.content);
+        if invites.is_empty() {
+            return Ok(());
+        }
+
+        let config = dis
+            .config_value_t::<InviteFilterConfig>(INVITE_FILTER_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !config.enabled || config.exempt_channels.contains(&orig.channel_id) {
+            return Ok(());
+        }
+        let exempt_role = orig
+            .member
+            .as_ref()
+            .map_or(false, |m| m.roles.iter().any(|r| config.exempt_roles.contains(r)));
+        if exempt_role || FilterModule::is_exempt(dis, ctx, orig, gid, self.info().name).await? {
+            trace!("exempt user posted an invite");
+            return Ok(());
+        }
+
+        let scored = defer_check(dis, orig).await?;
+        dis.jobs().enqueue(
+            gid,
+            InviteCheck {
+                message: orig.clone(),
+                invites,
+                config: (*config).clone(),
+                cache: self.cache.clone(),
+                scored,
+            },
+        );
+        Ok(())
+    }
+}
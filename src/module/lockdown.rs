@@ -0,0 +1,363 @@
+//! Contains the `lockdown` command, which stops `@everyone` from sending messages in a configured
+//! set of channels. The channels' previous overwrites are recorded in the database so ending the
+//! lockdown restores them exactly, and a lockdown may be ended automatically after a set duration.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::{ChannelType, Message};
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::model::permissions::Permissions;
+use shrinkwraprs::Shrinkwrap;
+use structopt::StructOpt;
+
+use crate::db::timed::{Action, ActionKind, TimedEvents, ONE_HUNDREDISH_YEARS};
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::error::{DatabaseError, GuildNotInCache, LogErrorExt};
+use crate::module::moderation::mod_log_channel;
+use crate::module::persona::react_success;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::overwrite::{denying_overwrite, restore_overwrite, StoredOverwrite};
+use crate::util::ClapExt;
+
+/// The config key for grabbing a [`LockdownConfig`].
+pub const LOCKDOWN_CONFIG_KEY: &str = "lockdown_config";
+
+/// Configuration for which channels are affected by a lockdown.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LockdownConfig {
+    /// Channels to lock down.
+    #[serde(default)]
+    pub channels: Vec<ChannelId>,
+    /// A category whose text channels should all be locked down.
+    #[serde(default)]
+    pub category: Option<ChannelId>,
+}
+
+impl FromStr for LockdownConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for LockdownConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+impl_err!(AlreadyLockedDown, "A lockdown is already in progress.", true);
+impl_err!(NotLockedDown, "There's no lockdown in progress.", true);
+impl_err!(
+    NoLockdownChannels,
+    "No channels are configured for lockdown; set lockdown_config first.",
+    true
+);
+
+/// A channel which is currently locked down.
+#[derive(Clone, Debug)]
+pub struct LockedChannel {
+    /// The locked channel.
+    pub channel: ChannelId,
+    /// The `@everyone` overwrite on the channel before the lockdown started.
+    pub previous_overwrite: Option<StoredOverwrite>,
+}
+
+/// Wrapper around DbContext to record and restore locked down channels.
+#[derive(Shrinkwrap)]
+pub struct Lockdowns<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> Lockdowns<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        Lockdowns {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Records that a channel is being locked down. Errors if it already is.
+    pub async fn record(
+        &self,
+        channel: ChannelId,
+        started_by: UserId,
+        previous_overwrite: Option<StoredOverwrite>,
+    ) -> crate::error::Result<()> {
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO lockdown_channels (guild, channel, started_by, previous_overwrite)
+            VALUES ($1, $2, $3, $4);
+            "#,
+            self.ctx.guild_as_i64(),
+            channel.0 as i64,
+            started_by.0 as i64,
+            previous_overwrite.map(serde_json::to_value).transpose()?
+        )
+        .execute(self.ctx.conn()?)
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(AlreadyLockedDown.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Forgets a locked down channel, e.g. because it has been restored.
+    pub async fn forget(&self, channel: ChannelId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM lockdown_channels WHERE guild = $1 AND channel = $2;",
+            self.ctx.guild_as_i64(),
+            channel.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Retrieves all channels which are currently locked down.
+    pub async fn locked(&self) -> crate::error::Result<Vec<LockedChannel>> {
+        let rows = sqlx::query!(
+            "SELECT channel, previous_overwrite FROM lockdown_channels WHERE guild = $1;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_all(self.ctx.conn()?)
+        .await?;
+
+        rows.into_iter()
+            .map(|r| {
+                Ok(LockedChannel {
+                    channel: ChannelId::from(r.channel as u64),
+                    previous_overwrite: r.previous_overwrite.map(serde_json::from_value).transpose()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Stops everyone from sending messages in the configured channels.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "lockdown", no_version)]
+pub enum LockdownOpt {
+    /// Starts a lockdown.
+    Start {
+        /// How long the lockdown should last. Specified in human format, i.e. "2h 30m".
+        /// If omitted, the lockdown lasts until it is ended with `lockdown end`.
+        #[structopt(short = "d")]
+        duration: Option<humantime::Duration>,
+    },
+    /// Ends the lockdown, restoring the channels' previous permissions.
+    End,
+}
+
+/// Adds the `lockdown` command.
+pub struct LockdownModule;
+
+/// The outcome of ending a lockdown.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LockdownEnded {
+    /// The number of channels whose permissions were restored.
+    pub restored: usize,
+    /// The number of channels which couldn't be restored. These stay recorded, so ending the
+    /// lockdown again will retry them.
+    pub failed: usize,
+}
+
+/// Determines which channels a lockdown should affect, skipping any that no longer exist.
+async fn lockdown_targets(
+    ctx: &Context,
+    guild: GuildId,
+    config: &LockdownConfig,
+) -> crate::error::Result<Vec<ChannelId>> {
+    let channels = ctx
+        .cache
+        .guild_field(guild, |g| g.channels.clone())
+        .await
+        .ok_or(GuildNotInCache)?;
+
+    let mut targets: Vec<ChannelId> = config
+        .channels
+        .iter()
+        .copied()
+        .filter(|c| channels.contains_key(c))
+        .collect();
+
+    if let Some(category) = config.category {
+        targets.extend(
+            channels
+                .values()
+                .filter(|c| c.category_id == Some(category) && c.kind == ChannelType::Text)
+                .map(|c| c.id),
+        );
+    }
+
+    targets.sort_unstable();
+    targets.dedup();
+    Ok(targets)
+}
+
+/// Posts a note about a lockdown in the mod log, if one is configured.
+async fn log_lockdown(dis: &Dispatch, ctx: &Context, guild: GuildId, note: &str) {
+    if let Ok(chan) = mod_log_channel(dis, guild).await {
+        chan.say(ctx, note).await.map_err(crate::error::Error::from).log_error();
+    }
+}
+
+/// Ends the lockdown in a guild, restoring every locked channel's previous `@everyone` overwrite.
+pub async fn end_lockdown(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<LockdownEnded> {
+    let lockdowns = Lockdowns::new(dis.db(guild));
+    let everyone = RoleId(guild.0);
+    let mut out = LockdownEnded::default();
+
+    for locked in lockdowns.locked().await? {
+        match restore_overwrite(ctx, locked.channel, everyone, locked.previous_overwrite).await {
+            Ok(_) => {
+                lockdowns.forget(locked.channel).await?;
+                out.restored += 1;
+            }
+            Err(e) => {
+                warn!("failed to restore {} after lockdown: {}", locked.channel, e);
+                out.failed += 1;
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Ends a lockdown whose duration has run out, noting it in the mod log.
+pub async fn expire_lockdown(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<()> {
+    let ended = end_lockdown(dis, ctx, guild).await?;
+    if ended.restored + ended.failed > 0 {
+        log_lockdown(dis, ctx, guild, &ended_note("The lockdown expired", ended)).await;
+    }
+    Ok(())
+}
+
+/// Describes the outcome of ending a lockdown.
+fn ended_note(prefix: &str, ended: LockdownEnded) -> String {
+    let mut note = format!("{}; {} channel(s) restored.", prefix, ended.restored);
+    if ended.failed > 0 {
+        note.push_str(&format!(
+            " {} channel(s) couldn't be restored; run `lockdown end` to retry.",
+            ended.failed
+        ));
+    }
+    note
+}
+
+#[async_trait::async_trait]
+impl Module for LockdownModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "lockdown",
+                "stops everyone from sending messages in the configured channels until the lockdown ends.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+            .with_config_value(config::Value::<LockdownConfig>::with_default(
+                LOCKDOWN_CONFIG_KEY,
+                "A JSON object describing which channels to lock down, e.g. {\"channels\": [\"1234\"], \"category\": \"5678\"}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let opts = LockdownOpt::from_iter_with_help(command)?;
+        let db = dis.db(gid);
+        let lockdowns = Lockdowns::new(&db);
+        let timed = TimedEvents::new(db.clone());
+
+        match opts {
+            LockdownOpt::Start { duration } => {
+                if !lockdowns.locked().await?.is_empty() {
+                    return Err(AlreadyLockedDown.into());
+                }
+
+                let config = dis
+                    .config_value_t::<LockdownConfig>(LOCKDOWN_CONFIG_KEY)?
+                    .get_or_default(&db)
+                    .await?;
+                let targets = lockdown_targets(ctx, gid, &config).await?;
+                if targets.is_empty() {
+                    return Err(NoLockdownChannels.into());
+                }
+
+                let everyone = RoleId(gid.0);
+                let mut locked = 0usize;
+                for channel in targets {
+                    let previous = match ctx.cache.guild_channel(channel).await {
+                        Some(c) => StoredOverwrite::of_role(&c, everyone),
+                        None => continue,
+                    };
+
+                    lockdowns.record(channel, orig.author.id, previous).await?;
+                    let overwrite = denying_overwrite(everyone, previous, Permissions::SEND_MESSAGES);
+                    match channel.create_permission(ctx, &overwrite).await {
+                        Ok(_) => locked += 1,
+                        Err(e) => {
+                            warn!("failed to lock down {}: {}", channel, e);
+                            lockdowns.forget(channel).await?;
+                        }
+                    }
+                }
+
+                if let Some(d) = duration {
+                    let chrono_dur = chrono::Duration::from_std(*d).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+                    Action::end_lockdown(gid, chrono_dur).store_action(dis).await?;
+                }
+
+                let until = duration.map_or_else(|| "until it is ended".to_string(), |d| format!("for {}", d));
+                log_lockdown(
+                    dis,
+                    ctx,
+                    gid,
+                    &format!(
+                        "{} started a lockdown of {} channel(s) {}.",
+                        orig.author.tag(),
+                        locked,
+                        until
+                    ),
+                )
+                .await;
+            }
+            LockdownOpt::End => {
+                let ended = end_lockdown(dis, ctx, gid).await?;
+                if ended.restored + ended.failed == 0 {
+                    return Err(NotLockedDown.into());
+                }
+                timed
+                    .cancel_actions_for(UserId::default(), ActionKind::EndLockdown)
+                    .await?;
+
+                let note = ended_note(&format!("{} ended the lockdown", orig.author.tag()), ended);
+                log_lockdown(dis, ctx, gid, &note).await;
+            }
+        }
+
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+}
@@ -102,7 +102,7 @@ impl Module for MockRaidModule {
     fn info(&self) -> &ModInfo {
         static INFO: Lazy<ModInfo> = Lazy::new(|| {
             ModInfo::with_name("mock-raid", "mocks a raid in this server in glimbot.")
-                .with_sensitivity(Sensitivity::Owner)
+                .with_sensitivity(Sensitivity::Operator)
                 .with_command(true)
         });
         &INFO
@@ -5,23 +5,87 @@ use std::cmp::Ordering;
 use std::fmt;
 use std::fmt::Formatter;
 use std::sync::Arc;
+use std::time::Duration;
 
 use serenity::client::Context;
-use serenity::model::channel::Message;
+use serenity::model::channel::{GuildChannel, Message, Reaction};
+use serenity::model::guild::Member;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::interactions::message_component::MessageComponentInteraction;
+use serenity::model::user::User;
+use serenity::model::voice::VoiceState;
 
+use crate::db::cache::CacheStats;
+use crate::dispatch::results::CommandReply;
 use crate::dispatch::{config, Dispatch};
 
+pub mod account_age;
+pub mod activity;
+pub mod afk;
+pub mod archive;
+pub mod attachments;
+pub mod audit;
+pub mod auto_publish;
+pub mod automod;
+pub mod automod_sync;
+pub mod autorole;
+pub mod ban_sync;
 pub mod base_filter;
+pub mod bookmark;
+pub mod cache;
 pub mod conf;
+pub mod counters;
+pub mod defaults;
+pub mod define;
+pub mod dice;
+pub mod duplicates;
+pub mod economy;
+pub mod emoji;
+pub mod emoji_filter;
+pub mod escalation;
+pub mod export;
+pub mod filter;
+pub mod global;
+pub mod growth;
+pub mod image_filter;
 pub mod info;
+pub mod invites;
+pub mod lockdown;
 pub mod mock_raid;
 pub mod moderation;
+pub mod nicknames;
+pub mod operators;
 pub mod owner;
+pub mod persistent_roles;
+pub mod persona;
+pub mod phishing;
 pub mod privilege;
+pub mod purge;
+pub mod queries;
+pub mod quota;
+pub mod raid;
+pub mod raidmode;
+pub mod reasons;
+pub mod regex_filter;
+pub mod remind;
 pub mod roles;
+pub mod scoring;
+pub mod selftest;
+pub mod setup;
 pub mod shutdown;
+pub mod slowmode;
 pub mod spam;
+pub mod stats_channels;
 pub mod status;
+pub mod suggestions;
+pub mod tags;
+pub mod threads;
+pub mod time;
+pub mod transcript;
+pub mod verification;
+pub mod version;
+pub mod voice_hubs;
+pub mod wall_of_text;
 
 pub const CHECKMARK_IN_GREEN_BOX: char = '✅';
 
@@ -34,7 +98,9 @@ pub enum Sensitivity {
     Medium,
     /// Sensitive commands related to managing users/spam
     High,
-    /// Commands only the bot owner should be able to run, like `shutdown`.
+    /// Commands the bot's operators (and owners) can run, like `status`.
+    Operator,
+    /// Commands only the bot's owners should be able to run, like `shutdown`.
     Owner,
 }
 
@@ -43,7 +109,7 @@ impl PartialOrd for Sensitivity {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
             (x, y) if x == y => Some(Ordering::Equal),
-            (Self::Owner, _) | (_, Self::Owner) => None,
+            (Self::Owner, _) | (_, Self::Owner) | (Self::Operator, _) | (_, Self::Operator) => None,
             (Self::High, o) => match o {
                 Sensitivity::Low | Sensitivity::Medium => Ordering::Greater,
                 Sensitivity::High => Ordering::Equal,
@@ -73,6 +139,7 @@ impl fmt::Display for Sensitivity {
             Sensitivity::Low => "low",
             Sensitivity::Medium => "medium",
             Sensitivity::High => "high",
+            Sensitivity::Operator => "operator",
             Sensitivity::Owner => "owner",
         };
         f.write_str(s)
@@ -96,8 +163,36 @@ pub struct ModInfo {
     pub on_tick: bool,
     /// Whether or not this message has an on_message hook.
     pub on_message: bool,
+    /// Whether or not this module has a hook that runs when a member joins a guild.
+    pub on_member_join: bool,
+    /// Whether or not this module has a hook that runs when a reaction is added to a message.
+    pub on_reaction: bool,
+    /// Whether or not this module has a hook that runs when a button or select menu on a message
+    /// is used.
+    pub on_component: bool,
+    /// Whether or not this module has a hook that runs when a user is banned from a guild.
+    pub on_ban: bool,
+    /// Whether or not this module has a hook that runs when a member leaves or is removed from a guild.
+    pub on_member_remove: bool,
+    /// Whether or not this module has a hook that runs when a member's roles or nickname change.
+    pub on_member_update: bool,
+    /// Whether or not this module has a hook that runs when a member joins, leaves or moves between
+    /// voice channels.
+    pub on_voice_state: bool,
+    /// Whether or not this module has a hook that runs when a thread is created, or Glimbot is added
+    /// to one.
+    pub on_thread_create: bool,
+    /// Whether or not this module's command is run in place of unknown commands, with the unknown
+    /// command's name as its first argument. Only the first such module installed is used.
+    pub command_fallback: bool,
+    /// How long the command's rendered results can be reused in a guild, if they can be cached.
+    /// See [`Module::render`].
+    pub result_cache: Option<Duration>,
     /// A short help message about the command.
     pub short_desc: &'static str,
+    /// The module's version. Modules are built into Glimbot, so this is Glimbot's version unless
+    /// a module tracks its own.
+    pub version: &'static str,
 }
 
 impl ModInfo {
@@ -111,10 +206,27 @@ impl ModInfo {
             config_values: Vec::new(),
             on_tick: false,
             on_message: false,
+            on_member_join: false,
+            on_reaction: false,
+            on_component: false,
+            on_ban: false,
+            on_member_remove: false,
+            on_member_update: false,
+            on_voice_state: false,
+            on_thread_create: false,
+            command_fallback: false,
+            result_cache: None,
             short_desc: desc,
+            version: crate::about::VERSION,
         }
     }
 
+    /// Specifies a version for this module, if it's versioned separately from Glimbot.
+    pub fn with_version(mut self, version: &'static str) -> Self {
+        self.version = version;
+        self
+    }
+
     /// Specifies whether or not this module has a command.
     pub fn with_command(mut self, command: bool) -> Self {
         self.command = command;
@@ -150,6 +262,68 @@ impl ModInfo {
         self.on_message = with_hook;
         self
     }
+
+    /// Specifies whether or not this module has a hook that runs when a member joins a guild.
+    pub fn with_member_join_hook(mut self, with_hook: bool) -> Self {
+        self.on_member_join = with_hook;
+        self
+    }
+
+    /// Specifies whether or not this module has a hook that runs when a reaction is added.
+    pub fn with_reaction_hook(mut self, with_hook: bool) -> Self {
+        self.on_reaction = with_hook;
+        self
+    }
+
+    /// Specifies whether or not this module has a hook for button and select menu interactions.
+    pub fn with_component_hook(mut self, with_hook: bool) -> Self {
+        self.on_component = with_hook;
+        self
+    }
+
+    /// Specifies whether or not this module has a hook that runs when a user is banned.
+    pub fn with_ban_hook(mut self, with_hook: bool) -> Self {
+        self.on_ban = with_hook;
+        self
+    }
+
+    /// Specifies whether or not this module has a hook that runs when a member leaves or is removed.
+    pub fn with_member_remove_hook(mut self, with_hook: bool) -> Self {
+        self.on_member_remove = with_hook;
+        self
+    }
+
+    /// Specifies whether or not this module has a hook that runs when a member is updated.
+    pub fn with_member_update_hook(mut self, with_hook: bool) -> Self {
+        self.on_member_update = with_hook;
+        self
+    }
+
+    /// Specifies whether or not this module has a hook that runs when a member's voice state changes.
+    pub fn with_voice_state_hook(mut self, with_hook: bool) -> Self {
+        self.on_voice_state = with_hook;
+        self
+    }
+
+    /// Specifies whether or not this module has a hook that runs when a thread is created.
+    pub fn with_thread_create_hook(mut self, with_hook: bool) -> Self {
+        self.on_thread_create = with_hook;
+        self
+    }
+
+    /// Specifies whether or not this module's command is run in place of unknown commands.
+    pub fn with_command_fallback(mut self, fallback: bool) -> Self {
+        self.command_fallback = fallback;
+        self
+    }
+
+    /// Specifies that the command's results can be reused for repeated invocations in a guild
+    /// within the given time. The module must implement [`Module::render`] instead of
+    /// [`Module::process`].
+    pub fn with_result_cache(mut self, ttl: Duration) -> Self {
+        self.result_cache = Some(ttl);
+        self
+    }
 }
 
 impl_err!(UnimplementedModule, "This module hasn't been finished yet.", true);
@@ -160,6 +334,20 @@ pub trait Module: Sync + Send {
     /// Returns meta information about the module.
     fn info(&self) -> &ModInfo;
 
+    /// Forgets anything the module holds in memory about a user, once their stored data has been
+    /// purged.
+    fn forget_user(&self, _user: UserId) {}
+
+    /// Forgets anything the module caches, for one guild or for every guild if `None`, so it's
+    /// read afresh.
+    fn flush_caches(&self, _guild: Option<GuildId>) {}
+
+    /// Lists statistics for any caches the module keeps, by name, so they can be shown in
+    /// `status`.
+    fn cache_statistics(&self) -> Vec<(&'static str, CacheStats)> {
+        Vec::new()
+    }
+
     /// Applies a filter to the command. The name of the invoked command is specified;
     /// it can be changed or left unchanged, and should be returned if it is okay for the command
     /// to be invoked.
@@ -186,6 +374,19 @@ pub trait Module: Sync + Send {
         Err(UnimplementedModule.into())
     }
 
+    /// Renders the reply to a command without sending it, so that it can be cached. Only called
+    /// for modules which specify [`ModInfo::with_result_cache`]; the command must not have any
+    /// side effects.
+    async fn render(
+        &self,
+        _dis: &Dispatch,
+        _ctx: &Context,
+        _orig: &Message,
+        _command: Vec<String>,
+    ) -> crate::error::Result<CommandReply> {
+        Err(UnimplementedModule.into())
+    }
+
     /// Hook to run some command at a regular interval.
     async fn on_tick(&self, _dis: &Dispatch, _ctx: &Context) -> crate::error::Result<()> {
         Err(UnimplementedModule.into())
@@ -195,4 +396,80 @@ pub trait Module: Sync + Send {
     async fn on_message(&self, _dis: &Dispatch, _ctx: &Context, _orig: &Message) -> crate::error::Result<()> {
         Err(UnimplementedModule.into())
     }
+
+    /// Hook to run when a member joins a guild.
+    async fn on_member_join(&self, _dis: &Dispatch, _ctx: &Context, _member: &Member) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run when a reaction is added to a message in a guild. Reactions from Glimbot itself
+    /// are not passed to this hook.
+    async fn on_reaction(&self, _dis: &Dispatch, _ctx: &Context, _reaction: &Reaction) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run when a button or select menu on a message in a guild is used. Every component
+    /// hook sees every interaction, so modules should ignore custom IDs they didn't create; the
+    /// module that owns the component must respond to the interaction.
+    async fn on_component(
+        &self,
+        _dis: &Dispatch,
+        _ctx: &Context,
+        _interaction: &MessageComponentInteraction,
+    ) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run when a user is banned from a guild, by anyone.
+    async fn on_ban(&self, _dis: &Dispatch, _ctx: &Context, _guild: GuildId, _user: &User) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run when a member leaves a guild or is removed from it, by anyone. The member's last
+    /// state is only available if they were cached.
+    async fn on_member_remove(
+        &self,
+        _dis: &Dispatch,
+        _ctx: &Context,
+        _guild: GuildId,
+        _user: &User,
+        _member: Option<&Member>,
+    ) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run when a member's roles or nickname change. The member's previous state is only
+    /// available if they were cached.
+    async fn on_member_update(
+        &self,
+        _dis: &Dispatch,
+        _ctx: &Context,
+        _old: Option<&Member>,
+        _new: &Member,
+    ) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run when a member joins, leaves or moves between voice channels in a guild, or
+    /// mutes or deafens themselves. The previous state is only available if it was cached.
+    async fn on_voice_state(
+        &self,
+        _dis: &Dispatch,
+        _ctx: &Context,
+        _guild: GuildId,
+        _old: Option<&VoiceState>,
+        _new: &VoiceState,
+    ) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
+
+    /// Hook to run when a thread is created in a guild, or Glimbot is added to a private thread.
+    async fn on_thread_create(
+        &self,
+        _dis: &Dispatch,
+        _ctx: &Context,
+        _thread: &GuildChannel,
+    ) -> crate::error::Result<()> {
+        Err(UnimplementedModule.into())
+    }
 }
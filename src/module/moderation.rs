@@ -2,7 +2,9 @@
 //! chats. Allows moderators to ban, kick, etc, and to set timed bans, kicks, etc.
 
 use std::borrow::{Borrow, Cow};
+use std::collections::HashSet;
 
+use chrono::{DateTime, Utc};
 use humantime::Duration;
 use once_cell::sync::Lazy;
 use serenity::builder::CreateEmbed;
@@ -11,13 +13,25 @@ use serenity::model::channel::Message;
 use serenity::model::guild::Member;
 use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
 use serenity::model::misc::Mentionable;
-use serenity::utils::Color;
+use serenity::model::user::User;
+use serenity::utils::{Color, MessageBuilder};
 use structopt::StructOpt;
 
-use crate::db::timed::{Action, ONE_HUNDREDISH_YEARS};
+use crate::db::cases::{Case, Cases, Evidence};
+use crate::db::timed::{self, Action, TimedEvents, ONE_HUNDREDISH_YEARS};
 use crate::db::DbContext;
-use crate::dispatch::config::{FromStrWithCtx, Value, VerifiedChannel, VerifiedRole, VerifiedUser};
+use crate::dispatch::config::{
+    parse_user_id, FromStrWithCtx, NoSuchUser, Value, VerifiedChannel, VerifiedRole, VerifiedUser,
+};
+use crate::dispatch::rest::RetryOp;
 use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::escalation::{
+    cases_with_decay, escalate_after_warning, EscalationRules, WarningTtl, WARNING_TTL_KEY, WARN_ESCALATION_KEY,
+};
+use crate::module::persona::react_success;
+use crate::module::reasons::{expand_reason, reason_presets, ReasonPresets, REASON_PRESETS_KEY};
+use crate::module::time::GuildClock;
 use crate::module::{ModInfo, Module, Sensitivity};
 use crate::util::constraints::AtMostU64;
 use crate::util::ClapExt;
@@ -31,7 +45,8 @@ pub struct ModerationModule;
 pub struct CommonOpts {
     /// Which user the action should apply to.
     user: String,
-    /// Why the action is being taken.
+    /// Why the action is being taken. Can be the name of one of the server's reason presets; see
+    /// `mod reasons`.
     reason: Option<String>,
 }
 
@@ -42,9 +57,10 @@ pub enum ModOpt {
     Warn(CommonOpts),
     /// Kick a user from the server.
     Kick(CommonOpts),
-    /// Ban a user from the server.
+    /// Ban a user from the server. Users who aren't members, like raiders who already left, can be
+    /// banned by ID.
     Ban {
-        ///
+        /// Which user to act on, and why.
         #[structopt(flatten)]
         common: CommonOpts,
         /// How long the user should be banned for. Specified in human format, i.e. "5d 2h 5m"
@@ -52,15 +68,22 @@ pub enum ModOpt {
         #[structopt(short = "d")]
         duration: Option<humantime::Duration>,
         #[structopt(short = "m")]
-        /// How many days of messages from the user should be deleted.
+        /// How many days of messages from the user should be deleted. Defaults to ban_delete_days.
         delete_messages: Option<AtMostU64<7>>,
     },
-    /// Bans a user with max number of days for message deletion, then unbans them.
+    /// Bans a user, deleting their recent messages, then unbans them.
     /// Useful for deleting spam.
-    SoftBan(CommonOpts),
+    SoftBan {
+        /// Which user to act on, and why.
+        #[structopt(flatten)]
+        common: CommonOpts,
+        #[structopt(short = "m")]
+        /// How many days of messages from the user should be deleted. Defaults to softban_delete_days.
+        delete_messages: Option<AtMostU64<7>>,
+    },
     /// Adds the muted user role to a user.
     Mute {
-        ///
+        /// Which user to act on, and why.
         #[structopt(flatten)]
         common: CommonOpts,
         #[structopt(short = "d")]
@@ -68,30 +91,76 @@ pub enum ModOpt {
         /// Max 100 years, min 1 minute. Very large values may be interpreted as indefinite in duration.
         duration: Option<humantime::Duration>,
     },
+    /// Unbans a user immediately, cancelling any pending timed unban. Accepts a user ID or mention,
+    /// since banned users aren't members of the server.
+    Unban(CommonOpts),
+    /// Removes the muted user role from a user immediately, cancelling any pending timed unmute.
+    Unmute(CommonOpts),
+    /// Times a user out using Discord's native timeouts, which stop them from sending messages,
+    /// reacting and joining voice channels without needing a mute role.
+    Timeout {
+        /// Which user to act on, and why.
+        #[structopt(flatten)]
+        common: CommonOpts,
+        #[structopt(short = "d", default_value = "1h")]
+        /// How long the user should be timed out for. Specified in human format, i.e. "2h 30m".
+        /// Discord limits timeouts to 28 days.
+        duration: humantime::Duration,
+    },
+    /// Removes a user's timeout immediately, cancelling any pending timed removal.
+    Untimeout(CommonOpts),
+    /// Bans every user in a list, e.g. to clear out a raid. IDs or mentions can be pasted after the
+    /// command or attached as a text file, separated by spaces, commas or new lines.
+    #[structopt(name = "massban")]
+    MassBan {
+        /// The IDs or mentions of the users to ban.
+        ids: Vec<String>,
+        /// Why the users are being banned. Shared by every ban. Can be the name of a reason preset.
+        #[structopt(short, long)]
+        reason: Option<String>,
+    },
+    /// Shows a moderation case, including any evidence recorded with it.
+    Case {
+        /// The case number, as shown in the mod log.
+        id: i64,
+    },
+    /// Lists the server's reason presets, which can be given by name in place of a reason.
+    Reasons,
 }
 
 impl ModOpt {
-    /// Retrieves the [`CommonOpts`] from each variant.
-    pub fn common_args(&self) -> &CommonOpts {
+    /// Retrieves the [`CommonOpts`] from each variant which acts on a user.
+    pub fn common_args(&self) -> Option<&CommonOpts> {
         match self {
-            ModOpt::Warn(c) => c,
-            ModOpt::Kick(c) => c,
-            ModOpt::Ban { common, .. } => common,
-            ModOpt::SoftBan(c) => c,
-            ModOpt::Mute { common, .. } => common,
+            ModOpt::Warn(c) => Some(c),
+            ModOpt::Kick(c) => Some(c),
+            ModOpt::Ban { common, .. } => Some(common),
+            ModOpt::SoftBan { common, .. } => Some(common),
+            ModOpt::Mute { common, .. } => Some(common),
+            ModOpt::Unban(c) => Some(c),
+            ModOpt::Unmute(c) => Some(c),
+            ModOpt::Timeout { common, .. } => Some(common),
+            ModOpt::Untimeout(c) => Some(c),
+            ModOpt::Case { .. } | ModOpt::MassBan { .. } | ModOpt::Reasons => None,
         }
     }
 
-    /// Retrieves the [`ActionKind`] which matches this variant.
-    pub fn kind(&self) -> ActionKind {
+    /// Retrieves the [`ActionKind`] which matches this variant, if it acts on a user.
+    pub fn kind(&self) -> Option<ActionKind> {
         use ActionKind::*;
-        match self {
+        let kind = match self {
             ModOpt::Warn(_) => Warn,
             ModOpt::Kick(_) => Kick,
             ModOpt::Ban { .. } => Ban,
-            ModOpt::SoftBan(_) => SoftBan,
+            ModOpt::SoftBan { .. } => SoftBan,
             ModOpt::Mute { .. } => Mute,
-        }
+            ModOpt::Unban(_) => Unban,
+            ModOpt::Unmute(_) => Unmute,
+            ModOpt::Timeout { .. } => Timeout,
+            ModOpt::Untimeout(_) => Untimeout,
+            ModOpt::Case { .. } | ModOpt::MassBan { .. } | ModOpt::Reasons => return None,
+        };
+        Some(kind)
     }
 
     /// Retrieves the duration for a timed action, if it exists.
@@ -99,14 +168,16 @@ impl ModOpt {
         match self {
             ModOpt::Ban { duration, .. } => *duration,
             ModOpt::Mute { duration, .. } => *duration,
+            ModOpt::Timeout { duration, .. } => Some(*duration),
             _ => None,
         }
     }
 
-    /// Retrieves the deletion time for a ban.
+    /// Retrieves the deletion time for a ban or soft ban.
     pub fn deletion_time(&self) -> Option<AtMostU64<7>> {
         match self {
             ModOpt::Ban { delete_messages, .. } => *delete_messages,
+            ModOpt::SoftBan { delete_messages, .. } => *delete_messages,
             _ => None,
         }
     }
@@ -116,6 +187,32 @@ pub const MOD_CHANNEL: &str = "mod_log_channel";
 /// Config key for the mute role, which should be assigned to users to prevent them from sending
 /// messages.
 pub const MUTE_ROLE: &str = "mute_role";
+/// Config key for the message users are sent when action is taken against them.
+pub const DM_TEMPLATE: &str = "mod_dm_template";
+/// Config key for a link users can follow to appeal moderation actions.
+pub const APPEAL_URL: &str = "appeal_url";
+/// Config key for the channel notices are posted in for members whose DMs are closed.
+pub const DM_FALLBACK_CHANNEL: &str = "mod_dm_fallback_channel";
+/// The most characters sent in a notice, keeping it under Discord's message size limit.
+pub const MAX_NOTICE_CHARS: usize = 1900;
+/// Config key for the number of days of messages deleted by bans which don't specify it.
+pub const BAN_DELETE_DAYS: &str = "ban_delete_days";
+/// Config key for the number of days of messages deleted by soft bans which don't specify it.
+pub const SOFTBAN_DELETE_DAYS: &str = "softban_delete_days";
+/// Config key for whether the anti-spam and filters only report what they would have done.
+pub const SIMULATE_ACTIONS: &str = "simulate_actions";
+/// The longest timeout Discord allows.
+pub const MAX_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(28 * 24 * 60 * 60);
+/// The most users `mod massban` will ban at once.
+pub const MAX_MASS_BAN: usize = 1000;
+/// The largest ID list attachment `mod massban` will read, in bytes.
+pub const MAX_ID_LIST_BYTES: u64 = 256 * 1024;
+/// The number of users banned between pauses during a mass ban.
+pub const MASS_BAN_BATCH_SIZE: usize = 10;
+/// The pause between mass ban batches, to stay clear of Discord's rate limits.
+pub const MASS_BAN_BATCH_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+/// The smallest possible user ID; anything lower would predate Discord.
+const MIN_SNOWFLAKE: u64 = 1 << 22;
 
 #[async_trait::async_trait]
 impl Module for ModerationModule {
@@ -130,6 +227,47 @@ impl Module for ModerationModule {
                     "Channel for logging moderation actions.",
                 ))
                 .with_config_value(Value::<VerifiedRole>::new(MUTE_ROLE, "Role to assign to muted users."))
+                .with_config_value(Value::<String>::new(
+                    DM_TEMPLATE,
+                    "A message to DM users when they're warned, kicked, banned, muted or timed out. {action}, {reason}, {duration}, {guild} and {appeal_url} are filled in with details of the action. Users aren't messaged if unset.",
+                ))
+                .with_config_value(Value::<String>::new(
+                    APPEAL_URL,
+                    "A link users can follow to appeal moderation actions; fills in {appeal_url} in mod_dm_template.",
+                ))
+                .with_config_value(Value::<VerifiedChannel>::new(
+                    DM_FALLBACK_CHANNEL,
+                    "A channel to post mod_dm_template in, mentioning the user, when a member who was warned, muted or timed out has closed their DMs.",
+                ))
+                .with_config_value(Value::<EscalationRules>::with_default(
+                    WARN_ESCALATION_KEY,
+                    "A JSON list of rules for automatically escalating warnings, e.g. [{\"warnings\": 3, \"window\": \"30d\", \"action\": \"Mute\", \"duration\": \"1d\"}].",
+                    Default::default,
+                ))
+                .with_config_value(Value::<AtMostU64<7>>::with_default(
+                    BAN_DELETE_DAYS,
+                    "How many days of messages to delete when banning a user, if -m isn't given. At most 7.",
+                    Default::default,
+                ))
+                .with_config_value(Value::<AtMostU64<7>>::with_default(
+                    SOFTBAN_DELETE_DAYS,
+                    "How many days of messages to delete when soft banning a user, if -m isn't given. At most 7.",
+                    || AtMostU64::new(7).unwrap(),
+                ))
+                .with_config_value(Value::<ReasonPresets>::with_default(
+                    REASON_PRESETS_KEY,
+                    "A JSON object of reason presets, like {\"spam\": \"Spamming messages.\"}. Giving a preset's name as the reason for an action uses its text instead.",
+                    Default::default,
+                ))
+                .with_config_value(Value::<bool>::with_default(
+                    SIMULATE_ACTIONS,
+                    "If true, the anti-spam and filters don't delete messages or act against anyone; they post what they would have done in mod_log_channel instead, so thresholds can be tuned safely. Moderators' own commands still work as usual.",
+                    || false,
+                ))
+                .with_config_value(Value::<WarningTtl>::new(
+                    WARNING_TTL_KEY,
+                    "How long warnings count toward warn_escalation, like \"90d\". Expired warnings stay in the case history. Warnings never expire if unset.",
+                ))
         });
 
         &INFO
@@ -144,33 +282,290 @@ impl Module for ModerationModule {
     ) -> crate::error::Result<()> {
         let gid = orig.guild_id.unwrap();
         let opts = ModOpt::from_iter_with_help(command)?;
-        let common = opts.common_args();
-        let kind = opts.kind();
-        let orig_mess = orig.message_reference.as_ref().map(|m| m.message_id).flatten();
+
+        if let ModOpt::Case { id } = opts {
+            let cases = cases_with_decay(dis, gid).await?;
+            let case = cases.get(id).await?.ok_or(NoSuchCase)?;
+            let decayed = cases.is_decayed(&case);
+            let clock = GuildClock::for_guild(dis, gid).await?;
+            orig.channel_id
+                .send_message(ctx, |m| {
+                    m.embed(|e| {
+                        create_case_embed(&case, &clock, e);
+                        if decayed {
+                            e.field("Status", "Expired; no longer counts toward escalation.", false);
+                        }
+                        e
+                    })
+                })
+                .await?;
+            return Ok(());
+        }
+
+        if let ModOpt::Reasons = opts {
+            let presets = reason_presets(dis, gid).await?;
+            let message = if presets.is_empty() {
+                "No reason presets are set.".to_string()
+            } else {
+                presets
+                    .iter()
+                    .map(|(name, text)| format!("{}: {}", name, text))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+            orig.reply(ctx, message).await?;
+            return Ok(());
+        }
+
+        if let ModOpt::MassBan { ids, reason } = &opts {
+            let reason = match reason.clone() {
+                Some(r) => Some(expand_reason(dis, gid, r).await?),
+                None => None,
+            };
+            mass_ban(dis, ctx, orig, ids, reason.as_deref()).await?;
+            react_success(dis, ctx, orig).await?;
+            return Ok(());
+        }
+
+        let (common, kind) = match (opts.common_args(), opts.kind()) {
+            (Some(c), Some(k)) => (c, k),
+            _ => unreachable!("every other subcommand acts on a user"),
+        };
+        let orig_mess = orig.message_reference.as_ref().and_then(|m| m.message_id);
         let duration = opts.duration();
+        let deletion_days = opts.deletion_time();
         let channel = orig.channel_id;
 
-        let user = VerifiedUser::from_str_with_ctx(&common.user, ctx, gid).await?;
-        let member = gid.member(ctx, user.into_inner()).await?;
-
-        let mut action = ModAction::new(&member, channel, orig.author.id, kind).with_duration(duration);
+        let mut action = if kind == ActionKind::Unban {
+            // Banned users aren't members, so we can only look them up by id.
+            let user = user_by_id(ctx, &common.user).await?;
+            ModAction::for_user(user, gid, channel, orig.author.id, kind)
+        } else {
+            match VerifiedUser::from_str_with_ctx(&common.user, ctx, gid).await {
+                Ok(user) => {
+                    let member = gid.member(ctx, user.into_inner()).await?;
+                    ModAction::new(&member, channel, orig.author.id, kind)
+                }
+                // Users can be banned pre-emptively, e.g. raiders who already left or never joined.
+                Err(_) if kind == ActionKind::Ban => {
+                    let user = user_by_id(ctx, &common.user).await?;
+                    ModAction::for_user(user, gid, channel, orig.author.id, kind)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        .with_duration(duration)
+        .with_deletion_days(deletion_days);
 
         if let Some(m) = orig_mess {
             action = action.with_original_message(m);
         }
 
         if let Some(r) = common.reason.clone() {
-            action = action.with_reason(r);
+            action = action.with_reason(expand_reason(dis, gid, r).await?);
         }
 
-        action.act(dis, ctx).await?;
-        action.report_action(dis, ctx).await?;
-        orig.react(ctx, '✅').await?;
+        let case = action.act_and_notify(dis, ctx).await?;
+        action.report_action(dis, ctx, case).await?;
+
+        if kind == ActionKind::Warn {
+            escalate_after_warning(dis, ctx, &action).await?;
+        }
+
+        react_success(dis, ctx, orig).await?;
 
         Ok(())
     }
 }
 
+/// Looks up a user by ID or mention, whether or not they're a member of the guild.
+async fn user_by_id(ctx: &Context, s: &str) -> crate::error::Result<User> {
+    let uid = parse_user_id(s).ok_or(NoSuchUser)?;
+    Ok(uid.to_user(ctx).await?)
+}
+
+/// User IDs read from a mass ban list.
+struct IdList {
+    /// Valid user IDs, without duplicates, in the order they were listed.
+    ids: Vec<UserId>,
+    /// Entries which weren't user IDs or mentions.
+    invalid: Vec<String>,
+}
+
+/// Parses a list of user IDs or mentions separated by whitespace or commas.
+fn parse_id_list(text: &str) -> IdList {
+    let mut seen = HashSet::new();
+    let mut out = IdList {
+        ids: Vec::new(),
+        invalid: Vec::new(),
+    };
+    for entry in text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|e| !e.is_empty())
+    {
+        match parse_user_id(entry) {
+            Some(id) if id.0 >= MIN_SNOWFLAKE => {
+                if seen.insert(id) {
+                    out.ids.push(id);
+                }
+            }
+            _ => out.invalid.push(entry.to_string()),
+        }
+    }
+    out
+}
+
+/// Bans a single user from a mass ban list, returning the id of the new case.
+async fn mass_ban_one(
+    dis: &Dispatch,
+    ctx: &Context,
+    orig: &Message,
+    user: UserId,
+    reason: Option<&str>,
+) -> crate::error::Result<i64> {
+    let user = user.to_user(ctx).await?;
+    let mut action = ModAction::for_user(
+        user,
+        orig.guild_id.unwrap(),
+        orig.channel_id,
+        orig.author.id,
+        ActionKind::Ban,
+    );
+    if let Some(r) = reason {
+        action = action.with_reason(r.to_string());
+    }
+    action.act(dis, ctx).await
+}
+
+/// Bans every user listed in a message and its attachments in small batches, reporting progress
+/// as it goes. Each banned user gets their own case, and a single summary is posted to the mod log.
+async fn mass_ban(
+    dis: &Dispatch,
+    ctx: &Context,
+    orig: &Message,
+    ids: &[String],
+    reason: Option<&str>,
+) -> crate::error::Result<()> {
+    let gid = orig.guild_id.unwrap();
+    let mut text = ids.join(" ");
+    for a in &orig.attachments {
+        if a.size > MAX_ID_LIST_BYTES {
+            return Err(IdListTooLarge.into());
+        }
+        let bytes = a.download().await?;
+        text.push('\n');
+        text.push_str(&String::from_utf8_lossy(&bytes));
+    }
+
+    let list = parse_id_list(&text);
+    if list.ids.is_empty() {
+        return Err(NoIdsGiven.into());
+    }
+    if list.ids.len() > MAX_MASS_BAN {
+        return Err(TooManyIds.into());
+    }
+    // Make sure the mod log is set before banning anyone, so the summary has somewhere to go.
+    let log = mod_log_channel(dis, gid).await?;
+
+    let mut protected = vec![orig.author.id, dis.bot().await];
+    if let Some(owner) = ctx.cache.guild_field(gid, |g| g.owner_id).await {
+        protected.push(owner);
+    }
+
+    let total = list.ids.len();
+    let mut progress = orig.channel_id.say(ctx, format!("Banning {} users...", total)).await?;
+    let mut banned = Vec::with_capacity(total);
+    let mut failed = Vec::new();
+    let mut skipped = 0;
+
+    for (i, batch) in list.ids.chunks(MASS_BAN_BATCH_SIZE).enumerate() {
+        if !dis.rest().breaker().allows_essential() {
+            warn!("Discord API degraded; halting mass ban");
+            skipped = total - i * MASS_BAN_BATCH_SIZE;
+            break;
+        }
+
+        if i > 0 {
+            tokio::time::sleep(MASS_BAN_BATCH_DELAY).await;
+        }
+
+        for user in batch {
+            if protected.contains(user) {
+                failed.push(*user);
+                continue;
+            }
+            match mass_ban_one(dis, ctx, orig, *user, reason).await {
+                Ok(case) => banned.push((*user, case)),
+                Err(e) => {
+                    debug!("failed to mass ban {}: {}", user, e);
+                    failed.push(*user);
+                }
+            }
+        }
+
+        let done = ((i + 1) * MASS_BAN_BATCH_SIZE).min(total);
+        progress
+            .edit(ctx, |m| {
+                m.content(format!("Banning {} users... {}/{}", total, done, total))
+            })
+            .await
+            .map_err(crate::error::Error::from)
+            .log_error();
+    }
+
+    let mut summary = format!("Banned {} of {} users.", banned.len(), total);
+    if !failed.is_empty() {
+        summary.push_str(&format!("\n{} could not be banned.", failed.len()));
+    }
+    if !list.invalid.is_empty() {
+        summary.push_str(&format!("\n{} entries weren't user IDs.", list.invalid.len()));
+    }
+    if skipped > 0 {
+        summary.push_str(&format!(
+            "\nStopped early because Discord is having trouble; {} users were not processed.",
+            skipped
+        ));
+    }
+    info!("{} mass banned {} users in {}", orig.author.id, banned.len(), gid);
+
+    let mut details = String::new();
+    for (user, case) in &banned {
+        details.push_str(&format!("{} banned (case #{})\n", user, case));
+    }
+    for user in &failed {
+        details.push_str(&format!("{} failed\n", user));
+    }
+    for entry in &list.invalid {
+        details.push_str(&format!("{} invalid\n", entry));
+    }
+    if details.is_empty() {
+        details.push_str("No users were processed.\n");
+    }
+    let file_name = format!("massban-{}.txt", orig.id);
+
+    let cases = match (banned.first(), banned.last()) {
+        (Some((_, first)), Some((_, last))) => format!("#{} to #{}", first, last),
+        _ => "None".to_string(),
+    };
+    log.send_files(ctx, vec![(details.as_bytes(), file_name.as_str())], |m| {
+        m.embed(|e| {
+            e.color(ActionKind::Ban.color())
+                .title(format!("Mass ban: {} users", banned.len()))
+                .field("Reason", reason.unwrap_or("No reason specified."), false)
+                .field("Moderator", orig.author.id.mention(), false)
+                .field("Channel", orig.channel_id.mention(), false)
+                .field("Cases", cases, false)
+                .field("Summary", &summary, false)
+        })
+    })
+    .await?;
+
+    let content = MessageBuilder::new().push_codeblock_safe(summary, None).build();
+    progress.edit(ctx, |m| m.content(content)).await?;
+    Ok(())
+}
+
 /// The kind of action to take against a user.
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -186,6 +581,14 @@ pub enum ActionKind {
     Ban,
     /// Applies the mute role to a user.
     Mute,
+    /// Reverses a ban.
+    Unban,
+    /// Removes the mute role from a user.
+    Unmute,
+    /// Times a user out with Discord's native timeouts.
+    Timeout,
+    /// Removes a user's timeout.
+    Untimeout,
 }
 
 impl ActionKind {
@@ -203,7 +606,8 @@ impl ActionKind {
             ActionKind::Kick => Self::SAFETY_ORANGE,
             ActionKind::SoftBan => Color::FABLED_PINK,
             ActionKind::Ban => Self::TRAFFIC_RED,
-            ActionKind::Mute => Color::DARK_BLUE,
+            ActionKind::Mute | ActionKind::Timeout => Color::DARK_BLUE,
+            ActionKind::Unban | ActionKind::Unmute | ActionKind::Untimeout => Color::DARK_GREEN,
         }
     }
 
@@ -215,6 +619,10 @@ impl ActionKind {
             ActionKind::SoftBan => "soft ban",
             ActionKind::Ban => "ban",
             ActionKind::Mute => "mute",
+            ActionKind::Unban => "unban",
+            ActionKind::Unmute => "unmute",
+            ActionKind::Timeout => "timeout",
+            ActionKind::Untimeout => "timeout removal",
         }
     }
 
@@ -226,21 +634,76 @@ impl ActionKind {
             ActionKind::SoftBan => "Soft ban",
             ActionKind::Ban => "Ban",
             ActionKind::Mute => "Mute",
+            ActionKind::Unban => "Unban",
+            ActionKind::Unmute => "Unmute",
+            ActionKind::Timeout => "Timeout",
+            ActionKind::Untimeout => "Timeout removal",
+        }
+    }
+
+    /// Retrieves the past tense of this action, completing the sentence "Glimbot ... the user".
+    pub const fn past_tense(&self) -> &str {
+        match self {
+            ActionKind::Warn => "warned",
+            ActionKind::Kick => "kicked",
+            ActionKind::SoftBan => "soft banned",
+            ActionKind::Ban => "banned",
+            ActionKind::Mute => "muted",
+            ActionKind::Unban => "unbanned",
+            ActionKind::Unmute => "unmuted",
+            ActionKind::Timeout => "timed out",
+            ActionKind::Untimeout => "removed the timeout of",
         }
     }
 
     /// Returns true if this action has a sensible duration (i.e. can reasonably be automatically
     /// reversed).
     pub const fn has_duration(&self) -> bool {
-        matches!(self, ActionKind::Ban | ActionKind::Mute)
+        matches!(self, ActionKind::Ban | ActionKind::Mute | ActionKind::Timeout)
+    }
+
+    /// Returns true if this action reverses a previous action.
+    pub const fn is_reversal(&self) -> bool {
+        matches!(self, ActionKind::Unban | ActionKind::Unmute | ActionKind::Untimeout)
+    }
+
+    /// Returns true if this action removes the user from the guild.
+    pub const fn removes_user(&self) -> bool {
+        matches!(self, ActionKind::Kick | ActionKind::SoftBan | ActionKind::Ban)
+    }
+}
+
+/// How a user was told about an action taken against them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Notice {
+    /// The user was sent a DM.
+    Direct,
+    /// The user's DMs are closed, so the notice was posted in the given channel instead.
+    Fallback(ChannelId),
+    /// The user's DMs are closed and the notice couldn't be posted anywhere else.
+    Undelivered,
+}
+
+impl Notice {
+    /// Describes how the user was notified, for the mod log.
+    pub fn describe(&self) -> String {
+        match self {
+            Notice::Direct => "By DM".to_string(),
+            Notice::Fallback(c) => format!("In {} (DMs closed)", c.mention()),
+            Notice::Undelivered => "No (DMs closed)".to_string(),
+        }
     }
 }
 
 /// Contains information about a moderation action.
 #[derive(Debug, Clone)]
 pub struct ModAction {
-    /// The user to take an action against.
-    user: Member,
+    /// The user to take an action against. They may not be a member of the guild.
+    user: User,
+    /// The guild in which the action takes place.
+    guild: GuildId,
+    /// The name to display for the user in the mod log.
+    display_name: String,
     /// The action to take.
     action: ActionKind,
     /// The moderator who initiated the action.
@@ -255,6 +718,12 @@ pub struct ModAction {
     duration: Option<Duration>,
     /// The number of days to delete messages for a ban.
     deletion_days: Option<AtMostU64<7>>,
+    /// Evidence to attach to the case for this action.
+    evidence: Option<Evidence>,
+    /// How the user was told about the action, if they were.
+    notice: Option<Notice>,
+    /// Whether the action was taken directly in Discord rather than through Glimbot.
+    external: bool,
 }
 
 impl ModAction {
@@ -266,7 +735,7 @@ impl ModAction {
 
 impl ModAction {
     /// Grabs a reference to the target user.
-    pub fn user(&self) -> &Member {
+    pub fn user(&self) -> &User {
         &self.user
     }
     /// Accessor for the action.
@@ -294,15 +763,33 @@ impl ModAction {
     }
     /// Returns the guild in which the action took place.
     pub fn guild(&self) -> GuildId {
-        self.user().guild_id
+        self.guild
+    }
+    /// Returns the channel in which the action was taken.
+    pub fn channel(&self) -> ChannelId {
+        self.channel
+    }
+    /// Returns the evidence attached to the action, if any.
+    pub fn evidence(&self) -> Option<&Evidence> {
+        self.evidence.as_ref()
     }
 }
 
 impl ModAction {
-    /// Creates a mod action.
+    /// Creates a mod action against a member of a guild.
     pub fn new(mem: impl Borrow<Member>, channel_id: ChannelId, moderator: UserId, action: ActionKind) -> Self {
+        let mem = mem.borrow();
+        let mut out = Self::for_user(mem.user.clone(), mem.guild_id, channel_id, moderator, action);
+        out.display_name = mem.display_name().into_owned();
+        out
+    }
+
+    /// Creates a mod action against a user who may not be a member of the guild.
+    pub fn for_user(user: User, guild: GuildId, channel_id: ChannelId, moderator: UserId, action: ActionKind) -> Self {
         ModAction {
-            user: mem.borrow().clone(),
+            display_name: user.name.clone(),
+            user,
+            guild,
             action,
             moderator,
             channel: channel_id,
@@ -310,47 +797,220 @@ impl ModAction {
             original_message: None,
             duration: None,
             deletion_days: None,
+            evidence: None,
+            notice: None,
+            external: false,
         }
     }
 
-    /// Performs the action in a guild.
-    pub async fn act(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+    /// Creates a follow-up action against the same user, e.g. when escalating a warning.
+    pub fn follow_up(&self, moderator: UserId, action: ActionKind) -> Self {
+        ModAction {
+            user: self.user.clone(),
+            guild: self.guild,
+            display_name: self.display_name.clone(),
+            action,
+            moderator,
+            channel: self.channel,
+            reason: None,
+            original_message: self.original_message,
+            duration: None,
+            deletion_days: None,
+            evidence: None,
+            notice: None,
+            external: false,
+        }
+    }
+
+    /// Performs the action in a guild, returning the id of the case opened for it.
+    pub async fn act(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<i64> {
         match self.action {
             ActionKind::Warn => {}
             ActionKind::Kick => {
-                self.user().kick_with_reason(ctx, self.reason()).await?;
+                self.guild.kick_with_reason(ctx, self.user.id, self.reason()).await?;
             }
             ActionKind::SoftBan => {
-                self.user().ban_with_reason(ctx, 7, self.reason()).await?;
-                self.user().unban(ctx).await?;
+                let days = self.resolve_deletion_days(dis).await?;
+                self.guild
+                    .ban_with_reason(ctx, self.user.id, days, self.reason())
+                    .await?;
+                let unban = RetryOp::Unban {
+                    guild: self.guild,
+                    user: self.user.id,
+                };
+                dis.rest().perform_or_queue(ctx, unban).await?;
             }
             ActionKind::Ban => {
-                self.user()
-                    .ban_with_reason(
-                        ctx,
-                        self.deletion_days.map(Into::into).unwrap_or(0u64) as u8,
-                        self.reason(),
-                    )
+                let days = self.resolve_deletion_days(dis).await?;
+                self.guild
+                    .ban_with_reason(ctx, self.user.id, days, self.reason())
                     .await?;
             }
             ActionKind::Mute => {
                 self.mute_user(dis, ctx).await?;
             }
+            ActionKind::Unban => {
+                let unban = RetryOp::Unban {
+                    guild: self.guild,
+                    user: self.user.id,
+                };
+                dis.rest().perform_or_queue(ctx, unban).await?;
+                self.cancel_pending(dis, timed::ActionKind::Ban).await?;
+            }
+            ActionKind::Unmute => {
+                self.unmute_user(dis, ctx).await?;
+                self.cancel_pending(dis, timed::ActionKind::Mute).await?;
+            }
+            ActionKind::Timeout => {
+                let until = Utc::now() + chrono::Duration::seconds(self.timeout_duration()?.as_secs() as i64);
+                set_timeout(ctx, self.guild, self.user.id, Some(until)).await?;
+            }
+            ActionKind::Untimeout => {
+                set_timeout(ctx, self.guild, self.user.id, None).await?;
+                self.cancel_pending(dis, timed::ActionKind::Timeout).await?;
+            }
         }
 
-        if let Some(d) = self.duration() {
-            let chrono_dur = chrono::Duration::from_std(*d).unwrap_or_else(|_| (*ONE_HUNDREDISH_YEARS));
-            let a = match self.action {
-                ActionKind::Ban => Action::unban(self.user().user.id, self.guild(), chrono_dur),
-                ActionKind::Mute => Action::unmute(self.user().user.id, self.guild(), chrono_dur),
+        // Timeouts always end, so their removal is always scheduled.
+        let duration = match self.action {
+            ActionKind::Timeout => Some(self.timeout_duration()?),
+            _ => self.duration().map(Into::into),
+        };
+
+        let reversal = duration.and_then(|d| {
+            let chrono_dur = chrono::Duration::from_std(d).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+            match self.action {
+                ActionKind::Ban => Some(Action::unban(self.user.id, self.guild, chrono_dur)),
+                ActionKind::Mute => Some(Action::unmute(self.user.id, self.guild, chrono_dur)),
+                ActionKind::Timeout => Some(Action::untimeout(self.user.id, self.guild, chrono_dur)),
                 _ => {
                     warn!("Got a duration with a nonsensical attribute.");
-                    return Ok(());
+                    None
                 }
-            };
-            a.store_action(dis).await?;
+            }
+        });
+
+        // Without Postgres there's nowhere to keep cases, so the reversal is scheduled on its own
+        // before the case is recorded; a missing case store mustn't leave a mute in place forever.
+        if dis.storage().postgres().is_none() {
+            if let Some(a) = reversal {
+                a.store_action(dis).await?;
+            }
+            return self.record(dis).await;
         }
-        Ok(())
+
+        // The case and its scheduled reversal are stored together, so neither outlives the other.
+        let (user, moderator, action) = (self.user.id, self.moderator, self.action);
+        let (reason, evidence) = (self.reason.clone(), self.evidence.clone());
+        dis.db(self.guild)
+            .transaction(|tx| {
+                Box::pin(async move {
+                    let case =
+                        Cases::record_in(tx, user, moderator, action, reason.as_deref(), evidence.as_ref()).await?;
+                    if let Some(a) = reversal {
+                        TimedEvents::store_action_in(tx, &a).await?;
+                    }
+                    Ok(case)
+                })
+            })
+            .await
+    }
+
+    /// Opens a case for the action without performing it, returning the case's id. Used directly for
+    /// actions which have already been taken outside of Glimbot.
+    pub async fn record(&self, dis: &Dispatch) -> crate::error::Result<i64> {
+        Cases::new(dis.db(self.guild))
+            .record(
+                self.user.id,
+                self.moderator,
+                self.action,
+                self.reason.as_deref(),
+                self.evidence.as_ref(),
+            )
+            .await
+    }
+
+    /// Performs the action like [`ModAction::act`], and tells the user about it using the guild's
+    /// [`DM_TEMPLATE`], if set. Users being removed from the guild are notified first, since they
+    /// may not be reachable afterwards.
+    pub async fn act_and_notify(&mut self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<i64> {
+        let before = self.action.removes_user();
+        if before {
+            self.notice = self.notify_user(dis, ctx).await?;
+        }
+        let case = self.act(dis, ctx).await?;
+        if !before {
+            self.notice = self.notify_user(dis, ctx).await?;
+        }
+        Ok(case)
+    }
+
+    /// Fills in a notice template with details of the action.
+    pub fn render_notice(&self, template: &str, guild_name: &str, appeal_url: Option<&str>) -> String {
+        let duration = match (self.duration.as_ref(), self.action) {
+            (_, a) if !a.has_duration() => Cow::from("N/A"),
+            (Some(d), _) => d.to_string().into(),
+            (None, ActionKind::Timeout) => humantime::format_duration(MAX_TIMEOUT).to_string().into(),
+            (None, _) => Cow::from("Indefinite"),
+        };
+        template
+            .replace("{action}", self.action.name())
+            .replace("{reason}", self.reason())
+            .replace("{duration}", &duration)
+            .replace("{guild}", guild_name)
+            .replace("{appeal_url}", appeal_url.unwrap_or("N/A"))
+            .chars()
+            .take(MAX_NOTICE_CHARS)
+            .collect()
+    }
+
+    /// Tells the user about the action using the guild's [`DM_TEMPLATE`]. If their DMs are closed
+    /// and they're still a member, the notice is posted in [`DM_FALLBACK_CHANNEL`] instead.
+    /// Returns `None` if the guild hasn't set a template or the action is a reversal.
+    async fn notify_user(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<Option<Notice>> {
+        if self.action.is_reversal() {
+            return Ok(None);
+        }
+
+        let db = dis.db(self.guild);
+        let template = match dis.config_value_t::<String>(DM_TEMPLATE)?.get(&db).await? {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+        let appeal_url = dis.config_value_t::<String>(APPEAL_URL)?.get(&db).await?;
+        let guild_name = ctx
+            .cache
+            .guild_field(self.guild, |g| g.name.clone())
+            .await
+            .unwrap_or_else(|| "the server".to_string());
+        let notice = self.render_notice(&template, &guild_name, appeal_url.as_deref().map(String::as_str));
+
+        match self.user.direct_message(ctx, |m| m.content(&notice)).await {
+            Ok(_) => return Ok(Some(Notice::Direct)),
+            Err(e) => debug!("couldn't DM {} about a {}: {}", self.user.id, self.action.name(), e),
+        }
+
+        if self.action.removes_user() {
+            return Ok(Some(Notice::Undelivered));
+        }
+        let fallback = match dis
+            .config_value_t::<VerifiedChannel>(DM_FALLBACK_CHANNEL)?
+            .get(&db)
+            .await?
+        {
+            Some(c) => c.into_inner(),
+            None => return Ok(Some(Notice::Undelivered)),
+        };
+        let posted = fallback
+            .say(ctx, format!("{}: {}", self.user.id.mention(), notice))
+            .await
+            .map_err(crate::error::Error::from);
+        posted.log_error();
+        Ok(Some(if posted.is_ok() {
+            Notice::Fallback(fallback)
+        } else {
+            Notice::Undelivered
+        }))
     }
 
     /// Specifies a duration for the action.
@@ -371,26 +1031,51 @@ impl ModAction {
         self
     }
 
+    /// Specifies how many days of messages a ban or soft ban should delete. The guild's default is
+    /// used if unspecified.
+    pub fn with_deletion_days(mut self, days: Option<AtMostU64<7>>) -> Self {
+        self.deletion_days = days;
+        self
+    }
+
+    /// Attaches evidence to the case opened for the action.
+    pub fn with_evidence(mut self, evidence: Evidence) -> Self {
+        self.evidence = Some(evidence);
+        self
+    }
+
+    /// Marks the action as having been taken directly in Discord, so the mod log doesn't point at
+    /// a channel it wasn't taken in.
+    pub fn taken_externally(mut self) -> Self {
+        self.external = true;
+        self
+    }
+
     /// Creates an embed representing the action for the mod log.
-    pub fn create_embed(&self, embed: &mut CreateEmbed) {
-        let user = format!("{} ({})", self.user.display_name(), self.user.user.id);
+    pub fn create_embed(&self, case: i64, embed: &mut CreateEmbed) {
+        let user = format!("{} ({})", self.display_name, self.user.id);
         let moderator = self.moderator.mention();
         let reason = self.reason.clone().unwrap_or_else(|| "No reason specified.".into());
 
         embed
             .color(self.action.color())
-            .title(self.action.title_name())
+            .title(format!("Case #{}: {}", case, self.action.title_name()))
             .field("User", user, false)
             .field("Reason", reason, false)
-            .field("Moderator", moderator, false)
-            .field("Channel", self.channel.mention(), false);
+            .field("Moderator", moderator, false);
+
+        if self.external {
+            embed.field("Source", "Taken directly in Discord", false);
+        } else {
+            embed.field("Channel", self.channel.mention(), false);
+        }
 
         if self.action.has_duration() {
-            let dur = self
-                .duration
-                .as_ref()
-                .map(|d| d.to_string().into())
-                .unwrap_or_else(|| Cow::from("Indefinite"));
+            let dur = match (self.duration.as_ref(), self.action) {
+                (Some(d), _) => d.to_string().into(),
+                (None, ActionKind::Timeout) => humantime::format_duration(MAX_TIMEOUT).to_string().into(),
+                (None, _) => Cow::from("Indefinite"),
+            };
 
             embed.field("Duration", dur, false);
         }
@@ -398,12 +1083,21 @@ impl ModAction {
         if let Some(m) = self.original_message {
             let url = format!(
                 "https://discord.com/channels/{gid}/{chan}/{mess}",
-                gid = self.user.guild_id,
+                gid = self.guild,
                 chan = self.channel,
                 mess = m
             );
             embed.field("In response to", url, false);
         }
+
+        if let Some(e) = &self.evidence {
+            let summary = format!("{}. Use `mod case {}` to review it.", e.summary(), case);
+            embed.field("Evidence", summary, false);
+        }
+
+        if let Some(n) = &self.notice {
+            embed.field("User Notified", n.describe(), false);
+        }
     }
 
     /// Mutes a user by adding the mute role to them.
@@ -415,22 +1109,94 @@ impl ModAction {
             .get(&cfg_db)
             .await?
             .ok_or(NoMuteRoleSet)?;
-        let mut mem = action.user().clone();
-        mem.add_role(ctx, mute_role.into_inner()).await?;
+        ctx.http
+            .add_member_role(action.guild.0, action.user.id.0, mute_role.into_inner().0)
+            .await?;
+        Ok(())
+    }
+
+    /// Unmutes a user by removing the mute role from them, queueing the removal for retry if
+    /// Discord is degraded.
+    pub async fn unmute_user(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let cfg_db = DbContext::new(dis, self.guild);
+        let mute_role = dis
+            .config_value_t::<VerifiedRole>(MUTE_ROLE)?
+            .get(&cfg_db)
+            .await?
+            .ok_or(NoMuteRoleSet)?;
+        let remove = RetryOp::RemoveRole {
+            guild: self.guild,
+            user: self.user.id,
+            role: mute_role.into_inner(),
+        };
+        dis.rest().perform_or_queue(ctx, remove).await?;
+        Ok(())
+    }
+
+    /// Retrieves how many days of messages a ban or soft ban should delete, falling back to the
+    /// guild's [`BAN_DELETE_DAYS`] or [`SOFTBAN_DELETE_DAYS`].
+    async fn resolve_deletion_days(&self, dis: &Dispatch) -> crate::error::Result<u8> {
+        if let Some(d) = self.deletion_days {
+            let d: u64 = d.into();
+            return Ok(d as u8);
+        }
+        let key = match self.action {
+            ActionKind::SoftBan => SOFTBAN_DELETE_DAYS,
+            _ => BAN_DELETE_DAYS,
+        };
+        let days: u64 = (*dis
+            .config_value_t::<AtMostU64<7>>(key)?
+            .get_or_default(&dis.db(self.guild))
+            .await?)
+            .into();
+        Ok(days as u8)
+    }
+
+    /// Retrieves how long a timeout should last, defaulting to the longest timeout Discord allows.
+    fn timeout_duration(&self) -> crate::error::Result<std::time::Duration> {
+        let d = self.duration.map_or(MAX_TIMEOUT, Into::into);
+        if d > MAX_TIMEOUT {
+            return Err(TimeoutTooLong.into());
+        }
+        Ok(d)
+    }
+
+    /// Cancels any pending timed reversal of the given kind for the target user, since it's
+    /// been reversed manually.
+    async fn cancel_pending(&self, dis: &Dispatch, kind: timed::ActionKind) -> crate::error::Result<()> {
+        let cancelled = TimedEvents::new(dis.db(self.guild))
+            .cancel_actions_for(self.user.id, kind)
+            .await?;
+        debug!("cancelled {} pending timed actions", cancelled);
         Ok(())
     }
 
-    /// Creates an embed and places it in the moderation log.
-    pub async fn report_action(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+    /// Reports in the moderation log what [`ModAction::act`] would have done, without doing it or
+    /// opening a case. Used while the guild has [`SIMULATE_ACTIONS`] set.
+    pub async fn simulate(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let duration = match (self.duration.as_ref(), self.action.has_duration()) {
+            (Some(d), true) => format!(" for {}", d),
+            _ => String::new(),
+        };
+        let what = format!(
+            "{} {} ({}){}: {}",
+            self.action.past_tense(),
+            self.display_name,
+            self.user.id,
+            duration,
+            self.reason()
+        );
+        report_simulated(dis, ctx, self.guild, &what).await
+    }
+
+    /// Creates an embed for the action's case and places it in the moderation log.
+    pub async fn report_action(&self, dis: &Dispatch, ctx: &Context, case: i64) -> crate::error::Result<()> {
         let action = self;
-        let mod_channel_v = dis.config_value_t::<VerifiedChannel>(MOD_CHANNEL)?;
-        let cfg_db = DbContext::new(dis, action.guild());
-        let mod_channel = mod_channel_v.get(&cfg_db).await?.ok_or(NoModChannelSet)?;
-        mod_channel
-            .into_inner()
+        mod_log_channel(dis, action.guild())
+            .await?
             .send_message(ctx, |e| {
                 e.embed(|emb| {
-                    action.create_embed(emb);
+                    action.create_embed(case, emb);
                     emb
                 })
             })
@@ -439,6 +1205,113 @@ impl ModAction {
     }
 }
 
+/// Sets or clears a member's native Discord timeout. Serenity doesn't know about timeouts yet, so
+/// the member is edited directly.
+pub async fn set_timeout(
+    ctx: &Context,
+    guild: GuildId,
+    user: UserId,
+    until: Option<DateTime<Utc>>,
+) -> serenity::Result<()> {
+    let mut map = serde_json::Map::new();
+    map.insert(
+        "communication_disabled_until".to_string(),
+        until.map_or(serde_json::Value::Null, |t| serde_json::Value::String(t.to_rfc3339())),
+    );
+    ctx.http.edit_member(guild.0, user.0, &map).await?;
+    Ok(())
+}
+
+/// Retrieves the moderation log channel for a guild, returning an error if it hasn't been set.
+pub async fn mod_log_channel(dis: &Dispatch, guild: GuildId) -> crate::error::Result<ChannelId> {
+    let mod_channel_v = dis.config_value_t::<VerifiedChannel>(MOD_CHANNEL)?;
+    let cfg_db = DbContext::new(dis, guild);
+    let mod_channel = mod_channel_v.get(&cfg_db).await?.ok_or(NoModChannelSet)?;
+    Ok(mod_channel.into_inner())
+}
+
+/// Returns true if the guild's anti-spam and filters should only report what they would have done;
+/// see [`SIMULATE_ACTIONS`].
+pub async fn simulating(dis: &Dispatch, guild: GuildId) -> crate::error::Result<bool> {
+    let simulate = dis
+        .config_value_t::<bool>(SIMULATE_ACTIONS)?
+        .get_or_default(&dis.db(guild))
+        .await?;
+    Ok(*simulate)
+}
+
+/// Posts a note in the moderation log that Glimbot would have done something, had the guild not set
+/// [`SIMULATE_ACTIONS`]. `what` completes the sentence "Would have ...".
+pub async fn report_simulated(dis: &Dispatch, ctx: &Context, guild: GuildId, what: &str) -> crate::error::Result<()> {
+    let note = MessageBuilder::new()
+        .push_bold("Simulated:")
+        .push(" would have ")
+        .push_safe(what)
+        .build();
+    mod_log_channel(dis, guild).await?.say(ctx, note).await?;
+    Ok(())
+}
+
+/// The maximum number of message snapshots shown when reviewing a case.
+pub const MAX_CASE_SNAPSHOTS_SHOWN: usize = 10;
+/// The maximum number of characters shown per message snapshot, keeping the embed under Discord's size limit.
+pub const MAX_CASE_SNAPSHOT_CHARS: usize = 400;
+
+/// Creates an embed describing a stored case, including its evidence, for review. Times are written
+/// out with the guild's clock.
+pub fn create_case_embed(case: &Case, clock: &GuildClock, embed: &mut CreateEmbed) {
+    embed
+        .color(case.action.color())
+        .title(format!("Case #{}: {}", case.id, case.action.title_name()))
+        .field("User", format!("{} ({})", case.user.mention(), case.user), false)
+        .field(
+            "Reason",
+            case.reason.as_deref().unwrap_or("No reason specified."),
+            false,
+        )
+        .field("Moderator", case.moderator.mention(), false)
+        .timestamp(case.created);
+
+    let evidence = match &case.evidence {
+        None => return,
+        Some(e) => e,
+    };
+
+    embed.field("Evidence", evidence.summary(), false);
+    if let Some(p) = &evidence.provenance {
+        let moderator = p
+            .moderator
+            .map(|m| format!("{} ({})", m.mention(), m))
+            .unwrap_or_else(|| "unknown".to_string());
+        embed.field(
+            "Synced From",
+            format!(
+                "{} ({}), banned by {} at {}: {}",
+                p.guild_name,
+                p.guild,
+                moderator,
+                clock.datetime(p.banned_at),
+                p.reason.as_deref().unwrap_or("No reason specified.")
+            ),
+            false,
+        );
+    }
+    for m in evidence.messages.iter().rev().take(MAX_CASE_SNAPSHOTS_SHOWN).rev() {
+        let content = m
+            .content
+            .as_deref()
+            .filter(|c| !c.is_empty())
+            .map(|c| c.chars().take(MAX_CASE_SNAPSHOT_CHARS).collect::<String>())
+            .unwrap_or_else(|| "*Content unavailable.*".to_string());
+        embed.field(
+            clock.datetime(m.timestamp),
+            format!("{}\n{}", content, m.channel.mention()),
+            false,
+        );
+    }
+}
+
+impl_err!(NoSuchCase, "No case with that number exists in this guild.", true);
 impl_err!(
     NoModChannelSet,
     "No mod channel has been set for this guild (`mod_log_channel`).",
@@ -449,3 +1322,11 @@ impl_err!(
     "No mute role has been set for this guild (`mute_role`).",
     true
 );
+impl_err!(TimeoutTooLong, "Discord timeouts can last at most 28 days.", true);
+impl_err!(
+    NoIdsGiven,
+    "No user IDs were given; paste them after the command or attach a text file.",
+    true
+);
+impl_err!(TooManyIds, "At most 1000 users can be mass banned at once.", true);
+impl_err!(IdListTooLarge, "ID list attachments can be at most 256 KiB.", true);
@@ -0,0 +1,417 @@
+//! Contains nickname moderation. Names which are hoisted (start with punctuation to sort above
+//! everyone else in the member list), covered in zalgo (stacked combining marks), or unreadable
+//! (written in "fancy" letters, or with no letters at all) are normalized, replaced, or flagged to
+//! moderators when members join or change their name, and `!dehoist` sweeps the whole server.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::guild::Member;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::misc::Mentionable;
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::dispatch::config::{self, VerifiedRole};
+use crate::dispatch::jobs::{Job, JobPriority};
+use crate::dispatch::Dispatch;
+use crate::error::GuildNotInCache;
+use crate::module::moderation::mod_log_channel;
+use crate::module::privilege::PRIV_ROLE;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// The config key for grabbing a [`NicknamePolicy`].
+pub const NICKNAME_POLICY_KEY: &str = "nickname_policy";
+/// The longest nickname Discord allows.
+pub const MAX_NICKNAME_LEN: usize = 32;
+/// The number of combining marks stacked on one character which counts as zalgo.
+pub const ZALGO_MARKS: usize = 3;
+
+/// What to do about a problematic name.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NicknameAction {
+    /// Rewrites the name in plain letters, without leading punctuation or zalgo.
+    Normalize,
+    /// Replaces the name with "Moderated Nickname" and a number.
+    Replace,
+    /// Leaves the name alone and tells moderators about it.
+    Flag,
+}
+
+impl Default for NicknameAction {
+    fn default() -> Self {
+        NicknameAction::Normalize
+    }
+}
+
+/// Configuration for nickname moderation.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NicknamePolicy {
+    /// Whether names are checked when members join or change them. `!dehoist` works either way.
+    #[serde(default)]
+    pub enabled: bool,
+    /// What to do about problematic names.
+    #[serde(default)]
+    pub action: NicknameAction,
+    /// Whether names starting with punctuation are problematic.
+    #[serde(default = "default_true")]
+    pub hoisted: bool,
+    /// Whether names with stacked combining marks are problematic.
+    #[serde(default = "default_true")]
+    pub zalgo: bool,
+    /// Whether names in fancy letters, or without any letters, are problematic.
+    #[serde(default = "default_true")]
+    pub unreadable: bool,
+}
+
+#[doc(hidden)]
+fn default_true() -> bool {
+    true
+}
+
+impl Default for NicknamePolicy {
+    fn default() -> Self {
+        NicknamePolicy {
+            enabled: false,
+            action: NicknameAction::default(),
+            hoisted: true,
+            zalgo: true,
+            unreadable: true,
+        }
+    }
+}
+
+impl FromStr for NicknamePolicy {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for NicknamePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Why a name is problematic.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NameIssue {
+    /// It starts with punctuation.
+    Hoisted,
+    /// It has stacked combining marks.
+    Zalgo,
+    /// It's in fancy letters, or has no letters.
+    Unreadable,
+}
+
+impl fmt::Display for NameIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            NameIssue::Hoisted => "hoisted",
+            NameIssue::Zalgo => "zalgo",
+            NameIssue::Unreadable => "unreadable",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Checks whether a character is a combining mark, which zalgo text stacks on other characters.
+fn is_combining(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036F | 0x0483..=0x0489 | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF | 0xFE20..=0xFE2F)
+}
+
+/// Checks whether a character is a stylized letter or number, like those in 𝓯𝓪𝓷𝓬𝔂 or ｆｕｌｌｗｉｄｔｈ
+/// text, which screen readers and searches don't handle.
+fn is_fancy(c: char) -> bool {
+    matches!(c as u32,
+        0x1D400..=0x1D7FF | 0x2460..=0x24FF | 0x1F100..=0x1F1FF | 0xFF01..=0xFF5E)
+}
+
+impl NicknamePolicy {
+    /// Finds the first problem this policy has with a name, if any.
+    pub fn check(&self, name: &str) -> Option<NameIssue> {
+        let hoisted = name.chars().next().map_or(false, |c| c.is_ascii_punctuation());
+        if self.hoisted && hoisted {
+            return Some(NameIssue::Hoisted);
+        }
+
+        let mut stacked = 0;
+        let zalgo = name.chars().any(|c| {
+            stacked = if is_combining(c) { stacked + 1 } else { 0 };
+            stacked >= ZALGO_MARKS
+        });
+        if self.zalgo && zalgo {
+            return Some(NameIssue::Zalgo);
+        }
+
+        let unreadable = name.chars().any(is_fancy) || !name.chars().any(char::is_alphanumeric);
+        if self.unreadable && unreadable {
+            return Some(NameIssue::Unreadable);
+        }
+        None
+    }
+}
+
+/// Rewrites a name in plain letters without combining marks or leading punctuation. Returns `None`
+/// if nothing readable is left.
+pub fn normalize(name: &str) -> Option<String> {
+    let stripped: String = name.chars().filter(|c| !is_combining(*c)).collect();
+    let plain = deunicode::deunicode(&stripped);
+    let plain = plain.trim_start_matches(|c: char| !c.is_ascii_alphanumeric());
+    let plain: String = plain
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_NICKNAME_LEN)
+        .collect();
+    let plain = plain.trim_end().to_string();
+    if plain.is_empty() {
+        None
+    } else {
+        Some(plain)
+    }
+}
+
+/// The name given to members whose names are replaced. The number comes from their ID, so a member
+/// keeps the same one.
+fn replacement(user: UserId) -> String {
+    format!("Moderated Nickname {:03}", user.0 % 1000)
+}
+
+/// Works out what a member should be renamed to under a policy, or `None` if they should only be
+/// flagged.
+fn new_name(policy: &NicknamePolicy, user: UserId, name: &str) -> Option<String> {
+    match policy.action {
+        NicknameAction::Flag => None,
+        NicknameAction::Replace => Some(replacement(user)),
+        NicknameAction::Normalize => {
+            // A name which normalizes to something still problematic gets replaced instead, so
+            // renaming never triggers another rename.
+            let normalized = normalize(name).filter(|n| policy.check(n).is_none());
+            Some(normalized.unwrap_or_else(|| replacement(user)))
+        }
+    }
+}
+
+/// Checks whether a member is exempt from nickname moderation: bots, the owner and moderators.
+async fn is_exempt(dis: &Dispatch, ctx: &Context, member: &Member) -> crate::error::Result<bool> {
+    if member.user.bot {
+        return Ok(true);
+    }
+    let owner = ctx
+        .cache
+        .guild_field(member.guild_id, |g| g.owner_id)
+        .await
+        .ok_or(GuildNotInCache)?;
+    if owner == member.user.id {
+        return Ok(true);
+    }
+    let mod_role = dis
+        .config_value_t::<VerifiedRole>(PRIV_ROLE)?
+        .get(&dis.db(member.guild_id))
+        .await?;
+    Ok(mod_role.map_or(false, |r| member.roles.contains(&r.into_inner())))
+}
+
+/// A member whose name needs moderating.
+struct ModerateNickname {
+    /// The member's guild.
+    guild: GuildId,
+    /// The member.
+    user: UserId,
+    /// The member's current name.
+    name: String,
+    /// What's wrong with it.
+    issue: NameIssue,
+    /// The name to give them, or `None` to flag them to moderators instead.
+    new_name: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl Job for ModerateNickname {
+    fn name(&self) -> &'static str {
+        "moderate-nickname"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::Low
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        if let Some(new) = &self.new_name {
+            self.guild.edit_member(ctx, self.user, |m| m.nickname(new)).await?;
+            debug!("renamed member with {} name", self.issue);
+            return Ok(());
+        }
+
+        let note = MessageBuilder::new()
+            .mention(&self.user)
+            .push(format!(" has a {} name: ", self.issue))
+            .push_mono_safe(&self.name)
+            .build();
+        let chan = mod_log_channel(dis, self.guild).await?;
+        chan.send_message(ctx, |m| m.content(note).allowed_mentions(|am| am.empty_parse()))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Queues moderation of a member's name if the policy has a problem with it. Returns whether it was
+/// queued.
+fn moderate(dis: &Dispatch, policy: &NicknamePolicy, member: &Member) -> bool {
+    let name = member.display_name();
+    let issue = match policy.check(&name) {
+        Some(i) => i,
+        None => return false,
+    };
+    dis.jobs().enqueue(
+        member.guild_id,
+        ModerateNickname {
+            guild: member.guild_id,
+            user: member.user.id,
+            name: name.to_string(),
+            issue,
+            new_name: new_name(policy, member.user.id, &name),
+        },
+    )
+}
+
+/// Fixes problematic names across the whole server, as set by the nickname policy.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "dehoist", no_version)]
+pub struct DehoistOpt {
+    /// Only lists the members whose names would be changed or flagged.
+    #[structopt(long)]
+    dry_run: bool,
+}
+
+/// Module which moderates problematic nicknames.
+pub struct NicknameModule;
+
+impl NicknameModule {
+    /// Checks a member's name, if the guild has turned nickname moderation on.
+    async fn check_member(&self, dis: &Dispatch, ctx: &Context, member: &Member) -> crate::error::Result<()> {
+        let policy = dis
+            .config_value_t::<NicknamePolicy>(NICKNAME_POLICY_KEY)?
+            .get_or_default(&dis.db(member.guild_id))
+            .await?;
+        if !policy.enabled || is_exempt(dis, ctx, member).await? {
+            return Ok(());
+        }
+        moderate(dis, &policy, member);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for NicknameModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "dehoist",
+                "normalizes hoisted, zalgo or unreadable nicknames, or flags them to moderators.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+            .with_member_join_hook(true)
+            .with_member_update_hook(true)
+            .with_config_value(config::Value::<NicknamePolicy>::with_default(
+                NICKNAME_POLICY_KEY,
+                "A JSON object configuring nickname moderation, e.g. {\"enabled\": true, \"action\": \"Normalize\"}. The action can be Normalize, Replace or Flag, and hoisted, zalgo and unreadable turn each check on or off.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = DehoistOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let policy = dis
+            .config_value_t::<NicknamePolicy>(NICKNAME_POLICY_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        let members: Vec<Member> = ctx
+            .cache
+            .guild_field(gid, |g| g.members.values().cloned().collect())
+            .await
+            .ok_or(GuildNotInCache)?;
+
+        let mut found = Vec::new();
+        let mut queued = 0;
+        for member in &members {
+            let name = member.display_name();
+            if policy.check(&name).is_none() || is_exempt(dis, ctx, member).await? {
+                continue;
+            }
+            found.push(member.user.id);
+            if !opts.dry_run && moderate(dis, &policy, member) {
+                queued += 1;
+            }
+        }
+
+        let reply = if opts.dry_run {
+            let listed: Vec<String> = found.iter().take(50).map(|u| u.mention().to_string()).collect();
+            let more = found.len().saturating_sub(listed.len());
+            let mut reply = format!("{} members have problematic names.", found.len());
+            if !listed.is_empty() {
+                reply.push('\n');
+                reply.push_str(&listed.join(" "));
+            }
+            if more > 0 {
+                reply.push_str(&format!(" and {} more", more));
+            }
+            reply
+        } else if queued < found.len() {
+            format!(
+                "Fixing {} of {} problematic names. I'm busy, so run this again later for the rest.",
+                queued,
+                found.len()
+            )
+        } else {
+            format!("Fixing {} problematic names.", queued)
+        };
+        orig.channel_id
+            .send_message(ctx, |m| {
+                m.content(reply)
+                    .reference_message(orig)
+                    .allowed_mentions(|am| am.empty_parse())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn on_member_join(&self, dis: &Dispatch, ctx: &Context, member: &Member) -> crate::error::Result<()> {
+        self.check_member(dis, ctx, member).await
+    }
+
+    async fn on_member_update(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        old: Option<&Member>,
+        new: &Member,
+    ) -> crate::error::Result<()> {
+        if old.map_or(false, |o| o.display_name() == new.display_name()) {
+            return Ok(());
+        }
+        self.check_member(dis, ctx, new).await
+    }
+}
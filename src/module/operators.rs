@@ -0,0 +1,126 @@
+//! Contains the `operators` command, which lets the bot's owners manage the operator team: users
+//! who may run instance-wide commands in any guild.
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::UserId;
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::db::operators::OperatorTier;
+use crate::dispatch::config::{parse_user_id, NoSuchUser};
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+impl_err!(
+    ConfiguredOwner,
+    "That user is an owner set in Glimbot's environment, and can only be changed there.",
+    true
+);
+impl_err!(NotAnOperator, "That user isn't on the operator team.", true);
+
+/// Command to manage the bot's owners and operators.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "operators", no_version)]
+enum OperatorsOpt {
+    /// Adds a user to the operator team, or changes their tier.
+    Add {
+        /// The user to add, as an ID or mention.
+        user: String,
+        /// Makes the user an owner, able to run every instance-wide command, instead of an operator.
+        #[structopt(long)]
+        owner: bool,
+    },
+    /// Removes a user from the operator team.
+    Remove {
+        /// The user to remove, as an ID or mention.
+        user: String,
+    },
+    /// Lists the operator team.
+    List,
+}
+
+/// Module to allow the bot's owners to manage its operators.
+pub struct OperatorsModule;
+
+/// Parses a user who may not share a guild with Glimbot.
+fn parse_user(s: &str) -> crate::error::Result<UserId> {
+    parse_user_id(s).ok_or_else(|| NoSuchUser.into())
+}
+
+#[async_trait::async_trait]
+impl Module for OperatorsModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("operators", "manages the users who may run instance-wide commands.")
+                .with_command(true)
+                .with_sensitivity(Sensitivity::Owner)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = OperatorsOpt::from_iter_with_help(command)?;
+        let operators = dis.operators();
+        let message = match opts {
+            OperatorsOpt::Add { user, owner } => {
+                let user = parse_user(&user)?;
+                if operators.is_configured_owner(user) {
+                    return Err(ConfiguredOwner.into());
+                }
+                let tier = if owner {
+                    OperatorTier::Owner
+                } else {
+                    OperatorTier::Operator
+                };
+                operators.set(dis.pool()?, user, tier, orig.author.id).await?;
+                info!("{} made {} an {}", orig.author.id, user, tier);
+                format!("{} is now an {}.", user, tier)
+            }
+            OperatorsOpt::Remove { user } => {
+                let user = parse_user(&user)?;
+                if operators.is_configured_owner(user) {
+                    return Err(ConfiguredOwner.into());
+                }
+                if !operators.remove(dis.pool()?, user).await? {
+                    return Err(NotAnOperator.into());
+                }
+                info!("{} removed {} from the operator team", orig.author.id, user);
+                format!("Removed {} from the operator team.", user)
+            }
+            OperatorsOpt::List => {
+                let mut lines = Vec::new();
+                for (user, tier) in operators.list() {
+                    let name = ctx
+                        .cache
+                        .user(user)
+                        .await
+                        .map_or_else(|| user.to_string(), |u| format!("{} ({})", u.tag(), user));
+                    let configured = if operators.is_configured_owner(user) {
+                        ", configured"
+                    } else {
+                        ""
+                    };
+                    lines.push(format!("{}: {}{}", name, tier, configured));
+                }
+                if let Some((guild, role)) = operators.home_role() {
+                    lines.push(format!("Members of role {} in guild {}: operator", role, guild));
+                }
+                lines.join("\n")
+            }
+        };
+
+        let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
 //! Contains the owner filter, which ensures that commands with Sensitivity::Owner are only
-//! run by the owner of the bot.
+//! run by the bot's owners, and commands with Sensitivity::Operator only by its operator team.
 
 use once_cell::sync::Lazy;
 use serenity::client::Context;
 use serenity::model::channel::Message;
 
+use crate::db::operators::OperatorTier;
 use crate::dispatch::Dispatch;
 use crate::module::{ModInfo, Module, Sensitivity};
 
@@ -15,7 +16,8 @@ static MOD_INFO: Lazy<ModInfo> = Lazy::new(|| {
         .with_filter(true)
 });
 
-/// Ensures that commands with owner sensitivity are run only by the owner of the bot.
+/// Ensures that commands with owner or operator sensitivity are run only by the bot's owners or
+/// operators, respectively.
 pub struct OwnerFilter;
 
 impl_err!(
@@ -23,6 +25,11 @@ impl_err!(
     "You must be the bot owner to do the specified command.",
     true
 );
+impl_err!(
+    MustBeBotOperator,
+    "You must be one of the bot's operators to do the specified command.",
+    true
+);
 
 #[async_trait::async_trait]
 impl Module for OwnerFilter {
@@ -33,22 +40,28 @@ impl Module for OwnerFilter {
     async fn filter(
         &self,
         dis: &Dispatch,
-        _ctx: &Context,
+        ctx: &Context,
         orig: &Message,
         name: String,
     ) -> crate::error::Result<String> {
         let cmd = name.as_str();
         let mod_info = dis.command_module(cmd)?;
-        if mod_info.info().sensitivity == Sensitivity::Owner {
-            if orig.author.id == dis.owner() {
-                trace!("Command invoked by owner.");
+        let required = match mod_info.info().sensitivity {
+            Sensitivity::Owner => OperatorTier::Owner,
+            Sensitivity::Operator => OperatorTier::Operator,
+            _ => {
+                trace!("Command not owner-only.");
+                return Ok(name);
+            }
+        };
+
+        match dis.operators().tier(ctx, orig.author.id).await {
+            Some(t) if t >= required => {
+                trace!("Command invoked by {}.", t);
                 Ok(name)
-            } else {
-                Err(MustBeBotOwner.into())
             }
-        } else {
-            trace!("Command not owner-only.");
-            Ok(name)
+            _ if required == OperatorTier::Owner => Err(MustBeBotOwner.into()),
+            _ => Err(MustBeBotOperator.into()),
         }
     }
 }
@@ -0,0 +1,325 @@
+//! Contains role persistence, which remembers the roles members held when they left and gives them
+//! back if they rejoin within a while. Roles granting moderation permissions are never given back
+//! automatically. Independently of that, members who leave while muted are muted again when they
+//! rejoin, so leaving and rejoining can't be used to shake off a mute.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::guild::{Member, Role};
+use serenity::model::id::{GuildId, RoleId, UserId};
+use serenity::model::permissions::Permissions;
+use serenity::model::user::User;
+
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::config::VerifiedRole;
+use crate::dispatch::Dispatch;
+use crate::error::{GuildNotInCache, LogErrorExt};
+use crate::module::moderation::{mod_log_channel, MUTE_ROLE};
+use crate::module::privilege::PRIV_ROLE;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing a [`RolePersistenceConfig`].
+pub const ROLE_PERSISTENCE_KEY: &str = "role_persistence";
+
+/// Permissions which make a role too sensitive to give back automatically.
+const PRIVILEGED_PERMISSIONS: Permissions = Permissions::from_bits_truncate(
+    Permissions::ADMINISTRATOR.bits()
+        | Permissions::MANAGE_GUILD.bits()
+        | Permissions::MANAGE_ROLES.bits()
+        | Permissions::MANAGE_CHANNELS.bits()
+        | Permissions::MANAGE_MESSAGES.bits()
+        | Permissions::MANAGE_NICKNAMES.bits()
+        | Permissions::MANAGE_WEBHOOKS.bits()
+        | Permissions::MANAGE_EMOJIS.bits()
+        | Permissions::KICK_MEMBERS.bits()
+        | Permissions::BAN_MEMBERS.bits()
+        | Permissions::MENTION_EVERYONE.bits(),
+);
+
+/// Configuration for role persistence.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RolePersistenceConfig {
+    /// Whether members' roles are given back when they rejoin.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Whether members who left while muted are muted again when they rejoin, even if `enabled` is
+    /// off.
+    #[serde(default = "default_restore_mute")]
+    pub restore_mute: bool,
+    /// How long after leaving members' roles are remembered.
+    #[serde(with = "humantime_serde", default = "default_window")]
+    pub window: Duration,
+    /// Roles which are never given back.
+    #[serde(default)]
+    pub exclude_roles: Vec<RoleId>,
+}
+
+#[doc(hidden)]
+fn default_restore_mute() -> bool {
+    true
+}
+
+#[doc(hidden)]
+fn default_window() -> Duration {
+    Duration::from_secs(30 * 24 * 60 * 60)
+}
+
+impl Default for RolePersistenceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            restore_mute: default_restore_mute(),
+            window: default_window(),
+            exclude_roles: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for RolePersistenceConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for RolePersistenceConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Wrapper around the roles of a guild's departed members in the database.
+pub struct DepartedMembers<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> DepartedMembers<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        DepartedMembers {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Records the roles a member held when they left, forgetting members who left longer than
+    /// `window` ago.
+    pub async fn record(&self, user: UserId, roles: &[RoleId], window: Duration) -> crate::error::Result<()> {
+        let window = chrono::Duration::from_std(window).unwrap_or_else(|_| chrono::Duration::max_value());
+        let cutoff = Utc::now().checked_sub_signed(window).unwrap_or(chrono::MIN_DATETIME);
+        sqlx::query!(
+            "DELETE FROM departed_members WHERE guild = $1 AND left_at < $2;",
+            self.ctx.guild_as_i64(),
+            cutoff
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+
+        let roles: Vec<i64> = roles.iter().map(|r| r.0 as i64).collect();
+        sqlx::query!(
+            r#"
+            INSERT INTO departed_members (guild, user_id, roles) VALUES ($1, $2, $3)
+            ON CONFLICT (guild, user_id) DO UPDATE
+                SET roles = EXCLUDED.roles,
+                    left_at = NOW();
+            "#,
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            &roles
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Retrieves and forgets the roles a member held when they left, if they left within `window`.
+    pub async fn take(&self, user: UserId, window: Duration) -> crate::error::Result<Vec<RoleId>> {
+        let row = sqlx::query!(
+            "DELETE FROM departed_members WHERE guild = $1 AND user_id = $2 RETURNING roles, left_at;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+
+        let window = chrono::Duration::from_std(window).unwrap_or_else(|_| chrono::Duration::max_value());
+        Ok(row
+            .filter(|r| Utc::now().signed_duration_since(r.left_at) <= window)
+            .map(|r| r.roles.into_iter().map(|r| RoleId(r as u64)).collect())
+            .unwrap_or_default())
+    }
+
+    /// Forgets that a departed member held a role, such as when a mute runs out while they're away.
+    pub async fn forget_role(&self, user: UserId, role: RoleId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "UPDATE departed_members SET roles = array_remove(roles, $3) WHERE guild = $1 AND user_id = $2;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            role.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Checks whether a role may be given back automatically: it has to be one Glimbot can assign, and
+/// mustn't grant moderation permissions.
+fn restorable(
+    role: &Role,
+    guild: GuildId,
+    bot_position: i64,
+    conf: &RolePersistenceConfig,
+    mod_role: Option<RoleId>,
+) -> bool {
+    role.id.0 != guild.0
+        && !role.managed
+        && role.position < bot_position
+        && !role.permissions.intersects(PRIVILEGED_PERMISSIONS)
+        && !conf.exclude_roles.contains(&role.id)
+        && mod_role != Some(role.id)
+}
+
+/// Module which gives members back their roles when they rejoin.
+pub struct RolePersistenceModule;
+
+#[async_trait::async_trait]
+impl Module for RolePersistenceModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "role-persistence",
+                "gives members back their roles, and their mute, when they rejoin.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_member_join_hook(true)
+            .with_member_remove_hook(true)
+            .with_config_value(config::Value::<RolePersistenceConfig>::with_default(
+                ROLE_PERSISTENCE_KEY,
+                "A JSON object configuring role persistence, e.g. {\"enabled\": true, \"restore_mute\": true, \"window\": \"30d\", \"exclude_roles\": []}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_member_remove(
+        &self,
+        dis: &Dispatch,
+        _ctx: &Context,
+        guild: GuildId,
+        user: &User,
+        member: Option<&Member>,
+    ) -> crate::error::Result<()> {
+        if user.bot {
+            return Ok(());
+        }
+        let db = dis.db(guild);
+        let conf = dis
+            .config_value_t::<RolePersistenceConfig>(ROLE_PERSISTENCE_KEY)?
+            .get_or_default(&db)
+            .await?;
+        if !conf.enabled && !conf.restore_mute {
+            return Ok(());
+        }
+        let member = match member {
+            Some(m) => m,
+            None => {
+                debug!("departed member wasn't cached; can't record their roles");
+                return Ok(());
+            }
+        };
+
+        let roles = if conf.enabled {
+            member.roles.clone()
+        } else {
+            let mute_role = dis.config_value_t::<VerifiedRole>(MUTE_ROLE)?.get(&db).await?;
+            mute_role
+                .map(|r| r.into_inner())
+                .filter(|r| member.roles.contains(r))
+                .into_iter()
+                .collect()
+        };
+        if roles.is_empty() {
+            return Ok(());
+        }
+        DepartedMembers::new(&db).record(user.id, &roles, conf.window).await
+    }
+
+    async fn on_member_join(&self, dis: &Dispatch, ctx: &Context, member: &Member) -> crate::error::Result<()> {
+        if member.user.bot {
+            return Ok(());
+        }
+        let gid = member.guild_id;
+        let db = dis.db(gid);
+        let conf = dis
+            .config_value_t::<RolePersistenceConfig>(ROLE_PERSISTENCE_KEY)?
+            .get_or_default(&db)
+            .await?;
+        let held = DepartedMembers::new(&db).take(member.user.id, conf.window).await?;
+        if held.is_empty() || (!conf.enabled && !conf.restore_mute) {
+            return Ok(());
+        }
+
+        let mute_role = dis
+            .config_value_t::<VerifiedRole>(MUTE_ROLE)?
+            .get(&db)
+            .await?
+            .map(|r| r.into_inner());
+        let mod_role = dis
+            .config_value_t::<VerifiedRole>(PRIV_ROLE)?
+            .get(&db)
+            .await?
+            .map(|r| r.into_inner());
+        let guild = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
+        let bot_position = match guild.members.get(&dis.bot().await) {
+            Some(me) => me.highest_role_info(ctx).await.map_or(0, |(_, pos)| pos),
+            None => 0,
+        };
+
+        let was_muted = mute_role.map_or(false, |r| held.contains(&r));
+        let roles: Vec<RoleId> = held
+            .into_iter()
+            .filter(|r| {
+                let is_mute = Some(*r) == mute_role;
+                (is_mute && conf.restore_mute) || (!is_mute && conf.enabled)
+            })
+            .filter(|r| {
+                guild
+                    .roles
+                    .get(r)
+                    .map_or(false, |role| restorable(role, gid, bot_position, &conf, mod_role))
+            })
+            .collect();
+        if roles.is_empty() {
+            return Ok(());
+        }
+
+        debug!("giving back {} roles", roles.len());
+        let mut member = member.clone();
+        member.add_roles(ctx, &roles).await?;
+
+        if was_muted && conf.restore_mute {
+            if let Ok(chan) = mod_log_channel(dis, gid).await {
+                let note = format!(
+                    "Muted {} ({}) again: they left while muted and have rejoined.",
+                    member.user.tag(),
+                    member.user.id
+                );
+                chan.say(ctx, note).await.map_err(crate::error::Error::from).log_error();
+            }
+        }
+        Ok(())
+    }
+}
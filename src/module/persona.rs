@@ -0,0 +1,250 @@
+//! Contains the `success_reaction` and `error_style` config values, which decide how Glimbot
+//! acknowledges commands in a server, along with helpers for acknowledging them that way.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use serde::de::Error as _;
+use serde::{Deserializer, Serializer};
+use serenity::client::Context;
+use serenity::model::channel::{Message, ReactionType};
+use serenity::model::id::{EmojiId, GuildId};
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::{ModInfo, Module, Sensitivity, CHECKMARK_IN_GREEN_BOX};
+
+/// The config key for the server's [`SuccessReaction`].
+pub const SUCCESS_REACTION_KEY: &str = "success_reaction";
+/// The config key for the server's [`ErrorStyle`].
+pub const ERROR_STYLE_KEY: &str = "error_style";
+/// The reaction added to a failed command when the error style is [`ErrorStyle::React`].
+pub const CROSS_MARK: char = '❌';
+/// The most code points a unicode reaction may have; enough for flags and joined sequences.
+pub const MAX_UNICODE_REACTION_LEN: usize = 16;
+
+impl_err!(
+    InvalidReaction,
+    "Reactions must be a single emoji, or a custom emoji like <:name:id>.",
+    true
+);
+impl_err!(InvalidErrorStyle, "Error styles are reply, react and dm.", true);
+
+/// The reaction added to a command's message when it succeeds.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SuccessReaction(pub ReactionType);
+
+impl Default for SuccessReaction {
+    fn default() -> Self {
+        SuccessReaction(ReactionType::Unicode(CHECKMARK_IN_GREEN_BOX.to_string()))
+    }
+}
+
+/// Parses a custom emoji, like `<:name:id>` or `<a:name:id>`.
+fn parse_custom_emoji(s: &str) -> Option<ReactionType> {
+    let inner = s.strip_prefix('<')?.strip_suffix('>')?;
+    let mut parts = inner.splitn(3, ':');
+    let animated = match parts.next()? {
+        "" => false,
+        "a" => true,
+        _ => return None,
+    };
+    let name = parts.next().filter(|n| !n.is_empty())?;
+    let id: u64 = parts.next()?.parse().ok()?;
+    Some(ReactionType::Custom {
+        animated,
+        id: EmojiId(id),
+        name: Some(name.to_string()),
+    })
+}
+
+impl FromStr for SuccessReaction {
+    type Err = InvalidReaction;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with('<') {
+            return parse_custom_emoji(s).map(SuccessReaction).ok_or(InvalidReaction);
+        }
+
+        let len = s.chars().count();
+        if len == 0
+            || len > MAX_UNICODE_REACTION_LEN
+            || s.chars().any(|c| c.is_ascii_alphanumeric() || c.is_whitespace())
+        {
+            return Err(InvalidReaction);
+        }
+
+        Ok(SuccessReaction(ReactionType::Unicode(s.to_string())))
+    }
+}
+
+impl fmt::Display for SuccessReaction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Stored the way it's written, rather than as Discord's emoji object, so the config history reads well.
+impl serde::Serialize for SuccessReaction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for SuccessReaction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+/// How Glimbot tells a user their command failed.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorStyle {
+    /// Reply to the command with the error.
+    Reply,
+    /// React to the command with a cross mark, without saying what went wrong.
+    React,
+    /// Send the error to the user in a DM, replying instead if their DMs are closed.
+    Dm,
+}
+
+impl ErrorStyle {
+    /// Every error style.
+    pub const ALL: [ErrorStyle; 3] = [ErrorStyle::Reply, ErrorStyle::React, ErrorStyle::Dm];
+
+    /// The style's name.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorStyle::Reply => "reply",
+            ErrorStyle::React => "react",
+            ErrorStyle::Dm => "dm",
+        }
+    }
+}
+
+impl Default for ErrorStyle {
+    fn default() -> Self {
+        ErrorStyle::Reply
+    }
+}
+
+impl FromStr for ErrorStyle {
+    type Err = InvalidErrorStyle;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        ErrorStyle::ALL
+            .iter()
+            .copied()
+            .find(|e| e.name().eq_ignore_ascii_case(s))
+            .ok_or(InvalidErrorStyle)
+    }
+}
+
+impl fmt::Display for ErrorStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Reacts to a command's message with the server's success reaction. If a custom emoji can't be
+/// used, say because it was deleted, the default checkmark is used instead.
+pub async fn react_success(dis: &Dispatch, ctx: &Context, msg: &Message) -> crate::error::Result<()> {
+    let reaction = match msg.guild_id {
+        Some(gid) => dis
+            .config_value_t::<SuccessReaction>(SUCCESS_REACTION_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?
+            .0
+            .clone(),
+        None => SuccessReaction::default().0,
+    };
+
+    if let Err(e) = msg.react(ctx, reaction.clone()).await {
+        if !matches!(reaction, ReactionType::Custom { .. }) {
+            return Err(e.into());
+        }
+        warn!("couldn't react with {}, falling back to the default: {}", reaction, e);
+        msg.react(ctx, CHECKMARK_IN_GREEN_BOX).await?;
+    }
+
+    Ok(())
+}
+
+/// Retrieves a guild's error style. Since this is used while reporting errors, which may well have
+/// come from the database, failures are logged and the default style is used.
+pub async fn error_style(dis: &Dispatch, gid: GuildId) -> ErrorStyle {
+    let style = async {
+        let style = dis
+            .config_value_t::<ErrorStyle>(ERROR_STYLE_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        crate::error::Result::Ok(*style)
+    }
+    .await;
+
+    style.log_error();
+    style.unwrap_or_default()
+}
+
+/// Tells the author of a command that it failed, in the style their server has chosen.
+pub async fn report_error(dis: &Dispatch, ctx: &Context, msg: &Message, text: String) -> crate::error::Result<()> {
+    let style = match msg.guild_id {
+        Some(gid) => error_style(dis, gid).await,
+        None => ErrorStyle::Reply,
+    };
+
+    match style {
+        ErrorStyle::Reply => {
+            msg.reply(ctx, text).await?;
+        }
+        ErrorStyle::React => {
+            msg.react(ctx, CROSS_MARK).await?;
+        }
+        ErrorStyle::Dm => {
+            let dm = msg
+                .author
+                .dm(ctx, |m| {
+                    m.content(format!("Your command in <#{}> failed:\n{}", msg.channel_id, text))
+                })
+                .await;
+            if let Err(e) = dm {
+                debug!("couldn't DM error to {}, replying instead: {}", msg.author.id, e);
+                msg.reply(ctx, text).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Module which holds how Glimbot acknowledges commands in a server.
+pub struct PersonaModule;
+
+#[async_trait::async_trait]
+impl Module for PersonaModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("persona", "")
+                .with_sensitivity(Sensitivity::Low)
+                .with_config_value(config::Value::<SuccessReaction>::with_default(
+                    SUCCESS_REACTION_KEY,
+                    "The reaction added to a command when it succeeds: an emoji, or a custom emoji like <:name:id>.",
+                    SuccessReaction::default,
+                ))
+                .with_config_value(config::Value::<ErrorStyle>::with_default(
+                    ERROR_STYLE_KEY,
+                    "How failed commands are reported: reply, react or dm.",
+                    ErrorStyle::default,
+                ))
+        });
+        &INFO
+    }
+}
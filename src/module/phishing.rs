@@ -0,0 +1,150 @@
+//! Contains the phishing filter, which removes links to known phishing and scam domains. The list of
+//! domains is kept up to date by the background service (see [`crate::db::phishing`]), so guilds
+//! don't have to maintain it themselves. It's on by default; guilds can turn it off, or allow
+//! domains the feed gets wrong.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::module::filter::{handle_filtered, FilterAction, FilterModule};
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing a [`PhishingFilterConfig`].
+pub const PHISHING_FILTER_KEY: &str = "phishing_filter";
+
+/// Matches the host of each link in a message.
+static LINK_HOST_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)\bhttps?://(?:[^\s/@]+@)?([a-z0-9.-]+)").expect("Invalid link host RE"));
+
+/// Configuration for the phishing filter.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PhishingFilterConfig {
+    /// Whether the phishing filter is on.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Domains which are never treated as phishing, along with their subdomains.
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// What happens to the author of a removed link.
+    #[serde(default = "default_action")]
+    pub action: FilterAction,
+}
+
+#[doc(hidden)]
+fn default_enabled() -> bool {
+    true
+}
+
+#[doc(hidden)]
+fn default_action() -> FilterAction {
+    FilterAction::Delete
+}
+
+impl Default for PhishingFilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            allowed_domains: Vec::new(),
+            action: default_action(),
+        }
+    }
+}
+
+impl FromStr for PhishingFilterConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for PhishingFilterConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+impl PhishingFilterConfig {
+    /// Checks whether a host is one of the allowed domains, or a subdomain of one.
+    fn allows(&self, host: &str) -> bool {
+        self.allowed_domains.iter().any(|d| {
+            let d = d.trim().trim_end_matches('.').to_lowercase();
+            !d.is_empty() && (host == d || host.ends_with(&format!(".{}", d)))
+        })
+    }
+}
+
+/// Finds the first link in some text pointing to a known phishing domain, returning its host.
+fn first_phishing_host(dis: &Dispatch, config: &PhishingFilterConfig, text: &str) -> Option<String> {
+    LINK_HOST_RE
+        .captures_iter(text)
+        .map(|c| c[1].trim_end_matches('.').to_lowercase())
+        .find(|h| !config.allows(h) && dis.phishing_domains().contains(h))
+}
+
+/// Module which removes links to known phishing domains.
+pub struct PhishingFilterModule;
+
+#[async_trait::async_trait]
+impl Module for PhishingFilterModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("phishing-filter", "removes links to known phishing and scam sites.")
+                .with_sensitivity(Sensitivity::High)
+                .with_message_hook(true)
+                .with_config_value(config::Value::<PhishingFilterConfig>::with_default(
+                    PHISHING_FILTER_KEY,
+                    "A JSON object configuring the phishing filter, e.g. {\"enabled\": true, \"allowed_domains\": [\"example.com\"], \"action\": \"Delete\"}.",
+                    Default::default,
+                ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if dis.phishing_domains().is_empty() || !orig.content.contains("://") {
+            return Ok(());
+        }
+
+        let config = dis
+            .config_value_t::<PhishingFilterConfig>(PHISHING_FILTER_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !config.enabled {
+            return Ok(());
+        }
+
+        let host = match first_phishing_host(dis, &config, &orig.content) {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+        if FilterModule::is_exempt(dis, ctx, orig, gid, self.info().name).await? {
+            trace!("exempt user posted a phishing link ({})", host);
+            return Ok(());
+        }
+
+        debug!("message linked to phishing domain {}", host);
+        handle_filtered(
+            dis,
+            orig,
+            self.info().name,
+            format!("linked to a known phishing site ({})", host),
+            config.action,
+        )
+        .await
+    }
+}
@@ -59,7 +59,8 @@ impl Module for PrivilegeFilter {
             return Ok(name);
         }
 
-        // Either an owner command or a high command. Owner commands are handled by a different module.
+        // Either an owner/operator command or a high command. Owner and operator commands are
+        // handled by a different module.
         let guild_owner = orig.guild_field(ctx, |g| g.owner_id).await.ok_or(GuildNotInCache)?;
 
         if orig.author.id == guild_owner {
@@ -113,7 +114,7 @@ pub async fn authorized_sensitivities(
     ctx: &Context,
     guild: &Guild,
     user: &Member,
-) -> crate::error::Result<SmallVec<[Sensitivity; 4]>> {
+) -> crate::error::Result<SmallVec<[Sensitivity; 5]>> {
     let mut out = SmallVec::new();
 
     out.push(Sensitivity::Low); // Everyone can use this.
@@ -136,6 +137,7 @@ pub async fn authorized_sensitivities(
     }
 
     if user.user.id == ctx.cache.current_user_id().await {
+        out.push(Sensitivity::Operator);
         out.push(Sensitivity::Owner);
     }
 
@@ -0,0 +1,66 @@
+//! Contains the `purge-user` command, an owner-only command to erase everything Glimbot stores
+//! about a user.
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::UserId;
+use serenity::utils::parse_mention;
+use structopt::StructOpt;
+
+use crate::db::purge::{purge_user, InvalidUserId};
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// Owner-only command to delete or anonymize everything stored about a user.
+pub struct PurgeModule;
+
+/// Command to erase everything Glimbot stores about a user, in every server.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "purge-user", no_version)]
+struct PurgeOpt {
+    /// The user's ID or mention. They don't need to share a server with Glimbot.
+    user: String,
+}
+
+#[async_trait::async_trait]
+impl Module for PurgeModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("purge-user", "erases everything stored about a user.")
+                .with_sensitivity(Sensitivity::Owner)
+                .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = PurgeOpt::from_iter_with_help(command)?;
+        let user = parse_mention(&opts.user)
+            .or_else(|| opts.user.trim().parse().ok())
+            .map(UserId)
+            .ok_or(InvalidUserId)?;
+
+        let report = purge_user(dis.pool()?, user).await?;
+        dis.forget_user(user);
+        dis.operators().load(dis.pool()?).await?;
+
+        orig.reply(
+            ctx,
+            format!(
+                "Purged {}: deleted {} rows and anonymized {}.",
+                user, report.deleted, report.anonymized
+            ),
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! Contains the `queries` command, which shows the database queries Glimbot has spent the most time
+//! on, so operators can find hot paths in production. See [`crate::db::instrumented`].
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// The most queries shown at once, so the reply fits in a message.
+pub const MAX_QUERIES_SHOWN: usize = 10;
+/// The longest query shown, in characters; longer ones are cut short.
+const MAX_QUERY_CHARS: usize = 100;
+
+/// Operator command to show per-query timing.
+pub struct QueriesModule;
+
+/// Shows the queries which have taken the most time since startup.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "queries", no_version)]
+struct QueriesOpt {
+    /// How many queries to show, at most 10
+    #[structopt(default_value = "10")]
+    count: usize,
+    /// Forget the timing recorded so far, after showing it
+    #[structopt(long)]
+    reset: bool,
+}
+
+#[async_trait::async_trait]
+impl Module for QueriesModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("queries", "shows which database queries take the most time.")
+                .with_sensitivity(Sensitivity::Operator)
+                .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = QueriesOpt::from_iter_with_help(command)?;
+        let stats = dis.query_stats();
+        let hottest = stats.hottest(opts.count.min(MAX_QUERIES_SHOWN));
+
+        let mut lines = vec![format!(
+            "All queries: {} (slow: over {}ms)",
+            stats.totals(),
+            stats.slow_threshold().as_millis()
+        )];
+        for (sql, timing) in hottest {
+            let mut shown: String = sql.chars().take(MAX_QUERY_CHARS).collect();
+            if shown.len() < sql.len() {
+                shown.push_str("...");
+            }
+            lines.push(format!("\n{}\n  {}", shown, timing));
+        }
+        if opts.reset {
+            stats.reset();
+            lines.push("\nReset query timing.".to_string());
+        }
+
+        let message = MessageBuilder::new()
+            .push_codeblock_safe(lines.join("\n"), None)
+            .build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+}
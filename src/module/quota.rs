@@ -0,0 +1,163 @@
+//! Contains the `quota` command module, which lets the bot owner see and change how many of each
+//! resource guilds may have. See [`crate::db::quota`] for how limits are chosen.
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::GuildId;
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::db::migrations::ModuleMigration;
+use crate::db::quota::{default_quota, set_default_quota, Quota, Resource};
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+inventory::submit! {
+    ModuleMigration::new(
+        "quota",
+        20210507101512,
+        "quotas",
+        include_str!("quota/migrations/20210507101512_quotas.sql"),
+    )
+}
+
+/// Module to allow the bot owner to manage resource quotas.
+pub struct QuotaModule;
+
+/// Command to manage resource quotas.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "quota", no_version)]
+enum QuotaOpt {
+    /// Shows a server's usage and quota for every resource.
+    Show {
+        /// The ID of the server to show; defaults to this one
+        #[structopt(long)]
+        guild: Option<u64>,
+    },
+    /// Sets a quota, either for one server or as the default for every server.
+    Set {
+        /// The resource to limit
+        resource: Resource,
+        /// The most of the resource allowed
+        limit: i64,
+        /// Set the instance default instead of overriding a single server
+        #[structopt(long, conflicts_with = "guild")]
+        default: bool,
+        /// The ID of the server to override; defaults to this one
+        #[structopt(long)]
+        guild: Option<u64>,
+    },
+    /// Removes a quota, falling back to the instance default or the built-in limit.
+    Unset {
+        /// The resource to stop limiting
+        resource: Resource,
+        /// Remove the instance default instead of a single server's override
+        #[structopt(long, conflicts_with = "guild")]
+        default: bool,
+        /// The ID of the server whose override to remove; defaults to this one
+        #[structopt(long)]
+        guild: Option<u64>,
+    },
+    /// Lists the resources, with their built-in limits and instance defaults.
+    List,
+}
+
+#[async_trait::async_trait]
+impl Module for QuotaModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("quota", "sets how many of each resource guilds may have.")
+                .with_command(true)
+                .with_sensitivity(Sensitivity::Owner)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = QuotaOpt::from_iter_with_help(command)?;
+        let here = orig.guild_id.unwrap();
+        let pick = |g: Option<u64>| g.map_or(here, GuildId);
+        let message = match opts {
+            QuotaOpt::Show { guild } => {
+                let db = dis.db(pick(guild));
+                let quota = Quota::new(&db);
+                let mut lines = Vec::new();
+                for r in Resource::ALL.iter().copied() {
+                    let (limit, source) = quota.limit_with_source(r).await?;
+                    if r.per_member() {
+                        lines.push(format!("{}: {} per member ({})", r, limit, source));
+                    } else {
+                        let used = quota.usage(r, None).await?;
+                        lines.push(format!("{}: {}/{} ({})", r, used, limit, source));
+                    }
+                }
+                lines.join("\n")
+            }
+            QuotaOpt::Set {
+                resource,
+                limit,
+                default,
+                guild,
+            } => {
+                if default {
+                    set_default_quota(dis.pool()?, resource, Some(limit)).await?;
+                    format!("Set the instance default quota for {} to {}.", resource, limit)
+                } else {
+                    let g = pick(guild);
+                    Quota::new(dis.db(g)).set_override(resource, Some(limit)).await?;
+                    format!("Set the quota for {} in {} to {}.", resource, g, limit)
+                }
+            }
+            QuotaOpt::Unset {
+                resource,
+                default,
+                guild,
+            } => {
+                if default {
+                    if set_default_quota(dis.pool()?, resource, None).await? {
+                        format!("Removed the instance default quota for {}.", resource)
+                    } else {
+                        format!("{} has no instance default quota.", resource)
+                    }
+                } else {
+                    let g = pick(guild);
+                    if Quota::new(dis.db(g)).set_override(resource, None).await? {
+                        format!("Removed the quota override for {} in {}.", resource, g)
+                    } else {
+                        format!("{} has no quota override for {}.", g, resource)
+                    }
+                }
+            }
+            QuotaOpt::List => {
+                let mut lines = Vec::new();
+                for r in Resource::ALL.iter().copied() {
+                    let mut line = format!("{}: built-in {}", r, r.built_in());
+                    if let Some(d) = default_quota(dis.pool()?, r).await? {
+                        line.push_str(&format!(", default {}", d));
+                    }
+                    if let Some(c) = r.cap() {
+                        line.push_str(&format!(", at most {}", c));
+                    }
+                    if r.per_member() {
+                        line.push_str(" (per member)");
+                    }
+                    lines.push(line);
+                }
+                lines.join("\n")
+            }
+        };
+
+        let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+}
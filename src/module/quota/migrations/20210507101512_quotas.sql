@@ -0,0 +1,24 @@
+-- Limits on how many of something, like tags, a guild may have. The bot owner can change the limit
+-- for every guild, and override it for particular guilds, with `!quota`.
+CREATE TABLE quota_defaults
+(
+    resource TEXT PRIMARY KEY,
+    quota    BIGINT NOT NULL CHECK (quota >= 0)
+);
+
+CREATE TABLE quota_overrides
+(
+    guild    BIGINT NOT NULL,
+    resource TEXT   NOT NULL,
+    quota    BIGINT NOT NULL CHECK (quota >= 0),
+    PRIMARY KEY (guild, resource),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_quota_guild
+    BEFORE INSERT OR UPDATE
+    ON quota_overrides
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
@@ -0,0 +1,720 @@
+//! Contains raid alerts. When an unusual number of members join a guild in a short period, Glimbot
+//! posts an alert in the mod log which staff can act on with a single button (or the matching
+//! reaction): raise the server's verification level, quarantine everyone who joined recently, or
+//! dismiss the alert.
+//!
+//! While a raid is in progress, everyone who joins is tagged as a suspect (and given
+//! [`RAID_SUSPECT_ROLE`], if the guild set one), so the whole raid can be dealt with in one batch.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::builder::CreateComponents;
+use serenity::client::Context;
+use serenity::model::channel::{Reaction, ReactionType};
+use serenity::model::guild::{Member, VerificationLevel};
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::model::interactions::message_component::{ButtonStyle, MessageComponentInteraction};
+use serenity::model::interactions::InteractionResponseType;
+use serenity::model::misc::Mentionable;
+use serenity::model::user::User;
+use serenity::utils::Color;
+
+use crate::db::cache::{Cache, TimedCache};
+use crate::dispatch::config::{self, VerifiedRole};
+use crate::dispatch::Dispatch;
+use crate::error::{GuildNotInCache, LogErrorExt};
+use crate::module::moderation::{mod_log_channel, ActionKind, ModAction, NoMuteRoleSet, MUTE_ROLE};
+use crate::module::privilege::PRIV_ROLE;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing a [`RaidConfig`].
+pub const RAID_CONFIG_KEY: &str = "raid_config";
+/// Config key for the role given to members tagged as raid suspects.
+pub const RAID_SUSPECT_ROLE: &str = "raid_suspect_role";
+/// Reaction used to raise the verification level in response to an alert.
+pub const LOCKDOWN_EMOJI: &str = "🔒";
+/// Reaction used to quarantine recent joiners in response to an alert.
+pub const QUARANTINE_EMOJI: &str = "🚧";
+/// Reaction used to dismiss an alert.
+pub const DISMISS_EMOJI: &str = "❌";
+/// Custom ID of the alert button which raises the verification level.
+pub const LOCKDOWN_BUTTON: &str = "raid:lockdown";
+/// Custom ID of the alert button which quarantines recent joiners.
+pub const QUARANTINE_BUTTON: &str = "raid:quarantine";
+/// Custom ID of the alert button which dismisses the alert.
+pub const DISMISS_BUTTON: &str = "raid:dismiss";
+/// The number of members quarantined between pauses.
+pub const QUARANTINE_BATCH_SIZE: usize = 5;
+/// The pause between quarantine batches, to stay clear of Discord's rate limits.
+pub const QUARANTINE_BATCH_DELAY: Duration = Duration::from_secs(2);
+/// The maximum number of recent joins tracked per guild.
+pub const MAX_TRACKED_JOINS: usize = 1024;
+/// The maximum number of recent joiners listed in an alert.
+pub const MAX_LISTED_JOINERS: usize = 20;
+/// The maximum number of characters in each message of a quarantine summary.
+pub const MAX_SUMMARY_CHUNK: usize = 1900;
+/// How long an alert can be acted on before it's forgotten.
+pub const ALERT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Configuration for raid alerts.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct RaidConfig {
+    /// The number of joins within `window` which triggers an alert. Set to 0 to disable alerts.
+    pub joins: u32,
+    /// The window in which joins are counted.
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+    /// How far back the quarantine action reaches, measured from when it's taken.
+    #[serde(with = "humantime_serde")]
+    pub quarantine_window: Duration,
+    /// Whether to raise the verification level as soon as an alert is raised, rather than waiting
+    /// for staff to.
+    #[serde(default)]
+    pub auto_lockdown: bool,
+}
+
+impl Default for RaidConfig {
+    fn default() -> Self {
+        Self {
+            joins: 10,
+            window: Duration::from_secs(60),
+            quarantine_window: Duration::from_secs(10 * 60),
+            auto_lockdown: false,
+        }
+    }
+}
+
+impl FromStr for RaidConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for RaidConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// A member who joined recently.
+#[derive(Clone, Debug)]
+struct RecentJoin {
+    /// When the member joined.
+    at: Instant,
+    /// The member's user.
+    user: User,
+}
+
+/// An alert which staff may still act on.
+#[derive(Clone, Debug)]
+struct RaidAlert {
+    /// The guild the alert was raised in.
+    guild: GuildId,
+    /// The channel the alert was posted in.
+    channel: ChannelId,
+    /// When the alert was raised.
+    raised: Instant,
+    /// See [`RaidConfig::quarantine_window`].
+    quarantine_window: Duration,
+    /// Whether the verification level has been raised in response to this alert.
+    locked: bool,
+    /// Whether recent joiners have been quarantined in response to this alert.
+    quarantined: bool,
+    /// Members tagged as suspects: those who joined in the window before the alert, and those who
+    /// joined while the raid continued.
+    suspects: Vec<User>,
+    /// When the last suspect joined. The raid is considered over once no one has joined for a
+    /// whole window.
+    last_suspect: Instant,
+    /// See [`RaidConfig::window`].
+    window: Duration,
+}
+
+impl RaidAlert {
+    /// Checks whether the raid this alert is about is still going, i.e. whether new joiners should
+    /// be tagged as suspects.
+    fn is_ongoing(&self, now: Instant) -> bool {
+        !self.quarantined && now.duration_since(self.last_suspect) <= self.window && self.raised.elapsed() <= ALERT_TTL
+    }
+}
+
+/// The action a staff member chose in response to an alert.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum AlertResponse {
+    /// Raise the verification level.
+    Lockdown,
+    /// Quarantine recent joiners.
+    Quarantine,
+    /// Forget the alert.
+    Dismiss,
+}
+
+impl AlertResponse {
+    /// Maps a reaction to a response.
+    fn from_reaction(r: &ReactionType) -> Option<Self> {
+        match r {
+            ReactionType::Unicode(s) if s == LOCKDOWN_EMOJI => Some(AlertResponse::Lockdown),
+            ReactionType::Unicode(s) if s == QUARANTINE_EMOJI => Some(AlertResponse::Quarantine),
+            ReactionType::Unicode(s) if s == DISMISS_EMOJI => Some(AlertResponse::Dismiss),
+            _ => None,
+        }
+    }
+
+    /// Maps a button's custom ID to a response.
+    fn from_custom_id(id: &str) -> Option<Self> {
+        match id {
+            LOCKDOWN_BUTTON => Some(AlertResponse::Lockdown),
+            QUARANTINE_BUTTON => Some(AlertResponse::Quarantine),
+            DISMISS_BUTTON => Some(AlertResponse::Dismiss),
+            _ => None,
+        }
+    }
+}
+
+/// Builds the buttons for an alert, disabling those for responses which have already been taken.
+/// Every button is disabled once the alert is dismissed or forgotten.
+fn alert_buttons<'a>(c: &'a mut CreateComponents, alert: Option<&RaidAlert>) -> &'a mut CreateComponents {
+    let locked = alert.map_or(true, |a| a.locked);
+    let quarantined = alert.map_or(true, |a| a.quarantined);
+    c.create_action_row(|r| {
+        r.create_button(|b| {
+            b.style(ButtonStyle::Primary)
+                .label("Lock down")
+                .emoji(ReactionType::Unicode(LOCKDOWN_EMOJI.to_string()))
+                .custom_id(LOCKDOWN_BUTTON)
+                .disabled(locked)
+        })
+        .create_button(|b| {
+            b.style(ButtonStyle::Danger)
+                .label("Quarantine")
+                .emoji(ReactionType::Unicode(QUARANTINE_EMOJI.to_string()))
+                .custom_id(QUARANTINE_BUTTON)
+                .disabled(quarantined)
+        })
+        .create_button(|b| {
+            b.style(ButtonStyle::Secondary)
+                .label("Dismiss")
+                .emoji(ReactionType::Unicode(DISMISS_EMOJI.to_string()))
+                .custom_id(DISMISS_BUTTON)
+                .disabled(alert.is_none())
+        })
+    })
+}
+
+/// Module which watches for raids and handles staff responses to raid alerts.
+pub struct RaidModule {
+    #[doc(hidden)]
+    config: TimedCache<GuildId, RaidConfig>,
+    #[doc(hidden)]
+    joins: Cache<GuildId, Mutex<VecDeque<RecentJoin>>>,
+    #[doc(hidden)]
+    alerts: Mutex<HashMap<MessageId, RaidAlert>>,
+    #[doc(hidden)]
+    last_alert: Mutex<HashMap<GuildId, Instant>>,
+}
+
+impl Default for RaidModule {
+    fn default() -> Self {
+        Self {
+            config: TimedCache::new(Duration::from_secs(10)),
+            joins: Cache::null(),
+            alerts: Default::default(),
+            last_alert: Default::default(),
+        }
+    }
+}
+
+impl RaidModule {
+    /// Records a join, returning the number of joins within the window.
+    fn record_join(&self, member: &Member, conf: &RaidConfig) -> usize {
+        let joins = self.joins.get_or_insert_default(&member.guild_id);
+        let mut joins = joins.lock();
+        let now = Instant::now();
+        let keep = conf.window.max(conf.quarantine_window);
+
+        joins.push_back(RecentJoin {
+            at: now,
+            user: member.user.clone(),
+        });
+        while joins.front().map_or(false, |j| {
+            now.duration_since(j.at) > keep || joins.len() > MAX_TRACKED_JOINS
+        }) {
+            joins.pop_front();
+        }
+
+        joins.iter().filter(|j| now.duration_since(j.at) <= conf.window).count()
+    }
+
+    /// Retrieves the members who joined the guild within the given window.
+    fn recent_joiners(&self, guild: GuildId, window: Duration) -> Vec<User> {
+        let now = Instant::now();
+        self.joins
+            .get_or_insert_default(&guild)
+            .lock()
+            .iter()
+            .filter(|j| now.duration_since(j.at) <= window)
+            .map(|j| j.user.clone())
+            .collect()
+    }
+
+    /// Tags a new joiner as a suspect if a raid is ongoing in their guild, returning true if they
+    /// were tagged.
+    fn tag_if_ongoing(&self, member: &Member) -> bool {
+        let now = Instant::now();
+        let mut alerts = self.alerts.lock();
+        let alert = alerts
+            .values_mut()
+            .filter(|a| a.guild == member.guild_id)
+            .max_by_key(|a| a.raised);
+        match alert {
+            Some(a) if a.is_ongoing(now) => {
+                a.suspects.push(member.user.clone());
+                a.last_suspect = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Gives the guild's suspect role, if it has one, to each of the given users, or takes it back
+    /// if `tag` is false. Failures are logged, since they shouldn't hold up the rest of the batch.
+    async fn set_suspect_role(
+        dis: &Dispatch,
+        ctx: &Context,
+        guild: GuildId,
+        users: &[User],
+        tag: bool,
+    ) -> crate::error::Result<()> {
+        let role = match dis
+            .config_value_t::<VerifiedRole>(RAID_SUSPECT_ROLE)?
+            .get(&dis.db(guild))
+            .await?
+        {
+            Some(r) => r.into_inner(),
+            None => return Ok(()),
+        };
+
+        for (i, batch) in users.chunks(QUARANTINE_BATCH_SIZE).enumerate() {
+            if i > 0 {
+                tokio::time::sleep(QUARANTINE_BATCH_DELAY).await;
+            }
+            for user in batch {
+                let res = if tag {
+                    ctx.http.add_member_role(guild.0, user.id.0, role.0).await
+                } else {
+                    ctx.http.remove_member_role(guild.0, user.id.0, role.0).await
+                };
+                res.map_err(crate::error::Error::from).log_error();
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if a new alert should be raised in the guild, i.e. no alert has been raised
+    /// within the window. Also forgets alerts which are too old to act on.
+    fn begin_alert(&self, guild: GuildId, window: Duration) -> bool {
+        self.alerts.lock().retain(|_, a| a.raised.elapsed() <= ALERT_TTL);
+
+        let mut last = self.last_alert.lock();
+        let now = Instant::now();
+        if last.get(&guild).map_or(false, |t| now.duration_since(*t) <= window) {
+            return false;
+        }
+        last.insert(guild, now);
+        true
+    }
+
+    /// Posts a raid alert in the mod log.
+    async fn raise_alert(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        guild: GuildId,
+        conf: &RaidConfig,
+        count: usize,
+    ) -> crate::error::Result<()> {
+        let channel = mod_log_channel(dis, guild).await?;
+        let joiners = self.recent_joiners(guild, conf.window);
+        let listed = joiners
+            .iter()
+            .rev()
+            .take(MAX_LISTED_JOINERS)
+            .map(|u| format!("{} ({})", u.mention(), u.id))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut description = format!(
+            "{} members joined in the last {}. Members who join while the raid continues are tagged as suspects. \
+            Use the buttons below (or react with the same emoji) to respond:\n\
+            {} raise the verification level to the highest setting\n\
+            {} quarantine the suspects and members who joined in the last {} (applies the mute role)\n\
+            {} dismiss this alert and untag the suspects",
+            count,
+            humantime::format_duration(conf.window),
+            LOCKDOWN_EMOJI,
+            QUARANTINE_EMOJI,
+            humantime::format_duration(conf.quarantine_window),
+            DISMISS_EMOJI
+        );
+        if conf.auto_lockdown {
+            description.push_str("\nThe verification level is being raised automatically.");
+        }
+
+        let now = Instant::now();
+        let alert = RaidAlert {
+            guild,
+            channel,
+            raised: now,
+            quarantine_window: conf.quarantine_window,
+            locked: conf.auto_lockdown,
+            quarantined: false,
+            suspects: joiners.clone(),
+            last_suspect: now,
+            window: conf.window,
+        };
+
+        let msg = channel
+            .send_message(ctx, |m| {
+                m.embed(|e| {
+                    e.color(Color::RED)
+                        .title("Possible raid")
+                        .description(description)
+                        .field("Recent joiners", listed, false)
+                })
+                .components(|c| alert_buttons(c, Some(&alert)))
+            })
+            .await?;
+        self.alerts.lock().insert(msg.id, alert.clone());
+
+        if conf.auto_lockdown {
+            self.lockdown(ctx, &alert, dis.bot().await).await.log_error();
+        }
+        Self::set_suspect_role(dis, ctx, guild, &joiners, true).await
+    }
+
+    /// Marks a response as taken for an alert, returning the alert if it's still live and the
+    /// response hasn't already been taken.
+    fn claim(&self, message: MessageId, response: AlertResponse) -> Option<RaidAlert> {
+        let mut alerts = self.alerts.lock();
+        if response == AlertResponse::Dismiss {
+            return alerts.remove(&message);
+        }
+
+        let alert = alerts.get_mut(&message)?;
+        let taken = match response {
+            AlertResponse::Lockdown => &mut alert.locked,
+            _ => &mut alert.quarantined,
+        };
+        if *taken {
+            return None;
+        }
+        *taken = true;
+        Some(alert.clone())
+    }
+
+    /// Carries out a response a staff member claimed for an alert.
+    async fn respond(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        alert: &RaidAlert,
+        alert_msg: MessageId,
+        response: AlertResponse,
+        user: UserId,
+    ) -> crate::error::Result<()> {
+        match response {
+            AlertResponse::Lockdown => self.lockdown(ctx, alert, user).await,
+            AlertResponse::Quarantine => self.quarantine(dis, ctx, alert, alert_msg, user).await,
+            AlertResponse::Dismiss => {
+                alert
+                    .channel
+                    .say(
+                        ctx,
+                        format!("{} {} dismissed the raid alert.", DISMISS_EMOJI, user.mention()),
+                    )
+                    .await?;
+                Self::set_suspect_role(dis, ctx, alert.guild, &alert.suspects, false).await
+            }
+        }
+    }
+
+    /// Raises the guild's verification level to the highest setting.
+    async fn lockdown(&self, ctx: &Context, alert: &RaidAlert, moderator: UserId) -> crate::error::Result<()> {
+        let previous = ctx
+            .cache
+            .guild_field(alert.guild, |g| g.verification_level)
+            .await
+            .ok_or(GuildNotInCache)?;
+        let mut guild = alert.guild;
+        guild
+            .edit(ctx, |g| g.verification_level(VerificationLevel::Higher))
+            .await?;
+
+        alert
+            .channel
+            .say(
+                ctx,
+                format!(
+                    "{} {} raised the verification level from {:?} to {:?} in response to a raid alert.",
+                    LOCKDOWN_EMOJI,
+                    moderator.mention(),
+                    previous,
+                    VerificationLevel::Higher
+                ),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Mutes every member who joined within the alert's quarantine window, in small batches.
+    /// Each quarantined member gets their own case, and a summary is posted to the mod log.
+    async fn quarantine(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        alert: &RaidAlert,
+        alert_msg: MessageId,
+        moderator: UserId,
+    ) -> crate::error::Result<()> {
+        dis.config_value_t::<VerifiedRole>(MUTE_ROLE)?
+            .get(&dis.db(alert.guild))
+            .await?
+            .ok_or(NoMuteRoleSet)?;
+
+        let mut joiners = alert.suspects.clone();
+        for u in self.recent_joiners(alert.guild, alert.quarantine_window) {
+            if !joiners.iter().any(|j| j.id == u.id) {
+                joiners.push(u);
+            }
+        }
+        let total = joiners.len();
+        let reason = format!("Raid quarantine in response to alert {}.", alert_msg);
+        let mut cases = Vec::with_capacity(total);
+        let mut failed = Vec::new();
+        let mut skipped = 0;
+
+        for (i, batch) in joiners.chunks(QUARANTINE_BATCH_SIZE).enumerate() {
+            if !dis.rest().breaker().allows_essential() {
+                warn!("Discord API degraded; halting quarantine");
+                skipped = total - i * QUARANTINE_BATCH_SIZE;
+                break;
+            }
+
+            if i > 0 {
+                tokio::time::sleep(QUARANTINE_BATCH_DELAY).await;
+            }
+
+            for user in batch {
+                let action = ModAction::for_user(user.clone(), alert.guild, alert.channel, moderator, ActionKind::Mute)
+                    .with_reason(reason.clone());
+                match action.act(dis, ctx).await {
+                    Ok(case) => cases.push(format!("#{}", case)),
+                    Err(e) => {
+                        debug!("failed to quarantine {}: {}", user.id, e);
+                        failed.push(user.id.mention().to_string());
+                    }
+                }
+            }
+        }
+
+        let mut summary = format!(
+            "{} {} quarantined {} of {} suspects and recent joiners.",
+            QUARANTINE_EMOJI,
+            moderator.mention(),
+            cases.len(),
+            total
+        );
+        if !cases.is_empty() {
+            summary.push_str(&format!("\nCases: {}", cases.join(", ")));
+        }
+        if !failed.is_empty() {
+            summary.push_str(&format!("\nFailed: {}", failed.join(", ")));
+        }
+        if skipped > 0 {
+            summary.push_str(&format!(
+                "\nStopped early because Discord is having trouble; {} members were not processed.",
+                skipped
+            ));
+        }
+
+        let summary = summary.chars().collect::<Vec<_>>();
+        for chunk in summary.chunks(MAX_SUMMARY_CHUNK) {
+            alert.channel.say(ctx, chunk.iter().collect::<String>()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the user is the guild owner or has the privileged role.
+pub async fn is_staff(dis: &Dispatch, ctx: &Context, guild: GuildId, user: UserId) -> crate::error::Result<bool> {
+    let owner = ctx
+        .cache
+        .guild_field(guild, |g| g.owner_id)
+        .await
+        .ok_or(GuildNotInCache)?;
+    if owner == user {
+        return Ok(true);
+    }
+
+    let mod_role = dis
+        .config_value_t::<VerifiedRole>(PRIV_ROLE)?
+        .get(&dis.db(guild))
+        .await?;
+    match mod_role {
+        Some(r) => Ok(user.to_user(ctx).await?.has_role(ctx, guild, r.into_inner()).await?),
+        None => Ok(false),
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for RaidModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("raid", "alerts staff when many members join at once.")
+                .with_sensitivity(Sensitivity::High)
+                .with_member_join_hook(true)
+                .with_reaction_hook(true)
+                .with_component_hook(true)
+                .with_config_value(config::Value::<RaidConfig>::with_default(
+                    RAID_CONFIG_KEY,
+                    "A JSON object describing when to raise raid alerts, e.g. {\"joins\": 10, \"window\": \"1m\", \"quarantine_window\": \"10m\", \"auto_lockdown\": false}. Set joins to 0 to disable alerts.",
+                    Default::default,
+                ))
+                .with_config_value(config::Value::<VerifiedRole>::new(
+                    RAID_SUSPECT_ROLE,
+                    "A role given to members tagged as suspects during a raid, so they're easy to find and act on.",
+                ))
+        });
+        &INFO
+    }
+
+    fn flush_caches(&self, guild: Option<GuildId>) {
+        match guild {
+            Some(g) => {
+                self.config.remove(&g);
+            }
+            None => self.config.clear(),
+        }
+    }
+
+    async fn on_member_join(&self, dis: &Dispatch, ctx: &Context, member: &Member) -> crate::error::Result<()> {
+        let gid = member.guild_id;
+        let f = async {
+            let db = dis.db(gid);
+            let v = dis.config_value_t::<RaidConfig>(RAID_CONFIG_KEY)?;
+            Ok(*v.get_or_default(&db).await?)
+        };
+        let conf = self.config.get_or_insert_with(&gid, f).await?;
+        if conf.joins == 0 {
+            return Ok(());
+        }
+
+        let count = self.record_join(member, &conf);
+        if self.tag_if_ongoing(member) {
+            debug!("tagged {} as a raid suspect", member.user.id);
+            return Self::set_suspect_role(dis, ctx, gid, std::slice::from_ref(&member.user), true).await;
+        }
+        if count < conf.joins as usize || !self.begin_alert(gid, conf.window) {
+            return Ok(());
+        }
+
+        info!("{} joins in {:?}; raising raid alert", count, conf.window);
+        let res = self.raise_alert(dis, ctx, gid, &conf, count).await;
+        crate::module::raidmode::auto_start(dis, ctx, gid).await.log_error();
+        res
+    }
+
+    async fn on_reaction(&self, dis: &Dispatch, ctx: &Context, reaction: &Reaction) -> crate::error::Result<()> {
+        let response = match AlertResponse::from_reaction(&reaction.emoji) {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+
+        let guild = match self.alerts.lock().get(&reaction.message_id) {
+            Some(a) => a.guild,
+            None => return Ok(()),
+        };
+
+        let user = match reaction.user_id {
+            Some(u) => u,
+            None => return Ok(()),
+        };
+
+        if !is_staff(dis, ctx, guild, user).await? {
+            trace!("ignoring raid alert reaction from non-staff user");
+            return Ok(());
+        }
+
+        let alert = match self.claim(reaction.message_id, response) {
+            Some(a) => a,
+            None => return Ok(()),
+        };
+
+        let live = self.alerts.lock().get(&reaction.message_id).cloned();
+        alert
+            .channel
+            .edit_message(ctx, reaction.message_id, |m| {
+                m.components(|c| alert_buttons(c, live.as_ref()))
+            })
+            .await
+            .map_err(crate::error::Error::from)
+            .log_error();
+
+        self.respond(dis, ctx, &alert, reaction.message_id, response, user)
+            .await
+    }
+
+    async fn on_component(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        interaction: &MessageComponentInteraction,
+    ) -> crate::error::Result<()> {
+        let response = match AlertResponse::from_custom_id(&interaction.data.custom_id) {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+
+        let message = interaction.message.id;
+        let guild = self.alerts.lock().get(&message).map(|a| a.guild);
+        let guild = match guild {
+            Some(g) => g,
+            None => {
+                return crate::util::reply_ephemeral(ctx, interaction, "This raid alert can no longer be acted on.")
+                    .await
+            }
+        };
+
+        let user = interaction.user.id;
+        if !is_staff(dis, ctx, guild, user).await? {
+            return crate::util::reply_ephemeral(ctx, interaction, "Only staff can respond to raid alerts.").await;
+        }
+
+        let alert = match self.claim(message, response) {
+            Some(a) => a,
+            None => return crate::util::reply_ephemeral(ctx, interaction, "Someone already did that.").await,
+        };
+
+        // Acknowledge the press straight away, since a quarantine can take longer than Discord
+        // waits for a response.
+        let live = self.alerts.lock().get(&message).cloned();
+        interaction
+            .create_interaction_response(ctx, |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| d.components(|c| alert_buttons(c, live.as_ref())))
+            })
+            .await?;
+
+        self.respond(dis, ctx, &alert, message, response, user).await
+    }
+}
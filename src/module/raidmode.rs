@@ -0,0 +1,572 @@
+//! Contains raid mode, a state guilds can put themselves in while they're being raided. While raid
+//! mode is on, the verification level is raised to the highest setting, everyone who joins is
+//! kicked or quarantined according to [`RaidModeConfig::join_action`], and spam thresholds are
+//! tightened. Raid mode is stored in the database, and turns itself off after its duration runs
+//! out. Guilds can also arm it to turn on by itself whenever a raid alert is raised.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::guild::{Member, VerificationLevel};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::utils::MessageBuilder;
+use shrinkwraprs::Shrinkwrap;
+use structopt::StructOpt;
+
+use crate::db::timed::{Action, ActionKind as TimedKind, TimedEvents, ONE_HUNDREDISH_YEARS};
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::jobs::{Job, JobPriority, OverloadPolicy};
+use crate::dispatch::Dispatch;
+use crate::error::{GuildNotInCache, LogErrorExt};
+use crate::module::moderation::{mod_log_channel, ActionKind, ModAction};
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::ClapExt;
+
+/// The config key for grabbing a [`RaidModeConfig`].
+pub const RAID_MODE_CONFIG_KEY: &str = "raid_mode_config";
+/// The reason given to members acted on because they joined during raid mode.
+pub const RAID_MODE_REASON: &str = "Joined while the server was in raid mode. Please try again later.";
+
+impl_err!(RaidModeOff, "Raid mode isn't on or armed.", true);
+impl_err!(
+    InvalidSpamFactor,
+    "raid_mode_config's spam_factor must be a number greater than 0 and no greater than 1.",
+    true
+);
+
+/// What happens to members who join while raid mode is on.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RaidJoinAction {
+    /// Nothing; they're let in as usual.
+    Nothing,
+    /// They're kicked, after being told why.
+    Kick,
+    /// They're given the mute role.
+    Quarantine,
+}
+
+impl Default for RaidJoinAction {
+    fn default() -> Self {
+        RaidJoinAction::Kick
+    }
+}
+
+impl RaidJoinAction {
+    /// The moderation action taken against new joiners, if any.
+    fn kind(&self) -> Option<ActionKind> {
+        match self {
+            RaidJoinAction::Nothing => None,
+            RaidJoinAction::Kick => Some(ActionKind::Kick),
+            RaidJoinAction::Quarantine => Some(ActionKind::Mute),
+        }
+    }
+}
+
+/// Configuration for raid mode.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct RaidModeConfig {
+    /// How long raid mode stays on if no duration is given.
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    /// What happens to members who join while raid mode is on.
+    #[serde(default)]
+    pub join_action: RaidJoinAction,
+    /// Whether to raise the verification level while raid mode is on.
+    #[serde(default = "default_raise_verification")]
+    pub raise_verification: bool,
+    /// The factor spam thresholds are scaled by while raid mode is on.
+    #[serde(default = "default_spam_factor")]
+    pub spam_factor: f64,
+}
+
+#[doc(hidden)]
+fn default_raise_verification() -> bool {
+    true
+}
+
+#[doc(hidden)]
+fn default_spam_factor() -> f64 {
+    0.5
+}
+
+impl Default for RaidModeConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(60 * 60),
+            join_action: RaidJoinAction::default(),
+            raise_verification: default_raise_verification(),
+            spam_factor: default_spam_factor(),
+        }
+    }
+}
+
+impl FromStr for RaidModeConfig {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conf: RaidModeConfig = serde_json::from_str(s)?;
+        if !conf.spam_factor.is_finite() || conf.spam_factor <= 0.0 || conf.spam_factor > 1.0 {
+            return Err(InvalidSpamFactor.into());
+        }
+        Ok(conf)
+    }
+}
+
+impl fmt::Display for RaidModeConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Converts a stored verification level back into a [`VerificationLevel`].
+fn verification_level(n: i16) -> Option<VerificationLevel> {
+    match n {
+        0 => Some(VerificationLevel::None),
+        1 => Some(VerificationLevel::Low),
+        2 => Some(VerificationLevel::Medium),
+        3 => Some(VerificationLevel::High),
+        4 => Some(VerificationLevel::Higher),
+        _ => None,
+    }
+}
+
+/// Raid mode while it's on.
+#[derive(Debug, Copy, Clone)]
+pub struct ActiveRaidMode {
+    /// Who turned raid mode on.
+    pub started_by: UserId,
+    /// When raid mode turns off.
+    pub expires: DateTime<Utc>,
+    /// The verification level before raid mode raised it, if it did.
+    pub previous_verification: Option<VerificationLevel>,
+}
+
+/// A guild's raid mode.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RaidModeState {
+    /// Whether raid mode turns on when a raid alert is raised.
+    pub auto: bool,
+    /// Raid mode, if it's on.
+    pub active: Option<ActiveRaidMode>,
+}
+
+impl RaidModeState {
+    /// Describes the state for display.
+    pub fn describe(&self) -> String {
+        let mut out = match self.active {
+            Some(a) => format!("On until {}", a.expires.format("%Y-%m-%d %H:%M UTC")),
+            None => "Off".to_string(),
+        };
+        if self.auto {
+            out.push_str("; turns on automatically when a raid alert is raised");
+        }
+        out
+    }
+}
+
+/// Wrapper around DbContext to manage a guild's raid mode.
+#[derive(Shrinkwrap)]
+pub struct RaidModes<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> RaidModes<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        RaidModes {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Retrieves the guild's raid mode. Raid mode which has expired counts as off even if it
+    /// hasn't been cleaned up yet.
+    pub async fn state(&self) -> crate::error::Result<RaidModeState> {
+        let row = sqlx::query!(
+            r#"
+            SELECT auto, started_by, expires, previous_verification, COALESCE(expires > NOW(), FALSE) AS "active!"
+            FROM raid_modes
+            WHERE guild = $1;
+            "#,
+            self.ctx.guild_as_i64()
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+
+        Ok(row.map_or_else(Default::default, |r| RaidModeState {
+            auto: r.auto,
+            active: match (r.started_by, r.expires) {
+                (Some(started_by), Some(expires)) if r.active => Some(ActiveRaidMode {
+                    started_by: UserId(started_by as u64),
+                    expires,
+                    previous_verification: r.previous_verification.and_then(verification_level),
+                }),
+                _ => None,
+            },
+        }))
+    }
+
+    /// Arms or disarms raid mode turning on by itself.
+    pub async fn set_auto(&self, auto: bool) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO raid_modes (guild, auto)
+            VALUES ($1, $2)
+            ON CONFLICT (guild) DO UPDATE
+                SET auto = EXCLUDED.auto;
+            "#,
+            self.ctx.guild_as_i64(),
+            auto
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Records that raid mode is on, replacing when it expires if it already was. The previous
+    /// verification level is only recorded if one isn't already, so it isn't lost when raid mode is
+    /// extended.
+    pub async fn start(
+        &self,
+        started_by: UserId,
+        expires: DateTime<Utc>,
+        previous_verification: Option<VerificationLevel>,
+    ) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO raid_modes (guild, started_by, expires, previous_verification)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (guild) DO UPDATE
+                SET started_by = EXCLUDED.started_by,
+                    expires = EXCLUDED.expires,
+                    previous_verification = COALESCE(raid_modes.previous_verification, EXCLUDED.previous_verification);
+            "#,
+            self.ctx.guild_as_i64(),
+            started_by.0 as i64,
+            expires,
+            previous_verification.map(|v| v.num() as i16)
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Records that raid mode is off.
+    pub async fn end(&self) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            UPDATE raid_modes
+            SET started_by = NULL, expires = NULL, previous_verification = NULL
+            WHERE guild = $1;
+            "#,
+            self.ctx.guild_as_i64()
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Posts a note about raid mode in the guild's mod log, if one is set.
+async fn log_raid_mode(dis: &Dispatch, ctx: &Context, guild: GuildId, note: &str) {
+    if let Ok(chan) = mod_log_channel(dis, guild).await {
+        chan.say(ctx, note).await.map_err(crate::error::Error::from).log_error();
+    }
+}
+
+/// Retrieves the factor spam thresholds should be scaled by because of raid mode, if it's on.
+pub async fn raid_mode_spam_factor(dis: &Dispatch, guild: GuildId) -> crate::error::Result<Option<f64>> {
+    let db = dis.db(guild);
+    if RaidModes::new(&db).state().await?.active.is_none() {
+        return Ok(None);
+    }
+    let conf = dis
+        .config_value_t::<RaidModeConfig>(RAID_MODE_CONFIG_KEY)?
+        .get_or_default(&db)
+        .await?;
+    Ok(Some(conf.spam_factor))
+}
+
+/// Turns raid mode on for the given duration, or the guild's default if none is given. If raid
+/// mode is already on, it's extended instead.
+pub async fn start_raid_mode(
+    dis: &Dispatch,
+    ctx: &Context,
+    guild: GuildId,
+    started_by: UserId,
+    duration: Option<Duration>,
+) -> crate::error::Result<Duration> {
+    let db = dis.db(guild);
+    let modes = RaidModes::new(&db);
+    let conf = dis
+        .config_value_t::<RaidModeConfig>(RAID_MODE_CONFIG_KEY)?
+        .get_or_default(&db)
+        .await?;
+    let duration = duration.unwrap_or(conf.duration);
+    let already_on = modes.state().await?.active.is_some();
+
+    let mut previous = None;
+    if conf.raise_verification && !already_on {
+        let current = ctx
+            .cache
+            .guild_field(guild, |g| g.verification_level)
+            .await
+            .ok_or(GuildNotInCache)?;
+        if current != VerificationLevel::Higher {
+            let mut g = guild;
+            g.edit(ctx, |e| e.verification_level(VerificationLevel::Higher)).await?;
+            previous = Some(current);
+        }
+    }
+
+    let chrono_dur = chrono::Duration::from_std(duration).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+    let action = Action::end_raid_mode(guild, chrono_dur);
+    TimedEvents::new(db.clone())
+        .cancel_actions_for(UserId::default(), TimedKind::EndRaidMode)
+        .await?;
+    modes.start(started_by, action.expiry(), previous).await?;
+    action.store_action(dis).await?;
+    Ok(duration)
+}
+
+/// Turns raid mode off, restoring the verification level it raised. Returns false if it wasn't on.
+pub async fn end_raid_mode(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<bool> {
+    let db = dis.db(guild);
+    let modes = RaidModes::new(&db);
+    let active = match modes.state().await?.active {
+        Some(a) => a,
+        None => {
+            // Clean up raid mode which expired without being processed.
+            modes.end().await?;
+            return Ok(false);
+        }
+    };
+
+    if let Some(previous) = active.previous_verification {
+        let mut g = guild;
+        g.edit(ctx, |e| e.verification_level(previous)).await?;
+    }
+    modes.end().await?;
+    TimedEvents::new(db.clone())
+        .cancel_actions_for(UserId::default(), TimedKind::EndRaidMode)
+        .await?;
+    Ok(true)
+}
+
+/// Turns raid mode off once it has run out, noting it in the mod log.
+pub async fn expire_raid_mode(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<()> {
+    let db = dis.db(guild);
+    let modes = RaidModes::new(&db);
+    let previous = sqlx::query_scalar!(
+        "SELECT previous_verification FROM raid_modes WHERE guild = $1 AND expires IS NOT NULL;",
+        db.guild_as_i64()
+    )
+    .fetch_optional(db.conn()?)
+    .await?;
+
+    // The raid mode has already expired, so `end_raid_mode` wouldn't see it as on.
+    let previous = match previous {
+        Some(p) => p.and_then(verification_level),
+        None => return Ok(()),
+    };
+    if let Some(previous) = previous {
+        let mut g = guild;
+        g.edit(ctx, |e| e.verification_level(previous)).await?;
+    }
+    modes.end().await?;
+    log_raid_mode(dis, ctx, guild, "Raid mode expired and is now off.").await;
+    Ok(())
+}
+
+/// Turns raid mode on if the guild has armed it to turn on by itself. Called when a raid alert is
+/// raised.
+pub async fn auto_start(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<()> {
+    let state = RaidModes::new(dis.db(guild)).state().await?;
+    if !state.auto || state.active.is_some() {
+        return Ok(());
+    }
+
+    let duration = start_raid_mode(dis, ctx, guild, dis.bot().await, None).await?;
+    info!("raid mode turned on automatically");
+    log_raid_mode(
+        dis,
+        ctx,
+        guild,
+        &format!(
+            "Raid mode turned on automatically in response to a raid alert, for {}.",
+            humantime::format_duration(duration)
+        ),
+    )
+    .await;
+    Ok(())
+}
+
+/// A member who joined while raid mode was on, dealt with as a background job since there may be
+/// many of them.
+struct RaidModeJoin {
+    /// The member who joined.
+    member: Member,
+    /// What to do to them.
+    kind: ActionKind,
+}
+
+#[async_trait::async_trait]
+impl Job for RaidModeJoin {
+    fn name(&self) -> &'static str {
+        "raid-mode-join"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::High
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let me = dis.bot().await;
+        let mut action =
+            ModAction::new(&self.member, ChannelId::default(), me, self.kind).with_reason(RAID_MODE_REASON);
+        let case = action.act_and_notify(dis, ctx).await?;
+        debug!(
+            "{} {} for joining during raid mode as case {}",
+            self.kind.name(),
+            self.member.user.id,
+            case
+        );
+        Ok(())
+    }
+}
+
+/// Turns raid mode on and off.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "raidmode", no_version)]
+pub enum RaidModeOpt {
+    /// Turns raid mode on now.
+    On {
+        /// How long raid mode stays on. Specified in human format, i.e. "2h 30m". Defaults to
+        /// raid_mode_config's duration.
+        #[structopt(short, long)]
+        duration: Option<humantime::Duration>,
+    },
+    /// Turns raid mode off, and stops it from turning on automatically.
+    Off,
+    /// Turns raid mode on automatically whenever a raid alert is raised.
+    Auto,
+    /// Shows whether raid mode is on.
+    Status,
+}
+
+/// Adds the `raidmode` command and deals with members who join during raid mode.
+pub struct RaidModeModule;
+
+#[async_trait::async_trait]
+impl Module for RaidModeModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "raidmode",
+                "locks the server down while it's being raided: raises verification, turns away new joiners and tightens the anti-spam.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+            .with_member_join_hook(true)
+            .with_config_value(config::Value::<RaidModeConfig>::with_default(
+                RAID_MODE_CONFIG_KEY,
+                "A JSON object describing raid mode, e.g. {\"duration\": \"1h\", \"join_action\": \"Kick\", \"raise_verification\": true, \"spam_factor\": 0.5}. join_action may be Nothing, Kick or Quarantine.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let opts = RaidModeOpt::from_iter_with_help(command)?;
+        let modes = RaidModes::new(dis.db(gid));
+
+        let message = match opts {
+            RaidModeOpt::On { duration } => {
+                let duration = start_raid_mode(dis, ctx, gid, orig.author.id, duration.map(Into::into)).await?;
+                let duration = humantime::format_duration(duration);
+                log_raid_mode(
+                    dis,
+                    ctx,
+                    gid,
+                    &format!("{} turned raid mode on for {}.", orig.author.tag(), duration),
+                )
+                .await;
+                format!("Raid mode is on for {}.", duration)
+            }
+            RaidModeOpt::Off => {
+                let was_auto = modes.state().await?.auto;
+                modes.set_auto(false).await?;
+                let was_on = end_raid_mode(dis, ctx, gid).await?;
+                if !was_on && !was_auto {
+                    return Err(RaidModeOff.into());
+                }
+                log_raid_mode(dis, ctx, gid, &format!("{} turned raid mode off.", orig.author.tag())).await;
+                "Raid mode is off, and won't turn on automatically.".to_string()
+            }
+            RaidModeOpt::Auto => {
+                modes.set_auto(true).await?;
+                log_raid_mode(
+                    dis,
+                    ctx,
+                    gid,
+                    &format!(
+                        "{} armed raid mode to turn on automatically when a raid alert is raised.",
+                        orig.author.tag()
+                    ),
+                )
+                .await;
+                "Raid mode will turn on automatically when a raid alert is raised.".to_string()
+            }
+            RaidModeOpt::Status => modes.state().await?.describe(),
+        };
+
+        let message = MessageBuilder::new().push_codeblock_safe(message, None).build();
+        orig.reply(ctx, message).await?;
+        Ok(())
+    }
+
+    async fn on_member_join(&self, dis: &Dispatch, _ctx: &Context, member: &Member) -> crate::error::Result<()> {
+        let gid = member.guild_id;
+        let db = dis.db(gid);
+        if RaidModes::new(&db).state().await?.active.is_none() {
+            return Ok(());
+        }
+
+        let conf = dis
+            .config_value_t::<RaidModeConfig>(RAID_MODE_CONFIG_KEY)?
+            .get_or_default(&db)
+            .await?;
+        if let Some(kind) = conf.join_action.kind() {
+            dis.jobs().enqueue(
+                gid,
+                RaidModeJoin {
+                    member: member.clone(),
+                    kind,
+                },
+            );
+        }
+        Ok(())
+    }
+}
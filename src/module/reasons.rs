@@ -0,0 +1,102 @@
+//! Contains reason presets, which let guilds give short names to reasons they use often, e.g.
+//! `spam` for "Spamming messages or mentions.". A moderator giving a preset's name as the reason
+//! for an action gets the preset's full text in the case and mod log instead.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use serenity::model::id::GuildId;
+
+use crate::dispatch::Dispatch;
+
+/// The config key for a guild's [`ReasonPresets`].
+pub const REASON_PRESETS_KEY: &str = "mod_reason_presets";
+/// The longest name a preset may have.
+pub const MAX_PRESET_NAME_LEN: usize = 32;
+/// The longest text a preset may expand to.
+pub const MAX_PRESET_TEXT_LEN: usize = 512;
+
+impl_err!(
+    InvalidReasonPreset,
+    "Reason presets must be a JSON object of names to reasons, like {\"spam\": \"Spamming messages.\"}. Names can't contain spaces or be longer than 32 characters, and reasons can't be empty or longer than 512 characters.",
+    true
+);
+
+/// A guild's reason presets, keyed by lower-case name.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct ReasonPresets(BTreeMap<String, String>);
+
+impl Default for ReasonPresets {
+    fn default() -> Self {
+        let presets = [
+            ("spam", "Spamming messages, mentions or emoji."),
+            ("nsfw", "Posting NSFW content outside of NSFW channels."),
+            ("advert", "Advertising other servers or services without permission."),
+        ];
+        ReasonPresets(presets.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+}
+
+impl FromStr for ReasonPresets {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let presets: BTreeMap<String, String> = serde_json::from_str(s).map_err(|_| InvalidReasonPreset)?;
+        let valid = presets.iter().all(|(k, v)| {
+            !k.is_empty()
+                && k.chars().count() <= MAX_PRESET_NAME_LEN
+                && !k.contains(char::is_whitespace)
+                && !v.trim().is_empty()
+                && v.chars().count() <= MAX_PRESET_TEXT_LEN
+        });
+        if !valid {
+            return Err(InvalidReasonPreset.into());
+        }
+        Ok(ReasonPresets(
+            presets.into_iter().map(|(k, v)| (k.to_lowercase(), v)).collect(),
+        ))
+    }
+}
+
+impl fmt::Display for ReasonPresets {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+impl ReasonPresets {
+    /// Retrieves the text of the preset with the given name, if there is one.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(&name.trim().to_lowercase()).map(String::as_str)
+    }
+
+    /// Iterates over the presets, in order of name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns true if there are no presets.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Retrieves a guild's reason presets.
+pub async fn reason_presets(dis: &Dispatch, guild: GuildId) -> crate::error::Result<ReasonPresets> {
+    let presets = dis
+        .config_value_t::<ReasonPresets>(REASON_PRESETS_KEY)?
+        .get_or_default(&dis.db(guild))
+        .await?;
+    Ok((*presets).clone())
+}
+
+/// Expands a reason into its preset's text if it names one of the guild's presets, leaving it as is
+/// otherwise.
+pub async fn expand_reason(dis: &Dispatch, guild: GuildId, reason: String) -> crate::error::Result<String> {
+    let presets = reason_presets(dis, guild).await?;
+    Ok(presets.get(&reason).map_or(reason, str::to_string))
+}
@@ -0,0 +1,201 @@
+//! Contains the regex filter, which applies a guild's own regular expression rules to messages.
+//! Unlike the patterns managed with `filter add`, which share the guild's [`FILTER_ACTION`], each
+//! rule has a name and its own action, so a guild can delete mild matches while banning for severe
+//! ones. Rules are set as a config value, and are checked and compiled when they're set, so a rule
+//! which is invalid or too expensive to run is rejected up front.
+//!
+//! [`FILTER_ACTION`]: crate::module::filter::FILTER_ACTION
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use once_cell::sync::{Lazy, OnceCell};
+use regex::{RegexSet, RegexSetBuilder};
+use serenity::client::Context;
+use serenity::model::channel::Message;
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::module::filter::{handle_filtered, FilterAction, FilterModule, MAX_PATTERN_LEN};
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing a guild's [`RegexRules`].
+pub const REGEX_FILTER_KEY: &str = "regex_filter";
+/// The most rules a guild may define.
+pub const MAX_REGEX_RULES: usize = 25;
+/// The longest rule name, in characters.
+pub const MAX_RULE_NAME_LEN: usize = 32;
+/// The largest a guild's compiled rules may be, in bytes.
+pub const MAX_RULES_SIZE: usize = 256 * 1024;
+/// How deeply groups and repetitions may be nested in a rule.
+pub const MAX_RULE_NESTING: u32 = 16;
+
+impl_err!(TooManyRegexRules, "A server may have at most 25 regex rules.", true);
+impl_err!(
+    InvalidRegexRule,
+    "Each regex rule needs a unique name of at most 32 characters and a pattern of at most 256 characters.",
+    true
+);
+impl_err!(
+    RegexRuleDoesNotCompile,
+    "A regex rule isn't a valid regular expression, or is too complex. Simplify it, or split it into several rules.",
+    true
+);
+
+/// A guild's regex rule.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RegexRule {
+    /// The rule's name, shown in the mod log when it matches.
+    pub name: String,
+    /// The pattern, as a case-insensitive regular expression.
+    pub pattern: String,
+    /// What happens to the author of a matching message.
+    #[serde(default = "default_action")]
+    pub action: FilterAction,
+}
+
+#[doc(hidden)]
+fn default_action() -> FilterAction {
+    FilterAction::Delete
+}
+
+/// A guild's regex rules, compiled the first time they're used.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(transparent)]
+pub struct RegexRules {
+    /// The rules, in the order they're checked.
+    rules: Vec<RegexRule>,
+    /// The compiled rules.
+    #[serde(skip)]
+    compiled: OnceCell<RegexSet>,
+}
+
+impl RegexRules {
+    /// Compiles the rules into a single case-insensitive regex set, enforcing the size and nesting
+    /// limits.
+    fn compile(rules: &[RegexRule]) -> crate::error::Result<RegexSet> {
+        RegexSetBuilder::new(rules.iter().map(|r| &r.pattern))
+            .case_insensitive(true)
+            .size_limit(MAX_RULES_SIZE)
+            .dfa_size_limit(MAX_RULES_SIZE)
+            .nest_limit(MAX_RULE_NESTING)
+            .build()
+            .map_err(|_| RegexRuleDoesNotCompile.into())
+    }
+
+    /// Checks the rules against the limits.
+    fn validate(&self) -> crate::error::Result<()> {
+        if self.rules.len() > MAX_REGEX_RULES {
+            return Err(TooManyRegexRules.into());
+        }
+        let mut names = HashSet::new();
+        for r in &self.rules {
+            let name = r.name.trim().to_lowercase();
+            let valid = !name.is_empty()
+                && name.chars().count() <= MAX_RULE_NAME_LEN
+                && !r.pattern.is_empty()
+                && r.pattern.chars().count() <= MAX_PATTERN_LEN
+                && names.insert(name);
+            if !valid {
+                return Err(InvalidRegexRule.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Retrieves the rules, in the order they're checked.
+    pub fn rules(&self) -> &[RegexRule] {
+        &self.rules
+    }
+
+    /// Returns the first rule matching the text, if any.
+    pub fn first_match(&self, text: &str) -> crate::error::Result<Option<&RegexRule>> {
+        if self.rules.is_empty() {
+            return Ok(None);
+        }
+        let set = self.compiled.get_or_try_init(|| Self::compile(&self.rules))?;
+        Ok(set.matches(text).into_iter().next().map(|i| &self.rules[i]))
+    }
+}
+
+impl FromStr for RegexRules {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules: Vec<RegexRule> = serde_json::from_str(s)?;
+        let out = RegexRules {
+            rules,
+            compiled: OnceCell::new(),
+        };
+        out.validate()?;
+        let compiled = Self::compile(&out.rules)?;
+        let _ = out.compiled.set(compiled);
+        Ok(out)
+    }
+}
+
+impl fmt::Display for RegexRules {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "[]".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Module which applies the guild's regex rules.
+pub struct RegexFilterModule;
+
+#[async_trait::async_trait]
+impl Module for RegexFilterModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "regex-filter",
+                "removes messages matching the server's regex rules, each with its own action.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_message_hook(true)
+            .with_config_value(config::Value::<RegexRules>::with_default(
+                REGEX_FILTER_KEY,
+                "A JSON array of up to 25 regex rules, checked in order, e.g. [{\"name\": \"nitro scam\", \"pattern\": \"free\\\\s+nitro\", \"action\": \"Ban\"}]. Patterns are case-insensitive; action may be Delete, Warn, Mute, Kick or Ban.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if orig.content.is_empty() {
+            return Ok(());
+        }
+
+        let rules = dis
+            .config_value_t::<RegexRules>(REGEX_FILTER_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        let rule = match rules.first_match(&orig.content)? {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        if FilterModule::is_exempt(dis, ctx, orig, gid, self.info().name).await? {
+            trace!("exempt user matched regex rule {}", rule.name);
+            return Ok(());
+        }
+
+        debug!("message matched regex rule {}", rule.name);
+        handle_filtered(
+            dis,
+            orig,
+            self.info().name,
+            format!("matched the rule \"{}\"", rule.name),
+            rule.action,
+        )
+        .await
+    }
+}
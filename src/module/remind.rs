@@ -0,0 +1,275 @@
+//! Contains reminders, which members set with `!remind 2h take out the laundry`. Once the time's
+//! up, Glimbot pings them with the text in the channel they asked in, or in their DMs if it can't.
+//! Reminders are delivered through timed events, so they survive restarts.
+
+use std::borrow::Borrow;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::model::misc::Mentionable;
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::db::quota::{Quota, Resource};
+use crate::db::timed::{Action, ActionKind, TimedEvents, ONE_MINUTE};
+use crate::db::DbContext;
+use crate::dispatch::Dispatch;
+use crate::module::persona::react_success;
+use crate::module::time::GuildClock;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::{text_after_words, ClapExt};
+
+/// The built-in quota for how many reminders a member may have waiting in a guild; see
+/// [`crate::db::quota`].
+pub const MAX_REMINDERS: i64 = 25;
+/// The longest reminder text, in characters.
+pub const MAX_REMINDER_LEN: usize = 1000;
+/// The furthest ahead a reminder may be set.
+pub const MAX_REMINDER_DELAY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+/// How much of each reminder's text is shown when listing them, in characters.
+const LIST_PREVIEW_LEN: usize = 50;
+
+impl_err!(
+    InvalidReminder,
+    "Reminders need a time like 2h or 3d12h, up to a year away, followed by up to 1000 characters of text.",
+    true
+);
+impl_err!(NoSuchReminder, "You have no reminder with that ID.", true);
+
+/// Something a member asked to be reminded of.
+#[derive(Debug, Clone)]
+pub struct Reminder {
+    /// The reminder's ID.
+    pub id: i64,
+    /// The member to remind.
+    pub user: UserId,
+    /// The channel the reminder was set in, which it's delivered to.
+    pub channel: ChannelId,
+    /// What to remind the member of.
+    pub content: String,
+    /// When the reminder is due.
+    pub due: DateTime<Utc>,
+}
+
+/// Wrapper around a guild's reminders in the database.
+pub struct Reminders<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> Reminders<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        Reminders {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Stores a reminder, returning its ID. Errors if the member has too many reminders already.
+    pub async fn add(
+        &self,
+        user: UserId,
+        channel: ChannelId,
+        content: &str,
+        due: DateTime<Utc>,
+    ) -> crate::error::Result<i64> {
+        Quota::new(&self.ctx).check_member(Resource::Reminders, user).await?;
+
+        let id = sqlx::query_scalar!(
+            "INSERT INTO reminders (guild, user_id, channel, content, due) VALUES ($1, $2, $3, $4, $5) RETURNING id;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            channel.0 as i64,
+            content,
+            due
+        )
+        .fetch_one(self.ctx.conn()?)
+        .await?;
+        Ok(id)
+    }
+
+    /// Retrieves and forgets a reminder, if it hasn't been cancelled.
+    pub async fn take(&self, id: i64) -> crate::error::Result<Option<Reminder>> {
+        let row = sqlx::query!(
+            "DELETE FROM reminders WHERE guild = $1 AND id = $2 RETURNING id, user_id, channel, content, due;",
+            self.ctx.guild_as_i64(),
+            id
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+        Ok(row.map(|r| Reminder {
+            id: r.id,
+            user: UserId(r.user_id as u64),
+            channel: ChannelId(r.channel as u64),
+            content: r.content,
+            due: r.due,
+        }))
+    }
+
+    /// Cancels one of a member's reminders.
+    pub async fn cancel(&self, user: UserId, id: i64) -> crate::error::Result<()> {
+        let res = sqlx::query!(
+            "DELETE FROM reminders WHERE guild = $1 AND user_id = $2 AND id = $3;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            id
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(NoSuchReminder.into());
+        }
+        Ok(())
+    }
+
+    /// Retrieves a member's reminders, soonest first.
+    pub async fn list(&self, user: UserId) -> crate::error::Result<Vec<Reminder>> {
+        let rows = sqlx::query!(
+            "SELECT id, user_id, channel, content, due FROM reminders WHERE guild = $1 AND user_id = $2 ORDER BY due ASC;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64
+        )
+        .fetch_all(self.ctx.conn()?)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| Reminder {
+                id: r.id,
+                user: UserId(r.user_id as u64),
+                channel: ChannelId(r.channel as u64),
+                content: r.content,
+                due: r.due,
+            })
+            .collect())
+    }
+}
+
+/// Delivers a reminder, unless it was cancelled. Falls back to the member's DMs if the channel it
+/// was set in can't be posted in anymore.
+pub async fn send_reminder(dis: &Dispatch, ctx: &Context, guild: GuildId, reminder: i64) -> crate::error::Result<()> {
+    let reminder = match Reminders::new(dis.db(guild)).take(reminder).await? {
+        Some(r) => r,
+        None => {
+            debug!("reminder was cancelled");
+            return Ok(());
+        }
+    };
+
+    let text = format!(
+        "{}, you asked to be reminded: {}",
+        reminder.user.mention(),
+        reminder.content
+    );
+    let res = reminder
+        .channel
+        .send_message(ctx, |m| {
+            m.content(&text).allowed_mentions(|am| am.users(vec![reminder.user]))
+        })
+        .await;
+    if let Err(e) = res {
+        debug!("couldn't post reminder in its channel, sending it by DM instead: {}", e);
+        let dm = reminder.user.create_dm_channel(ctx).await?;
+        dm.say(ctx, format!("You asked to be reminded: {}", reminder.content))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Reminds you of something after a while, e.g. `remind 2h take out the laundry`.
+#[derive(StructOpt)]
+#[structopt(name = "remind", no_version)]
+enum RemindOpt {
+    /// Lists your reminders in this server.
+    List,
+    /// Cancels one of your reminders.
+    Cancel {
+        /// The reminder's ID, as shown by list.
+        id: i64,
+    },
+    /// Sets a reminder: how long from now, like 2h or 3d12h, followed by what to remind you of.
+    #[structopt(external_subcommand)]
+    Set(Vec<String>),
+}
+
+/// Module which lets members set reminders.
+pub struct RemindModule;
+
+#[async_trait::async_trait]
+impl Module for RemindModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("remind", "reminds you of something after a while.")
+                .with_sensitivity(Sensitivity::Medium)
+                .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = RemindOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let reminders = Reminders::new(dis.db(gid));
+
+        match opts {
+            RemindOpt::List => {
+                let list = reminders.list(orig.author.id).await?;
+                let clock = GuildClock::for_guild(dis, gid).await?;
+                let listing = if list.is_empty() {
+                    "You have no reminders in this server.".to_string()
+                } else {
+                    list.iter()
+                        .map(|r| {
+                            let mut preview: String = r.content.chars().take(LIST_PREVIEW_LEN).collect();
+                            if preview.len() < r.content.len() {
+                                preview.push('…');
+                            }
+                            format!("#{} at {}: {}", r.id, clock.datetime(r.due), preview)
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                };
+                let msg = MessageBuilder::new().push_codeblock_safe(listing, None).build();
+                orig.reply(ctx, msg).await?;
+            }
+            RemindOpt::Cancel { id } => {
+                reminders.cancel(orig.author.id, id).await?;
+                TimedEvents::new(dis.db(gid))
+                    .cancel_actions_for(orig.author.id, ActionKind::Remind { reminder: id })
+                    .await?;
+                react_success(dis, ctx, orig).await?;
+            }
+            RemindOpt::Set(args) => {
+                let delay = args
+                    .first()
+                    .and_then(|d| humantime::parse_duration(d).ok())
+                    .filter(|d| *d <= MAX_REMINDER_DELAY)
+                    .ok_or(InvalidReminder)?;
+                let content = text_after_words(&orig.content, 2);
+                if content.is_empty() || content.chars().count() > MAX_REMINDER_LEN {
+                    return Err(InvalidReminder.into());
+                }
+
+                let delay = chrono::Duration::from_std(delay).map_err(|_| InvalidReminder)?;
+                let due = Utc::now() + delay.max(*ONE_MINUTE);
+                let id = reminders.add(orig.author.id, orig.channel_id, content, due).await?;
+                Action::remind(orig.author.id, gid, id, due).store_action(dis).await?;
+
+                let clock = GuildClock::for_guild(dis, gid).await?;
+                let reply = format!("I'll remind you at {} (reminder #{}).", clock.datetime(due), id);
+                orig.reply(ctx, reply).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,28 +1,45 @@
-//! Contains logic related to joining/assigning/leaving/unassigning roles.
+//! Contains logic related to joining/assigning/leaving/unassigning roles, including temporary roles.
 
 use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::time::Duration;
 
 use futures::StreamExt;
 use once_cell::sync::Lazy;
 use serenity::client::Context;
 use serenity::model::channel::Message;
+use serenity::model::guild::Member;
+use serenity::model::id::GuildId;
+use serenity::model::interactions::message_component::MessageComponentInteraction;
+use serenity::model::misc::Mentionable;
 use serenity::model::prelude::RoleId;
-use serenity::utils::MessageBuilder;
 use shrinkwraprs::Shrinkwrap;
 use structopt::StructOpt;
 
+use crate::db::quota::{Quota, Resource};
+use crate::db::timed::{Action, ActionKind, TimedEvents, ONE_HUNDREDISH_YEARS};
 use crate::db::DbContext;
 use crate::dispatch::config::VerifiedRole;
-use crate::dispatch::config::{FromStrWithCtx, NoSuchUser, RoleExt, VerifiedUser};
+use crate::dispatch::config::{FromStrWithCtx, NoSuchUser, VerifiedUser};
 use crate::dispatch::Dispatch;
-use crate::error::{DatabaseError, GuildNotInCache, RoleNotInCache};
+use crate::error::{GuildNotInCache, RoleNotInCache};
+use crate::module::persona::react_success;
 use crate::module::privilege::ensure_authorized_for_role;
+use crate::module::status::GLIM_COLOR;
 use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::ConstrainedU64;
 use crate::util::ClapExt;
 
 /// Adds `role` and `mod_role` command.
 pub struct RoleModule;
 
+/// The prefix of the custom IDs of role menus.
+pub const ROLE_MENU_PREFIX: &str = "role:menu:";
+/// The most roles Discord allows in one select menu.
+const MENU_OPTIONS: usize = 25;
+/// The most select menus Discord allows on one message.
+const MENUS_PER_MESSAGE: usize = 5;
+
 /// Command to join joinable roles. Use list-joinable to join a role.
 #[derive(StructOpt)]
 #[structopt(name = "role", no_version)]
@@ -37,10 +54,59 @@ pub enum RoleOpt {
         /// The role to leave.
         role: String,
     },
-    /// Lists all joinable roles.
+    /// Lists all joinable roles by category, along with their descriptions and how long members keep
+    /// those which expire.
     ListJoinable,
 }
 
+/// A role members can join on their own.
+#[derive(Debug, Clone)]
+pub struct JoinableRole {
+    /// The role.
+    pub role: RoleId,
+    /// How long members keep the role after joining it, if it expires.
+    pub expires_after: Option<Duration>,
+    /// The group the role belongs to, if any.
+    pub group: Option<String>,
+    /// A short description of the role.
+    pub description: Option<String>,
+    /// The category the role is listed under.
+    pub category: Option<String>,
+}
+
+/// A named group of joinable roles, of which members may hold a limited number at once.
+#[derive(Debug, Clone)]
+pub struct RoleGroup {
+    /// The group's name.
+    pub name: String,
+    /// The most roles in the group a member may hold at once.
+    pub max_roles: u64,
+    /// The roles in the group.
+    pub roles: Vec<RoleId>,
+}
+
+/// The built-in quota for how many role groups a guild may have; see [`crate::db::quota`].
+pub const MAX_ROLE_GROUPS: i64 = 25;
+/// The most joinable roles a guild may have. This is also the highest quota which may be set, as
+/// a check constraint in the database enforces it.
+pub const MAX_JOINABLE_ROLES: i64 = 128;
+/// The longest role group name, in characters.
+pub const MAX_ROLE_GROUP_NAME_LEN: usize = 32;
+
+/// The longest joinable role description, in characters.
+pub const MAX_ROLE_DESCRIPTION_LEN: usize = 100;
+/// The longest joinable role category name, in characters.
+pub const MAX_ROLE_CATEGORY_LEN: usize = 32;
+
+/// Normalizes a role group name, checking it's a valid one.
+fn group_name(name: &str) -> crate::error::Result<String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.chars().count() > MAX_ROLE_GROUP_NAME_LEN {
+        return Err(InvalidRoleGroupName.into());
+    }
+    Ok(name)
+}
+
 /// Wrapper around DbContext to retrieve/set joinable roles.
 #[derive(Shrinkwrap)]
 pub struct JoinableRoles<'pool> {
@@ -54,6 +120,19 @@ impl_err!(
     true
 );
 impl_err!(AlreadyJoinable, "This role is already joinable.", true);
+impl_err!(
+    InvalidRoleGroupName,
+    "Role group names must be between 1 and 32 characters long.",
+    true
+);
+impl_err!(
+    InvalidRoleDescription,
+    "Role descriptions must be between 1 and 100 characters long, and categories between 1 and 32.",
+    true
+);
+impl_err!(RoleGroupExists, "There's already a role group by that name.", true);
+impl_err!(NoSuchRoleGroup, "There's no role group by that name.", true);
+impl_err!(NoJoinableRoles, "There are no joinable roles to put in a menu.", true);
 
 impl<'pool> JoinableRoles<'pool> {
     /// Creates a wrapper around the database context.
@@ -63,68 +142,123 @@ impl<'pool> JoinableRoles<'pool> {
         }
     }
 
-    /// Inserts a new joinable role into the database.
-    /// This will error if the guild has too many roles or if the role is already joinable.
-    pub async fn add_joinable_role(&self, role: VerifiedRole) -> crate::error::Result<()> {
-        let res: Result<_, sqlx::Error> = sqlx::query!(
-            "INSERT INTO joinable_roles (guild, role) VALUES ($1, $2);",
-            self.ctx.guild_as_i64(),
-            role.to_i64()
-        )
-        .execute(self.ctx.conn())
-        .await;
-
-        if let Err(e) = res {
-            if e.is_check() {
-                Err(TooManyRoles.into())
-            } else if e.is_unique() {
-                Err(AlreadyJoinable.into())
-            } else {
-                Err(e.into())
-            }
-        } else {
-            Ok(())
-        }
+    /// Inserts a new joinable role into the database, optionally expiring a while after members
+    /// join it. This will error if the guild has reached its quota or if the role is already joinable.
+    pub async fn add_joinable_role(
+        &self,
+        role: VerifiedRole,
+        expires_after: Option<Duration>,
+    ) -> crate::error::Result<()> {
+        Quota::new(&self.ctx).check(Resource::JoinableRoles).await?;
+        self.ctx
+            .storage()
+            .add_joinable_role(self.ctx.guild(), role.into_inner(), expires_after)
+            .await
     }
 
     /// Removes a role from the joinable list.
     pub async fn del_joinable_role(&self, role: VerifiedRole) -> crate::error::Result<()> {
-        sqlx::query!(
-            "DELETE FROM joinable_roles WHERE guild = $1 AND role = $2;",
-            self.ctx.guild_as_i64(),
-            role.to_i64()
-        )
-        .execute(self.ctx.conn())
-        .await?;
-
-        Ok(())
+        self.ctx
+            .storage()
+            .del_joinable_role(self.ctx.guild(), role.into_inner())
+            .await
     }
 
-    /// Returns whether or not the role is in the joinable roles list.
-    pub async fn is_joinable(&self, role: VerifiedRole) -> crate::error::Result<bool> {
-        Ok(sqlx::query_scalar!(
-            "SELECT COUNT(*) AS matching FROM joinable_roles WHERE guild = $1 AND role = $2;",
-            self.ctx.guild_as_i64(),
-            role.to_i64()
-        )
-        .fetch_one(self.ctx.conn())
-        .await?
-        .unwrap_or_default()
-            > 0)
+    /// Retrieves a role from the joinable roles list, if it's there.
+    pub async fn joinable(&self, role: VerifiedRole) -> crate::error::Result<Option<JoinableRole>> {
+        self.ctx
+            .storage()
+            .joinable_role(self.ctx.guild(), role.into_inner())
+            .await
     }
 
     /// Retrieves the list of joinable roles. Keeping this query sane is why
     /// we limit the number of joinable roles.
-    pub async fn joinable_roles(&self) -> crate::error::Result<Vec<RoleId>> {
-        let s: Vec<i64> = sqlx::query_scalar!(
-            "SELECT role FROM joinable_roles WHERE guild = $1 ORDER BY role ASC;",
-            self.ctx.guild_as_i64()
-        )
-        .fetch_all(self.ctx.conn())
-        .await?;
-        let mut out = Vec::with_capacity(s.len());
-        out.extend(s.into_iter().map(|r| RoleId::from(r as u64)));
-        Ok(out)
+    pub async fn joinable_roles(&self) -> crate::error::Result<Vec<JoinableRole>> {
+        self.ctx.storage().joinable_roles(self.ctx.guild()).await
+    }
+
+    /// Sets the description shown alongside a joinable role, or removes it if `description` is
+    /// `None`.
+    pub async fn set_description(&self, role: VerifiedRole, description: Option<&str>) -> crate::error::Result<()> {
+        let description = description.map(str::trim);
+        if description.map_or(false, |d| d.is_empty() || d.chars().count() > MAX_ROLE_DESCRIPTION_LEN) {
+            return Err(InvalidRoleDescription.into());
+        }
+        let joinable = self
+            .ctx
+            .storage()
+            .set_role_description(self.ctx.guild(), role.into_inner(), description)
+            .await?;
+        if !joinable {
+            return Err(RoleNotSelfAssignable.into());
+        }
+        Ok(())
+    }
+
+    /// Lists a joinable role under a category, or takes it out of its category if `category` is
+    /// `None`.
+    pub async fn set_category(&self, role: VerifiedRole, category: Option<&str>) -> crate::error::Result<()> {
+        let category = category.map(str::trim);
+        if category.map_or(false, |c| c.is_empty() || c.chars().count() > MAX_ROLE_CATEGORY_LEN) {
+            return Err(InvalidRoleDescription.into());
+        }
+        let joinable = self
+            .ctx
+            .storage()
+            .set_role_category(self.ctx.guild(), role.into_inner(), category)
+            .await?;
+        if !joinable {
+            return Err(RoleNotSelfAssignable.into());
+        }
+        Ok(())
+    }
+
+    /// Creates a role group, of which members may hold at most `max_roles` roles at once.
+    pub async fn create_group(&self, name: &str, max_roles: u64) -> crate::error::Result<()> {
+        let name = group_name(name)?;
+        Quota::new(&self.ctx).check(Resource::RoleGroups).await?;
+        self.ctx
+            .storage()
+            .create_role_group(self.ctx.guild(), &name, max_roles)
+            .await
+    }
+
+    /// Deletes a role group. Its roles stay joinable, but no longer belong to a group.
+    pub async fn delete_group(&self, name: &str) -> crate::error::Result<()> {
+        let name = group_name(name)?;
+        if !self.ctx.storage().delete_role_group(self.ctx.guild(), &name).await? {
+            return Err(NoSuchRoleGroup.into());
+        }
+        Ok(())
+    }
+
+    /// Puts a joinable role in a group, or takes it out of its group if `group` is `None`.
+    pub async fn set_group(&self, role: VerifiedRole, group: Option<&str>) -> crate::error::Result<()> {
+        let group = group.map(group_name).transpose()?;
+        let joinable = self
+            .ctx
+            .storage()
+            .set_role_group(self.ctx.guild(), role.into_inner(), group.as_deref())
+            .await?;
+        if !joinable {
+            return Err(RoleNotSelfAssignable.into());
+        }
+        Ok(())
+    }
+
+    /// Retrieves a role group along with its roles.
+    pub async fn group(&self, name: &str) -> crate::error::Result<RoleGroup> {
+        self.ctx
+            .storage()
+            .role_group(self.ctx.guild(), name)
+            .await?
+            .ok_or_else(|| NoSuchRoleGroup.into())
+    }
+
+    /// Retrieves the guild's role groups, without their roles.
+    pub async fn groups(&self) -> crate::error::Result<Vec<RoleGroup>> {
+        self.ctx.storage().role_groups(self.ctx.guild()).await
     }
 }
 
@@ -139,6 +273,7 @@ impl Module for RoleModule {
                 .with_sensitivity(Sensitivity::Low)
                 .with_filter(false)
                 .with_command(true)
+                .with_component_hook(true)
         });
         &INFO
     }
@@ -172,46 +307,256 @@ impl Module for RoleModule {
 
                 ensure_authorized_for_role(ctx, &auth_mem, &full_role).await?;
 
-                let is_joinable = join.is_joinable(vrole).await?;
-
-                if !is_joinable {
-                    return Err(RoleNotSelfAssignable.into());
-                }
+                let joinable = join.joinable(vrole).await?.ok_or(RoleNotSelfAssignable)?;
 
                 let guild = gid.to_guild_cached(ctx).await.ok_or(GuildNotInCache)?;
                 let mut mem = guild.member(ctx, orig.author.id).await?;
-
-                match &role_opts {
-                    RoleOpt::Join { .. } => {
-                        mem.add_role(ctx, vrole.into_inner()).await?;
-                    }
-                    _ => {
-                        mem.remove_role(ctx, vrole.into_inner()).await?;
-                    }
-                }
+                let joining = matches!(role_opts, RoleOpt::Join { .. });
+                set_joined(dis, ctx, &join, &mut mem, &joinable, joining).await?;
             }
             RoleOpt::ListJoinable => {
                 let roles = join.joinable_roles().await?;
-                let roles: Vec<_> = futures::stream::iter(roles.into_iter())
-                    .then(|r| async move { r.to_role_name_or_id(ctx, gid).await })
-                    .collect()
-                    .await;
+                if roles.is_empty() {
+                    orig.reply(ctx, "No joinable roles.").await?;
+                    return Ok(());
+                }
 
-                let message = if roles.is_empty() {
-                    "No joinable roles.".to_string()
+                let groups = join.groups().await?;
+                let footer = if groups.is_empty() {
+                    None
                 } else {
-                    roles.join(", ")
+                    let groups = groups
+                        .iter()
+                        .map(|g| format!("{} (up to {})", g.name, g.max_roles))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    Some(format!("Groups: {}", groups))
                 };
 
-                let msg = MessageBuilder::new().push_codeblock_safe(message, None).build();
-                orig.reply(ctx, msg).await?;
+                let pages = joinable_role_pages(roles);
+                let page_count = pages.len();
+                for (i, fields) in pages.into_iter().enumerate() {
+                    let title = if page_count > 1 {
+                        format!("Joinable Roles ({}/{})", i + 1, page_count)
+                    } else {
+                        "Joinable Roles".to_string()
+                    };
+                    orig.channel_id
+                        .send_message(ctx, |m| {
+                            m.embed(|e| {
+                                e.color(GLIM_COLOR).title(title).fields(fields);
+                                if let (Some(footer), true) = (&footer, i + 1 == page_count) {
+                                    e.footer(|f| f.text(footer));
+                                }
+                                e
+                            });
+                            if i == 0 {
+                                m.reference_message(orig);
+                            }
+                            m
+                        })
+                        .await?;
+                }
                 return Ok(());
             }
         };
 
-        orig.react(ctx, '✅').await?;
+        react_success(dis, ctx, orig).await?;
         Ok(())
     }
+
+    async fn on_component(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        interaction: &MessageComponentInteraction,
+    ) -> crate::error::Result<()> {
+        if !interaction.data.custom_id.starts_with(ROLE_MENU_PREFIX) {
+            return Ok(());
+        }
+        let gid = interaction.guild_id.ok_or(GuildNotInCache)?;
+
+        let res = toggle_from_menu(dis, ctx, gid, interaction).await;
+        let reply = match &res {
+            Ok(r) => r.clone(),
+            Err(e) if e.is_user_error() => e.to_string(),
+            Err(_) => "Something went wrong; try again later.".to_string(),
+        };
+        crate::util::reply_ephemeral(ctx, interaction, reply).await?;
+        res.map(|_| ())
+    }
+}
+
+/// Joins or leaves each of the roles picked from a role menu, depending on whether the member
+/// already has them, and describes what changed.
+async fn toggle_from_menu(
+    dis: &Dispatch,
+    ctx: &Context,
+    gid: GuildId,
+    interaction: &MessageComponentInteraction,
+) -> crate::error::Result<String> {
+    let join = JoinableRoles::new(DbContext::new(dis, gid));
+    let mut mem = gid.member(ctx, interaction.user.id).await?;
+
+    let mut joined = Vec::new();
+    let mut left = Vec::new();
+    for value in &interaction.data.values {
+        let role = value.parse::<u64>().map(RoleId).map_err(|_| RoleNotSelfAssignable)?;
+        let vrole = VerifiedRole::from_str_with_ctx(&role.to_string(), ctx, gid).await?;
+        let full_role = role.to_role_cached(ctx).await.ok_or(RoleNotInCache)?;
+        ensure_authorized_for_role(ctx, &mem, &full_role).await?;
+        let joinable = join.joinable(vrole).await?.ok_or(RoleNotSelfAssignable)?;
+
+        let joining = !mem.roles.contains(&role);
+        set_joined(dis, ctx, &join, &mut mem, &joinable, joining).await?;
+        if joining {
+            joined.push(role.mention().to_string());
+        } else {
+            left.push(role.mention().to_string());
+        }
+    }
+
+    let mut reply = Vec::new();
+    if !joined.is_empty() {
+        reply.push(format!("Joined {}.", joined.join(", ")));
+    }
+    if !left.is_empty() {
+        reply.push(format!("Left {}.", left.join(", ")));
+    }
+    Ok(reply.join(" "))
+}
+
+/// Gives a member a joinable role, or takes it away if `joining` is false. Joining makes room in
+/// the role's group first and schedules the role's expiry; either way, any expiry already pending
+/// for the role is cancelled, so joining again restarts it rather than adding another.
+async fn set_joined(
+    dis: &Dispatch,
+    ctx: &Context,
+    join: &JoinableRoles<'_>,
+    mem: &mut Member,
+    joinable: &JoinableRole,
+    joining: bool,
+) -> crate::error::Result<()> {
+    let role = joinable.role;
+    let timed = TimedEvents::new(dis.db(mem.guild_id));
+    timed
+        .cancel_actions_for(mem.user.id, ActionKind::ExpireJoinedRole { role })
+        .await?;
+
+    if !joining {
+        mem.remove_role(ctx, role).await?;
+        return Ok(());
+    }
+
+    if let (Some(group), false) = (&joinable.group, mem.roles.contains(&role)) {
+        make_room_in_group(dis, ctx, join, mem, group).await?;
+    }
+    mem.add_role(ctx, role).await?;
+    if let Some(d) = joinable.expires_after {
+        let duration = chrono::Duration::from_std(d).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+        Action::expire_joined_role(mem.user.id, mem.guild_id, role, duration)
+            .store_action(dis)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Posts select menus listing the guild's joinable roles, grouped by category, which members can
+/// pick roles from to join or leave them. Roles are split across as many menus and messages as
+/// Discord's limits need.
+async fn post_role_menus(
+    ctx: &Context,
+    orig: &Message,
+    gid: GuildId,
+    join: &JoinableRoles<'_>,
+) -> crate::error::Result<()> {
+    let mut roles = join.joinable_roles().await?;
+    roles.sort_by_key(|r| (r.category.is_none(), r.category.as_ref().map(|c| c.to_lowercase())));
+
+    let names: HashMap<RoleId, String> = ctx
+        .cache
+        .guild_field(gid, |g| g.roles.iter().map(|(id, r)| (*id, r.name.clone())).collect())
+        .await
+        .ok_or(GuildNotInCache)?;
+    // Roles deleted from the server can't be joined, so they're left out.
+    let roles: Vec<(JoinableRole, &String)> = roles
+        .into_iter()
+        .filter_map(|r| names.get(&r.role).map(|n| (r, n)))
+        .collect();
+    if roles.is_empty() {
+        return Err(NoJoinableRoles.into());
+    }
+
+    let menus: Vec<_> = roles.chunks(MENU_OPTIONS).collect();
+    let menu_count = menus.len();
+    for (i, batch) in menus.chunks(MENUS_PER_MESSAGE).enumerate() {
+        orig.channel_id
+            .send_message(ctx, |m| {
+                if i == 0 {
+                    m.content("Pick roles to join them; pick a role you already have to leave it.");
+                }
+                m.components(|c| {
+                    for (j, menu) in batch.iter().enumerate() {
+                        let n = i * MENUS_PER_MESSAGE + j;
+                        c.create_action_row(|row| {
+                            row.create_select_menu(|s| {
+                                let placeholder = if menu_count > 1 {
+                                    format!("Joinable roles ({}/{})", n + 1, menu_count)
+                                } else {
+                                    "Joinable roles".to_string()
+                                };
+                                s.custom_id(format!("{}{}", ROLE_MENU_PREFIX, n))
+                                    .placeholder(placeholder)
+                                    .min_values(1)
+                                    .max_values(menu.len() as u64)
+                                    .options(|o| {
+                                        for (role, name) in menu.iter() {
+                                            o.create_option(|opt| {
+                                                opt.label(name).value(role.role);
+                                                if let Some(d) = &role.description {
+                                                    opt.description(d);
+                                                }
+                                                opt
+                                            });
+                                        }
+                                        o
+                                    })
+                            })
+                        });
+                    }
+                    c
+                })
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Removes roles in a group from a member, so they can join another without holding more than the
+/// group allows. The roles with the lowest IDs, which are usually the oldest, are removed first.
+async fn make_room_in_group(
+    dis: &Dispatch,
+    ctx: &Context,
+    join: &JoinableRoles<'_>,
+    mem: &mut Member,
+    group: &str,
+) -> crate::error::Result<()> {
+    let group = join.group(group).await?;
+    let held: Vec<RoleId> = group.roles.iter().filter(|r| mem.roles.contains(r)).copied().collect();
+    let excess = (held.len() + 1).saturating_sub(group.max_roles as usize);
+    if excess == 0 {
+        return Ok(());
+    }
+
+    let swapped = &held[..excess];
+    mem.remove_roles(ctx, swapped).await?;
+    let timed = TimedEvents::new(dis.db(mem.guild_id));
+    for role in swapped {
+        timed
+            .cancel_actions_for(mem.user.id, ActionKind::ExpireJoinedRole { role: *role })
+            .await?;
+    }
+    Ok(())
 }
 
 /// Represents the `mod-role` command.
@@ -235,6 +580,13 @@ enum ModRoleOpt {
     AddJoinable {
         /// The role to make joinable.
         role: String,
+        /// Removes the role from members this long after they join it, i.e. "24h". The role
+        /// never expires if omitted.
+        #[structopt(short, long)]
+        expires_after: Option<humantime::Duration>,
+        /// Puts the role in a role group.
+        #[structopt(short, long)]
+        group: Option<String>,
     },
     /// Removes a role from the joinable list.
     DelJoinable {
@@ -247,6 +599,10 @@ enum ModRoleOpt {
         role: String,
         /// The user to assign/unassign a role to.
         user: String,
+        /// Removes the role from the user after this long, i.e. "7d". The role is kept until
+        /// unassigned if omitted.
+        #[structopt(long = "for")]
+        duration: Option<humantime::Duration>,
     },
     /// Unassign a role to a user.
     Unassign {
@@ -255,32 +611,81 @@ enum ModRoleOpt {
         /// The user to assign/unassign a role to.
         user: String,
     },
+    /// Sets the description shown for a joinable role, or removes it if none is given.
+    Describe {
+        /// The joinable role.
+        role: String,
+        /// The description, up to 100 characters.
+        description: Vec<String>,
+    },
+    /// Lists a joinable role under a category, or takes it out of its category if none is given.
+    Categorize {
+        /// The joinable role.
+        role: String,
+        /// The category, up to 32 characters.
+        category: Option<String>,
+    },
+    /// Manages groups of joinable roles, of which members may only hold a limited number at once.
+    Group(GroupOpt),
+    /// Posts menus in this channel which members can pick joinable roles from, instead of using
+    /// `role join` and `role leave`. Post them again after changing the joinable roles.
+    Menu,
+}
+
+/// Subcommands for managing role groups.
+#[derive(StructOpt)]
+#[structopt(no_version)]
+enum GroupOpt {
+    /// Creates a role group. Joining a role in a full group swaps out a role the member holds.
+    Create {
+        /// The group's name.
+        name: String,
+        /// The most roles in the group a member may hold at once.
+        #[structopt(short, long, default_value = "1")]
+        max: ConstrainedU64<1, 128>,
+    },
+    /// Deletes a role group. Its roles stay joinable.
+    Delete {
+        /// The group's name.
+        name: String,
+    },
+    /// Puts a joinable role in a group, or takes it out of its group if no group is given.
+    Set {
+        /// The joinable role.
+        role: String,
+        /// The group to put it in.
+        group: Option<String>,
+    },
 }
 
 impl ModRoleOpt {
     /// Extracts the role string from the arguments
-    pub fn extract_role(&self) -> &str {
+    pub fn extract_role(&self) -> Option<&str> {
         match self {
-            ModRoleOpt::AddJoinable { role, .. } => role.as_str(),
-            ModRoleOpt::DelJoinable { role, .. } => role.as_str(),
-            ModRoleOpt::Assign { role, .. } => role.as_str(),
-            ModRoleOpt::Unassign { role, .. } => role.as_str(),
+            ModRoleOpt::AddJoinable { role, .. } => Some(role.as_str()),
+            ModRoleOpt::DelJoinable { role, .. } => Some(role.as_str()),
+            ModRoleOpt::Assign { role, .. } => Some(role.as_str()),
+            ModRoleOpt::Unassign { role, .. } => Some(role.as_str()),
+            ModRoleOpt::Describe { role, .. } => Some(role.as_str()),
+            ModRoleOpt::Categorize { role, .. } => Some(role.as_str()),
+            ModRoleOpt::Group(GroupOpt::Set { role, .. }) => Some(role.as_str()),
+            ModRoleOpt::Group(_) | ModRoleOpt::Menu => None,
         }
     }
 
     /// Extracts the user string from the arguments
     pub fn extract_user(&self) -> Option<&str> {
         match self {
-            ModRoleOpt::AddJoinable { .. } | ModRoleOpt::DelJoinable { .. } => None,
+            ModRoleOpt::AddJoinable { .. }
+            | ModRoleOpt::DelJoinable { .. }
+            | ModRoleOpt::Describe { .. }
+            | ModRoleOpt::Categorize { .. }
+            | ModRoleOpt::Group(_)
+            | ModRoleOpt::Menu => None,
             ModRoleOpt::Assign { user, .. } => Some(user.as_ref()),
             ModRoleOpt::Unassign { user, .. } => Some(user.as_ref()),
         }
     }
-
-    /// Returns true if this is an assign variant.
-    pub fn is_assign(&self) -> bool {
-        matches!(self, ModRoleOpt::Assign { .. })
-    }
 }
 
 #[async_trait::async_trait]
@@ -307,7 +712,22 @@ impl Module for ModRoleModule {
     ) -> crate::error::Result<()> {
         let opts = ModRoleOpt::from_iter_with_help(command)?;
         let gid = orig.guild_id.unwrap();
-        let role = VerifiedRole::from_str_with_ctx(opts.extract_role(), ctx, gid).await?;
+        let db = DbContext::new(dis, gid);
+        let join = JoinableRoles::new(db);
+
+        let role = match opts.extract_role() {
+            Some(r) => VerifiedRole::from_str_with_ctx(r, ctx, gid).await?,
+            None => {
+                match opts {
+                    ModRoleOpt::Group(GroupOpt::Create { name, max }) => join.create_group(&name, max.into()).await?,
+                    ModRoleOpt::Group(GroupOpt::Delete { name }) => join.delete_group(&name).await?,
+                    ModRoleOpt::Menu => post_role_menus(ctx, orig, gid, &join).await?,
+                    _ => unreachable!("only role menus and creating and deleting role groups don't involve a role"),
+                }
+                react_success(dis, ctx, orig).await?;
+                return Ok(());
+            }
+        };
 
         let full_role = role.into_inner().to_role_cached(ctx).await.ok_or(RoleNotInCache)?;
 
@@ -315,8 +735,6 @@ impl Module for ModRoleModule {
 
         ensure_authorized_for_role(ctx, &auth_mem, &full_role).await?;
 
-        let db = DbContext::new(dis, gid);
-        let join = JoinableRoles::new(db);
         let user = futures::stream::iter(opts.extract_user())
             .then(|s| VerifiedUser::from_str_with_ctx(s, ctx, gid))
             .next()
@@ -324,14 +742,35 @@ impl Module for ModRoleModule {
             .transpose()?;
 
         match opts {
-            ModRoleOpt::AddJoinable { .. } => {
-                join.add_joinable_role(role).await?;
+            ModRoleOpt::AddJoinable {
+                expires_after, group, ..
+            } => {
+                // Check the group exists first, so a typo doesn't leave the role joinable outside it.
+                if let Some(group) = &group {
+                    join.group(&group_name(group)?).await?;
+                }
+                join.add_joinable_role(role, expires_after.map(Into::into)).await?;
+                join.set_group(role, group.as_deref()).await?;
                 "Set role to joinable."
             }
             ModRoleOpt::DelJoinable { .. } => {
                 join.del_joinable_role(role).await?;
                 "Role is/was no longer joinable."
             }
+            ModRoleOpt::Describe { description, .. } => {
+                let description = description.join(" ");
+                let description = Some(description.as_str()).filter(|d| !d.is_empty());
+                join.set_description(role, description).await?;
+                "Set the role's description."
+            }
+            ModRoleOpt::Categorize { category, .. } => {
+                join.set_category(role, category.as_deref()).await?;
+                "Set the role's category."
+            }
+            ModRoleOpt::Group(GroupOpt::Set { group, .. }) => {
+                join.set_group(role, group.as_deref()).await?;
+                "Set the role's group."
+            }
             _ => {
                 let user = user.unwrap();
                 let mut member = gid
@@ -342,17 +781,173 @@ impl Module for ModRoleModule {
                     .await
                     .map_err(|_| NoSuchUser)?;
 
-                if opts.is_assign() {
-                    member.add_role(ctx, role.into_inner()).await?;
-                    "Added role to user."
-                } else {
-                    member.remove_role(ctx, role.into_inner()).await?;
-                    "Removed role from user if they had it."
+                // Assigning or unassigning the role replaces any expiry it had.
+                let kind = ActionKind::RemoveRole {
+                    role: role.into_inner(),
+                };
+                TimedEvents::new(dis.db(gid))
+                    .cancel_actions_for(member.user.id, kind)
+                    .await?;
+
+                match opts {
+                    ModRoleOpt::Assign { duration, .. } => {
+                        member.add_role(ctx, role.into_inner()).await?;
+                        if let Some(duration) = duration {
+                            let duration =
+                                chrono::Duration::from_std(*duration).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+                            Action::remove_role(member.user.id, gid, role.into_inner(), duration)
+                                .store_action(dis)
+                                .await?;
+                        }
+                        "Added role to user."
+                    }
+                    _ => {
+                        member.remove_role(ctx, role.into_inner()).await?;
+                        "Removed role from user if they had it."
+                    }
                 }
             }
         };
 
-        orig.react(ctx, '✅').await?;
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+}
+
+/// Adds the `temprole` command.
+pub struct TempRoleModule;
+
+/// Command to give a user a role which is automatically removed after a while.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "temprole", no_version)]
+pub struct TempRoleOpt {
+    /// The user to assign the role to.
+    user: String,
+    /// The role to assign.
+    role: String,
+    /// How long the user should keep the role. Specified in human format, i.e. "5d 2h 5m".
+    /// Max 100 years, min 1 minute.
+    duration: humantime::Duration,
+}
+
+#[async_trait::async_trait]
+impl Module for TempRoleModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "temprole",
+                "allows moderators to assign a role to a user which is removed automatically after a set duration.",
+            )
+            .with_command(true)
+            .with_sensitivity(Sensitivity::High)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = TempRoleOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let role = VerifiedRole::from_str_with_ctx(&opts.role, ctx, gid).await?;
+        let full_role = role.into_inner().to_role_cached(ctx).await.ok_or(RoleNotInCache)?;
+
+        let auth_mem = orig.member(ctx).await?;
+        ensure_authorized_for_role(ctx, &auth_mem, &full_role).await?;
+
+        let user = VerifiedUser::from_str_with_ctx(&opts.user, ctx, gid)
+            .await?
+            .into_inner();
+        let mut member = gid.member(ctx, user).await.map_err(|_| NoSuchUser)?;
+        member.add_role(ctx, role.into_inner()).await?;
+
+        // Assigning the role again replaces its expiry rather than adding another.
+        let kind = ActionKind::RemoveRole {
+            role: role.into_inner(),
+        };
+        TimedEvents::new(dis.db(gid)).cancel_actions_for(user, kind).await?;
+        let duration = chrono::Duration::from_std(*opts.duration).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+        Action::remove_role(user, gid, role.into_inner(), duration)
+            .store_action(dis)
+            .await?;
+
+        react_success(dis, ctx, orig).await?;
         Ok(())
     }
 }
+
+/// The most bytes put in one embed field's value, which Discord limits to 1024 characters.
+const EMBED_FIELD_LEN: usize = 1024;
+/// The most fields Discord allows in one embed.
+const EMBED_FIELDS: usize = 25;
+/// The most bytes of fields put in one embed, leaving room under Discord's limit of 6000
+/// characters for the title and footer.
+const EMBED_LEN: usize = 5000;
+
+/// Describes a joinable role on a single line.
+fn joinable_role_line(role: &JoinableRole) -> String {
+    let mut line = role.role.mention().to_string();
+    if let Some(d) = &role.description {
+        line.push_str(&format!(" — {}", d));
+    }
+    if let Some(d) = role.expires_after {
+        line.push_str(&format!(" *(expires after {})*", humantime::format_duration(d)));
+    }
+    if let Some(g) = &role.group {
+        line.push_str(&format!(" [{}]", g));
+    }
+    line
+}
+
+/// Lays out joinable roles as embed fields, one per category, split into pages which each fit in
+/// an embed. Categories too long for one field are continued in another. Roles without a category
+/// are listed last.
+fn joinable_role_pages(mut roles: Vec<JoinableRole>) -> Vec<Vec<(String, String, bool)>> {
+    let has_categories = roles.iter().any(|r| r.category.is_some());
+    // The sort is stable, so roles stay ordered by ID within their category.
+    roles.sort_by_key(|r| (r.category.is_none(), r.category.as_ref().map(|c| c.to_lowercase())));
+
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut current: Option<(String, String)> = None;
+    for role in &roles {
+        let name = match &role.category {
+            Some(c) => c.clone(),
+            None if has_categories => "Other".to_string(),
+            None => "Roles".to_string(),
+        };
+        let line = joinable_role_line(role);
+        match &mut current {
+            Some((n, v)) if n.to_lowercase() == name.to_lowercase() && v.len() + line.len() < EMBED_FIELD_LEN => {
+                v.push('\n');
+                v.push_str(&line);
+            }
+            _ => {
+                fields.extend(current.take());
+                current = Some((name, line));
+            }
+        }
+    }
+    fields.extend(current);
+
+    let mut pages = Vec::new();
+    let mut page = Vec::new();
+    let mut len = 0;
+    for (name, value) in fields {
+        let field_len = name.len() + value.len();
+        if !page.is_empty() && (page.len() == EMBED_FIELDS || len + field_len > EMBED_LEN) {
+            pages.push(std::mem::take(&mut page));
+            len = 0;
+        }
+        len += field_len;
+        page.push((name, value, false));
+    }
+    if !page.is_empty() {
+        pages.push(page);
+    }
+    pages
+}
@@ -0,0 +1,440 @@
+//! Contains spam scoring, which lets a guild weigh everything its filters catch in a message
+//! together rather than have each filter act on its own. While scoring is on, each filter that
+//! catches a message adds its score to the message's tally instead of acting, and once every filter
+//! has had its say, the total is mapped to an action by the guild's score bands. A message caught by
+//! only a mild filter might just be noted in the mod log, while one caught by several is removed and
+//! its author muted.
+//!
+//! Filters which check messages in the background, like the invite and image filters, hold the
+//! tally open until they're done, so their scores count too.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{GuildId, MessageId};
+
+use crate::dispatch::config;
+use crate::dispatch::jobs::{Job, JobPriority, OverloadPolicy};
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::filter::{act_on_filtered, FilterAction};
+use crate::module::moderation::mod_log_channel;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing a [`SpamScoringConfig`].
+pub const SPAM_SCORING_KEY: &str = "spam_scoring";
+/// How long a message's tally is kept waiting for background checks before it's dropped. Checks
+/// are only this slow if the job queue is overloaded, in which case they may never run at all.
+pub const TALLY_TTL: Duration = Duration::from_secs(120);
+
+/// What happens to a message whose score falls in a band.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ScoreAction {
+    /// The message is left alone, but noted in the mod log.
+    Log,
+    /// The message is removed.
+    Delete,
+    /// The message is removed and its author warned.
+    Warn,
+    /// The message is removed and its author muted.
+    Mute,
+    /// The message is removed and its author kicked.
+    Kick,
+    /// The message is removed and its author banned.
+    Ban,
+}
+
+impl ScoreAction {
+    /// The filter action taken, if the message is removed.
+    fn filter_action(&self) -> Option<FilterAction> {
+        match self {
+            ScoreAction::Log => None,
+            ScoreAction::Delete => Some(FilterAction::Delete),
+            ScoreAction::Warn => Some(FilterAction::Warn),
+            ScoreAction::Mute => Some(FilterAction::Mute),
+            ScoreAction::Kick => Some(FilterAction::Kick),
+            ScoreAction::Ban => Some(FilterAction::Ban),
+        }
+    }
+}
+
+/// A range of scores, from `min` up to the next band, and what happens to messages in it.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct ScoreBand {
+    /// The lowest score in the band.
+    pub min: u32,
+    /// What happens to messages in the band.
+    pub action: ScoreAction,
+}
+
+/// Configuration for spam scoring.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SpamScoringConfig {
+    /// Whether spam scoring is on. While it's off, each filter acts on its own.
+    #[serde(default)]
+    pub enabled: bool,
+    /// The score each filter gives a message it catches, by filter name.
+    #[serde(default = "default_scores")]
+    pub scores: BTreeMap<String, u32>,
+    /// The score given by filters missing from `scores`.
+    #[serde(default = "default_score")]
+    pub default_score: u32,
+    /// The score bands. Messages scoring below every band are left alone.
+    #[serde(default = "default_bands")]
+    pub bands: Vec<ScoreBand>,
+}
+
+#[doc(hidden)]
+fn default_scores() -> BTreeMap<String, u32> {
+    vec![
+        ("filter", 50),
+        ("regex-filter", 50),
+        ("invite-filter", 40),
+        ("duplicate-filter", 30),
+        ("emoji-filter", 30),
+        ("wall-of-text-filter", 20),
+        ("attachment-filter", 60),
+        ("image-filter", 80),
+        ("phishing-filter", 100),
+    ]
+    .into_iter()
+    .map(|(f, s)| (f.to_string(), s))
+    .collect()
+}
+
+#[doc(hidden)]
+fn default_score() -> u32 {
+    50
+}
+
+#[doc(hidden)]
+fn default_bands() -> Vec<ScoreBand> {
+    vec![
+        ScoreBand {
+            min: 30,
+            action: ScoreAction::Log,
+        },
+        ScoreBand {
+            min: 50,
+            action: ScoreAction::Delete,
+        },
+        ScoreBand {
+            min: 100,
+            action: ScoreAction::Mute,
+        },
+        ScoreBand {
+            min: 150,
+            action: ScoreAction::Ban,
+        },
+    ]
+}
+
+impl Default for SpamScoringConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            scores: default_scores(),
+            default_score: default_score(),
+            bands: default_bands(),
+        }
+    }
+}
+
+impl FromStr for SpamScoringConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for SpamScoringConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+impl SpamScoringConfig {
+    /// The score a filter gives a message it catches.
+    fn score_of(&self, filter: &str) -> u32 {
+        self.scores.get(filter).copied().unwrap_or(self.default_score)
+    }
+
+    /// Totals the scores of the filters which caught a message. Each filter counts once, however
+    /// many times it caught the message.
+    fn total(&self, hits: &[FilterHit]) -> u32 {
+        hits.iter()
+            .map(|h| h.filter)
+            .unique()
+            .map(|f| self.score_of(f))
+            .fold(0u32, u32::saturating_add)
+    }
+
+    /// Finds the action for a score: that of the highest band the score reaches.
+    fn action_for(&self, score: u32) -> Option<ScoreAction> {
+        self.bands
+            .iter()
+            .filter(|b| b.min <= score)
+            .max_by_key(|b| b.min)
+            .map(|b| b.action)
+    }
+}
+
+/// Something a filter caught in a message.
+#[derive(Clone, Debug)]
+pub struct FilterHit {
+    /// The name of the filter.
+    pub filter: &'static str,
+    /// Why the filter caught the message. Completes the sentence "The message ...".
+    pub why: String,
+}
+
+/// The hits on a message so far.
+#[derive(Debug)]
+struct Tally {
+    /// The hits so far.
+    hits: Vec<FilterHit>,
+    /// The number of background checks still running on the message.
+    pending: usize,
+    /// Whether every message hook has run on the message.
+    hooks_done: bool,
+    /// When the tally was started.
+    started: Instant,
+}
+
+impl Default for Tally {
+    fn default() -> Self {
+        Tally {
+            hits: Vec::new(),
+            pending: 0,
+            hooks_done: false,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Tally {
+    /// Whether every filter has had its say.
+    fn is_settled(&self) -> bool {
+        self.hooks_done && self.pending == 0
+    }
+}
+
+/// The tallies of messages being scored.
+#[derive(Debug, Default)]
+pub struct ScoreBoard {
+    /// The tallies, by message.
+    tallies: Mutex<HashMap<MessageId, Tally>>,
+}
+
+impl ScoreBoard {
+    /// Runs a function on a message's tally, creating it if needed. If the tally is settled
+    /// afterwards, it's removed and its hits returned.
+    fn with_tally(&self, msg: MessageId, f: impl FnOnce(&mut Tally)) -> Option<Vec<FilterHit>> {
+        let mut tallies = self.tallies.lock();
+        if !tallies.contains_key(&msg) {
+            tallies.retain(|_, t| t.started.elapsed() < TALLY_TTL);
+        }
+        let tally = tallies.entry(msg).or_default();
+        f(tally);
+        if !tally.is_settled() {
+            return None;
+        }
+        tallies.remove(&msg).map(|t| t.hits).filter(|h| !h.is_empty())
+    }
+
+    /// Adds a hit to a message's tally.
+    pub fn record(&self, msg: MessageId, hit: FilterHit) -> Option<Vec<FilterHit>> {
+        self.with_tally(msg, |t| t.hits.push(hit))
+    }
+
+    /// Holds a message's tally open until a background check on it finishes.
+    pub fn defer(&self, msg: MessageId) {
+        self.with_tally(msg, |t| t.pending += 1);
+    }
+
+    /// Records that a background check on a message finished, with its hit if it caught anything.
+    /// Returns the message's hits if it's settled.
+    pub fn resolve(&self, msg: MessageId, hit: Option<FilterHit>) -> Option<Vec<FilterHit>> {
+        self.with_tally(msg, |t| {
+            t.pending = t.pending.saturating_sub(1);
+            t.hits.extend(hit);
+        })
+    }
+
+    /// Records that every message hook has run on a message. Returns the message's hits if it's
+    /// settled.
+    pub fn finish_hooks(&self, msg: MessageId) -> Option<Vec<FilterHit>> {
+        let mut tallies = self.tallies.lock();
+        let tally = tallies.get_mut(&msg)?;
+        tally.hooks_done = true;
+        if !tally.is_settled() {
+            return None;
+        }
+        tallies.remove(&msg).map(|t| t.hits).filter(|h| !h.is_empty())
+    }
+}
+
+/// Retrieves the guild's scoring configuration, if scoring is on.
+async fn scoring_config(dis: &Dispatch, gid: GuildId) -> crate::error::Result<Option<Arc<SpamScoringConfig>>> {
+    let conf = dis
+        .config_value_t::<SpamScoringConfig>(SPAM_SCORING_KEY)?
+        .get_or_default(&dis.db(gid))
+        .await?;
+    Ok(Some(conf).filter(|c| c.enabled))
+}
+
+/// Adds a filter's hit to a message's tally, if the guild uses spam scoring. Returns false if it
+/// doesn't, in which case the filter should act on its own.
+pub async fn score_hit(dis: &Dispatch, orig: &Message, hit: FilterHit) -> crate::error::Result<bool> {
+    let gid = orig.guild_id.unwrap();
+    if scoring_config(dis, gid).await?.is_none() {
+        return Ok(false);
+    }
+    trace!("{} scored the message", hit.filter);
+    if let Some(hits) = dis.score_board().record(orig.id, hit) {
+        settle(dis, orig, hits).await?;
+    }
+    Ok(true)
+}
+
+/// Holds a message's tally open while a filter checks it in the background, if the guild uses spam
+/// scoring. Returns whether it does; if so, the check must finish with [`resolve_check`].
+pub async fn defer_check(dis: &Dispatch, orig: &Message) -> crate::error::Result<bool> {
+    let gid = orig.guild_id.unwrap();
+    if scoring_config(dis, gid).await?.is_none() {
+        return Ok(false);
+    }
+    dis.score_board().defer(orig.id);
+    Ok(true)
+}
+
+/// Finishes a background check started with [`defer_check`], with its hit if it caught anything.
+pub async fn resolve_check(dis: &Dispatch, orig: &Message, hit: Option<FilterHit>) -> crate::error::Result<()> {
+    match dis.score_board().resolve(orig.id, hit) {
+        Some(hits) => settle(dis, orig, hits).await,
+        None => Ok(()),
+    }
+}
+
+/// Called once every message hook has run on a message, settling its tally if no background checks
+/// are still running.
+pub async fn finish_hooks(dis: &Dispatch, orig: &Message) -> crate::error::Result<()> {
+    match dis.score_board().finish_hooks(orig.id) {
+        Some(hits) => settle(dis, orig, hits).await,
+        None => Ok(()),
+    }
+}
+
+/// Totals a settled message's score and queues whatever its band calls for.
+async fn settle(dis: &Dispatch, orig: &Message, hits: Vec<FilterHit>) -> crate::error::Result<()> {
+    let gid = orig.guild_id.unwrap();
+    let conf = dis
+        .config_value_t::<SpamScoringConfig>(SPAM_SCORING_KEY)?
+        .get_or_default(&dis.db(gid))
+        .await?;
+    let score = conf.total(&hits);
+    let action = match conf.action_for(score) {
+        Some(a) => a,
+        None => {
+            debug!("message scored {}, below every band", score);
+            return Ok(());
+        }
+    };
+
+    debug!("message scored {}: {:?}", score, action);
+    dis.jobs().enqueue(
+        gid,
+        ScoredMessage {
+            message: orig.clone(),
+            hits,
+            score,
+            action,
+        },
+    );
+    Ok(())
+}
+
+/// A message whose score reached a band, dealt with as a background job.
+struct ScoredMessage {
+    /// The scored message.
+    message: Message,
+    /// What the filters caught.
+    hits: Vec<FilterHit>,
+    /// The message's score.
+    score: u32,
+    /// What the message's band calls for.
+    action: ScoreAction,
+}
+
+#[async_trait::async_trait]
+impl Job for ScoredMessage {
+    fn name(&self) -> &'static str {
+        "scored-message"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::High
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let reasons = self.hits.iter().map(|h| h.why.as_str()).join("; ");
+        let why = format!("scored {} on the filters: it {}", self.score, reasons);
+
+        match self.action.filter_action() {
+            Some(a) => act_on_filtered(dis, ctx, &self.message, &why, a).await,
+            None => {
+                let gid = self.message.guild_id.unwrap();
+                if let Ok(chan) = mod_log_channel(dis, gid).await {
+                    let note = format!(
+                        "A message from {} ({}) in <#{}> {}. Left it alone. {}",
+                        self.message.author.tag(),
+                        self.message.author.id,
+                        self.message.channel_id,
+                        why,
+                        self.message.link()
+                    );
+                    chan.say(ctx, note).await.map_err(crate::error::Error::from).log_error();
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Module holding the spam scoring configuration. The scoring itself is done by the filters.
+pub struct SpamScoringModule;
+
+#[async_trait::async_trait]
+impl Module for SpamScoringModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "spam-scoring",
+                "weighs everything the filters catch in a message together, and acts on the total.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_config_value(config::Value::<SpamScoringConfig>::with_default(
+                SPAM_SCORING_KEY,
+                "A JSON object configuring spam scoring, e.g. {\"enabled\": true, \"scores\": {\"filter\": 50, \"invite-filter\": 40}, \"default_score\": 50, \"bands\": [{\"min\": 30, \"action\": \"Log\"}, {\"min\": 50, \"action\": \"Delete\"}, {\"min\": 100, \"action\": \"Mute\"}]}. Actions may be Log, Delete, Warn, Mute, Kick or Ban.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+}
@@ -0,0 +1,194 @@
+//! Contains the `selftest` command, which exercises Glimbot's critical paths end-to-end so an
+//! operator can quickly confirm a deployment works: config storage, timed events, the mod log,
+//! reactions, and role changes.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, RoleId};
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::db::timed::{Action, ActionKind, ONE_MINUTE};
+use crate::dispatch::config::{FromStrWithCtx, Value, VerifiedChannel, VerifiedRole};
+use crate::dispatch::Dispatch;
+use crate::module::moderation::mod_log_channel;
+use crate::module::{ModInfo, Module, Sensitivity, CHECKMARK_IN_GREEN_BOX};
+use crate::util::ClapExt;
+
+/// Config key written and read back by the config check.
+pub const SELFTEST_MARKER: &str = "selftest_marker";
+
+impl_err!(
+    ConfigMismatch,
+    "The config value read back didn't match the one written.",
+    false
+);
+
+/// Runs end-to-end checks of Glimbot's critical paths and reports which passed.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "selftest", no_version)]
+pub struct SelfTestOpt {
+    /// The channel to run the checks in. Defaults to the current channel.
+    channel: Option<String>,
+    /// A role to assign to and remove from Glimbot itself. The role check is skipped if omitted.
+    #[structopt(short, long)]
+    role: Option<String>,
+}
+
+/// The outcome of a single check.
+enum Outcome {
+    /// The check passed, taking the given time.
+    Pass(Duration),
+    /// The check failed with the given error.
+    Fail(String),
+    /// The check wasn't run, for the given reason.
+    Skip(&'static str),
+}
+
+/// A named check along with its outcome.
+struct Check {
+    /// What the check exercises.
+    name: &'static str,
+    /// How the check went.
+    outcome: Outcome,
+}
+
+impl Check {
+    /// Runs a check, timing it.
+    async fn run<F>(name: &'static str, f: F) -> Self
+    where
+        F: Future<Output = crate::error::Result<()>>,
+    {
+        let start = Instant::now();
+        let outcome = match f.await {
+            Ok(()) => Outcome::Pass(start.elapsed()),
+            Err(e) => Outcome::Fail(e.to_string()),
+        };
+        Check { name, outcome }
+    }
+
+    /// Whether the check failed.
+    fn failed(&self) -> bool {
+        matches!(self.outcome, Outcome::Fail(_))
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Outcome::Pass(d) => write!(f, "PASS {} ({} ms)", self.name, d.as_millis()),
+            Outcome::Fail(e) => write!(f, "FAIL {}: {}", self.name, e),
+            Outcome::Skip(why) => write!(f, "SKIP {}: {}", self.name, why),
+        }
+    }
+}
+
+/// Writes a random marker to the guild config and reads it back.
+async fn check_config(dis: &Dispatch, guild: GuildId) -> crate::error::Result<()> {
+    let db = dis.db(guild);
+    let marker: u64 = rand::random();
+    db.insert(SELFTEST_MARKER, marker).await?;
+    let read = db.get::<_, u64>(SELFTEST_MARKER).await?;
+    if read.as_deref() != Some(&marker) {
+        return Err(ConfigMismatch.into());
+    }
+    Ok(())
+}
+
+/// Stores a debug action and then executes it immediately, which also removes it from the store.
+async fn check_timed(dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<()> {
+    let action = Action::with_duration(dis.bot().await, guild, ActionKind::Debug, *ONE_MINUTE);
+    action.store_action(dis).await?;
+    action.act(dis, ctx).await
+}
+
+/// Posts a note in the mod log.
+async fn check_mod_log(dis: &Dispatch, ctx: &Context, guild: GuildId, orig: &Message) -> crate::error::Result<()> {
+    mod_log_channel(dis, guild)
+        .await?
+        .say(ctx, format!("Self-test requested by {}.", orig.author.tag()))
+        .await?;
+    Ok(())
+}
+
+/// Assigns a role to Glimbot and removes it again.
+async fn check_role(dis: &Dispatch, ctx: &Context, guild: GuildId, role: RoleId) -> crate::error::Result<()> {
+    let bot = dis.bot().await;
+    ctx.http.add_member_role(guild.0, bot.0, role.0).await?;
+    ctx.http.remove_member_role(guild.0, bot.0, role.0).await?;
+    Ok(())
+}
+
+/// Adds the operator-only `selftest` command.
+pub struct SelfTestModule;
+
+#[async_trait::async_trait]
+impl Module for SelfTestModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("selftest", "checks that Glimbot's critical features work end-to-end.")
+                .with_sensitivity(Sensitivity::Operator)
+                .with_command(true)
+                .with_config_value(Value::<u64>::new(
+                    SELFTEST_MARKER,
+                    "Written by `selftest` to check that config values can be stored. Safe to ignore.",
+                ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let opts = SelfTestOpt::from_iter_with_help(command)?;
+        let channel: ChannelId = match &opts.channel {
+            Some(c) => VerifiedChannel::from_str_with_ctx(c, ctx, gid).await?.into_inner(),
+            None => orig.channel_id,
+        };
+        let role = match &opts.role {
+            Some(r) => Some(VerifiedRole::from_str_with_ctx(r, ctx, gid).await?.into_inner()),
+            None => None,
+        };
+
+        let mut report = channel.say(ctx, "Running self-test...").await?;
+
+        let mut checks = vec![
+            Check::run("config read/write", check_config(dis, gid)).await,
+            Check::run("timed event store/execute", check_timed(dis, ctx, gid)).await,
+            Check::run("mod log post", check_mod_log(dis, ctx, gid, orig)).await,
+            Check::run("reaction add", async {
+                report.react(ctx, CHECKMARK_IN_GREEN_BOX).await?;
+                Ok(())
+            })
+            .await,
+        ];
+        checks.push(match role {
+            Some(r) => Check::run("role assign/remove", check_role(dis, ctx, gid, r)).await,
+            None => Check {
+                name: "role assign/remove",
+                outcome: Outcome::Skip("pass --role to test"),
+            },
+        });
+
+        let failed = checks.iter().filter(|c| c.failed()).count();
+        info!("self-test finished with {} failure(s)", failed);
+
+        let mut text = checks.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n");
+        text.push_str(&format!("\n\n{} of {} checks failed.", failed, checks.len()));
+        let content = MessageBuilder::new().push_codeblock_safe(text, None).build();
+        report.edit(ctx, |m| m.content(content)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! Contains the `setup` wizard, which walks a moderator through the config values a new server
+//! should set, one prompt at a time. Each answer is validated and stored exactly as
+//! `config set` would, including the audit history.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, UserId};
+use serenity::utils::MessageBuilder;
+
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::conf::{describe_value, record_change};
+use crate::module::moderation::{MOD_CHANNEL, MUTE_ROLE};
+use crate::module::spam::SPAM_CONFIG_KEY;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config values the wizard asks for, in order. Values whose module isn't loaded are skipped.
+pub const SETUP_STEPS: &[&str] = &["command_prefix", MOD_CHANNEL, MUTE_ROLE, SPAM_CONFIG_KEY];
+/// How long the wizard waits for an answer before giving up.
+pub const SETUP_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// The longest a current value is shown in a prompt, in characters.
+const MAX_PROMPT_VALUE_LEN: usize = 400;
+
+/// A moderator's progress through the wizard.
+#[derive(Debug, Clone)]
+struct Session {
+    /// The config values left to ask for, the first being the one asked for now.
+    steps: Vec<&'static str>,
+    /// How many values there were to ask for at the start.
+    total: usize,
+    /// When the wizard last heard from the moderator.
+    last_active: Instant,
+}
+
+/// Module which runs the setup wizard.
+#[derive(Default)]
+pub struct SetupModule {
+    /// The wizards in progress, by the channel they're in and the moderator answering.
+    sessions: Arc<Mutex<HashMap<(ChannelId, UserId), Session>>>,
+}
+
+impl SetupModule {
+    /// Retrieves the session for a moderator in a channel, forgetting any which have timed out.
+    fn session(&self, channel: ChannelId, user: UserId) -> Option<Session> {
+        let mut sessions = self.sessions.lock();
+        sessions.retain(|_, s| s.last_active.elapsed() < SETUP_TIMEOUT);
+        sessions.get(&(channel, user)).cloned()
+    }
+
+    /// Asks the moderator for the first value in their session, or finishes the wizard if
+    /// there's none left.
+    async fn prompt(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        mut session: Session,
+    ) -> crate::error::Result<()> {
+        let key = if let Some(k) = session.steps.first() {
+            *k
+        } else {
+            self.sessions.lock().remove(&(orig.channel_id, orig.author.id));
+            orig.channel_id
+                .say(ctx, "Setup complete! Use `config list` to review every setting.")
+                .await?;
+            return Ok(());
+        };
+
+        let val = dis.config_value(key)?;
+        let db = dis.db(orig.guild_id.unwrap());
+        let current = match val.get_json(&db).await? {
+            Some(v) => Some(v),
+            None => val.default_json(&db).await?,
+        };
+        let step = session.total - session.steps.len() + 1;
+        let message = MessageBuilder::new()
+            .push_bold_line_safe(format!("Step {}/{}: {}", step, session.total, key))
+            .push_line_safe(val.help())
+            .push("Currently: ")
+            .push_mono_line_safe(describe_value(val, current, MAX_PROMPT_VALUE_LEN))
+            .push("Reply with a new value, ")
+            .push_mono("skip")
+            .push(" to keep it, or ")
+            .push_mono("cancel")
+            .push(" to stop.")
+            .build();
+        orig.channel_id
+            .send_message(ctx, |m| m.content(message).allowed_mentions(|am| am.empty_parse()))
+            .await?;
+
+        session.last_active = Instant::now();
+        self.sessions.lock().insert((orig.channel_id, orig.author.id), session);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for SetupModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("setup", "walks you through the settings a new server should configure.")
+                .with_sensitivity(Sensitivity::High)
+                .with_command(true)
+                .with_message_hook(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        _command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let steps: Vec<&'static str> = SETUP_STEPS
+            .iter()
+            .copied()
+            .filter(|k| dis.config_values().contains_key(k))
+            .collect();
+        let session = Session {
+            total: steps.len(),
+            steps,
+            last_active: Instant::now(),
+        };
+        self.prompt(dis, ctx, orig, session).await
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            Some(g) if !orig.author.bot => g,
+            _ => return Ok(()),
+        };
+        let mut session = match self.session(orig.channel_id, orig.author.id) {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let key = session.steps[0];
+
+        // Other commands run while the wizard waits shouldn't be taken as answers. A prefix on its
+        // own is still an answer, to the prefix step.
+        let prefix = dis
+            .config_value_t::<char>("command_prefix")?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        let answer = orig.content.trim();
+        if answer.len() > prefix.len_utf8() && answer.starts_with(*prefix) {
+            return Ok(());
+        }
+
+        match answer.to_lowercase().as_str() {
+            "cancel" => {
+                self.sessions.lock().remove(&(orig.channel_id, orig.author.id));
+                orig.reply(ctx, "Setup cancelled. Anything already set has been kept.")
+                    .await?;
+                return Ok(());
+            }
+            "skip" => {}
+            _ => {
+                let val = dis.config_value(key)?;
+                let new_val = match val.validate(ctx, gid, answer).await {
+                    Ok(v) => v,
+                    Err(e) if e.is_user_error() => {
+                        let message = MessageBuilder::new()
+                            .push_codeblock_safe(e.to_string(), None)
+                            .push("Try again, or reply ")
+                            .push_mono("skip")
+                            .push(" or ")
+                            .push_mono("cancel")
+                            .push(".")
+                            .build();
+                        orig.reply(ctx, message).await?;
+                        session.last_active = Instant::now();
+                        self.sessions.lock().insert((orig.channel_id, orig.author.id), session);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                };
+                if val.is_secret() {
+                    orig.delete(ctx).await.map_err(crate::error::Error::from).log_error();
+                }
+                let db = dis.db(gid);
+                let old_val = val.get_json(&db).await?;
+                val.insert_json(new_val.clone(), &db).await?;
+                record_change(dis, ctx, gid, orig.author.id, val, old_val, Some(new_val)).await?;
+            }
+        }
+
+        session.steps.remove(0);
+        self.prompt(dis, ctx, orig, session).await
+    }
+}
@@ -0,0 +1,523 @@
+//! Contains automatic slowmode, which watches how fast messages arrive in each channel and puts
+//! busy channels in Discord's slowmode until traffic calms down. Traffic is measured from the
+//! message cache, so it costs nothing until a channel gets busy. Slowmode Glimbot sets is recorded
+//! in the database along with the channel's previous slowmode, which is restored once the channel
+//! has been quiet for a while; if a moderator changes the slowmode in the meantime, Glimbot leaves
+//! the channel to them.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId};
+
+use crate::db::timed::Action;
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::jobs::{Job, JobPriority, OverloadPolicy};
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::moderation::mod_log_channel;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing an [`AutoSlowmodeConfig`].
+pub const AUTO_SLOWMODE_KEY: &str = "auto_slowmode";
+/// How often a channel's traffic is measured, at most.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// The most steps a guild may configure.
+pub const MAX_SLOWMODE_STEPS: usize = 10;
+/// The longest window traffic may be measured over.
+pub const MAX_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// The longest slowmode Discord allows.
+pub const MAX_SLOWMODE_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+
+impl_err!(
+    InvalidSlowmodeSteps,
+    "auto_slowmode may have at most 10 steps, each needing at least 1 message and a delay between 1s and 6h.",
+    true
+);
+impl_err!(
+    InvalidSlowmodeWindow,
+    "auto_slowmode's window must be between 1s and 10m.",
+    true
+);
+
+/// A level of traffic, and the slowmode it calls for.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SlowmodeStep {
+    /// How many messages must be sent in a channel within the window.
+    pub messages: usize,
+    /// The slowmode to apply.
+    #[serde(with = "humantime_serde")]
+    pub delay: Duration,
+}
+
+/// Configuration for automatic slowmode.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AutoSlowmodeConfig {
+    /// Whether automatic slowmode is on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// How far back messages are counted.
+    #[serde(with = "humantime_serde", default = "default_window")]
+    pub window: Duration,
+    /// The levels of traffic which call for slowmode. The step with the longest delay whose
+    /// `messages` is reached applies.
+    #[serde(default = "default_steps")]
+    pub steps: Vec<SlowmodeStep>,
+    /// How long traffic must stay below the applied step before slowmode is relaxed.
+    #[serde(with = "humantime_serde", default = "default_cooldown")]
+    pub cooldown: Duration,
+    /// The channels to watch. If empty, every channel is watched.
+    #[serde(default)]
+    pub channels: Vec<ChannelId>,
+    /// Channels which are never put in slowmode.
+    #[serde(default)]
+    pub exempt_channels: Vec<ChannelId>,
+}
+
+#[doc(hidden)]
+fn default_window() -> Duration {
+    Duration::from_secs(30)
+}
+
+#[doc(hidden)]
+fn default_steps() -> Vec<SlowmodeStep> {
+    [(20, 5), (40, 15), (80, 60)]
+        .iter()
+        .map(|&(messages, secs)| SlowmodeStep {
+            messages,
+            delay: Duration::from_secs(secs),
+        })
+        .collect()
+}
+
+#[doc(hidden)]
+fn default_cooldown() -> Duration {
+    Duration::from_secs(2 * 60)
+}
+
+impl Default for AutoSlowmodeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window: default_window(),
+            steps: default_steps(),
+            cooldown: default_cooldown(),
+            channels: Vec::new(),
+            exempt_channels: Vec::new(),
+        }
+    }
+}
+
+impl AutoSlowmodeConfig {
+    /// Checks whether a channel is watched.
+    fn watches(&self, channel: ChannelId) -> bool {
+        (self.channels.is_empty() || self.channels.contains(&channel)) && !self.exempt_channels.contains(&channel)
+    }
+
+    /// The slowmode, in seconds, called for by the given number of messages within the window.
+    fn delay_for(&self, messages: usize) -> Option<u64> {
+        self.steps
+            .iter()
+            .filter(|s| messages >= s.messages)
+            .map(|s| s.delay.as_secs())
+            .max()
+    }
+}
+
+impl FromStr for AutoSlowmodeConfig {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conf: AutoSlowmodeConfig = serde_json::from_str(s)?;
+        let valid_step = |s: &SlowmodeStep| s.messages > 0 && s.delay.as_secs() >= 1 && s.delay <= MAX_SLOWMODE_DELAY;
+        if conf.steps.len() > MAX_SLOWMODE_STEPS || !conf.steps.iter().all(valid_step) {
+            return Err(InvalidSlowmodeSteps.into());
+        }
+        if conf.window.as_secs() < 1 || conf.window > MAX_WINDOW {
+            return Err(InvalidSlowmodeWindow.into());
+        }
+        Ok(conf)
+    }
+}
+
+impl fmt::Display for AutoSlowmodeConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// A channel Glimbot has put in slowmode.
+#[derive(Copy, Clone, Debug)]
+pub struct SlowmodeState {
+    /// The channel's slowmode before Glimbot changed it, in seconds.
+    pub previous: u64,
+    /// The slowmode Glimbot set, in seconds.
+    pub applied: u64,
+    /// When traffic last called for `applied`.
+    pub last_busy: DateTime<Utc>,
+}
+
+/// Wrapper around a guild's automatic slowmodes in the database.
+pub struct AutoSlowmodes<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> AutoSlowmodes<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        AutoSlowmodes {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Retrieves a channel's automatic slowmode, if it's in one.
+    pub async fn get(&self, channel: ChannelId) -> crate::error::Result<Option<SlowmodeState>> {
+        let row = sqlx::query!(
+            "SELECT previous, applied, last_busy FROM auto_slowmodes WHERE guild = $1 AND channel = $2;",
+            self.ctx.guild_as_i64(),
+            channel.0 as i64
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+
+        Ok(row.map(|r| SlowmodeState {
+            previous: r.previous as u64,
+            applied: r.applied as u64,
+            last_busy: r.last_busy,
+        }))
+    }
+
+    /// Records the slowmode applied to a channel, and that its traffic called for it just now.
+    pub async fn set(&self, channel: ChannelId, previous: u64, applied: u64) -> crate::error::Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO auto_slowmodes (guild, channel, previous, applied)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (guild, channel) DO UPDATE
+                SET applied = EXCLUDED.applied,
+                    last_busy = NOW();
+            "#,
+            self.ctx.guild_as_i64(),
+            channel.0 as i64,
+            previous as i32,
+            applied as i32
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Records that a channel's traffic still calls for its slowmode.
+    pub async fn mark_busy(&self, channel: ChannelId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "UPDATE auto_slowmodes SET last_busy = NOW() WHERE guild = $1 AND channel = $2;",
+            self.ctx.guild_as_i64(),
+            channel.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Forgets a channel's automatic slowmode.
+    pub async fn remove(&self, channel: ChannelId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM auto_slowmodes WHERE guild = $1 AND channel = $2;",
+            self.ctx.guild_as_i64(),
+            channel.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Counts the messages sent in a channel within the window.
+fn count_messages(dis: &Dispatch, guild: GuildId, channel: ChannelId, window: Duration) -> usize {
+    let window = chrono::Duration::from_std(window).unwrap_or_else(|_| chrono::Duration::max_value());
+    let since = Utc::now().checked_sub_signed(window).unwrap_or(chrono::MIN_DATETIME);
+    dis.message_cache()
+        .get(&guild)
+        .map(|cv| {
+            cv.snapshot()
+                .iter()
+                .rev()
+                .take_while(|m| m.timestamp >= since)
+                .filter(|m| m.channel == channel)
+                .count()
+        })
+        .unwrap_or_default()
+}
+
+/// Describes a slowmode for the mod log.
+fn describe(delay: u64) -> String {
+    if delay == 0 {
+        "off".to_string()
+    } else {
+        humantime::format_duration(Duration::from_secs(delay)).to_string()
+    }
+}
+
+/// Posts a note about automatic slowmode in the guild's mod log, if one is set.
+async fn log_slowmode(dis: &Dispatch, ctx: &Context, guild: GuildId, note: &str) {
+    if let Ok(chan) = mod_log_channel(dis, guild).await {
+        chan.say(ctx, note).await.map_err(crate::error::Error::from).log_error();
+    }
+}
+
+/// Retrieves a channel's current slowmode, in seconds, or `None` if the channel is gone.
+// slow_mode_rate is only renamed in serenity 0.11.
+#[allow(deprecated)]
+async fn current_delay(ctx: &Context, channel: ChannelId) -> Option<u64> {
+    ctx.cache
+        .guild_channel(channel)
+        .await
+        .map(|c| c.slow_mode_rate.unwrap_or_default())
+}
+
+/// Sets a channel's slowmode.
+async fn set_delay(ctx: &Context, channel: ChannelId, delay: u64) -> crate::error::Result<()> {
+    channel.edit(ctx, |c| c.rate_limit_per_user(delay)).await?;
+    Ok(())
+}
+
+/// Relaxes a channel's automatic slowmode, if its traffic has calmed down. While the channel is
+/// still busy, the check is scheduled again; once traffic is below every step, the channel's
+/// previous slowmode is restored.
+pub async fn relax_slowmode(
+    dis: &Dispatch,
+    ctx: &Context,
+    guild: GuildId,
+    channel: ChannelId,
+) -> crate::error::Result<()> {
+    let db = dis.db(guild);
+    let slowmodes = AutoSlowmodes::new(&db);
+    let state = match slowmodes.get(channel).await? {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    if current_delay(ctx, channel).await != Some(state.applied) {
+        debug!("channel slowmode was changed by someone else; leaving it alone");
+        return slowmodes.remove(channel).await;
+    }
+
+    let conf = dis
+        .config_value_t::<AutoSlowmodeConfig>(AUTO_SLOWMODE_KEY)?
+        .get_or_default(&db)
+        .await?;
+    let cooldown = chrono::Duration::from_std(conf.cooldown).unwrap_or_else(|_| chrono::Duration::minutes(2));
+    let quiet_at = state.last_busy + cooldown;
+    if conf.enabled && quiet_at > Utc::now() {
+        return Action::relax_slowmode(guild, channel, quiet_at).store_action(dis).await;
+    }
+
+    let messages = count_messages(dis, guild, channel, conf.window);
+    let target = conf.delay_for(messages).filter(|d| conf.enabled && *d > state.previous);
+    match target {
+        Some(delay) if delay >= state.applied => {
+            slowmodes.mark_busy(channel).await?;
+        }
+        Some(delay) => {
+            set_delay(ctx, channel, delay).await?;
+            slowmodes.set(channel, state.previous, delay).await?;
+            log_slowmode(
+                dis,
+                ctx,
+                guild,
+                &format!(
+                    "Lowered slowmode in <#{}> to {}: {} messages in the last {}.",
+                    channel,
+                    describe(delay),
+                    messages,
+                    humantime::format_duration(conf.window)
+                ),
+            )
+            .await;
+        }
+        None => {
+            set_delay(ctx, channel, state.previous).await?;
+            slowmodes.remove(channel).await?;
+            log_slowmode(
+                dis,
+                ctx,
+                guild,
+                &format!(
+                    "Traffic in <#{}> has calmed down, so its slowmode is back to {}.",
+                    channel,
+                    describe(state.previous)
+                ),
+            )
+            .await;
+            return Ok(());
+        }
+    }
+
+    Action::relax_slowmode(guild, channel, Utc::now() + cooldown)
+        .store_action(dis)
+        .await
+}
+
+/// A busy channel whose slowmode may need raising, dealt with as a background job since it takes
+/// REST calls.
+struct BusyChannel {
+    /// The guild the channel is in.
+    guild: GuildId,
+    /// The busy channel.
+    channel: ChannelId,
+    /// The slowmode its traffic calls for, in seconds.
+    delay: u64,
+    /// How many messages were sent within the window.
+    messages: usize,
+    /// The guild's automatic slowmode config.
+    config: AutoSlowmodeConfig,
+}
+
+#[async_trait::async_trait]
+impl Job for BusyChannel {
+    fn name(&self) -> &'static str {
+        "auto-slowmode"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::High
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let db = dis.db(self.guild);
+        let slowmodes = AutoSlowmodes::new(&db);
+        let state = slowmodes.get(self.channel).await?;
+        let previous = match state {
+            Some(s) if self.delay == s.applied => return slowmodes.mark_busy(self.channel).await,
+            Some(s) if self.delay < s.applied => return Ok(()),
+            Some(s) => s.previous,
+            None => match current_delay(ctx, self.channel).await {
+                Some(current) if current < self.delay => current,
+                // Either the channel is gone or its slowmode is already long enough.
+                _ => return Ok(()),
+            },
+        };
+
+        set_delay(ctx, self.channel, self.delay).await?;
+        slowmodes.set(self.channel, previous, self.delay).await?;
+        if state.is_none() {
+            let cooldown =
+                chrono::Duration::from_std(self.config.cooldown).unwrap_or_else(|_| chrono::Duration::minutes(2));
+            Action::relax_slowmode(self.guild, self.channel, Utc::now() + cooldown)
+                .store_action(dis)
+                .await?;
+        }
+
+        info!("raised slowmode to {}s", self.delay);
+        log_slowmode(
+            dis,
+            ctx,
+            self.guild,
+            &format!(
+                "Raised slowmode in <#{}> to {}: {} messages in the last {}.",
+                self.channel,
+                describe(self.delay),
+                self.messages,
+                humantime::format_duration(self.config.window)
+            ),
+        )
+        .await;
+        Ok(())
+    }
+}
+
+/// Module which puts busy channels in slowmode.
+#[derive(Default)]
+pub struct AutoSlowmodeModule {
+    /// When each channel's traffic was last measured, so busy channels aren't measured on every
+    /// message.
+    last_checked: Arc<Mutex<HashMap<ChannelId, Instant>>>,
+}
+
+impl AutoSlowmodeModule {
+    /// Checks whether a channel's traffic is due to be measured, marking it as measured if so.
+    fn check_due(&self, channel: ChannelId) -> bool {
+        let now = Instant::now();
+        let mut last_checked = self.last_checked.lock();
+        if let Some(last) = last_checked.get(&channel) {
+            if now.duration_since(*last) < CHECK_INTERVAL {
+                return false;
+            }
+        }
+        last_checked.retain(|_, last| now.duration_since(*last) < CHECK_INTERVAL);
+        last_checked.insert(channel, now);
+        true
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for AutoSlowmodeModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "auto-slowmode",
+                "puts busy channels in slowmode until traffic calms down.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_message_hook(true)
+            .with_config_value(config::Value::<AutoSlowmodeConfig>::with_default(
+                AUTO_SLOWMODE_KEY,
+                "A JSON object configuring automatic slowmode, e.g. {\"enabled\": true, \"window\": \"30s\", \"steps\": [{\"messages\": 20, \"delay\": \"5s\"}, {\"messages\": 40, \"delay\": \"15s\"}], \"cooldown\": \"2m\", \"channels\": [], \"exempt_channels\": []}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, _ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+
+        let config = dis
+            .config_value_t::<AutoSlowmodeConfig>(AUTO_SLOWMODE_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !config.enabled || !config.watches(orig.channel_id) || !self.check_due(orig.channel_id) {
+            return Ok(());
+        }
+
+        let messages = count_messages(dis, gid, orig.channel_id, config.window);
+        let delay = match config.delay_for(messages) {
+            Some(d) => d,
+            None => return Ok(()),
+        };
+
+        trace!("{} messages in channel call for {}s slowmode", messages, delay);
+        dis.jobs().enqueue(
+            gid,
+            BusyChannel {
+                guild: gid,
+                channel: orig.channel_id,
+                delay,
+                messages,
+                config: (*config).clone(),
+            },
+        );
+        Ok(())
+    }
+}
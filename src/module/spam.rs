@@ -1,7 +1,8 @@
 //! Contains logic relating to calculating and tracking spam pressure.
 
 use crate::dispatch::config::{FromStrWithCtx, VerifiedChannel, VerifiedRole, VerifiedUser};
-use crate::module::{ModInfo, Module, Sensitivity, CHECKMARK_IN_GREEN_BOX};
+use crate::module::persona::react_success;
+use crate::module::{ModInfo, Module, Sensitivity};
 use noisy_float::prelude::Float;
 use noisy_float::types::R64;
 use serenity::client::Context;
@@ -9,12 +10,14 @@ use serenity::model::channel::Message;
 
 use std::{fmt, time};
 
-use crate::db::cache::{Cache, TimedCache};
+use crate::db::cache::{Cache, CacheStats, Cached, LruEvictionStrategy, TimedCache};
+use crate::db::cases::{Evidence, MessageSnapshot, PressureEvidence};
 use crate::dispatch::config;
-use crate::dispatch::message_info::MsgInfo;
+use crate::dispatch::message_info::{content_hash, MsgInfo};
 use crate::dispatch::Dispatch;
 use crate::error::{GuildNotInCache, LogErrorExt};
-use crate::module::moderation::{ActionKind, ModAction};
+use crate::module::automod::SpamRelaxations;
+use crate::module::moderation::{report_simulated, simulating, ActionKind, ModAction};
 use crate::module::privilege::PRIV_ROLE;
 use crate::util::clock::CacheInstant;
 use crate::util::constraints::ConstrainedU64;
@@ -46,6 +49,12 @@ pub const DEFAULT_LENGTH_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PR
 pub const DEFAULT_LINE_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 70.0;
 /// Default pressure per ping.
 pub const DEFAULT_PING_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 20.0;
+/// Default pressure for attachments which aren't images.
+pub const DEFAULT_ATTACHMENT_PRESSURE: f64 = DEFAULT_IMAGE_PRESSURE;
+/// Default pressure for each recent message a message repeats.
+pub const DEFAULT_REPEAT_PRESSURE: f64 = (DEFAULT_MAX_PRESSURE - DEFAULT_BASE_PRESSURE) / 4.0;
+/// The number of a user's most recent messages checked for repeats.
+pub const REPEAT_LOOKBACK: usize = 10;
 /// Default pressure decay; this is the period in seconds for removal of one base pressure.
 pub const DEFAULT_PRESSURE_DECAY: f64 = 2.5;
 /// Default silence timeout; this the duration of any automutes Glimbot performs.
@@ -61,6 +70,33 @@ pub const SPAM_IGNORE_ROLE: &str = "spam_ignore_role";
 pub static VERTICAL_WHITESPACE_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r#"[\r\v\f\n\u2028\u2029]"#).expect("Invalid vertical whitespace RE"));
 
+/// What Glimbot does when a user's pressure exceeds the maximum.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SpamAction {
+    /// Delete the message which pushed the user over the limit.
+    Delete,
+    /// Mute the user for `silence_timeout`.
+    Mute,
+    /// Kick the user from the server.
+    Kick,
+}
+
+impl Default for SpamAction {
+    fn default() -> Self {
+        SpamAction::Mute
+    }
+}
+
+#[doc(hidden)]
+fn default_attachment_pressure() -> R64 {
+    R64::new(DEFAULT_ATTACHMENT_PRESSURE)
+}
+
+#[doc(hidden)]
+fn default_repeat_pressure() -> R64 {
+    R64::new(DEFAULT_REPEAT_PRESSURE)
+}
+
 /// The numerical configuration values for the spam module.
 #[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct SpamConfig {
@@ -68,6 +104,9 @@ pub struct SpamConfig {
     pub base_pressure: R64,
     /// Pressure generated by each image in a message.
     pub image_pressure: R64,
+    /// Pressure generated by each attachment in a message which isn't an image.
+    #[serde(default = "default_attachment_pressure")]
+    pub attachment_pressure: R64,
     /// Pressure generated per UTF-8 code point in a message.
     pub length_pressure: R64,
     /// Pressure generated per newline in a message.
@@ -76,11 +115,17 @@ pub struct SpamConfig {
     pub max_pressure: R64,
     /// Pressure generated per ping in a message.
     pub ping_pressure: R64,
+    /// Pressure generated for each of the user's recent messages this message repeats.
+    #[serde(default = "default_repeat_pressure")]
+    pub repeat_pressure: R64,
     /// The amount of time it will take for one `base_pressure` worth of pressure to decay.
     pub pressure_decay: R64,
     /// The amount of time users will be muted for.
     #[serde(with = "humantime_serde")]
     pub silence_timeout: time::Duration,
+    /// What to do when a user's pressure exceeds `max_pressure`.
+    #[serde(default)]
+    pub action: SpamAction,
 }
 
 impl FromStr for SpamConfig {
@@ -143,25 +188,60 @@ impl Default for SpamConfig {
         Self {
             base_pressure: R64::new(DEFAULT_BASE_PRESSURE),
             image_pressure: R64::new(DEFAULT_IMAGE_PRESSURE),
+            attachment_pressure: R64::new(DEFAULT_ATTACHMENT_PRESSURE),
             length_pressure: R64::new(DEFAULT_LENGTH_PRESSURE),
             line_pressure: R64::new(DEFAULT_LINE_PRESSURE),
             max_pressure: R64::new(DEFAULT_MAX_PRESSURE),
             ping_pressure: R64::new(DEFAULT_PING_PRESSURE),
+            repeat_pressure: R64::new(DEFAULT_REPEAT_PRESSURE),
             pressure_decay: R64::new(DEFAULT_PRESSURE_DECAY),
             silence_timeout: DEFAULT_SILENCE_TIMEOUT,
+            action: SpamAction::default(),
         }
     }
 }
 
-/// Calculates the message pressure of a single message.
-pub fn message_pressure(conf: &SpamConfig, msg: &Message) -> R64 {
+/// Counts how many of a user's recent messages, as seen in the message cache, a message repeats.
+/// Messages without text, like those which are only attachments, never count as repeats.
+pub fn count_repeats(dis: &Dispatch, msg: &Message) -> usize {
+    if msg.content.trim().is_empty() {
+        return 0;
+    }
+    let gid = match msg.guild_id {
+        Some(g) => g,
+        None => return 0,
+    };
+    let hash = content_hash(&msg.content);
+    dis.message_cache()
+        .get(&gid)
+        .map(|cv| {
+            cv.snapshot()
+                .iter()
+                .rev()
+                .filter(|m| m.user == msg.author.id && m.msg != msg.id)
+                .take(REPEAT_LOOKBACK)
+                .filter(|m| m.content_hash == hash)
+                .count()
+        })
+        .unwrap_or_default()
+}
+
+/// Calculates the message pressure of a single message, which repeats the given number of the
+/// user's recent messages.
+pub fn message_pressure(conf: &SpamConfig, msg: &Message, repeats: usize) -> R64 {
     let mut pres = conf.base_pressure.raw();
 
-    // Add image pressure.
+    // Add image and other attachment pressure.
     pres += msg
         .attachments
         .iter()
-        .filter_map(|a| a.height.map(|_| conf.image_pressure.raw()))
+        .map(|a| {
+            if a.height.is_some() {
+                conf.image_pressure.raw()
+            } else {
+                conf.attachment_pressure.raw()
+            }
+        })
         .sum::<f64>();
 
     // Length pressure.
@@ -174,13 +254,31 @@ pub fn message_pressure(conf: &SpamConfig, msg: &Message) -> R64 {
     // Line pressure.
     pres += VERTICAL_WHITESPACE_RE.find_iter(&msg.content).count() as f64 * conf.line_pressure.raw();
 
+    // Repeated messages.
+    pres += repeats as f64 * conf.repeat_pressure.raw();
+
     R64::try_new(pres).unwrap_or_else(R64::max_value)
 }
 
+/// The most members whose spam pressure is tracked in each guild. Past this, the least recently
+/// active member's pressure is forgotten; it would have decayed by then anyway.
+pub const MAX_TRACKED_PRESSURES: usize = 10_000;
+
+/// The spam pressure of each member in a guild who has spoken recently.
+type GuildPressures = Cache<UserId, UserPressure, LruEvictionStrategy<UserId>>;
+
 /// Module containing the spam filtering logic for Glimbot.
 pub struct SpamModule {
     cache: TimedCache<GuildId, SpamConfig>,
-    user_pressure: Cache<GuildId, Cache<UserId, UserPressure>>,
+    user_pressure: Cache<GuildId, GuildPressures>,
+}
+
+impl SpamModule {
+    /// Retrieves the spam pressures tracked for a guild.
+    fn pressures(&self, gid: GuildId) -> Cached<GuildPressures, ()> {
+        self.user_pressure
+            .get_or_insert(&gid, || Cache::new(LruEvictionStrategy::new(MAX_TRACKED_PRESSURES)))
+    }
 }
 
 impl Default for SpamModule {
@@ -250,6 +348,33 @@ impl Module for SpamModule {
         &INFO
     }
 
+    fn cache_statistics(&self) -> Vec<(&'static str, CacheStats)> {
+        let pressures = self
+            .user_pressure
+            .values()
+            .iter()
+            .map(|p| p.statistics())
+            .fold(CacheStats::default(), CacheStats::combine);
+        vec![("Spam config", self.cache.statistics()), ("Spam pressure", pressures)]
+    }
+
+    fn flush_caches(&self, guild: Option<GuildId>) {
+        match guild {
+            Some(g) => {
+                self.cache.remove(&g);
+            }
+            None => self.cache.clear(),
+        }
+    }
+
+    fn forget_user(&self, user: UserId) {
+        for gid in self.user_pressure.keys() {
+            if let Some(pressures) = self.user_pressure.get(&gid) {
+                pressures.remove(&user);
+            }
+        }
+    }
+
     async fn process(
         &self,
         dis: &Dispatch,
@@ -265,14 +390,14 @@ impl Module for SpamModule {
         match opts {
             SpamOpts::Clean { num, channel, who } => {
                 let channel = futures::stream::iter(channel.as_ref())
-                    .then(|c| VerifiedChannel::from_str_with_ctx(&c, ctx, gid))
+                    .then(|c| VerifiedChannel::from_str_with_ctx(c, ctx, gid))
                     .next()
                     .await
                     .transpose()?
                     .unwrap_or_else(|| VerifiedChannel::from_known(orig.channel_id));
 
                 let who = futures::stream::iter(who.as_ref())
-                    .then(|u| VerifiedUser::from_str_with_ctx(&u, ctx, gid))
+                    .then(|u| VerifiedUser::from_str_with_ctx(u, ctx, gid))
                     .next()
                     .await
                     .transpose()?
@@ -290,24 +415,18 @@ impl Module for SpamModule {
                 let user = VerifiedUser::from_str_with_ctx(op.user(), ctx, gid).await?;
                 match op {
                     PressureOp::GetFor { .. } => {
-                        let pres = self
-                            .user_pressure
-                            .get_or_insert_default(&gid)
-                            .get_or_insert_default(&user.into_inner());
+                        let pres = self.pressures(gid).get_or_insert_default(&user.into_inner());
 
                         orig.reply(ctx, format!("`{}`", pres.pressure)).await?;
                     }
                     PressureOp::SetFor { pressure, .. } => {
-                        self.user_pressure
-                            .get_or_insert_default(&gid)
+                        self.pressures(gid)
                             .insert(&user.into_inner(), UserPressure::with_pressure(pressure));
-                        orig.react(ctx, CHECKMARK_IN_GREEN_BOX).await?;
+                        react_success(dis, ctx, orig).await?;
                     }
                     PressureOp::ClearFor { .. } => {
-                        self.user_pressure
-                            .get_or_insert_default(&gid)
-                            .insert(&user.into_inner(), UserPressure::default());
-                        orig.react(ctx, CHECKMARK_IN_GREEN_BOX).await?;
+                        self.pressures(gid).insert(&user.into_inner(), UserPressure::default());
+                        react_success(dis, ctx, orig).await?;
                     }
                 }
             }
@@ -333,13 +452,23 @@ impl Module for SpamModule {
         let f = async {
             let db = dis.db(gid);
             let v = dis.config_value_t::<SpamConfig>(SPAM_CONFIG_KEY).unwrap();
-            Ok(*v.get_or_default(&db).await?)
+            let mut conf = *v.get_or_default(&db).await?;
+            if let Some(r) = SpamRelaxations::new(&db).active().await? {
+                conf.max_pressure = R64::try_new(conf.max_pressure.raw() * r.factor).unwrap_or_else(R64::max_value);
+            }
+            if let Some(factor) = crate::module::raidmode::raid_mode_spam_factor(dis, gid).await? {
+                conf.max_pressure = R64::try_new(conf.max_pressure.raw() * factor).unwrap_or_else(R64::max_value);
+            }
+            if let Some(ceiling) = dis.global_config().spam_pressure_ceiling() {
+                conf.max_pressure = std::cmp::min(conf.max_pressure, R64::new(ceiling));
+            }
+            Ok(conf)
         };
         let conf = self.cache.get_or_insert_with(&gid, f).await?;
         let pre_mess = start.elapsed();
-        let lp = message_pressure(&conf, orig);
+        let lp = message_pressure(&conf, orig, count_repeats(dis, orig));
 
-        let pres_cache = self.user_pressure.get_or_insert_default(&gid);
+        let pres_cache = self.pressures(gid);
         let pres = pres_cache
             .update_and_fetch(&orig.author.id, |o| {
                 let o = o.cloned().unwrap_or_else(Default::default);
@@ -348,9 +477,15 @@ impl Module for SpamModule {
             .unwrap();
 
         if pres.pressure > conf.max_pressure {
-            let r = mute_for_spam(dis, ctx, conf.as_ref(), orig).await;
+            let pressure = PressureEvidence {
+                pressure: pres.pressure.raw(),
+                message_pressure: lp.raw(),
+                max_pressure: conf.max_pressure.raw(),
+            };
+            let r = act_on_spam(dis, ctx, conf.as_ref(), orig, pressure).await;
             r.log_error();
-            if let Ok(true) = r {
+            if matches!(r, Ok(true)) && conf.action != SpamAction::Delete && dis.rest().breaker().allows_non_essential()
+            {
                 // tell em to shut up
                 orig.react(ctx, Unicode("⚠️".to_string()))
                     .await
@@ -371,7 +506,54 @@ impl Module for SpamModule {
     }
 }
 
-async fn mute_for_spam(dis: &Dispatch, ctx: &Context, conf: &SpamConfig, orig: &Message) -> crate::error::Result<bool> {
+/// The maximum number of the spammer's recent messages to snapshot as evidence.
+pub const MAX_EVIDENCE_MESSAGES: usize = 10;
+
+/// Gathers evidence for a spam case: snapshots of the user's most recent messages in the guild,
+/// taken from the message cache, along with their spam pressure.
+async fn spam_evidence(dis: &Dispatch, ctx: &Context, orig: &Message, pressure: PressureEvidence) -> Evidence {
+    let recent = orig
+        .guild_id
+        .and_then(|g| dis.message_cache().get(&g))
+        .map(|cv| {
+            cv.snapshot()
+                .iter()
+                .rev()
+                .filter(|m| m.user == orig.author.id && m.msg != orig.id)
+                .take(MAX_EVIDENCE_MESSAGES - 1)
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut messages = Vec::with_capacity(recent.len() + 1);
+    for m in recent.into_iter().rev() {
+        let snap = MessageSnapshot::new(m.msg, m.channel, m.timestamp);
+        let snap = match ctx.cache.message(m.channel, m.msg).await {
+            Some(full) => snap.with_content(&full.content),
+            None => snap,
+        };
+        messages.push(snap);
+    }
+    messages.push(MessageSnapshot::from(orig));
+
+    Evidence {
+        messages,
+        pressure: Some(pressure),
+        ..Default::default()
+    }
+}
+
+/// Takes the guild's configured [`SpamAction`] against the author of a message which pushed them
+/// over the pressure limit. Returns false if nothing was done to the author, because they're exempt
+/// from the anti-spam or the guild is only simulating actions.
+async fn act_on_spam(
+    dis: &Dispatch,
+    ctx: &Context,
+    conf: &SpamConfig,
+    orig: &Message,
+    pressure: PressureEvidence,
+) -> crate::error::Result<bool> {
     // Ignore if this is the guild owner.
     let guild = orig.guild(ctx).await.ok_or(GuildNotInCache)?;
     if guild.owner_id == orig.author.id {
@@ -401,7 +583,27 @@ async fn mute_for_spam(dis: &Dispatch, ctx: &Context, conf: &SpamConfig, orig: &
         }
     }
 
-    let duration = if conf.silence_timeout > Duration::from_secs(0) {
+    let simulate = simulating(dis, guild.id).await?;
+    let kind = match conf.action {
+        SpamAction::Delete if simulate => {
+            let what = format!(
+                "deleted a message from {} ({}) in <#{}> as spam.",
+                orig.author.tag(),
+                orig.author.id,
+                orig.channel_id
+            );
+            report_simulated(dis, ctx, guild.id, &what).await?;
+            return Ok(false);
+        }
+        SpamAction::Delete => {
+            orig.delete(ctx).await?;
+            return Ok(true);
+        }
+        SpamAction::Mute => ActionKind::Mute,
+        SpamAction::Kick => ActionKind::Kick,
+    };
+
+    let duration = if kind == ActionKind::Mute && conf.silence_timeout > Duration::from_secs(0) {
         Some(conf.silence_timeout.into())
     } else {
         None
@@ -409,12 +611,18 @@ async fn mute_for_spam(dis: &Dispatch, ctx: &Context, conf: &SpamConfig, orig: &
 
     let full_mem = orig.member(ctx).await?;
     let me = dis.bot().await;
-    let action = ModAction::new(full_mem, orig.channel_id, me, ActionKind::Mute)
+    let evidence = spam_evidence(dis, ctx, orig, pressure).await;
+    let action = ModAction::new(full_mem, orig.channel_id, me, kind)
         .with_duration(duration)
         .with_reason("Spam")
-        .with_original_message(orig.id);
-    action.act(dis, ctx).await?;
-    action.report_action(dis, ctx).await.map(|_| true)
+        .with_original_message(orig.id)
+        .with_evidence(evidence);
+    if simulate {
+        action.simulate(dis, ctx).await?;
+        return Ok(false);
+    }
+    let case = action.act(dis, ctx).await?;
+    action.report_action(dis, ctx, case).await.map(|_| true)
 }
 
 pub async fn clean_messages(
@@ -447,7 +655,7 @@ pub async fn clean_messages(
     let v = match &mids[..] {
         [] => 0,
         [id] => in_channel.into_inner().delete_message(ctx, id.msg).await.map(|_| 1)?,
-        [vals @ ..] => in_channel
+        vals => in_channel
             .into_inner()
             .delete_messages(ctx, vals.iter().map(|m| m.msg))
             .await
@@ -0,0 +1,244 @@
+//! Contains stats channels, which are channels (usually locked voice channels) whose names show live
+//! server statistics like "Members: 1,234". Names are refreshed from the background service's tick,
+//! and each channel is renamed at most once every few minutes, since Discord only allows two
+//! renames per channel every ten minutes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::user::OnlineStatus;
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing a [`StatsChannelsConfig`].
+pub const STATS_CHANNELS_KEY: &str = "stats_channels";
+/// The most stats channels a guild may have.
+pub const MAX_STATS_CHANNELS: usize = 5;
+/// The longest channel name Discord allows.
+pub const MAX_CHANNEL_NAME_LEN: usize = 100;
+/// How often stats are recomputed.
+pub const STATS_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// The shortest time between renames of the same channel. Discord allows two every ten minutes.
+pub const STATS_RENAME_INTERVAL: Duration = Duration::from_secs(6 * 60);
+/// The placeholders a template may contain.
+const PLACEHOLDERS: [&str; 5] = ["{members}", "{humans}", "{bots}", "{online}", "{boosts}"];
+
+impl_err!(
+    InvalidStatsChannels,
+    "Stats channels must be a JSON object like {\"channels\": [{\"channel\": \"<id>\", \"template\": \"Members: {members}\"}]}. There can be at most 5 channels, and templates must contain one of {members}, {humans}, {bots}, {online} or {boosts} and be at most 100 characters long.",
+    true
+);
+
+/// A channel whose name shows a statistic.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StatsChannel {
+    /// The channel to rename.
+    pub channel: ChannelId,
+    /// The name to give it, with placeholders like `{members}` filled in.
+    pub template: String,
+}
+
+/// Configuration for stats channels.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StatsChannelsConfig {
+    /// The channels to keep up to date.
+    #[serde(default)]
+    pub channels: Vec<StatsChannel>,
+}
+
+impl FromStr for StatsChannelsConfig {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conf: StatsChannelsConfig = serde_json::from_str(s).map_err(|_| InvalidStatsChannels)?;
+        let valid = conf.channels.len() <= MAX_STATS_CHANNELS
+            && conf.channels.iter().all(|c| {
+                c.template.chars().count() <= MAX_CHANNEL_NAME_LEN
+                    && PLACEHOLDERS.iter().any(|p| c.template.contains(p))
+            });
+        if !valid {
+            return Err(InvalidStatsChannels.into());
+        }
+        Ok(conf)
+    }
+}
+
+impl fmt::Display for StatsChannelsConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// A snapshot of a guild's statistics, taken from the cache.
+#[derive(Debug, Copy, Clone)]
+struct GuildStats {
+    /// The total number of members.
+    members: u64,
+    /// The number of cached members who aren't bots.
+    humans: u64,
+    /// The number of cached members who are bots.
+    bots: u64,
+    /// The number of members who aren't offline.
+    online: u64,
+    /// The number of server boosts.
+    boosts: u64,
+}
+
+/// Formats a number with thousands separators, e.g. 1,234.
+fn with_separators(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Fills in a template's placeholders, truncating the result to a valid channel name.
+fn render(template: &str, stats: GuildStats) -> String {
+    let name = template
+        .replace("{members}", &with_separators(stats.members))
+        .replace("{humans}", &with_separators(stats.humans))
+        .replace("{bots}", &with_separators(stats.bots))
+        .replace("{online}", &with_separators(stats.online))
+        .replace("{boosts}", &with_separators(stats.boosts));
+    name.chars().take(MAX_CHANNEL_NAME_LEN).collect()
+}
+
+/// Module which keeps stats channels up to date.
+#[derive(Default)]
+pub struct StatsChannelsModule {
+    /// When stats were last recomputed.
+    last_check: Mutex<Option<Instant>>,
+    /// When each stats channel was last renamed.
+    last_renamed: Mutex<HashMap<ChannelId, Instant>>,
+}
+
+impl StatsChannelsModule {
+    /// Checks whether stats are due to be recomputed, marking them as recomputed if so.
+    fn check_due(&self) -> bool {
+        let mut last_check = self.last_check.lock();
+        match *last_check {
+            Some(t) if t.elapsed() < STATS_CHECK_INTERVAL => false,
+            _ => {
+                *last_check = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Checks whether a channel may be renamed, marking it as renamed if so. Failed renames count
+    /// too, so a channel Discord is refusing to rename isn't retried every tick.
+    fn rename_due(&self, channel: ChannelId) -> bool {
+        let now = Instant::now();
+        let mut last_renamed = self.last_renamed.lock();
+        if let Some(last) = last_renamed.get(&channel) {
+            if now.duration_since(*last) < STATS_RENAME_INTERVAL {
+                return false;
+            }
+        }
+        last_renamed.retain(|_, last| now.duration_since(*last) < STATS_RENAME_INTERVAL);
+        last_renamed.insert(channel, now);
+        true
+    }
+
+    /// Renames a guild's stats channels whose names are out of date.
+    async fn update_guild(&self, dis: &Dispatch, ctx: &Context, guild: GuildId) -> crate::error::Result<()> {
+        let conf = dis
+            .config_value_t::<StatsChannelsConfig>(STATS_CHANNELS_KEY)?
+            .get_or_default(&dis.db(guild))
+            .await?;
+        if conf.channels.is_empty() {
+            return Ok(());
+        }
+
+        let stats = ctx
+            .cache
+            .guild_field(guild, |g| GuildStats {
+                members: g.member_count,
+                humans: g.members.values().filter(|m| !m.user.bot).count() as u64,
+                bots: g.members.values().filter(|m| m.user.bot).count() as u64,
+                online: g
+                    .presences
+                    .values()
+                    .filter(|p| p.status != OnlineStatus::Offline && p.status != OnlineStatus::Invisible)
+                    .count() as u64,
+                boosts: g.premium_subscription_count,
+            })
+            .await;
+        let stats = match stats {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+
+        for sc in conf.channels.iter() {
+            let channel = match ctx.cache.guild_channel(sc.channel).await {
+                Some(c) if c.guild_id == guild => c,
+                _ => {
+                    trace!("stats channel {} isn't in this guild", sc.channel);
+                    continue;
+                }
+            };
+            let name = render(&sc.template, stats);
+            if channel.name == name || !self.rename_due(sc.channel) {
+                continue;
+            }
+
+            debug!("renaming stats channel {} to {}", sc.channel, name);
+            let res = sc.channel.edit(ctx, |c| c.name(&name)).await;
+            dis.rest()
+                .breaker()
+                .observe(res)
+                .map_err(crate::error::Error::from)
+                .log_error();
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for StatsChannelsModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "stats-channels",
+                "keeps channel names like \"Members: 1,234\" up to date.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_tick_hook(true)
+            .with_config_value(config::Value::<StatsChannelsConfig>::with_default(
+                STATS_CHANNELS_KEY,
+                "A JSON object listing channels whose names show server stats, e.g. {\"channels\": [{\"channel\": \"<id>\", \"template\": \"Members: {members}\"}]}. Templates may use {members}, {humans}, {bots}, {online} and {boosts}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_tick(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        // Renames are purely cosmetic, so they're the first thing to go when Discord is struggling.
+        if !dis.rest().breaker().allows_non_essential() || !self.check_due() {
+            return Ok(());
+        }
+
+        for guild in ctx.cache.guilds().await {
+            self.update_guild(dis, ctx, guild).await.log_error();
+        }
+        Ok(())
+    }
+}
@@ -11,12 +11,14 @@ use systemstat::Platform;
 
 use crate::about::REPO_URL;
 use crate::dispatch::{Dispatch, ShardManKey};
+use crate::module::automod::SpamRelaxations;
+use crate::module::version::{describe_schema, describe_version};
 use crate::module::{ModInfo, Module, Sensitivity};
 
 #[doc(hidden)]
 static STATUS_INFO: Lazy<ModInfo> = Lazy::new(|| {
     ModInfo::with_name("status", "prints info about glimbot's current operating status.")
-        .with_sensitivity(Sensitivity::Owner)
+        .with_sensitivity(Sensitivity::Operator)
         .with_command(true)
         .with_filter(true)
         .with_message_hook(true)
@@ -84,7 +86,45 @@ impl Module for StatusModule {
         let total_shards = shard_man.lock().await.shards_instantiated().await.len();
 
         let commands_seen = self.command_counter.load(Ordering::Relaxed);
-        let stats = dis.config_cache().statistics();
+        let mut caches = vec![
+            format!("Config: {}", dis.config_cache().statistics()),
+            format!("Messages: {}", dis.message_cache().statistics()),
+        ];
+        for (name, stats) in dis.modules().flat_map(|m| m.cache_statistics()) {
+            caches.push(format!("{}: {}", name, stats));
+        }
+        let breaker = dis.rest().breaker();
+        let api_state = format!("{} ({} trips)", breaker.state(), breaker.trips());
+        let pending_retries = dis.rest().retries().len();
+        let jobs = dis.jobs().statistics();
+        let db_health = dis.db_health().statistics();
+        let (connections, idle) = dis.storage().connections();
+        let queries = dis.query_stats().totals();
+        let db_state = format!(
+            "{}, {}\n{} connections ({} idle), {} writes queued\n{} queries ({} slow), {}ms avg",
+            dis.storage().name(),
+            db_health,
+            connections,
+            idle,
+            dis.config_writes().len(),
+            queries.count,
+            queries.slow,
+            queries.mean().as_millis()
+        );
+        // Status is most wanted when something's wrong, so don't let a down database hide the rest.
+        let (spam_filter, schema) = if dis.db_health().is_degraded() {
+            ("Unavailable".to_string(), "Unavailable".to_string())
+        } else {
+            // Relaxations are kept in Postgres.
+            let spam_filter = match orig.guild_id.filter(|_| dis.storage().postgres().is_some()) {
+                Some(g) => SpamRelaxations::new(dis.db(g))
+                    .active()
+                    .await?
+                    .map_or_else(|| "Normal".to_string(), |r| r.describe()),
+                None => "N/A".to_string(),
+            };
+            (spam_filter, describe_schema(dis).await?)
+        };
 
         orig.channel_id
             .send_message(ctx, |e| {
@@ -92,6 +132,8 @@ impl Module for StatusModule {
                     emb.color(GLIM_COLOR)
                         .title("Bot Status")
                         .url(REPO_URL)
+                        .field("Version", describe_version(), true)
+                        .field("DB Schema", schema, true)
                         .field(
                             "CPU Load",
                             format!("{:5.2} {:5.2} {:5.2}", load.one, load.five, load.fifteen),
@@ -102,17 +144,18 @@ impl Module for StatusModule {
                             format!("{:5} / {:5} MiB", used_mem_mib, total_mem_mib),
                             true,
                         )
-                        .field(
-                            "Cache Miss/Access",
-                            format!("{} / {}", stats.misses, stats.accesses),
-                            true,
-                        )
+                        .field("Caches", caches.join("\n"), false)
                         .field("Uptime", pretty_elapsed, false)
                         .field("Sys Uptime", pretty_sys_uptime, false)
                         .field("Shard Id", shard, true)
                         .field("Shard Count", total_shards, true)
                         .field("Commands Seen", commands_seen, true)
                         .field("Messages Seen", self.messages_seen.load(Ordering::Relaxed), true)
+                        .field("Discord API", api_state, true)
+                        .field("Pending Retries", pending_retries, true)
+                        .field("Database", db_state, false)
+                        .field("Background Jobs", jobs, false)
+                        .field("Anti-Spam (this server)", spam_filter, false)
                 })
                 .reference_message(orig)
             })
@@ -0,0 +1,400 @@
+//! Contains suggestions, which members post with `!suggest <text>`. Each suggestion is posted as a
+//! numbered embed in the guild's [`SUGGESTION_CHANNEL`], with 👍 and 👎 reactions for voting.
+//! Moderators mark suggestions approved, denied or implemented with `!suggestion`, which updates the
+//! original embed.
+
+use std::borrow::Borrow;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serenity::builder::CreateEmbed;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, MessageId, UserId};
+use serenity::model::misc::Mentionable;
+use serenity::utils::Color;
+use structopt::StructOpt;
+
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::config::VerifiedChannel;
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::persona::react_success;
+use crate::module::status::GLIM_COLOR;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::{text_after_words, ClapExt};
+
+/// The config key for the channel suggestions are posted in.
+pub const SUGGESTION_CHANNEL: &str = "suggestion_channel";
+/// The longest suggestion, in characters.
+pub const MAX_SUGGESTION_LEN: usize = 1400;
+/// The longest moderator response to a suggestion, in characters.
+pub const MAX_RESPONSE_LEN: usize = 500;
+/// The reaction members vote for a suggestion with.
+pub const UPVOTE: char = '👍';
+/// The reaction members vote against a suggestion with.
+pub const DOWNVOTE: char = '👎';
+
+impl_err!(
+    NoSuggestionChannel,
+    "This server hasn't set a suggestion_channel, so suggestions are off.",
+    true
+);
+impl_err!(
+    InvalidSuggestion,
+    "Suggestions must be between 1 and 1400 characters long, and responses to them at most 500.",
+    true
+);
+impl_err!(NoSuchSuggestion, "There's no suggestion with that number.", true);
+
+/// Where a suggestion stands.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SuggestionStatus {
+    /// Still up for voting.
+    Open,
+    /// Accepted by a moderator.
+    Approved,
+    /// Turned down by a moderator.
+    Denied,
+    /// Carried out.
+    Implemented,
+}
+
+impl SuggestionStatus {
+    /// The status's name in the database.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            SuggestionStatus::Open => "open",
+            SuggestionStatus::Approved => "approved",
+            SuggestionStatus::Denied => "denied",
+            SuggestionStatus::Implemented => "implemented",
+        }
+    }
+
+    /// Parses a status's name in the database, treating unknown names as open.
+    fn from_db(s: &str) -> Self {
+        match s {
+            "approved" => SuggestionStatus::Approved,
+            "denied" => SuggestionStatus::Denied,
+            "implemented" => SuggestionStatus::Implemented,
+            _ => SuggestionStatus::Open,
+        }
+    }
+
+    /// The status as shown on a suggestion.
+    pub const fn label(self) -> &'static str {
+        match self {
+            SuggestionStatus::Open => "Open",
+            SuggestionStatus::Approved => "Approved",
+            SuggestionStatus::Denied => "Denied",
+            SuggestionStatus::Implemented => "Implemented",
+        }
+    }
+
+    /// The color of a suggestion's embed in this status.
+    pub const fn color(self) -> Color {
+        match self {
+            SuggestionStatus::Open => GLIM_COLOR,
+            SuggestionStatus::Approved => Color::DARK_GREEN,
+            SuggestionStatus::Denied => Color::RED,
+            SuggestionStatus::Implemented => Color::BLUE,
+        }
+    }
+}
+
+/// A suggestion posted by a member.
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    /// The suggestion's number.
+    pub id: i64,
+    /// Who made the suggestion.
+    pub author: UserId,
+    /// The channel the suggestion was posted in.
+    pub channel: ChannelId,
+    /// The message the suggestion was posted as, once it's been posted.
+    pub message: Option<MessageId>,
+    /// The suggestion itself.
+    pub content: String,
+    /// Where the suggestion stands.
+    pub status: SuggestionStatus,
+    /// The moderator who last changed the suggestion's status.
+    pub moderator: Option<UserId>,
+    /// The moderator's reasoning, if they gave any.
+    pub response: Option<String>,
+    /// When the suggestion was made.
+    pub created_at: DateTime<Utc>,
+}
+
+/// Wrapper around a guild's suggestions in the database.
+pub struct Suggestions<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> Suggestions<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        Suggestions {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Stores a new suggestion, returning it.
+    pub async fn add(&self, author: UserId, channel: ChannelId, content: &str) -> crate::error::Result<Suggestion> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO suggestions (guild, author, channel, content) VALUES ($1, $2, $3, $4)
+            RETURNING id, created_at;
+            "#,
+            self.ctx.guild_as_i64(),
+            author.0 as i64,
+            channel.0 as i64,
+            content
+        )
+        .fetch_one(self.ctx.conn()?)
+        .await?;
+        Ok(Suggestion {
+            id: row.id,
+            author,
+            channel,
+            message: None,
+            content: content.to_string(),
+            status: SuggestionStatus::Open,
+            moderator: None,
+            response: None,
+            created_at: row.created_at,
+        })
+    }
+
+    /// Records the message a suggestion was posted as.
+    pub async fn set_message(&self, id: i64, message: MessageId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "UPDATE suggestions SET message = $3 WHERE guild = $1 AND id = $2;",
+            self.ctx.guild_as_i64(),
+            id,
+            message.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes a suggestion, such as one which couldn't be posted.
+    pub async fn remove(&self, id: i64) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM suggestions WHERE guild = $1 AND id = $2;",
+            self.ctx.guild_as_i64(),
+            id
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Changes a suggestion's status, returning the updated suggestion. Reopened suggestions have no
+    /// moderator or response.
+    pub async fn respond(
+        &self,
+        id: i64,
+        status: SuggestionStatus,
+        moderator: Option<UserId>,
+        response: Option<&str>,
+    ) -> crate::error::Result<Suggestion> {
+        let row = sqlx::query!(
+            r#"
+            UPDATE suggestions SET status = $3, moderator = $4, response = $5 WHERE guild = $1 AND id = $2
+            RETURNING id, author, channel, message, content, status, moderator, response, created_at;
+            "#,
+            self.ctx.guild_as_i64(),
+            id,
+            status.as_str(),
+            moderator.map(|m| m.0 as i64),
+            response
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?
+        .ok_or(NoSuchSuggestion)?;
+        Ok(Suggestion {
+            id: row.id,
+            author: UserId(row.author as u64),
+            channel: ChannelId(row.channel as u64),
+            message: row.message.map(|m| MessageId(m as u64)),
+            content: row.content,
+            status: SuggestionStatus::from_db(&row.status),
+            moderator: row.moderator.map(|m| UserId(m as u64)),
+            response: row.response,
+            created_at: row.created_at,
+        })
+    }
+}
+
+/// Fills in a suggestion's embed.
+fn render_suggestion<'e>(e: &'e mut CreateEmbed, s: &Suggestion) -> &'e mut CreateEmbed {
+    e.color(s.status.color())
+        .title(format!("Suggestion #{}", s.id))
+        .description(&s.content)
+        .field("Suggested by", s.author.mention(), true)
+        .field("Status", s.status.label(), true)
+        .timestamp(s.created_at);
+    if let Some(m) = s.moderator {
+        let response = s.response.as_deref().unwrap_or("No reason given.");
+        e.field("Response", format!("{}: {}", m.mention(), response), false);
+    }
+    e
+}
+
+/// Posts a suggestion in the guild's suggestion channel.
+pub struct SuggestModule;
+
+#[async_trait::async_trait]
+impl Module for SuggestModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "suggest",
+                "posts a suggestion in the server's suggestion channel for members to vote on.",
+            )
+            .with_sensitivity(Sensitivity::Medium)
+            .with_command(true)
+            .with_config_value(config::Value::<VerifiedChannel>::new(
+                SUGGESTION_CHANNEL,
+                "The channel suggestions made with the suggest command are posted in. Suggestions are off while this is unset.",
+            ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        _command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let db = dis.db(gid);
+        let channel = dis
+            .config_value_t::<VerifiedChannel>(SUGGESTION_CHANNEL)?
+            .get(&db)
+            .await?
+            .ok_or(NoSuggestionChannel)?
+            .into_inner();
+
+        let content = text_after_words(&orig.content, 1);
+        if content.is_empty() || content.chars().count() > MAX_SUGGESTION_LEN {
+            return Err(InvalidSuggestion.into());
+        }
+
+        let suggestions = Suggestions::new(&db);
+        let suggestion = suggestions.add(orig.author.id, channel, content).await?;
+        let posted = channel
+            .send_message(ctx, |m| m.embed(|e| render_suggestion(e, &suggestion)))
+            .await;
+        let posted = match posted {
+            Ok(m) => m,
+            Err(e) => {
+                suggestions.remove(suggestion.id).await.log_error();
+                return Err(e.into());
+            }
+        };
+        suggestions.set_message(suggestion.id, posted.id).await?;
+        posted.react(ctx, UPVOTE).await?;
+        posted.react(ctx, DOWNVOTE).await?;
+
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+}
+
+/// Command to respond to suggestions.
+#[derive(StructOpt)]
+#[structopt(name = "suggestion", no_version)]
+enum SuggestionOpt {
+    /// Marks a suggestion approved.
+    Approve {
+        /// The suggestion's number.
+        id: i64,
+        /// Why, up to 500 characters.
+        reason: Vec<String>,
+    },
+    /// Marks a suggestion denied.
+    Deny {
+        /// The suggestion's number.
+        id: i64,
+        /// Why, up to 500 characters.
+        reason: Vec<String>,
+    },
+    /// Marks a suggestion implemented.
+    Implement {
+        /// The suggestion's number.
+        id: i64,
+        /// Any notes, up to 500 characters.
+        reason: Vec<String>,
+    },
+    /// Reopens a suggestion for voting, clearing its response.
+    Reopen {
+        /// The suggestion's number.
+        id: i64,
+    },
+}
+
+/// Lets moderators respond to suggestions.
+pub struct SuggestionModule;
+
+#[async_trait::async_trait]
+impl Module for SuggestionModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "suggestion",
+                "allows moderators to mark suggestions approved, denied or implemented.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = SuggestionOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+
+        let (id, status) = match opts {
+            SuggestionOpt::Approve { id, .. } => (id, SuggestionStatus::Approved),
+            SuggestionOpt::Deny { id, .. } => (id, SuggestionStatus::Denied),
+            SuggestionOpt::Implement { id, .. } => (id, SuggestionStatus::Implemented),
+            SuggestionOpt::Reopen { id } => (id, SuggestionStatus::Open),
+        };
+        let response = match status {
+            SuggestionStatus::Open => None,
+            _ => Some(text_after_words(&orig.content, 3)).filter(|r| !r.is_empty()),
+        };
+        if response.map_or(false, |r| r.chars().count() > MAX_RESPONSE_LEN) {
+            return Err(InvalidSuggestion.into());
+        }
+
+        let moderator = Some(orig.author.id).filter(|_| status != SuggestionStatus::Open);
+        let suggestion = Suggestions::new(dis.db(gid))
+            .respond(id, status, moderator, response)
+            .await?;
+
+        if let Some(message) = suggestion.message {
+            suggestion
+                .channel
+                .edit_message(ctx, message, |m| m.embed(|e| render_suggestion(e, &suggestion)))
+                .await?;
+        }
+
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+}
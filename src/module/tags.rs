@@ -0,0 +1,441 @@
+//! Contains tags, short snippets of text like server rules or FAQ answers which moderators save
+//! under a name for members to recall with `!tag <name>`. Names which aren't commands can also be
+//! recalled directly, so `!rules` works like `!tag rules`, and servers can set a separate prefix
+//! for tags in [`TAG_PREFIX`].
+
+use std::borrow::Borrow;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::UserId;
+use serenity::utils::MessageBuilder;
+use structopt::StructOpt;
+
+use crate::db::migrations::ModuleMigration;
+use crate::db::quota::{Quota, Resource};
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::error::{DatabaseError, LogErrorExt};
+use crate::module::economy::{charge, refund, EconomyConfig, ECONOMY_KEY};
+use crate::module::persona::react_success;
+use crate::module::privilege::InsufficientUserPrivilege;
+use crate::module::raid::is_staff;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::{text_after_words, ClapExt};
+
+/// The config key for the prefix which recalls tags without a command.
+pub const TAG_PREFIX: &str = "tag_prefix";
+/// The built-in quota for how many tags a guild may have; see [`crate::db::quota`].
+pub const MAX_TAGS: i64 = 250;
+/// The longest tag name, in characters.
+pub const MAX_TAG_NAME_LEN: usize = 32;
+/// The longest tag text, in characters. Kept under the longest command Glimbot accepts.
+pub const MAX_TAG_LEN: usize = 1400;
+/// The most characters in each message of a tag listing.
+const MAX_LIST_CHUNK: usize = 1900;
+
+inventory::submit! {
+    ModuleMigration::new(
+        "tags",
+        20210425140832,
+        "tags",
+        include_str!("tags/migrations/20210425140832_tags.sql"),
+    )
+}
+
+impl_err!(
+    InvalidTagName,
+    "Tag names must be between 1 and 32 characters long, and may only contain letters, numbers, - and _. They can't be the name of a command.",
+    true
+);
+impl_err!(
+    InvalidTagContent,
+    "Tags must have between 1 and 1400 characters of text.",
+    true
+);
+impl_err!(TagExists, "There's already a tag by that name.", true);
+impl_err!(NoSuchTag, "There's no tag or command by that name.", true);
+
+/// A snippet of text saved under a name.
+#[derive(Debug, Clone)]
+pub struct Tag {
+    /// The tag's name.
+    pub name: String,
+    /// The tag's text.
+    pub content: String,
+    /// Who made the tag.
+    pub owner: UserId,
+    /// How many times the tag has been recalled.
+    pub uses: u64,
+    /// When the tag was made.
+    pub created_at: DateTime<Utc>,
+}
+
+/// Normalizes a tag name, checking it's a valid one.
+fn tag_name(name: &str) -> crate::error::Result<String> {
+    let name = name.trim().to_lowercase();
+    let valid = !name.is_empty()
+        && name.chars().count() <= MAX_TAG_NAME_LEN
+        && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(InvalidTagName.into());
+    }
+    Ok(name)
+}
+
+/// Checks a tag's text is a valid length.
+fn tag_content(content: &str) -> crate::error::Result<&str> {
+    let content = content.trim();
+    if content.is_empty() || content.chars().count() > MAX_TAG_LEN {
+        return Err(InvalidTagContent.into());
+    }
+    Ok(content)
+}
+
+/// Wrapper around a guild's tags in the database.
+pub struct Tags<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> Tags<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        Tags {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Creates a tag, erroring if there's already one by that name or the guild has too many.
+    pub async fn add(&self, name: &str, content: &str, owner: UserId) -> crate::error::Result<()> {
+        let name = tag_name(name)?;
+        let content = tag_content(content)?;
+        Quota::new(&self.ctx).check(Resource::Tags).await?;
+
+        let res: Result<_, sqlx::Error> = sqlx::query!(
+            "INSERT INTO tags (guild, name, content, owner) VALUES ($1, $2, $3, $4);",
+            self.ctx.guild_as_i64(),
+            name,
+            content,
+            owner.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await;
+
+        match res {
+            Err(e) if e.is_unique() => Err(TagExists.into()),
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Replaces a tag's text.
+    pub async fn edit(&self, name: &str, content: &str) -> crate::error::Result<()> {
+        let name = tag_name(name)?;
+        let content = tag_content(content)?;
+        let res = sqlx::query!(
+            "UPDATE tags SET content = $3 WHERE guild = $1 AND name = $2;",
+            self.ctx.guild_as_i64(),
+            name,
+            content
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(NoSuchTag.into());
+        }
+        Ok(())
+    }
+
+    /// Deletes a tag.
+    pub async fn remove(&self, name: &str) -> crate::error::Result<()> {
+        let name = tag_name(name)?;
+        let res = sqlx::query!(
+            "DELETE FROM tags WHERE guild = $1 AND name = $2;",
+            self.ctx.guild_as_i64(),
+            name
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(NoSuchTag.into());
+        }
+        Ok(())
+    }
+
+    /// Retrieves a tag without counting it as used.
+    pub async fn get(&self, name: &str) -> crate::error::Result<Tag> {
+        let name = tag_name(name).map_err(|_| NoSuchTag)?;
+        let row = sqlx::query!(
+            "SELECT name, content, owner, uses, created_at FROM tags WHERE guild = $1 AND name = $2;",
+            self.ctx.guild_as_i64(),
+            name
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?
+        .ok_or(NoSuchTag)?;
+        Ok(Tag {
+            name: row.name,
+            content: row.content,
+            owner: UserId(row.owner as u64),
+            uses: row.uses as u64,
+            created_at: row.created_at,
+        })
+    }
+
+    /// Retrieves a tag's text, counting it as used. Returns `None` if there's no such tag.
+    pub async fn recall(&self, name: &str) -> crate::error::Result<Option<String>> {
+        let name = match tag_name(name) {
+            Ok(n) => n,
+            Err(_) => return Ok(None),
+        };
+        let content = sqlx::query_scalar!(
+            "UPDATE tags SET uses = uses + 1 WHERE guild = $1 AND name = $2 RETURNING content;",
+            self.ctx.guild_as_i64(),
+            name
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+        Ok(content)
+    }
+
+    /// Retrieves the guild's tags, in order of name.
+    pub async fn list(&self) -> crate::error::Result<Vec<Tag>> {
+        let rows = sqlx::query!(
+            "SELECT name, content, owner, uses, created_at FROM tags WHERE guild = $1 ORDER BY name ASC;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_all(self.ctx.read_conn()?)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|r| Tag {
+                name: r.name,
+                content: r.content,
+                owner: UserId(r.owner as u64),
+                uses: r.uses as u64,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+}
+
+/// Sends a tag's text in reply to a message, or to the message it replies to if there is one.
+/// Mentions in tags never ping anyone.
+async fn send_tag(ctx: &Context, orig: &Message, content: String) -> crate::error::Result<()> {
+    let target = orig.referenced_message.as_deref().unwrap_or(orig);
+    orig.channel_id
+        .send_message(ctx, |m| {
+            m.content(content)
+                .reference_message(target)
+                .allowed_mentions(|am| am.empty_parse())
+        })
+        .await?;
+    Ok(())
+}
+
+#[derive(StructOpt)]
+#[structopt(name = "tag", no_version)]
+/// Recalls tags, short snippets of text saved by moderators. Run `tag <name>` to show one.
+enum TagOpt {
+    /// Creates a tag. Moderators only, unless the server lets members buy tags.
+    Add {
+        /// The tag's name.
+        name: String,
+        /// The tag's text, up to 1400 characters. Line breaks are kept.
+        #[structopt(required = true)]
+        content: Vec<String>,
+    },
+    /// Replaces a tag's text. Moderators and the tag's owner only.
+    Edit {
+        /// The tag's name.
+        name: String,
+        /// The tag's new text, up to 1400 characters. Line breaks are kept.
+        #[structopt(required = true)]
+        content: Vec<String>,
+    },
+    /// Deletes a tag. Moderators and the tag's owner only.
+    Remove {
+        /// The tag's name.
+        name: String,
+    },
+    /// Lists the server's tags, along with how often they've been used.
+    List,
+    /// Shows who made a tag, when, and how often it's been used.
+    Info {
+        /// The tag's name.
+        name: String,
+    },
+    /// Shows a tag.
+    #[structopt(external_subcommand)]
+    Show(Vec<String>),
+}
+
+/// Module which lets moderators save tags and members recall them.
+pub struct TagModule;
+
+#[async_trait::async_trait]
+impl Module for TagModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("tag", "recalls short snippets of text saved by moderators.")
+                .with_sensitivity(Sensitivity::Medium)
+                .with_command(true)
+                .with_command_fallback(true)
+                .with_message_hook(true)
+                .with_config_value(config::Value::<char>::new(
+                    TAG_PREFIX,
+                    "A prefix which recalls tags without a command, e.g. ? to show the rules tag with ?rules. Tags can always be recalled with the command prefix too.",
+                ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = TagOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let tags = Tags::new(dis.db(gid));
+
+        let staff = match opts {
+            TagOpt::Add { .. } | TagOpt::Edit { .. } | TagOpt::Remove { .. } => {
+                is_staff(dis, ctx, gid, orig.author.id).await?
+            }
+            _ => false,
+        };
+
+        match opts {
+            TagOpt::Add { name, .. } => {
+                // Tags named after commands could never be recalled directly.
+                let reserved = ["add", "edit", "remove", "list", "info", "help"];
+                if dis.command_module(&name.to_lowercase()).is_ok() || reserved.contains(&name.to_lowercase().as_str())
+                {
+                    return Err(InvalidTagName.into());
+                }
+
+                // Members who aren't moderators can buy tags, if the server allows it.
+                let cost = if staff {
+                    0
+                } else {
+                    let economy = dis
+                        .config_value_t::<EconomyConfig>(ECONOMY_KEY)?
+                        .get_or_default(&dis.db(gid))
+                        .await?;
+                    if !economy.enabled || economy.tag_cost == 0 {
+                        return Err(InsufficientUserPrivilege.into());
+                    }
+                    economy.tag_cost
+                };
+                charge(dis, gid, orig.author.id, cost).await?;
+                let res = tags
+                    .add(&name, text_after_words(&orig.content, 3), orig.author.id)
+                    .await;
+                if res.is_err() {
+                    refund(dis, gid, orig.author.id, cost).await.log_error();
+                }
+                res?;
+            }
+            TagOpt::Edit { name, .. } => {
+                // Members who bought a tag may change it, but only moderators may change anyone's.
+                if !staff && tags.get(&name).await?.owner != orig.author.id {
+                    return Err(InsufficientUserPrivilege.into());
+                }
+                tags.edit(&name, text_after_words(&orig.content, 3)).await?
+            }
+            TagOpt::Remove { name } => {
+                if !staff && tags.get(&name).await?.owner != orig.author.id {
+                    return Err(InsufficientUserPrivilege.into());
+                }
+                tags.remove(&name).await?
+            }
+            TagOpt::List => {
+                let list = tags.list().await?;
+                if list.is_empty() {
+                    orig.reply(ctx, "No tags.").await?;
+                    return Ok(());
+                }
+                let listing = list
+                    .iter()
+                    .map(|t| format!("{} ({} uses)", t.name, t.uses))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let listing = listing.chars().collect::<Vec<_>>();
+                for chunk in listing.chunks(MAX_LIST_CHUNK) {
+                    let msg = MessageBuilder::new()
+                        .push_codeblock_safe(chunk.iter().collect::<String>(), None)
+                        .build();
+                    orig.channel_id.say(ctx, msg).await?;
+                }
+                return Ok(());
+            }
+            TagOpt::Info { name } => {
+                let tag = tags.get(&name).await?;
+                let owner = match tag.owner.to_user(ctx).await {
+                    Ok(u) => u.tag(),
+                    Err(_) => tag.owner.to_string(),
+                };
+                let info = format!(
+                    "{}\nMade by {} on {}, used {} times.",
+                    tag.name,
+                    owner,
+                    tag.created_at.format("%Y-%m-%d"),
+                    tag.uses
+                );
+                let msg = MessageBuilder::new().push_codeblock_safe(info, None).build();
+                orig.reply(ctx, msg).await?;
+                return Ok(());
+            }
+            TagOpt::Show(args) => {
+                let name = args.first().map(String::as_str).unwrap_or_default();
+                let content = tags.recall(name).await?.ok_or(NoSuchTag)?;
+                return send_tag(ctx, orig, content).await;
+            }
+        }
+
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if orig.author.bot {
+            return Ok(());
+        }
+
+        let db = dis.db(gid);
+        let prefix = match dis.config_value_t::<char>(TAG_PREFIX)?.get(&db).await? {
+            Some(p) => *p,
+            None => return Ok(()),
+        };
+        let name = match orig.content.strip_prefix(prefix) {
+            Some(rest) => rest.split_whitespace().next().unwrap_or_default(),
+            None => return Ok(()),
+        };
+        // The command prefix is handled by dispatch, which falls back to tags itself.
+        let command_prefix = dis
+            .config_value_t::<char>("command_prefix")?
+            .get_or_default(&db)
+            .await?;
+        if name.is_empty() || prefix == *command_prefix {
+            return Ok(());
+        }
+
+        if let Some(content) = Tags::new(&db).recall(name).await? {
+            trace!("recalling tag by prefix");
+            send_tag(ctx, orig, content).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+-- Short snippets of text moderators save for members to recall by name.
+CREATE TABLE tags
+(
+    guild      BIGINT      NOT NULL,
+    name       TEXT        NOT NULL CHECK (char_length(name) BETWEEN 1 AND 32),
+    content    TEXT        NOT NULL CHECK (char_length(content) BETWEEN 1 AND 1400),
+    owner      BIGINT      NOT NULL,
+    uses       BIGINT      NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (guild, name),
+    FOREIGN KEY (guild)
+        REFERENCES known_guilds (guild)
+        ON DELETE CASCADE
+);
+
+CREATE TRIGGER ensure_tag_guild
+    BEFORE INSERT OR UPDATE
+    ON tags
+    FOR EACH ROW
+EXECUTE PROCEDURE ensure_guild();
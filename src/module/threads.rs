@@ -0,0 +1,166 @@
+//! Contains thread support. Glimbot joins every thread as it's created, so its filters see messages
+//! in threads just as they do in channels, and guilds can set a default auto-archive duration for new
+//! threads and make channels thread-only, where every message gets a thread of its own.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::{GuildChannel, Message, MessageType};
+use serenity::model::id::{ChannelId, GuildId};
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing a [`ThreadConfig`].
+pub const THREAD_CONFIG_KEY: &str = "thread_config";
+/// The auto-archive durations Discord accepts, in minutes.
+pub const AUTO_ARCHIVE_MINUTES: [u16; 4] = [60, 1440, 4320, 10080];
+/// The longest thread name Discord allows.
+pub const MAX_THREAD_NAME_LEN: usize = 100;
+/// The number of characters of a message used to name its thread in a thread-only channel.
+const THREAD_NAME_PREVIEW: usize = 50;
+
+impl_err!(
+    InvalidThreadConfig,
+    "Thread config must be a JSON object like {\"auto_archive\": 1440, \"thread_only\": [\"<channel id>\"]}, where auto_archive is 60, 1440, 4320 or 10080 minutes.",
+    true
+);
+
+/// Configuration for threads.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ThreadConfig {
+    /// How many minutes of inactivity new threads are archived after, if not Discord's default.
+    #[serde(default)]
+    pub auto_archive: Option<u16>,
+    /// Channels where every message gets its own thread.
+    #[serde(default)]
+    pub thread_only: Vec<ChannelId>,
+}
+
+impl FromStr for ThreadConfig {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conf: ThreadConfig = serde_json::from_str(s).map_err(|_| InvalidThreadConfig)?;
+        if let Some(m) = conf.auto_archive {
+            if !AUTO_ARCHIVE_MINUTES.contains(&m) {
+                return Err(InvalidThreadConfig.into());
+            }
+        }
+        Ok(conf)
+    }
+}
+
+impl fmt::Display for ThreadConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Finds the channel a thread belongs to, or `None` if the channel isn't a cached thread. Settings
+/// which apply to a channel, like filter exemptions, should usually apply to its threads too.
+pub async fn thread_parent(ctx: &Context, guild: GuildId, channel: ChannelId) -> Option<ChannelId> {
+    ctx.cache
+        .guild_field(guild, |g| {
+            g.threads.iter().find(|t| t.id == channel).and_then(|t| t.category_id)
+        })
+        .await
+        .flatten()
+}
+
+/// Names the thread for a message in a thread-only channel after the start of the message.
+fn thread_name(orig: &Message) -> String {
+    let preview: String = orig
+        .content
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(THREAD_NAME_PREVIEW)
+        .collect();
+    if preview.is_empty() {
+        format!("{}'s thread", orig.author.name)
+            .chars()
+            .take(MAX_THREAD_NAME_LEN)
+            .collect()
+    } else {
+        preview
+    }
+}
+
+/// Module which joins threads and applies the guild's thread settings.
+pub struct ThreadsModule;
+
+#[async_trait::async_trait]
+impl Module for ThreadsModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "threads",
+                "joins new threads so filters apply in them, and applies the server's thread settings.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_thread_create_hook(true)
+            .with_message_hook(true)
+            .with_config_value(config::Value::<ThreadConfig>::with_default(
+                THREAD_CONFIG_KEY,
+                "A JSON object configuring threads, e.g. {\"auto_archive\": 1440, \"thread_only\": [\"<channel id>\"]}. auto_archive is how many minutes of inactivity new threads are archived after (60, 1440, 4320 or 10080), and every message in a thread_only channel gets its own thread.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_thread_create(&self, dis: &Dispatch, ctx: &Context, thread: &GuildChannel) -> crate::error::Result<()> {
+        // Glimbot only sees messages in threads it's a member of.
+        thread.id.join_thread(ctx).await?;
+
+        let conf = dis
+            .config_value_t::<ThreadConfig>(THREAD_CONFIG_KEY)?
+            .get_or_default(&dis.db(thread.guild_id))
+            .await?;
+        let current = thread.thread_metadata.as_ref().and_then(|m| m.auto_archive_duration);
+        if let Some(minutes) = conf.auto_archive {
+            if current != Some(u64::from(minutes)) && !thread.thread_metadata.as_ref().map_or(false, |m| m.archived) {
+                thread.id.edit_thread(ctx, |t| t.auto_archive_duration(minutes)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            Some(g) if !orig.author.bot => g,
+            _ => return Ok(()),
+        };
+        if !matches!(orig.kind, MessageType::Regular | MessageType::InlineReply) {
+            return Ok(());
+        }
+
+        let conf = dis
+            .config_value_t::<ThreadConfig>(THREAD_CONFIG_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !conf.thread_only.contains(&orig.channel_id) {
+            return Ok(());
+        }
+
+        let name = thread_name(orig);
+        orig.channel_id
+            .create_public_thread(ctx, orig.id, |t| {
+                t.name(name);
+                if let Some(minutes) = conf.auto_archive {
+                    t.auto_archive_duration(minutes);
+                }
+                t
+            })
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+//! Contains the server's timezone and locale, which decide how Glimbot writes out times in places
+//! like reminders, case reviews and the config history, along with the `time` command.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::GuildId;
+
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for the server's [`Timezone`].
+pub const TIMEZONE_KEY: &str = "timezone";
+/// The config key for the server's [`TimeLocale`].
+pub const LOCALE_KEY: &str = "locale";
+
+impl_err!(
+    InvalidTimezone,
+    "That isn't a timezone I know. Use a name from the IANA database, like \"Europe/London\" or \"America/New_York\".",
+    true
+);
+impl_err!(
+    InvalidLocale,
+    "Locales are iso, en-US, en-GB, de-DE, fr-FR and ja-JP.",
+    true
+);
+
+/// A timezone from the IANA database.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct Timezone(pub Tz);
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Timezone(Tz::UTC)
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tz: Tz = s.trim().parse().map_err(|_| InvalidTimezone)?;
+        Ok(Timezone(tz))
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.name())
+    }
+}
+
+/// The conventions used to write out dates and times.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TimeLocale {
+    /// ISO 8601, like 2021-05-06 14:30.
+    #[serde(rename = "iso")]
+    Iso,
+    /// United States English, like May 6, 2021 2:30 PM.
+    #[serde(rename = "en-US")]
+    EnUs,
+    /// British English, like 6 May 2021 14:30.
+    #[serde(rename = "en-GB")]
+    EnGb,
+    /// German, like 06.05.2021 14:30.
+    #[serde(rename = "de-DE")]
+    DeDe,
+    /// French, like 06/05/2021 14:30.
+    #[serde(rename = "fr-FR")]
+    FrFr,
+    /// Japanese, like 2021/05/06 14:30.
+    #[serde(rename = "ja-JP")]
+    JaJp,
+}
+
+impl TimeLocale {
+    /// Every locale, in the order they're documented.
+    pub const ALL: [TimeLocale; 6] = [
+        TimeLocale::Iso,
+        TimeLocale::EnUs,
+        TimeLocale::EnGb,
+        TimeLocale::DeDe,
+        TimeLocale::FrFr,
+        TimeLocale::JaJp,
+    ];
+
+    /// The locale's name, like en-US.
+    pub fn name(self) -> &'static str {
+        match self {
+            TimeLocale::Iso => "iso",
+            TimeLocale::EnUs => "en-US",
+            TimeLocale::EnGb => "en-GB",
+            TimeLocale::DeDe => "de-DE",
+            TimeLocale::FrFr => "fr-FR",
+            TimeLocale::JaJp => "ja-JP",
+        }
+    }
+
+    /// The `strftime` format for a date.
+    fn date_format(self) -> &'static str {
+        match self {
+            TimeLocale::Iso => "%Y-%m-%d",
+            TimeLocale::EnUs => "%b %-d, %Y",
+            TimeLocale::EnGb => "%-d %b %Y",
+            TimeLocale::DeDe => "%d.%m.%Y",
+            TimeLocale::FrFr => "%d/%m/%Y",
+            TimeLocale::JaJp => "%Y/%m/%d",
+        }
+    }
+
+    /// The `strftime` format for a time of day.
+    fn time_format(self) -> &'static str {
+        match self {
+            TimeLocale::EnUs => "%-I:%M %p",
+            _ => "%H:%M",
+        }
+    }
+}
+
+impl Default for TimeLocale {
+    fn default() -> Self {
+        TimeLocale::Iso
+    }
+}
+
+impl FromStr for TimeLocale {
+    type Err = InvalidLocale;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        TimeLocale::ALL
+            .iter()
+            .copied()
+            .find(|l| l.name().eq_ignore_ascii_case(s))
+            .ok_or(InvalidLocale)
+    }
+}
+
+impl fmt::Display for TimeLocale {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Writes out times the way a server has asked for.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct GuildClock {
+    /// The server's timezone.
+    pub timezone: Timezone,
+    /// The server's conventions for writing dates and times.
+    pub locale: TimeLocale,
+}
+
+impl GuildClock {
+    /// Retrieves the clock for a guild.
+    pub async fn for_guild(dis: &Dispatch, gid: GuildId) -> crate::error::Result<Self> {
+        let db = dis.db(gid);
+        let timezone = dis
+            .config_value_t::<Timezone>(TIMEZONE_KEY)?
+            .get_or_default(&db)
+            .await?;
+        let locale = dis
+            .config_value_t::<TimeLocale>(LOCALE_KEY)?
+            .get_or_default(&db)
+            .await?;
+        Ok(GuildClock {
+            timezone: *timezone,
+            locale: *locale,
+        })
+    }
+
+    /// Writes out a date and time, along with the timezone's abbreviation, like
+    /// `2021-05-06 15:30 BST`.
+    pub fn datetime(&self, t: DateTime<Utc>) -> String {
+        let format = format!("{} {} %Z", self.locale.date_format(), self.locale.time_format());
+        t.with_timezone(&self.timezone.0).format(&format).to_string()
+    }
+
+    /// Writes out a date alone.
+    pub fn date(&self, t: DateTime<Utc>) -> String {
+        t.with_timezone(&self.timezone.0)
+            .format(self.locale.date_format())
+            .to_string()
+    }
+}
+
+/// Module which holds the server's timezone and locale, and tells the time in them.
+pub struct TimeModule;
+
+#[async_trait::async_trait]
+impl Module for TimeModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("time", "tells the time in this server's timezone.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_command(true)
+                .with_config_value(config::Value::<Timezone>::with_default(
+                    TIMEZONE_KEY,
+                    "The server's timezone from the IANA database, like \"Europe/London\", used when Glimbot writes out times. UTC by default.",
+                    Default::default,
+                ))
+                .with_config_value(config::Value::<TimeLocale>::with_default(
+                    LOCALE_KEY,
+                    "How Glimbot writes out dates and times: iso (2021-05-06 14:30, the default), en-US (May 6, 2021 2:30 PM), en-GB (6 May 2021 14:30), de-DE (06.05.2021 14:30), fr-FR (06/05/2021 14:30) or ja-JP (2021/05/06 14:30).",
+                    Default::default,
+                ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        _command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let clock = GuildClock::for_guild(dis, orig.guild_id.unwrap()).await?;
+        orig.reply(
+            ctx,
+            format!("It's {} here ({}).", clock.datetime(Utc::now()), clock.timezone),
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Contains the `transcript` command, which renders part of a channel's history as an HTML or text
+//! file for use as moderation evidence. Transcripts are only posted in the mod log, so they stay
+//! visible to staff alone, and each one is noted there along with who requested it.
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::MessageId;
+use structopt::StructOpt;
+
+use crate::dispatch::config::{FromStrWithCtx, NoSuchChannel, VerifiedChannel};
+use crate::dispatch::Dispatch;
+use crate::module::moderation::mod_log_channel;
+use crate::module::persona::react_success;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::ConstrainedU64;
+use crate::util::transcript::{Transcript, TranscriptRange};
+use crate::util::ClapExt;
+
+/// Generates a transcript of part of a channel's history.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "transcript", no_version)]
+pub struct TranscriptOpt {
+    /// The channel to make a transcript of.
+    channel: String,
+    /// The maximum number of messages to include. The most recent messages in the range are kept.
+    #[structopt(short = "n", long, default_value = "100")]
+    limit: ConstrainedU64<1, 2000>,
+    /// Only include messages sent after the message with this ID.
+    #[structopt(long)]
+    after: Option<u64>,
+    /// Only include messages sent before the message with this ID.
+    #[structopt(long)]
+    before: Option<u64>,
+    /// Render the transcript as plain text instead of HTML.
+    #[structopt(long)]
+    text: bool,
+}
+
+impl TranscriptOpt {
+    /// The range of messages the transcript should cover.
+    fn range(&self) -> TranscriptRange {
+        TranscriptRange {
+            after: self.after.map(MessageId),
+            before: self.before.map(MessageId),
+            limit: self.limit.into(),
+        }
+    }
+}
+
+/// Adds the `transcript` command.
+pub struct TranscriptModule;
+
+#[async_trait::async_trait]
+impl Module for TranscriptModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "transcript",
+                "posts a transcript of part of a channel's history in the mod log.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let gid = orig.guild_id.unwrap();
+        let opts = TranscriptOpt::from_iter_with_help(command)?;
+        let channel = VerifiedChannel::from_str_with_ctx(&opts.channel, ctx, gid)
+            .await?
+            .into_inner();
+        let name = ctx
+            .cache
+            .guild_channel(channel)
+            .await
+            .map(|c| c.name)
+            .ok_or(NoSuchChannel)?;
+
+        // Transcripts may include messages from private channels, so they only ever go to the mod log.
+        let log = mod_log_channel(dis, gid).await?;
+        let transcript = Transcript::collect(dis, ctx, gid, channel, name, opts.range()).await?;
+
+        let (body, ext) = if opts.text {
+            (transcript.to_text(), "txt")
+        } else {
+            (transcript.to_html(), "html")
+        };
+        let file_name = format!(
+            "transcript-{}-{}.{}",
+            transcript.channel_name,
+            transcript.generated.format("%Y%m%d%H%M%S"),
+            ext
+        );
+
+        info!(
+            "{} requested a transcript of {} ({} messages)",
+            orig.author.id,
+            channel,
+            transcript.messages.len()
+        );
+        log.send_files(ctx, vec![(body.as_bytes(), file_name.as_str())], |m| {
+            m.content(format!(
+                "Transcript of <#{}> requested by {} ({}): {} messages.",
+                channel,
+                orig.author.tag(),
+                orig.author.id,
+                transcript.messages.len()
+            ))
+        })
+        .await?;
+
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+}
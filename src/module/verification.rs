@@ -0,0 +1,415 @@
+//! Contains the verification gate, which holds back new members' verified role until they show
+//! they're a person: either by reacting to a message in a verification channel, or by sending back
+//! a code Glimbot DMs them when they join. Members who haven't verified after a while can be kicked
+//! automatically.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serenity::client::Context;
+use serenity::model::channel::{Message, Reaction, ReactionType};
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, GuildId, MessageId, RoleId, UserId};
+use structopt::StructOpt;
+
+use crate::db::timed::{Action, ActionKind, TimedEvents, ONE_HUNDREDISH_YEARS};
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::error::LogErrorExt;
+use crate::module::moderation::{self, ModAction};
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::{text_after_words, ClapExt};
+
+/// The config key for grabbing a [`VerificationConfig`].
+pub const VERIFICATION_KEY: &str = "verification";
+/// The emoji members react with to verify.
+pub const VERIFY_EMOJI: &str = "✅";
+/// The length of the codes sent to members verifying by challenge.
+pub const CHALLENGE_CODE_LEN: usize = 6;
+/// The text of the verification message if moderators don't give their own.
+pub const DEFAULT_GATE_TEXT: &str = "Welcome! React with ✅ below to get access to the rest of the server.";
+
+impl_err!(
+    NoVerifiedRole,
+    "Set the role given to verified members in the verification config first.",
+    true
+);
+impl_err!(NotUsingChallenges, "This server doesn't use verification codes.", true);
+impl_err!(
+    WrongVerificationCode,
+    "That code isn't right. Check the DM I sent you, or run this command without a code to get a new one.",
+    true
+);
+
+/// How members verify themselves.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum VerificationMode {
+    /// They react to the verification message.
+    Reaction,
+    /// They send back a code DMed to them with the `verify` command.
+    Challenge,
+}
+
+/// Configuration for the verification gate.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VerificationConfig {
+    /// Whether the gate is on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// The role given to members once they verify.
+    #[serde(default)]
+    pub role: Option<RoleId>,
+    /// How members verify themselves.
+    #[serde(default = "default_mode")]
+    pub mode: VerificationMode,
+    /// The message members react to, when verifying by reaction. Set by `verification post`.
+    #[serde(default)]
+    pub message: Option<MessageId>,
+    /// How long members may take to verify before being kicked. Members are never kicked if unset.
+    #[serde(with = "humantime_serde", default)]
+    pub kick_after: Option<Duration>,
+}
+
+#[doc(hidden)]
+fn default_mode() -> VerificationMode {
+    VerificationMode::Reaction
+}
+
+impl Default for VerificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            role: None,
+            mode: default_mode(),
+            message: None,
+            kick_after: None,
+        }
+    }
+}
+
+impl FromStr for VerificationConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for VerificationConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Wrapper around the verification codes issued in a guild.
+pub struct Challenges<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> Challenges<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        Challenges {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Issues a member a new code, replacing any they had.
+    pub async fn issue(&self, user: UserId) -> crate::error::Result<String> {
+        let code: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(CHALLENGE_CODE_LEN)
+            .map(char::from)
+            .collect();
+        let code = code.to_uppercase();
+        sqlx::query!(
+            r#"
+            INSERT INTO verification_challenges (guild, user_id, code) VALUES ($1, $2, $3)
+            ON CONFLICT (guild, user_id) DO UPDATE SET code = EXCLUDED.code, issued_at = NOW();
+            "#,
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            &code
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(code)
+    }
+
+    /// Checks a code a member sent back, forgetting it if it's right.
+    pub async fn answer(&self, user: UserId, code: &str) -> crate::error::Result<bool> {
+        let res = sqlx::query!(
+            "DELETE FROM verification_challenges WHERE guild = $1 AND user_id = $2 AND code = $3;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64,
+            code.trim().to_uppercase()
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Forgets a member's code, such as when they're verified some other way.
+    pub async fn forget(&self, user: UserId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM verification_challenges WHERE guild = $1 AND user_id = $2;",
+            self.ctx.guild_as_i64(),
+            user.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Retrieves a guild's verification config.
+async fn verification_config(dis: &Dispatch, guild: GuildId) -> crate::error::Result<VerificationConfig> {
+    let conf = dis
+        .config_value_t::<VerificationConfig>(VERIFICATION_KEY)?
+        .get_or_default(&dis.db(guild))
+        .await?;
+    Ok((*conf).clone())
+}
+
+/// Issues a member a verification code and DMs it to them.
+async fn send_challenge(dis: &Dispatch, ctx: &Context, guild: GuildId, user: UserId) -> crate::error::Result<()> {
+    let db = dis.db(guild);
+    let code = Challenges::new(&db).issue(user).await?;
+    let prefix = dis
+        .config_value_t::<char>("command_prefix")?
+        .get_or_default(&db)
+        .await?;
+    let guild_name = ctx
+        .cache
+        .guild_field(guild, |g| g.name.clone())
+        .await
+        .unwrap_or_else(|| "the server".to_string());
+    let text = format!(
+        "Welcome to {}! To get access to the rest of the server, send `{}verify {}` there.",
+        guild_name, *prefix, code
+    );
+    user.create_dm_channel(ctx).await?.say(ctx, text).await?;
+    Ok(())
+}
+
+/// Gives a member the verified role and stops them from being kicked for not verifying.
+async fn verify(dis: &Dispatch, ctx: &Context, guild: GuildId, user: UserId, role: RoleId) -> crate::error::Result<()> {
+    let db = dis.db(guild);
+    let mut member = guild.member(ctx, user).await?;
+    if !member.roles.contains(&role) {
+        member.add_role(ctx, role).await?;
+        debug!("verified {}", user);
+    }
+    TimedEvents::new(db.clone())
+        .cancel_actions_for(user, ActionKind::KickUnverified)
+        .await?;
+    Challenges::new(&db).forget(user).await
+}
+
+/// Kicks a member who hasn't verified in time, unless the gate has been turned off since they
+/// joined.
+pub async fn kick_unverified(dis: &Dispatch, ctx: &Context, guild: GuildId, user: UserId) -> crate::error::Result<()> {
+    let conf = verification_config(dis, guild).await?;
+    let role = match conf.role {
+        Some(r) if conf.enabled => r,
+        _ => return Ok(()),
+    };
+    let member = match guild.member(ctx, user).await {
+        Ok(m) => m,
+        Err(e) => {
+            debug!("couldn't find unverified member to kick: {}", e);
+            return Ok(());
+        }
+    };
+    if member.roles.contains(&role) {
+        return Ok(());
+    }
+
+    Challenges::new(dis.db(guild)).forget(user).await.log_error();
+    let why = match conf.kick_after {
+        Some(d) => format!("Didn't verify within {}.", humantime::format_duration(d)),
+        None => "Didn't verify.".to_string(),
+    };
+    let action = ModAction::new(
+        &member,
+        ChannelId::default(),
+        dis.bot().await,
+        moderation::ActionKind::Kick,
+    )
+    .with_reason(why);
+    let case = action.act(dis, ctx).await?;
+    action.report_action(dis, ctx, case).await
+}
+
+/// Sets up the verification gate.
+#[derive(StructOpt)]
+#[structopt(name = "verification", no_version)]
+enum VerificationOpt {
+    /// Posts the message members react to in this channel, replacing any posted before.
+    Post {
+        /// The message's text. A short welcome is used if left out.
+        text: Vec<String>,
+    },
+}
+
+/// Module which holds back new members' verified role until they verify.
+pub struct VerificationModule;
+
+#[async_trait::async_trait]
+impl Module for VerificationModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "verification",
+                "gives new members a verified role once they react to a message or send back a code.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_command(true)
+            .with_member_join_hook(true)
+            .with_reaction_hook(true)
+            .with_config_value(config::Value::<VerificationConfig>::with_default(
+                VERIFICATION_KEY,
+                "A JSON object configuring the verification gate, e.g. {\"enabled\": true, \"role\": \"1234\", \"mode\": \"Reaction\", \"kick_after\": \"1d\"}. mode may be Reaction or Challenge.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let VerificationOpt::Post { text } = VerificationOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        // Taken from the message itself rather than the parsed words, to keep its formatting.
+        let text = if text.is_empty() {
+            DEFAULT_GATE_TEXT
+        } else {
+            text_after_words(&orig.content, 2).trim()
+        };
+
+        let gate = orig.channel_id.say(ctx, text).await?;
+        gate.react(ctx, ReactionType::Unicode(VERIFY_EMOJI.to_string())).await?;
+
+        let db = dis.db(gid);
+        let v = dis.config_value_t::<VerificationConfig>(VERIFICATION_KEY)?;
+        let mut conf = (*v.get_or_default(&db).await?).clone();
+        conf.message = Some(gate.id);
+        v.set(&db, conf).await?;
+        orig.delete(ctx).await.map_err(crate::error::Error::from).log_error();
+        Ok(())
+    }
+
+    async fn on_member_join(&self, dis: &Dispatch, ctx: &Context, member: &Member) -> crate::error::Result<()> {
+        if member.user.bot {
+            return Ok(());
+        }
+        let gid = member.guild_id;
+        let conf = verification_config(dis, gid).await?;
+        if !conf.enabled || conf.role.is_none() {
+            return Ok(());
+        }
+
+        if let Some(after) = conf.kick_after {
+            let after = chrono::Duration::from_std(after).unwrap_or_else(|_| *ONE_HUNDREDISH_YEARS);
+            Action::kick_unverified(member.user.id, gid, after)
+                .store_action(dis)
+                .await?;
+        }
+        if conf.mode == VerificationMode::Challenge {
+            if let Err(e) = send_challenge(dis, ctx, gid, member.user.id).await {
+                debug!("couldn't send verification code to {}: {}", member.user.id, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_reaction(&self, dis: &Dispatch, ctx: &Context, reaction: &Reaction) -> crate::error::Result<()> {
+        let (gid, user) = match (reaction.guild_id, reaction.user_id) {
+            (Some(g), Some(u)) => (g, u),
+            _ => return Ok(()),
+        };
+        if !matches!(&reaction.emoji, ReactionType::Unicode(s) if s == VERIFY_EMOJI) {
+            return Ok(());
+        }
+        let conf = verification_config(dis, gid).await?;
+        if !conf.enabled || conf.mode != VerificationMode::Reaction || conf.message != Some(reaction.message_id) {
+            return Ok(());
+        }
+        let role = conf.role.ok_or(NoVerifiedRole)?;
+        verify(dis, ctx, gid, user, role).await
+    }
+}
+
+/// Lets members verify themselves with the code they were sent, e.g. `verify ABC123`. Without a
+/// code, sends a new one.
+#[derive(StructOpt)]
+#[structopt(name = "verify", no_version)]
+struct VerifyOpt {
+    /// The code from the DM you were sent.
+    code: Option<String>,
+}
+
+/// Module which lets members send back their verification code.
+pub struct VerifyModule;
+
+#[async_trait::async_trait]
+impl Module for VerifyModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("verify", "verifies you with the code you were DMed when you joined.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = VerifyOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let conf = verification_config(dis, gid).await?;
+        if !conf.enabled || conf.mode != VerificationMode::Challenge {
+            return Err(NotUsingChallenges.into());
+        }
+        let role = conf.role.ok_or(NoVerifiedRole)?;
+
+        // Whether or not it worked, the code shouldn't sit in the channel for others to copy.
+        orig.delete(ctx).await.map_err(crate::error::Error::from).log_error();
+        match opts.code {
+            None => {
+                send_challenge(dis, ctx, gid, orig.author.id).await?;
+                orig.channel_id
+                    .say(ctx, "I've sent you a new code in your DMs.")
+                    .await?;
+            }
+            Some(code) => {
+                if !Challenges::new(dis.db(gid)).answer(orig.author.id, &code).await? {
+                    return Err(WrongVerificationCode.into());
+                }
+                verify(dis, ctx, gid, orig.author.id, role).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Contains the `version` command, which describes the build Glimbot is running: its version and
+//! commit, how it was built, the modules it has installed, and the database schema it's using.
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::utils::MessageBuilder;
+
+use crate::about;
+use crate::dispatch::Dispatch;
+use crate::module::status::GLIM_COLOR;
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// Describes the database's schema version, noting when it doesn't match the one this binary
+/// expects.
+pub async fn describe_schema(dis: &Dispatch) -> crate::error::Result<String> {
+    let storage = dis.storage();
+    let latest = storage.migrations().iter().map(|m| m.version).max();
+    let describe = |v: Option<i64>| v.map_or_else(|| "none".to_string(), |v| v.to_string());
+    Ok(match storage.migration_state().await? {
+        Some((v, true)) => format!("{} (failed partway through)", v),
+        current => {
+            let current = current.map(|(v, _)| v);
+            if current == latest {
+                describe(current)
+            } else {
+                format!("{} (this build expects {})", describe(current), describe(latest))
+            }
+        }
+    })
+}
+
+/// Describes Glimbot's version and the commit it was built from.
+pub fn describe_version() -> String {
+    format!("{} ({})", about::VERSION, about::GIT_HASH)
+}
+
+/// Adds the `version` command.
+pub struct VersionModule;
+
+#[async_trait::async_trait]
+impl Module for VersionModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "version",
+                "shows which build of glimbot is running and how it was built.",
+            )
+            .with_sensitivity(Sensitivity::Low)
+            .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(&self, dis: &Dispatch, ctx: &Context, orig: &Message, _: Vec<String>) -> crate::error::Result<()> {
+        let schema = describe_schema(dis).await?;
+        let modules = dis
+            .modules()
+            .map(|m| format!("{} {}", m.info().name, m.info().version))
+            .join("\n");
+        let modules = MessageBuilder::new().push_codeblock_safe(modules, None).build();
+
+        orig.channel_id
+            .send_message(ctx, |e| {
+                e.embed(|emb| {
+                    emb.color(GLIM_COLOR)
+                        .title("Glimbot Version")
+                        .url(about::REPO_URL)
+                        .field("Version", describe_version(), false)
+                        .field("Built", about::build_date(), true)
+                        .field("Compiler", about::RUSTC_VERSION, true)
+                        .field("Features", about::features(), true)
+                        .field("DB Schema", schema, false)
+                        .field("Modules", modules, false)
+                })
+                .reference_message(orig)
+            })
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,446 @@
+//! Contains voice hubs: voice channels which, when joined, spawn a temporary voice channel owned by
+//! the member who joined and move them into it. Owners can rename, limit and lock their channel
+//! with the `voice` command, and channels are deleted once everyone has left. Temporary channels are
+//! tracked in the database, so ones left behind across a restart are still cleaned up.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serenity::client::Context;
+use serenity::model::channel::{ChannelType, Message, PermissionOverwrite, PermissionOverwriteType};
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::model::permissions::Permissions;
+use serenity::model::voice::VoiceState;
+use structopt::StructOpt;
+
+use crate::db::DbContext;
+use crate::dispatch::config;
+use crate::dispatch::Dispatch;
+use crate::error::{GuildNotInCache, LogErrorExt};
+use crate::module::persona::react_success;
+use crate::module::{ModInfo, Module, Sensitivity};
+use crate::util::constraints::AtMostU64;
+use crate::util::overwrite::{denying_overwrite, StoredOverwrite};
+use crate::util::{text_after_words, ClapExt};
+
+/// The config key for grabbing a [`VoiceHubConfig`].
+pub const VOICE_HUBS_KEY: &str = "voice_hubs";
+/// The most temporary channels a guild may have at once.
+pub const MAX_TEMP_CHANNELS: i64 = 50;
+/// The longest channel name Discord allows.
+pub const MAX_CHANNEL_NAME_LEN: usize = 100;
+/// How often temporary channels left empty while Glimbot wasn't watching are swept up.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+impl_err!(
+    NoTempChannel,
+    "You don't own a temporary voice channel. Join a voice hub to make one.",
+    true
+);
+impl_err!(
+    InvalidTempChannelName,
+    "Channel names must be between 1 and 100 characters long.",
+    true
+);
+impl_err!(
+    TooManyTempChannels,
+    "This server has the maximum of 50 temporary voice channels.",
+    true
+);
+
+/// Configuration for voice hubs.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VoiceHubConfig {
+    /// The voice channels which spawn temporary channels when joined.
+    #[serde(default)]
+    pub hubs: Vec<ChannelId>,
+    /// The name given to new channels. `{user}` is replaced with the owner's display name.
+    #[serde(default = "default_name")]
+    pub name: String,
+}
+
+#[doc(hidden)]
+fn default_name() -> String {
+    "{user}'s channel".to_string()
+}
+
+impl Default for VoiceHubConfig {
+    fn default() -> Self {
+        Self {
+            hubs: Vec::new(),
+            name: default_name(),
+        }
+    }
+}
+
+impl FromStr for VoiceHubConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for VoiceHubConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// Wrapper around a guild's temporary voice channels in the database.
+pub struct TempChannels<'pool> {
+    #[doc(hidden)]
+    ctx: DbContext<'pool>,
+}
+
+impl<'pool> TempChannels<'pool> {
+    /// Creates a wrapper around the database context.
+    pub fn new(ctx: impl Borrow<DbContext<'pool>>) -> Self {
+        TempChannels {
+            ctx: ctx.borrow().clone(),
+        }
+    }
+
+    /// Records a new temporary channel.
+    pub async fn add(&self, channel: ChannelId, owner: UserId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "INSERT INTO temp_voice_channels (guild, channel_id, owner) VALUES ($1, $2, $3);",
+            self.ctx.guild_as_i64(),
+            channel.0 as i64,
+            owner.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+
+    /// Counts the guild's temporary channels.
+    pub async fn count(&self) -> crate::error::Result<i64> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM temp_voice_channels WHERE guild = $1;",
+            self.ctx.guild_as_i64()
+        )
+        .fetch_one(self.ctx.conn()?)
+        .await?;
+        Ok(count.unwrap_or_default())
+    }
+
+    /// Retrieves the temporary channel a member owns, if any.
+    pub async fn owned_by(&self, owner: UserId) -> crate::error::Result<Option<ChannelId>> {
+        let channel = sqlx::query_scalar!(
+            "SELECT channel_id FROM temp_voice_channels WHERE guild = $1 AND owner = $2;",
+            self.ctx.guild_as_i64(),
+            owner.0 as i64
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+        Ok(channel.map(|c| ChannelId(c as u64)))
+    }
+
+    /// Checks whether a channel is a temporary channel.
+    pub async fn is_temp(&self, channel: ChannelId) -> crate::error::Result<bool> {
+        let row = sqlx::query_scalar!(
+            "SELECT channel_id FROM temp_voice_channels WHERE guild = $1 AND channel_id = $2;",
+            self.ctx.guild_as_i64(),
+            channel.0 as i64
+        )
+        .fetch_optional(self.ctx.conn()?)
+        .await?;
+        Ok(row.is_some())
+    }
+
+    /// Forgets a temporary channel.
+    pub async fn remove(&self, channel: ChannelId) -> crate::error::Result<()> {
+        sqlx::query!(
+            "DELETE FROM temp_voice_channels WHERE guild = $1 AND channel_id = $2;",
+            self.ctx.guild_as_i64(),
+            channel.0 as i64
+        )
+        .execute(self.ctx.conn()?)
+        .await?;
+        Ok(())
+    }
+}
+
+/// Counts the members in a voice channel, or returns `None` if the guild isn't cached.
+async fn occupants(ctx: &Context, guild: GuildId, channel: ChannelId) -> Option<usize> {
+    ctx.cache
+        .guild_field(guild, |g| {
+            g.voice_states
+                .values()
+                .filter(|v| v.channel_id == Some(channel))
+                .count()
+        })
+        .await
+}
+
+/// Deletes a temporary channel if everyone has left it.
+async fn delete_if_empty(
+    dis: &Dispatch,
+    ctx: &Context,
+    guild: GuildId,
+    channel: ChannelId,
+) -> crate::error::Result<()> {
+    let temp = TempChannels::new(dis.db(guild));
+    if !temp.is_temp(channel).await? || occupants(ctx, guild, channel).await != Some(0) {
+        return Ok(());
+    }
+
+    debug!("deleting empty temporary channel {}", channel);
+    temp.remove(channel).await?;
+    channel.delete(ctx).await?;
+    Ok(())
+}
+
+/// Moves a member who joined a hub into their temporary channel, making one if they don't have one.
+async fn spawn(
+    dis: &Dispatch,
+    ctx: &Context,
+    guild: GuildId,
+    hub: ChannelId,
+    state: &VoiceState,
+    conf: &VoiceHubConfig,
+) -> crate::error::Result<()> {
+    let db = dis.db(guild);
+    let temp = TempChannels::new(&db);
+    let user = state.user_id;
+
+    if let Some(existing) = temp.owned_by(user).await? {
+        if ctx.cache.guild_channel(existing).await.is_some() {
+            guild.move_member(ctx, user, existing).await?;
+            return Ok(());
+        }
+        temp.remove(existing).await?;
+    }
+    if temp.count().await? >= MAX_TEMP_CHANNELS {
+        return Err(TooManyTempChannels.into());
+    }
+
+    let hub = ctx.cache.guild_channel(hub).await.ok_or(GuildNotInCache)?;
+    let owner_name = match &state.member {
+        Some(m) => m.display_name().into_owned(),
+        None => guild.member(ctx, user).await?.display_name().into_owned(),
+    };
+    let name: String = conf
+        .name
+        .replace("{user}", &owner_name)
+        .chars()
+        .take(MAX_CHANNEL_NAME_LEN)
+        .collect();
+
+    // New channels copy the hub's overwrites, so a hub in a private category spawns private channels.
+    let mut overwrites = hub.permission_overwrites.clone();
+    overwrites.push(PermissionOverwrite {
+        allow: Permissions::CONNECT,
+        deny: Permissions::empty(),
+        kind: PermissionOverwriteType::Member(user),
+    });
+    let channel = guild
+        .create_channel(ctx, |c| {
+            c.name(&name).kind(ChannelType::Voice).permissions(overwrites);
+            if let Some(category) = hub.category_id {
+                c.category(category);
+            }
+            c
+        })
+        .await?;
+    temp.add(channel.id, user).await?;
+
+    if let Err(e) = guild.move_member(ctx, user, channel.id).await {
+        debug!("couldn't move {} into their new channel: {}", user, e);
+        delete_if_empty(dis, ctx, guild, channel.id).await?;
+    }
+    Ok(())
+}
+
+/// Deletes temporary channels which emptied, or were deleted by hand, while Glimbot wasn't watching.
+async fn sweep(dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+    let rows = sqlx::query!("SELECT guild, channel_id FROM temp_voice_channels;")
+        .fetch_all(dis.pool()?)
+        .await?;
+    for r in rows {
+        let guild = GuildId(r.guild as u64);
+        let channel = ChannelId(r.channel_id as u64);
+        if ctx.cache.guild_field(guild, |_| ()).await.is_none() {
+            continue;
+        }
+        if ctx.cache.guild_channel(channel).await.is_none() {
+            TempChannels::new(dis.db(guild)).remove(channel).await.log_error();
+            continue;
+        }
+        delete_if_empty(dis, ctx, guild, channel).await.log_error();
+    }
+    Ok(())
+}
+
+/// Module which spawns temporary voice channels from voice hubs.
+#[derive(Default)]
+pub struct VoiceHubModule {
+    /// When temporary channels were last swept up.
+    last_sweep: Mutex<Option<Instant>>,
+}
+
+impl VoiceHubModule {
+    /// Checks whether a sweep is due, marking one as done if so.
+    fn sweep_due(&self) -> bool {
+        let mut last_sweep = self.last_sweep.lock();
+        match *last_sweep {
+            Some(t) if t.elapsed() < SWEEP_INTERVAL => false,
+            _ => {
+                *last_sweep = Some(Instant::now());
+                true
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Module for VoiceHubModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "voice-hubs",
+                "gives members who join a voice hub a temporary voice channel of their own.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_voice_state_hook(true)
+            .with_tick_hook(true)
+            .with_config_value(config::Value::<VoiceHubConfig>::with_default(
+                VOICE_HUBS_KEY,
+                "A JSON object listing voice hubs, e.g. {\"hubs\": [\"1234\"], \"name\": \"{user}'s channel\"}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_voice_state(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        guild: GuildId,
+        old: Option<&VoiceState>,
+        new: &VoiceState,
+    ) -> crate::error::Result<()> {
+        let left = old.and_then(|o| o.channel_id).filter(|c| Some(*c) != new.channel_id);
+        if let Some(left) = left {
+            delete_if_empty(dis, ctx, guild, left).await?;
+        }
+
+        let joined = match new.channel_id {
+            Some(c) if old.and_then(|o| o.channel_id) != Some(c) => c,
+            _ => return Ok(()),
+        };
+        if new.member.as_ref().map_or(false, |m| m.user.bot) {
+            return Ok(());
+        }
+        let conf = dis
+            .config_value_t::<VoiceHubConfig>(VOICE_HUBS_KEY)?
+            .get_or_default(&dis.db(guild))
+            .await?;
+        if !conf.hubs.contains(&joined) {
+            return Ok(());
+        }
+        spawn(dis, ctx, guild, joined, new, &conf).await
+    }
+
+    async fn on_tick(&self, dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        if !self.sweep_due() {
+            return Ok(());
+        }
+        sweep(dis, ctx).await
+    }
+}
+
+/// Stops `@everyone` from joining a temporary channel, or lets them again, keeping the rest of its
+/// overwrite.
+async fn set_locked(ctx: &Context, channel: ChannelId, everyone: RoleId, locked: bool) -> crate::error::Result<()> {
+    let current = ctx.cache.guild_channel(channel).await.ok_or(NoTempChannel)?;
+    let previous = StoredOverwrite::of_role(&current, everyone);
+    let mut overwrite = denying_overwrite(everyone, previous, Permissions::CONNECT);
+    if !locked {
+        overwrite.deny.remove(Permissions::CONNECT);
+    }
+    channel.create_permission(ctx, &overwrite).await?;
+    Ok(())
+}
+
+/// Manages the temporary voice channel you own.
+#[derive(StructOpt)]
+#[structopt(name = "voice", no_version)]
+enum VoiceOpt {
+    /// Renames your channel.
+    Rename {
+        /// The new name.
+        name: Vec<String>,
+    },
+    /// Limits how many members can join your channel. 0 removes the limit.
+    Limit {
+        /// The most members who can join, up to 99.
+        limit: AtMostU64<99>,
+    },
+    /// Stops anyone else from joining your channel. Members already in it can stay.
+    Lock,
+    /// Lets anyone join your channel again.
+    Unlock,
+}
+
+/// Module which lets members manage their temporary voice channels.
+pub struct VoiceModule;
+
+#[async_trait::async_trait]
+impl Module for VoiceModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name("voice", "renames, limits or locks the temporary voice channel you own.")
+                .with_sensitivity(Sensitivity::Low)
+                .with_command(true)
+        });
+        &INFO
+    }
+
+    async fn process(
+        &self,
+        dis: &Dispatch,
+        ctx: &Context,
+        orig: &Message,
+        command: Vec<String>,
+    ) -> crate::error::Result<()> {
+        let opts = VoiceOpt::from_iter_with_help(command)?;
+        let gid = orig.guild_id.unwrap();
+        let channel = TempChannels::new(dis.db(gid))
+            .owned_by(orig.author.id)
+            .await?
+            .ok_or(NoTempChannel)?;
+        let everyone = RoleId(gid.0);
+
+        match opts {
+            VoiceOpt::Rename { name } => {
+                let name = if name.is_empty() {
+                    ""
+                } else {
+                    text_after_words(&orig.content, 2).trim()
+                };
+                if name.is_empty() || name.chars().count() > MAX_CHANNEL_NAME_LEN {
+                    return Err(InvalidTempChannelName.into());
+                }
+                channel.edit(ctx, |c| c.name(name)).await?;
+            }
+            VoiceOpt::Limit { limit } => {
+                channel.edit(ctx, |c| c.user_limit(limit.into())).await?;
+            }
+            VoiceOpt::Lock => set_locked(ctx, channel, everyone, true).await?,
+            VoiceOpt::Unlock => set_locked(ctx, channel, everyone, false).await?,
+        }
+        react_success(dis, ctx, orig).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! Contains the wall-of-text filter, which deals with messages that are too many lines or
+//! characters long, whether pasted logs or newline spam meant to push the conversation off screen.
+//! Guilds which only mind the clutter can have such messages turned into text file attachments
+//! instead of removed.
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::str::FromStr;
+
+use once_cell::sync::Lazy;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::ChannelId;
+use serenity::model::misc::Mentionable;
+
+use crate::dispatch::config;
+use crate::dispatch::jobs::{Job, JobPriority, OverloadPolicy};
+use crate::dispatch::Dispatch;
+use crate::module::filter::{handle_filtered, FilterAction, FilterModule};
+use crate::module::{ModInfo, Module, Sensitivity};
+
+/// The config key for grabbing a [`WallOfTextConfig`].
+pub const WALL_OF_TEXT_KEY: &str = "wall_of_text_filter";
+/// Default number of lines a message may span.
+pub const DEFAULT_MAX_LINES: usize = 30;
+/// Default number of characters a message may contain.
+pub const DEFAULT_MAX_CHARS: usize = 1800;
+
+/// Configuration for the wall-of-text filter.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WallOfTextConfig {
+    /// Whether the wall-of-text filter is on.
+    #[serde(default)]
+    pub enabled: bool,
+    /// The most lines a message may span. 0 turns the check off.
+    #[serde(default = "default_max_lines")]
+    pub max_lines: usize,
+    /// The most characters, in UTF-8 code points, a message may contain. 0 turns the check off.
+    #[serde(default = "default_max_chars")]
+    pub max_chars: usize,
+    /// Whether long messages are reposted as a text file instead of being removed. Messages with
+    /// attachments of their own are always removed, since reposting them would lose the attachments.
+    #[serde(default)]
+    pub convert_to_file: bool,
+    /// Channels long messages may be posted in.
+    #[serde(default)]
+    pub exempt_channels: Vec<ChannelId>,
+    /// What happens to the author of a removed message.
+    #[serde(default = "default_action")]
+    pub action: FilterAction,
+}
+
+#[doc(hidden)]
+fn default_max_lines() -> usize {
+    DEFAULT_MAX_LINES
+}
+
+#[doc(hidden)]
+fn default_max_chars() -> usize {
+    DEFAULT_MAX_CHARS
+}
+
+#[doc(hidden)]
+fn default_action() -> FilterAction {
+    FilterAction::Delete
+}
+
+impl Default for WallOfTextConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_lines: DEFAULT_MAX_LINES,
+            max_chars: DEFAULT_MAX_CHARS,
+            convert_to_file: false,
+            exempt_channels: Vec::new(),
+            action: default_action(),
+        }
+    }
+}
+
+impl FromStr for WallOfTextConfig {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl fmt::Display for WallOfTextConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = serde_json::to_string_pretty(self).unwrap_or_else(|_| "{}".to_string());
+        write!(f, "{}", s)
+    }
+}
+
+/// A long message to be reposted as a text file.
+struct ConvertedMessage {
+    /// The long message.
+    message: Message,
+}
+
+#[async_trait::async_trait]
+impl Job for ConvertedMessage {
+    fn name(&self) -> &'static str {
+        "wall-of-text-convert"
+    }
+
+    fn priority(&self) -> JobPriority {
+        JobPriority::High
+    }
+
+    fn overload_policy(&self) -> OverloadPolicy {
+        OverloadPolicy::DropOldest
+    }
+
+    async fn run(self: Box<Self>, _dis: &Dispatch, ctx: &Context) -> crate::error::Result<()> {
+        let msg = &self.message;
+        let name = format!("message-{}.txt", msg.id);
+        msg.channel_id
+            .send_files(ctx, vec![(msg.content.as_bytes(), name.as_str())], |m| {
+                m.content(format!(
+                    "{} sent a long message, so it's been turned into a file.",
+                    msg.author.id.mention()
+                ))
+            })
+            .await?;
+        msg.delete(ctx).await?;
+        Ok(())
+    }
+}
+
+/// Module which deals with overly long messages.
+pub struct WallOfTextModule;
+
+#[async_trait::async_trait]
+impl Module for WallOfTextModule {
+    fn info(&self) -> &ModInfo {
+        #[doc(hidden)]
+        static INFO: Lazy<ModInfo> = Lazy::new(|| {
+            ModInfo::with_name(
+                "wall-of-text-filter",
+                "removes messages with too many lines or characters, or turns them into text files.",
+            )
+            .with_sensitivity(Sensitivity::High)
+            .with_message_hook(true)
+            .with_config_value(config::Value::<WallOfTextConfig>::with_default(
+                WALL_OF_TEXT_KEY,
+                "A JSON object configuring the wall-of-text filter, e.g. {\"enabled\": true, \"max_lines\": 30, \"max_chars\": 1800, \"convert_to_file\": false, \"exempt_channels\": [], \"action\": \"Delete\"}.",
+                Default::default,
+            ))
+        });
+        &INFO
+    }
+
+    async fn on_message(&self, dis: &Dispatch, ctx: &Context, orig: &Message) -> crate::error::Result<()> {
+        let gid = match orig.guild_id {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+        if orig.content.is_empty() {
+            return Ok(());
+        }
+
+        let config = dis
+            .config_value_t::<WallOfTextConfig>(WALL_OF_TEXT_KEY)?
+            .get_or_default(&dis.db(gid))
+            .await?;
+        if !config.enabled || config.exempt_channels.contains(&orig.channel_id) {
+            return Ok(());
+        }
+
+        let lines = orig.content.lines().count();
+        let chars = orig.content.chars().count();
+        let why = if config.max_lines > 0 && lines > config.max_lines {
+            format!("was {} lines long", lines)
+        } else if config.max_chars > 0 && chars > config.max_chars {
+            format!("was {} characters long", chars)
+        } else {
+            return Ok(());
+        };
+
+        if FilterModule::is_exempt(dis, ctx, orig, gid, self.info().name).await? {
+            trace!("exempt user posted a wall of text");
+            return Ok(());
+        }
+
+        if config.convert_to_file && orig.attachments.is_empty() {
+            debug!("message {}; converting it to a file", why);
+            dis.jobs().enqueue(gid, ConvertedMessage { message: orig.clone() });
+            return Ok(());
+        }
+
+        debug!("message {}", why);
+        handle_filtered(dis, orig, self.info().name, why, config.action).await
+    }
+}
@@ -2,7 +2,11 @@
 
 use serenity::client::bridge::gateway::GatewayIntents;
 
+use serenity::model::id::{GuildId, RoleId, UserId};
+
+use crate::db::operators::Operators;
 use crate::dispatch::{ArcDispatch, ShardManKey};
+use crate::error::LogErrorExt;
 use crate::module::status::START_TIME;
 use once_cell::sync::Lazy;
 use tokio::sync::broadcast;
@@ -14,35 +18,127 @@ pub static PANIC_ALERT_CHANNEL: Lazy<(broadcast::Sender<()>, broadcast::Receiver
 /// Starts Glimbot.
 /// This is where modules are loaded.
 pub async fn start_bot() -> crate::error::Result<()> {
-    let pool = crate::db::create_pool().await?;
-    let mut dispatch = crate::dispatch::Dispatch::new(
-        std::env::var("GLIMBOT_OWNER")
-            .expect("Couldn't find owner information.")
-            .parse()
-            .expect("Invalid owner token."),
-        pool,
-    );
+    let storage = crate::db::storage::create().await?;
+    let owners: Vec<UserId> = std::env::var("GLIMBOT_OWNER")
+        .expect("Couldn't find owner information.")
+        .split(',')
+        .map(|o| o.trim().parse().expect("Invalid owner token."))
+        .collect();
+    let home_role = match (
+        std::env::var("GLIMBOT_HOME_GUILD"),
+        std::env::var("GLIMBOT_OPERATOR_ROLE"),
+    ) {
+        (Ok(g), Ok(r)) => Some((
+            GuildId(g.trim().parse().expect("Invalid home guild.")),
+            RoleId(r.trim().parse().expect("Invalid operator role.")),
+        )),
+        _ => None,
+    };
+    let mut dispatch = crate::dispatch::Dispatch::new(Operators::new(owners, home_role), storage);
+    if let Ok(ms) = std::env::var("GLIMBOT_SLOW_QUERY_MS") {
+        let ms = ms.trim().parse().expect("Invalid slow query threshold.");
+        dispatch
+            .query_stats()
+            .set_slow_threshold(std::time::Duration::from_millis(ms));
+    }
     dispatch.add_module(crate::module::base_filter::BaseFilter);
     dispatch.add_module(crate::module::owner::OwnerFilter);
     dispatch.add_module(crate::module::privilege::PrivilegeFilter);
     dispatch.add_module(crate::module::conf::ConfigModule);
+    dispatch.add_module(crate::module::setup::SetupModule::default());
+    dispatch.add_module(crate::module::defaults::DefaultsModule);
+    dispatch.add_module(crate::module::global::GlobalModule);
+    dispatch.add_module(crate::module::operators::OperatorsModule);
     dispatch.add_module(crate::module::status::StatusModule::default());
+    dispatch.add_module(crate::module::version::VersionModule);
+    dispatch.add_module(crate::module::selftest::SelfTestModule);
     dispatch.add_module(crate::module::roles::RoleModule);
+    dispatch.add_module(crate::module::tags::TagModule);
+    dispatch.add_module(crate::module::counters::CountersModule);
+    dispatch.add_module(crate::module::remind::RemindModule);
+    dispatch.add_module(crate::module::suggestions::SuggestModule);
+    dispatch.add_module(crate::module::suggestions::SuggestionModule);
+    dispatch.add_module(crate::module::economy::BalanceModule);
+    dispatch.add_module(crate::module::economy::DailyModule);
+    dispatch.add_module(crate::module::economy::GiveModule);
+    dispatch.add_module(crate::module::dice::DiceModule);
+    dispatch.add_module(crate::module::define::DefineModule);
+    dispatch.add_module(crate::module::time::TimeModule);
+    dispatch.add_module(crate::module::persona::PersonaModule);
+    dispatch.add_module(crate::module::afk::AfkModule::default());
+    dispatch.add_module(crate::module::bookmark::BookmarkModule::default());
     dispatch.add_module(crate::module::moderation::ModerationModule);
     dispatch.add_module(crate::module::spam::SpamModule::default());
+    dispatch.add_module(crate::module::automod::AutomodModule);
+    dispatch.add_module(crate::module::automod_sync::AutomodSyncModule);
+    dispatch.add_module(crate::module::filter::FilterModule::default());
+    dispatch.add_module(crate::module::regex_filter::RegexFilterModule);
+    dispatch.add_module(crate::module::invites::InviteFilterModule::default());
+    dispatch.add_module(crate::module::duplicates::DuplicateFilterModule);
+    dispatch.add_module(crate::module::emoji_filter::EmojiFilterModule);
+    dispatch.add_module(crate::module::wall_of_text::WallOfTextModule);
+    dispatch.add_module(crate::module::attachments::AttachmentFilterModule);
+    dispatch.add_module(crate::module::image_filter::ImageFilterModule);
+    dispatch.add_module(crate::module::phishing::PhishingFilterModule);
+    dispatch.add_module(crate::module::scoring::SpamScoringModule);
+    dispatch.add_module(crate::module::raid::RaidModule::default());
+    dispatch.add_module(crate::module::raidmode::RaidModeModule);
+    dispatch.add_module(crate::module::slowmode::AutoSlowmodeModule::default());
+    dispatch.add_module(crate::module::account_age::AccountAgeGateModule);
+    dispatch.add_module(crate::module::verification::VerificationModule);
+    dispatch.add_module(crate::module::verification::VerifyModule);
+    dispatch.add_module(crate::module::persistent_roles::RolePersistenceModule);
+    dispatch.add_module(crate::module::autorole::AutoroleModule);
+    dispatch.add_module(crate::module::nicknames::NicknameModule);
     dispatch.add_module(crate::module::shutdown::Shutdown);
+    dispatch.add_module(crate::module::cache::CacheModule);
+    dispatch.add_module(crate::module::purge::PurgeModule);
+    dispatch.add_module(crate::module::queries::QueriesModule);
+    dispatch.add_module(crate::module::quota::QuotaModule);
+    dispatch.add_module(crate::module::export::ExportModule);
     dispatch.add_module(crate::module::roles::ModRoleModule);
+    dispatch.add_module(crate::module::roles::TempRoleModule);
+    dispatch.add_module(crate::module::archive::ArchiveModule::default());
+    dispatch.add_module(crate::module::archive::UnarchiveModule);
+    dispatch.add_module(crate::module::lockdown::LockdownModule);
+    dispatch.add_module(crate::module::emoji::EmojiModule);
+    dispatch.add_module(crate::module::auto_publish::AutoPublishModule::default());
+    dispatch.add_module(crate::module::threads::ThreadsModule);
+    dispatch.add_module(crate::module::voice_hubs::VoiceHubModule::default());
+    dispatch.add_module(crate::module::voice_hubs::VoiceModule);
+    dispatch.add_module(crate::module::transcript::TranscriptModule);
+    dispatch.add_module(crate::module::ban_sync::BanSyncModule::default());
+    dispatch.add_module(crate::module::audit::AuditModule);
+    dispatch.add_module(crate::module::growth::GrowthModule);
+    dispatch.add_module(crate::module::activity::ActivityModule::default());
+    dispatch.add_module(crate::module::stats_channels::StatsChannelsModule::default());
     dispatch.add_module(crate::module::mock_raid::MockRaidModule::default());
     dispatch.add_module(crate::module::info::HelpModule);
 
+    // Instance-wide settings are kept in Postgres. Without it, only the owners and operator role
+    // from the environment apply.
+    if let Ok(pool) = dispatch.pool() {
+        dispatch.instance_defaults().load(pool).await?;
+        dispatch.global_config().load(pool).await?;
+        dispatch.operators().load(pool).await?;
+        dispatch.phishing_domains().load(pool).await?;
+    } else {
+        info!(
+            "Keeping data in {}; features which need PostgreSQL are unavailable.",
+            dispatch.storage().name()
+        );
+    }
     let dispatch = ArcDispatch::from(dispatch);
+    let flush_handle = dispatch.clone();
 
     let mut client = serenity::Client::builder(std::env::var("GLIMBOT_TOKEN").expect("Didn't find a token."))
         .intents(
             GatewayIntents::privileged()
                 | GatewayIntents::GUILD_MESSAGES
+                | GatewayIntents::GUILD_MESSAGE_REACTIONS
                 | GatewayIntents::GUILD_BANS
                 | GatewayIntents::GUILDS
+                | GatewayIntents::GUILD_VOICE_STATES
                 | GatewayIntents::DIRECT_MESSAGES,
         )
         .event_handler(dispatch)
@@ -70,6 +166,14 @@ pub async fn start_bot() -> crate::error::Result<()> {
 
     dg.insert::<ShardManKey>(shard_man);
     std::mem::drop(dg);
-    client.start_autosharded().await?;
+    let res = client.start_autosharded().await;
+
+    info!("Flushing buffered config writes.");
+    flush_handle
+        .config_writes()
+        .flush(flush_handle.storage())
+        .await
+        .log_error();
+    res?;
     Ok(())
 }
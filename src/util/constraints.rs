@@ -1,4 +1,5 @@
-//! Contains constrained integer types, mostly useful as hard limits in module options.
+//! Contains constrained integer and duration types, mostly useful as hard limits in module options
+//! and config values.
 
 #![allow(clippy::from_over_into)]
 
@@ -6,10 +7,58 @@ use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::Formatter;
 use std::str::FromStr;
+use std::time::Duration;
 
 use crate::error::Error;
 use num::ToPrimitive;
 
+/// Describes the numbers allowed by an unsigned constraint, like "Expected a number from 1 to 10".
+fn describe_range_u64(min: u64, max: u64) -> String {
+    if max == u64::MAX {
+        format!("Expected a number of at least {}", min)
+    } else {
+        format!("Expected a number from {} to {}", min, max)
+    }
+}
+
+/// Describes the numbers allowed by a signed constraint, like "Expected a number from -5 to 5".
+fn describe_range_i64(min: i64, max: i64) -> String {
+    match (min == i64::MIN, max == i64::MAX) {
+        (true, true) => "Expected a number".to_string(),
+        (false, true) => format!("Expected a number of at least {}", min),
+        (true, false) => format!("Expected a number of at most {}", max),
+        (false, false) => format!("Expected a number from {} to {}", min, max),
+    }
+}
+
+/// Error returned when a constrained number can't be parsed at all.
+#[derive(Debug)]
+pub struct InvalidNumber {
+    /// A description of the numbers allowed.
+    expected: String,
+    /// What was given instead.
+    got: String,
+}
+
+impl InvalidNumber {
+    /// Creates an error for input which should have been a number in the described range.
+    fn new(expected: String, got: &str) -> Self {
+        Self {
+            expected,
+            got: got.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for InvalidNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, got \"{}\"", self.expected, self.got)
+    }
+}
+
+impl std::error::Error for InvalidNumber {}
+impl_user_err_from!(InvalidNumber);
+
 /// A constrained unsigned 64-bit integer. It is guaranteed to be no more than `MAX` and no less than
 /// `MIN`.
 #[derive(Debug, Default, Hash, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
@@ -35,11 +84,7 @@ impl<const MIN: u64, const MAX: u64> ConstraintFailureU64<MIN, MAX> {
 
 impl<const MIN: u64, const MAX: u64> fmt::Display for ConstraintFailureU64<MIN, MAX> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        if MAX == u64::MAX {
-            write!(f, "Expected number greater than {}, got {}", MIN, self.val)
-        } else {
-            write!(f, "Expected number in range [{}, {}], got {}", MIN, MAX, self.val)
-        }
+        write!(f, "{}, got {}", describe_range_u64(MIN, MAX), self.val)
     }
 }
 
@@ -99,11 +144,7 @@ impl<const MIN: i64, const MAX: i64> ConstraintFailureI64<MIN, MAX> {
 
 impl<const MIN: i64, const MAX: i64> fmt::Display for ConstraintFailureI64<MIN, MAX> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        if MAX == i64::MAX {
-            write!(f, "Expected number greater than {}, got {}", MIN, self.val)
-        } else {
-            write!(f, "Expected number in range [{}, {}], got {}", MIN, MAX, self.val)
-        }
+        write!(f, "{}, got {}", describe_range_i64(MIN, MAX), self.val)
     }
 }
 
@@ -151,9 +192,23 @@ impl<const MIN: u64, const MAX: u64> FromStr for ConstrainedU64<MIN, MAX> {
     type Err = crate::error::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.parse::<u64>()
-            .map_err(|e| Self::Err::from_err(e, true))
-            .and_then(|u| Self::try_from(u).map_err(Error::from))
+        let val = s
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| InvalidNumber::new(describe_range_u64(MIN, MAX), s))?;
+        Ok(Self::try_from(val)?)
+    }
+}
+
+impl<const MIN: i64, const MAX: i64> FromStr for ConstrainedI64<MIN, MAX> {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let val = s
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| InvalidNumber::new(describe_range_i64(MIN, MAX), s))?;
+        Ok(Self::try_from(val)?)
     }
 }
 
@@ -193,3 +248,135 @@ impl<const MIN: i64, const MAX: i64> ToPrimitive for ConstrainedI64<MIN, MAX> {
 pub type AtLeastI64<const MIN: i64> = ConstrainedI64<MIN, { i64::MAX }>;
 /// Same as [`AtMostU64`], but for signed values.
 pub type AtMostI64<const MAX: i64> = ConstrainedI64<{ i64::MIN }, MAX>;
+
+impl<const MIN: u64, const MAX: u64> serde::Serialize for ConstrainedU64<MIN, MAX> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.val)
+    }
+}
+
+impl<'de, const MIN: u64, const MAX: u64> serde::Deserialize<'de> for ConstrainedU64<MIN, MAX> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let val = <u64 as serde::Deserialize>::deserialize(deserializer)?;
+        Self::new(val).map_err(serde::de::Error::custom)
+    }
+}
+
+impl<const MIN: i64, const MAX: i64> serde::Serialize for ConstrainedI64<MIN, MAX> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(self.val)
+    }
+}
+
+impl<'de, const MIN: i64, const MAX: i64> serde::Deserialize<'de> for ConstrainedI64<MIN, MAX> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let val = <i64 as serde::Deserialize>::deserialize(deserializer)?;
+        Self::new(val).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A duration which is no shorter than `MIN_SECS` seconds and no longer than `MAX_SECS` seconds.
+/// It's parsed from and displayed as a human-readable duration like `1h 30m`, and is stored that way
+/// too, so it can be used directly as a config value.
+#[derive(Debug, Default, Hash, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
+pub struct ConstrainedDuration<const MIN_SECS: u64, const MAX_SECS: u64> {
+    /// The contained duration.
+    val: Duration,
+}
+
+/// See [`ConstraintFailureU64`]. This variation is for durations.
+#[derive(Debug)]
+pub struct ConstraintFailureDuration<const MIN_SECS: u64, const MAX_SECS: u64> {
+    /// The duration which violated the constraint.
+    val: Duration,
+}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> ConstraintFailureDuration<MIN_SECS, MAX_SECS> {
+    /// Creates a new constraint failure.
+    pub fn new(v: Duration) -> Self {
+        debug_assert!(v < Duration::from_secs(MIN_SECS) || v > Duration::from_secs(MAX_SECS));
+        Self { val: v }
+    }
+}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> fmt::Display for ConstraintFailureDuration<MIN_SECS, MAX_SECS> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let min = humantime::format_duration(Duration::from_secs(MIN_SECS));
+        let got = humantime::format_duration(self.val);
+        if MAX_SECS == u64::MAX {
+            write!(f, "Expected a duration of at least {}, got {}", min, got)
+        } else {
+            let max = humantime::format_duration(Duration::from_secs(MAX_SECS));
+            write!(f, "Expected a duration between {} and {}, got {}", min, max, got)
+        }
+    }
+}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> std::error::Error for ConstraintFailureDuration<MIN_SECS, MAX_SECS> {}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> From<ConstraintFailureDuration<MIN_SECS, MAX_SECS>> for Error {
+    fn from(e: ConstraintFailureDuration<MIN_SECS, MAX_SECS>) -> Self {
+        Error::from_err(e, true)
+    }
+}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> ConstrainedDuration<MIN_SECS, MAX_SECS> {
+    /// Creates a new constrained duration, returning an error if it falls outside the constraint.
+    pub fn new(val: Duration) -> Result<Self, ConstraintFailureDuration<MIN_SECS, MAX_SECS>> {
+        if val >= Duration::from_secs(MIN_SECS) && val <= Duration::from_secs(MAX_SECS) {
+            Ok(Self { val })
+        } else {
+            Err(ConstraintFailureDuration::new(val))
+        }
+    }
+}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> Into<Duration> for ConstrainedDuration<MIN_SECS, MAX_SECS> {
+    fn into(self) -> Duration {
+        self.val
+    }
+}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> TryFrom<Duration> for ConstrainedDuration<MIN_SECS, MAX_SECS> {
+    type Error = ConstraintFailureDuration<MIN_SECS, MAX_SECS>;
+
+    fn try_from(value: Duration) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> FromStr for ConstrainedDuration<MIN_SECS, MAX_SECS> {
+    type Err = crate::error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        humantime::parse_duration(s.trim())
+            .map_err(|e| Self::Err::from_err(e, true))
+            .and_then(|d| Self::try_from(d).map_err(Error::from))
+    }
+}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> fmt::Display for ConstrainedDuration<MIN_SECS, MAX_SECS> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", humantime::format_duration(self.val))
+    }
+}
+
+impl<const MIN_SECS: u64, const MAX_SECS: u64> serde::Serialize for ConstrainedDuration<MIN_SECS, MAX_SECS> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        humantime_serde::serialize(&self.val, serializer)
+    }
+}
+
+impl<'de, const MIN_SECS: u64, const MAX_SECS: u64> serde::Deserialize<'de>
+    for ConstrainedDuration<MIN_SECS, MAX_SECS>
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let val: Duration = humantime_serde::deserialize(deserializer)?;
+        Self::new(val).map_err(serde::de::Error::custom)
+    }
+}
+
+/// An alias for constrained durations which can be no shorter than `MIN_SECS` seconds.
+pub type AtLeastDuration<const MIN_SECS: u64> = ConstrainedDuration<MIN_SECS, { u64::MAX }>;
+/// An alias for constrained durations which can be no longer than `MAX_SECS` seconds.
+pub type AtMostDuration<const MAX_SECS: u64> = ConstrainedDuration<{ u64::MIN }, MAX_SECS>;
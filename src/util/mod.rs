@@ -10,6 +10,8 @@ use noisy_float::types::R64;
 pub mod clock;
 pub mod constraints;
 pub mod ordset;
+pub mod overwrite;
+pub mod transcript;
 
 /// An extension trait to allow for extraction of the help string from command invocations,
 /// as well as converting errors into Glimbot errors.
@@ -62,3 +64,52 @@ pub fn parse_nonnegative_real(s: &str) -> crate::error::Result<R64> {
         Ok(r)
     }
 }
+
+/// Returns the text of a message after its first `n` words, keeping its line breaks and spacing.
+/// Useful for commands which take free text, since splitting the command into arguments loses them.
+pub fn text_after_words(s: &str, n: usize) -> &str {
+    let mut rest = s.trim_start();
+    for _ in 0..n {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    rest.trim_end()
+}
+
+/// Characters used to draw sparklines, from lowest to highest.
+const SPARK_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Draws a sparkline of the given values.
+pub fn sparkline(values: &[i64]) -> String {
+    let min = values.iter().copied().min().unwrap_or_default();
+    let max = values.iter().copied().max().unwrap_or_default();
+    let range = (max - min).max(1) as f64;
+    values
+        .iter()
+        .map(|v| {
+            let idx = ((*v - min) as f64 / range * (SPARK_CHARS.len() - 1) as f64).round() as usize;
+            SPARK_CHARS[idx.min(SPARK_CHARS.len() - 1)]
+        })
+        .collect()
+}
+
+/// Responds to a button or select menu interaction with a message only the user who used it can
+/// see.
+pub async fn reply_ephemeral(
+    ctx: &serenity::client::Context,
+    interaction: &serenity::model::interactions::message_component::MessageComponentInteraction,
+    content: impl std::fmt::Display,
+) -> crate::error::Result<()> {
+    use serenity::model::interactions::{InteractionApplicationCommandCallbackDataFlags, InteractionResponseType};
+
+    interaction
+        .create_interaction_response(ctx, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| {
+                    d.content(content)
+                        .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                })
+        })
+        .await?;
+    Ok(())
+}
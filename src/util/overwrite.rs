@@ -0,0 +1,73 @@
+//! Contains helpers for temporarily denying permissions on a channel and later restoring the
+//! channel's permission overwrites exactly.
+
+use serenity::client::Context;
+use serenity::model::channel::{GuildChannel, PermissionOverwrite, PermissionOverwriteType};
+use serenity::model::id::{ChannelId, RoleId};
+use serenity::model::permissions::Permissions;
+
+/// A role's permission overwrite on a channel, as stored in the database.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+pub struct StoredOverwrite {
+    /// The bits of the allowed permissions.
+    pub allow: u64,
+    /// The bits of the denied permissions.
+    pub deny: u64,
+}
+
+impl StoredOverwrite {
+    /// Retrieves the overwrite for a role on a channel, if it has one.
+    pub fn of_role(channel: &GuildChannel, role: RoleId) -> Option<Self> {
+        channel
+            .permission_overwrites
+            .iter()
+            .find(|o| o.kind == PermissionOverwriteType::Role(role))
+            .map(|o| StoredOverwrite {
+                allow: o.allow.bits(),
+                deny: o.deny.bits(),
+            })
+    }
+}
+
+/// Builds an overwrite for a role which denies `perms`, preserving any other permissions in the
+/// previous overwrite.
+pub fn denying_overwrite(role: RoleId, previous: Option<StoredOverwrite>, perms: Permissions) -> PermissionOverwrite {
+    let (allow, deny) = previous.map_or((Permissions::empty(), Permissions::empty()), |p| {
+        (
+            Permissions::from_bits_truncate(p.allow),
+            Permissions::from_bits_truncate(p.deny),
+        )
+    });
+
+    PermissionOverwrite {
+        allow: allow - perms,
+        deny: deny | perms,
+        kind: PermissionOverwriteType::Role(role),
+    }
+}
+
+/// Restores a role's overwrite on a channel to a previously stored one, deleting the overwrite
+/// if the role didn't have one.
+pub async fn restore_overwrite(
+    ctx: &Context,
+    channel: ChannelId,
+    role: RoleId,
+    previous: Option<StoredOverwrite>,
+) -> crate::error::Result<()> {
+    match previous {
+        Some(p) => {
+            let overwrite = PermissionOverwrite {
+                allow: Permissions::from_bits_truncate(p.allow),
+                deny: Permissions::from_bits_truncate(p.deny),
+                kind: PermissionOverwriteType::Role(role),
+            };
+            channel.create_permission(ctx, &overwrite).await?;
+        }
+        None => {
+            channel
+                .delete_permission(ctx, PermissionOverwriteType::Role(role))
+                .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,247 @@
+//! Contains transcripts: snapshots of a channel's message history which can be stored or rendered
+//! to HTML for review.
+
+use std::fmt::Write;
+
+use chrono::Utc;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+
+use crate::dispatch::Dispatch;
+
+/// The number of messages fetched per REST call; this is Discord's maximum.
+pub const MESSAGES_PER_REQUEST: u64 = 100;
+
+/// A single message in a transcript.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TranscriptMessage {
+    /// The id of the message.
+    pub id: MessageId,
+    /// The author of the message.
+    pub author: UserId,
+    /// The author's username and discriminator at the time the transcript was made.
+    pub author_name: String,
+    /// When the message was sent.
+    pub timestamp: chrono::DateTime<Utc>,
+    /// When the message was last edited, if ever.
+    pub edited: Option<chrono::DateTime<Utc>>,
+    /// The content of the message.
+    pub content: String,
+    /// URLs of any attachments on the message.
+    pub attachments: Vec<String>,
+}
+
+impl From<&Message> for TranscriptMessage {
+    fn from(m: &Message) -> Self {
+        TranscriptMessage {
+            id: m.id,
+            author: m.author.id,
+            author_name: m.author.tag(),
+            timestamp: m.timestamp,
+            edited: m.edited_timestamp,
+            content: m.content.clone(),
+            attachments: m.attachments.iter().map(|a| a.url.clone()).collect(),
+        }
+    }
+}
+
+/// A snapshot of part of a channel's history.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Transcript {
+    /// The guild the channel belongs to.
+    pub guild: GuildId,
+    /// The channel the messages were sent in.
+    pub channel: ChannelId,
+    /// The name of the channel when the transcript was made.
+    pub channel_name: String,
+    /// When the transcript was made.
+    pub generated: chrono::DateTime<Utc>,
+    /// The messages in the transcript, oldest first.
+    pub messages: Vec<TranscriptMessage>,
+}
+
+/// The span of a channel's history a transcript should cover.
+#[derive(Debug, Copy, Clone)]
+pub struct TranscriptRange {
+    /// Only include messages sent after this one.
+    pub after: Option<MessageId>,
+    /// Only include messages sent before this one.
+    pub before: Option<MessageId>,
+    /// The maximum number of messages to include. The most recent messages in the range are kept.
+    pub limit: u64,
+}
+
+impl TranscriptRange {
+    /// A range covering up to `limit` of the most recent messages in a channel.
+    pub fn latest(limit: u64) -> Self {
+        TranscriptRange {
+            after: None,
+            before: None,
+            limit,
+        }
+    }
+
+    /// Whether a message falls within the range, ignoring the limit.
+    fn contains(&self, id: MessageId) -> bool {
+        self.after.map_or(true, |a| id > a) && self.before.map_or(true, |b| id < b)
+    }
+}
+
+impl Transcript {
+    /// Collects the messages in a range of a channel's history. Messages are taken from the cache
+    /// where possible; once the cache runs out, the rest are fetched from Discord.
+    pub async fn collect(
+        dis: &Dispatch,
+        ctx: &Context,
+        guild: GuildId,
+        channel: ChannelId,
+        channel_name: impl Into<String>,
+        range: TranscriptRange,
+    ) -> crate::error::Result<Self> {
+        let known = dis
+            .message_cache()
+            .get(&guild)
+            .map(|cv| {
+                cv.snapshot()
+                    .iter()
+                    .rev()
+                    .filter(|m| m.channel == channel && range.contains(m.msg))
+                    .map(|m| m.msg)
+                    .take(range.limit as usize)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        // Newest first until the very end. We can only use the cache for as long as it's unbroken;
+        // as soon as a message is missing, we fall back to paging from Discord.
+        let mut messages = Vec::new();
+        for id in known {
+            match ctx.cache.message(channel, id).await {
+                Some(m) => messages.push(TranscriptMessage::from(&m)),
+                None => break,
+            }
+        }
+
+        let mut before = messages.last().map(|m| m.id).or(range.before);
+        while (messages.len() as u64) < range.limit {
+            let want = (range.limit - messages.len() as u64).min(MESSAGES_PER_REQUEST);
+            let batch = channel
+                .messages(ctx, |r| {
+                    r.limit(want);
+                    if let Some(b) = before {
+                        r.before(b);
+                    }
+                    r
+                })
+                .await?;
+
+            let exhausted = (batch.len() as u64) < want || batch.iter().any(|m| !range.contains(m.id));
+            before = batch.last().map(|m| m.id);
+            messages.extend(
+                batch
+                    .iter()
+                    .filter(|m| range.contains(m.id))
+                    .map(TranscriptMessage::from),
+            );
+
+            if exhausted || before.is_none() {
+                break;
+            }
+        }
+
+        // Discord returns the newest messages first.
+        messages.reverse();
+        Ok(Transcript {
+            guild,
+            channel,
+            channel_name: channel_name.into(),
+            generated: Utc::now(),
+            messages,
+        })
+    }
+
+    /// Renders the transcript as a standalone HTML document.
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        let title = escape_html(&format!("#{} ({})", self.channel_name, self.channel));
+        let _ = write!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+            <style>body{{font-family:sans-serif}} .meta{{color:#666}} .msg{{margin:0.5em 0}} \
+            .content{{white-space:pre-wrap}}</style>\n</head>\n<body>\n<h1>{title}</h1>\n\
+            <p class=\"meta\">{} messages, generated {}</p>\n",
+            self.messages.len(),
+            self.generated.format("%F %T UTC"),
+            title = title
+        );
+
+        for m in &self.messages {
+            let _ = write!(
+                out,
+                "<div class=\"msg\" id=\"{id}\"><span class=\"meta\">{ts}{edited}</span> <b>{name}</b> \
+                <span class=\"meta\">({author})</span>\n<div class=\"content\">{content}</div>\n",
+                id = m.id,
+                ts = m.timestamp.format("%F %T"),
+                edited = if m.edited.is_some() { " (edited)" } else { "" },
+                name = escape_html(&m.author_name),
+                author = m.author,
+                content = escape_html(&m.content)
+            );
+            for a in &m.attachments {
+                let a = escape_html(a);
+                let _ = writeln!(out, "<div><a href=\"{a}\">{a}</a></div>", a = a);
+            }
+            out.push_str("</div>\n");
+        }
+
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    /// Renders the transcript as plain text, one message per paragraph.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "#{} ({}): {} messages, generated {}\n",
+            self.channel_name,
+            self.channel,
+            self.messages.len(),
+            self.generated.format("%F %T UTC")
+        );
+
+        for m in &self.messages {
+            let _ = writeln!(
+                out,
+                "[{}{}] {} ({}): {}",
+                m.timestamp.format("%F %T"),
+                if m.edited.is_some() { ", edited" } else { "" },
+                m.author_name,
+                m.author,
+                m.content
+            );
+            for a in &m.attachments {
+                let _ = writeln!(out, "    attachment: {}", a);
+            }
+        }
+
+        out
+    }
+}
+
+/// Escapes text for inclusion in HTML.
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}